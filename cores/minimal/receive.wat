@@ -0,0 +1,8 @@
+;; Called with each message a connection sends. Just echoes it back for now.
+(module
+  (import "host" "send" (func $host/send (param i32) (result i32 i32)))
+  (memory $mem 1)
+  (export "memory" (memory $mem))
+  (func $send (param $0 i32) (result i32 i32) get_local $0 (call $host/send))
+  (export "invoke" (func $send))
+)
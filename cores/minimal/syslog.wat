@@ -0,0 +1,8 @@
+;; Writes its arguments to the server log.
+(module
+  (import "host" "log" (func $host/log (param i32) (result i32 i32)))
+  (memory $mem 1)
+  (export "memory" (memory $mem))
+  (func $log (param $0 i32) (result i32 i32) get_local $0 (call $host/log))
+  (export "invoke" (func $log))
+)
@@ -0,0 +1,8 @@
+// Lay out the examples' memory as the driver expects: the stack first, growing down towards the
+// argument region at the start of memory, then data, then the heap.
+fn main() {
+    if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("wasm32") {
+        println!("cargo:rustc-link-arg=--stack-first");
+        println!("cargo:rustc-link-arg=-zstack-size=1048576");
+    }
+}
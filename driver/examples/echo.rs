@@ -0,0 +1,9 @@
+//! A verb which logs its arguments and returns them.
+use driver::{host, room_verb, Value};
+
+fn echo(args: Vec<Value>) -> Value {
+    host::log(args.clone());
+    Value::Vector(args)
+}
+
+room_verb!(echo);
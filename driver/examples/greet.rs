@@ -0,0 +1,11 @@
+//! A verb which greets someone by name, a number of times, declared with `#[verb]`.
+use driver::verb;
+
+#[verb]
+fn greet(name: String, times: i64) -> String {
+    let mut greeting = String::new();
+    for _ in 0..times.max(0) {
+        greeting.push_str("hello, ");
+    }
+    greeting + &name
+}
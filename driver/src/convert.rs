@@ -0,0 +1,115 @@
+//! Conversions between Values and the Rust types verbs declared with `#[verb]` take and return.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use value::Error::NoError;
+use value::{Error, Oid, Value};
+
+/// A type a verb's parameter can be, read from one of its arguments.
+pub trait FromValue: Sized {
+    /// The argument as this type, or None if it's some other type of Value.
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+/// A type a verb can return, as the Value its result is.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Option<Self> {
+        Some(value)
+    }
+}
+
+/// I32s are widened, since the host doesn't always say which it means.
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::I64(i) => Some(i),
+            Value::I32(i) => Some(i as i64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::String(s) => Some(s.into()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Oid {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::IdKey(oid) => Some(oid),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Vec<Value> {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Vector(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::I64(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl IntoValue for Oid {
+    fn into_value(self) -> Value {
+        Value::IdKey(self)
+    }
+}
+
+impl IntoValue for Vec<Value> {
+    fn into_value(self) -> Value {
+        Value::Vector(self)
+    }
+}
+
+impl IntoValue for Error {
+    fn into_value(self) -> Value {
+        Value::Error(self)
+    }
+}
+
+/// A verb returning nothing returns NoError.
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Error(NoError)
+    }
+}
+
+/// A verb which fails returns its Error.
+impl<T: IntoValue> IntoValue for Result<T, Error> {
+    fn into_value(self) -> Value {
+        match self {
+            Ok(value) => value.into_value(),
+            Err(e) => Value::Error(e),
+        }
+    }
+}
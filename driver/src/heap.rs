@@ -0,0 +1,52 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::arch::wasm32::{memory_grow, memory_size};
+use core::cell::Cell;
+use core::ptr::{addr_of, null_mut};
+
+const PAGE_SIZE: usize = 64 * 1024;
+
+extern "C" {
+    // Where the linker put the end of the module's data and stack, and so the start of the heap.
+    static __heap_base: u8;
+}
+
+/// Hands out memory from `__heap_base` up, growing memory as it needs to, and never frees it:
+/// every verb invocation gets a fresh instance, so there's nothing to free it for.
+pub struct BumpAllocator {
+    // The next free address, or 0 before the first allocation.
+    next: Cell<usize>,
+}
+
+// Guests are single threaded.
+unsafe impl Sync for BumpAllocator {}
+
+impl BumpAllocator {
+    pub const fn new() -> Self {
+        BumpAllocator { next: Cell::new(0) }
+    }
+}
+
+unsafe impl GlobalAlloc for BumpAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let next = match self.next.get() {
+            0 => addr_of!(__heap_base) as usize,
+            next => next,
+        };
+        let start = (next + layout.align() - 1) & !(layout.align() - 1);
+        let end = match start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => return null_mut(),
+        };
+        let available = memory_size(0) * PAGE_SIZE;
+        if end > available {
+            let pages = (end - available + PAGE_SIZE - 1) / PAGE_SIZE;
+            if memory_grow(0, pages) == usize::MAX {
+                return null_mut();
+            }
+        }
+        self.next.set(end);
+        start as *mut u8
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
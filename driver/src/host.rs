@@ -0,0 +1,96 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use value::Error::InternalError;
+use value::{decode_frame, encode_frame, DecodeError, Oid, Value};
+
+use crate::region::{self, ARG_REGION_SIZE};
+
+/// A builtin, as imported from the engine's packed host module: it takes the length of its
+/// arguments at the start of memory, and returns the location and length of its result packed as
+/// `length << 32 | location`.
+pub type Builtin = unsafe extern "C" fn(stack_end: i32) -> i64;
+
+#[link(wasm_import_module = "host_packed")]
+extern "C" {
+    #[link_name = "invoke"]
+    fn host_invoke(stack_end: i32) -> i64;
+    #[link_name = "send"]
+    fn host_send(stack_end: i32) -> i64;
+    #[link_name = "log"]
+    fn host_log(stack_end: i32) -> i64;
+    #[link_name = "get_slot"]
+    fn host_get_slot(stack_end: i32) -> i64;
+}
+
+/// Why a builtin couldn't be called.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallError {
+    /// The arguments, as a frame, wouldn't fit in the argument region.
+    TooLarge(usize),
+    /// The builtin's result couldn't be decoded.
+    Decode(DecodeError),
+}
+
+/// Call `builtin` with `args`, for those without a wrapper below. Other builtins are imported
+/// like those here, e.g. `#[link(wasm_import_module = "host_packed")] extern "C" { fn now(stack_end:
+/// i32) -> i64; }`.
+pub fn call(builtin: Builtin, args: Vec<Value>) -> Result<Value, CallError> {
+    let frame = encode_frame(&Value::Vector(args));
+    if frame.len() > ARG_REGION_SIZE {
+        return Err(CallError::TooLarge(frame.len()));
+    }
+    region::write(&frame);
+    let packed = unsafe { builtin(frame.len() as i32) };
+    let (location, length) = (packed as u32 as usize, (packed >> 32) as u32 as usize);
+    decode_frame(&region::read(location, length)).map_err(CallError::Decode)
+}
+
+// The wrappers' results, with a builtin which couldn't be called as an InternalError.
+fn call_or_error(builtin: Builtin, args: Vec<Value>) -> Value {
+    call(builtin, args).unwrap_or(Value::Error(InternalError))
+}
+
+/// What a verb may send to a connection.
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Invoke `verb` on `oid` with `args`, returning its result.
+pub fn invoke(oid: Oid, verb: &str, args: Vec<Value>) -> Value {
+    call_or_error(
+        host_invoke,
+        alloc::vec![
+            Value::IdKey(oid),
+            Value::String(verb.into()),
+            Value::Vector(args),
+        ],
+    )
+}
+
+/// Send `message` to `connection`.
+pub fn send(connection: Oid, message: Message) -> Value {
+    let message = match message {
+        Message::Text(text) => Value::String(text.into()),
+        Message::Binary(bytes) => Value::Binary(bytes.into()),
+    };
+    call_or_error(host_send, alloc::vec![Value::IdKey(connection), message])
+}
+
+/// Write `args` to the server log.
+pub fn log(args: Vec<Value>) -> Value {
+    call_or_error(host_log, args)
+}
+
+/// Read the slot `name` of `key` on `oid`, or SlotDoesNotExist.
+pub fn get_slot(oid: Oid, key: Oid, name: &str) -> Value {
+    call_or_error(
+        host_get_slot,
+        alloc::vec![
+            Value::IdKey(oid),
+            Value::IdKey(key),
+            Value::String(name.into()),
+        ],
+    )
+}
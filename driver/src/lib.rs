@@ -1,62 +1,83 @@
 #![no_std]
-#![allow(unused_attributes)]
-#![feature(lang_items)]
-#![feature(core_intrinsics)]
-/// WASM-side ABI / heap memory mgmt
-/// Management of heap-arguments
-//
+//! The guest side of the verb ABI, for writing verbs in Rust compiled to wasm32.
+//!
+//! A verb is a function from its arguments to its result, exported with `room_verb!`:
+//!
+//! ```ignore
+//! use driver::{room_verb, Value};
+//!
+//! fn greet(args: Vec<Value>) -> Value {
+//!     driver::host::log(args);
+//!     Value::String("hello".into())
+//! }
+//!
+//! room_verb!(greet);
+//! ```
+//!
+//! or, with its arguments and result converted to and from Rust types, declared with `#[verb]`:
+//!
+//! ```ignore
+//! #[driver::verb]
+//! fn greet(name: String) -> String {
+//!     name + ", hello"
+//! }
+//! ```
+//!
+//! The host writes a verb's arguments, as a frame, at the start of memory, and calls its exported
+//! 'invoke' with their length; 'invoke' returns where its result's frame is. Builtins are called
+//! the same way in the other direction (see `host`). Rust can't export or import functions
+//! returning two values, so verbs written with the driver use the engine's packed forms of both.
+//!
+//! A crate of verbs should be a cdylib, linked with `--stack-first` as the driver's build script
+//! does for its examples, so that the start of memory is clear for arguments.
 
-// We need alloc for Vec (and probably String).
-// We will probably need to define our own custom allocator for WASM-land.
-// For now we will proceed using the one provided by alloc::System
 extern crate alloc;
 
-
+mod convert;
+#[cfg(target_arch = "wasm32")]
+mod heap;
+pub mod host;
+pub mod region;
 
 use alloc::vec::Vec;
+use core::mem;
 
+pub use convert::{FromValue, IntoValue};
+pub use room_macros::verb;
+use value::Error::BadType;
+use value::{decode_frame, encode_frame};
+pub use value::{Error, Oid, Value};
 
-use value::Error::NoError;
-use value::{append_value, parse_value, Value};
-
-#[link(wasm_import_module = "host")]
-extern "C" {
-    static memory: *mut u8;
-    static mut __data_end: i32;
-    static __heap_base: i32;
-    fn log(stack_end: i32) -> i32;
-}
+#[cfg(target_arch = "wasm32")]
+#[global_allocator]
+static ALLOCATOR: heap::BumpAllocator = heap::BumpAllocator::new();
 
-/// static_end is the offset into memory of where memory passed into WASM-land from the runtime
-/// ends.
-/// action is the function to invoke with arguments passed.
-/// the contents of this region are deserialized into a call record structure containing all
-/// arguments and any additional context from the runtime.
-/// from there, any heap allocations are performed above that wall and
-/// the intended function is then dispatched with the deserialized arguments passed through
-/// using rust's wasm calling conventions.
-/// finally the return back to the runtime is a tuple containing the offset and size of the
-/// returned values.
-fn trampoline<F>(static_end: i32, action: F) -> (i32, i32)
+/// Run `verb` on the arguments the host passed, `args_len` bytes at the start of memory, and
+/// return the location of its result as 'invoke' must. A verb passed something other than a
+/// Vector of arguments returns BadType without being run.
+pub fn entry<F>(args_len: i32, verb: F) -> i64
 where
-    F: Fn(&Value) -> Value,
+    F: FnOnce(Vec<Value>) -> Value,
 {
-    let value: Value = unsafe {
-        let tramp_args = Vec::from_raw_parts(memory, static_end as usize, static_end as usize);
-        parse_value(&mut tramp_args.as_slice())
+    let result = match decode_frame(&region::read(0, args_len as usize)) {
+        Ok(Value::Vector(args)) => verb(args),
+        _ => Value::Error(BadType),
     };
-    let result = action(&value);
-    unsafe {
-        let mut buf: Vec<u8> = Vec::new();
-        append_value(&mut buf, &result);
-        let (offset, size) = (__heap_base, buf.len() as i32);
-        let region = memory.offset(offset as isize);
-        region.copy_from(region, size as usize);
-        (offset, size)
-    }
+    // The result stays where it was encoded, in the heap; the instance is thrown away once the
+    // host has read it.
+    let frame = encode_frame(&result);
+    let packed = ((frame.len() as u32 as i64) << 32) | frame.as_ptr() as u32 as i64;
+    mem::forget(frame);
+    packed
 }
 
-#[no_mangle]
-pub extern "C" fn syslog(static_end: i32) -> (i32, i32) {
-    trampoline(static_end, |_v| Value::Error(NoError))
+/// Export `verb`, a `fn(Vec<Value>) -> Value`, as the module's 'invoke'.
+#[macro_export]
+macro_rules! room_verb {
+    ($verb:path) => {
+        #[no_mangle]
+        pub extern "C" fn invoke(args_len: i32) -> i64 {
+            $crate::entry(args_len, $verb)
+        }
+    };
 }
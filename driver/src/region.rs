@@ -0,0 +1,38 @@
+use alloc::vec::Vec;
+use core::ptr::{addr_of, read_volatile, write_volatile};
+
+/// The size of the region at the start of memory which arguments are passed through: the host
+/// writes a verb's arguments there, and a verb writes a builtin's there. The result a builtin
+/// returns follows its arguments, so the two together must fit.
+///
+/// The driver's build script puts the stack first in memory, so this is the bottom of the stack,
+/// which stays clear unless a verb uses all but this much of its 1MiB.
+pub const ARG_REGION_SIZE: usize = 256 * 1024;
+
+// The region isn't memory Rust allocated, so it's only ever accessed with volatile reads and
+// writes, byte by byte. It's reached through the linker's symbol for the bottom of the stack,
+// which with the stack first is the start of memory, rather than through a pointer made from
+// address 0.
+extern "C" {
+    static __stack_low: u8;
+}
+
+fn start() -> *mut u8 {
+    addr_of!(__stack_low) as *mut u8
+}
+
+/// Copy `len` bytes from `offset` in memory.
+pub fn read(offset: usize, len: usize) -> Vec<u8> {
+    let start = start();
+    (offset..offset + len)
+        .map(|i| unsafe { read_volatile(start.wrapping_add(i)) })
+        .collect()
+}
+
+/// Copy `bytes` to the start of the region.
+pub fn write(bytes: &[u8]) {
+    let start = start();
+    for (i, byte) in bytes.iter().enumerate() {
+        unsafe { write_volatile(start.wrapping_add(i), *byte) };
+    }
+}
@@ -0,0 +1,117 @@
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+
+/// What whole-world archives identify themselves as in their manifests.
+pub const ARCHIVE_FORMAT: &str = "room-world";
+
+/// The version of the archive layout written. Archives from later versions aren't loaded.
+/// Version 2 added NAMES_ENTRY, and version 3 incremental archives, so that engines which would
+/// load those as whole worlds refuse them.
+pub const ARCHIVE_VERSION: u32 = 3;
+
+/// The entry every archive starts with.
+pub const MANIFEST_ENTRY: &str = "manifest.json";
+
+/// The entry an incremental archive ends with: a JSON array of the objects destroyed since the
+/// archive it follows.
+pub const DESTROYED_ENTRY: &str = "destroyed.json";
+
+/// The entry after the objects: a JSON array of [oid, name] for each object in the archive which
+/// holds a name. Those in it which don't are to hold none.
+pub const NAMES_ENTRY: &str = "names.json";
+
+/// Describes a whole-world archive: a zstd compressed tar file holding the manifest, then an
+/// `objects/<oid>.json` entry for each object with the dumps of all its slots, then NAMES_ENTRY.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorldManifest {
+    pub format: String,
+    pub version: u32,
+    /// When it was written, in seconds since the Unix epoch.
+    pub created: u64,
+    /// The version of the engine which wrote it.
+    pub engine_version: String,
+    /// Where the change journal was, as a versionstamp in hex, when it was begun; a later
+    /// incremental archive from here has every change this one might have missed. None unless
+    /// changes were being journaled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub journal_position: Option<String>,
+    /// If it's incremental, the journal position it follows on from: it holds only the objects
+    /// changed since, and DESTROYED_ENTRY.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+}
+
+/// What a backup wrote, or a restore read.
+#[derive(Serialize, Debug, Default)]
+pub struct ArchiveReport {
+    pub objects: usize,
+    pub slots: usize,
+    /// For an incremental archive, the objects destroyed since the one it follows on from.
+    pub destroyed: usize,
+    /// As the manifest has them.
+    pub journal_position: Option<String>,
+    pub since: Option<String>,
+}
+
+/// Writes regular files into a tar archive, which is all world archives need.
+pub struct ArchiveWriter<W: Write> {
+    builder: tar::Builder<W>,
+    mtime: u64,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(out: W, mtime: u64) -> Self {
+        ArchiveWriter {
+            builder: tar::Builder::new(out),
+            mtime,
+        }
+    }
+
+    pub fn append(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        self.builder
+            .append_data(&mut header, name, data)
+            .map_err(|e| anyhow!("Can't archive {}: {}", name, e))
+    }
+
+    /// The output, e.g. to take what's been written so far.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.builder.get_mut()
+    }
+
+    /// Write the end of archive marker, and hand back the output.
+    pub fn finish(self) -> Result<W, Error> {
+        Ok(self.builder.into_inner()?)
+    }
+}
+
+/// Read back the regular files from a tar archive in order, handing each one's name and contents
+/// to `entry` until it returns false. Other kinds of entry are skipped.
+pub fn read_archive<R: Read>(
+    input: R,
+    mut entry: impl FnMut(String, Vec<u8>) -> Result<bool, Error>,
+) -> Result<(), Error> {
+    let mut archive = tar::Archive::new(input);
+    for file in archive.entries()? {
+        let mut file = file?;
+        if !file.header().entry_type().is_file() {
+            continue;
+        }
+        let name = match file.path()?.to_str() {
+            Some(name) => name.to_string(),
+            None => return Err(anyhow!("Archive entry name isn't UTF-8")),
+        };
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+        if !entry(name, data)? {
+            break;
+        }
+    }
+    Ok(())
+}
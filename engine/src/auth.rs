@@ -0,0 +1,151 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{range::Range, subspace::Subspace, tuple::Tuple, Key};
+use tokio_stream::StreamExt;
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// Limits on failed login attempts, to slow down credential stuffing.
+///
+/// Failures are counted over a sliding window, both per account and per client address. Once
+/// either count passes `free_attempts`, further attempts must wait a backoff after the most recent
+/// failure, starting at `base_lockout` and doubling with each failure up to `max_lockout`.
+#[derive(Clone, Debug)]
+pub struct AuthPolicy {
+    pub window: Duration,
+    pub free_attempts: u32,
+    pub base_lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+impl Default for AuthPolicy {
+    fn default() -> Self {
+        AuthPolicy {
+            window: Duration::from_secs(15 * 60),
+            free_attempts: 5,
+            base_lockout: Duration::from_secs(1),
+            max_lockout: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl AuthPolicy {
+    /// When the window of failures counted at `now` starts; the epoch, if the window reaches back
+    /// further than that.
+    pub fn window_start(&self, now: SystemTime) -> SystemTime {
+        now.checked_sub(self.window)
+            .unwrap_or(UNIX_EPOCH)
+            .max(UNIX_EPOCH)
+    }
+
+    /// How long from `now` until another attempt is allowed, given the failures in the window.
+    pub fn retry_after(&self, failures: &[SystemTime], now: SystemTime) -> Duration {
+        let excess = failures.len().saturating_sub(self.free_attempts as usize);
+        let last = match failures.iter().max() {
+            Some(last) if excess > 0 => *last,
+            _ => return Duration::ZERO,
+        };
+        let lockout = self
+            .base_lockout
+            .checked_mul(1 << (excess - 1).min(31))
+            .unwrap_or(self.max_lockout)
+            .min(self.max_lockout);
+        match last.checked_add(lockout) {
+            Some(until) => until.duration_since(now).unwrap_or(Duration::ZERO),
+            // Too far in the future to represent; it's as long as a lockout gets.
+            None => self.max_lockout,
+        }
+    }
+}
+
+/// What failed attempts are counted against.
+#[derive(Clone, Copy, Debug)]
+pub enum Scope {
+    Account(Oid),
+    Address(IpAddr),
+}
+
+fn auth_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("AUTHFAIL".as_bytes()))
+}
+
+fn micros(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64
+}
+
+fn scope_tuple(scope: Scope) -> Tuple {
+    let mut tup = Tuple::new();
+    match scope {
+        Scope::Account(oid) => {
+            tup.add_string(String::from("account"));
+            tup.add_uuid(oid.id);
+        }
+        Scope::Address(ip) => {
+            tup.add_string(String::from("address"));
+            tup.add_string(ip.to_string());
+        }
+    }
+    tup
+}
+
+// Failures are keyed by (scope, time) so those within the window are a single range read.
+fn failure_key(scope: Scope, time: SystemTime) -> Key {
+    let mut tup = scope_tuple(scope);
+    tup.add_i64(micros(time));
+    auth_subspace().subspace(&tup).pack().into()
+}
+
+// Reads and records failed login attempts via one transaction.
+pub struct AuthTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> AuthTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        AuthTxHandle { tr: tx }
+    }
+
+    /// The times of the failures against `scope` since `since`.
+    pub async fn failures(
+        &self,
+        scope: Scope,
+        since: SystemTime,
+    ) -> Result<Vec<SystemTime>, DbError> {
+        let end = auth_subspace()
+            .subspace(&scope_tuple(scope))
+            .range(&Tuple::new());
+        let range = Range::new(failure_key(scope, since), end.into_end_key());
+        let mut stream = self.tr.get_range(range);
+        let mut failures = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, _) = kv?;
+            let key_bytes: Bytes = key.into();
+            let tuple = auth_subspace().unpack(&key_bytes).unwrap();
+            let at = tuple.get_i64(2).unwrap();
+            failures.push(UNIX_EPOCH + Duration::from_micros(at as u64));
+        }
+        Ok(failures)
+    }
+
+    /// Record a failure against `scope`, forgetting any from before `expired`.
+    pub fn record_failure(&self, scope: Scope, at: SystemTime, expired: SystemTime) {
+        let begin = auth_subspace().subspace(&scope_tuple(scope)).pack();
+        self.tr
+            .clear_range(Range::new(begin, failure_key(scope, expired)));
+        self.tr.set(failure_key(scope, at), Bytes::new());
+    }
+
+    /// Forget all failures against `scope`.
+    pub fn clear(&self, scope: Scope) {
+        self.tr.clear_range(
+            auth_subspace()
+                .subspace(&scope_tuple(scope))
+                .range(&Tuple::new()),
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+/// What to do with a connection which goes over its bandwidth cap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BandwidthAction {
+    /// Hold its messages back until it's within its cap again.
+    #[default]
+    Throttle,
+    /// Close it.
+    Disconnect,
+}
+
+/// Caps on how fast each connection may send and be sent data, in bytes per second, averaged over
+/// a second. None is uncapped.
+#[derive(Clone, Debug, Default)]
+pub struct BandwidthPolicy {
+    pub inbound: Option<u64>,
+    pub outbound: Option<u64>,
+    pub action: BandwidthAction,
+}
+
+/// How many messages in a row a connection may have refused for flooding, by default, before
+/// it's closed.
+pub const DEFAULT_FLOOD_STRIKES: u32 = 20;
+
+/// Limits on how fast each connection may send messages (lines, frames and requests), so that one
+/// client can't keep the VM and database busy. Messages over a limit are dropped rather than held
+/// back, as they are by the bandwidth caps. None is unlimited.
+#[derive(Clone, Debug)]
+pub struct FloodPolicy {
+    /// Allowing bursts of up to a second's worth.
+    pub messages_per_sec: Option<u64>,
+    /// Allowing bursts of up to a second's worth; a larger message is always refused.
+    pub bytes_per_sec: Option<u64>,
+    /// How many messages in a row may be refused before the connection is closed.
+    pub max_strikes: u32,
+}
+
+impl Default for FloodPolicy {
+    fn default() -> Self {
+        FloodPolicy {
+            messages_per_sec: None,
+            bytes_per_sec: None,
+            max_strikes: DEFAULT_FLOOD_STRIKES,
+        }
+    }
+}
+
+/// Bytes received from and sent to a peer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Traffic {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl Traffic {
+    pub fn add(&mut self, other: Traffic) {
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
+
+/// A token bucket holding up to a second's worth of a rate.
+#[derive(Clone, Debug, Default)]
+pub struct Meter {
+    allowance: Option<f64>,
+    updated: Option<Instant>,
+}
+
+impl Meter {
+    /// Take `bytes` from the allowance, which refills at `rate` bytes per second, returning how
+    /// long until the allowance is back in credit. Zero if it still is.
+    pub fn take(&mut self, bytes: usize, rate: u64, now: Instant) -> Duration {
+        let allowance = self.refilled(rate, now) - bytes as f64;
+        self.allowance = Some(allowance);
+        self.updated = Some(now);
+        match allowance >= 0.0 {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64(-allowance / rate.max(1) as f64),
+        }
+    }
+
+    /// How long from `now` until the allowance, refilling at `rate`, is back in credit. Zero if it
+    /// still is.
+    pub fn debt(&self, rate: u64, now: Instant) -> Duration {
+        let allowance = self.refilled(rate, now);
+        match allowance >= 0.0 {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64(-allowance / rate.max(1) as f64),
+        }
+    }
+
+    /// Whether `amount` could be taken from the allowance at `now` without going into debt.
+    /// Nothing is taken.
+    pub fn allows(&self, amount: usize, rate: u64, now: Instant) -> bool {
+        self.refilled(rate, now) >= amount as f64
+    }
+
+    fn refilled(&self, rate: u64, now: Instant) -> f64 {
+        let rate = rate.max(1) as f64;
+        let elapsed = self
+            .updated
+            .map_or(0.0, |updated| now.duration_since(updated).as_secs_f64());
+        (self.allowance.unwrap_or(rate) + elapsed * rate).min(rate)
+    }
+}
@@ -0,0 +1,117 @@
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use futures::stream::{BoxStream, StreamExt};
+
+use crate::database::{DbError, Tx};
+use crate::object::SlotDef;
+use value::{Oid, Value};
+
+/// Binary values which encode larger than this are stored as blobs rather than in their slots.
+/// (FDB refuses values over 100KB.)
+pub const BLOB_THRESHOLD: usize = 64 * 1024;
+
+/// The size of each stored piece of a blob. Every chunk but the last is this size.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Whether `value` is, or holds in a Vector, a Blob handle. Only the engine makes those, for the
+/// blobs it stores, so verbs can't set slots to values which hold them.
+pub fn holds_blob(value: &Value) -> bool {
+    match value {
+        Value::Blob(_) => true,
+        Value::Vector(values) => values.iter().any(holds_blob),
+        _ => false,
+    }
+}
+
+/// Large Binary values, stored in chunks under the slot which holds them. The slot itself holds a
+/// `Value::Blob` with the length, and the chunks are only read when asked for.
+///
+/// Chunks are keyed by slot so that overwriting or clearing a slot can drop its blob with a range
+/// clear, without having to read what it held first.
+pub struct BlobTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+fn object_blobs(location: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(location.id);
+    Subspace::new(Bytes::from_static("BLOB".as_bytes())).subspace(&tup)
+}
+
+fn slot_blob(slot: &SlotDef) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(slot.key.id);
+    tup.add_string(slot.name.clone());
+    object_blobs(slot.location).subspace(&tup)
+}
+
+fn chunk_key(slot: &SlotDef, index: usize) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_i64(index as i64);
+    slot_blob(slot).subspace(&tup).pack().into()
+}
+
+impl<'tx_lifetime> BlobTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        BlobTxHandle { tr: tx }
+    }
+
+    /// Store `data` as the blob held by `slot`, replacing whatever it held.
+    pub fn put(&self, slot: &SlotDef, data: &[u8]) {
+        self.clear(slot);
+        for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            self.tr
+                .set(chunk_key(slot, index), Bytes::copy_from_slice(chunk));
+        }
+    }
+
+    /// Drop the blob held by `slot`, if it holds one.
+    pub fn clear(&self, slot: &SlotDef) {
+        self.tr.clear_range(slot_blob(slot).range(&Tuple::new()));
+    }
+
+    /// Drop every blob held by slots on `location`.
+    pub fn clear_object(&self, location: Oid) {
+        self.tr
+            .clear_range(object_blobs(location).range(&Tuple::new()));
+    }
+
+    /// Stream the chunks of the blob held by `slot`, in order.
+    pub fn chunks(&self, slot: &SlotDef) -> BoxStream<'static, Result<Bytes, DbError>> {
+        self.tr
+            .get_range(slot_blob(slot).range(&Tuple::new()))
+            .map(|kv| kv.map(|(_, chunk)| Bytes::from(chunk)))
+            .boxed()
+    }
+
+    /// Up to `len` bytes of the blob held by `slot`, from `offset`. Fewer if it ends sooner.
+    pub async fn read(&self, slot: &SlotDef, offset: u64, len: usize) -> Result<Vec<u8>, DbError> {
+        let mut result = Vec::with_capacity(len);
+        let mut index = offset as usize / CHUNK_SIZE;
+        let mut skip = offset as usize % CHUNK_SIZE;
+        while result.len() < len {
+            let chunk = match self.tr.get(chunk_key(slot, index)).await? {
+                Some(chunk) => Bytes::from(chunk),
+                None => break,
+            };
+            if skip >= chunk.len() {
+                break;
+            }
+            let wanted = (len - result.len()).min(chunk.len() - skip);
+            result.extend_from_slice(&chunk[skip..skip + wanted]);
+            skip = 0;
+            index += 1;
+        }
+        Ok(result)
+    }
+
+    /// The whole of the blob held by `slot`.
+    pub async fn read_all(&self, slot: &SlotDef) -> Result<Vec<u8>, DbError> {
+        let mut chunks = self.chunks(slot);
+        let mut result = vec![];
+        while let Some(chunk) = chunks.next().await {
+            result.extend_from_slice(&chunk?);
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+use value::Value;
+
+/// Who a builtin is meant to be called by. Nothing enforces this yet; it tells world developers
+/// which builtins only trusted verbs should be allowed to reach.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Privilege {
+    /// Harmless for any verb to call.
+    Any,
+    /// Changes the world's objects and programs.
+    Programmer,
+    /// Deals with accounts' credentials and logins.
+    Security,
+}
+
+/// A host function bound into every verb's instance, as described to world developers.
+#[derive(Clone, Debug, Serialize)]
+pub struct BuiltinInfo {
+    pub name: &'static str,
+    /// The Values it takes and returns, e.g. `(IdKey oid, String name) -> Value`.
+    pub signature: &'static str,
+    pub privilege: Privilege,
+    pub description: &'static str,
+}
+
+impl BuiltinInfo {
+    /// A Vector of its name, signature, privilege and description, as Strings.
+    pub fn to_value(&self) -> Value {
+        Value::Vector(vec![
+            Value::String(self.name.into()),
+            Value::String(self.signature.into()),
+            Value::String(format!("{:?}", self.privilege).into()),
+            Value::String(self.description.into()),
+        ])
+    }
+}
+
+/// Every builtin bound so far, recorded as `bind_builtins` binds them.
+#[derive(Default)]
+pub struct BuiltinRegistry {
+    builtins: Mutex<Vec<BuiltinInfo>>,
+}
+
+impl BuiltinRegistry {
+    /// Record a builtin, replacing any already recorded under its name, and return its name to
+    /// bind it with.
+    pub fn record(
+        &self,
+        name: &'static str,
+        signature: &'static str,
+        privilege: Privilege,
+        description: &'static str,
+    ) -> &'static str {
+        let info = BuiltinInfo {
+            name,
+            signature,
+            privilege,
+            description,
+        };
+        let mut builtins = self.builtins.lock().unwrap();
+        match builtins.iter_mut().find(|b| b.name == name) {
+            Some(existing) => *existing = info,
+            None => builtins.push(info),
+        }
+        name
+    }
+
+    /// The builtins recorded so far, in the order they were bound.
+    pub fn list(&self) -> Vec<BuiltinInfo> {
+        self.builtins.lock().unwrap().clone()
+    }
+}
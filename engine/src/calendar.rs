@@ -0,0 +1,295 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use bytes::Bytes;
+use fdb::{range::Range, subspace::Subspace, tuple::Tuple, Key};
+use serde::Serialize;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::database::{DbError, Tx};
+use crate::schedule::CronSchedule;
+use value::{Oid, Value};
+
+/// Occurrences of a recurring event listed at most, however far ahead is asked about.
+pub const MAX_OCCURRENCES: usize = 1000;
+
+/// An event on the world's calendar, e.g. a festival or a maintenance window, announced by running
+/// a verb as each of its occurrences starts.
+#[derive(Clone, Debug, Serialize)]
+pub struct CalendarEvent {
+    pub id: Uuid,
+    pub name: String,
+    /// When its first occurrence starts and ends, in nanoseconds since the Unix epoch. Later
+    /// occurrences last as long.
+    pub start: i64,
+    pub end: i64,
+    /// A cron expression it recurs by from its start, if it recurs.
+    pub recurrence: Option<String>,
+    /// The object whose verb announces each occurrence.
+    pub target: Oid,
+    pub verb: String,
+    /// When its next occurrence starts, or None once it's had its last.
+    pub next: Option<i64>,
+    /// The player who added it, if it was added by a verb run for one.
+    pub owner: Option<Oid>,
+}
+
+fn nanos(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as i64
+}
+
+fn time_of(nanos: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64)
+}
+
+impl CalendarEvent {
+    /// A new event, whose first occurrence is the first its recurrence allows at or after `start`.
+    pub fn new(
+        name: String,
+        start: i64,
+        end: i64,
+        recurrence: Option<String>,
+        target: Oid,
+        verb: String,
+        owner: Option<Oid>,
+    ) -> Result<Self, Error> {
+        let mut event = CalendarEvent {
+            id: Uuid::new_v4(),
+            name,
+            start,
+            end,
+            recurrence,
+            target,
+            verb,
+            next: None,
+            owner,
+        };
+        event.next = match event.schedule()? {
+            Some(schedule) => schedule.next_after(time_of(start - 1)).map(nanos),
+            None => Some(start),
+        };
+        Ok(event)
+    }
+
+    fn schedule(&self) -> Result<Option<CronSchedule>, Error> {
+        self.recurrence
+            .as_deref()
+            .map(CronSchedule::parse)
+            .transpose()
+    }
+
+    /// How long each occurrence lasts, in nanoseconds.
+    pub fn duration(&self) -> i64 {
+        (self.end - self.start).max(0)
+    }
+
+    /// When the occurrence after the one starting at `start` starts, if there is one.
+    pub fn following(&self, start: i64) -> Option<i64> {
+        let schedule = self.schedule().ok()??;
+        schedule.next_after(time_of(start)).map(nanos)
+    }
+
+    /// The starts of its occurrences from its next up to `until`, at most MAX_OCCURRENCES.
+    pub fn occurrences(&self, until: i64) -> Vec<i64> {
+        let mut starts = vec![];
+        let mut next = self.next;
+        while let Some(start) = next {
+            if start > until || starts.len() >= MAX_OCCURRENCES {
+                break;
+            }
+            starts.push(start);
+            next = self.following(start);
+        }
+        starts
+    }
+
+    fn value(&self) -> fdb::Value {
+        let mut tup = Tuple::new();
+        tup.add_string(self.name.clone());
+        tup.add_i64(self.start);
+        tup.add_i64(self.end);
+        tup.add_string(self.recurrence.clone().unwrap_or_default());
+        tup.add_uuid(self.target.id);
+        tup.add_string(self.verb.clone());
+        match self.next {
+            Some(next) => tup.add_i64(next),
+            None => tup.add_null(),
+        }
+        match self.owner {
+            Some(owner) => tup.add_uuid(owner.id),
+            None => tup.add_null(),
+        }
+        tup.pack().into()
+    }
+
+    fn from_value(id: Uuid, value: fdb::Value) -> Self {
+        let tup = Tuple::from_bytes(value).unwrap();
+        let recurrence = tup.get_string_ref(3).unwrap().clone();
+        CalendarEvent {
+            id,
+            name: tup.get_string_ref(0).unwrap().clone(),
+            start: tup.get_i64(1).unwrap(),
+            end: tup.get_i64(2).unwrap(),
+            recurrence: (!recurrence.is_empty()).then_some(recurrence),
+            target: Oid {
+                id: *tup.get_uuid_ref(4).unwrap(),
+            },
+            verb: tup.get_string_ref(5).unwrap().clone(),
+            next: tup.get_i64(6).ok(),
+            // (Events added before owners were recorded have none.)
+            owner: tup.get_uuid_ref(7).ok().map(|id| Oid { id: *id }),
+        }
+    }
+
+    /// Move its next occurrence to `start`, and those after it to follow on by its recurrence. A
+    /// one-off event is moved as a whole, lasting as long as it did.
+    pub fn reschedule(&mut self, start: i64) {
+        if self.recurrence.is_none() {
+            self.end = start + self.duration();
+            self.start = start;
+        }
+        self.next = Some(start);
+    }
+
+    /// A Vector of [name, value] pairs: its "id", "name", "start", "end", "recurrence" (if it
+    /// recurs), "target", "verb", the "next" occurrence's start (if there is one) and its "owner"
+    /// (if it has one).
+    pub fn info(&self) -> Value {
+        let field = |name: &str, value| Value::Vector(vec![Value::String(name.into()), value]);
+        let mut info = vec![
+            field("id", Value::IdKey(Oid { id: self.id })),
+            field("name", Value::String(self.name.as_str().into())),
+            field("start", Value::Timestamp(self.start)),
+            field("end", Value::Timestamp(self.end)),
+        ];
+        if let Some(recurrence) = &self.recurrence {
+            info.push(field(
+                "recurrence",
+                Value::String(recurrence.as_str().into()),
+            ));
+        }
+        info.push(field("target", Value::IdKey(self.target)));
+        info.push(field("verb", Value::String(self.verb.as_str().into())));
+        if let Some(next) = self.next {
+            info.push(field("next", Value::Timestamp(next)));
+        }
+        if let Some(owner) = self.owner {
+            info.push(field("owner", Value::IdKey(owner)));
+        }
+        Value::Vector(info)
+    }
+}
+
+/// The world's calendar. Events are kept by id, and indexed by when their next occurrences start
+/// so that those coming up can be found without reading every event.
+pub struct CalendarTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+fn events_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("CALENDAR".as_bytes()))
+}
+
+fn event_key(id: Uuid) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_uuid(id);
+    events_subspace().subspace(&tup).pack().into()
+}
+
+// Not prefixed with CALENDAR, so that reading every event doesn't read these too.
+fn due_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("EVENT_DUE".as_bytes()))
+}
+
+fn due_key(next: i64, id: Uuid) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_i64(next);
+    tup.add_uuid(id);
+    due_subspace().subspace(&tup).pack().into()
+}
+
+impl<'tx_lifetime> CalendarTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        CalendarTxHandle { tr: tx }
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<CalendarEvent>, DbError> {
+        let value = self.tr.get(event_key(id)).await?;
+        Ok(value.map(|value| CalendarEvent::from_value(id, value)))
+    }
+
+    /// Add `event`, or replace the event with its id.
+    pub async fn put(&self, event: &CalendarEvent) -> Result<(), DbError> {
+        self.remove(event.id).await?;
+        self.tr.set(event_key(event.id), event.value());
+        if let Some(next) = event.next {
+            self.tr.set(due_key(next, event.id), Bytes::new());
+        }
+        Ok(())
+    }
+
+    /// Remove the event `id`. False if there was none.
+    pub async fn remove(&self, id: Uuid) -> Result<bool, DbError> {
+        let event = match self.get(id).await? {
+            Some(event) => event,
+            None => return Ok(false),
+        };
+        self.tr.clear(event_key(id));
+        if let Some(next) = event.next {
+            self.tr.clear(due_key(next, id));
+        }
+        Ok(true)
+    }
+
+    /// Every event, in no particular order.
+    pub async fn all(&self) -> Result<Vec<CalendarEvent>, DbError> {
+        let mut stream = self.tr.get_range(events_subspace().range(&Tuple::new()));
+        let mut events = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, value) = kv?;
+            let key_bytes: Bytes = key.into();
+            let tuple = events_subspace().unpack(&key_bytes).unwrap();
+            events.push(CalendarEvent::from_value(
+                *tuple.get_uuid_ref(0).unwrap(),
+                value,
+            ));
+        }
+        Ok(events)
+    }
+
+    /// The events `owner` added, soonest first, with those which have had their last occurrences
+    /// after the rest. This reads every event.
+    pub async fn owned_by(&self, owner: Oid) -> Result<Vec<CalendarEvent>, DbError> {
+        let mut events: Vec<CalendarEvent> = self
+            .all()
+            .await?
+            .into_iter()
+            .filter(|event| event.owner == Some(owner))
+            .collect();
+        events.sort_by_key(|event| event.next.unwrap_or(i64::MAX));
+        Ok(events)
+    }
+
+    /// The events whose next occurrences start by `until`, soonest first.
+    pub async fn due(&self, until: i64) -> Result<Vec<CalendarEvent>, DbError> {
+        let mut tup = Tuple::new();
+        tup.add_i64(until.saturating_add(1));
+        let range = Range::new(due_subspace().pack(), due_subspace().subspace(&tup).pack());
+        let mut stream = self.tr.get_range(range);
+        let mut ids = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, _) = kv?;
+            let key_bytes: Bytes = key.into();
+            let tuple = due_subspace().unpack(&key_bytes).unwrap();
+            ids.push(*tuple.get_uuid_ref(1).unwrap());
+        }
+        let mut events = vec![];
+        for id in ids {
+            events.extend(self.get(id).await?);
+        }
+        Ok(events)
+    }
+}
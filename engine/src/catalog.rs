@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Error};
+use tracing::info;
+
+/// The locale text is given in when there's none for a connection's own.
+pub const DEFAULT_LOCALE: &str = "en";
+
+// English text for the engine's own messages, so there's always something to fall back to.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("error.slot_does_not_exist", "No such slot or verb."),
+    ("error.invalid_program", "That isn't a valid program."),
+    ("error.permission_denied", "Permission denied."),
+    (
+        "error.internal_error",
+        "Something went wrong on the server.",
+    ),
+    ("error.bad_type", "An argument was of the wrong type."),
+    ("error.name_taken", "That name is already taken."),
+    (
+        "error.second_factor_required",
+        "A second factor is required to log in.",
+    ),
+    (
+        "error.resource_limit",
+        "The verb ran out of time or memory, and was rolled back.",
+    ),
+    (
+        "error.quota_exceeded",
+        "That would take you over your quota of objects or storage.",
+    ),
+    (
+        "error.contended",
+        "The server is too busy with the same things right now; try again.",
+    ),
+    (
+        "error.peer_unavailable",
+        "That object is in another world, which can't be reached right now.",
+    ),
+    ("error.trapped", "The verb failed, and was rolled back."),
+];
+
+/// Human readable text for messages, by key, in each locale it's been translated to.
+///
+/// Locales are BCP 47 tags, e.g. `pt-BR`. Lookups fall back from a region's locale to its
+/// language's, then to DEFAULT_LOCALE.
+pub struct Catalog {
+    locales: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        let built_in = BUILT_IN
+            .iter()
+            .map(|(key, text)| (key.to_string(), text.to_string()))
+            .collect();
+        Catalog {
+            locales: HashMap::from([(DEFAULT_LOCALE.to_string(), built_in)]),
+        }
+    }
+}
+
+impl Catalog {
+    /// The built in catalog, with translations added from each `<locale>.json` file in `dir`, an
+    /// object mapping keys to text. Text for the default locale replaces the built in text.
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let mut catalog = Catalog::default();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let locale = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(stem) => stem.to_lowercase(),
+                None => continue,
+            };
+            let text: HashMap<String, String> = serde_json::from_slice(&std::fs::read(&path)?)
+                .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+            info!("Loaded {} messages for locale {}", text.len(), locale);
+            catalog.locales.entry(locale).or_default().extend(text);
+        }
+        Ok(catalog)
+    }
+
+    /// The text for `key` in `locale`, or the nearest one there's text for.
+    pub fn lookup(&self, locale: Option<&str>, key: &str) -> Option<&str> {
+        let locale = locale.unwrap_or(DEFAULT_LOCALE).to_lowercase();
+        let language = locale.split('-').next().unwrap_or(DEFAULT_LOCALE);
+        [locale.as_str(), language, DEFAULT_LOCALE]
+            .iter()
+            .find_map(|l| self.locales.get(*l).and_then(|text| text.get(key)))
+            .map(|text| text.as_str())
+    }
+}
+
+/// The locale a client most prefers, going by the weights in an Accept-Language header. Wildcards
+/// are ignored.
+pub fn preferred_locale(accept_language: &str) -> Option<String> {
+    let mut best: Option<(f32, &str)> = None;
+    for range in accept_language.split(',') {
+        let mut parts = range.split(';');
+        let tag = parts.next().unwrap_or("").trim();
+        let weight = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.parse().ok())
+            .unwrap_or(1.0);
+        if tag.is_empty() || tag == "*" {
+            continue;
+        }
+        if best.is_none_or(|(w, _)| weight > w) {
+            best = Some((weight, tag));
+        }
+    }
+    best.map(|(_, tag)| tag.to_string())
+}
@@ -0,0 +1,354 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{range::Range, tuple::Tuple, Key};
+use futures::future::BoxFuture;
+use serde::{Serialize, Serializer};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::database::{DbError, Tx};
+use crate::object::SlotDef;
+use value::Oid;
+
+/// Changes read from the journal at a time, by default.
+pub const CHANGES_BATCH: usize = 1000;
+
+/// Options for journaling changes to slots, for replication, indexers and watches to follow.
+#[derive(Clone, Copy, Debug)]
+pub struct ChangeJournalOptions {
+    /// Changes older than this are deleted.
+    pub retention: Duration,
+}
+
+/// Where a change falls in the order transactions committed in: the ten bytes of the versionstamp
+/// of the transaction which made it, then two ordering it among that transaction's changes. Shown
+/// as hex.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Versionstamp(pub [u8; 12]);
+
+impl Versionstamp {
+    /// Before every change.
+    pub const ZERO: Versionstamp = Versionstamp([0; 12]);
+}
+
+impl fmt::Display for Versionstamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Versionstamp {
+    type Err = ();
+
+    fn from_str(hex: &str) -> Result<Self, ()> {
+        if hex.len() != 24 || !hex.is_ascii() {
+            return Err(());
+        }
+        let mut stamp = [0; 12];
+        for (i, byte) in stamp.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| ())?;
+        }
+        Ok(Versionstamp(stamp))
+    }
+}
+
+impl Serialize for Versionstamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// What happened to a slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// It was set, or its metadata was.
+    Set = 0,
+    Cleared = 1,
+    /// Its object was destroyed, and every slot on it with it. The change's key is nil and its
+    /// name empty.
+    Destroyed = 2,
+}
+
+/// A change to a slot, as the journal has it: not the value, but a digest of it, enough to tell
+/// whether a copy of it is current.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChangeRecord {
+    pub versionstamp: Versionstamp,
+    /// When it was made, in microseconds since the Unix epoch.
+    pub timestamp: i64,
+    pub kind: ChangeKind,
+    pub location: Oid,
+    pub key: Oid,
+    pub name: String,
+    /// The SHA-256 digest of the value it was set to, in hex; None unless it was set.
+    pub hash: Option<String>,
+}
+
+// Changes are keyed by "CHANGES" then the versionstamp, which the database fills in as the
+// transaction commits, so that they're in the order they took effect in however the transactions
+// which made them interleaved. (JOURNAL is the message journal's.)
+const CHANGES_PREFIX: &[u8] = b"CHANGES";
+
+// Just past every change's key.
+const CHANGES_END: &[u8] = b"CHANGET";
+
+// Added to as changes are journaled, so that tailing can wait on it rather than poll.
+const CHANGES_COUNT: &[u8] = b"CHANGE_COUNT";
+
+fn micros(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64
+}
+
+// The first key after that of the change at `stamp`.
+fn after(stamp: Versionstamp) -> Key {
+    let mut key = CHANGES_PREFIX.to_vec();
+    key.extend_from_slice(&stamp.0);
+    key.push(0);
+    Bytes::from(key).into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl ChangeRecord {
+    fn from_kv(key: Key, value: fdb::Value) -> Option<Self> {
+        let key: Bytes = key.into();
+        let versionstamp = Versionstamp(key.get(CHANGES_PREFIX.len()..)?.try_into().ok()?);
+        let tuple = Tuple::from_bytes(value).ok()?;
+        let kind = match tuple.get_i8(1).ok()? {
+            0 => ChangeKind::Set,
+            1 => ChangeKind::Cleared,
+            _ => ChangeKind::Destroyed,
+        };
+        Some(ChangeRecord {
+            versionstamp,
+            timestamp: tuple.get_i64(0).ok()?,
+            kind,
+            location: Oid {
+                id: *tuple.get_uuid_ref(2).ok()?,
+            },
+            key: Oid {
+                id: *tuple.get_uuid_ref(3).ok()?,
+            },
+            name: tuple.get_string_ref(4).ok()?.clone(),
+            hash: tuple.get_bytes_ref(5).ok().map(|hash| hex(hash)),
+        })
+    }
+}
+
+// Reads and writes the change journal via one transaction.
+pub struct ChangesTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> ChangesTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        ChangesTxHandle { tr: tx }
+    }
+
+    /// Journal `kind` of change to `slot`, with the digest of the value it was set to, if it was.
+    pub fn record(&self, kind: ChangeKind, slot: &SlotDef, hash: Option<&[u8]>) {
+        let mut tup = Tuple::new();
+        tup.add_i64(micros(SystemTime::now()));
+        tup.add_i8(kind as i8);
+        tup.add_uuid(slot.location.id);
+        tup.add_uuid(slot.key.id);
+        tup.add_string(slot.name.clone());
+        match hash {
+            Some(hash) => tup.add_bytes(Bytes::copy_from_slice(hash)),
+            None => tup.add_null(),
+        }
+        self.tr.set_versionstamped(CHANGES_PREFIX, tup.pack());
+        self.tr.add(Bytes::from_static(CHANGES_COUNT), 1);
+    }
+
+    /// Journal the destruction of `location`.
+    pub fn record_destroyed(&self, location: Oid) {
+        let slot = SlotDef {
+            location,
+            key: Oid { id: Uuid::nil() },
+            name: String::new(),
+        };
+        self.record(ChangeKind::Destroyed, &slot, None);
+    }
+
+    /// Up to `limit` of the changes after `since`, in the order they were made.
+    pub async fn since(
+        &self,
+        since: Versionstamp,
+        limit: usize,
+    ) -> Result<Vec<ChangeRecord>, DbError> {
+        let range = Range::new(after(since), Bytes::from_static(CHANGES_END));
+        let mut stream = self.tr.snapshot_get_range(range);
+        let mut changes = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, value) = kv?;
+            changes.extend(ChangeRecord::from_kv(key, value));
+            if changes.len() >= limit {
+                break;
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Where the journal ends: the position of the last change journaled, if any has been (and
+    /// not pruned). Only that change is read.
+    pub async fn last(&self) -> Result<Option<Versionstamp>, DbError> {
+        let all = Range::new(
+            Bytes::from_static(CHANGES_PREFIX),
+            Bytes::from_static(CHANGES_END),
+        );
+        Ok(self
+            .tr
+            .snapshot_last_in_range(all)
+            .await?
+            .and_then(|(key, value)| ChangeRecord::from_kv(key, value))
+            .map(|change| change.versionstamp))
+    }
+
+    /// Delete up to `limit` of the changes made before `cutoff`, oldest first, returning how many
+    /// were. Only the changes being deleted are read, as they're the oldest.
+    pub async fn prune(&self, cutoff: SystemTime, limit: usize) -> Result<usize, DbError> {
+        let cutoff = micros(cutoff);
+        let all = Range::new(
+            Bytes::from_static(CHANGES_PREFIX),
+            Bytes::from_static(CHANGES_END),
+        );
+        let mut stream = self.tr.snapshot_get_range(all);
+        let mut pruned = 0;
+        let mut last = None;
+        while pruned < limit {
+            let (key, value) = match stream.next().await {
+                Some(kv) => kv?,
+                None => break,
+            };
+            match ChangeRecord::from_kv(key, value) {
+                Some(change) if change.timestamp < cutoff => {
+                    last = Some(change.versionstamp);
+                    pruned += 1;
+                }
+                _ => break,
+            }
+        }
+        if let Some(last) = last {
+            self.tr
+                .clear_range(Range::new(Bytes::from_static(CHANGES_PREFIX), after(last)));
+        }
+        Ok(pruned)
+    }
+
+    /// A future which resolves once more changes have been journaled, after the transaction
+    /// commits.
+    pub fn watch(&self) -> BoxFuture<'static, Result<(), DbError>> {
+        self.tr.watch(Bytes::from_static(CHANGES_COUNT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(last: u8) -> Versionstamp {
+        let mut stamp = [0x11; 12];
+        stamp[11] = last;
+        Versionstamp(stamp)
+    }
+
+    fn change_key(stamp: Versionstamp) -> Key {
+        let mut key = CHANGES_PREFIX.to_vec();
+        key.extend_from_slice(&stamp.0);
+        Bytes::from(key).into()
+    }
+
+    fn change_value(kind: ChangeKind, location: Oid, hash: Option<&[u8]>) -> fdb::Value {
+        let mut tup = Tuple::new();
+        tup.add_i64(1_700_000_000_000_000);
+        tup.add_i8(kind as i8);
+        tup.add_uuid(location.id);
+        tup.add_uuid(Uuid::nil());
+        tup.add_string(String::from("name"));
+        match hash {
+            Some(hash) => tup.add_bytes(Bytes::copy_from_slice(hash)),
+            None => tup.add_null(),
+        }
+        tup.pack().into()
+    }
+
+    #[test]
+    fn versionstamps_round_trip_through_hex() {
+        let stamp = stamp(0xab);
+        let hex = stamp.to_string();
+        assert_eq!(hex, "1111111111111111111111ab");
+        assert_eq!(hex.parse::<Versionstamp>(), Ok(stamp));
+        assert_eq!(
+            Versionstamp::ZERO.to_string().parse::<Versionstamp>(),
+            Ok(Versionstamp::ZERO)
+        );
+    }
+
+    #[test]
+    fn malformed_versionstamps_are_refused() {
+        assert!("".parse::<Versionstamp>().is_err());
+        assert!("1111111111111111111111".parse::<Versionstamp>().is_err());
+        assert!("1111111111111111111111abcd"
+            .parse::<Versionstamp>()
+            .is_err());
+        assert!("11111111111111111111zz11".parse::<Versionstamp>().is_err());
+        assert!("1111111111111111111111é".parse::<Versionstamp>().is_err());
+    }
+
+    #[test]
+    fn changes_are_read_back_from_the_journal() {
+        let location = Oid {
+            id: Uuid::from_u128(7),
+        };
+        let change = ChangeRecord::from_kv(
+            change_key(stamp(1)),
+            change_value(ChangeKind::Set, location, Some(&[0xde, 0xad])),
+        )
+        .unwrap();
+        assert_eq!(change.versionstamp, stamp(1));
+        assert_eq!(change.timestamp, 1_700_000_000_000_000);
+        assert_eq!(change.kind, ChangeKind::Set);
+        assert_eq!(change.location, location);
+        assert_eq!(change.name, "name");
+        assert_eq!(change.hash.as_deref(), Some("dead"));
+
+        let cleared = ChangeRecord::from_kv(
+            change_key(stamp(2)),
+            change_value(ChangeKind::Cleared, location, None),
+        )
+        .unwrap();
+        assert_eq!(cleared.kind, ChangeKind::Cleared);
+        assert_eq!(cleared.hash, None);
+    }
+
+    #[test]
+    fn malformed_changes_are_skipped() {
+        let value = change_value(ChangeKind::Set, Oid { id: Uuid::nil() }, None);
+        let short: Key = Bytes::from_static(b"CHANGES\x11").into();
+        assert!(ChangeRecord::from_kv(short, value).is_none());
+        let garbage: fdb::Value = Bytes::from_static(b"\xff\xff").into();
+        assert!(ChangeRecord::from_kv(change_key(stamp(1)), garbage).is_none());
+    }
+
+    #[test]
+    fn changes_after_a_stamp_start_past_it() {
+        let key = |stamp| Bytes::from(change_key(stamp));
+        let past = Bytes::from(after(stamp(1)));
+        assert!(key(stamp(1)) < past);
+        assert!(past < key(stamp(2)));
+        assert!(Bytes::from(after(Versionstamp::ZERO)) < key(stamp(0)));
+        assert!(key(stamp(0xff)) < Bytes::from_static(CHANGES_END));
+    }
+}
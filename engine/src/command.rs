@@ -0,0 +1,306 @@
+use crate::contents::{ContentsTxHandle, LOCATION_SLOT};
+use crate::database::Tx;
+use crate::fdb_object::ObjDBTxHandle;
+use crate::object::ObjDBHandle;
+use value::Error::{NameTaken, SlotDoesNotExist};
+use value::{Oid, Value};
+
+/// Words which separate a command's direct object from its indirect object, by default.
+pub const DEFAULT_PREPOSITIONS: &[&str] = &[
+    "with", "using", "at", "to", "in", "inside", "into", "on", "onto", "upon", "from", "under",
+    "beneath", "behind", "over", "through", "about", "for", "off",
+];
+
+/// What the names of the verbs commands run start with, by default.
+pub const DEFAULT_VERB_PREFIX: &str = "cmd_";
+
+/// How lines of text from players are read as commands, of the form `verb [dobj] [prep iobj]`:
+/// "put lamp on table", "look", "say \"hello there\"".
+#[derive(Clone, Debug)]
+pub struct CommandGrammar {
+    /// Words which end the direct object and start the indirect object. Matched without regard
+    /// to case; the first in the line is the one used.
+    pub prepositions: Vec<String>,
+    /// What the names of the verbs commands run start with, so that a player can't run any verb
+    /// by typing its name: "look" runs the verb 'cmd_look'.
+    pub verb_prefix: String,
+}
+
+impl Default for CommandGrammar {
+    fn default() -> Self {
+        CommandGrammar {
+            prepositions: DEFAULT_PREPOSITIONS.iter().map(|p| p.to_string()).collect(),
+            verb_prefix: String::from(DEFAULT_VERB_PREFIX),
+        }
+    }
+}
+
+/// A line of text read as a command, before the objects it names are looked for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsedCommand {
+    pub verb: String,
+    pub dobj: Option<String>,
+    pub prep: Option<String>,
+    pub iobj: Option<String>,
+}
+
+impl CommandGrammar {
+    /// The name of the verb a command runs.
+    pub fn verb_name(&self, command: &ParsedCommand) -> String {
+        format!("{}{}", self.verb_prefix, command.verb)
+    }
+
+    /// `line` read as a command, or None if it's blank.
+    pub fn parse(&self, line: &str) -> Option<ParsedCommand> {
+        let mut words = tokenize(line).into_iter();
+        let verb = words.next()?.to_lowercase();
+        let words: Vec<String> = words.collect();
+        let at = words.iter().position(|word| {
+            self.prepositions
+                .iter()
+                .any(|p| p.eq_ignore_ascii_case(word))
+        });
+        let (dobj, prep, iobj) = match at {
+            Some(at) => (
+                &words[..at],
+                Some(words[at].to_lowercase()),
+                &words[at + 1..],
+            ),
+            None => (&words[..], None, &words[words.len()..]),
+        };
+        let phrase = |words: &[String]| (!words.is_empty()).then(|| words.join(" "));
+        Some(ParsedCommand {
+            verb,
+            dobj: phrase(dobj),
+            prep,
+            iobj: phrase(iobj),
+        })
+    }
+}
+
+/// The words of `line`, split on whitespace, except that text in double quotes is kept together
+/// as one word (without its quotes).
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// The objects a player can refer to by name: themself, their location, and whatever's in
+/// either, by the contents index.
+pub struct CommandScope {
+    pub player: Oid,
+    pub location: Option<Oid>,
+    pub objects: Vec<Oid>,
+}
+
+impl CommandScope {
+    pub async fn of(tr: &Tx, player: Oid) -> Self {
+        let odb = ObjDBTxHandle::new(tr);
+        let cdb = ContentsTxHandle::new(tr);
+        let location = match odb
+            .get_slot(player, player, String::from(LOCATION_SLOT))
+            .await
+        {
+            Ok(Value::IdKey(location)) => Some(location),
+            _ => None,
+        };
+        let mut objects = vec![player];
+        objects.extend(location);
+        for holder in [Some(player), location].into_iter().flatten() {
+            if let Ok(contents) = cdb.contents(holder).await {
+                for oid in contents {
+                    if !objects.contains(&oid) {
+                        objects.push(oid);
+                    }
+                }
+            }
+        }
+        CommandScope {
+            player,
+            location,
+            objects,
+        }
+    }
+
+    /// The object in scope `name` refers to: "me" is the player and "here" their location;
+    /// otherwise whichever object has it as its 'name' slot or among its 'aliases', without
+    /// regard to case. SlotDoesNotExist if there's none, NameTaken if there's more than one.
+    pub async fn resolve(&self, tr: &Tx, name: &str) -> Value {
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("me") {
+            return Value::IdKey(self.player);
+        }
+        if name.eq_ignore_ascii_case("here") {
+            return self
+                .location
+                .map_or(Value::Error(SlotDoesNotExist), Value::IdKey);
+        }
+        let odb = ObjDBTxHandle::new(tr);
+        let mut matched = None;
+        for &oid in &self.objects {
+            let mut names = vec![];
+            if let Ok(Value::String(own)) = odb.get_slot(oid, oid, String::from("name")).await {
+                names.push(own);
+            }
+            if let Ok(Value::Vector(aliases)) =
+                odb.get_slot(oid, oid, String::from("aliases")).await
+            {
+                names.extend(aliases.into_iter().filter_map(|alias| match alias {
+                    Value::String(alias) => Some(alias),
+                    _ => None,
+                }));
+            }
+            if names.iter().any(|n| n.trim().eq_ignore_ascii_case(name)) {
+                if matched.is_some() {
+                    return Value::Error(NameTaken);
+                }
+                matched = Some(oid);
+            }
+        }
+        matched.map_or(Value::Error(SlotDoesNotExist), Value::IdKey)
+    }
+
+    /// The object a part of a command refers to, or the text as given if it refers to nothing
+    /// in scope; an empty String if the command has no such part.
+    pub async fn object(&self, tr: &Tx, phrase: &Option<String>) -> Value {
+        match phrase {
+            Some(phrase) => match self.resolve(tr, phrase).await {
+                Value::IdKey(oid) => Value::IdKey(oid),
+                _ => Value::String(phrase.as_str().into()),
+            },
+            None => Value::String("".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Option<ParsedCommand> {
+        CommandGrammar::default().parse(line)
+    }
+
+    fn command(
+        verb: &str,
+        dobj: Option<&str>,
+        prep: Option<&str>,
+        iobj: Option<&str>,
+    ) -> ParsedCommand {
+        ParsedCommand {
+            verb: verb.to_string(),
+            dobj: dobj.map(String::from),
+            prep: prep.map(String::from),
+            iobj: iobj.map(String::from),
+        }
+    }
+
+    #[test]
+    fn words_are_split_on_whitespace() {
+        assert_eq!(
+            tokenize("  put   lamp\ton table "),
+            vec!["put", "lamp", "on", "table"]
+        );
+        assert!(tokenize("   ").is_empty());
+    }
+
+    #[test]
+    fn quoted_text_is_one_word() {
+        assert_eq!(tokenize("say \"hello there\""), vec!["say", "hello there"]);
+        assert_eq!(tokenize("say \"\""), vec!["say"]);
+        // An unclosed quote runs to the end of the line.
+        assert_eq!(tokenize("say \"hello  there"), vec!["say", "hello  there"]);
+    }
+
+    #[test]
+    fn blank_lines_are_not_commands() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse(" \t "), None);
+    }
+
+    #[test]
+    fn verbs_alone() {
+        assert_eq!(parse("look"), Some(command("look", None, None, None)));
+        assert_eq!(parse("LOOK"), Some(command("look", None, None, None)));
+    }
+
+    #[test]
+    fn direct_and_indirect_objects() {
+        assert_eq!(
+            parse("get brass lamp"),
+            Some(command("get", Some("brass lamp"), None, None))
+        );
+        assert_eq!(
+            parse("put brass lamp ON the table"),
+            Some(command(
+                "put",
+                Some("brass lamp"),
+                Some("on"),
+                Some("the table")
+            ))
+        );
+        assert_eq!(
+            parse("look at painting"),
+            Some(command("look", None, Some("at"), Some("painting")))
+        );
+        assert_eq!(
+            parse("put lamp in"),
+            Some(command("put", Some("lamp"), Some("in"), None))
+        );
+    }
+
+    #[test]
+    fn the_first_preposition_is_used() {
+        assert_eq!(
+            parse("give book to bob with care"),
+            Some(command(
+                "give",
+                Some("book"),
+                Some("to"),
+                Some("bob with care")
+            ))
+        );
+    }
+
+    #[test]
+    fn prepositions_in_quotes_are_part_of_the_phrase() {
+        assert_eq!(
+            parse("say \"hello on there\""),
+            Some(command("say", Some("hello on there"), None, None))
+        );
+    }
+
+    #[test]
+    fn grammars_can_differ() {
+        let grammar = CommandGrammar {
+            prepositions: vec![String::from("avec")],
+            verb_prefix: String::from("do_"),
+        };
+        let parsed = grammar.parse("frappe troll avec épée").unwrap();
+        assert_eq!(
+            parsed,
+            command("frappe", Some("troll"), Some("avec"), Some("épée"))
+        );
+        assert_eq!(grammar.verb_name(&parsed), "do_frappe");
+        assert_eq!(
+            CommandGrammar::default().verb_name(&command("look", None, None, None)),
+            "cmd_look"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::path::Path;
+
+use wasmtime::{Engine, ExternType, FuncType, Module, ValType};
+
+use crate::{js_vm, lua_vm};
+use value::{Program, ProgramLang};
+
+/// Why a program couldn't be used as a verb.
+#[derive(Clone, Debug)]
+pub enum CompileError {
+    /// Not valid WAT or a valid wasm module. Carries the parser or validator's explanation,
+    /// including the location of the problem.
+    Invalid(String),
+    /// The module doesn't export something every verb must.
+    MissingExport(&'static str),
+    /// An export has the wrong kind or signature.
+    BadExport(&'static str),
+    /// The program is in a language there's no VM for.
+    Unsupported(ProgramLang),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Invalid(e) => write!(f, "{}", e),
+            CompileError::MissingExport(name) => write!(f, "Program must export '{}'", name),
+            CompileError::BadExport(name) => match *name {
+                "invoke" => write!(
+                    f,
+                    "'invoke' must be a function (i32) -> (i32, i32), or (i32) -> i64"
+                ),
+                _ => write!(f, "'{}' must be a memory", name),
+            },
+            CompileError::Unsupported(lang) => write!(f, "Can't run {:?} programs", lang),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Read the program in the file at `path`: JavaScript if it's named `.js`, Lua if it's named
+/// `.lua`, and otherwise WAT or a wasm binary, whichever it is.
+pub fn read_program(path: &Path) -> std::io::Result<Program> {
+    let code = std::fs::read(path)?;
+    Ok(match path.extension().and_then(|e| e.to_str()) {
+        Some("js") => Program::new(ProgramLang::JavaScript, code),
+        Some("lua") => Program::new(ProgramLang::Lua, code),
+        _ => Program::wasm(code),
+    })
+}
+
+/// Check that a program could be run as a verb, in whichever language it's written: compiling it
+/// if it's WebAssembly, or parsing it if it's Lua or JavaScript.
+pub fn check_program(engine: &Engine, program: &Program) -> Result<(), CompileError> {
+    match program.lang {
+        ProgramLang::Lua => lua_vm::check(program).map_err(CompileError::Invalid),
+        ProgramLang::JavaScript => js_vm::check(program).map_err(CompileError::Invalid),
+        _ => compile(engine, program).map(|_| ()),
+    }
+}
+
+/// Compile a program from either WAT text or a wasm binary, as its language says, checking that
+/// it's usable as a verb: it must export an 'invoke' function taking the length of its arguments
+/// and returning the location and length of its result (or both packed into an i64, see
+/// `wasm_vm::PACKED_HOST_MODULE`), and the 'memory' they're passed through.
+pub fn compile(engine: &Engine, program: &Program) -> Result<Module, CompileError> {
+    let module = match program.lang {
+        ProgramLang::Wat => std::str::from_utf8(&program.code)
+            .map_err(|e| CompileError::Invalid(format!("WAT isn't UTF-8: {}", e)))
+            .and_then(|text| {
+                Module::new(engine, text).map_err(|e| CompileError::Invalid(format!("{:#}", e)))
+            })?,
+        ProgramLang::Wasm => Module::from_binary(engine, &program.code)
+            .map_err(|e| CompileError::Invalid(format!("{:#}", e)))?,
+        lang => return Err(CompileError::Unsupported(lang)),
+    };
+
+    match module.get_export("invoke") {
+        Some(ExternType::Func(func)) => {
+            let expected = FuncType::new([ValType::I32], [ValType::I32, ValType::I32]);
+            let packed = FuncType::new([ValType::I32], [ValType::I64]);
+            if func != expected && func != packed {
+                return Err(CompileError::BadExport("invoke"));
+            }
+        }
+        Some(_) => return Err(CompileError::BadExport("invoke")),
+        None => return Err(CompileError::MissingExport("invoke")),
+    }
+    match module.get_export("memory") {
+        Some(ExternType::Memory(_)) => {}
+        Some(_) => return Err(CompileError::BadExport("memory")),
+        None => return Err(CompileError::MissingExport("memory")),
+    }
+
+    Ok(module)
+}
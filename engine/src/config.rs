@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+
+use crate::database::{Storage, DEFAULT_CLUSTER_FILE};
+use crate::listeners::{check_listener_names, ListenerConfig};
+
+/// The configuration file read at startup if it's there and no other is named.
+pub const DEFAULT_CONFIG_PATH: &str = "room.toml";
+
+/// Which storage backend to keep the world in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    #[default]
+    Fdb,
+    Embedded,
+}
+
+/// The server's configuration, as read from a room.toml file. Anything the file leaves out has
+/// the default the command line flag of the same name has, and the flags override the file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen_address: String,
+    pub telnet_address: Option<String>,
+    pub metrics_address: Option<String>,
+    pub admin_address: Option<String>,
+    pub admin_token_file: Option<PathBuf>,
+    /// Further listeners, as `[[listeners]]` tables.
+    pub listeners: Vec<ListenerConfig>,
+    pub storage: StorageBackend,
+    pub storage_path: PathBuf,
+    pub fdb_cluster_file: Option<PathBuf>,
+    pub dump_path: PathBuf,
+    pub fuel_limit: u64,
+    pub memory_limit_mb: usize,
+    pub time_limit_ms: u64,
+    /// Seconds between checkpoints, if there are to be any.
+    pub checkpoint_interval: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen_address: "127.0.0.1:9002".to_string(),
+            telnet_address: None,
+            metrics_address: None,
+            admin_address: None,
+            admin_token_file: None,
+            listeners: vec![],
+            storage: StorageBackend::default(),
+            storage_path: "room.db".into(),
+            fdb_cluster_file: None,
+            dump_path: "dump".into(),
+            fuel_limit: 100_000_000,
+            memory_limit_mb: 64,
+            time_limit_ms: 5000,
+            checkpoint_interval: None,
+        }
+    }
+}
+
+impl Config {
+    /// Read the configuration file at `path`.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)?;
+        let config: Config =
+            toml::from_str(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        check_listener_names(&config.listeners)?;
+        Ok(config)
+    }
+
+    /// The storage the configuration names; FoundationDB's located by its cluster file, or else
+    /// the client's default one.
+    pub fn storage(&self) -> Storage {
+        match self.storage {
+            StorageBackend::Fdb => Storage::Fdb(
+                self.fdb_cluster_file
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_CLUSTER_FILE.into()),
+            ),
+            StorageBackend::Embedded => Storage::Embedded(self.storage_path.clone()),
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::VecDeque;
+use std::io::{self, Stdout, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::MoveTo;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use futures::StreamExt;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+use uuid::Uuid;
+
+use room::dump::DumpTarget;
+use room::world::{boot, connection_summaries, save_all, set_admin, set_remote, World};
+use value::Oid;
+
+// Log lines kept for scrolling back through.
+const LOG_CAPACITY: usize = 5000;
+
+// How often the status header is redrawn, and new log lines shown.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+// Header, separator and prompt.
+const CHROME_ROWS: u16 = 3;
+
+const HELP: &[&str] = &[
+    "who                      list the connections to this server",
+    "boot <uuid> [reason]     close a connection",
+    "checkpoint               dump every object now",
+    "evict                    drop every compiled module",
+    "admin <uuid>             make a player an admin",
+    "unadmin <uuid>           make a player no longer an admin",
+    "remote <uuid> <peer>     list an object as a peer's",
+    "unremote <uuid>          list an object as no peer's",
+    "clear                    clear the log",
+    "quit                     shut the server down (as does ctrl-c)",
+    "PgUp/PgDn, Up/Down and End scroll the log.",
+];
+
+struct LogLine {
+    // None for the console's own output.
+    level: Option<Level>,
+    text: String,
+}
+
+#[derive(Default)]
+struct LogState {
+    lines: VecDeque<LogLine>,
+    attached: bool,
+}
+
+/// Where log lines are written while the console is showing: they're kept for it to draw, the
+/// most recent LOG_CAPACITY of them. Before the console starts, and once it's stopped, they're
+/// written to stdout as usual.
+#[derive(Clone, Default)]
+pub struct ConsoleLog {
+    state: Arc<Mutex<LogState>>,
+}
+
+impl ConsoleLog {
+    fn push(&self, level: Option<Level>, text: String) {
+        let mut state = self.state.lock().unwrap();
+        if !state.attached {
+            drop(state);
+            println!("{}", text);
+            return;
+        }
+        if state.lines.len() == LOG_CAPACITY {
+            state.lines.pop_front();
+        }
+        state.lines.push_back(LogLine { level, text });
+    }
+
+    fn note(&self, text: impl Into<String>) {
+        self.push(None, text.into());
+    }
+
+    fn set_attached(&self, attached: bool) {
+        self.state.lock().unwrap().attached = attached;
+    }
+}
+
+/// Collects a log event as it's formatted, and adds its lines to the log once it's done.
+pub struct LineWriter {
+    log: ConsoleLog,
+    level: Level,
+    buffer: Vec<u8>,
+}
+
+impl Write for LineWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LineWriter {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.buffer);
+        for line in text.lines() {
+            self.log.push(Some(self.level), line.to_string());
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for ConsoleLog {
+    type Writer = LineWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LineWriter {
+            log: self.clone(),
+            level: Level::INFO,
+            buffer: vec![],
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        LineWriter {
+            log: self.clone(),
+            level: *meta.level(),
+            buffer: vec![],
+        }
+    }
+}
+
+// Puts the terminal back as it was however the console stops, including by being aborted.
+struct TerminalGuard {
+    log: ConsoleLog,
+}
+
+impl TerminalGuard {
+    fn enter(log: ConsoleLog) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        log.set_attached(true);
+        Ok(TerminalGuard { log })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        self.log.set_attached(false);
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+struct Console {
+    world: Arc<World>,
+    dump_target: DumpTarget,
+    log: ConsoleLog,
+    input: String,
+    // Lines scrolled back from the most recent.
+    scroll: usize,
+    // The transactions committed as of the last sample, and when it was taken.
+    sampled: (Instant, u64),
+    tps: f64,
+}
+
+/// Show the interactive console on the terminal until it's quit: a header with the server's
+/// status, the recent log below it, and a prompt for admin commands at the bottom. Log lines are
+/// taken from `log`, which must be the subscriber's writer.
+pub async fn run(world: Arc<World>, dump_target: DumpTarget, log: ConsoleLog) -> io::Result<()> {
+    let _guard = TerminalGuard::enter(log.clone())?;
+    let mut console = Console {
+        sampled: (Instant::now(), world.transactions()),
+        world,
+        dump_target,
+        log,
+        input: String::new(),
+        scroll: 0,
+        tps: 0.0,
+    };
+    console.log.note("Type help for the console's commands.");
+
+    let mut stdout = io::stdout();
+    let mut events = EventStream::new();
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        console.draw(&mut stdout)?;
+        tokio::select! {
+            _ = refresh.tick() => console.sample(),
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) => {
+                    if let Flow::Quit = console.key(key).await {
+                        return Ok(());
+                    }
+                }
+                // Resizes are picked up as it's redrawn.
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+impl Console {
+    fn sample(&mut self) {
+        let now = Instant::now();
+        let transactions = self.world.transactions();
+        let elapsed = now.duration_since(self.sampled.0).as_secs_f64();
+        if elapsed >= 1.0 {
+            self.tps = (transactions - self.sampled.1) as f64 / elapsed;
+            self.sampled = (now, transactions);
+        }
+    }
+
+    async fn key(&mut self, key: KeyEvent) -> Flow {
+        let page =
+            terminal::size().map_or(1, |(_, rows)| rows.saturating_sub(CHROME_ROWS)) as usize;
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d')
+                if key.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                return Flow::Quit
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => {
+                let command = std::mem::take(&mut self.input);
+                self.scroll = 0;
+                return self.command(command.trim()).await;
+            }
+            KeyCode::PageUp => self.scroll += page,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(page),
+            KeyCode::Up => self.scroll += 1,
+            KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::End => self.scroll = 0,
+            _ => {}
+        }
+        Flow::Continue
+    }
+
+    async fn command(&mut self, command: &str) -> Flow {
+        if command.is_empty() {
+            return Flow::Continue;
+        }
+        self.log.note(format!("> {}", command));
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words[..] {
+            ["help"] => HELP.iter().for_each(|line| self.log.note(*line)),
+            ["who"] => {
+                let summaries = connection_summaries(&self.world);
+                if summaries.is_empty() {
+                    self.log.note("No connections.");
+                }
+                for summary in summaries {
+                    let player = summary
+                        .player
+                        .map_or("-".to_string(), |player| player.id.to_string());
+                    self.log.note(format!(
+                        "{}  {}  player {}  idle {}s  {} tasks",
+                        summary.id.id, summary.address, player, summary.idle_secs, summary.tasks
+                    ));
+                }
+            }
+            ["boot", id, ..] => match Uuid::parse_str(id) {
+                Ok(id) => {
+                    let reason = match command.splitn(3, ' ').nth(2) {
+                        Some(reason) => reason.trim(),
+                        None => "Booted by an admin",
+                    };
+                    if !boot(&self.world, Oid { id }, reason) {
+                        self.log.note(format!("No connection {}.", id));
+                    }
+                }
+                Err(_) => self.log.note(format!("Not a connection id: {}", id)),
+            },
+            ["checkpoint"] => {
+                self.log.note("Checkpointing...");
+                match save_all(self.world.clone(), &self.dump_target).await {
+                    Ok(()) => self.log.note("Checkpointed."),
+                    Err(e) => self.log.note(format!("Checkpoint failed: {}", e)),
+                }
+            }
+            [change @ ("admin" | "unadmin"), id] => match Uuid::parse_str(id) {
+                Ok(id) => {
+                    let admin = change == "admin";
+                    let state = if admin { "an admin" } else { "not an admin" };
+                    match set_admin(&self.world, Oid { id }, admin).await {
+                        Ok(true) => self.log.note(format!("{} is now {}.", id, state)),
+                        Ok(false) => self.log.note(format!("{} is {} already.", id, state)),
+                        Err(e) => self.log.note(format!("Could not change admins: {}", e)),
+                    }
+                }
+                Err(_) => self.log.note(format!("Not a player id: {}", id)),
+            },
+            ["remote", id, peer] => self.list_remote(id, Some(peer)).await,
+            ["unremote", id] => self.list_remote(id, None).await,
+            ["evict"] => {
+                self.world.module_cache().evict_all();
+                self.log.note("Evicted every compiled module.");
+            }
+            ["clear"] => self.log.state.lock().unwrap().lines.clear(),
+            ["quit"] => return Flow::Quit,
+            _ => self
+                .log
+                .note(format!("Unknown command {:?}; try help.", words[0])),
+        }
+        Flow::Continue
+    }
+
+    // List the object `id` as `peer`'s, or as no peer's.
+    async fn list_remote(&self, id: &str, peer: Option<&str>) {
+        let id = match Uuid::parse_str(id) {
+            Ok(id) => id,
+            Err(_) => return self.log.note(format!("Not an object id: {}", id)),
+        };
+        if let Some(peer) = peer.filter(|peer| self.world.federation().peer(peer).is_none()) {
+            return self.log.note(format!("No peer {}.", peer));
+        }
+        let state = match peer {
+            Some(peer) => format!("{}'s", peer),
+            None => String::from("no peer's"),
+        };
+        match set_remote(&self.world, Oid { id }, peer).await {
+            Ok(true) => self.log.note(format!("{} is now listed as {}.", id, state)),
+            Ok(false) => self
+                .log
+                .note(format!("{} is listed as {} already.", id, state)),
+            Err(e) => self.log.note(format!("Could not change peers: {}", e)),
+        }
+    }
+
+    fn draw(&mut self, out: &mut Stdout) -> io::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let width = columns as usize;
+        let (hits, misses) = self.world.module_cache().stats();
+        let hit_rate = match hits + misses {
+            0 => 0.0,
+            lookups => hits as f64 * 100.0 / lookups as f64,
+        };
+        let traffic = self.world.traffic();
+
+        // Every row is drawn over in full, rather than the screen cleared first, so that it
+        // doesn't flicker.
+        queue!(
+            out,
+            MoveTo(0, 0),
+            SetAttribute(Attribute::Reverse),
+            Print(" room "),
+            SetAttribute(Attribute::Reset),
+        )?;
+        let fields = [
+            ("connections", self.world.connection_count().to_string()),
+            ("tps", format!("{:.1}", self.tps)),
+            (
+                "cache hits",
+                format!("{} ({:.0}%, {} misses)", hits, hit_rate, misses),
+            ),
+            (
+                "traffic",
+                format!("{}B in, {}B out", traffic.bytes_in, traffic.bytes_out),
+            ),
+        ];
+        for (name, value) in fields {
+            queue!(
+                out,
+                Print("  "),
+                SetForegroundColor(Color::DarkGrey),
+                Print(name),
+                Print(" "),
+                SetForegroundColor(Color::White),
+                SetAttribute(Attribute::Bold),
+                Print(value),
+                SetAttribute(Attribute::Reset),
+            )?;
+        }
+        queue!(
+            out,
+            Clear(ClearType::UntilNewLine),
+            MoveTo(0, 1),
+            SetForegroundColor(Color::DarkGrey),
+            Print("─".repeat(width)),
+            ResetColor,
+        )?;
+
+        // The log, scrolled back from the end by as much as there is to scroll back through.
+        let height = rows.saturating_sub(CHROME_ROWS) as usize;
+        {
+            let state = self.log.state.lock().unwrap();
+            self.scroll = self.scroll.min(state.lines.len().saturating_sub(height));
+            let end = state.lines.len() - self.scroll;
+            let start = end.saturating_sub(height);
+            let mut lines = state.lines.range(start..end);
+            for row in 0..height {
+                let (level, text) = lines
+                    .next()
+                    .map_or((None, ""), |line| (line.level, line.text.as_str()));
+                queue!(
+                    out,
+                    MoveTo(0, 2 + row as u16),
+                    SetForegroundColor(color(level)),
+                    Print(fit(text, width)),
+                    ResetColor,
+                )?;
+            }
+        }
+
+        let prompt = match self.scroll {
+            0 => "> ".to_string(),
+            lines => format!("[{} up] > ", lines),
+        };
+        queue!(
+            out,
+            MoveTo(0, rows.saturating_sub(1)),
+            SetForegroundColor(Color::Cyan),
+            Print(&prompt),
+            ResetColor,
+            Print(fit(&self.input, width.saturating_sub(prompt.len()))),
+        )?;
+        out.flush()
+    }
+}
+
+fn color(level: Option<Level>) -> Color {
+    match level {
+        None => Color::Cyan,
+        Some(Level::ERROR) => Color::Red,
+        Some(Level::WARN) => Color::Yellow,
+        Some(Level::INFO) => Color::Green,
+        Some(Level::DEBUG) => Color::Blue,
+        Some(Level::TRACE) => Color::DarkGrey,
+    }
+}
+
+// `text` cut, or padded, to `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    format!("{:width$.width$}", text, width = width)
+}
@@ -0,0 +1,70 @@
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use tokio_stream::StreamExt;
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// The slot on an object which says where it is. It's kept by `move_object`, never set directly,
+/// so that it always agrees with the contents index.
+pub const LOCATION_SLOT: &str = "location";
+
+/// How deeply objects may be nested inside one another.
+pub const MAX_NESTING: usize = 256;
+
+/// The index of what's in each location: the reverse of objects' 'location' slots, so that a
+/// location's contents can be found without scanning every object.
+///
+/// It's only written alongside the 'location' slots it indexes, in the same transaction, so the
+/// two can't disagree.
+pub struct ContentsTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+fn contents_subspace(location: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(location.id);
+    Subspace::new(Bytes::from_static("CONTENTS".as_bytes())).subspace(&tup)
+}
+
+fn contents_key(location: Oid, oid: Oid) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_uuid(oid.id);
+    contents_subspace(location).subspace(&tup).pack().into()
+}
+
+impl<'tx_lifetime> ContentsTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        ContentsTxHandle { tr: tx }
+    }
+
+    pub fn add(&self, location: Oid, oid: Oid) {
+        self.tr.set(contents_key(location, oid), Bytes::new());
+    }
+
+    pub fn remove(&self, location: Oid, oid: Oid) {
+        self.tr.clear(contents_key(location, oid));
+    }
+
+    /// What's in `location`, in Oid order.
+    pub async fn contents(&self, location: Oid) -> Result<Vec<Oid>, DbError> {
+        let contents = contents_subspace(location);
+        let mut stream = self.tr.get_range(contents.range(&Tuple::new()));
+        let mut oids = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, _) = kv?;
+            let key_bytes: Bytes = key.into();
+            let tuple = contents.unpack(&key_bytes).unwrap();
+            oids.push(Oid {
+                id: *tuple.get_uuid_ref(0).unwrap(),
+            });
+        }
+        Ok(oids)
+    }
+
+    /// Forget everything in `location`, as it's being destroyed.
+    pub fn clear_location(&self, location: Oid) {
+        self.tr
+            .clear_range(contents_subspace(location).range(&Tuple::new()));
+    }
+}
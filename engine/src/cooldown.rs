@@ -0,0 +1,81 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// Named cooldowns on objects, e.g. how long until a player may shout again.
+///
+/// Each is kept as its expiry, in milliseconds since the Unix epoch, so verbs need only ask how
+/// long is left. Expired cooldowns are cleared when they're next checked.
+pub struct CooldownTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+fn cooldown_subspace(oid: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(oid.id);
+    Subspace::new(Bytes::from_static("COOLDOWN".as_bytes())).subspace(&tup)
+}
+
+fn cooldown_key(oid: Oid, name: &str) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_string(name.to_string());
+    cooldown_subspace(oid).subspace(&tup).pack().into()
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl<'tx_lifetime> CooldownTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        CooldownTxHandle { tr: tx }
+    }
+
+    /// How long is left of the cooldown `name` on `oid` at `now`. Zero if it's expired, or was
+    /// never set.
+    pub async fn remaining(
+        &self,
+        oid: Oid,
+        name: &str,
+        now: SystemTime,
+    ) -> Result<Duration, DbError> {
+        let key = cooldown_key(oid, name);
+        let expiry = match self.tr.get(key.clone()).await? {
+            Some(v) => Bytes::from(v)[..]
+                .try_into()
+                .map(u64::from_le_bytes)
+                .unwrap_or(0),
+            None => return Ok(Duration::ZERO),
+        };
+        let now = unix_millis(now);
+        if expiry <= now {
+            self.tr.clear(key);
+            return Ok(Duration::ZERO);
+        }
+        Ok(Duration::from_millis(expiry - now))
+    }
+
+    /// Start the cooldown `name` on `oid`, lasting `duration` from `now`. A zero duration ends it.
+    pub fn set(&self, oid: Oid, name: &str, duration: Duration, now: SystemTime) {
+        let key = cooldown_key(oid, name);
+        if duration.is_zero() {
+            self.tr.clear(key);
+            return;
+        }
+        let expiry = unix_millis(now).saturating_add(duration.as_millis() as u64);
+        self.tr
+            .set(key, Bytes::copy_from_slice(&expiry.to_le_bytes()));
+    }
+
+    /// Forget every cooldown on `oid`.
+    pub fn clear_object(&self, oid: Oid) {
+        self.tr
+            .clear_range(cooldown_subspace(oid).range(&Tuple::new()));
+    }
+}
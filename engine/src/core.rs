@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+use sha2::{Digest, Sha512};
+use uuid::Uuid;
+use wasmtime::Engine;
+
+use crate::compile::{check_program, read_program};
+use value::{Oid, Value};
+
+/// The file in a core's directory describing it.
+pub const MANIFEST_FILE: &str = "core.json";
+
+/// The name the system object goes by in a manifest. It always gets the nil Oid.
+pub const SYS_NAME: &str = "sys";
+
+// Manifests look like:
+//
+// {
+//   "objects": {
+//     "sys": {
+//       "slots": {
+//         "receive": { "program": "receive.wat" },
+//         "motd": { "value": { "String": "Welcome!" } }
+//       }
+//     },
+//     "lobby": { "oid": "5f0c2f7e-...", "slots": { ... } }
+//   }
+// }
+//
+// Program paths are relative to the manifest, and may be WAT or wasm binaries, Lua, named .lua,
+// or JavaScript, named .js.
+#[derive(Deserialize)]
+struct Manifest {
+    objects: BTreeMap<String, ObjectManifest>,
+}
+
+#[derive(Deserialize)]
+struct ObjectManifest {
+    #[serde(default)]
+    oid: Option<Uuid>,
+    #[serde(default)]
+    slots: BTreeMap<String, SlotManifest>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SlotManifest {
+    Program(PathBuf),
+    Value(Value),
+}
+
+/// The objects a new world starts out with, and their slots.
+pub struct Core {
+    pub objects: Vec<CoreObject>,
+}
+
+pub struct CoreObject {
+    pub name: String,
+    pub oid: Oid,
+    pub slots: Vec<(String, Value)>,
+}
+
+/// The Oid an object named `name` in a core gets if its manifest doesn't give one. It's derived
+/// from the name, so that it's the same every time the core is installed.
+pub fn core_oid(name: &str) -> Oid {
+    if name == SYS_NAME {
+        return Oid { id: Uuid::nil() };
+    }
+    let digest = Sha512::digest(format!("room core object {}", name).as_bytes());
+    Oid {
+        id: Uuid::from_slice(&digest[..16]).unwrap(),
+    }
+}
+
+impl Core {
+    /// Read the core described by the manifest in `dir`, checking that every program in it
+    /// compiles with `engine`.
+    pub fn load(dir: &Path, engine: &Engine) -> Result<Self, Error> {
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest: Manifest = serde_json::from_slice(&std::fs::read(&manifest_path)?)
+            .map_err(|e| anyhow!("{}: {}", manifest_path.display(), e))?;
+
+        let mut objects = vec![];
+        for (name, object) in manifest.objects {
+            let oid = match (object.oid, name.as_str()) {
+                (Some(id), SYS_NAME) if !id.is_nil() => {
+                    return Err(anyhow!("'{}' must have the nil Oid", SYS_NAME));
+                }
+                (Some(id), _) => Oid { id },
+                (None, _) => core_oid(&name),
+            };
+            let mut slots = vec![];
+            for (slot_name, slot) in object.slots {
+                let value = match slot {
+                    SlotManifest::Program(path) => {
+                        let path = dir.join(path);
+                        let program = read_program(&path)
+                            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                        check_program(engine, &program)
+                            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                        Value::Program(program)
+                    }
+                    SlotManifest::Value(value) => value,
+                };
+                slots.push((slot_name, value));
+            }
+            if let Some(other) = objects.iter().find(|o: &&CoreObject| o.oid == oid) {
+                return Err(anyhow!("'{}' and '{}' have the same Oid", other.name, name));
+            }
+            objects.push(CoreObject { name, oid, slots });
+        }
+        Ok(Core { objects })
+    }
+}
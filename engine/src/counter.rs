@@ -0,0 +1,64 @@
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use tokio_stream::StreamExt;
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// Named counters on objects, e.g. how many times a door has been opened.
+///
+/// Each is a little-endian i64 which increments add to atomically, so that verbs counting at once
+/// don't conflict with one another as they would setting a slot. Only reading one does.
+pub struct CounterTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+fn counter_subspace(oid: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(oid.id);
+    Subspace::new(Bytes::from_static("COUNTER".as_bytes())).subspace(&tup)
+}
+
+fn counter_key(oid: Oid, name: &str) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_string(name.to_string());
+    counter_subspace(oid).subspace(&tup).pack().into()
+}
+
+impl<'tx_lifetime> CounterTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        CounterTxHandle { tr: tx }
+    }
+
+    /// Add `delta` to the counter `name` on `oid` as the transaction commits.
+    pub fn incr(&self, oid: Oid, name: &str, delta: i64) {
+        self.tr.add(counter_key(oid, name), delta);
+    }
+
+    /// The counter `name` on `oid`; zero if it's never been added to. A snapshot read, so that it
+    /// doesn't conflict with increments.
+    pub async fn get(&self, oid: Oid, name: &str) -> Result<i64, DbError> {
+        Ok(match self.tr.snapshot_get(counter_key(oid, name)).await? {
+            Some(v) => Bytes::from(v)[..]
+                .try_into()
+                .map(i64::from_le_bytes)
+                .unwrap_or(0),
+            None => 0,
+        })
+    }
+
+    /// Whether `oid` has any counters.
+    pub async fn any(&self, oid: Oid) -> Result<bool, DbError> {
+        let range = counter_subspace(oid).range(&Tuple::new());
+        match self.tr.get_range(range).next().await {
+            Some(kv) => kv.map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Forget every counter on `oid`.
+    pub fn clear_object(&self, oid: Oid) {
+        self.tr
+            .clear_range(counter_subspace(oid).range(&Tuple::new()));
+    }
+}
@@ -0,0 +1,736 @@
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use fdb::{
+    database::FdbDatabase,
+    error::FdbError,
+    range::{Range, RangeOptions},
+    transaction::{FdbTransaction, MutationType, ReadTransaction, Transaction},
+    Key, Value,
+};
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, BoxStream, StreamExt};
+use rand::Rng;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::{debug, warn, Span};
+
+use crate::embedded_db::{AtomicOp, EmbeddedDatabase, EmbeddedTransaction};
+#[cfg(feature = "faults")]
+use crate::faults::FaultOptions;
+use crate::verb_error::VerbError;
+
+// FoundationDB's not_committed error; a conflict, which is retried.
+const NOT_COMMITTED: i32 = 1020;
+
+// The other FoundationDB errors a transaction is retried after: transaction_too_old,
+// future_version, commit_unknown_result, transaction_timed_out, process_behind and
+// tag_throttled. Anything else is fatal.
+const RETRYABLE: [i32; 6] = [1007, 1009, 1021, 1031, 1037, 1213];
+
+/// Retries a transaction gets, by default, before it's given up on.
+pub const DEFAULT_MAX_RETRIES: u32 = 50;
+
+/// How transactions which fail with retryable errors (conflicts, timeouts) are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Retries before the transaction is given up on with RetriesExhausted. Never if None.
+    pub max_retries: Option<u32>,
+    /// The wait before the first retry, doubling with each after it up to `max_backoff`. Each
+    /// wait is jittered down by up to half, so that transactions which conflicted with one another
+    /// don't retry in step. FoundationDB waits by itself before most retries, so there these are
+    /// only waited before those it doesn't (after timeouts).
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: Some(DEFAULT_MAX_RETRIES),
+            base_backoff: Duration::from_millis(2),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // How long to wait before the `retry`th retry (from 1).
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_backoff
+            .saturating_mul(1u32 << retry.saturating_sub(1).min(16))
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// How often transactions have been retried since the database was opened.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct RetryStats {
+    /// Retries for any reason.
+    pub retries: u64,
+    /// Of those, the ones after conflicts with other transactions.
+    pub conflicts: u64,
+    /// Transactions given up on once they'd used their retries.
+    pub exhausted: u64,
+}
+
+// Committed transactions a slow subscriber can fall behind by before it misses some.
+const COMMITS_CAPACITY: usize = 1024;
+
+/// What a transaction wrote, as subscribers are told once it's committed.
+pub struct Written {
+    /// The keys set or cleared. (Keys cleared by `clear_range` aren't included.)
+    pub keys: Vec<Key>,
+    /// Whether it ran within `unwatched`, so that watchers aren't to be told of it.
+    pub unwatched: bool,
+}
+
+tokio::task_local! {
+    // Set within `unwatched`.
+    static UNWATCHED: ();
+}
+
+/// Run `future`, with the transactions it runs marked as unwatched: what they write isn't told to
+/// the slots' watchers. For the verbs which tell them, so that what those write doesn't have them
+/// told again, and again.
+pub async fn unwatched<F: Future>(future: F) -> F::Output {
+    UNWATCHED.scope((), future).await
+}
+
+/// The first key after `key`, for reading a range on from where a page of it ended.
+pub fn key_after(key: Key) -> Key {
+    let mut after = Bytes::from(key).to_vec();
+    after.push(0);
+    Bytes::from(after).into()
+}
+
+/// Where the FoundationDB client looks for its cluster file, unless told otherwise.
+pub const DEFAULT_CLUSTER_FILE: &str = "/etc/foundationdb/fdb.cluster";
+
+/// Which storage backend the world is kept in.
+#[derive(Clone, Debug)]
+pub enum Storage {
+    /// FoundationDB, located via the given cluster file.
+    Fdb(PathBuf),
+    /// An embedded database in the given directory, for single node deployments.
+    Embedded(PathBuf),
+    /// An embedded database which is thrown away when the world is, for test worlds.
+    Temporary,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage::Fdb(DEFAULT_CLUSTER_FILE.into())
+    }
+}
+
+/// Errors from the storage layer, whichever backend is in use.
+#[derive(Debug)]
+pub enum DbError {
+    Fdb(FdbError),
+    Embedded(sled::Error),
+    /// The transaction conflicted with one committed concurrently, and should be retried.
+    Conflict,
+    /// The transaction was deliberately abandoned by its closure, for the given reason, and
+    /// nothing was committed.
+    Aborted(value::Error),
+    /// The transaction kept failing with retryable errors, and was given up on after this many
+    /// retries. Nothing was committed.
+    RetriesExhausted(u32),
+    /// The transaction was abandoned as the verb it ran trapped, or otherwise failed, as said.
+    /// Nothing was committed.
+    Trapped(Box<VerbError>),
+}
+
+impl DbError {
+    /// Whether running the transaction again could succeed: after a conflict, or a timeout.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DbError::Fdb(e) => is_retryable_code(e.code()),
+            DbError::Conflict => true,
+            DbError::Embedded(_)
+            | DbError::Aborted(_)
+            | DbError::RetriesExhausted(_)
+            | DbError::Trapped(_) => false,
+        }
+    }
+}
+
+fn is_retryable_code(code: i32) -> bool {
+    code == NOT_COMMITTED || RETRYABLE.contains(&code)
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Fdb(e) => write!(f, "FoundationDB error: {:?}", e),
+            DbError::Embedded(e) => write!(f, "Embedded database error: {}", e),
+            DbError::Conflict => write!(f, "Transaction conflict"),
+            DbError::Aborted(reason) => write!(f, "Transaction aborted: {:?}", reason),
+            DbError::RetriesExhausted(retries) => {
+                write!(f, "Transaction given up on after {} retries", retries)
+            }
+            DbError::Trapped(e) => write!(f, "Transaction abandoned, its verb failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<FdbError> for DbError {
+    fn from(e: FdbError) -> Self {
+        DbError::Fdb(e)
+    }
+}
+
+impl From<sled::Error> for DbError {
+    fn from(e: sled::Error) -> Self {
+        DbError::Embedded(e)
+    }
+}
+
+/// The database the world lives in.
+/// Keys and values are encoded identically (as FDB tuples) regardless of backend, so the object
+/// layer on top, and dumps taken from it, don't care which one is in use.
+pub struct Database {
+    backend: Backend,
+    #[cfg(feature = "faults")]
+    faults: Option<FaultOptions>,
+    commits: broadcast::Sender<Arc<Written>>,
+    committed: AtomicU64,
+    index_references: bool,
+    journal_changes: bool,
+    retry_policy: RetryPolicy,
+    retries: AtomicU64,
+    conflicts: AtomicU64,
+    exhausted: AtomicU64,
+}
+
+enum Backend {
+    Fdb(FdbDatabase),
+    Embedded(EmbeddedDatabase),
+}
+
+/// A transaction against either backend.
+#[derive(Clone)]
+pub struct Tx {
+    backend: TxBackend,
+    // The keys set or cleared so far, if anyone is subscribed to commits.
+    written: Option<Arc<Mutex<Vec<Key>>>>,
+    unwatched: bool,
+    index_references: bool,
+    journal_changes: bool,
+    // Orders the versionstamped keys it sets among themselves.
+    stamped: Arc<AtomicU16>,
+    // What's to be done once it has committed; dropped unrun if it never does.
+    after_commit: Arc<Mutex<Vec<AfterCommit>>>,
+}
+
+type AfterCommit = Box<dyn FnOnce() + Send>;
+
+#[derive(Clone)]
+enum TxBackend {
+    Fdb(FdbTransaction),
+    Embedded(EmbeddedTransaction),
+}
+
+impl Database {
+    /// Open the database.
+    pub fn open(storage: &Storage) -> Result<Self, DbError> {
+        let backend = match storage {
+            Storage::Fdb(cluster_file) => {
+                unsafe {
+                    fdb::select_api_version(710);
+                    fdb::start_network();
+                }
+                Backend::Fdb(fdb::open_database(cluster_file)?)
+            }
+            Storage::Embedded(path) => Backend::Embedded(EmbeddedDatabase::open(path)?),
+            Storage::Temporary => Backend::Embedded(EmbeddedDatabase::open_temporary()?),
+        };
+        let (commits, _) = broadcast::channel(COMMITS_CAPACITY);
+        Ok(Database {
+            backend,
+            #[cfg(feature = "faults")]
+            faults: None,
+            commits,
+            committed: AtomicU64::new(0),
+            index_references: false,
+            journal_changes: false,
+            retry_policy: RetryPolicy::default(),
+            retries: AtomicU64::new(0),
+            conflicts: AtomicU64::new(0),
+            exhausted: AtomicU64::new(0),
+        })
+    }
+
+    /// Inject `faults` into its transactions, if given.
+    #[cfg(feature = "faults")]
+    pub fn with_faults(self, faults: Option<FaultOptions>) -> Self {
+        Database { faults, ..self }
+    }
+
+    /// Have `run` retry transactions as `policy` says.
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Database {
+            retry_policy,
+            ..self
+        }
+    }
+
+    /// Have its transactions keep the index of which slots refer to which objects as they set
+    /// slots, or not.
+    pub fn with_reference_index(self, index_references: bool) -> Self {
+        Database {
+            index_references,
+            ..self
+        }
+    }
+
+    /// Have its transactions journal the changes they make to slots, or not.
+    pub fn with_change_journal(self, journal_changes: bool) -> Self {
+        Database {
+            journal_changes,
+            ..self
+        }
+    }
+
+    /// Receive what each transaction wrote, once it has committed.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Written>> {
+        self.commits.subscribe()
+    }
+
+    /// The number of transactions committed since the database was opened.
+    pub fn committed(&self) -> u64 {
+        self.committed.load(Ordering::Relaxed)
+    }
+
+    /// How often transactions have been retried, and given up on, since the database was opened.
+    pub fn retry_stats(&self) -> RetryStats {
+        RetryStats {
+            retries: self.retries.load(Ordering::Relaxed),
+            conflicts: self.conflicts.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
+    }
+
+    fn begin(&self, backend: TxBackend) -> Tx {
+        let subscribed = self.commits.receiver_count() > 0;
+        Tx {
+            backend,
+            written: subscribed.then(Default::default),
+            unwatched: UNWATCHED.try_with(|_| ()).is_ok(),
+            index_references: self.index_references,
+            journal_changes: self.journal_changes,
+            stamped: Default::default(),
+            after_commit: Default::default(),
+        }
+    }
+
+    // Count a transaction which has just committed, run what was waiting for it to, and tell
+    // subscribers what it wrote.
+    fn publish(&self, tx: Tx) {
+        self.committed.fetch_add(1, Ordering::Relaxed);
+        let after_commit = std::mem::take(&mut *tx.after_commit.lock().unwrap());
+        for f in after_commit {
+            f();
+        }
+        if let Some(written) = tx.written {
+            let keys = std::mem::take(&mut *written.lock().unwrap());
+            if !keys.is_empty() {
+                // (Which only fails if everyone has unsubscribed since.)
+                let _ = self.commits.send(Arc::new(Written {
+                    keys,
+                    unwatched: tx.unwatched,
+                }));
+            }
+        }
+    }
+
+    /// Runs a closure in a transaction, and commits it, retrying as the database's RetryPolicy
+    /// says.
+    ///
+    /// As with `FdbDatabase::run` the closure will be run again if the transaction fails with a
+    /// retryable error (e.g. a conflict), so it should take care with side effects.
+    pub async fn run<T, F, Fut>(&self, f: F) -> Result<T, DbError>
+    where
+        F: FnMut(Tx) -> Fut,
+        Fut: Future<Output = Result<T, DbError>>,
+    {
+        self.run_with_policy(&self.retry_policy, f).await
+    }
+
+    /// Runs a closure in a transaction, and commits it, retrying it after retryable errors as
+    /// `policy` says. Fails with RetriesExhausted once it's used its retries, and at once with
+    /// any error which isn't retryable.
+    #[tracing::instrument(
+        name = "transaction",
+        level = "debug",
+        skip_all,
+        fields(backend = self.backend_name(), retries = 0)
+    )]
+    pub async fn run_with_policy<T, F, Fut>(
+        &self,
+        policy: &RetryPolicy,
+        mut f: F,
+    ) -> Result<T, DbError>
+    where
+        F: FnMut(Tx) -> Fut,
+        Fut: Future<Output = Result<T, DbError>>,
+    {
+        let mut retries = 0;
+        match &self.backend {
+            Backend::Fdb(db) => {
+                let mut t = db.create_transaction()?;
+                loop {
+                    self.inject_delay().await;
+                    let tx = self.begin(TxBackend::Fdb(t.clone()));
+                    let result = match f(tx.clone()).await {
+                        // Injected failures look like commits which didn't make it.
+                        Ok(_) if self.inject_failure() => Err(FdbError::new(NOT_COMMITTED)),
+                        Ok(v) => unsafe { t.commit() }.await.map(|_| v),
+                        Err(DbError::Fdb(e)) => Err(e),
+                        Err(e) => return Err(e),
+                    };
+                    match result {
+                        Ok(v) => {
+                            self.publish(tx);
+                            return Ok(v);
+                        }
+                        Err(e) => {
+                            let code = e.code();
+                            if !is_retryable_code(code) {
+                                return Err(DbError::Fdb(e));
+                            }
+                            // on_error waits out its own backoff and resets the transaction, but
+                            // fails for the retryable errors it doesn't retry itself (timeouts),
+                            // which are waited for here and get a new transaction.
+                            let why = format!("{:?}", e);
+                            let waited = unsafe { t.on_error(e) }.await.is_ok();
+                            if !waited {
+                                t = db.create_transaction()?;
+                            }
+                            let conflict = code == NOT_COMMITTED;
+                            self.back_off(policy, &mut retries, conflict, !waited, &why)
+                                .await?;
+                        }
+                    }
+                }
+            }
+            Backend::Embedded(db) => loop {
+                self.inject_delay().await;
+                let t = db.create_transaction();
+                let tx = self.begin(TxBackend::Embedded(t.clone()));
+                let v = f(tx.clone()).await?;
+                // Injected failures look like conflicts.
+                if self.inject_failure() {
+                    self.back_off(policy, &mut retries, true, true, "injected failure")
+                        .await?;
+                    continue;
+                }
+                match t.commit() {
+                    Ok(()) => {
+                        self.publish(tx);
+                        return Ok(v);
+                    }
+                    Err(DbError::Conflict) => {
+                        self.back_off(policy, &mut retries, true, true, "conflict")
+                            .await?;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+            },
+        }
+    }
+
+    // Count a retry, and if `wait`, wait as long as `policy` says before it; or give up if it's
+    // used them.
+    async fn back_off(
+        &self,
+        policy: &RetryPolicy,
+        retries: &mut u32,
+        conflict: bool,
+        wait: bool,
+        why: &str,
+    ) -> Result<(), DbError> {
+        if policy.max_retries.is_some_and(|max| *retries >= max) {
+            self.exhausted.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Giving up on transaction after {} retries: {}",
+                retries, why
+            );
+            return Err(DbError::RetriesExhausted(*retries));
+        }
+        *retries += 1;
+        self.retries.fetch_add(1, Ordering::Relaxed);
+        if conflict {
+            self.conflicts.fetch_add(1, Ordering::Relaxed);
+        }
+        Span::current().record("retries", &*retries);
+        debug!("Retrying transaction: {}", why);
+        if wait {
+            tokio::time::sleep(policy.backoff(*retries)).await;
+        }
+        Ok(())
+    }
+
+    /// Runs a closure in a transaction which is then abandoned rather than committed, so that
+    /// nothing it writes takes effect.
+    pub async fn run_uncommitted<T, F, Fut>(&self, f: F) -> Result<T, DbError>
+    where
+        F: FnOnce(Tx) -> Fut,
+        Fut: Future<Output = Result<T, DbError>>,
+    {
+        let tx = match &self.backend {
+            Backend::Fdb(db) => self.begin(TxBackend::Fdb(db.create_transaction()?)),
+            Backend::Embedded(db) => self.begin(TxBackend::Embedded(db.create_transaction())),
+        };
+        f(tx).await
+    }
+
+    /// Make sure everything committed so far is on disk.
+    pub async fn flush(&self) -> Result<(), DbError> {
+        match &self.backend {
+            Backend::Fdb(_) => Ok(()),
+            Backend::Embedded(db) => db.flush().await,
+        }
+    }
+
+    fn backend_name(&self) -> &'static str {
+        match &self.backend {
+            Backend::Fdb(_) => "fdb",
+            Backend::Embedded(_) => "embedded",
+        }
+    }
+
+    #[cfg(feature = "faults")]
+    async fn inject_delay(&self) {
+        if let Some(faults) = &self.faults {
+            faults.delay_tx().await;
+        }
+    }
+
+    #[cfg(not(feature = "faults"))]
+    async fn inject_delay(&self) {}
+
+    #[cfg(feature = "faults")]
+    fn inject_failure(&self) -> bool {
+        self.faults.as_ref().is_some_and(|faults| faults.fail_tx())
+    }
+
+    #[cfg(not(feature = "faults"))]
+    fn inject_failure(&self) -> bool {
+        false
+    }
+}
+
+impl Tx {
+    /// Whether the slots it sets are to be added to the index of references to objects.
+    pub fn indexes_references(&self) -> bool {
+        self.index_references
+    }
+
+    /// Whether the changes it makes to slots are to be journaled.
+    pub fn journals_changes(&self) -> bool {
+        self.journal_changes
+    }
+
+    /// Do `f` once the transaction has committed: side effects outside the database, which
+    /// mustn't happen if it's abandoned, or happen again if it's retried. If it never commits, `f`
+    /// is dropped without being called.
+    pub fn after_commit(&self, f: impl FnOnce() + Send + 'static) {
+        self.after_commit.lock().unwrap().push(Box::new(f));
+    }
+
+    fn note_written(&self, key: Key) -> Key {
+        if let Some(written) = &self.written {
+            written.lock().unwrap().push(key.clone());
+        }
+        key
+    }
+
+    pub async fn get(&self, key: impl Into<Key>) -> Result<Option<Value>, DbError> {
+        match &self.backend {
+            TxBackend::Fdb(t) => Ok(t.get(key).await?),
+            TxBackend::Embedded(t) => Ok(t.get(key.into().into())?.map(Value::from)),
+        }
+    }
+
+    /// As `get`, but a snapshot read: the transaction won't conflict with others for writing
+    /// `key`. For reads which only report, and which nothing written depends on.
+    pub async fn snapshot_get(&self, key: impl Into<Key>) -> Result<Option<Value>, DbError> {
+        match &self.backend {
+            TxBackend::Fdb(t) => Ok(t.snapshot().get(key).await?),
+            TxBackend::Embedded(t) => Ok(t.snapshot_get(key.into().into())?.map(Value::from)),
+        }
+    }
+
+    pub fn set(&self, key: impl Into<Key>, value: impl Into<Value>) {
+        let key = self.note_written(key.into());
+        match &self.backend {
+            TxBackend::Fdb(t) => t.set(key, value),
+            TxBackend::Embedded(t) => t.set(key.into(), value.into().into()),
+        }
+    }
+
+    pub fn clear(&self, key: impl Into<Key>) {
+        let key = self.note_written(key.into());
+        match &self.backend {
+            TxBackend::Fdb(t) => t.clear(key),
+            TxBackend::Embedded(t) => t.clear(key.into()),
+        }
+    }
+
+    /// Add `delta` to the little-endian i64 at `key` (zero if unset) when the transaction commits,
+    /// without conflicting with anything else adding to it.
+    pub fn add(&self, key: impl Into<Key>, delta: i64) {
+        self.atomic_op(key.into(), AtomicOp::Add(delta));
+    }
+
+    /// Raise the little-endian i64 at `key` to `value`, if it's less, when the transaction commits.
+    /// Only for values which aren't negative, as FDB compares them unsigned.
+    pub fn max(&self, key: impl Into<Key>, value: i64) {
+        self.atomic_op(key.into(), AtomicOp::Max(value));
+    }
+
+    fn atomic_op(&self, key: Key, op: AtomicOp) {
+        let key = self.note_written(key);
+        match &self.backend {
+            TxBackend::Fdb(t) => {
+                let (optype, param) = match op {
+                    AtomicOp::Add(delta) => (MutationType::Add, delta),
+                    AtomicOp::Max(value) => (MutationType::Max, value),
+                };
+                let param = Bytes::copy_from_slice(&param.to_le_bytes());
+                unsafe { t.mutate(optype, key, param) }
+            }
+            TxBackend::Embedded(t) => t.atomic_op(key.into(), op),
+        }
+    }
+
+    /// Set the key made of `prefix` and then the transaction's versionstamp, as it commits: ten
+    /// bytes which order it after everything committed before, and two ordering it among the
+    /// other keys the transaction sets so. The key can't be read in the transaction which sets it,
+    /// and isn't told to subscribers.
+    pub fn set_versionstamped(&self, prefix: &[u8], value: impl Into<Value>) {
+        let order = self.stamped.fetch_add(1, Ordering::Relaxed);
+        match &self.backend {
+            TxBackend::Fdb(t) => {
+                // The placeholder's offset follows the key, for FDB to find it by.
+                let mut key = prefix.to_vec();
+                key.extend_from_slice(&[0; 10]);
+                key.extend_from_slice(&order.to_be_bytes());
+                key.extend_from_slice(&(prefix.len() as u32).to_le_bytes());
+                let param: Bytes = value.into().into();
+                unsafe {
+                    t.mutate(
+                        MutationType::SetVersionstampedKey,
+                        Key::from(Bytes::from(key)),
+                        param,
+                    )
+                }
+            }
+            TxBackend::Embedded(t) => {
+                t.set_versionstamped(Bytes::copy_from_slice(prefix), order, value.into().into())
+            }
+        }
+    }
+
+    pub fn clear_range(&self, range: Range) {
+        match &self.backend {
+            TxBackend::Fdb(t) => t.clear_range(range),
+            TxBackend::Embedded(t) => {
+                let (begin, end) = range.into_parts();
+                t.clear_range(begin.into(), end.into())
+            }
+        }
+    }
+
+    /// Stream all the key/value pairs within `range`, in key order.
+    pub fn get_range(&self, range: Range) -> BoxStream<'static, Result<(Key, Value), DbError>> {
+        self.read_range(range, false)
+    }
+
+    /// As `get_range`, but a snapshot read, as `snapshot_get` is.
+    pub fn snapshot_get_range(
+        &self,
+        range: Range,
+    ) -> BoxStream<'static, Result<(Key, Value), DbError>> {
+        self.read_range(range, true)
+    }
+
+    /// The last key/value pair within `range`, if there is one, read as a snapshot without reading
+    /// the rest of the range.
+    pub async fn snapshot_last_in_range(
+        &self,
+        range: Range,
+    ) -> Result<Option<(Key, Value)>, DbError> {
+        match &self.backend {
+            TxBackend::Fdb(t) => {
+                let mut options = RangeOptions::default();
+                options.set_limit(1);
+                options.set_reverse(true);
+                match range.into_stream(&t.snapshot(), options).next().await {
+                    Some(kv) => Ok(Some(kv?.into_parts())),
+                    None => Ok(None),
+                }
+            }
+            TxBackend::Embedded(t) => {
+                let (begin, end) = range.into_parts();
+                let last = t.snapshot_last_in_range(begin.into(), end.into())?;
+                Ok(last.map(|(k, v)| (Key::from(k), Value::from(v))))
+            }
+        }
+    }
+
+    fn read_range(
+        &self,
+        range: Range,
+        snapshot: bool,
+    ) -> BoxStream<'static, Result<(Key, Value), DbError>> {
+        match &self.backend {
+            TxBackend::Fdb(t) if snapshot => range
+                .into_stream(&t.snapshot(), RangeOptions::default())
+                .map(|kv| Ok(kv?.into_parts()))
+                .boxed(),
+            TxBackend::Fdb(t) => range
+                .into_stream(t, RangeOptions::default())
+                .map(|kv| Ok(kv?.into_parts()))
+                .boxed(),
+            TxBackend::Embedded(t) => {
+                let (begin, end) = range.into_parts();
+                let kvs = match snapshot {
+                    true => t.snapshot_get_range(begin.into(), end.into()),
+                    false => t.get_range(begin.into(), end.into()),
+                };
+                match kvs {
+                    Ok(kvs) => stream::iter(
+                        kvs.into_iter()
+                            .map(|(k, v)| Ok((Key::from(k), Value::from(v)))),
+                    )
+                    .boxed(),
+                    Err(e) => stream::once(async { Err(e) }).boxed(),
+                }
+            }
+        }
+    }
+
+    /// Returns a future which resolves when the value at `key` is next changed.
+    pub fn watch(&self, key: impl Into<Key>) -> BoxFuture<'static, Result<(), DbError>> {
+        match &self.backend {
+            TxBackend::Fdb(t) => {
+                let watch = t.watch(key);
+                async move { Ok(watch.await?) }.boxed()
+            }
+            TxBackend::Embedded(t) => t.watch(key.into().into()),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashSet};
+
+use bytes::Bytes;
+use fdb::{range::Range, subspace::Subspace, tuple::Tuple, Key, Value};
+use futures::StreamExt;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::database::{key_after, DbError, Tx};
+use value::Oid;
+
+const VERB: &str = "verb";
+const READ: &str = "read";
+const WRITE: &str = "write";
+
+/// The slots one verb invocation read and wrote, as (oid, key, name). Listing the slots under a
+/// key reads them all, and is recorded with an empty name.
+#[derive(Clone, Debug, Default)]
+pub struct SlotAccesses {
+    pub reads: HashSet<(Oid, Oid, String)>,
+    pub writes: HashSet<(Oid, Oid, String)>,
+}
+
+/// A slot a program touched, and in how many of its invocations.
+#[derive(Serialize, Clone, Debug)]
+pub struct SlotDependency {
+    pub oid: Oid,
+    pub key: Oid,
+    pub name: String,
+    pub invocations: i64,
+}
+
+/// A verb a program was invoked as, and how many times.
+#[derive(Serialize, Clone, Debug)]
+pub struct VerbUse {
+    pub oid: Oid,
+    pub verb: String,
+    pub invocations: i64,
+}
+
+/// Everything recorded of one program, by its digest in hex.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ProgramDependencies {
+    pub digest: String,
+    pub invocations: i64,
+    pub verbs: Vec<VerbUse>,
+    pub reads: Vec<SlotDependency>,
+    pub writes: Vec<SlotDependency>,
+}
+
+fn dependency_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("DEPENDENCY".as_bytes()))
+}
+
+fn digest_prefix(digest: &[u8; 64]) -> Tuple {
+    let mut tup = Tuple::new();
+    tup.add_bytes(Bytes::copy_from_slice(digest));
+    tup
+}
+
+fn verb_key(digest: &[u8; 64], oid: Oid, verb: &str) -> Key {
+    let mut tup = digest_prefix(digest);
+    tup.add_string(VERB.to_string());
+    tup.add_uuid(oid.id);
+    tup.add_string(verb.to_string());
+    dependency_subspace().subspace(&tup).pack().into()
+}
+
+fn slot_key(digest: &[u8; 64], access: &str, slot: &(Oid, Oid, String)) -> Key {
+    let mut tup = digest_prefix(digest);
+    tup.add_string(access.to_string());
+    tup.add_uuid(slot.0.id);
+    tup.add_uuid(slot.1.id);
+    tup.add_string(slot.2.clone());
+    dependency_subspace().subspace(&tup).pack().into()
+}
+
+/// Recorded counters, as `counters` reads them a page at a time, added up by program.
+#[derive(Default)]
+pub struct DependencyReport {
+    programs: BTreeMap<Vec<u8>, ProgramDependencies>,
+}
+
+// What one recorded counter counts.
+enum Counted {
+    Verb(VerbUse),
+    Read(SlotDependency),
+    Write(SlotDependency),
+}
+
+impl DependencyReport {
+    /// Add in the counter at `key`. Keys which aren't counters are skipped.
+    pub fn add(&mut self, key: Key, value: Value) {
+        let key_bytes: Bytes = key.into();
+        let (digest, counted) = match parse_counter(&key_bytes, &Bytes::from(value)) {
+            Some(counter) => counter,
+            None => {
+                warn!("Skipping malformed dependency counter {:?}", key_bytes);
+                return;
+            }
+        };
+        let program = self
+            .programs
+            .entry(digest.clone())
+            .or_insert_with(|| ProgramDependencies {
+                digest: hex(&digest),
+                ..Default::default()
+            });
+        match counted {
+            Counted::Verb(verb) => {
+                program.invocations += verb.invocations;
+                program.verbs.push(verb);
+            }
+            Counted::Read(dependency) => program.reads.push(dependency),
+            Counted::Write(dependency) => program.writes.push(dependency),
+        }
+    }
+
+    /// Everything added, aggregated by program, the most invoked first.
+    pub fn finish(self) -> Vec<ProgramDependencies> {
+        let mut programs: Vec<_> = self.programs.into_values().collect();
+        programs.sort_by_key(|program| -program.invocations);
+        programs
+    }
+}
+
+// The program digest a counter is for, and what it counts.
+fn parse_counter(key: &Bytes, value: &[u8]) -> Option<(Vec<u8>, Counted)> {
+    let tuple = dependency_subspace().unpack(key).ok()?;
+    let digest = tuple.get_bytes_ref(0).ok()?.to_vec();
+    let oid = Oid {
+        id: *tuple.get_uuid_ref(2).ok()?,
+    };
+    let count = value.try_into().map(i64::from_le_bytes).unwrap_or(0);
+    let counted = match tuple.get_string_ref(1).ok()?.as_str() {
+        VERB => Counted::Verb(VerbUse {
+            oid,
+            verb: tuple.get_string_ref(3).ok()?.clone(),
+            invocations: count,
+        }),
+        access => {
+            let dependency = SlotDependency {
+                oid,
+                key: Oid {
+                    id: *tuple.get_uuid_ref(3).ok()?,
+                },
+                name: tuple.get_string_ref(4).ok()?.clone(),
+                invocations: count,
+            };
+            match access {
+                READ => Counted::Read(dependency),
+                _ => Counted::Write(dependency),
+            }
+        }
+    };
+    Some((digest, counted))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub struct DependencyTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> DependencyTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        DependencyTxHandle { tr: tx }
+    }
+
+    /// Count an invocation of the program with `digest`, as `verb`, and the slots it touched.
+    /// The counters are updated with atomic operations, so that recording never makes verbs
+    /// conflict with each other.
+    pub fn record(&self, digest: &[u8; 64], verb: (Oid, &str), accesses: &SlotAccesses) {
+        self.tr.add(verb_key(digest, verb.0, verb.1), 1);
+        for slot in &accesses.reads {
+            self.tr.add(slot_key(digest, READ, slot), 1);
+        }
+        for slot in &accesses.writes {
+            self.tr.add(slot_key(digest, WRITE, slot), 1);
+        }
+    }
+
+    /// Up to `limit` of the recorded counters in key order, from just after `after` or else the
+    /// first, for a DependencyReport to add up.
+    pub async fn counters(
+        &self,
+        after: Option<Key>,
+        limit: usize,
+    ) -> Result<Vec<(Key, Value)>, DbError> {
+        let (begin, end) = dependency_subspace().range(&Tuple::new()).into_parts();
+        let begin = match after {
+            Some(key) => key_after(key),
+            None => begin,
+        };
+        let mut stream = self
+            .tr
+            .snapshot_get_range(Range::new(begin, end))
+            .take(limit);
+        let mut counters = vec![];
+        while let Some(kv) = stream.next().await {
+            counters.push(kv?);
+        }
+        Ok(counters)
+    }
+
+    /// Forget everything recorded.
+    pub fn clear(&self) {
+        self.tr
+            .clear_range(dependency_subspace().range(&Tuple::new()));
+    }
+}
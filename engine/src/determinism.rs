@@ -0,0 +1,86 @@
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use sha2::{Digest, Sha256};
+use uuid::{Builder, Uuid, Variant, Version};
+
+use value::{encode_frame, Oid, Value};
+
+/// How far the logical clock advances each time it's read, in nanoseconds.
+pub const LOGICAL_TICK_NANOS: i64 = 1000;
+
+/// Builtins whose results depend on the wall clock, on fresh randomness or on state outside the
+/// verb's transaction, which deterministic executions refuse with PermissionDenied.
+pub const NONDETERMINISTIC_BUILTINS: &[&str] = &[
+    "cooldown_check",
+    "cooldown_set",
+    "login_allowed",
+    "login_attempt",
+    "login_verify",
+    "totp_provision",
+    "totp_enable",
+    "totp_disable",
+    "totp_recovery_codes",
+    "connection_info",
+    "player_stats",
+    "parse_cron",
+    "calendar_add",
+    "upcoming_events",
+    "impersonate",
+    "impersonation_audit",
+    "next_id",
+    "send_later",
+    "cancel_send",
+    "send_form",
+    "task_list",
+    "kill_task",
+    "scratch_put",
+    "scratch_get",
+];
+
+/// What a deterministic execution draws on in place of the wall clock and the system's randomness:
+/// a logical clock, which starts at the Unix epoch and advances by LOGICAL_TICK_NANOS each time
+/// it's read, and a random number generator seeded from the invocation.
+pub struct Determinism {
+    rng: StdRng,
+    clock: i64,
+}
+
+impl Determinism {
+    pub fn new(seed: u64) -> Self {
+        Determinism {
+            rng: StdRng::seed_from_u64(seed),
+            clock: 0,
+        }
+    }
+
+    /// The seed for invoking `verb` with `args`, which depends on nothing else, so that the
+    /// invocation can be replayed.
+    pub fn seed(verb: (Oid, &str), args: &Value) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(verb.0.id.as_bytes());
+        hasher.update(verb.1.as_bytes());
+        hasher.update(encode_frame(args));
+        let digest = hasher.finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+
+    /// The logical time, in nanoseconds since the Unix epoch.
+    pub fn now(&mut self) -> i64 {
+        self.clock += LOGICAL_TICK_NANOS;
+        self.clock
+    }
+
+    pub fn fill(&mut self, bytes: &mut [u8]) {
+        self.rng.fill_bytes(bytes);
+    }
+
+    /// A random (version 4) UUID, from the generator.
+    pub fn uuid(&mut self) -> Uuid {
+        let mut bytes = [0; 16];
+        self.fill(&mut bytes);
+        Builder::from_bytes(bytes)
+            .set_variant(Variant::RFC4122)
+            .set_version(Version::Random)
+            .build()
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use fdb::Key;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::compile::check_program;
+use crate::database::{Database, Storage};
+use crate::dump::DumpTarget;
+use crate::module_cache::{ModuleCache, Preemption, DEFAULT_CAPACITY_BYTES};
+use value::Value;
+
+/// A read taking longer than this is worth warning about.
+pub const SLOW_READ: Duration = Duration::from_millis(100);
+
+// The verbs on the system object the server can't do without.
+const REQUIRED_VERBS: &[&str] = &["receive"];
+
+/// What `doctor` is to check: the environment the server would be started in.
+pub struct DoctorOptions {
+    pub storage: Storage,
+    pub dump_target: DumpTarget,
+    /// The addresses the server would listen on.
+    pub listen_addresses: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Failed,
+}
+
+/// The outcome of one check, with what to do about it if it didn't pass.
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub advice: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &str, detail: String) -> Self {
+        Check {
+            name: name.to_string(),
+            status: CheckStatus::Ok,
+            detail,
+            advice: None,
+        }
+    }
+
+    fn warning(name: &str, detail: String, advice: &str) -> Self {
+        Check {
+            name: name.to_string(),
+            status: CheckStatus::Warning,
+            detail,
+            advice: Some(advice.to_string()),
+        }
+    }
+
+    fn failed(name: &str, detail: String, advice: &str) -> Self {
+        Check {
+            name: name.to_string(),
+            status: CheckStatus::Failed,
+            detail,
+            advice: Some(advice.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warning => "warning",
+            CheckStatus::Failed => "FAILED",
+        };
+        write!(f, "[{}] {}: {}", status, self.name, self.detail)?;
+        if let Some(advice) = &self.advice {
+            write!(f, "\n    {}", advice)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that the server could start and serve: that the database can be reached, and quickly;
+/// that the dump reads back cleanly and its system verbs compile; that the WebAssembly engine can
+/// be configured as verbs need; that the listen addresses are free; and that the dump directory
+/// can be written to. Nothing is changed, but for a probe file written to and removed from the
+/// dump directory.
+pub async fn run_checks(options: &DoctorOptions) -> Vec<Check> {
+    let mut checks = vec![check_database(&options.storage).await];
+    let engine = check_engine(&mut checks);
+    check_dump(&options.dump_target, engine.as_ref(), &mut checks).await;
+    for address in &options.listen_addresses {
+        checks.push(check_listen(address).await);
+    }
+    if let DumpTarget::Directory(dir) = &options.dump_target {
+        checks.push(check_writable(dir));
+    }
+    checks
+}
+
+async fn check_database(storage: &Storage) -> Check {
+    const NAME: &str = "database";
+    if let Storage::Fdb(cluster_file) = storage {
+        if !cluster_file.exists() {
+            return Check::failed(
+                NAME,
+                format!("there's no cluster file at {}", cluster_file.display()),
+                "Point --fdb-cluster-file (or FDB_CLUSTER_FILE) at the cluster file, or use \
+                 --storage embedded.",
+            );
+        }
+    }
+    let database = match Database::open(storage) {
+        Ok(database) => database,
+        Err(e) => {
+            return Check::failed(
+                NAME,
+                format!("could not open: {}", e),
+                "Check the cluster file and that the cluster is up, or that --storage-path is \
+                 writable and no other server has it open.",
+            )
+        }
+    };
+    let started = Instant::now();
+    let read = database
+        .run(|tr| async move {
+            tr.get(Key::from(Bytes::from_static(b"DOCTOR"))).await?;
+            Ok(())
+        })
+        .await;
+    let latency = started.elapsed();
+    match read {
+        Err(e) => Check::failed(
+            NAME,
+            format!("opened, but could not read: {}", e),
+            "Check the cluster's status with `fdbcli --exec status`.",
+        ),
+        Ok(()) if latency > SLOW_READ => Check::warning(
+            NAME,
+            format!("a read took {:?}", latency),
+            "The database is slow to answer; verbs will be too. Check the cluster's load and \
+             the network to it.",
+        ),
+        Ok(()) => Check::ok(NAME, format!("a read took {:?}", latency)),
+    }
+}
+
+fn check_engine(checks: &mut Vec<Check>) -> Option<ModuleCache> {
+    const NAME: &str = "wasm engine";
+    // The configuration verbs run with: async, metered by fuel, and interruptible by epoch.
+    let cache = match std::panic::catch_unwind(|| {
+        ModuleCache::new(DEFAULT_CAPACITY_BYTES, Preemption::Fuel)
+    }) {
+        Ok(cache) => cache,
+        Err(_) => {
+            checks.push(Check::failed(
+                NAME,
+                "could not create an engine with async support, fuel metering and epoch interruption"
+                    .to_string(),
+                "This build of wasmtime doesn't support this platform.",
+            ));
+            return None;
+        }
+    };
+    match wasmtime::Module::new(cache.engine(), "(module)") {
+        Ok(_) => checks.push(Check::ok(
+            NAME,
+            "async execution and fuel metering are available".to_string(),
+        )),
+        Err(e) => checks.push(Check::failed(
+            NAME,
+            format!("could not compile an empty module: {:#}", e),
+            "This build of wasmtime can't compile for this platform.",
+        )),
+    }
+    Some(cache)
+}
+
+async fn check_dump(target: &DumpTarget, cache: Option<&ModuleCache>, checks: &mut Vec<Check>) {
+    const NAME: &str = "dump";
+    let (dumps, report) = match target.read(false).await {
+        Ok(read) => read,
+        Err(e) => {
+            checks.push(Check::failed(
+                NAME,
+                format!("could not be read: {:#}", e),
+                "Check --dump-path, or the bucket's name, region and credentials.",
+            ));
+            return;
+        }
+    };
+    if dumps.is_empty() {
+        checks.push(Check::warning(
+            NAME,
+            "there isn't one".to_string(),
+            "The world will be bootstrapped afresh (from --core, if given).",
+        ));
+        return;
+    }
+    let summary = format!("{} slots", report.loaded);
+    checks.push(match (report.clean(), report.verified) {
+        (true, _) => Check::ok(NAME, format!("{}, all matching its manifest", summary)),
+        (false, false) => Check::warning(
+            NAME,
+            format!("{}, with no manifest to verify against", summary),
+            "It was written by an older server; the next save will write one.",
+        ),
+        (false, true) => Check::failed(
+            NAME,
+            format!(
+                "{}; {} corrupt, {} not in the manifest, {} missing",
+                summary,
+                report.corrupt.len(),
+                report.unlisted.len(),
+                report.missing.len()
+            ),
+            "Restore the dump from a backup, or start without --strict-load to load what's there.",
+        ),
+    });
+
+    const VERBS: &str = "system verbs";
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return,
+    };
+    let system: Vec<_> = dumps
+        .iter()
+        .filter(|dump| dump.slot_def.location.id == Uuid::nil())
+        .collect();
+    for required in REQUIRED_VERBS {
+        if !system.iter().any(|dump| dump.slot_def.name == *required) {
+            checks.push(Check::failed(
+                VERBS,
+                format!("the system object has no '{}' verb", required),
+                "Install a core which defines it.",
+            ));
+        }
+    }
+    let mut compiled = 0;
+    for dump in system {
+        if let Value::Program(program) = &dump.value {
+            match check_program(cache.engine(), program) {
+                Ok(_) => compiled += 1,
+                Err(e) => checks.push(Check::failed(
+                    VERBS,
+                    format!("'{}' doesn't compile: {}", dump.slot_def.name, e),
+                    "Fix or replace the verb; it will fail every time it's called.",
+                )),
+            }
+        }
+    }
+    checks.push(Check::ok(VERBS, format!("{} compiled", compiled)));
+}
+
+async fn check_listen(address: &str) -> Check {
+    let name = format!("listen {}", address);
+    match tokio::net::TcpListener::bind(address).await {
+        Ok(_) => Check::ok(&name, "free".to_string()),
+        Err(e) => Check::failed(
+            &name,
+            format!("can't bind: {}", e),
+            "Stop whatever is using the port, or choose another address.",
+        ),
+    }
+}
+
+fn check_writable(dir: &Path) -> Check {
+    const NAME: &str = "dump directory";
+    let probe = dir.join(".doctor-probe");
+    let written = std::fs::create_dir_all(dir)
+        .and_then(|()| std::fs::write(&probe, b"probe"))
+        .and_then(|()| std::fs::remove_file(&probe));
+    match written {
+        Ok(()) => Check::ok(NAME, format!("{} is writable", dir.display())),
+        Err(e) => Check::failed(
+            NAME,
+            format!("{} isn't writable: {}", dir.display(), e),
+            "Saves and checkpoints would fail. Fix its permissions or choose another --dump-path.",
+        ),
+    }
+}
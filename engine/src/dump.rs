@@ -0,0 +1,508 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+use crate::object::{SlotDef, SlotMeta};
+use crate::object_store::{ObjectStore, ObjectStoreOptions, Snapshot};
+use value::{Oid, Value};
+
+/// A single slot, as written out to a dump.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Dump {
+    pub slot_def: SlotDef,
+    pub value: Value,
+    /// Its owner, permissions and timestamps, if it has any; dumps from before slots had them
+    /// have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<SlotMeta>,
+}
+
+/// The file in a dump directory listing the slot files a save wrote, with a digest of each.
+pub const DUMP_MANIFEST_FILE: &str = "manifest.json";
+
+/// What a save to a directory wrote: each slot file's name with the SHA-256 of its contents (in
+/// hex), and how many slots and objects there were. Written after the slot files, so a save which
+/// failed part way leaves the last complete save's manifest.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct DumpManifest {
+    pub written: u64,
+    pub slots: usize,
+    pub objects: usize,
+    pub entries: BTreeMap<String, String>,
+}
+
+impl DumpManifest {
+    fn new(entries: BTreeMap<String, String>) -> Self {
+        // Slot files are named after the object's hyphenated Oid first.
+        let objects: HashSet<&str> = entries
+            .keys()
+            .map(|name| name.get(..36).unwrap_or(name))
+            .collect();
+        DumpManifest {
+            written: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            slots: entries.len(),
+            objects: objects.len(),
+            entries,
+        }
+    }
+
+    fn read(slot_path: &Path) -> Result<Option<Self>, Error> {
+        match std::fs::read(slot_path.join(DUMP_MANIFEST_FILE)) {
+            Ok(payload) => Ok(Some(serde_json::from_slice(&payload)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Moved into place, so it's never seen half written.
+    fn write(&self, slot_path: &Path) -> Result<(), Error> {
+        let temporary = slot_path.join(format!(".{}.tmp", DUMP_MANIFEST_FILE));
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&temporary, slot_path.join(DUMP_MANIFEST_FILE))?;
+        Ok(())
+    }
+}
+
+/// What loading a dump found. Files are only checked against a manifest if the dump has one.
+#[derive(Serialize, Debug, Default)]
+pub struct LoadReport {
+    /// Whether there was a manifest to check the files against.
+    pub verified: bool,
+    pub loaded: usize,
+    /// Files which aren't slot dumps, and aren't in the manifest.
+    pub skipped: Vec<String>,
+    /// Files in the manifest whose digest doesn't match, or which aren't slot dumps. Those which
+    /// are slot dumps are loaded all the same, unless loading is strict.
+    pub corrupt: Vec<String>,
+    /// Slot dumps which aren't in the manifest, as a save which failed part way leaves.
+    pub unlisted: Vec<String>,
+    /// Files in the manifest which aren't there.
+    pub missing: Vec<String>,
+}
+
+impl LoadReport {
+    /// Whether everything in the dump was as its manifest says. There being no dump at all is
+    /// clean too.
+    pub fn clean(&self) -> bool {
+        let intact = self.skipped.is_empty()
+            && self.corrupt.is_empty()
+            && self.unlisted.is_empty()
+            && self.missing.is_empty();
+        intact && (self.verified || self.loaded == 0)
+    }
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Where `save` writes dumps to and `load` reads them back from.
+#[derive(Clone, Debug)]
+pub enum DumpTarget {
+    /// A local directory, with one json file per slot.
+    Directory(PathBuf),
+    /// An S3-compatible bucket, with each save written as a single timestamped snapshot archive.
+    ObjectStore(ObjectStoreOptions),
+}
+
+impl DumpTarget {
+    /// Read back the most recent dump, with a report of what was found. Returns an empty vector if
+    /// there isn't one. If `strict`, a directory whose files don't all match its manifest (or which
+    /// has none) isn't read at all, and nor is a snapshot which doesn't match its digest (or which
+    /// has none).
+    pub async fn read(&self, strict: bool) -> Result<(Vec<Dump>, LoadReport), Error> {
+        match self {
+            DumpTarget::Directory(path) => {
+                let (dumps, report) = read_directory(path)?;
+                if strict && !report.clean() {
+                    return Err(anyhow!(
+                        "Dump in {:?} failed verification: {}",
+                        path,
+                        serde_json::to_string(&report)?
+                    ));
+                }
+                Ok((dumps, report))
+            }
+            DumpTarget::ObjectStore(options) => {
+                let store = ObjectStore::open(options)?;
+                let snapshot = match store.latest_snapshot().await? {
+                    Some(snapshot) => snapshot,
+                    None => return Ok((vec![], LoadReport::default())),
+                };
+                let mut report = snapshot_report(&snapshot);
+                if strict && !(report.verified && report.corrupt.is_empty()) {
+                    return Err(anyhow!(
+                        "Snapshot {} failed verification: {}",
+                        snapshot.key,
+                        serde_json::to_string(&report)?
+                    ));
+                }
+                let dumps: Vec<Dump> = serde_json::from_slice(snapshot.payload.as_slice())?;
+                report.loaded = dumps.len();
+                Ok((dumps, report))
+            }
+        }
+    }
+
+    pub async fn write(&self, dumps: &[Dump]) -> Result<(), Error> {
+        match self {
+            DumpTarget::Directory(path) => write_directory(path, dumps),
+            DumpTarget::ObjectStore(options) => {
+                let store = ObjectStore::open(options)?;
+                let payload = serde_json::to_vec(dumps)?;
+                let size = payload.len();
+                let written = store.put_snapshot(payload).await?;
+                prune_snapshots(&store, &written, size).await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Replace just the slots of `oids` with `dumps`, leaving those of other objects as they were
+    /// last written. Slots of `oids` which aren't among `dumps` are removed.
+    ///
+    /// A directory only has the objects' files rewritten. Snapshots are whole, so a new one is
+    /// written from the latest, with the objects' slots replaced.
+    pub async fn write_objects(&self, oids: &HashSet<Oid>, dumps: &[Dump]) -> Result<(), Error> {
+        match self {
+            DumpTarget::Directory(path) => write_directory_objects(path, oids, dumps),
+            DumpTarget::ObjectStore(options) => {
+                let store = ObjectStore::open(options)?;
+                let mut merged: Vec<Dump> = match store.latest_snapshot().await? {
+                    Some(snapshot) => serde_json::from_slice(snapshot.payload.as_slice())?,
+                    None => vec![],
+                };
+                merged.retain(|dump| !oids.contains(&dump.slot_def.location));
+                merged.extend_from_slice(dumps);
+                let payload = serde_json::to_vec(&merged)?;
+                let size = payload.len();
+                let written = store.put_snapshot(payload).await?;
+                prune_snapshots(&store, &written, size).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+// Prune the snapshots the store's retention policy doesn't keep, now that `written` is saved. The
+// save stands whether or not they can be pruned, so failing to is only logged.
+async fn prune_snapshots(store: &ObjectStore, written: &str, size: usize) {
+    if let Err(e) = store.prune(written, size).await {
+        error!("Could not prune snapshots after writing {}: {}", written, e);
+    }
+}
+
+// What checking a snapshot against its digest found, before it's loaded.
+fn snapshot_report(snapshot: &Snapshot) -> LoadReport {
+    let mut report = LoadReport {
+        verified: snapshot.intact.is_some(),
+        ..LoadReport::default()
+    };
+    if snapshot.intact == Some(false) {
+        warn!("Snapshot {} doesn't match its digest", snapshot.key);
+        report.corrupt.push(snapshot.key.clone());
+    }
+    report
+}
+
+fn dump_file_name(slot_def: &SlotDef) -> String {
+    format!(
+        "{}-{}.{}",
+        slot_def.location.id.to_hyphenated(),
+        slot_def.key.id.to_hyphenated(),
+        slot_def.name
+    )
+}
+
+fn ensure_directory(slot_path: &Path) -> Result<(), Error> {
+    match slot_path.is_dir() {
+        true => Ok(()),
+        false => Err(anyhow!("Dump path {:?} isn't a directory", slot_path)),
+    }
+}
+
+// Each file contains a json serialization of:
+// A header defining the slot
+// The value defining the slot contents
+// and each is checked against the manifest, if there is one.
+fn read_directory(slot_path: &Path) -> Result<(Vec<Dump>, LoadReport), Error> {
+    ensure_directory(slot_path)?;
+
+    let mut manifest = DumpManifest::read(slot_path)?;
+    let mut report = LoadReport {
+        verified: manifest.is_some(),
+        ..LoadReport::default()
+    };
+    let mut dumps = vec![];
+    for entry in std::fs::read_dir(slot_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if path.is_dir() || file_name == DUMP_MANIFEST_FILE {
+            continue;
+        }
+        let payload = std::fs::read(&path)?;
+        let expected = manifest
+            .as_mut()
+            .and_then(|manifest| manifest.entries.remove(&file_name));
+        let dump_result: Result<Dump, _> = serde_json::from_slice(payload.as_slice());
+        match (dump_result, expected) {
+            (Ok(dump), Some(digest)) => {
+                if hex_digest(&payload) != digest {
+                    warn!("Slot dump {:?} doesn't match its digest", path);
+                    report.corrupt.push(file_name);
+                }
+                dumps.push(dump);
+            }
+            (Ok(dump), None) => {
+                if report.verified {
+                    warn!("Slot dump {:?} isn't in the manifest", path);
+                    report.unlisted.push(file_name);
+                }
+                dumps.push(dump);
+            }
+            (Err(e), Some(_)) => {
+                warn!("Slot dump {:?} is unreadable: {:?}", path, e);
+                report.corrupt.push(file_name);
+            }
+            (Err(e), None) => {
+                info!("File {:?} is not a valid slot dump: {:?}", path, e);
+                report.skipped.push(file_name);
+            }
+        }
+    }
+    // Whatever's left in the manifest wasn't found.
+    if let Some(manifest) = manifest {
+        report.missing.extend(manifest.entries.into_keys());
+    }
+    report.loaded = dumps.len();
+    Ok((dumps, report))
+}
+
+// Slot files left from an earlier dump on the objects written, but which aren't part of this one,
+// are removed, so that slots which have since been deleted aren't brought back by the next load.
+// Files on other objects are left alone, and keep their manifest entries.
+fn write_directory(slot_path: &Path, dumps: &[Dump]) -> Result<(), Error> {
+    ensure_directory(slot_path)?;
+
+    let current: HashSet<&SlotDef> = dumps.iter().map(|dump| &dump.slot_def).collect();
+    let locations: HashSet<Oid> = dumps.iter().map(|dump| dump.slot_def.location).collect();
+    for entry in std::fs::read_dir(slot_path)? {
+        let path = entry?.path();
+        if path.is_dir() || path.ends_with(DUMP_MANIFEST_FILE) {
+            continue;
+        }
+        if let Ok(dump) = serde_json::from_slice::<Dump>(&std::fs::read(&path)?) {
+            if locations.contains(&dump.slot_def.location) && !current.contains(&dump.slot_def) {
+                info!("Removing stale slot {:?}", path);
+                std::fs::remove_file(path)?;
+            }
+        }
+    }
+
+    let prefixes: Vec<String> = locations
+        .iter()
+        .map(|oid| format!("{}-", oid.id.to_hyphenated()))
+        .collect();
+    let mut entries = match DumpManifest::read(slot_path)? {
+        Some(manifest) => manifest.entries,
+        None => BTreeMap::new(),
+    };
+    entries.retain(|name, _| !prefixes.iter().any(|p| name.starts_with(p)));
+    for dump in dumps {
+        let result_buf = serde_json::to_vec(&dump)?;
+        let pathname = dump_file_name(&dump.slot_def);
+        let path = slot_path.join(Path::new(pathname.as_str()));
+        info!("Writing slot {:?}", path);
+        entries.insert(pathname, hex_digest(&result_buf));
+        std::fs::write(path, result_buf)?;
+    }
+    DumpManifest::new(entries).write(slot_path)
+}
+
+// Files are named after the slots in them, so the objects' old files can be found without reading
+// them. Each is written to a temporary file first and moved into place, so that a crash part way
+// through leaves either the old slot or the new one. The manifest, if there is one, has the
+// objects' entries replaced; without one, there's no whole save to add them to.
+fn write_directory_objects(
+    slot_path: &Path,
+    oids: &HashSet<Oid>,
+    dumps: &[Dump],
+) -> Result<(), Error> {
+    ensure_directory(slot_path)?;
+
+    let current: HashSet<String> = dumps
+        .iter()
+        .map(|dump| dump_file_name(&dump.slot_def))
+        .collect();
+    let prefixes: Vec<String> = oids
+        .iter()
+        .map(|oid| format!("{}-", oid.id.to_hyphenated()))
+        .collect();
+    for entry in std::fs::read_dir(slot_path)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if prefixes.iter().any(|p| file_name.starts_with(p)) && !current.contains(&file_name) {
+            info!("Removing stale slot {:?}", entry.path());
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+
+    let mut manifest = DumpManifest::read(slot_path)?;
+    if let Some(manifest) = &mut manifest {
+        manifest
+            .entries
+            .retain(|name, _| !prefixes.iter().any(|p| name.starts_with(p)));
+    }
+    for dump in dumps {
+        let pathname = dump_file_name(&dump.slot_def);
+        let path = slot_path.join(Path::new(pathname.as_str()));
+        let temporary = slot_path.join(format!(".{}.tmp", pathname));
+        let payload = serde_json::to_vec(&dump)?;
+        if let Some(manifest) = &mut manifest {
+            manifest.entries.insert(pathname, hex_digest(&payload));
+        }
+        std::fs::write(&temporary, payload)?;
+        std::fs::rename(&temporary, &path)?;
+    }
+    match manifest {
+        Some(manifest) => DumpManifest::new(manifest.entries).write(slot_path),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(location: Oid, name: &str, value: i64) -> Dump {
+        Dump {
+            slot_def: SlotDef {
+                location,
+                key: Oid {
+                    id: uuid::Uuid::nil(),
+                },
+                name: name.to_string(),
+            },
+            value: Value::I64(value),
+            meta: None,
+        }
+    }
+
+    // A directory of its own, removed on drop.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("room-dump-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir(&path).unwrap();
+            Scratch(path)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn names(dumps: &[Dump]) -> Vec<String> {
+        let mut names: Vec<String> = dumps.iter().map(|d| d.slot_def.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn round_trip_is_verified() {
+        let dir = Scratch::new();
+        let oid = Oid {
+            id: uuid::Uuid::new_v4(),
+        };
+        write_directory(&dir.0, &[dump(oid, "a", 1), dump(oid, "b", 2)]).unwrap();
+        let (dumps, report) = read_directory(&dir.0).unwrap();
+        assert_eq!(names(&dumps), vec!["a", "b"]);
+        assert!(report.verified);
+        assert_eq!(report.loaded, 2);
+        assert!(report.clean());
+    }
+
+    #[test]
+    fn stale_slots_are_removed() {
+        let dir = Scratch::new();
+        let oid = Oid {
+            id: uuid::Uuid::new_v4(),
+        };
+        write_directory(&dir.0, &[dump(oid, "a", 1), dump(oid, "b", 2)]).unwrap();
+        write_directory(&dir.0, &[dump(oid, "a", 3)]).unwrap();
+        let (dumps, report) = read_directory(&dir.0).unwrap();
+        assert_eq!(names(&dumps), vec!["a"]);
+        assert!(report.clean());
+    }
+
+    #[test]
+    fn tampering_is_reported() {
+        let dir = Scratch::new();
+        let oid = Oid {
+            id: uuid::Uuid::new_v4(),
+        };
+        let (a, b) = (dump(oid, "a", 1), dump(oid, "b", 2));
+        write_directory(&dir.0, &[a.clone(), b.clone()]).unwrap();
+
+        let changed = dump(oid, "a", 4);
+        std::fs::write(
+            dir.0.join(dump_file_name(&a.slot_def)),
+            serde_json::to_vec(&changed).unwrap(),
+        )
+        .unwrap();
+        std::fs::remove_file(dir.0.join(dump_file_name(&b.slot_def))).unwrap();
+        std::fs::write(dir.0.join("notes.txt"), b"not a dump").unwrap();
+
+        let (dumps, report) = read_directory(&dir.0).unwrap();
+        assert_eq!(names(&dumps), vec!["a"]);
+        assert_eq!(report.corrupt, vec![dump_file_name(&a.slot_def)]);
+        assert_eq!(report.missing, vec![dump_file_name(&b.slot_def)]);
+        assert_eq!(report.skipped, vec!["notes.txt"]);
+        assert!(!report.clean());
+    }
+
+    #[test]
+    fn without_a_manifest_is_unverified() {
+        let dir = Scratch::new();
+        let oid = Oid {
+            id: uuid::Uuid::new_v4(),
+        };
+        write_directory(&dir.0, &[dump(oid, "a", 1)]).unwrap();
+        std::fs::remove_file(dir.0.join(DUMP_MANIFEST_FILE)).unwrap();
+        let (dumps, report) = read_directory(&dir.0).unwrap();
+        assert_eq!(dumps.len(), 1);
+        assert!(!report.verified);
+        assert!(report.unlisted.is_empty());
+        assert!(!report.clean());
+        assert!(LoadReport::default().clean());
+    }
+
+    #[test]
+    fn snapshots_are_checked_against_their_digest() {
+        let snapshot = |intact| Snapshot {
+            key: "snapshots/1.json".to_string(),
+            payload: vec![],
+            intact,
+        };
+        let report = snapshot_report(&snapshot(Some(true)));
+        assert!(report.verified && report.corrupt.is_empty());
+        let report = snapshot_report(&snapshot(Some(false)));
+        assert_eq!(report.corrupt, vec!["snapshots/1.json"]);
+        assert!(!snapshot_report(&snapshot(None)).verified);
+    }
+}
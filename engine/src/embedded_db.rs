@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::future::{BoxFuture, FutureExt};
+
+use crate::database::DbError;
+
+/// An embedded storage backend built on sled, for single node deployments which can't run
+/// FoundationDB.
+///
+/// Transactions are optimistic, like FDB's: reads go straight to the store, writes are buffered
+/// until commit, and at commit time the transaction is rejected with `DbError::Conflict` if
+/// anything it read was written by a transaction which committed after it began.
+pub struct EmbeddedDatabase {
+    db: sled::Db,
+    commits: Arc<Mutex<CommitLog>>,
+}
+
+// How many recent commits to remember write sets for. Transactions which began before the oldest
+// of these can't be checked and will always conflict.
+const COMMIT_LOG_SIZE: usize = 4096;
+
+// A half-open [begin, end) range of keys.
+type KeyRange = (Bytes, Bytes);
+
+#[derive(Default)]
+struct CommitLog {
+    version: u64,
+    entries: VecDeque<(u64, Vec<KeyRange>)>,
+}
+
+struct TxState {
+    read_version: u64,
+    reads: Vec<KeyRange>,
+    writes: BTreeMap<Bytes, Option<Bytes>>,
+    cleared: Vec<KeyRange>,
+    // Applied in order at commit, to whatever the keys hold by then.
+    atomic_ops: Vec<(Bytes, AtomicOp)>,
+    // Keys to be completed with the commit's versionstamp: their prefixes, orders and values.
+    versionstamped: Vec<(Bytes, u16, Bytes)>,
+}
+
+/// Read-modify-write operations on little-endian i64 values, done at commit time without reading
+/// the key into the transaction, so they don't conflict with each other. Missing (or malformed)
+/// values count as zero.
+#[derive(Clone, Copy, Debug)]
+pub enum AtomicOp {
+    Add(i64),
+    Max(i64),
+}
+
+impl AtomicOp {
+    fn apply(self, current: Option<&[u8]>) -> i64 {
+        let current = current
+            .and_then(|v| v.try_into().ok())
+            .map(i64::from_le_bytes)
+            .unwrap_or(0);
+        match self {
+            AtomicOp::Add(delta) => current.wrapping_add(delta),
+            AtomicOp::Max(value) => current.max(value),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EmbeddedTransaction {
+    db: sled::Db,
+    commits: Arc<Mutex<CommitLog>>,
+    state: Arc<Mutex<TxState>>,
+}
+
+fn key_range(key: &Bytes) -> KeyRange {
+    let mut end = key.to_vec();
+    end.push(0);
+    (key.clone(), Bytes::from(end))
+}
+
+fn in_range(range: &KeyRange, key: &[u8]) -> bool {
+    range.0[..] <= *key && *key < range.1[..]
+}
+
+fn overlaps(a: &KeyRange, b: &KeyRange) -> bool {
+    a.0 < b.1 && b.0 < a.1
+}
+
+impl EmbeddedDatabase {
+    pub fn open(path: &Path) -> Result<Self, DbError> {
+        Ok(EmbeddedDatabase {
+            db: sled::open(path)?,
+            commits: Arc::new(Mutex::new(CommitLog::default())),
+        })
+    }
+
+    /// Open a database which lives only as long as it's open, and is deleted afterwards.
+    pub fn open_temporary() -> Result<Self, DbError> {
+        Ok(EmbeddedDatabase {
+            db: sled::Config::new().temporary(true).open()?,
+            commits: Arc::new(Mutex::new(CommitLog::default())),
+        })
+    }
+
+    pub fn create_transaction(&self) -> EmbeddedTransaction {
+        let read_version = self.commits.lock().unwrap().version;
+        EmbeddedTransaction {
+            db: self.db.clone(),
+            commits: self.commits.clone(),
+            state: Arc::new(Mutex::new(TxState {
+                read_version,
+                reads: vec![],
+                writes: BTreeMap::new(),
+                cleared: vec![],
+                atomic_ops: vec![],
+                versionstamped: vec![],
+            })),
+        }
+    }
+
+    pub async fn flush(&self) -> Result<(), DbError> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+}
+
+impl EmbeddedTransaction {
+    pub fn get(&self, key: Bytes) -> Result<Option<Bytes>, DbError> {
+        self.read(key, true)
+    }
+
+    /// As `get`, without the read counting towards conflicts.
+    pub fn snapshot_get(&self, key: Bytes) -> Result<Option<Bytes>, DbError> {
+        self.read(key, false)
+    }
+
+    fn read(&self, key: Bytes, conflicting: bool) -> Result<Option<Bytes>, DbError> {
+        let mut state = self.state.lock().unwrap();
+        if conflicting {
+            state.reads.push(key_range(&key));
+        }
+        let current = match state.writes.get(&key) {
+            Some(written) => written.clone(),
+            None if state.cleared.iter().any(|r| in_range(r, &key)) => None,
+            None => self.db.get(&key)?.map(|v| Bytes::copy_from_slice(&v)),
+        };
+        // Reads see the transaction's own atomic operations, as with FDB.
+        let ops = state.atomic_ops.iter().filter(|(k, _)| *k == key);
+        Ok(ops.fold(current, |current, (_, op)| {
+            let value = op.apply(current.as_deref());
+            Some(Bytes::copy_from_slice(&value.to_le_bytes()))
+        }))
+    }
+
+    pub fn get_range(&self, begin: Bytes, end: Bytes) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        self.read_range(begin, end, true)
+    }
+
+    /// As `get_range`, without the read counting towards conflicts.
+    pub fn snapshot_get_range(
+        &self,
+        begin: Bytes,
+        end: Bytes,
+    ) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        self.read_range(begin, end, false)
+    }
+
+    /// The last key in [begin, end), with its value, without the read counting towards
+    /// conflicts.
+    pub fn snapshot_last_in_range(
+        &self,
+        begin: Bytes,
+        end: Bytes,
+    ) -> Result<Option<(Bytes, Bytes)>, DbError> {
+        let state = self.state.lock().unwrap();
+        // The last stored which the transaction hasn't cleared, or the last it's written itself,
+        // whichever's later.
+        let mut stored = None;
+        for kv in self.db.range(&begin[..]..&end[..]).rev() {
+            let (k, v) = kv?;
+            let k = Bytes::copy_from_slice(&k);
+            match state.writes.get(&k) {
+                Some(Some(written)) => stored = Some((k, written.clone())),
+                Some(None) => continue,
+                None if state.cleared.iter().any(|r| in_range(r, &k)) => continue,
+                None => stored = Some((k, Bytes::copy_from_slice(&v))),
+            }
+            break;
+        }
+        let written = state
+            .writes
+            .range(begin..end)
+            .rev()
+            .find_map(|(k, v)| Some((k.clone(), v.clone()?)));
+        Ok(match (stored, written) {
+            (Some(stored), Some(written)) => Some(stored.max(written)),
+            (stored, written) => stored.or(written),
+        })
+    }
+
+    fn read_range(
+        &self,
+        begin: Bytes,
+        end: Bytes,
+        conflicting: bool,
+    ) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        let mut state = self.state.lock().unwrap();
+        if conflicting {
+            state.reads.push((begin.clone(), end.clone()));
+        }
+
+        let mut result = BTreeMap::new();
+        for kv in self.db.range(&begin[..]..&end[..]) {
+            let (k, v) = kv?;
+            if !state.cleared.iter().any(|r| in_range(r, &k)) {
+                result.insert(Bytes::copy_from_slice(&k), Bytes::copy_from_slice(&v));
+            }
+        }
+        // Overlay our own uncommitted writes.
+        for (k, written) in state.writes.range(begin..end) {
+            match written {
+                Some(v) => result.insert(k.clone(), v.clone()),
+                None => result.remove(k),
+            };
+        }
+        Ok(result.into_iter().collect())
+    }
+
+    pub fn set(&self, key: Bytes, value: Bytes) {
+        self.state.lock().unwrap().writes.insert(key, Some(value));
+    }
+
+    pub fn clear(&self, key: Bytes) {
+        self.state.lock().unwrap().writes.insert(key, None);
+    }
+
+    pub fn atomic_op(&self, key: Bytes, op: AtomicOp) {
+        self.state.lock().unwrap().atomic_ops.push((key, op));
+    }
+
+    /// Set `prefix`, followed by the commit's versionstamp and `order`, to `value` at commit.
+    pub fn set_versionstamped(&self, prefix: Bytes, order: u16, value: Bytes) {
+        let mut state = self.state.lock().unwrap();
+        state.versionstamped.push((prefix, order, value));
+    }
+
+    pub fn clear_range(&self, begin: Bytes, end: Bytes) {
+        let mut state = self.state.lock().unwrap();
+        let range = (begin, end);
+        state.writes.retain(|k, _| !in_range(&range, k));
+        state.cleared.push(range);
+    }
+
+    /// Emulates an FDB watch with a sled subscription on the key.
+    pub fn watch(&self, key: Bytes) -> BoxFuture<'static, Result<(), DbError>> {
+        let mut subscriber = self.db.watch_prefix(&key[..]);
+        async move {
+            while let Some(event) = (&mut subscriber).await {
+                if event.key()[..] == key[..] {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    pub fn commit(&self) -> Result<(), DbError> {
+        let mut state = self.state.lock().unwrap();
+        let mut log = self.commits.lock().unwrap();
+
+        if !state.reads.is_empty() {
+            if let Some((oldest, _)) = log.entries.front() {
+                if *oldest > state.read_version + 1 {
+                    return Err(DbError::Conflict);
+                }
+            }
+            let conflicted = log
+                .entries
+                .iter()
+                .filter(|(version, _)| *version > state.read_version)
+                .any(|(_, written)| {
+                    written
+                        .iter()
+                        .any(|w| state.reads.iter().any(|r| overlaps(w, r)))
+                });
+            if conflicted {
+                return Err(DbError::Conflict);
+            }
+        }
+
+        if state.writes.is_empty()
+            && state.cleared.is_empty()
+            && state.atomic_ops.is_empty()
+            && state.versionstamped.is_empty()
+        {
+            return Ok(());
+        }
+
+        // Holding the commit log's lock, nothing else can write the keys between reading them
+        // here and applying the batch.
+        for (k, op) in std::mem::take(&mut state.atomic_ops) {
+            let current = match state.writes.get(&k) {
+                Some(written) => written.clone(),
+                None if state.cleared.iter().any(|r| in_range(r, &k)) => None,
+                None => self.db.get(&k)?.map(|v| Bytes::copy_from_slice(&v)),
+            };
+            let value = op.apply(current.as_deref());
+            state
+                .writes
+                .insert(k, Some(Bytes::copy_from_slice(&value.to_le_bytes())));
+        }
+
+        // Versionstamps are sled's generated ids, which keep increasing across restarts, followed
+        // by two bytes which are always zero, to be as long as FDB's.
+        if !state.versionstamped.is_empty() {
+            let stamp = self.db.generate_id()?;
+            for (prefix, order, value) in std::mem::take(&mut state.versionstamped) {
+                let mut key = prefix.to_vec();
+                key.extend_from_slice(&stamp.to_be_bytes());
+                key.extend_from_slice(&[0; 2]);
+                key.extend_from_slice(&order.to_be_bytes());
+                state.writes.insert(Bytes::from(key), Some(value));
+            }
+        }
+
+        let mut batch = sled::Batch::default();
+        let mut written = vec![];
+        for range in &state.cleared {
+            for k in self.db.range(&range.0[..]..&range.1[..]).keys() {
+                batch.remove(k?);
+            }
+            written.push(range.clone());
+        }
+        for (k, v) in &state.writes {
+            match v {
+                Some(v) => batch.insert(&k[..], &v[..]),
+                None => batch.remove(&k[..]),
+            }
+            written.push(key_range(k));
+        }
+        self.db.apply_batch(batch)?;
+
+        log.version += 1;
+        let version = log.version;
+        log.entries.push_back((version, written));
+        if log.entries.len() > COMMIT_LOG_SIZE {
+            log.entries.pop_front();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use rand::Rng;
+use tracing::warn;
+
+/// Faults to inject deliberately, at random, so that the engine's retry and cleanup paths can be
+/// exercised in integration tests. Rates are probabilities from 0 (never) to 1 (always).
+///
+/// Never enable these on a world anyone is actually using.
+#[derive(Clone, Debug, Default)]
+pub struct FaultOptions {
+    /// Rate at which to hold up transactions before running them.
+    pub tx_delay_rate: f64,
+    /// Longest to hold up a transaction for. Each delay is picked uniformly up to this.
+    pub tx_max_delay: Duration,
+    /// Rate at which to fail transactions with a retryable error instead of committing them.
+    pub tx_fail_rate: f64,
+    /// Rate at which to silently drop messages on their way out to connections.
+    pub frame_drop_rate: f64,
+    /// Rate at which to trap verb invocations before they run.
+    pub trap_rate: f64,
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::thread_rng().gen_bool(rate.min(1.0))
+}
+
+impl FaultOptions {
+    pub async fn delay_tx(&self) {
+        if roll(self.tx_delay_rate) && !self.tx_max_delay.is_zero() {
+            let delay = rand::thread_rng().gen_range(Duration::ZERO..=self.tx_max_delay);
+            warn!(target: "faults", "Delaying transaction by {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    pub fn fail_tx(&self) -> bool {
+        let fail = roll(self.tx_fail_rate);
+        if fail {
+            warn!(target: "faults", "Failing transaction");
+        }
+        fail
+    }
+
+    pub fn drop_frame(&self) -> bool {
+        let drop = roll(self.frame_drop_rate);
+        if drop {
+            warn!(target: "faults", "Dropping outbound message");
+        }
+        drop
+    }
+
+    pub fn trap_verb(&self) -> bool {
+        let trap = roll(self.trap_rate);
+        if trap {
+            warn!(target: "faults", "Trapping verb");
+        }
+        trap
+    }
+}
@@ -1,20 +1,22 @@
 use assert_str::assert_str_eq;
 use bytes::Bytes;
 
-use fdb::{
-    range::RangeOptions,
-    subspace::Subspace,
-    transaction::{FdbTransaction, ReadTransaction, Transaction},
-    tuple::Tuple,
-    Key,
-};
+use fdb::range::Range;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
 use futures::future::{BoxFuture, FutureExt};
+use futures::stream::BoxStream;
 use int_enum::IntEnum;
+use sha2::{Digest, Sha256};
 
 use tokio_stream::StreamExt;
+use tracing::warn;
 
-use crate::object::{AdminHandle, ObjDBHandle, SlotDef};
-use value::{Error, Oid, Value, ValueType};
+use crate::blob::{BlobTxHandle, BLOB_THRESHOLD};
+use crate::changes::{ChangeKind, ChangesTxHandle};
+use crate::database::{key_after, DbError, Tx};
+use crate::object::{AdminHandle, ObjDBHandle, SlotDef, SlotMeta};
+use crate::search::SearchTxHandle;
+use value::{Error, Oid, Program, ProgramLang, Value, ValueType};
 
 pub trait RangeKey {
     fn list_start_key(location: Oid, definer: Oid) -> Tuple;
@@ -57,6 +59,25 @@ impl From<SlotDef> for fdb::Key {
     }
 }
 
+/// The slot whose value is kept at `key`, if it's a slot's key.
+pub fn slot_at(key: &Key) -> Option<SlotDef> {
+    let slotdef_subspace = Subspace::new(Bytes::from_static("SLOT".as_bytes()));
+    let bytes: Bytes = key.clone().into();
+    slotdef_subspace
+        .contains(&bytes)
+        .then(|| SlotDef::from(key.clone()))
+}
+
+/// The object a slot key, or an object's own key, belongs to.
+pub fn object_at(key: &Key) -> Option<Oid> {
+    let oid_subspace = Subspace::new(Bytes::from_static("OID".as_bytes()));
+    let bytes: Bytes = key.clone().into();
+    match oid_subspace.contains(&bytes) {
+        true => Some(FdbOid::from(key.clone()).0),
+        false => slot_at(key).map(|slot| slot.location),
+    }
+}
+
 impl From<fdb::Key> for SlotDef {
     fn from(key: fdb::Key) -> Self {
         let slotdef_subspace = Subspace::new(Bytes::from_static("SLOT".as_bytes()));
@@ -108,7 +129,7 @@ impl From<&Tuple> for FdbValue {
             }
             ValueType::String => {
                 let str = tuple.get_string_ref(2).unwrap();
-                FdbValue(Value::String(str.clone()))
+                FdbValue(Value::String(str.as_str().into()))
             }
             ValueType::IdKey => {
                 let oid = tuple.get_uuid_ref(2).unwrap();
@@ -126,16 +147,36 @@ impl From<&Tuple> for FdbValue {
             }
             ValueType::Binary => {
                 let bytes = tuple.get_bytes_ref(2).unwrap();
-                FdbValue(Value::Binary(bytes.to_vec()))
+                FdbValue(Value::Binary(bytes.clone()))
             }
             ValueType::Program => {
                 let bytes = tuple.get_bytes_ref(2).unwrap();
-                FdbValue(Value::Program(bytes.to_vec()))
+                // Programs stored before they were tagged with their language have no tag. One
+                // tagged with a language this engine doesn't know reads as InvalidProgram.
+                let lang = match tuple.get_i8(3) {
+                    Ok(lang) => ProgramLang::from_int(lang).map_err(|_| lang),
+                    Err(_) => Ok(ProgramLang::detect(bytes)),
+                };
+                match lang {
+                    Ok(lang) => FdbValue(Value::Program(Program::new(lang, bytes.clone()))),
+                    Err(lang) => {
+                        warn!("Program in an unknown language {}", lang);
+                        FdbValue(Value::Error(Error::InvalidProgram))
+                    }
+                }
             }
             ValueType::Error => {
                 let num = tuple.get_i8(2).unwrap();
                 FdbValue(Value::Error(Error::from_int(num).unwrap()))
             }
+            ValueType::Timestamp => {
+                let nanos = tuple.get_i64(2).unwrap();
+                FdbValue(Value::Timestamp(nanos))
+            }
+            ValueType::Blob => {
+                let len = tuple.get_i64(2).unwrap();
+                FdbValue(Value::Blob(len as u64))
+            }
         }
     }
 }
@@ -176,7 +217,7 @@ impl From<&FdbValue> for Tuple {
             }
             Value::String(s) => {
                 tup.add_i8(ValueType::String as i8);
-                tup.add_string(s.clone());
+                tup.add_string(s.to_string());
             }
             Value::IdKey(u) => {
                 tup.add_i8(ValueType::IdKey as i8);
@@ -192,16 +233,25 @@ impl From<&FdbValue> for Tuple {
             }
             Value::Binary(b) => {
                 tup.add_i8(ValueType::Binary as i8);
-                tup.add_bytes(Bytes::from(b.clone()));
+                tup.add_bytes(b.clone());
             }
-            Value::Program(b) => {
+            Value::Program(p) => {
                 tup.add_i8(ValueType::Program as i8);
-                tup.add_bytes(Bytes::from(b.clone()));
+                tup.add_bytes(p.code.clone());
+                tup.add_i8(p.lang as i8);
             }
             Value::Error(err) => {
                 tup.add_i8(ValueType::Error as i8);
                 tup.add_i8(*err as i8);
             }
+            Value::Timestamp(nanos) => {
+                tup.add_i8(ValueType::Timestamp as i8);
+                tup.add_i64(*nanos);
+            }
+            Value::Blob(len) => {
+                tup.add_i8(ValueType::Blob as i8);
+                tup.add_i64(*len as i64);
+            }
         }
         tup
     }
@@ -220,25 +270,285 @@ impl From<&FdbValue> for fdb::Value {
     }
 }
 
+// A slot's metadata is kept in a tuple of its own after its value's fields:
+// ("META", owner, flags, created, modified), with the nil Uuid for no owner.
+const META: &str = "META";
+
+// Where the metadata follows the value in a slot's tuple, if it's there.
+fn meta_index(tuple: &Tuple) -> usize {
+    match ValueType::from_int(tuple.get_i8(1).unwrap()).unwrap() {
+        ValueType::Vector => 3 + tuple.get_i32(2).unwrap() as usize,
+        ValueType::Program => 4,
+        _ => 3,
+    }
+}
+
+fn slot_meta(tuple: &Tuple) -> Option<SlotMeta> {
+    let meta = tuple.get_tuple_ref(meta_index(tuple)).ok()?;
+    if meta.get_string_ref(0).ok()? != META {
+        return None;
+    }
+    let owner = *meta.get_uuid_ref(1).ok()?;
+    Some(SlotMeta {
+        owner: (!owner.is_nil()).then_some(Oid { id: owner }),
+        flags: meta.get_i8(2).ok()? as u8,
+        created: meta.get_i64(3).ok()?,
+        modified: meta.get_i64(4).ok()?,
+    })
+}
+
+// A slot's value, with its metadata if it has any, as it's stored.
+fn encode_slot(value: &Value, meta: Option<&SlotMeta>) -> fdb::Value {
+    let mut tup: Tuple = (&FdbValue(value.clone())).into();
+    if let Some(meta) = meta {
+        let mut meta_tup = Tuple::new();
+        meta_tup.add_string(String::from(META));
+        meta_tup.add_uuid(meta.owner.map_or(uuid::Uuid::nil(), |owner| owner.id));
+        meta_tup.add_i8(meta.flags as i8);
+        meta_tup.add_i64(meta.created);
+        meta_tup.add_i64(meta.modified);
+        tup.add_tuple(meta_tup);
+    }
+    tup.pack().into()
+}
+
+/// FoundationDB refuses values larger than this.
+pub const MAX_STORED_VALUE: usize = 100_000;
+
+fn encoded_len(value: &Value) -> usize {
+    Bytes::from(encode_slot(value, Some(&SlotMeta::new(0)))).len()
+}
+
+/// Whether `value` can be kept in a slot. Binary values of any size can, as they're stored as
+/// blobs when they must be; anything else has to encode, with its slot's meta, within
+/// MAX_STORED_VALUE.
+pub fn fits_in_slot(value: &Value) -> bool {
+    match value {
+        Value::Binary(_) => true,
+        _ => encoded_len(value) <= MAX_STORED_VALUE,
+    }
+}
+
+// Streams slots for dumping, with any blobs they hold read back in whole as Binary values. The
+// stream ends after the first error, reading a slot or its blob.
+fn with_blobs(
+    tr: &Tx,
+    slots: impl tokio_stream::Stream<Item = Result<(SlotDef, Value), Error>> + Send + 'static,
+) -> Box<dyn tokio_stream::Stream<Item = Result<(SlotDef, Value), Error>> + Send + Unpin> {
+    let tr = tr.clone();
+    let slots = futures::StreamExt::then(slots, move |slot| {
+        let tr = tr.clone();
+        async move {
+            match slot? {
+                (slotdef, Value::Blob(_)) => {
+                    match BlobTxHandle::new(&tr).read_all(&slotdef).await {
+                        Ok(data) => Ok((slotdef, Value::Binary(data.into()))),
+                        Err(_) => Err(Error::InternalError),
+                    }
+                }
+                slot => Ok(slot),
+            }
+        }
+    });
+    let mut failed = false;
+    let slots = futures::StreamExt::take_while(slots, move |slot| {
+        let more = !failed;
+        failed = slot.is_err();
+        futures::future::ready(more)
+    });
+    Box::new(Box::pin(slots))
+}
+
+// A slot as it's read from the database.
+fn read_slot(kv: Result<(Key, fdb::Value), DbError>) -> Result<(SlotDef, Value), Error> {
+    match kv {
+        Ok((key, val)) => Ok((SlotDef::from(key), FdbValue::from(val).0)),
+        Err(_) => Err(Error::InternalError),
+    }
+}
+
+// The reverse index of references: a key ("REF", target, location, key, name) for each slot
+// whose value is, or holds in a Vector, target's IdKey. Entries are only added as slots are set,
+// so they can go stale; they're checked against the slot as they're found, and cleared if so.
+fn refs_subspace(target: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(target.id);
+    Subspace::new(Bytes::from_static("REF".as_bytes())).subspace(&tup)
+}
+
+fn ref_key(target: Oid, slot: &SlotDef) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_uuid(slot.location.id);
+    tup.add_uuid(slot.key.id);
+    tup.add_string(slot.name.clone());
+    refs_subspace(target).subspace(&tup).pack().into()
+}
+
+fn ref_slot(target: Oid, key: Key) -> SlotDef {
+    let bytes: Bytes = key.into();
+    let tuple = refs_subspace(target).unpack(&bytes).unwrap();
+    SlotDef {
+        location: Oid {
+            id: *tuple.get_uuid_ref(0).unwrap(),
+        },
+        key: Oid {
+            id: *tuple.get_uuid_ref(1).unwrap(),
+        },
+        name: tuple.get_string_ref(2).unwrap().clone(),
+    }
+}
+
+/// The objects `value` refers to: itself if it's an IdKey, or those in it if it's a Vector.
+pub fn referenced(value: &Value) -> Vec<Oid> {
+    match value {
+        Value::IdKey(oid) => vec![*oid],
+        Value::Vector(values) => values.iter().flat_map(referenced).collect(),
+        _ => vec![],
+    }
+}
+
+// The digest the change journal keeps of a value: of its encoding, without any metadata.
+fn value_hash(value: &Value) -> Vec<u8> {
+    let encoded: Bytes = encode_slot(value, None).into();
+    Sha256::digest(&encoded[..]).to_vec()
+}
+
 // Performs operations on objects via one transaction.
 pub struct ObjDBTxHandle<'tx_lifetime> {
-    tr: &'tx_lifetime FdbTransaction,
+    tr: &'tx_lifetime Tx,
+    // Whether slots are read, and listed, with snapshot reads.
+    snapshot: bool,
 }
 
 impl<'tx_lifetime> ObjDBTxHandle<'tx_lifetime> {
-    pub fn new(tx: &'tx_lifetime FdbTransaction) -> Self {
-        ObjDBTxHandle { tr: tx }
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        ObjDBTxHandle {
+            tr: tx,
+            snapshot: false,
+        }
+    }
+
+    /// A handle whose reads of slots, and listings of them, are snapshot reads, which don't
+    /// conflict with transactions writing them. For enumeration and introspection, where nothing
+    /// written depends on what's read; writes through it (and moves, which read what they move)
+    /// are as usual.
+    pub fn snapshot(tx: &'tx_lifetime Tx) -> Self {
+        ObjDBTxHandle {
+            tr: tx,
+            snapshot: true,
+        }
+    }
+
+    async fn read(&self, key: impl Into<Key>) -> Result<Option<fdb::Value>, DbError> {
+        match self.snapshot {
+            true => self.tr.snapshot_get(key).await,
+            false => self.tr.get(key).await,
+        }
+    }
+
+    fn read_range(&self, range: Range) -> BoxStream<'static, Result<(Key, fdb::Value), DbError>> {
+        match self.snapshot {
+            true => self.tr.snapshot_get_range(range),
+            false => self.tr.get_range(range),
+        }
+    }
+
+    /// Whether `oid` has any slots, which is all there is to an object existing.
+    pub async fn exists(&self, oid: Oid) -> Result<bool, DbError> {
+        let slotdef_subspace = Subspace::new(Bytes::from_static("SLOT".as_bytes()));
+        let mut tup = Tuple::new();
+        tup.add_uuid(oid.id);
+        let mut stream = self.read_range(slotdef_subspace.range(&tup)).take(1);
+        Ok(stream.next().await.transpose()?.is_some())
+    }
+
+    /// Up to `limit` slots in key order, from just after `after` or else the first; for paging
+    /// through every slot in the world a transaction at a time, where reading them all in one
+    /// would take longer than a transaction may. Blobs are left as their handles.
+    pub async fn slots_after(
+        &self,
+        after: Option<SlotDef>,
+        limit: usize,
+    ) -> Result<Vec<(SlotDef, Value)>, Error> {
+        let slot_range = Subspace::new(Bytes::from_static("SLOT".as_bytes())).range(&Tuple::new());
+        let (begin, end) = slot_range.into_parts();
+        let begin = match after {
+            Some(slot) => key_after(slot.into()),
+            None => begin,
+        };
+        let mut stream = self.read_range(Range::new(begin, end)).take(limit);
+        let mut slots = vec![];
+        while let Some(kv) = stream.next().await {
+            slots.push(read_slot(kv)?);
+        }
+        Ok(slots)
+    }
+
+    /// Set a slot, keeping `meta` with its value (or nothing, replacing whatever was kept).
+    pub fn set_slot_and_meta(&self, slotdef: SlotDef, value: &Value, meta: Option<&SlotMeta>) {
+        self.index_references(&slotdef, value);
+        self.record_change(ChangeKind::Set, &slotdef, Some(value));
+        let blobs = BlobTxHandle::new(self.tr);
+        match value {
+            // Measured encoded, as escaping zero bytes can make that up to twice as long.
+            Value::Binary(data) if encoded_len(value) > BLOB_THRESHOLD => {
+                blobs.put(&slotdef, data);
+                self.tr
+                    .set(slotdef, encode_slot(&Value::Blob(data.len() as u64), meta));
+            }
+            _ => {
+                blobs.clear(&slotdef);
+                self.tr.set(slotdef, encode_slot(value, meta));
+            }
+        }
+    }
+
+    // Journal a change to `slot`, and the value it was set to if it was, if changes are being
+    // journaled.
+    fn record_change(&self, kind: ChangeKind, slot: &SlotDef, value: Option<&Value>) {
+        if self.tr.journals_changes() {
+            let hash = value.map(value_hash);
+            ChangesTxHandle::new(self.tr).record(kind, slot, hash.as_deref());
+        }
+    }
+
+    /// Add `slot`, holding `value`, to the index of references to the objects it refers to, if
+    /// the index is being kept.
+    pub fn index_references(&self, slot: &SlotDef, value: &Value) {
+        if !self.tr.indexes_references() {
+            return;
+        }
+        for target in referenced(value) {
+            self.tr.set(ref_key(target, slot), Bytes::new());
+        }
     }
 }
 
 impl<'tx_lifetime> ObjDBHandle for ObjDBTxHandle<'tx_lifetime> {
-    fn set_slot(&self, location: Oid, definer: Oid, name: String, value: &Value) {
-        let slotdef = SlotDef {
-            location,
-            key: definer,
-            name,
-        };
-        self.tr.set(slotdef, &FdbValue(value.clone()));
+    fn set_slot(
+        &self,
+        location: Oid,
+        definer: Oid,
+        name: String,
+        value: &Value,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let value = value.clone();
+        async move {
+            let slotdef = SlotDef {
+                location,
+                key: definer,
+                name,
+            };
+            let meta = match self.tr.get(slotdef.clone()).await {
+                Ok(Some(r)) => slot_meta(&Tuple::from_bytes(r).map_err(|_| Error::InternalError)?),
+                Ok(None) => None,
+                Err(_) => return Err(Error::InternalError),
+            };
+            SearchTxHandle::new(self.tr).update(&slotdef, &value).await;
+            self.set_slot_and_meta(slotdef, &value, meta.as_ref());
+            Ok(())
+        }
+        .boxed()
     }
 
     fn get_slot(
@@ -253,7 +563,7 @@ impl<'tx_lifetime> ObjDBHandle for ObjDBTxHandle<'tx_lifetime> {
                 key: definer,
                 name,
             };
-            let result_future = self.tr.get(slotdef).await;
+            let result_future = self.read(slotdef).await;
 
             match result_future {
                 Ok(result) => match result {
@@ -269,6 +579,95 @@ impl<'tx_lifetime> ObjDBHandle for ObjDBTxHandle<'tx_lifetime> {
         .boxed()
     }
 
+    fn get_slot_meta(
+        &self,
+        location: Oid,
+        definer: Oid,
+        name: String,
+    ) -> BoxFuture<Result<Option<SlotMeta>, Error>> {
+        async move {
+            let slotdef = SlotDef {
+                location,
+                key: definer,
+                name,
+            };
+            match self.read(slotdef).await {
+                Ok(Some(r)) => Ok(slot_meta(&Tuple::from_bytes(r).unwrap())),
+                Ok(None) => Err(Error::SlotDoesNotExist),
+                Err(_) => Err(Error::InternalError),
+            }
+        }
+        .boxed()
+    }
+
+    fn set_slot_meta(
+        &self,
+        location: Oid,
+        definer: Oid,
+        name: String,
+        meta: &SlotMeta,
+    ) -> BoxFuture<'_, Result<(), Error>> {
+        let meta = *meta;
+        async move {
+            let slotdef = SlotDef {
+                location,
+                key: definer,
+                name,
+            };
+            // The value is kept as it's stored, blob or not, so its blob is left alone.
+            let value = match self.tr.get(slotdef.clone()).await {
+                Ok(Some(r)) => FdbValue::from(r).0,
+                Ok(None) => return Err(Error::SlotDoesNotExist),
+                Err(_) => return Err(Error::InternalError),
+            };
+            if self.tr.journals_changes() {
+                // Journaled with the digest of the value itself, not of its blob's length.
+                let current = match value {
+                    Value::Blob(_) => Value::Binary(
+                        BlobTxHandle::new(self.tr)
+                            .read_all(&slotdef)
+                            .await
+                            .map_err(|_| Error::InternalError)?
+                            .into(),
+                    ),
+                    _ => value.clone(),
+                };
+                self.record_change(ChangeKind::Set, &slotdef, Some(&current));
+            }
+            self.tr.set(slotdef, encode_slot(&value, Some(&meta)));
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn find_references(
+        &self,
+        oid: Oid,
+    ) -> Result<Box<dyn tokio_stream::Stream<Item = SlotDef> + Send + Unpin>, Error> {
+        if !self.tr.indexes_references() {
+            return Err(Error::SlotDoesNotExist);
+        }
+        let tr = self.tr.clone();
+        let refs = self.tr.get_range(refs_subspace(oid).range(&Tuple::new()));
+        let slotdefs = futures::StreamExt::filter_map(refs, move |kv| {
+            let tr = tr.clone();
+            async move {
+                let (key, _) = kv.ok()?;
+                let slot = ref_slot(oid, key);
+                match tr.get(slot.clone()).await.ok()? {
+                    Some(value) if referenced(&FdbValue::from(value).0).contains(&oid) => {
+                        Some(slot)
+                    }
+                    _ => {
+                        tr.clear(ref_key(oid, &slot));
+                        None
+                    }
+                }
+            }
+        });
+        Ok(Box::new(Box::pin(slotdefs)))
+    }
+
     fn get_slots(
         &self,
         location: Oid,
@@ -279,33 +678,135 @@ impl<'tx_lifetime> ObjDBHandle for ObjDBTxHandle<'tx_lifetime> {
         tup.add_uuid(location.id);
         tup.add_uuid(key.id);
         let slot_range = slotdef_subspace.range(&tup);
-        let range_stream = slot_range.into_stream(self.tr, RangeOptions::default());
+        let range_stream = self.read_range(slot_range);
         let slotdefs = range_stream.map(|kv| -> SlotDef {
-            let key = kv.unwrap().get_key_ref().clone();
+            let (key, _) = kv.unwrap();
 
             SlotDef::from(key)
         });
         Ok(Box::new(slotdefs))
     }
+
+    fn move_slot(&self, from: SlotDef, to: SlotDef) -> BoxFuture<'_, Result<(), Error>> {
+        async move {
+            if from == to {
+                return Ok(());
+            }
+            let value = match self.tr.get(from.clone()).await {
+                Ok(Some(value)) => value,
+                Ok(None) => return Err(Error::SlotDoesNotExist),
+                Err(_) => return Err(Error::InternalError),
+            };
+            match self.tr.get(to.clone()).await {
+                Ok(None) => {}
+                Ok(Some(_)) => return Err(Error::NameTaken),
+                Err(_) => return Err(Error::InternalError),
+            }
+            let blobs = BlobTxHandle::new(self.tr);
+            let decoded = FdbValue::from(value.clone()).0;
+            self.index_references(&to, &decoded);
+            let moved = match decoded {
+                Value::Blob(_) => {
+                    let data = blobs
+                        .read_all(&from)
+                        .await
+                        .map_err(|_| Error::InternalError)?;
+                    blobs.put(&to, &data);
+                    Value::Binary(data.into())
+                }
+                decoded => decoded,
+            };
+            self.record_change(ChangeKind::Cleared, &from, None);
+            self.record_change(ChangeKind::Set, &to, Some(&moved));
+            blobs.clear(&from);
+            // The value is moved still encoded, as there's no need to decode it.
+            self.tr.clear(from);
+            self.tr.set(to, value);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn destroy_object(&self, location: Oid) {
+        let slotdef_subspace = Subspace::new(Bytes::from_static("SLOT".as_bytes()));
+        let mut tup = Tuple::new();
+        tup.add_uuid(location.id);
+        self.tr.clear_range(slotdef_subspace.range(&tup));
+        self.tr.clear(FdbOid(location));
+        BlobTxHandle::new(self.tr).clear_object(location);
+        if self.tr.journals_changes() {
+            ChangesTxHandle::new(self.tr).record_destroyed(location);
+        }
+    }
 }
 
 impl<'tx_lifetime> AdminHandle for ObjDBTxHandle<'tx_lifetime> {
     fn dump_slots(
         &self,
         location: Oid,
-    ) -> Result<Box<dyn tokio_stream::Stream<Item = (SlotDef, Value)> + Send + Unpin>, Error> {
+    ) -> Result<
+        Box<dyn tokio_stream::Stream<Item = Result<(SlotDef, Value), Error>> + Send + Unpin>,
+        Error,
+    > {
         let slotdef_subspace = Subspace::new(Bytes::from_static("SLOT".as_bytes()));
         let mut tup = Tuple::new();
         tup.add_uuid(location.id);
         let slot_range = slotdef_subspace.range(&tup);
-        let range_stream = slot_range.into_stream(self.tr, RangeOptions::default());
-        let slotdefs = range_stream.map(|kv| -> (SlotDef, Value) {
-            let kv = kv.unwrap();
-            let key = kv.get_key_ref().clone();
-            let val = kv.get_value_ref().clone();
+        let slotdefs = self.read_range(slot_range).map(read_slot);
+        Ok(with_blobs(self.tr, slotdefs))
+    }
+
+    fn slots_involving(
+        &self,
+        oid: Oid,
+    ) -> Result<
+        Box<dyn tokio_stream::Stream<Item = Result<(SlotDef, Value), Error>> + Send + Unpin>,
+        Error,
+    > {
+        let slotdef_subspace = Subspace::new(Bytes::from_static("SLOT".as_bytes()));
+        let slot_range = slotdef_subspace.range(&Tuple::new());
+        let slotdefs = self
+            .read_range(slot_range)
+            .map(read_slot)
+            .filter(move |slot| match slot {
+                Ok((slotdef, _)) => slotdef.location == oid || slotdef.key == oid,
+                Err(_) => true,
+            });
+        Ok(with_blobs(self.tr, slotdefs))
+    }
 
-            (SlotDef::from(key), FdbValue::from(val).0)
+    fn objects(&self) -> Result<Box<dyn tokio_stream::Stream<Item = Oid> + Send + Unpin>, Error> {
+        let slotdef_subspace = Subspace::new(Bytes::from_static("SLOT".as_bytes()));
+        let slot_range = slotdef_subspace.range(&Tuple::new());
+        let range_stream = self.read_range(slot_range);
+        // Slots are ordered by location, so each object's are together.
+        let mut last = None;
+        let oids = range_stream.filter_map(move |kv| {
+            let (key, _) = kv.unwrap();
+            let location = SlotDef::from(key).location;
+            match last.replace(location) {
+                Some(previous) if previous == location => None,
+                _ => Some(location),
+            }
         });
-        Ok(Box::new(slotdefs))
+        Ok(Box::new(oids))
+    }
+
+    fn dump_all_slots(
+        &self,
+    ) -> Result<
+        Box<dyn tokio_stream::Stream<Item = Result<(SlotDef, Value), Error>> + Send + Unpin>,
+        Error,
+    > {
+        let slotdef_subspace = Subspace::new(Bytes::from_static("SLOT".as_bytes()));
+        let slot_range = slotdef_subspace.range(&Tuple::new());
+        let slotdefs = self.read_range(slot_range).map(read_slot);
+        Ok(with_blobs(self.tr, slotdefs))
+    }
+
+    fn clear_slot(&self, slot: SlotDef) {
+        self.record_change(ChangeKind::Cleared, &slot, None);
+        BlobTxHandle::new(self.tr).clear(&slot);
+        self.tr.clear(slot);
     }
 }
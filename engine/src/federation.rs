@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::connect_async;
+use tracing::{info, warn};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::HeaderValue;
+use tungstenite::Message;
+
+use crate::protocol::{Request, Response, FEDERATION_SUBPROTOCOL};
+use value::{Error, Oid, Value};
+
+/// The slot on the system object, under SERVER_KEY, listing the objects which live in other
+/// worlds: a Vector of [IdKey object, String peer] pairs, for those whose Oids don't carry a peer's
+/// prefix.
+pub const PEERS_SLOT: &str = "peers";
+
+/// How long a verb invoked on another world has to answer before it's taken to be unavailable.
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How long to wait before connecting to a peer again, at first and at most.
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+/// How many requests may wait to go out to a peer; those made while as many are waiting get
+/// PeerUnavailable.
+pub const PEER_QUEUE: usize = 1024;
+
+/// Another world this one links to.
+#[derive(Clone, Debug)]
+pub struct PeerOptions {
+    /// What the registry slot calls it.
+    pub name: String,
+    /// Where its server listens, e.g. wss://example.org:8080.
+    pub url: String,
+    /// If set, objects whose uuids, as hex without hyphens, begin with this are its.
+    pub prefix: Option<String>,
+}
+
+/// The worlds this one links to, and the token they and it present to one another.
+#[derive(Clone, Debug, Default)]
+pub struct FederationOptions {
+    pub peers: Vec<PeerOptions>,
+    /// Presented to peers as a bearer token, and required of those connecting here with the
+    /// federation subprotocol. They're refused if None.
+    pub token: Option<String>,
+}
+
+/// A connection to another world, over which verbs are invoked on its objects.
+///
+/// Each request goes out over the structured protocol, with the federation subprotocol, and is
+/// matched to its response by request id. The connection is made again, backing off, whenever
+/// it drops; requests made meanwhile, or outstanding as it drops, get PeerUnavailable.
+pub struct Peer {
+    options: PeerOptions,
+    next_id: AtomicI64,
+    pending: Mutex<HashMap<i64, oneshot::Sender<Value>>>,
+    outbound: Mutex<Option<mpsc::Sender<Message>>>,
+}
+
+impl Peer {
+    fn new(options: PeerOptions) -> Self {
+        Peer {
+            options,
+            next_id: AtomicI64::new(1),
+            pending: Default::default(),
+            outbound: Default::default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.options.name
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.outbound.lock().unwrap().is_some()
+    }
+
+    /// Invoke `verb` on `target`, in the peer's world, with `args`. Its result is as the peer
+    /// gave it, errors included; PeerUnavailable if the peer couldn't be reached, had PEER_QUEUE
+    /// requests waiting for it already, or didn't answer within PEER_TIMEOUT.
+    pub async fn invoke(&self, target: Oid, verb: &str, args: &[Value]) -> Value {
+        let request = Request {
+            request_id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            target,
+            verb: verb.to_string(),
+            args: args.to_vec(),
+            dry_run: false,
+            deterministic: false,
+        };
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(request.request_id, tx);
+        let sent = match self.outbound.lock().unwrap().as_ref() {
+            Some(outbound) => outbound.try_send(Message::Binary(request.encode())).is_ok(),
+            None => false,
+        };
+        if sent {
+            if let Ok(Ok(result)) = tokio::time::timeout(PEER_TIMEOUT, rx).await {
+                return result;
+            }
+        }
+        self.pending.lock().unwrap().remove(&request.request_id);
+        warn!(
+            "Peer {} unavailable for {:?}:{}",
+            self.options.name, target, verb
+        );
+        Value::Error(Error::PeerUnavailable)
+    }
+
+    // Stay connected, presenting `token`, for as long as the server runs.
+    async fn run(self: Arc<Self>, token: Option<String>) {
+        let mut backoff = RECONNECT_MIN;
+        loop {
+            match self.connect(token.as_deref()).await {
+                Ok(()) => backoff = RECONNECT_MIN,
+                Err(e) => warn!("Connection to peer {} failed: {}", self.options.name, e),
+            }
+            // Nothing outstanding will be answered now.
+            *self.outbound.lock().unwrap() = None;
+            self.pending.lock().unwrap().clear();
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX);
+        }
+    }
+
+    // Connect, and send requests and match responses until the connection drops. An error if it
+    // couldn't be made.
+    async fn connect(&self, token: Option<&str>) -> tungstenite::Result<()> {
+        let mut request = self.options.url.as_str().into_client_request()?;
+        let headers = request.headers_mut();
+        headers.insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(FEDERATION_SUBPROTOCOL),
+        );
+        if let Some(token) = token {
+            let bearer = HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|e| tungstenite::Error::HttpFormat(e.into()))?;
+            headers.insert("Authorization", bearer);
+        }
+        let (ws_stream, _) = connect_async(request).await?;
+        info!(
+            "Connected to peer {} at {}",
+            self.options.name, self.options.url
+        );
+        let (mut outgoing, mut incoming) = ws_stream.split();
+        let (tx, mut rx) = mpsc::channel(PEER_QUEUE);
+        *self.outbound.lock().unwrap() = Some(tx);
+
+        let send = async {
+            while let Some(message) = rx.recv().await {
+                outgoing.send(message).await?;
+            }
+            Ok::<_, tungstenite::Error>(())
+        };
+        let receive = async {
+            while let Some(message) = incoming.next().await {
+                let message = message?;
+                if !message.is_binary() {
+                    continue;
+                }
+                // Anything else the peer sends, e.g. the token to resume with, is ignored.
+                if let Ok(response) = Response::decode(&message.into_data()) {
+                    self.answer(response);
+                }
+            }
+            Ok::<_, tungstenite::Error>(())
+        };
+        let closed = tokio::select! {
+            closed = send => closed,
+            closed = receive => closed,
+        };
+        match closed {
+            Ok(()) => info!("Peer {} closed the connection", self.options.name),
+            Err(e) => warn!("Connection to peer {} lost: {}", self.options.name, e),
+        }
+        Ok(())
+    }
+
+    // Give the request `response` answers its result, if it's still waiting for one.
+    fn answer(&self, response: Response) {
+        if let Some(tx) = self.pending.lock().unwrap().remove(&response.request_id) {
+            let _ = tx.send(response.result);
+        }
+    }
+}
+
+/// The worlds this one links to, and which objects are theirs.
+pub struct Federation {
+    peers: HashMap<String, Arc<Peer>>,
+    token: Option<String>,
+}
+
+impl Federation {
+    pub fn new(options: &FederationOptions) -> Self {
+        Federation {
+            peers: options
+                .peers
+                .iter()
+                .map(|peer| (peer.name.clone(), Arc::new(Peer::new(peer.clone()))))
+                .collect(),
+            token: options.token.clone(),
+        }
+    }
+
+    /// Whether there are no peers, so that no object can be another world's.
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Connect to every peer, and keep connected, in the background.
+    pub fn connect_peers(&self) {
+        for peer in self.peers.values() {
+            tokio::spawn(peer.clone().run(self.token.clone()));
+        }
+    }
+
+    /// The peer called `name`, if there is one.
+    pub fn peer(&self, name: &str) -> Option<Arc<Peer>> {
+        self.peers.get(name).cloned()
+    }
+
+    /// The peer whose prefix `oid` carries, if any does.
+    pub fn peer_by_prefix(&self, oid: Oid) -> Option<Arc<Peer>> {
+        let hex = oid.id.to_simple().to_string();
+        self.peers
+            .values()
+            .find(|peer| match &peer.options.prefix {
+                Some(prefix) => hex.starts_with(&prefix.to_lowercase()),
+                None => false,
+            })
+            .cloned()
+    }
+
+    /// The peer `registry`, the system object's PEERS_SLOT, lists `oid` under, if it does.
+    pub fn peer_listed(&self, registry: &Value, oid: Oid) -> Option<Arc<Peer>> {
+        let entries = match registry {
+            Value::Vector(entries) => entries,
+            _ => return None,
+        };
+        entries.iter().find_map(|entry| match entry {
+            Value::Vector(pair) => match pair.as_slice() {
+                [Value::IdKey(listed), Value::String(name)] if *listed == oid => self.peer(name),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// Whether a peer connecting with the federation subprotocol, with `authorization` as its
+    /// Authorization header, is admitted. Digests are compared, rather than the tokens
+    /// themselves, so that the time taken says nothing about how much of the token was right.
+    pub fn admits(&self, authorization: Option<&str>) -> bool {
+        let token = match &self.token {
+            Some(token) => token,
+            None => return false,
+        };
+        match authorization.and_then(|header| header.strip_prefix("Bearer ")) {
+            Some(presented) => Sha256::digest(presented.trim()) == Sha256::digest(token),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn federation(token: Option<&str>) -> Federation {
+        Federation::new(&FederationOptions {
+            peers: vec![
+                PeerOptions {
+                    name: "east".to_string(),
+                    url: "ws://east.invalid".to_string(),
+                    prefix: Some("3F2A".to_string()),
+                },
+                PeerOptions {
+                    name: "west".to_string(),
+                    url: "ws://west.invalid".to_string(),
+                    prefix: None,
+                },
+            ],
+            token: token.map(str::to_string),
+        })
+    }
+
+    fn oid(hex: &str) -> Oid {
+        Oid {
+            id: Uuid::parse_str(hex).unwrap(),
+        }
+    }
+
+    fn listing(object: Oid, peer: &str) -> Value {
+        Value::Vector(vec![Value::IdKey(object), Value::String(peer.into())])
+    }
+
+    fn unavailable(result: Value) -> bool {
+        matches!(result, Value::Error(Error::PeerUnavailable))
+    }
+
+    #[test]
+    fn prefixes_pick_out_peers_objects() {
+        let federation = federation(None);
+        let east = oid("3f2a0000-0000-0000-0000-000000000001");
+        assert_eq!(federation.peer_by_prefix(east).unwrap().name(), "east");
+        assert!(federation
+            .peer_by_prefix(oid("0f2a0000-0000-0000-0000-000000000001"))
+            .is_none());
+        assert!(!federation.is_empty());
+        assert!(Federation::new(&FederationOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn registries_list_objects_under_known_peers() {
+        let federation = federation(None);
+        let (listed, unknown, unlisted) = (
+            oid("00000000-0000-0000-0000-000000000001"),
+            oid("00000000-0000-0000-0000-000000000002"),
+            oid("00000000-0000-0000-0000-000000000003"),
+        );
+        let registry = Value::Vector(vec![
+            Value::I32(7),
+            listing(unknown, "north"),
+            listing(listed, "west"),
+        ]);
+        assert_eq!(
+            federation.peer_listed(&registry, listed).unwrap().name(),
+            "west"
+        );
+        assert!(federation.peer_listed(&registry, unknown).is_none());
+        assert!(federation.peer_listed(&registry, unlisted).is_none());
+        assert!(federation.peer_listed(&Value::I32(0), listed).is_none());
+    }
+
+    #[test]
+    fn only_the_token_is_admitted() {
+        let federation = federation(Some("secret"));
+        assert!(federation.admits(Some("Bearer secret")));
+        assert!(federation.admits(Some("Bearer secret ")));
+        assert!(!federation.admits(Some("Bearer secrets")));
+        assert!(!federation.admits(Some("secret")));
+        assert!(!federation.admits(None));
+        // No-one is, without a token of this world's own.
+        assert!(!self::federation(None).admits(Some("Bearer ")));
+    }
+
+    #[tokio::test]
+    async fn unconnected_peers_are_unavailable() {
+        let peer = federation(None).peer("east").unwrap();
+        assert!(!peer.is_connected());
+        assert!(unavailable(
+            peer.invoke(Oid { id: Uuid::nil() }, "look", &[]).await
+        ));
+        assert!(peer.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn full_queues_are_unavailable() {
+        let peer = federation(None).peer("east").unwrap();
+        let (tx, _rx) = mpsc::channel(1);
+        tx.try_send(Message::Binary(vec![])).unwrap();
+        *peer.outbound.lock().unwrap() = Some(tx);
+        assert!(unavailable(
+            peer.invoke(Oid { id: Uuid::nil() }, "look", &[]).await
+        ));
+        assert!(peer.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn responses_answer_their_requests() {
+        let peer = federation(None).peer("east").unwrap();
+        let (tx, mut rx) = mpsc::channel(PEER_QUEUE);
+        *peer.outbound.lock().unwrap() = Some(tx);
+        let target = oid("3f2a0000-0000-0000-0000-000000000001");
+        let invoking = tokio::spawn({
+            let peer = peer.clone();
+            async move { peer.invoke(target, "look", &[Value::I32(1)]).await }
+        });
+        let request = match rx.recv().await.unwrap() {
+            Message::Binary(frame) => Request::decode(frame.into()).unwrap(),
+            message => panic!("Sent {:?}", message),
+        };
+        assert_eq!(request.target, target);
+        assert_eq!(request.verb, "look");
+        assert!(matches!(request.args[..], [Value::I32(1)]));
+        // Responses to requests no longer waiting are dropped.
+        peer.answer(Response {
+            request_id: request.request_id + 1,
+            result: Value::I32(0),
+            message: None,
+        });
+        peer.answer(Response {
+            request_id: request.request_id,
+            result: Value::I32(42),
+            message: None,
+        });
+        assert!(matches!(invoking.await.unwrap(), Value::I32(42)));
+        assert!(peer.pending.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+use crate::patterns::PatternCache;
+use value::{Oid, Value};
+
+/// How many forms a connection may have been sent and not yet answered.
+pub const MAX_PENDING_FORMS: usize = 16;
+
+/// The kinds of answer a form field takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    /// A String, whose length `min` and `max` bound, and which `pattern` must match.
+    Text,
+    /// An I32 or I64 (delivered as an I64), which `min` and `max` bound.
+    Integer,
+    /// Any number (delivered as an F64), which `min` and `max` bound.
+    Number,
+    /// One of the field's `choices`, as a String.
+    Choice,
+    /// An IdKey.
+    Object,
+}
+
+impl FieldType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(FieldType::Text),
+            "integer" => Some(FieldType::Integer),
+            "number" => Some(FieldType::Number),
+            "choice" => Some(FieldType::Choice),
+            "object" => Some(FieldType::Object),
+            _ => None,
+        }
+    }
+}
+
+/// A field of a form, as a Vector of [String name, String type, Vector hints], where the hints
+/// are [String hint, value] pairs: "required" (any non-zero I32), "min" and "max" (numbers),
+/// "choices" (a Vector of Strings), "pattern" (a regular expression), and any others the client
+/// may make use of, such as "label", which are passed on to it but not checked.
+#[derive(Clone, Debug)]
+pub struct FieldDef {
+    pub name: String,
+    pub kind: FieldType,
+    pub required: bool,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub choices: Vec<String>,
+    pub pattern: Option<String>,
+}
+
+/// What a verb asks a client to fill in: a Vector of fields.
+#[derive(Clone, Debug)]
+pub struct FormDefinition {
+    pub fields: Vec<FieldDef>,
+}
+
+/// A form sent to a connection, awaiting its answers, and the verb they're to be delivered to.
+#[derive(Clone, Debug)]
+pub struct PendingForm {
+    pub definition: FormDefinition,
+    pub target: Oid,
+    pub verb: String,
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::I32(n) => Some(*n as f64),
+        Value::I64(n) => Some(*n as f64),
+        Value::F32(n) => Some(*n as f64),
+        Value::F64(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn problem(field: &str, reason: &str) -> Value {
+    Value::Vector(vec![
+        Value::String(field.into()),
+        Value::String(reason.into()),
+    ])
+}
+
+impl FieldDef {
+    fn parse(field: &Value) -> Result<Self, String> {
+        let (name, kind, hints) = match field {
+            Value::Vector(field) => match &field[..] {
+                [Value::String(name), Value::String(kind)] => (name, kind, &[][..]),
+                [Value::String(name), Value::String(kind), Value::Vector(hints)] => {
+                    (name, kind, &hints[..])
+                }
+                _ => return Err("a field isn't [name, type, hints]".to_string()),
+            },
+            _ => return Err("a field isn't a Vector".to_string()),
+        };
+        let mut def = FieldDef {
+            name: name.to_string(),
+            kind: FieldType::parse(kind)
+                .ok_or_else(|| format!("'{}' has an unknown type '{}'", name, kind))?,
+            required: false,
+            min: None,
+            max: None,
+            choices: vec![],
+            pattern: None,
+        };
+        for hint in hints {
+            let (hint, value) = match hint {
+                Value::Vector(pair) => match &pair[..] {
+                    [Value::String(hint), value] => (hint.as_str(), value),
+                    _ => return Err(format!("'{}' has a hint which isn't [name, value]", name)),
+                },
+                _ => return Err(format!("'{}' has a hint which isn't a Vector", name)),
+            };
+            let invalid = || format!("'{}' has an invalid '{}'", name, hint);
+            match hint {
+                "required" => def.required = !matches!(value, Value::I32(0)),
+                "min" => def.min = Some(number(value).ok_or_else(invalid)?),
+                "max" => def.max = Some(number(value).ok_or_else(invalid)?),
+                "choices" => match value {
+                    Value::Vector(choices) => {
+                        for choice in choices {
+                            match choice {
+                                Value::String(choice) => def.choices.push(choice.to_string()),
+                                _ => return Err(invalid()),
+                            }
+                        }
+                    }
+                    _ => return Err(invalid()),
+                },
+                "pattern" => match value {
+                    Value::String(pattern) => def.pattern = Some(pattern.to_string()),
+                    _ => return Err(invalid()),
+                },
+                // For the client.
+                _ => {}
+            }
+        }
+        if def.kind == FieldType::Choice && def.choices.is_empty() {
+            return Err(format!("'{}' is a choice with no choices", name));
+        }
+        Ok(def)
+    }
+
+    // `answer` as the verb is given it, or why it won't do.
+    fn check(&self, answer: &Value, patterns: &PatternCache) -> Result<Value, String> {
+        let in_bounds =
+            |n: f64| self.min.map_or(true, |min| n >= min) && self.max.map_or(true, |max| n <= max);
+        match (self.kind, answer) {
+            (FieldType::Text, Value::String(text)) => {
+                if !in_bounds(text.chars().count() as f64) {
+                    return Err("is too short or too long".to_string());
+                }
+                if let Some(pattern) = &self.pattern {
+                    let regex = patterns
+                        .get(pattern)
+                        .map_err(|_| "can't be checked".to_string())?;
+                    if !regex.is_match(text) {
+                        return Err("isn't in the expected form".to_string());
+                    }
+                }
+                Ok(answer.clone())
+            }
+            (FieldType::Integer, Value::I32(n)) if in_bounds(*n as f64) => {
+                Ok(Value::I64(*n as i64))
+            }
+            (FieldType::Integer, Value::I64(n)) if in_bounds(*n as f64) => Ok(Value::I64(*n)),
+            (FieldType::Integer, Value::I32(_) | Value::I64(_)) => {
+                Err("is out of range".to_string())
+            }
+            (FieldType::Number, answer) if number(answer).is_some() => {
+                let n = number(answer).unwrap_or_default();
+                match in_bounds(n) {
+                    true => Ok(Value::F64(n)),
+                    false => Err("is out of range".to_string()),
+                }
+            }
+            (FieldType::Choice, Value::String(choice))
+                if self.choices.iter().any(|c| c == choice.as_str()) =>
+            {
+                Ok(answer.clone())
+            }
+            (FieldType::Choice, Value::String(_)) => Err("isn't one of the choices".to_string()),
+            (FieldType::Object, Value::IdKey(_)) => Ok(answer.clone()),
+            _ => Err("is the wrong type".to_string()),
+        }
+    }
+}
+
+impl FormDefinition {
+    /// A form's definition, from the Vector of fields a verb gave; or why it isn't one.
+    pub fn parse(definition: &Value) -> Result<Self, String> {
+        let fields = match definition {
+            Value::Vector(fields) if !fields.is_empty() => fields,
+            _ => return Err("a form is a Vector of one or more fields".to_string()),
+        };
+        let fields = fields
+            .iter()
+            .map(FieldDef::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        for (i, field) in fields.iter().enumerate() {
+            if fields[..i].iter().any(|f| f.name == field.name) {
+                return Err(format!("'{}' is defined twice", field.name));
+            }
+        }
+        Ok(FormDefinition { fields })
+    }
+
+    /// The answers a client gave, as [String field, value] pairs, checked against the form: the
+    /// answers in the order of the form's fields, with those left out omitted; or, if they won't
+    /// do, a [String field, String reason] pair for each problem.
+    pub fn validate(&self, answers: &[Value], patterns: &PatternCache) -> Result<Value, Value> {
+        let mut given = vec![];
+        let mut problems = vec![];
+        for answer in answers {
+            match answer {
+                Value::Vector(pair) => match &pair[..] {
+                    [Value::String(name), value] => {
+                        match self.fields.iter().any(|f| f.name == name.as_str()) {
+                            true => given.push((name.as_str(), value)),
+                            false => problems.push(problem(name, "isn't a field of the form")),
+                        }
+                    }
+                    _ => problems.push(problem("", "an answer isn't [field, value]")),
+                },
+                _ => problems.push(problem("", "an answer isn't a Vector")),
+            }
+        }
+        let mut checked = vec![];
+        for field in &self.fields {
+            let answer = given.iter().find(|(name, _)| *name == field.name);
+            match answer {
+                Some((_, value)) => match field.check(value, patterns) {
+                    Ok(value) => checked.push(Value::Vector(vec![
+                        Value::String(field.name.as_str().into()),
+                        value,
+                    ])),
+                    Err(reason) => problems.push(problem(&field.name, &reason)),
+                },
+                None if field.required => problems.push(problem(&field.name, "is required")),
+                None => {}
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(Value::Vector(checked)),
+            false => Err(Value::Vector(problems)),
+        }
+    }
+}
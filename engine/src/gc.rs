@@ -0,0 +1,90 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use value::Oid;
+
+/// What garbage collection starts from, and whether, and how, it runs by itself.
+#[derive(Clone, Debug, Default)]
+pub struct GcOptions {
+    /// Objects kept, with everything they refer to, besides the system object, whatever is
+    /// connected, and what's named, tagged, on the calendar, subscribed or counted.
+    pub roots: Vec<Oid>,
+    /// How often a sweep runs by itself. Only when an admin asks if None.
+    pub interval: Option<Duration>,
+    /// Whether the sweeps which run by themselves clear the objects they find, rather than just
+    /// reporting them.
+    pub clear: bool,
+}
+
+/// Where a sweep has got to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GcPhase {
+    /// Following references from the roots.
+    #[default]
+    Marking,
+    /// Clearing the objects which weren't reached.
+    Sweeping,
+    Done,
+    Failed,
+}
+
+/// How a sweep is going, or went.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GcProgress {
+    /// When it started, in seconds since the Unix epoch.
+    pub started: u64,
+    /// Whether it clears what it finds, or only reports it.
+    pub clear: bool,
+    pub phase: GcPhase,
+    /// The objects in the world as it started; only these are candidates.
+    pub objects: usize,
+    /// The objects reached from the roots so far.
+    pub marked: usize,
+    /// The objects which weren't, once marking's done.
+    pub unreachable: Vec<Oid>,
+    /// Of those, how many have been cleared.
+    pub cleared: usize,
+    /// Those which were referred to again by the time they were to be cleared, and were kept.
+    pub kept: usize,
+    pub error: Option<String>,
+}
+
+/// The sweep going on, or the last one, so that admins can follow it.
+#[derive(Default)]
+pub struct GcRegistry {
+    progress: Mutex<Option<GcProgress>>,
+}
+
+impl GcRegistry {
+    /// Begin a sweep. False, and nothing's changed, if one is going on already.
+    pub fn start(&self, clear: bool) -> bool {
+        let mut progress = self.progress.lock().unwrap();
+        if let Some(GcPhase::Marking | GcPhase::Sweeping) = progress.as_ref().map(|p| p.phase) {
+            return false;
+        }
+        *progress = Some(GcProgress {
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            clear,
+            ..GcProgress::default()
+        });
+        true
+    }
+
+    /// Record how the sweep going on is getting on.
+    pub fn update(&self, f: impl FnOnce(&mut GcProgress)) {
+        if let Some(progress) = self.progress.lock().unwrap().as_mut() {
+            f(progress);
+        }
+    }
+
+    /// The sweep going on, or the last one; None if there's been none.
+    pub fn progress(&self) -> Option<GcProgress> {
+        self.progress.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::dump::Dump;
+use value::{Oid, Value};
+
+/// Where, in a graph export's directory, the objects are written.
+pub const NODES_FILE: &str = "nodes.csv";
+
+/// Where the references between them are written.
+pub const RELATIONSHIPS_FILE: &str = "relationships.csv";
+
+/// Where the objects which have gone since the last export are written.
+pub const REMOVED_FILE: &str = "removed.csv";
+
+/// Where what was exported is recorded, so the next export can be incremental.
+pub const STATE_FILE: &str = "graph-state.json";
+
+/// The kind of relationship a reference from a slot is, by the slot's name. Containment,
+/// ownership, exits and prototypes are told apart; anything else is a plain reference.
+pub fn relationship_type(slot_name: &str) -> &'static str {
+    match slot_name {
+        "location" => "LOCATED_IN",
+        "contents" => "CONTAINS",
+        "owner" => "OWNED_BY",
+        "exits" => "EXIT",
+        "prototype" => "INSTANCE_OF",
+        _ => "REFERENCES",
+    }
+}
+
+/// An object, as a node of the graph.
+#[derive(Debug)]
+pub struct Node {
+    pub oid: Oid,
+    pub name: Option<String>,
+    pub slots: usize,
+}
+
+/// A reference from a slot on one object to another, as a relationship of the graph.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Relationship {
+    pub from: Oid,
+    pub to: Oid,
+    pub slot: String,
+}
+
+/// The references out of an object's slots, including those inside Vectors. References an object
+/// makes to itself are left out.
+pub fn relationships(dumps: &[Dump]) -> Vec<Relationship> {
+    let mut relationships = vec![];
+    for dump in dumps {
+        let mut targets = vec![];
+        references(&dump.value, &mut targets);
+        for to in targets {
+            if to != dump.slot_def.location {
+                relationships.push(Relationship {
+                    from: dump.slot_def.location,
+                    to,
+                    slot: dump.slot_def.name.clone(),
+                });
+            }
+        }
+    }
+    relationships
+}
+
+fn references(value: &Value, targets: &mut Vec<Oid>) {
+    match value {
+        Value::IdKey(oid) => targets.push(*oid),
+        Value::Vector(values) => values.iter().for_each(|v| references(v, targets)),
+        _ => {}
+    }
+}
+
+/// A digest of an object's slots, to tell whether it's changed since it was last exported.
+pub fn object_digest(dumps: &[Dump]) -> Result<String, Error> {
+    let digest = Sha256::digest(serde_json::to_vec(dumps)?);
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// What the last export to a directory wrote: the digest of each object it found.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct GraphState {
+    pub exported: u64,
+    pub objects: BTreeMap<Uuid, String>,
+}
+
+impl GraphState {
+    /// Read the state in `dir`, or an empty one if nothing's been exported there yet.
+    pub fn read(dir: &Path) -> Result<Self, Error> {
+        match std::fs::read(dir.join(STATE_FILE)) {
+            Ok(payload) => Ok(serde_json::from_slice(&payload)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<(), Error> {
+        std::fs::write(dir.join(STATE_FILE), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// How much an export wrote.
+#[derive(Serialize, Debug, Default)]
+pub struct GraphReport {
+    pub incremental: bool,
+    pub nodes: usize,
+    pub relationships: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+}
+
+/// The CSV files of an export, with headers as Neo4j's bulk importer expects.
+pub struct GraphWriter {
+    nodes: std::io::BufWriter<std::fs::File>,
+    relationships: std::io::BufWriter<std::fs::File>,
+    removed: std::io::BufWriter<std::fs::File>,
+}
+
+impl GraphWriter {
+    /// Start an export in `dir`, creating it if need be and replacing the files of any earlier
+    /// one (but not its state).
+    pub fn create(dir: &Path) -> Result<Self, Error> {
+        std::fs::create_dir_all(dir)?;
+        let create = |name: &str| -> Result<_, Error> {
+            Ok(std::io::BufWriter::new(std::fs::File::create(
+                dir.join(name),
+            )?))
+        };
+        let mut writer = GraphWriter {
+            nodes: create(NODES_FILE)?,
+            relationships: create(RELATIONSHIPS_FILE)?,
+            removed: create(REMOVED_FILE)?,
+        };
+        writeln!(writer.nodes, "oid:ID,name,slots:int,:LABEL")?;
+        writeln!(writer.relationships, ":START_ID,:END_ID,:TYPE,slot")?;
+        writeln!(writer.removed, "oid:ID")?;
+        Ok(writer)
+    }
+
+    pub fn node(&mut self, node: &Node) -> Result<(), Error> {
+        writeln!(
+            self.nodes,
+            "{},{},{},Object",
+            node.oid.id.to_hyphenated(),
+            csv_field(node.name.as_deref().unwrap_or("")),
+            node.slots
+        )?;
+        Ok(())
+    }
+
+    pub fn relationship(&mut self, relationship: &Relationship) -> Result<(), Error> {
+        writeln!(
+            self.relationships,
+            "{},{},{},{}",
+            relationship.from.id.to_hyphenated(),
+            relationship.to.id.to_hyphenated(),
+            relationship_type(&relationship.slot),
+            csv_field(&relationship.slot)
+        )?;
+        Ok(())
+    }
+
+    pub fn removed(&mut self, oid: Uuid) -> Result<(), Error> {
+        writeln!(self.removed, "{}", oid.to_hyphenated())?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.nodes.flush()?;
+        self.relationships.flush()?;
+        self.removed.flush()?;
+        Ok(())
+    }
+}
+
+// A field quoted if it needs to be, with its quotes doubled.
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
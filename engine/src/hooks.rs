@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use futures::future::{BoxFuture, FutureExt};
+use serde::Deserialize;
+use wasmtime::Engine;
+
+use crate::compile::{compile, read_program};
+use crate::world::{run_system_program, World};
+use value::{Program, Value};
+
+/// The points in the server's lifecycle at which hooks are run.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecyclePoint {
+    /// After the world has been loaded from a dump at startup.
+    PostLoad,
+    /// After a new world has been bootstrapped, there having been no dump to load.
+    PostBootstrap,
+    /// Just before connections start being accepted.
+    PreListen,
+    /// Before the world, or part of it, is dumped.
+    PreSave,
+}
+
+impl LifecyclePoint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LifecyclePoint::PostLoad => "post_load",
+            LifecyclePoint::PostBootstrap => "post_bootstrap",
+            LifecyclePoint::PreListen => "pre_listen",
+            LifecyclePoint::PreSave => "pre_save",
+        }
+    }
+}
+
+/// Customisation of the server's lifecycle, for those embedding the engine. Hooks are added to a
+/// world with `World::add_hooks`, and run in the order they were added.
+///
+/// A hook which fails at startup stops the server starting. One which fails before a save is
+/// logged, and the save goes ahead regardless.
+pub trait LifecycleHooks: Send + Sync {
+    fn run(&self, world: Arc<World>, point: LifecyclePoint) -> BoxFuture<'_, Result<(), Error>>;
+}
+
+/// Hooks written as WASM programs, named by a hooks file mapping lifecycle points to their
+/// programs' paths (relative to the file), e.g. `{"post_load": "post_load.wat"}`.
+///
+/// Each runs on the system object like a verb, in a transaction of its own, with the name of the
+/// point as its only argument. Returning an Error other than NoError fails the hook.
+pub struct WasmHooks {
+    programs: BTreeMap<LifecyclePoint, Program>,
+}
+
+impl WasmHooks {
+    /// Read the hooks file at `path`, checking that every program in it compiles with `engine`.
+    pub fn load(path: &Path, engine: &Engine) -> Result<Self, Error> {
+        let paths: BTreeMap<LifecyclePoint, PathBuf> =
+            serde_json::from_slice(&std::fs::read(path)?)
+                .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut programs = BTreeMap::new();
+        for (point, program_path) in paths {
+            let program_path = dir.join(program_path);
+            let program = read_program(&program_path)
+                .map_err(|e| anyhow!("{}: {}", program_path.display(), e))?;
+            compile(engine, &program).map_err(|e| anyhow!("{}: {}", program_path.display(), e))?;
+            programs.insert(point, program);
+        }
+        Ok(WasmHooks { programs })
+    }
+}
+
+impl LifecycleHooks for WasmHooks {
+    fn run(&self, world: Arc<World>, point: LifecyclePoint) -> BoxFuture<'_, Result<(), Error>> {
+        async move {
+            let program = match self.programs.get(&point) {
+                Some(program) => program,
+                None => return Ok(()),
+            };
+            let args = [Value::String(point.name().into())];
+            match run_system_program(&world, point.name(), program, &args).await? {
+                Value::Error(e) if e != value::Error::NoError => {
+                    Err(anyhow!("The {} hook failed: {:?}", point.name(), e))
+                }
+                _ => Ok(()),
+            }
+        }
+        .boxed()
+    }
+}
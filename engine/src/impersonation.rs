@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{range::Range, subspace::Subspace, tuple::Tuple, Key};
+use serde::Serialize;
+use tokio_stream::StreamExt;
+
+use crate::database::{DbError, Tx};
+use value::{Oid, Value};
+
+/// How much of a player's perspective an admin takes on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ImpersonationMode {
+    /// Copies of the messages sent to the player's connections, and nothing more.
+    ReadOnly = 0,
+    /// As well as the copies, the admin's connection acts as the player's: its lines are run as
+    /// the player's commands.
+    Full = 1,
+}
+
+impl ImpersonationMode {
+    /// The mode named "read_only" or "full".
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "read_only" => Some(ImpersonationMode::ReadOnly),
+            "full" => Some(ImpersonationMode::Full),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImpersonationMode::ReadOnly => "read_only",
+            ImpersonationMode::Full => "full",
+        }
+    }
+}
+
+/// An admin's connection attached to a player's perspective.
+#[derive(Clone, Debug, Serialize)]
+pub struct Impersonation {
+    pub id: Oid,
+    /// The admin's connection, and the player it's logged in to.
+    pub connection: Oid,
+    pub admin: Oid,
+    /// The player impersonated.
+    pub player: Oid,
+    pub mode: ImpersonationMode,
+    pub started: SystemTime,
+}
+
+/// The impersonations going on now, by the admins' connections. Each connection impersonates one
+/// player at a time.
+#[derive(Default)]
+pub struct ImpersonationRegistry {
+    sessions: Mutex<HashMap<Oid, Impersonation>>,
+}
+
+impl ImpersonationRegistry {
+    /// Start `impersonation`, returning the one its connection had going, if any, which it
+    /// replaces.
+    pub fn start(&self, impersonation: Impersonation) -> Option<Impersonation> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(impersonation.connection, impersonation)
+    }
+
+    /// End `connection`'s impersonation, returning it. None if it had none going.
+    pub fn end(&self, connection: Oid) -> Option<Impersonation> {
+        self.sessions.lock().unwrap().remove(&connection)
+    }
+
+    /// `connection`'s impersonation, if it has one going.
+    pub fn of(&self, connection: Oid) -> Option<Impersonation> {
+        self.sessions.lock().unwrap().get(&connection).cloned()
+    }
+
+    /// The connections impersonating `player`.
+    pub fn watching(&self, player: Oid) -> Vec<Oid> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|impersonation| impersonation.player == player)
+            .map(|impersonation| impersonation.connection)
+            .collect()
+    }
+
+    /// Every impersonation going on, the longest running first.
+    pub fn list(&self) -> Vec<Impersonation> {
+        let mut sessions: Vec<_> = self.sessions.lock().unwrap().values().cloned().collect();
+        sessions.sort_by_key(|impersonation| impersonation.started);
+        sessions
+    }
+}
+
+/// What happened in an impersonation.
+#[derive(Clone, Debug, Serialize)]
+pub enum AuditEvent {
+    Started,
+    /// A line or request the admin sent, which was run as the player's.
+    Command(String),
+    Ended,
+}
+
+/// An entry in the audit trail of impersonations, which is kept indefinitely.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    pub session: Oid,
+    pub timestamp: SystemTime,
+    pub admin: Oid,
+    pub player: Oid,
+    pub mode: ImpersonationMode,
+    pub event: AuditEvent,
+}
+
+impl AuditEntry {
+    pub fn new(impersonation: &Impersonation, event: AuditEvent) -> Self {
+        AuditEntry {
+            session: impersonation.id,
+            timestamp: SystemTime::now(),
+            admin: impersonation.admin,
+            player: impersonation.player,
+            mode: impersonation.mode,
+            event,
+        }
+    }
+
+    /// A Vector of [name, value] pairs: its "session", "timestamp", "admin", "player", "mode",
+    /// "event" ("started", "command" or "ended") and, for a command, its "text".
+    pub fn info(&self) -> Value {
+        let field = |name: &str, value| Value::Vector(vec![Value::String(name.into()), value]);
+        let since_epoch = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut info = vec![
+            field("session", Value::IdKey(self.session)),
+            field("timestamp", Value::Timestamp(since_epoch.as_nanos() as i64)),
+            field("admin", Value::IdKey(self.admin)),
+            field("player", Value::IdKey(self.player)),
+            field("mode", Value::String(self.mode.name().into())),
+        ];
+        let event = match &self.event {
+            AuditEvent::Started => "started",
+            AuditEvent::Command(_) => "command",
+            AuditEvent::Ended => "ended",
+        };
+        info.push(field("event", Value::String(event.into())));
+        if let AuditEvent::Command(text) = &self.event {
+            info.push(field("text", Value::String(text.as_str().into())));
+        }
+        Value::Vector(info)
+    }
+
+    fn key(&self) -> Key {
+        let mut tup = Tuple::new();
+        tup.add_i64(micros(self.timestamp));
+        tup.add_uuid(self.session.id);
+        tup.add_i64(SEQUENCE.fetch_add(1, Ordering::Relaxed));
+        audit_subspace().subspace(&tup).pack().into()
+    }
+
+    fn value(&self) -> fdb::Value {
+        let mut tup = Tuple::new();
+        tup.add_uuid(self.admin.id);
+        tup.add_uuid(self.player.id);
+        tup.add_i8(self.mode as i8);
+        match &self.event {
+            AuditEvent::Started => tup.add_i8(0),
+            AuditEvent::Command(text) => {
+                tup.add_i8(1);
+                tup.add_string(text.clone());
+            }
+            AuditEvent::Ended => tup.add_i8(2),
+        }
+        tup.pack().into()
+    }
+
+    fn from_kv(key: Key, value: fdb::Value) -> Self {
+        let key_bytes: Bytes = key.into();
+        let key_tuple = audit_subspace().unpack(&key_bytes).unwrap();
+        let value_tuple = Tuple::from_bytes(value).unwrap();
+        let event = match value_tuple.get_i8(3).unwrap() {
+            0 => AuditEvent::Started,
+            1 => AuditEvent::Command(value_tuple.get_string_ref(4).unwrap().clone()),
+            _ => AuditEvent::Ended,
+        };
+        AuditEntry {
+            session: Oid {
+                id: *key_tuple.get_uuid_ref(1).unwrap(),
+            },
+            timestamp: UNIX_EPOCH + Duration::from_micros(key_tuple.get_i64(0).unwrap() as u64),
+            admin: Oid {
+                id: *value_tuple.get_uuid_ref(0).unwrap(),
+            },
+            player: Oid {
+                id: *value_tuple.get_uuid_ref(1).unwrap(),
+            },
+            mode: match value_tuple.get_i8(2).unwrap() {
+                0 => ImpersonationMode::ReadOnly,
+                _ => ImpersonationMode::Full,
+            },
+            event,
+        }
+    }
+}
+
+/// How many entries of the audit trail are read at once.
+pub const AUDIT_PAGE: usize = 1000;
+
+// Entries are keyed by (timestamp, session, sequence), so that they're read in order.
+fn audit_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("IMPERSONATION_AUDIT".as_bytes()))
+}
+
+// Disambiguates entries written in the same microsecond.
+static SEQUENCE: AtomicI64 = AtomicI64::new(0);
+
+fn micros(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64
+}
+
+// Entries from `time` on, rounded up to the microsecond, as entries' times are truncated to one.
+fn time_key(time: SystemTime) -> Key {
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut tup = Tuple::new();
+    tup.add_i64(((nanos + 999) / 1000) as i64);
+    audit_subspace().subspace(&tup).pack().into()
+}
+
+// Reads and writes the audit trail via one transaction.
+pub struct AuditTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> AuditTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        AuditTxHandle { tr: tx }
+    }
+
+    pub fn append(&self, entry: &AuditEntry) {
+        self.tr.set(entry.key(), entry.value());
+    }
+
+    /// The entries in the time range [from, to), oldest first, up to AUDIT_PAGE of them and
+    /// then any more from the same microsecond as the last, so that the next page can start
+    /// just after it.
+    pub async fn entries(
+        &self,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<Vec<AuditEntry>, DbError> {
+        let range = Range::new(time_key(from), time_key(to));
+        let mut stream = self.tr.get_range(range);
+        let mut entries: Vec<AuditEntry> = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, value) = kv?;
+            let entry = AuditEntry::from_kv(key, value);
+            if entries.len() >= AUDIT_PAGE
+                && entries.last().map(|last| last.timestamp) != Some(entry.timestamp)
+            {
+                break;
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
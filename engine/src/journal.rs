@@ -0,0 +1,311 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{range::Range, subspace::Subspace, tuple::Tuple, Key};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio_stream::StreamExt;
+use tungstenite::Message;
+use uuid::Uuid;
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// How much of each outbound message to keep in the journal.
+#[derive(Clone, Copy, Debug)]
+pub enum JournalPrivacy {
+    /// The full message content, compressed.
+    Full,
+    /// Only a SHA-256 digest of the content, enough to confirm or refute a claim about what was
+    /// sent without retaining it.
+    Digest,
+    /// Only when the message was sent, its kind and its length.
+    Metadata,
+}
+
+/// Options for journaling messages sent to connections, for later audit.
+#[derive(Clone, Debug)]
+pub struct JournalOptions {
+    pub privacy: JournalPrivacy,
+    /// Entries older than this are deleted.
+    pub retention: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageKind {
+    Text = 0,
+    Binary = 1,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum JournalContent {
+    Message(Vec<u8>),
+    Digest(Vec<u8>),
+    Redacted,
+}
+
+/// A message which was sent to a connection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub connection: Oid,
+    /// The player the connection was logged in to, if it was.
+    pub player: Option<Oid>,
+    pub timestamp: SystemTime,
+    pub kind: MessageKind,
+    pub length: usize,
+    pub content: JournalContent,
+}
+
+/// Entries pruned in one transaction, at most.
+pub const PRUNE_BATCH: usize = 1000;
+
+// Entries are keyed by (subject, timestamp, sequence), the subject being the player the connection
+// was logged in to, or the connection if it wasn't, so that everything sent to a player is one
+// range. An index keyed by (timestamp, subject, sequence) finds the oldest for retention.
+// (Entries journaled before they were keyed this way, under "JOURNAL", aren't read.)
+fn journal_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("JOURNAL_ENTRY".as_bytes()))
+}
+
+fn journal_time_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("JOURNAL_TIME".as_bytes()))
+}
+
+// Disambiguates entries written in the same microsecond.
+static SEQUENCE: AtomicI64 = AtomicI64::new(0);
+
+fn micros(time: SystemTime) -> i64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    i64::try_from(since_epoch.as_micros()).unwrap_or(i64::MAX)
+}
+
+fn from_micros(micros: i64) -> SystemTime {
+    let since_epoch = Duration::from_micros(u64::try_from(micros).unwrap_or(0));
+    UNIX_EPOCH.checked_add(since_epoch).unwrap_or(UNIX_EPOCH)
+}
+
+fn subject_key(subject: Oid, time: SystemTime) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_uuid(subject.id);
+    tup.add_i64(micros(time));
+    journal_subspace().subspace(&tup).pack().into()
+}
+
+fn time_key(time: SystemTime) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_i64(micros(time));
+    journal_time_subspace().subspace(&tup).pack().into()
+}
+
+impl JournalEntry {
+    /// Build the journal entry for `message`, retaining as much of it as `privacy` allows.
+    /// Returns None for messages which aren't journaled (control frames).
+    pub fn new(
+        connection: Oid,
+        player: Option<Oid>,
+        message: &Message,
+        privacy: JournalPrivacy,
+    ) -> Option<Self> {
+        let (kind, data) = match message {
+            Message::Text(s) => (MessageKind::Text, s.as_bytes()),
+            Message::Binary(b) => (MessageKind::Binary, &b[..]),
+            _ => return None,
+        };
+        let content = match privacy {
+            JournalPrivacy::Full => JournalContent::Message(data.to_vec()),
+            JournalPrivacy::Digest => JournalContent::Digest(Sha256::digest(data).to_vec()),
+            JournalPrivacy::Metadata => JournalContent::Redacted,
+        };
+        Some(JournalEntry {
+            connection,
+            player,
+            timestamp: SystemTime::now(),
+            kind,
+            length: data.len(),
+            content,
+        })
+    }
+
+    /// Whom the entry is kept under: the player, or the connection if there was none.
+    pub fn subject(&self) -> Oid {
+        self.player.unwrap_or(self.connection)
+    }
+
+    // The entry's key, and its key in the time index.
+    fn keys(&self) -> (Key, Key) {
+        let (time, subject) = (micros(self.timestamp), self.subject().id);
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let mut tup = Tuple::new();
+        tup.add_uuid(subject);
+        tup.add_i64(time);
+        tup.add_i64(sequence);
+        let key = journal_subspace().subspace(&tup).pack().into();
+        let mut tup = Tuple::new();
+        tup.add_i64(time);
+        tup.add_uuid(subject);
+        tup.add_i64(sequence);
+        (key, journal_time_subspace().subspace(&tup).pack().into())
+    }
+
+    fn value(&self) -> fdb::Value {
+        let mut tup = Tuple::new();
+        tup.add_i8(self.kind as i8);
+        tup.add_i64(i64::try_from(self.length).unwrap_or(i64::MAX));
+        tup.add_uuid(self.connection.id);
+        match &self.content {
+            JournalContent::Message(m) => {
+                tup.add_i8(0);
+                let compressed = zstd::encode_all(&m[..], 0).expect("Could not compress message");
+                tup.add_bytes(Bytes::from(compressed));
+            }
+            JournalContent::Digest(d) => {
+                tup.add_i8(1);
+                tup.add_bytes(Bytes::from(d.clone()));
+            }
+            JournalContent::Redacted => {
+                tup.add_i8(2);
+            }
+        }
+        tup.pack().into()
+    }
+
+    // The entry stored at `key`, or None if it can't be made sense of.
+    fn from_kv(key: &Key, value: fdb::Value) -> Option<Self> {
+        let key_bytes: Bytes = key.clone().into();
+        let key_tuple = journal_subspace().unpack(&key_bytes).ok()?;
+        let value_tuple = Tuple::from_bytes(value).ok()?;
+
+        let subject = Oid {
+            id: *key_tuple.get_uuid_ref(0).ok()?,
+        };
+        let connection = Oid {
+            id: *value_tuple.get_uuid_ref(2).ok()?,
+        };
+        let content = match value_tuple.get_i8(3).ok()? {
+            0 => {
+                let compressed = value_tuple.get_bytes_ref(4).ok()?;
+                JournalContent::Message(zstd::decode_all(&compressed[..]).ok()?)
+            }
+            1 => JournalContent::Digest(value_tuple.get_bytes_ref(4).ok()?.to_vec()),
+            _ => JournalContent::Redacted,
+        };
+        Some(JournalEntry {
+            connection,
+            player: (subject != connection).then_some(subject),
+            timestamp: from_micros(key_tuple.get_i64(1).ok()?),
+            kind: if value_tuple.get_i8(0).ok()? == MessageKind::Text as i8 {
+                MessageKind::Text
+            } else {
+                MessageKind::Binary
+            },
+            length: usize::try_from(value_tuple.get_i64(1).ok()?).unwrap_or(0),
+            content,
+        })
+    }
+}
+
+// The key of the entry a key in the time index stands for.
+fn entry_key(time_key: &Key) -> Option<Key> {
+    let key_bytes: Bytes = time_key.clone().into();
+    let tuple = journal_time_subspace().unpack(&key_bytes).ok()?;
+    let mut tup = Tuple::new();
+    tup.add_uuid(*tuple.get_uuid_ref(1).ok()?);
+    tup.add_i64(tuple.get_i64(0).ok()?);
+    tup.add_i64(tuple.get_i64(2).ok()?);
+    Some(journal_subspace().subspace(&tup).pack().into())
+}
+
+// The key in the time index of the entry at `entry_key`.
+fn time_index_key(entry_key: &Key) -> Option<Key> {
+    let key_bytes: Bytes = entry_key.clone().into();
+    let tuple = journal_subspace().unpack(&key_bytes).ok()?;
+    let mut tup = Tuple::new();
+    tup.add_i64(tuple.get_i64(1).ok()?);
+    tup.add_uuid(*tuple.get_uuid_ref(0).ok()?);
+    tup.add_i64(tuple.get_i64(2).ok()?);
+    Some(journal_time_subspace().subspace(&tup).pack().into())
+}
+
+// Reads and writes the journal via one transaction.
+pub struct JournalTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> JournalTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        JournalTxHandle { tr: tx }
+    }
+
+    pub fn append(&self, entry: &JournalEntry) {
+        let (key, time_key) = entry.keys();
+        self.tr.set(key, entry.value());
+        self.tr.set(time_key, Bytes::new());
+    }
+
+    /// Delete up to `limit` of the entries from before `cutoff`, oldest first, returning how many
+    /// were deleted.
+    pub async fn prune(&self, cutoff: SystemTime, limit: usize) -> Result<usize, DbError> {
+        let range = Range::new(journal_time_subspace().pack(), time_key(cutoff));
+        let mut stream = self.tr.snapshot_get_range(range);
+        let mut pruned = 0;
+        while pruned < limit {
+            let (time_key, _) = match stream.next().await {
+                Some(kv) => kv?,
+                None => break,
+            };
+            if let Some(key) = entry_key(&time_key) {
+                self.tr.clear(key);
+            }
+            self.tr.clear(time_key);
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+
+    /// The entries for `subject` (a player, or a connection which wasn't logged in) in the time
+    /// range [from, to).
+    pub async fn entries(
+        &self,
+        subject: Oid,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<Vec<JournalEntry>, DbError> {
+        let range = Range::new(subject_key(subject, from), subject_key(subject, to));
+        let mut stream = self.tr.get_range(range);
+        let mut entries = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, value) = kv?;
+            entries.extend(JournalEntry::from_kv(&key, value));
+        }
+        Ok(entries)
+    }
+
+    /// Remove all of `subject`'s entries from the journal, returning how many there were.
+    /// If `anonymize` is set the entries are kept, but with their content, connection and player
+    /// stripped, so that the timing and volume of traffic remains on record.
+    pub async fn erase(&self, subject: Oid, anonymize: bool) -> Result<usize, DbError> {
+        let mut tup = Tuple::new();
+        tup.add_uuid(subject.id);
+        let mut stream = self.tr.get_range(journal_subspace().range(&tup));
+        let mut erased = 0;
+        while let Some(kv) = stream.next().await {
+            let (key, value) = kv?;
+            if let Some(time_key) = time_index_key(&key) {
+                self.tr.clear(time_key);
+            }
+            self.tr.clear(key.clone());
+            erased += 1;
+            match JournalEntry::from_kv(&key, value) {
+                Some(entry) if anonymize => self.append(&JournalEntry {
+                    connection: Oid { id: Uuid::nil() },
+                    player: None,
+                    content: JournalContent::Redacted,
+                    ..entry
+                }),
+                _ => {}
+            }
+        }
+        Ok(erased)
+    }
+}
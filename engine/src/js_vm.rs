@@ -0,0 +1,824 @@
+use std::cell::{Cell, RefCell};
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Once};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::runtime::Handle;
+use tracing::{info, warn};
+use tungstenite::Message;
+
+use crate::database::Tx;
+use crate::dependencies::{DependencyTxHandle, SlotAccesses};
+use crate::module_cache;
+use crate::outbound::Delivery;
+use crate::trace::Invocation;
+use crate::verb_audit::InvocationRecord;
+use crate::wasm_vm::{DryRun, ExecutionLimits, WasmVM};
+use crate::world::{
+    audit_invocation, get_slot, invoke_verb, list_slots, send_connection_message_in, set_slot,
+    World,
+};
+use value::Error::{NoError, PermissionDenied, ResourceLimit};
+use value::{encode_frame, Oid, Program, Value};
+
+/// The least a verb's heap is limited to, whatever its 'memory_limit', as V8 won't run in less.
+const MIN_HEAP: usize = 8 * 1024 * 1024;
+
+/// How often a verb's watchdog checks whether it's run out of time or been killed.
+const WATCH_SLICE: Duration = Duration::from_millis(10);
+
+// V8's flags are process wide, so they're set once, before the first isolate is made: no code is
+// made from strings (`eval`, `new Function`), so everything a verb runs was checked with it, and
+// verbs have no WebAssembly of their own.
+const V8_FLAGS: &str = "--disallow-code-generation-from-strings --noexpose-wasm";
+
+// Verbs are run as the body of a function, so that they get their arguments as `arguments` and
+// give their result with `return`.
+const PROLOGUE: &str = "(function () {\n";
+const EPILOGUE: &str = "\n})";
+
+/// What a JavaScript verb runs against: the world, the connection its VM belongs to (if any), the
+/// transaction it acts within, and the object it was found on.
+pub struct JsContext {
+    pub world: Arc<World>,
+    pub connection: Option<Oid>,
+    pub tx: Tx,
+    pub this: Oid,
+    /// Who invoked the verb, as the audit trail records it.
+    pub caller: Option<Oid>,
+    /// The seed for the verb's randomness, if it's executed deterministically.
+    pub seed: Option<u64>,
+}
+
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        v8::V8::set_flags_from_string(V8_FLAGS);
+        let platform = v8::new_default_platform(0, false).make_shared();
+        v8::V8::initialize_platform(platform);
+        v8::V8::initialize();
+    });
+}
+
+// What the builtins share while a verb runs. It's kept in the isolate's slot, as V8 only calls
+// plain functions.
+struct Host {
+    context: JsContext,
+    handle: Handle,
+    dry_run: RefCell<Option<DryRun>>,
+    accesses: RefCell<Option<SlotAccesses>>,
+    host_calls: Cell<u64>,
+    // Values with no natural JavaScript form, which verbs hold as opaque objects naming them here.
+    values: RefCell<Vec<Value>>,
+    random: RefCell<Option<StdRng>>,
+    // The VM the verbs it invokes are run by, as they may be WebAssembly: made by the first
+    // 'invoke', and kept for the rest of the verb.
+    vm: RefCell<Option<Arc<WasmVM>>>,
+    // Why the host couldn't carry on, which ends the verb where it can't be caught.
+    failure: RefCell<Option<anyhow::Error>>,
+}
+
+impl Host {
+    fn call(&self) {
+        self.host_calls.set(self.host_calls.get() + 1);
+    }
+
+    fn vm(&self) -> Result<Arc<WasmVM>, Failure> {
+        if let Some(vm) = self.vm.borrow().as_ref() {
+            return Ok(vm.clone());
+        }
+        let world = &self.context.world;
+        let vm =
+            Arc::new(WasmVM::new(world.clone(), self.context.connection).map_err(Failure::Host)?);
+        vm.clone().bind_builtins().map_err(Failure::Host)?;
+        vm.set_deterministic(self.context.seed.is_some());
+        vm.set_caller(Some(self.context.this));
+        *self.vm.borrow_mut() = Some(vm.clone());
+        Ok(vm)
+    }
+
+    fn block_on<T>(
+        &self,
+        future: impl std::future::Future<Output = Result<T, anyhow::Error>>,
+    ) -> Result<T, Failure> {
+        self.handle.block_on(future).map_err(Failure::Host)
+    }
+}
+
+fn host(scope: &mut v8::HandleScope) -> Rc<Host> {
+    scope
+        .get_slot::<Rc<Host>>()
+        .expect("JavaScript verb run without its host")
+        .clone()
+}
+
+// Why a builtin didn't return: arguments it can't take, which the verb may catch as a TypeError,
+// or the host failing, which ends the verb.
+enum Failure {
+    Invalid,
+    Host(anyhow::Error),
+}
+
+fn string<'s>(scope: &mut v8::HandleScope<'s>, text: &str) -> Option<v8::Local<'s, v8::String>> {
+    v8::String::new(scope, text)
+}
+
+// The private property holding which of the host's values an opaque object stands for, and the
+// one on the global object holding the Map of those objects by the values' encodings, so that
+// the same value is always the same object, and `===` compares them.
+fn value_key<'s>(scope: &mut v8::HandleScope<'s>) -> Option<v8::Local<'s, v8::Private>> {
+    let name = string(scope, "room.value")?;
+    Some(v8::Private::for_api(scope, Some(name)))
+}
+
+fn interned_key<'s>(scope: &mut v8::HandleScope<'s>) -> Option<v8::Local<'s, v8::Private>> {
+    let name = string(scope, "room.values")?;
+    Some(v8::Private::for_api(scope, Some(name)))
+}
+
+fn host_value_to_string(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let host = host(scope);
+    if let Ok(value) = from_js(scope, &host, args.this().into()) {
+        if let Some(text) = string(scope, &format!("{:?}", value)) {
+            rv.set(text.into());
+        }
+    }
+}
+
+fn host_value<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    host: &Host,
+    value: &Value,
+) -> Option<v8::Local<'s, v8::Value>> {
+    let context = scope.get_current_context();
+    let global = context.global(scope);
+    let interned_key = interned_key(scope)?;
+    let interned = v8::Local::<v8::Map>::try_from(global.get_private(scope, interned_key)?).ok()?;
+    let encoding = encode_frame(value)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    let encoding = string(scope, &encoding)?;
+    let existing = interned.get(scope, encoding.into())?;
+    if existing.is_object() {
+        return Some(existing);
+    }
+
+    let index = {
+        let mut values = host.values.borrow_mut();
+        values.push(value.clone());
+        values.len() - 1
+    };
+    let object = v8::Object::new(scope);
+    let value_key = value_key(scope)?;
+    let index = v8::Number::new(scope, index as f64);
+    object.set_private(scope, value_key, index.into())?;
+    let to_string = v8::Function::new(scope, host_value_to_string)?;
+    let name = string(scope, "toString")?;
+    object.set(scope, name.into(), to_string.into())?;
+    interned.set(scope, encoding.into(), object.into())?;
+    Some(object.into())
+}
+
+// Integers within the range a double holds exactly are numbers; others are BigInts.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+fn to_js<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    host: &Host,
+    value: &Value,
+) -> Option<v8::Local<'s, v8::Value>> {
+    Some(match value {
+        Value::I32(n) => v8::Integer::new(scope, *n).into(),
+        Value::I64(n) if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(n) => {
+            v8::Number::new(scope, *n as f64).into()
+        }
+        Value::I64(n) => v8::BigInt::new_from_i64(scope, *n).into(),
+        Value::F32(n) => v8::Number::new(scope, *n as f64).into(),
+        Value::F64(n) => v8::Number::new(scope, *n).into(),
+        Value::String(text) => string(scope, text)?.into(),
+        Value::Vector(values) => {
+            let array = v8::Array::new(scope, values.len() as i32);
+            for (i, value) in values.iter().enumerate() {
+                let value = to_js(scope, host, value)?;
+                array.set_index(scope, i as u32, value)?;
+            }
+            array.into()
+        }
+        Value::Binary(bytes) => {
+            let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(
+                bytes.to_vec().into_boxed_slice(),
+            )
+            .make_shared();
+            let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+            v8::Uint8Array::new(scope, buffer, 0, bytes.len())?.into()
+        }
+        other => host_value(scope, host, other)?,
+    })
+}
+
+// Arrays are Vectors; any other object a Vector of [key, value] pairs.
+fn object_value(
+    scope: &mut v8::HandleScope,
+    host: &Host,
+    object: v8::Local<v8::Object>,
+) -> Result<Value, String> {
+    if let Ok(array) = v8::Local::<v8::Array>::try_from(object) {
+        let mut values = Vec::with_capacity(array.length() as usize);
+        for i in 0..array.length() {
+            let value = array
+                .get_index(scope, i)
+                .ok_or("Unreadable array element")?;
+            values.push(from_js(scope, host, value)?);
+        }
+        return Ok(Value::Vector(values));
+    }
+    let keys = object
+        .get_own_property_names(scope, v8::GetPropertyNamesArgs::default())
+        .ok_or("Unreadable object")?;
+    let mut pairs = Vec::with_capacity(keys.length() as usize);
+    for i in 0..keys.length() {
+        let key = keys.get_index(scope, i).ok_or("Unreadable object key")?;
+        let value = object.get(scope, key).ok_or("Unreadable object value")?;
+        pairs.push(Value::Vector(vec![
+            from_js(scope, host, key)?,
+            from_js(scope, host, value)?,
+        ]));
+    }
+    Ok(Value::Vector(pairs))
+}
+
+fn from_js(
+    scope: &mut v8::HandleScope,
+    host: &Host,
+    value: v8::Local<v8::Value>,
+) -> Result<Value, String> {
+    if value.is_null_or_undefined() {
+        return Ok(Value::I32(0));
+    }
+    if value.is_boolean() {
+        return Ok(Value::I32(value.boolean_value(scope) as i32));
+    }
+    if value.is_number() {
+        let n = value.number_value(scope).unwrap_or(f64::NAN);
+        return Ok(
+            match n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER as f64 {
+                true => Value::I64(n as i64),
+                false => Value::F64(n),
+            },
+        );
+    }
+    if let Ok(n) = v8::Local::<v8::BigInt>::try_from(value) {
+        return match n.i64_value() {
+            (n, true) => Ok(Value::I64(n)),
+            (_, false) => Err("BigInt out of range".to_string()),
+        };
+    }
+    if value.is_string() {
+        return Ok(Value::String(value.to_rust_string_lossy(scope).into()));
+    }
+    if let Ok(view) = v8::Local::<v8::ArrayBufferView>::try_from(value) {
+        let mut bytes = vec![0; view.byte_length()];
+        view.copy_contents(&mut bytes);
+        return Ok(Value::Binary(Bytes::from(bytes)));
+    }
+    if value.is_function() {
+        return Err("Functions can't be passed to the host".to_string());
+    }
+    let object = match v8::Local::<v8::Object>::try_from(value) {
+        Ok(object) => object,
+        Err(_) => return Err("Value can't be passed to the host".to_string()),
+    };
+    let value_key = value_key(scope).ok_or("Out of memory")?;
+    if let Some(index) = object.get_private(scope, value_key) {
+        if index.is_number() {
+            let index = index.number_value(scope).unwrap_or(-1.0);
+            return match host.values.borrow().get(index as usize) {
+                Some(value) if index >= 0.0 => Ok(value.clone()),
+                _ => Err("Unknown host value".to_string()),
+            };
+        }
+    }
+    object_value(scope, host, object)
+}
+
+fn throw(scope: &mut v8::HandleScope, message: &str) {
+    if let Some(message) = string(scope, message) {
+        let exception = v8::Exception::type_error(scope, message);
+        scope.throw_exception(exception);
+    }
+}
+
+// Runs a builtin: its arguments converted from JavaScript, and its result converted back.
+fn builtin(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+    name: &str,
+    body: impl FnOnce(&Host, Vec<Value>) -> Result<Value, Failure>,
+) {
+    let host = host(scope);
+    host.call();
+    let mut values = Vec::with_capacity(args.length() as usize);
+    for i in 0..args.length() {
+        match from_js(scope, &host, args.get(i)) {
+            Ok(value) => values.push(value),
+            Err(e) => return throw(scope, &format!("Invalid arguments to '{}': {}", name, e)),
+        }
+    }
+    match body(&host, values) {
+        Ok(result) => match to_js(scope, &host, &result) {
+            Some(result) => rv.set(result),
+            None => throw(scope, &format!("'{}' returned too large a value", name)),
+        },
+        Err(Failure::Invalid) => throw(scope, &format!("Invalid arguments to '{}'", name)),
+        Err(Failure::Host(e)) => {
+            *host.failure.borrow_mut() = Some(e);
+            scope.terminate_execution();
+        }
+    }
+}
+
+fn oid_arg(value: &Value) -> Result<Oid, Failure> {
+    match value {
+        Value::IdKey(oid) => Ok(*oid),
+        _ => Err(Failure::Invalid),
+    }
+}
+
+fn get_slot_builtin(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    builtin(scope, args, rv, "get_slot", |h, args| {
+        let (oid, key, name) = match &args[..] {
+            [oid, key, Value::String(name)] => (oid_arg(oid)?, oid_arg(key)?, name),
+            _ => return Err(Failure::Invalid),
+        };
+        if let Some(accesses) = h.accesses.borrow_mut().as_mut() {
+            accesses.reads.insert((oid, key, name.to_string()));
+        }
+        h.block_on(get_slot(
+            &h.context.world,
+            &h.context.tx,
+            h.context.connection,
+            oid,
+            key,
+            name,
+        ))
+    })
+}
+
+fn list_slots_builtin(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    builtin(scope, args, rv, "list_slots", |h, args| {
+        let (oid, key) = match &args[..] {
+            [oid, key] => (oid_arg(oid)?, oid_arg(key)?),
+            _ => return Err(Failure::Invalid),
+        };
+        if let Some(accesses) = h.accesses.borrow_mut().as_mut() {
+            accesses.reads.insert((oid, key, String::new()));
+        }
+        h.block_on(list_slots(&h.context.tx, oid, key))
+    })
+}
+
+fn set_slot_builtin(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    builtin(scope, args, rv, "set_slot", |h, args| {
+        let (oid, key, name, value) = match &args[..] {
+            [oid, key, Value::String(name), value] => (oid_arg(oid)?, oid_arg(key)?, name, value),
+            _ => return Err(Failure::Invalid),
+        };
+        let world = &h.context.world;
+        let written = h.block_on(set_slot(
+            world,
+            &h.context.tx,
+            h.context.connection,
+            oid,
+            key,
+            name,
+            value,
+        ))?;
+        Ok(match written {
+            Value::Error(NoError) => {
+                if let Some(accesses) = h.accesses.borrow_mut().as_mut() {
+                    accesses.writes.insert((oid, key, name.to_string()));
+                }
+                h.handle
+                    .block_on(world.module_cache().slot_written(oid, key, name));
+                if let Some(dry_run) = h.dry_run.borrow_mut().as_mut() {
+                    dry_run.writes.push(Value::Vector(vec![
+                        Value::IdKey(oid),
+                        Value::IdKey(key),
+                        Value::String(name.clone()),
+                        value.clone(),
+                    ]));
+                }
+                Value::I32(0)
+            }
+            refused => refused,
+        })
+    })
+}
+
+fn invoke_builtin(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    builtin(scope, args, rv, "invoke", |h, args| {
+        let (oid, verb, arguments) = match &args[..] {
+            [oid, Value::String(verb), Value::Vector(arguments)] => {
+                (oid_arg(oid)?, verb, arguments)
+            }
+            _ => return Err(Failure::Invalid),
+        };
+        if h.dry_run.borrow().is_some() {
+            return Ok(Value::Error(PermissionDenied));
+        }
+        let vm = h.vm()?;
+        h.block_on(invoke_verb(
+            &h.context.world,
+            &h.context.tx,
+            vm,
+            oid,
+            verb,
+            arguments,
+        ))
+    })
+}
+
+fn send_builtin(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    builtin(scope, args, rv, "send", |h, args| {
+        let (connection, message) = match &args[..] {
+            [connection, Value::String(text)] => {
+                (oid_arg(connection)?, Message::Text(text.to_string()))
+            }
+            [connection, Value::Binary(bytes)] => {
+                (oid_arg(connection)?, Message::Binary(bytes.to_vec()))
+            }
+            _ => return Err(Failure::Invalid),
+        };
+        let delivery = match h.dry_run.borrow_mut().as_mut() {
+            Some(dry_run) => {
+                dry_run.messages.push(Value::Vector(vec![
+                    Value::IdKey(connection),
+                    args[1].clone(),
+                ]));
+                Delivery::Queued
+            }
+            None => h.block_on(send_connection_message_in(
+                h.context.world.clone(),
+                &h.context.tx,
+                connection,
+                message,
+            ))?,
+        };
+        Ok(Value::I32(delivery.code()))
+    })
+}
+
+fn log_builtin(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    builtin(scope, args, rv, "log", |h, args| {
+        let redaction = h.context.world.redaction();
+        let shown: Vec<_> = args.iter().map(|a| redaction.value(a)).collect();
+        info!("Log: {:?}", shown);
+        Ok(Value::I32(0))
+    })
+}
+
+// Math.random, for verbs executed deterministically.
+fn seeded_random(
+    scope: &mut v8::HandleScope,
+    _args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let host = host(scope);
+    let n = match host.random.borrow_mut().as_mut() {
+        Some(random) => random.gen::<f64>(),
+        None => 0.0,
+    };
+    rv.set(v8::Number::new(scope, n).into());
+}
+
+fn bind(
+    scope: &mut v8::HandleScope,
+    room: v8::Local<v8::Object>,
+    name: &str,
+    callback: impl v8::MapFnTo<v8::FunctionCallback>,
+) -> Option<()> {
+    let function = v8::Function::new(scope, callback)?;
+    let name = string(scope, name)?;
+    room.set(scope, name.into(), function.into())?;
+    Some(())
+}
+
+// The builtins, as functions in a global 'room' object, with the verb's object as 'room.this' and
+// its connection (if any) as 'room.connection'.
+fn bind_builtins(scope: &mut v8::HandleScope, host: &Host) -> Option<()> {
+    let context = scope.get_current_context();
+    let global = context.global(scope);
+    let interned_key = interned_key(scope)?;
+    let interned = v8::Map::new(scope);
+    global.set_private(scope, interned_key, interned.into())?;
+
+    let room = v8::Object::new(scope);
+    bind(scope, room, "get_slot", get_slot_builtin)?;
+    bind(scope, room, "list_slots", list_slots_builtin)?;
+    bind(scope, room, "set_slot", set_slot_builtin)?;
+    bind(scope, room, "invoke", invoke_builtin)?;
+    bind(scope, room, "send", send_builtin)?;
+    bind(scope, room, "log", log_builtin)?;
+    let this = to_js(scope, host, &Value::IdKey(host.context.this))?;
+    let name = string(scope, "this")?;
+    room.set(scope, name.into(), this)?;
+    if let Some(connection) = host.context.connection {
+        let connection = to_js(scope, host, &Value::IdKey(connection))?;
+        let name = string(scope, "connection")?;
+        room.set(scope, name.into(), connection)?;
+    }
+    let name = string(scope, "room")?;
+    global.set(scope, name.into(), room.into())?;
+
+    // Deterministic verbs draw their randomness from the invocation, and have no clock.
+    if host.context.seed.is_some() {
+        let name = string(scope, "Math")?;
+        let math = global.get(scope, name.into())?;
+        let math = v8::Local::<v8::Object>::try_from(math).ok()?;
+        let random = v8::Function::new(scope, seeded_random)?;
+        let name = string(scope, "random")?;
+        math.set(scope, name.into(), random.into())?;
+        let name = string(scope, "Date")?;
+        global.delete(scope, name.into())?;
+    }
+    Some(())
+}
+
+// The verb, as a function of its arguments.
+fn compile<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    code: &[u8],
+) -> Result<Option<v8::Local<'s, v8::Function>>, String> {
+    let code = std::str::from_utf8(code).map_err(|e| format!("Program isn't UTF-8: {}", e))?;
+    let source = match string(scope, &format!("{}{}{}", PROLOGUE, code, EPILOGUE)) {
+        Some(source) => source,
+        None => return Err("Program too large".to_string()),
+    };
+    let function = v8::Script::compile(scope, source, None)
+        .and_then(|script| script.run(scope))
+        .and_then(|function| v8::Local::<v8::Function>::try_from(function).ok());
+    Ok(function)
+}
+
+fn exception(scope: &mut v8::TryCatch<v8::HandleScope>) -> String {
+    let message = scope
+        .exception()
+        .map(|exception| exception.to_rust_string_lossy(scope));
+    let line = scope
+        .message()
+        .and_then(|message| message.get_line_number(scope));
+    match (message, line) {
+        // Lines are counted from the verb's, not the function wrapped around it.
+        (Some(message), Some(line)) => format!("{} (line {})", message, line.saturating_sub(1)),
+        (Some(message), None) => message,
+        (None, _) => "Unknown error".to_string(),
+    }
+}
+
+/// Check that a JavaScript program at least compiles, as it's only compiled when it's run.
+pub fn check(program: &Program) -> Result<(), String> {
+    init();
+    let isolate = &mut v8::Isolate::new(v8::CreateParams::default());
+    let scope = &mut v8::HandleScope::new(isolate);
+    let context = v8::Context::new(scope);
+    let scope = &mut v8::ContextScope::new(scope, context);
+    let code = std::str::from_utf8(&program.code).map_err(|e| e.to_string())?;
+    let source = string(scope, &format!("{}{}{}", PROLOGUE, code, EPILOGUE))
+        .ok_or_else(|| "Program too large".to_string())?;
+    let scope = &mut v8::TryCatch::new(scope);
+    match v8::Script::compile(scope, source, None) {
+        Some(_) => Ok(()),
+        None => Err(exception(scope)),
+    }
+}
+
+// Runs the verb in a fresh context of `isolate`, with its builtins bound.
+fn evaluate(
+    isolate: &mut v8::OwnedIsolate,
+    host: &Host,
+    code: &[u8],
+    args: Value,
+) -> Result<Value, String> {
+    let scope = &mut v8::HandleScope::new(isolate);
+    let context = v8::Context::new(scope);
+    let scope = &mut v8::ContextScope::new(scope, context);
+    bind_builtins(scope, host).ok_or("Could not bind builtins")?;
+    let scope = &mut v8::TryCatch::new(scope);
+    let function = match compile(scope, code)? {
+        Some(function) => function,
+        None => return Err(exception(scope)),
+    };
+    let args = match args {
+        Value::Vector(args) => args,
+        other => vec![other],
+    };
+    let args = args
+        .iter()
+        .map(|arg| to_js(scope, host, arg))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("Arguments too large")?;
+    let receiver: v8::Local<v8::Value> = v8::undefined(scope).into();
+    match function.call(scope, receiver, &args) {
+        Some(result) => from_js(scope, host, result),
+        None => Err(exception(scope)),
+    }
+}
+
+// Ends a verb which has run past its time, or been killed, by terminating its isolate from outside.
+// Termination can't be caught by the verb, as exceptions can.
+fn watch(
+    isolate: v8::IsolateHandle,
+    done: mpsc::Receiver<()>,
+    time: Duration,
+    cancelled: Arc<AtomicBool>,
+    exceeded: Arc<AtomicBool>,
+) {
+    let started = Instant::now();
+    while let Err(mpsc::RecvTimeoutError::Timeout) = done.recv_timeout(WATCH_SLICE) {
+        if cancelled.load(Ordering::Relaxed) {
+            isolate.terminate_execution();
+            return;
+        }
+        if started.elapsed() >= time {
+            exceeded.store(true, Ordering::Relaxed);
+            isolate.terminate_execution();
+            return;
+        }
+    }
+}
+
+// What V8 is given to call as the heap nears its limit.
+struct HeapGuard {
+    isolate: v8::IsolateHandle,
+    exceeded: Arc<AtomicBool>,
+}
+
+// Terminates the verb, and raises the limit so that V8 doesn't abort the process before the
+// termination takes effect.
+extern "C" fn near_heap_limit(data: *mut c_void, current: usize, _initial: usize) -> usize {
+    let guard = unsafe { &*(data as *const HeapGuard) };
+    guard.exceeded.store(true, Ordering::Relaxed);
+    guard.isolate.terminate_execution();
+    current * 2
+}
+
+// What running a verb came to, passed back from the thread it ran on.
+struct Outcome {
+    result: Result<Value, anyhow::Error>,
+    dry_run: Option<DryRun>,
+    accesses: Option<SlotAccesses>,
+    host_calls: u64,
+}
+
+// Lets a verb's watchdog know it's been abandoned, as its task was killed, so that it ends it.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+fn run(
+    host: Host,
+    code: Bytes,
+    args: Value,
+    limits: ExecutionLimits,
+    cancelled: Arc<AtomicBool>,
+) -> Outcome {
+    init();
+    let host = Rc::new(host);
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let heap = limits.memory.max(MIN_HEAP);
+    let isolate = &mut v8::Isolate::new(v8::CreateParams::default().heap_limits(0, heap));
+    let guard = Box::new(HeapGuard {
+        isolate: isolate.thread_safe_handle(),
+        exceeded: exceeded.clone(),
+    });
+    let guard = Box::into_raw(guard) as *mut c_void;
+    isolate.add_near_heap_limit_callback(near_heap_limit, guard);
+    isolate.set_slot(host.clone());
+
+    let (done, finished) = mpsc::channel();
+    let watchdog = {
+        let (handle, cancelled, exceeded) = (
+            isolate.thread_safe_handle(),
+            cancelled.clone(),
+            exceeded.clone(),
+        );
+        std::thread::spawn(move || watch(handle, finished, limits.time, cancelled, exceeded))
+    };
+    let result = evaluate(isolate, &host, &code, args);
+    drop(done);
+    let _ = watchdog.join();
+    isolate.remove_near_heap_limit_callback(near_heap_limit, 0);
+    drop(unsafe { Box::from_raw(guard as *mut HeapGuard) });
+
+    let failure = host.failure.borrow_mut().take();
+    let result = match (failure, result) {
+        (Some(failure), _) => Err(failure),
+        _ if exceeded.load(Ordering::Relaxed) => {
+            warn!("JavaScript verb exceeded its limits");
+            Ok(Value::Error(ResourceLimit))
+        }
+        _ if cancelled.load(Ordering::Relaxed) => Err(anyhow!("Killed")),
+        (None, Ok(value)) => Ok(value),
+        (None, Err(e)) => Err(anyhow!("JavaScript error: {}", e)),
+    };
+    Outcome {
+        result,
+        dry_run: host.dry_run.borrow_mut().take(),
+        accesses: host.accesses.borrow_mut().take(),
+        host_calls: host.host_calls.get(),
+    }
+}
+
+/// Run the JavaScript `method`, found as `verb` on `context.this`, with `args`, as
+/// `lua_vm::execute` runs Lua: within the same transaction, with the same builtins, and within the
+/// same limits, though as V8 can't count instructions a JavaScript verb is always limited by time.
+/// Each invocation gets an isolate of its own, on a thread of its own.
+pub async fn execute(
+    context: JsContext,
+    verb: &str,
+    method: &Program,
+    args: &Value,
+    limits: ExecutionLimits,
+    dry_run: Option<DryRun>,
+) -> Result<(Value, Option<DryRun>), anyhow::Error> {
+    let world = context.world.clone();
+    let tx = context.tx.clone();
+    let (this, caller) = (context.this, context.caller);
+    let audited = dry_run.is_none();
+    let digest = module_cache::digest(method);
+    let host = Host {
+        accesses: RefCell::new(world.records_dependencies().then(SlotAccesses::default)),
+        random: RefCell::new(context.seed.map(StdRng::seed_from_u64)),
+        context,
+        handle: Handle::current(),
+        dry_run: RefCell::new(dry_run),
+        host_calls: Cell::new(0),
+        values: RefCell::new(vec![]),
+        vm: RefCell::new(None),
+        failure: RefCell::new(None),
+    };
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let _cancel = CancelOnDrop(cancelled.clone());
+    let (code, arguments) = (method.code.clone(), args.clone());
+    let started = Instant::now();
+    let outcome =
+        tokio::task::spawn_blocking(move || run(host, code, arguments, limits, cancelled)).await?;
+
+    world.tracer().record(&Invocation {
+        oid: this,
+        verb: verb.to_string(),
+        module: digest,
+        wall_time: started.elapsed(),
+        fuel: 0,
+        host_calls: outcome.host_calls,
+        failed: outcome.result.is_err(),
+    });
+    if let Some(accesses) = &outcome.accesses {
+        DependencyTxHandle::new(&tx).record(&digest, (this, verb), accesses);
+    }
+    if audited {
+        let record = InvocationRecord::new(caller, (this, verb), args, &outcome.result, 0);
+        audit_invocation(&world, &tx, record);
+    }
+    Ok((outcome.result?, outcome.dry_run))
+}
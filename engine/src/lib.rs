@@ -0,0 +1,74 @@
+// The engine: the world, its storage, the VMs verbs run in, and the listeners connections come in
+// on. The binary in main.rs parses the command line and does the rest through this.
+
+pub mod archive;
+pub mod auth;
+pub mod bandwidth;
+pub mod blob;
+pub mod builtins;
+pub mod calendar;
+pub mod catalog;
+pub mod changes;
+pub mod command;
+pub mod compile;
+pub mod config;
+pub mod contents;
+pub mod cooldown;
+pub mod core;
+pub mod counter;
+pub mod database;
+pub mod dependencies;
+pub mod determinism;
+pub mod doctor;
+pub mod dump;
+pub mod embedded_db;
+#[cfg(feature = "faults")]
+pub mod faults;
+pub mod fdb_object;
+pub mod federation;
+pub mod forms;
+pub mod gc;
+pub mod graph;
+pub mod hooks;
+pub mod impersonation;
+pub mod journal;
+pub mod js_vm;
+pub mod listeners;
+pub mod localtime;
+pub mod lua_vm;
+pub mod module_cache;
+pub mod names;
+pub mod net;
+pub mod object;
+pub mod object_store;
+pub mod operations;
+pub mod outbound;
+pub mod patterns;
+pub mod player_stats;
+pub mod preload;
+pub mod protocol;
+pub mod pubsub;
+pub mod quota;
+pub mod redact;
+pub mod refactor;
+pub mod resume;
+pub mod retention;
+pub mod schedule;
+pub mod scratch;
+pub mod search;
+pub mod sequence;
+pub mod sessions;
+pub mod stdlib;
+pub mod tags;
+pub mod tasks;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod totp;
+pub mod trace;
+pub mod verb_audit;
+pub mod verb_cache;
+pub mod verb_error;
+pub mod wasi_policy;
+pub mod wasm_vm;
+pub mod watch;
+pub mod world;
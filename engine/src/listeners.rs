@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use value::Oid;
+
+/// The verb messages from a listener's connections are given to.
+pub const DEFAULT_ENTRY_VERB: &str = "receive";
+
+/// How a listener's connections talk to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Websocket,
+    Telnet,
+}
+
+/// Where a listener's connections enter the world: the object and verb their messages (those
+/// which aren't read as commands) are given to, as [connection, message].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct EntryPoint {
+    pub object: Oid,
+    pub verb: String,
+}
+
+impl Default for EntryPoint {
+    /// The system object's 'receive'.
+    fn default() -> Self {
+        EntryPoint {
+            object: Oid { id: Uuid::nil() },
+            verb: DEFAULT_ENTRY_VERB.to_string(),
+        }
+    }
+}
+
+fn default_verb() -> String {
+    DEFAULT_ENTRY_VERB.to_string()
+}
+
+fn default_transport() -> Transport {
+    Transport::Websocket
+}
+
+/// A listener as a listeners file gives it, e.g.
+/// `{"name": "api", "address": "0.0.0.0:9003", "object": "<uuid>", "verb": "receive"}`.
+/// The transport defaults to "websocket", the object to the system object and the verb to
+/// 'receive'.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ListenerConfig {
+    pub name: String,
+    pub address: String,
+    #[serde(default = "default_transport")]
+    pub transport: Transport,
+    #[serde(default)]
+    pub object: Option<Uuid>,
+    #[serde(default = "default_verb")]
+    pub verb: String,
+}
+
+impl ListenerConfig {
+    /// One of the server's built in listeners, whose connections' messages go to the system
+    /// object's 'receive'.
+    pub fn built_in(name: &str, address: &str, transport: Transport) -> ListenerConfig {
+        let entry = EntryPoint::default();
+        ListenerConfig {
+            name: name.to_string(),
+            address: address.to_string(),
+            transport,
+            object: Some(entry.object.id),
+            verb: entry.verb,
+        }
+    }
+
+    pub fn entry(&self) -> EntryPoint {
+        EntryPoint {
+            object: Oid {
+                id: self.object.unwrap_or_else(Uuid::nil),
+            },
+            verb: self.verb.clone(),
+        }
+    }
+}
+
+/// Read a listeners file: a JSON array of ListenerConfigs, whose names must be unique.
+pub fn read_listener_configs(path: &Path) -> Result<Vec<ListenerConfig>, Error> {
+    let configs: Vec<ListenerConfig> = serde_json::from_slice(&std::fs::read(path)?)?;
+    check_listener_names(&configs)?;
+    Ok(configs)
+}
+
+/// Fail if any two of `configs` have the same name.
+pub fn check_listener_names(configs: &[ListenerConfig]) -> Result<(), Error> {
+    for (i, config) in configs.iter().enumerate() {
+        if configs[..i].iter().any(|other| other.name == config.name) {
+            return Err(anyhow!("Listener {} is configured twice", config.name));
+        }
+    }
+    Ok(())
+}
+
+/// A listener the server is accepting connections on.
+#[derive(Clone, Debug, Serialize)]
+pub struct Listener {
+    pub name: String,
+    pub address: String,
+    pub transport: Transport,
+    pub entry: EntryPoint,
+}
+
+/// The listeners the server is accepting connections on, by name, so that their connections can
+/// be given to their entry points.
+#[derive(Default)]
+pub struct ListenerRegistry {
+    listeners: Mutex<HashMap<String, Listener>>,
+}
+
+impl ListenerRegistry {
+    /// Add `listener`. False, and nothing's changed, if there's one by its name already.
+    pub fn add(&self, listener: Listener) -> bool {
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.contains_key(&listener.name) {
+            return false;
+        }
+        listeners.insert(listener.name.clone(), listener);
+        true
+    }
+
+    /// The entry point of the listener `name`; the default if there's no such listener.
+    pub fn entry(&self, name: &str) -> EntryPoint {
+        self.listeners
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|listener| listener.entry.clone())
+            .unwrap_or_default()
+    }
+
+    /// Every listener, by name.
+    pub fn list(&self) -> Vec<Listener> {
+        let mut listeners: Vec<_> = self.listeners.lock().unwrap().values().cloned().collect();
+        listeners.sort_by(|a, b| a.name.cmp(&b.name));
+        listeners
+    }
+}
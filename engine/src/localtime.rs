@@ -0,0 +1,58 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, Error};
+use chrono::{Locale, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// The time zone times are shown in when neither the world nor the player names one.
+pub const DEFAULT_TIME_ZONE: Tz = Tz::UTC;
+
+/// How times are shown to someone: in their time zone, in their locale's formats.
+#[derive(Clone, Debug)]
+pub struct TimeSettings {
+    pub time_zone: Tz,
+    /// A BCP 47 tag, e.g. `pt-BR`.
+    pub locale: String,
+}
+
+/// An IANA time zone name, e.g. `Europe/Lisbon`.
+pub fn parse_time_zone(name: &str) -> Result<Tz, Error> {
+    name.trim()
+        .parse::<Tz>()
+        .map_err(|e| anyhow!("{}: {}", name, e))
+}
+
+// chrono's locales are POSIX names, like pt_BR, where ours are BCP 47 tags, like pt-BR. A locale
+// it doesn't know falls back to its language's, then to POSIX.
+fn chrono_locale(locale: &str) -> Locale {
+    let posix = locale.replace('-', "_");
+    let language = posix.split('_').next().unwrap_or_default();
+    Locale::try_from(posix.as_str())
+        .or_else(|_| Locale::try_from(language))
+        .unwrap_or(Locale::POSIX)
+}
+
+/// `nanos` since the Unix epoch, as text in `style`: "date", "time" or "datetime" in the locale's
+/// own form, "iso" for RFC 3339, or a strftime pattern such as "%A %H:%M". Always in the time
+/// zone of `settings`.
+pub fn format_time(nanos: i64, style: &str, settings: &TimeSettings) -> Result<String, Error> {
+    let time = Utc
+        .timestamp_nanos(nanos)
+        .with_timezone(&settings.time_zone);
+    let pattern = match style {
+        "date" => "%x",
+        "time" => "%X",
+        "datetime" => "%c",
+        "iso" => return Ok(time.to_rfc3339()),
+        pattern if pattern.contains('%') => pattern,
+        _ => return Err(anyhow!("Unknown time style {:?}", style)),
+    };
+    let mut text = String::new();
+    write!(
+        text,
+        "{}",
+        time.format_localized(pattern, chrono_locale(&settings.locale))
+    )
+    .map_err(|_| anyhow!("Invalid time pattern {:?}", pattern))?;
+    Ok(text)
+}
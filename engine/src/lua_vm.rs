@@ -0,0 +1,691 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use mlua::{
+    AnyUserData, HookTriggers, Lua, LuaOptions, MetaMethod, MultiValue, StdLib, Table, UserData,
+    UserDataMethods, Variadic,
+};
+use tokio::runtime::Handle;
+use tracing::{info, warn};
+use tungstenite::Message;
+
+use crate::database::Tx;
+use crate::dependencies::{DependencyTxHandle, SlotAccesses};
+use crate::module_cache;
+use crate::outbound::Delivery;
+use crate::trace::Invocation;
+use crate::verb_audit::InvocationRecord;
+use crate::wasm_vm::{DryRun, ExecutionLimits, WasmVM};
+use crate::world::{
+    audit_invocation, get_slot, invoke_verb, list_slots, send_connection_message_in, set_slot,
+    World,
+};
+use value::Error::{NoError, PermissionDenied, ResourceLimit};
+use value::{encode_frame, Oid, Program, Value};
+
+/// Lua instructions run between checks of a verb's limits. Fuel is counted as one unit per
+/// instruction.
+const INSTRUCTION_SLICE: u32 = 1000;
+
+// Globals of the base library which reach outside the sandbox, or could be used to load code
+// without it being checked.
+const REMOVED_GLOBALS: &[&str] = &["dofile", "loadfile", "load", "collectgarbage", "print"];
+
+/// What a Lua verb runs against: the world, the connection its VM belongs to (if any), the
+/// transaction it acts within, and the object it was found on.
+pub struct LuaContext {
+    pub world: Arc<World>,
+    pub connection: Option<Oid>,
+    pub tx: Tx,
+    pub this: Oid,
+    /// Who invoked the verb, as the audit trail records it.
+    pub caller: Option<Oid>,
+    /// The seed for the verb's randomness, if it's executed deterministically.
+    pub seed: Option<u64>,
+}
+
+// A Value with no natural Lua form (IdKeys, Errors, Binaries and the rest), passed through Lua as
+// userdata so that it comes back unchanged.
+#[derive(Clone)]
+struct HostValue(Value);
+
+impl UserData for HostValue {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(format!("{:?}", this.0))
+        });
+        methods.add_meta_method(MetaMethod::Eq, |_, this, other: AnyUserData| {
+            let other = other.borrow::<HostValue>()?;
+            Ok(encode_frame(&this.0) == encode_frame(&other.0))
+        });
+    }
+}
+
+fn to_lua<'lua>(lua: &'lua Lua, value: &Value) -> mlua::Result<mlua::Value<'lua>> {
+    Ok(match value {
+        Value::I32(n) => mlua::Value::Integer(*n as i64),
+        Value::I64(n) => mlua::Value::Integer(*n),
+        Value::F32(n) => mlua::Value::Number(*n as f64),
+        Value::F64(n) => mlua::Value::Number(*n),
+        Value::String(text) => mlua::Value::String(lua.create_string(text.as_bytes())?),
+        Value::Vector(values) => {
+            let table = lua.create_table()?;
+            for (i, value) in values.iter().enumerate() {
+                table.raw_set(i + 1, to_lua(lua, value)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+        other => mlua::Value::UserData(lua.create_userdata(HostValue(other.clone()))?),
+    })
+}
+
+// Sequences are Vectors; any other table a Vector of [key, value] pairs.
+fn table_value(table: Table) -> mlua::Result<Value> {
+    let len = table.raw_len() as usize;
+    let pairs = table
+        .pairs::<mlua::Value, mlua::Value>()
+        .collect::<mlua::Result<Vec<_>>>()?;
+    let in_sequence =
+        |key: &mlua::Value| matches!(key, mlua::Value::Integer(i) if *i >= 1 && *i as usize <= len);
+    if pairs.len() == len && pairs.iter().all(|(key, _)| in_sequence(key)) {
+        let mut values = vec![Value::I32(0); len];
+        for (key, value) in pairs {
+            if let mlua::Value::Integer(i) = key {
+                values[i as usize - 1] = from_lua(value)?;
+            }
+        }
+        return Ok(Value::Vector(values));
+    }
+    let pairs = pairs
+        .into_iter()
+        .map(|(key, value)| Ok(Value::Vector(vec![from_lua(key)?, from_lua(value)?])))
+        .collect::<mlua::Result<_>>()?;
+    Ok(Value::Vector(pairs))
+}
+
+fn from_lua(value: mlua::Value) -> mlua::Result<Value> {
+    Ok(match value {
+        mlua::Value::Nil => Value::I32(0),
+        mlua::Value::Boolean(b) => Value::I32(b as i32),
+        mlua::Value::Integer(n) => Value::I64(n),
+        mlua::Value::Number(n) => Value::F64(n),
+        mlua::Value::String(s) => match s.to_str() {
+            Ok(text) => Value::String(text.into()),
+            Err(_) => Value::Binary(Bytes::copy_from_slice(s.as_bytes())),
+        },
+        mlua::Value::Table(table) => table_value(table)?,
+        mlua::Value::UserData(data) => data.borrow::<HostValue>()?.0.clone(),
+        other => {
+            return Err(mlua::Error::FromLuaConversionError {
+                from: other.type_name(),
+                to: "Value",
+                message: None,
+            })
+        }
+    })
+}
+
+fn invalid(builtin: &str) -> mlua::Error {
+    mlua::Error::RuntimeError(format!("Invalid arguments to '{}'", builtin))
+}
+
+// A Lua state with only the libraries which can't reach outside it.
+fn sandbox() -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8,
+        LuaOptions::new(),
+    )?;
+    {
+        let globals = lua.globals();
+        for name in REMOVED_GLOBALS {
+            globals.raw_set(*name, mlua::Value::Nil)?;
+        }
+    }
+    Ok(lua)
+}
+
+/// Check that a Lua program at least parses, as it's only compiled when it's run.
+pub fn check(program: &Program) -> Result<(), String> {
+    let lua = Lua::new_with(StdLib::NONE, LuaOptions::new()).map_err(|e| e.to_string())?;
+    lua.load(&program.code[..])
+        .into_function()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// What the builtins share while a verb runs.
+struct Host {
+    context: LuaContext,
+    handle: Handle,
+    dry_run: RefCell<Option<DryRun>>,
+    accesses: RefCell<Option<SlotAccesses>>,
+    host_calls: Cell<u64>,
+}
+
+impl Host {
+    fn call(&self) {
+        self.host_calls.set(self.host_calls.get() + 1);
+    }
+
+    fn block_on<T>(
+        &self,
+        future: impl std::future::Future<Output = Result<T, anyhow::Error>>,
+    ) -> mlua::Result<T> {
+        self.handle.block_on(future).map_err(mlua::Error::external)
+    }
+}
+
+fn oid_arg(value: &Value, builtin: &str) -> mlua::Result<Oid> {
+    match value {
+        Value::IdKey(oid) => Ok(*oid),
+        _ => Err(invalid(builtin)),
+    }
+}
+
+// The builtins, as functions in a global 'room' table, with the verb's object as 'room.this' and
+// its connection (if any) as 'room.connection'.
+fn bind_builtins(lua: &Lua, host: &Rc<Host>) -> mlua::Result<()> {
+    let room = lua.create_table()?;
+
+    let h = host.clone();
+    room.set(
+        "get_slot",
+        lua.create_function(move |lua, args: Variadic<mlua::Value>| {
+            h.call();
+            let args = args
+                .into_iter()
+                .map(from_lua)
+                .collect::<mlua::Result<Vec<_>>>()?;
+            let (oid, key, name) = match &args[..] {
+                [oid, key, Value::String(name)] => {
+                    (oid_arg(oid, "get_slot")?, oid_arg(key, "get_slot")?, name)
+                }
+                _ => return Err(invalid("get_slot")),
+            };
+            if let Some(accesses) = h.accesses.borrow_mut().as_mut() {
+                accesses.reads.insert((oid, key, name.to_string()));
+            }
+            let value = h.block_on(get_slot(
+                &h.context.world,
+                &h.context.tx,
+                h.context.connection,
+                oid,
+                key,
+                name,
+            ))?;
+            to_lua(lua, &value)
+        })?,
+    )?;
+
+    let h = host.clone();
+    room.set(
+        "list_slots",
+        lua.create_function(move |lua, args: Variadic<mlua::Value>| {
+            h.call();
+            let args = args
+                .into_iter()
+                .map(from_lua)
+                .collect::<mlua::Result<Vec<_>>>()?;
+            let (oid, key) = match &args[..] {
+                [oid, key] => (oid_arg(oid, "list_slots")?, oid_arg(key, "list_slots")?),
+                _ => return Err(invalid("list_slots")),
+            };
+            if let Some(accesses) = h.accesses.borrow_mut().as_mut() {
+                accesses.reads.insert((oid, key, String::new()));
+            }
+            let value = h.block_on(list_slots(&h.context.tx, oid, key))?;
+            to_lua(lua, &value)
+        })?,
+    )?;
+
+    let h = host.clone();
+    room.set(
+        "set_slot",
+        lua.create_function(move |lua, args: Variadic<mlua::Value>| {
+            h.call();
+            let args = args
+                .into_iter()
+                .map(from_lua)
+                .collect::<mlua::Result<Vec<_>>>()?;
+            let (oid, key, name, value) = match &args[..] {
+                [oid, key, Value::String(name), value] => (
+                    oid_arg(oid, "set_slot")?,
+                    oid_arg(key, "set_slot")?,
+                    name,
+                    value,
+                ),
+                _ => return Err(invalid("set_slot")),
+            };
+            let world = &h.context.world;
+            let written = h.block_on(set_slot(
+                world,
+                &h.context.tx,
+                h.context.connection,
+                oid,
+                key,
+                name,
+                value,
+            ))?;
+            let result = match written {
+                Value::Error(NoError) => {
+                    if let Some(accesses) = h.accesses.borrow_mut().as_mut() {
+                        accesses.writes.insert((oid, key, name.to_string()));
+                    }
+                    let module_cache = h.context.world.module_cache();
+                    h.handle.block_on(module_cache.slot_written(oid, key, name));
+                    if let Some(dry_run) = h.dry_run.borrow_mut().as_mut() {
+                        dry_run.writes.push(Value::Vector(vec![
+                            Value::IdKey(oid),
+                            Value::IdKey(key),
+                            Value::String(name.clone()),
+                            value.clone(),
+                        ]));
+                    }
+                    Value::I32(0)
+                }
+                refused => refused,
+            };
+            to_lua(lua, &result)
+        })?,
+    )?;
+
+    let h = host.clone();
+    room.set(
+        "invoke",
+        lua.create_function(move |lua, args: Variadic<mlua::Value>| {
+            h.call();
+            let args = args
+                .into_iter()
+                .map(from_lua)
+                .collect::<mlua::Result<Vec<_>>>()?;
+            let (oid, verb, arguments) = match &args[..] {
+                [oid, Value::String(verb), Value::Vector(arguments)] => {
+                    (oid_arg(oid, "invoke")?, verb, arguments)
+                }
+                _ => return Err(invalid("invoke")),
+            };
+            if h.dry_run.borrow().is_some() {
+                return to_lua(lua, &Value::Error(PermissionDenied));
+            }
+            // The verb invoked may be WebAssembly, so it's run by a VM of its own.
+            let world = &h.context.world;
+            let vm = Arc::new(
+                WasmVM::new(world.clone(), h.context.connection).map_err(mlua::Error::external)?,
+            );
+            vm.clone().bind_builtins().map_err(mlua::Error::external)?;
+            vm.set_deterministic(h.context.seed.is_some());
+            vm.set_caller(Some(h.context.this));
+            let value = h.block_on(invoke_verb(world, &h.context.tx, vm, oid, verb, arguments))?;
+            to_lua(lua, &value)
+        })?,
+    )?;
+
+    let h = host.clone();
+    room.set(
+        "send",
+        lua.create_function(move |_, args: Variadic<mlua::Value>| {
+            h.call();
+            let args = args
+                .into_iter()
+                .map(from_lua)
+                .collect::<mlua::Result<Vec<_>>>()?;
+            let (connection, message) = match &args[..] {
+                [connection, Value::String(text)] => (
+                    oid_arg(connection, "send")?,
+                    Message::Text(text.to_string()),
+                ),
+                [connection, Value::Binary(bytes)] => (
+                    oid_arg(connection, "send")?,
+                    Message::Binary(bytes.to_vec()),
+                ),
+                _ => return Err(invalid("send")),
+            };
+            let delivery = match h.dry_run.borrow_mut().as_mut() {
+                Some(dry_run) => {
+                    dry_run.messages.push(Value::Vector(vec![
+                        Value::IdKey(connection),
+                        args[1].clone(),
+                    ]));
+                    Delivery::Queued
+                }
+                None => h.block_on(send_connection_message_in(
+                    h.context.world.clone(),
+                    &h.context.tx,
+                    connection,
+                    message,
+                ))?,
+            };
+            Ok(delivery.code())
+        })?,
+    )?;
+
+    let h = host.clone();
+    room.set(
+        "log",
+        lua.create_function(move |_, args: Variadic<mlua::Value>| {
+            h.call();
+            let args = args
+                .into_iter()
+                .map(from_lua)
+                .collect::<mlua::Result<Vec<_>>>()?;
+            let redaction = h.context.world.redaction();
+            let shown: Vec<_> = args.iter().map(|a| redaction.value(a)).collect();
+            info!("Log: {:?}", shown);
+            Ok(0)
+        })?,
+    )?;
+
+    room.set("this", to_lua(lua, &Value::IdKey(host.context.this))?)?;
+    if let Some(connection) = host.context.connection {
+        room.set("connection", to_lua(lua, &Value::IdKey(connection))?)?;
+    }
+    lua.globals().set("room", room)
+}
+
+// What running a verb came to, passed back from the thread it ran on.
+struct Outcome {
+    result: Result<Value, anyhow::Error>,
+    dry_run: Option<DryRun>,
+    accesses: Option<SlotAccesses>,
+    instructions: u64,
+    host_calls: u64,
+}
+
+// Lets a verb's thread know it's been abandoned, as its task was killed, so that it stops at its
+// next check of its limits.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// What a verb's limits are checked against: the instructions it's run, whether it's gone over its
+// limits, and whether it's been killed.
+struct Meter {
+    instructions: Rc<Cell<u64>>,
+    exceeded: Rc<Cell<bool>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+// Lua's pcall and xpcall, wrapped so that errors raised because the verb is over its limits or has
+// been killed are raised again rather than caught, and the verb can't carry on regardless.
+const GUARDED_PCALL: &str = r#"
+local pcall, xpcall, error, tripped = ...
+local function checked(ok, ...)
+    if not ok and tripped((...)) then
+        error((...), 0)
+    end
+    return ok, ...
+end
+return function(f, ...) return checked(pcall(f, ...)) end,
+    function(f, handler, ...) return checked(xpcall(f, handler, ...)) end
+"#;
+
+// Hold a Lua state to `limits`: stop it once it's run out of fuel (if `metered`) or time, or has
+// been cancelled, every INSTRUCTION_SLICE instructions, and refuse it memory beyond its limit.
+fn limit(lua: &Lua, limits: ExecutionLimits, metered: bool, meter: Meter) -> mlua::Result<()> {
+    lua.set_memory_limit(limits.memory)?;
+    let started = Instant::now();
+    let (counted, over, cancelled) = (
+        meter.instructions,
+        meter.exceeded.clone(),
+        meter.cancelled.clone(),
+    );
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(INSTRUCTION_SLICE),
+            ..Default::default()
+        },
+        move |_, _| {
+            let used = counted.get() + INSTRUCTION_SLICE as u64;
+            counted.set(used);
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(mlua::Error::RuntimeError("Killed".to_string()));
+            }
+            let exhausted = match metered {
+                true => used >= limits.fuel,
+                false => started.elapsed() >= limits.time,
+            };
+            if exhausted {
+                over.set(true);
+                return Err(mlua::Error::RuntimeError("Out of resources".to_string()));
+            }
+            Ok(())
+        },
+    )?;
+
+    // Lua raises this when it's refused memory.
+    const OUT_OF_MEMORY: &[u8] = b"not enough memory";
+    let (over, cancelled) = (meter.exceeded, meter.cancelled);
+    let tripped = lua.create_function(move |_, error: mlua::Value| {
+        if matches!(&error, mlua::Value::String(message) if message.as_bytes() == OUT_OF_MEMORY) {
+            over.set(true);
+        }
+        Ok(over.get() || cancelled.load(Ordering::Relaxed))
+    })?;
+    let globals = lua.globals();
+    let (pcall, xpcall): (mlua::Function, mlua::Function) = lua.load(GUARDED_PCALL).call((
+        globals.get::<_, mlua::Function>("pcall")?,
+        globals.get::<_, mlua::Function>("xpcall")?,
+        globals.get::<_, mlua::Function>("error")?,
+        tripped,
+    ))?;
+    globals.raw_set("pcall", pcall)?;
+    globals.raw_set("xpcall", xpcall)?;
+    Ok(())
+}
+
+fn run(
+    host: Host,
+    code: bytes::Bytes,
+    args: Value,
+    limits: ExecutionLimits,
+    metered: bool,
+    cancelled: Arc<AtomicBool>,
+) -> Outcome {
+    let host = Rc::new(host);
+    let instructions = Rc::new(Cell::new(0u64));
+    let exceeded = Rc::new(Cell::new(false));
+    let result = (|| -> mlua::Result<Value> {
+        let lua = sandbox()?;
+        // Deterministic verbs' math.random is seeded from the invocation.
+        if let Some(seed) = host.context.seed {
+            let math: mlua::Table = lua.globals().get("math")?;
+            let randomseed: mlua::Function = math.get("randomseed")?;
+            randomseed.call::<_, ()>(seed as i64)?;
+        }
+        let meter = Meter {
+            instructions: instructions.clone(),
+            exceeded: exceeded.clone(),
+            cancelled,
+        };
+        limit(&lua, limits, metered, meter)?;
+        bind_builtins(&lua, &host)?;
+        let function = lua.load(&code[..]).into_function()?;
+        let args = match args {
+            Value::Vector(args) => args,
+            other => vec![other],
+        };
+        let args = args
+            .iter()
+            .map(|arg| to_lua(&lua, arg))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let results: MultiValue = function.call(MultiValue::from_vec(args))?;
+        match results.into_iter().next() {
+            Some(result) => from_lua(result),
+            None => Ok(Value::I32(0)),
+        }
+    })();
+    let result = match result {
+        Ok(value) => Ok(value),
+        Err(e) if exceeded.get() => {
+            warn!("Lua verb exceeded its limits: {}", e);
+            Ok(Value::Error(ResourceLimit))
+        }
+        Err(mlua::Error::MemoryError(e)) => {
+            warn!(
+                "Lua verb exceeded its memory limit of {}: {}",
+                limits.memory, e
+            );
+            Ok(Value::Error(ResourceLimit))
+        }
+        Err(e) => Err(anyhow!("Lua error: {}", e)),
+    };
+    let dry_run = host.dry_run.borrow_mut().take();
+    let accesses = host.accesses.borrow_mut().take();
+    Outcome {
+        result,
+        dry_run,
+        accesses,
+        instructions: instructions.get(),
+        host_calls: host.host_calls.get(),
+    }
+}
+
+/// Run the Lua `method`, found as `verb` on `context.this`, with `args`, as `WasmVM::execute`
+/// runs WebAssembly: within the same transaction, with the same builtins for slots, messages and
+/// invoking other verbs, and within the same limits. The Lua state is made afresh for each
+/// invocation, on a thread of its own, as Lua can't yield to the runtime.
+pub async fn execute(
+    context: LuaContext,
+    verb: &str,
+    method: &Program,
+    args: &Value,
+    limits: ExecutionLimits,
+    dry_run: Option<DryRun>,
+) -> Result<(Value, Option<DryRun>), anyhow::Error> {
+    let world = context.world.clone();
+    let tx = context.tx.clone();
+    let (this, caller) = (context.this, context.caller);
+    let audited = dry_run.is_none();
+    let digest = module_cache::digest(method);
+    let metered = world.module_cache().preemption() == module_cache::Preemption::Fuel;
+    let host = Host {
+        accesses: RefCell::new(world.records_dependencies().then(SlotAccesses::default)),
+        context,
+        handle: Handle::current(),
+        dry_run: RefCell::new(dry_run),
+        host_calls: Cell::new(0),
+    };
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let _cancel = CancelOnDrop(cancelled.clone());
+    let (code, arguments) = (method.code.clone(), args.clone());
+    let started = Instant::now();
+    let outcome =
+        tokio::task::spawn_blocking(move || run(host, code, arguments, limits, metered, cancelled))
+            .await?;
+
+    world.tracer().record(&Invocation {
+        oid: this,
+        verb: verb.to_string(),
+        module: digest,
+        wall_time: started.elapsed(),
+        fuel: outcome.instructions,
+        host_calls: outcome.host_calls,
+        failed: outcome.result.is_err(),
+    });
+    if let Some(accesses) = &outcome.accesses {
+        DependencyTxHandle::new(&tx).record(&digest, (this, verb), accesses);
+    }
+    if audited {
+        let record = InvocationRecord::new(
+            caller,
+            (this, verb),
+            args,
+            &outcome.result,
+            outcome.instructions,
+        );
+        audit_invocation(&world, &tx, record);
+    }
+    Ok((outcome.result?, outcome.dry_run))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ExecutionLimits = ExecutionLimits {
+        fuel: 1_000_000,
+        time: std::time::Duration::from_secs(10),
+        memory: 4 << 20,
+    };
+
+    // A sandbox held to LIMITS by fuel, with its meter's instructions and whether it was exceeded.
+    fn limited(cancelled: bool) -> (Lua, Rc<Cell<u64>>, Rc<Cell<bool>>) {
+        let lua = sandbox().unwrap();
+        let meter = Meter {
+            instructions: Rc::new(Cell::new(0)),
+            exceeded: Rc::new(Cell::new(false)),
+            cancelled: Arc::new(AtomicBool::new(cancelled)),
+        };
+        let (instructions, exceeded) = (meter.instructions.clone(), meter.exceeded.clone());
+        limit(&lua, LIMITS, true, meter).unwrap();
+        (lua, instructions, exceeded)
+    }
+
+    #[test]
+    fn looping_pcall_runs_out_of_fuel() {
+        let (lua, instructions, exceeded) = limited(false);
+        let looping = "while true do pcall(function() while true do end end) end";
+        assert!(lua.load(looping).exec().is_err());
+        assert!(exceeded.get());
+        assert!(instructions.get() <= LIMITS.fuel + INSTRUCTION_SLICE as u64);
+    }
+
+    #[test]
+    fn looping_pcall_is_killed() {
+        let (lua, instructions, exceeded) = limited(true);
+        let looping = "while true do pcall(function() while true do end end) end";
+        assert!(lua.load(looping).exec().is_err());
+        assert!(!exceeded.get());
+        assert_eq!(instructions.get(), INSTRUCTION_SLICE as u64);
+    }
+
+    #[test]
+    fn xpcall_and_its_handler_are_stopped() {
+        let (lua, _, exceeded) = limited(false);
+        let looping =
+            "while true do xpcall(function() while true do end end, function(e) return e end) end";
+        assert!(lua.load(looping).exec().is_err());
+        assert!(exceeded.get());
+
+        let (lua, _, exceeded) = limited(false);
+        let handler_loops = "xpcall(error, function() while true do end end)";
+        assert!(lua.load(handler_loops).exec().is_err());
+        assert!(exceeded.get());
+    }
+
+    #[test]
+    fn running_out_of_memory_in_pcall_is_not_caught() {
+        let (lua, instructions, _) = limited(false);
+        let hoarding = "local t = {}
+            local function hoard() t[#t + 1] = string.rep('x', 4096) .. #t end
+            while true do pcall(hoard) end";
+        assert!(lua.load(hoarding).exec().is_err());
+        assert!(instructions.get() < LIMITS.fuel);
+    }
+
+    #[test]
+    fn other_errors_are_still_caught() {
+        let (lua, _, exceeded) = limited(false);
+        let (ok, message): (bool, String) =
+            lua.load("return pcall(error, 'oops', 0)").eval().unwrap();
+        assert_eq!((ok, message.as_str()), (false, "oops"));
+        let handled = "return xpcall(error, function(e) return 'handled ' .. e end, 'oops', 0)";
+        let (ok, message): (bool, String) = lua.load(handled).eval().unwrap();
+        assert_eq!((ok, message.as_str()), (false, "handled oops"));
+        let (ok, value): (bool, i64) = lua
+            .load("return pcall(function(n) return n + 1 end, 1)")
+            .eval()
+            .unwrap();
+        assert_eq!((ok, value), (true, 2));
+        assert!(!exceeded.get());
+    }
+}
@@ -1,121 +1,1176 @@
-use std::{error::Error, net::SocketAddr, sync::Arc};
+use std::{error::Error, net::IpAddr, path::Path, sync::Arc, time::Duration};
 
-use bytes::Bytes;
 use clap::Parser;
-use futures::{future, pin_mut, StreamExt};
-use futures_channel::mpsc::unbounded;
-use log::*;
-use tokio::net::{TcpListener, TcpStream};
-
-use tokio_tungstenite::accept_async;
-use tungstenite::{Message, Result};
+use crossterm::tty::IsTty;
+use futures::future;
+use regex::Regex;
+use sd_notify::NotifyState;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 use uuid::Uuid;
-use value::Oid;
+use value::{Oid, Value};
 
-use crate::world::{
-    bootstrap_world, disconnect, load, receive_connection_message, register_connection, save, World,
+use crate::console::ConsoleLog;
+use room::auth::AuthPolicy;
+use room::bandwidth::{BandwidthAction, BandwidthPolicy, FloodPolicy};
+use room::changes::{ChangeJournalOptions, Versionstamp};
+use room::command::CommandGrammar;
+use room::config::{Config, StorageBackend, DEFAULT_CONFIG_PATH};
+use room::core::Core;
+use room::database::RetryPolicy;
+use room::doctor::{run_checks, CheckStatus, DoctorOptions};
+use room::dump::DumpTarget;
+#[cfg(feature = "faults")]
+use room::faults::FaultOptions;
+use room::federation::{FederationOptions, PeerOptions};
+use room::gc::GcOptions;
+use room::hooks::{LifecyclePoint, WasmHooks};
+use room::journal::{JournalOptions, JournalPrivacy};
+use room::listeners::{read_listener_configs, ListenerConfig, Transport};
+use room::localtime::parse_time_zone;
+use room::module_cache::Preemption;
+use room::net::admin::AdminOptions;
+use room::net::origin::OriginPolicy;
+use room::net::proxy::ProxyOptions;
+use room::net::systemd::{self, Listeners, WEBSOCKET_LISTENER};
+use room::object_store::{parse_s3_url, ObjectStore, ObjectStoreOptions, ServerSideEncryption};
+use room::operations::{inspect_archive, run_operation, Archive, Operation};
+use room::outbound::{OutboundPolicy, OverflowAction};
+use room::preload::PreloadManifest;
+use room::quota::QuotaPolicy;
+use room::redact::RedactionPolicy;
+use room::refactor::Refactor;
+use room::resume::ResumeOptions;
+use room::retention::RetentionPolicy;
+use room::verb_audit::VerbAuditOptions;
+use room::world::{
+    bootstrap_world, forget_sessions, install_core, load, preload, run_hooks, save, save_all,
+    ErasureMode, World, WorldOptions,
 };
+use room::{net, world};
 
-pub mod fdb_object;
-pub mod object;
-pub mod world;
-
-pub mod wasm_vm;
+mod console;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Listen address to bind the websocket server to.
-    #[clap(short, long, default_value = "127.0.0.1:9002")]
-    listen_address: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// TOML configuration file, whose settings (listen_address, storage, dump_path, fuel_limit and
+    /// so on, named as the flags are) the flags here override. room.toml is read if there is one
+    /// and no other is given.
+    #[clap(long)]
+    config: Option<String>,
+
+    /// Listen address to bind the websocket server to. [default: 127.0.0.1:9002]
+    #[clap(short, long)]
+    listen_address: Option<String>,
+
+    /// Origin a browser may open websocket connections from, e.g. https://example.com. May be given
+    /// more than once. If none are given, any origin is allowed.
+    #[clap(long = "allowed-origin")]
+    allowed_origins: Vec<String>,
+
+    /// Host header websocket connections must be made to. May be given more than once, with or
+    /// without a port. If none are given, any host is allowed.
+    #[clap(long = "allowed-host")]
+    allowed_hosts: Vec<String>,
+
+    /// Expect connections to begin with a PROXY protocol v2 header from a load balancer, and record
+    /// the client address it gives. Connections without one are refused.
+    #[clap(long)]
+    proxy_protocol: bool,
+
+    /// Address of a reverse proxy whose X-Forwarded-For headers on websocket connections are
+    /// trusted. May be given more than once.
+    #[clap(long = "trusted-proxy")]
+    trusted_proxies: Vec<IpAddr>,
+
+    /// Address to accept plain TCP (telnet) connections on, in addition to websockets.
+    #[clap(long)]
+    telnet_address: Option<String>,
+
+    /// JSON file of further listeners: an array of {"name", "address", "transport", "object",
+    /// "verb"}, each giving the messages of its connections to the verb on the object it names
+    /// rather than to the system object's 'receive'. Transport is "websocket" (the default) or
+    /// "telnet".
+    #[clap(long)]
+    listeners: Option<String>,
+
+    /// Address to serve Prometheus metrics on, at /metrics, and who's logged in, at /sessions.
+    #[clap(long)]
+    metrics_address: Option<String>,
+
+    /// Address to serve the admin API on: listing connections, inspecting objects, evicting
+    /// compiled modules, forcing a checkpoint and booting connections.
+    #[clap(long)]
+    admin_address: Option<String>,
+
+    /// File holding the token admin API requests must bear.
+    #[clap(long)]
+    admin_token_file: Option<String>,
+
+    /// IANA time zone, e.g. Europe/Lisbon, that times are shown to players in unless their
+    /// 'time_zone' slot names another. UTC if not given.
+    #[clap(long)]
+    time_zone: Option<String>,
+
+    /// Locale, e.g. pt-BR, that text and times are given in to players who haven't chosen one and
+    /// whose clients don't ask for one.
+    #[clap(long)]
+    default_locale: Option<String>,
+
+    /// Ping websocket connections this often, in seconds, so that those which have gone away are
+    /// noticed.
+    #[clap(long)]
+    ping_interval_secs: Option<u64>,
+
+    /// Close connections which have sent nothing (pongs included) for this many seconds, after
+    /// running the system object's 'on_idle_disconnect' verb.
+    #[clap(long)]
+    idle_timeout_secs: Option<u64>,
+
+    /// What this server is called in the records of who's logged in to it, to tell it apart from
+    /// others sharing the database. Required with FoundationDB.
+    #[clap(long)]
+    node_name: Option<String>,
+
+    /// Most bytes per second each connection may send, averaged over a second. Uncapped if not
+    /// given.
+    #[clap(long)]
+    max_inbound_bytes_per_sec: Option<u64>,
+
+    /// Most bytes per second each connection may be sent, averaged over a second. Uncapped if not
+    /// given.
+    #[clap(long)]
+    max_outbound_bytes_per_sec: Option<u64>,
+
+    /// What to do with connections which go over their bandwidth caps.
+    #[clap(long, value_enum, default_value = "throttle")]
+    over_bandwidth: OverBandwidthKind,
+
+    /// Messages which may be queued for each connection, waiting for its client to read them.
+    #[clap(long, default_value = "1024")]
+    outbound_queue: usize,
+
+    /// What to do with messages for connections whose queues are full.
+    #[clap(long, value_enum, default_value = "refuse")]
+    outbound_overflow: OutboundOverflowKind,
+
+    /// How many messages from each connection may be handled at once; those after wait for one
+    /// to finish.
+    #[clap(long, default_value = "4")]
+    tasks_per_connection: usize,
+
+    /// Most messages per second each connection may send, in bursts of up to a second's worth.
+    /// Messages over the limit are dropped, and the system object's 'on_flood' verb is run.
+    /// Unlimited if not given.
+    #[clap(long)]
+    max_messages_per_sec: Option<u64>,
+
+    /// Most bytes per second each connection may send in messages, as --max-messages-per-sec
+    /// limits their number. Unlimited if not given.
+    #[clap(long)]
+    max_message_bytes_per_sec: Option<u64>,
+
+    /// How many messages in a row a connection may have dropped for flooding before it's closed.
+    #[clap(long, default_value = "20")]
+    max_flood_strikes: u32,
+
+    /// How many times a transaction which conflicts with others, or times out, is retried before
+    /// it's given up on. Verbs whose transactions are given up on get Contended. 0 to retry
+    /// forever.
+    #[clap(long, default_value = "50")]
+    tx_max_retries: u32,
+
+    /// Longest wait before retrying a transaction. The waits start at 2ms and double with each
+    /// retry, jittered so that transactions which conflicted don't retry in step. With
+    /// FoundationDB, which waits by itself before retrying conflicts, only timeouts wait this.
+    #[clap(long, default_value = "1000")]
+    tx_max_backoff_ms: u64,
+
+    /// Inject faults at random, to exercise retry and cleanup paths when testing. Never use this on
+    /// a real world. The rates below are probabilities from 0 to 1. Only built with the "faults"
+    /// feature.
+    #[cfg(feature = "faults")]
+    #[clap(long)]
+    inject_faults: bool,
+
+    /// Rate at which to delay transactions, by up to --fault-tx-max-delay-ms.
+    #[cfg(feature = "faults")]
+    #[clap(long, default_value = "0")]
+    fault_tx_delay_rate: f64,
+
+    /// Longest delay to inject into a transaction, in milliseconds.
+    #[cfg(feature = "faults")]
+    #[clap(long, default_value = "100")]
+    fault_tx_max_delay_ms: u64,
+
+    /// Rate at which to fail transactions with a retryable error.
+    #[cfg(feature = "faults")]
+    #[clap(long, default_value = "0")]
+    fault_tx_fail_rate: f64,
+
+    /// Rate at which to drop messages sent to connections.
+    #[cfg(feature = "faults")]
+    #[clap(long, default_value = "0")]
+    fault_frame_drop_rate: f64,
+
+    /// Rate at which to trap verb invocations.
+    #[cfg(feature = "faults")]
+    #[clap(long, default_value = "0")]
+    fault_trap_rate: f64,
+
+    /// Send the return value of the 'receive' verb back to the connection as a message.
+    #[clap(long)]
+    echo_results: bool,
+
+    /// Log a line, to the 'trace' target, with the wall time, fuel and builtin calls of each verb
+    /// invocation as it finishes.
+    #[clap(long)]
+    trace_verbs: bool,
+
+    /// How log lines are written: as text for people, or as a JSON object per line for log
+    /// pipelines. Either way, which are written is set by RUST_LOG.
+    #[clap(long, value_enum, default_value = "text")]
+    log_format: LogFormat,
+
+    /// When stdout is a terminal, show an interactive console on it while serving: the server's
+    /// status, the recent log (as text, whatever --log-format says) and a prompt for admin
+    /// commands.
+    #[clap(long)]
+    console: bool,
+
+    /// Count the slots each verb invocation reads and writes, by program, for the `dependencies`
+    /// report. Every invocation then writes its counts along with its transaction.
+    #[clap(long)]
+    record_dependencies: bool,
+
+    /// Index which slots refer to which objects as they're set, so that verbs can ask what still
+    /// points at an object with `find_references`. Slots set before it was on are found once
+    /// `index-references` has been run.
+    #[clap(long)]
+    reference_index: bool,
+
+    /// An object garbage collection keeps, with everything reachable from it, besides the system
+    /// object and whatever's connected. May be given more than once.
+    #[clap(long = "gc-root")]
+    gc_roots: Vec<Uuid>,
+
+    /// Every this many seconds, collect garbage: find the objects which can't be reached from the
+    /// roots. Only when an admin asks, with `POST /gc`, if not given.
+    #[clap(long)]
+    gc_interval_secs: Option<u64>,
+
+    /// Have the garbage collection which runs every --gc-interval-secs clear what it finds, rather
+    /// than just logging it. Needs --reference-index.
+    #[clap(long)]
+    gc_clear: bool,
+
+    /// Journal every change to a slot, in the order the changes took effect, for replication,
+    /// indexers and watches to follow with the admin API's `/changes`.
+    #[clap(long)]
+    change_journal: bool,
+
+    /// Days to retain journaled changes for.
+    #[clap(long, default_value = "7")]
+    change_retention_days: u64,
+
+    /// Another world to link to, as name=url, e.g. east=wss://east.example.org:8080. Verbs on its
+    /// objects are invoked there. May be given more than once.
+    #[clap(long = "peer")]
+    peers: Vec<String>,
+
+    /// The hex prefix, as name=prefix, of the uuids of a peer's objects. Objects without one are
+    /// only the peer's if the system object's 'peers' slot lists them. May be given more than
+    /// once.
+    #[clap(long = "peer-prefix")]
+    peer_prefixes: Vec<String>,
+
+    /// File holding the token presented to peers, and required of worlds connecting here as
+    /// peers.
+    #[clap(long)]
+    federation_token_file: Option<String>,
+
+    /// Directory of <locale>.json files translating the text sent to clients with error codes,
+    /// e.g. fr.json. Clients' locales are taken from their Accept-Language headers.
+    #[clap(long)]
+    catalog: Option<String>,
+
+    /// Storage backend to keep the world in. [default: fdb]
+    #[clap(long, value_enum)]
+    storage: Option<StorageKind>,
+
+    /// Directory for the embedded storage backend's database. [default: room.db]
+    #[clap(long)]
+    storage_path: Option<String>,
+
+    /// FoundationDB cluster file. Defaults to FDB_CLUSTER_FILE if that's set, and otherwise to
+    /// /etc/foundationdb/fdb.cluster.
+    #[clap(long)]
+    fdb_cluster_file: Option<String>,
+
+    /// Directory to load the world from at startup, and dump it to on shutdown. [default: dump]
+    #[clap(long)]
+    dump_path: Option<String>,
+
+    /// Bytes of a string or binary value shown in log lines before it's cut short.
+    #[clap(long, default_value = "64")]
+    log_max_bytes: usize,
+
+    /// Name of a slot whose values, or verb whose arguments, are masked in log lines, in addition
+    /// to 'password', 'secret' and 'token'. May be given more than once.
+    #[clap(long = "sensitive-slot")]
+    sensitive_slots: Vec<String>,
+
+    /// Refuse to start from a dump directory whose files don't all match the digests in its
+    /// manifest, rather than loading what can be loaded.
+    #[clap(long)]
+    strict_load: bool,
+
+    /// Read lines of text from logged in players as commands ("put lamp on table"), and run the
+    /// 'cmd_' verb they name, before giving them to 'receive'.
+    #[clap(long)]
+    command_parser: bool,
+
+    /// Preposition for the command parser, replacing the default set. May be given more than once.
+    #[clap(long = "preposition")]
+    prepositions: Vec<String>,
+
+    /// Every this many seconds, dump the objects written since the last time, so that a crash
+    /// doesn't lose everything since startup. Shutdown then dumps every object, not just the
+    /// system object.
+    #[clap(long)]
+    checkpoint_interval: Option<u64>,
+
+    /// Directory holding a core (a core.json manifest and the programs it names) to bootstrap new
+    /// worlds from, instead of the built in minimal one. Ignored if there's a dump to load.
+    #[clap(long)]
+    core: Option<String>,
+
+    /// Preload manifest: objects whose slots to read, and verbs to compile, before accepting
+    /// connections. Its verbs are replaced with the hottest ones on shutdown.
+    #[clap(long)]
+    preload: Option<String>,
+
+    /// Hooks file, naming WASM programs to run at points in the server's lifecycle: post_load,
+    /// post_bootstrap, pre_listen and pre_save.
+    #[clap(long)]
+    hooks: Option<String>,
+
+    /// Journal messages sent to connections, for auditing disputes.
+    #[clap(long)]
+    journal: bool,
+
+    /// How much of each message to record in the journal.
+    #[clap(long, value_enum, default_value = "full")]
+    journal_privacy: JournalPrivacyKind,
+
+    /// Days to retain journal entries for.
+    #[clap(long, default_value = "30")]
+    journal_retention_days: u64,
+
+    /// Objects each player may own, unless their 'object_quota' slot (under the system object's
+    /// key) says otherwise. Unlimited if not given.
+    #[clap(long)]
+    quota_objects: Option<u64>,
+
+    /// Bytes each player may store in the slots of the objects they own, unless their
+    /// 'byte_quota' slot (under the system object's key) says otherwise. Unlimited if not given.
+    #[clap(long)]
+    quota_bytes: Option<u64>,
+
+    /// Record every verb invocation on an audit trail, in the same transaction as it, for
+    /// investigating abuse. Query it with the admin API's `/audit`.
+    #[clap(long)]
+    audit_verbs: bool,
+
+    /// Days to retain verb audit records for.
+    #[clap(long, default_value = "90")]
+    audit_retention_days: u64,
+
+    /// Keep websocket connections which drop for this many seconds, buffering what's sent to
+    /// them, so that their clients can reconnect with their resume tokens and carry on where they
+    /// left off. Connections are disconnected as soon as they drop if not given.
+    #[clap(long)]
+    resume_grace_secs: Option<u64>,
+
+    /// Export all data associated with this player Oid, then exit.
+    #[clap(long)]
+    export_player: Option<Uuid>,
+
+    /// File to write --export-player's archive to.
+    #[clap(long, default_value = "export.json")]
+    export_path: String,
+
+    /// Erase all data associated with this player Oid, print a report of what was removed, then
+    /// exit.
+    #[clap(long)]
+    erase_player: Option<Uuid>,
+
+    /// With --erase-player, keep the player's journal entries with their identity and content
+    /// stripped rather than deleting them.
+    #[clap(long)]
+    erase_anonymize: bool,
+
+    /// Print the usage counters kept for this player Oid as JSON, then exit.
+    #[clap(long)]
+    player_stats: Option<Uuid>,
+
+    /// Print the Oids of every object with this tag as JSON, then exit.
+    #[clap(long)]
+    tag_query: Option<String>,
+
+    /// Print the builtins verbs can call, with their signatures and descriptions, as JSON, then
+    /// exit.
+    #[clap(long)]
+    list_builtins: bool,
+
+    /// Failed logins to an account, or from an address, allowed within --login-window-secs before
+    /// further attempts are locked out with an increasing backoff.
+    #[clap(long, default_value = "5")]
+    login_free_attempts: u32,
+
+    /// Window over which failed logins are counted.
+    #[clap(long, default_value = "900")]
+    login_window_secs: u64,
+
+    /// Longest a login lockout can last.
+    #[clap(long, default_value = "900")]
+    login_max_lockout_secs: u64,
+
+    /// Memory to allow for compiled programs shared between connections, in megabytes.
+    #[clap(long, default_value = "256")]
+    module_cache_mb: u64,
+
+    /// Fuel (roughly, WebAssembly instructions) each verb invocation may consume before it's
+    /// aborted. Objects can override this for their own verbs with a 'fuel_limit' slot.
+    /// [default: 100000000]
+    #[clap(long)]
+    fuel_limit: Option<u64>,
+
+    /// Memory each verb invocation may use, in megabytes. Objects can override this for their own
+    /// verbs with a 'memory_limit' slot, in bytes. [default: 64]
+    #[clap(long)]
+    memory_limit_mb: Option<usize>,
+
+    /// How running verbs are preempted, and so limited: by the fuel they consume
+    /// (--fuel-limit), or by the engine's epoch (--time-limit-ms), which is cheaper.
+    #[clap(long, value_enum, default_value = "fuel")]
+    preemption: PreemptionKind,
+
+    /// Time each verb invocation may take before it's aborted, with --preemption epoch. Objects
+    /// can override this for their own verbs with a 'time_limit_ms' slot. [default: 5000]
+    #[clap(long)]
+    time_limit_ms: Option<u64>,
+
+    /// Dump to and load from this S3 bucket instead of --dump-path.
+    #[clap(long)]
+    s3_bucket: Option<String>,
+
+    /// Region of --s3-bucket.
+    #[clap(long, default_value = "us-east-1")]
+    s3_region: String,
+
+    /// Endpoint URL for S3-compatible object stores other than AWS.
+    #[clap(long)]
+    s3_endpoint: Option<String>,
+
+    /// Key prefix for snapshots within --s3-bucket.
+    #[clap(long, default_value = "room/")]
+    s3_prefix: String,
+
+    /// Server-side encryption to request for snapshots.
+    #[clap(long, value_enum)]
+    s3_encryption: Option<EncryptionKind>,
+
+    /// KMS key to encrypt snapshots with, when --s3-encryption=kms. Defaults to the account key.
+    #[clap(long)]
+    s3_kms_key_id: Option<String>,
+
+    /// Number of snapshots to retain in --s3-bucket. Older snapshots are deleted after each save,
+    /// but for those the options below keep.
+    #[clap(long)]
+    s3_keep: Option<usize>,
+
+    /// Also retain the most recent snapshot in each of this many hours.
+    #[clap(long, default_value = "0")]
+    s3_keep_hourly: usize,
+
+    /// Also retain the most recent snapshot in each of this many days.
+    #[clap(long, default_value = "0")]
+    s3_keep_daily: usize,
+
+    /// Also retain the most recent snapshot in each of this many weeks.
+    #[clap(long, default_value = "0")]
+    s3_keep_weekly: usize,
 }
 
-async fn handle_message(conn_oid: Oid, msg: Result<Message>, world: Arc<world::World>) {
-    match msg {
-        Ok(m) => {
-            if m.is_text() || m.is_binary() {
-                // Consume message and pass off to receive..
-                let message = Bytes::from(m.into_data());
+/// Whole-world operations which run and exit rather than serving the world.
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Check that the server could start here: that the database can be reached, the dump reads
+    /// back and its system verbs compile, the listen addresses are free and the dump directory is
+    /// writable, printing what to do about anything that isn't.
+    Doctor,
+    /// Write every object in the world to a single archive.
+    Dump {
+        /// Archive to write, e.g. world.tar.zst.
+        #[clap(long)]
+        out: String,
+    },
+    /// Restore a world written by `dump` into an empty database, then dump it to --dump-path (or
+    /// --s3-bucket) so that the server starts from it.
+    Load {
+        /// Archive to read.
+        #[clap(long = "in")]
+        input: String,
+    },
+    /// Stream an archive of the world, as `dump` writes, to S3-compatible object storage a part at
+    /// a time, printing what was written as JSON. The bucket is reached with --s3-region and
+    /// --s3-endpoint, and the archive encrypted as --s3-encryption says.
+    Backup {
+        /// Where to write it, as s3://bucket/key.
+        #[clap(long)]
+        to: String,
+        /// Only back up what's changed since this position in the change journal, as an earlier
+        /// backup printed it. Needs --change-journal.
+        #[clap(long)]
+        since: Option<String>,
+    },
+    /// Restore a world from an archive `backup` wrote, reading it from object storage a range at a
+    /// time, then dump it to --dump-path (or --s3-bucket) so that the server starts from it. A full
+    /// archive goes into an empty database, an incremental one over the world it follows on from.
+    Restore {
+        /// Where to read it from, as s3://bucket/key.
+        #[clap(long)]
+        from: String,
+    },
+    /// Search every program's source for a pattern, and optionally replace it, printing a report
+    /// of the changes as JSON. Replacements are compiled before anything is written.
+    Refactor {
+        /// Regular expression to search for.
+        #[clap(long)]
+        pattern: String,
+        /// Text to replace matches with, which may refer to groups as $1 or $name.
+        #[clap(long)]
+        replace: Option<String>,
+        /// Write the replacements which compile, then dump the world. Otherwise nothing is
+        /// changed.
+        #[clap(long, requires = "replace")]
+        apply: bool,
+        /// File to write the report to, rather than printing it.
+        #[clap(long)]
+        report: Option<String>,
+    },
+    /// Mirror the world's objects and the references between them to a directory, as a property
+    /// graph in Neo4j's bulk import CSV format, printing what was written as JSON.
+    Graph {
+        /// Directory to write nodes.csv, relationships.csv and removed.csv to.
+        #[clap(long)]
+        out: String,
+        /// Only write the objects which have changed since the last export to the directory, and
+        /// those which have gone.
+        #[clap(long)]
+        incremental: bool,
+    },
+    /// Add every slot to the index of references, for a world whose slots were set before
+    /// --reference-index was given. Implies --reference-index.
+    IndexReferences,
+    /// Print, as JSON, the slots each program has been recorded reading and writing under
+    /// --record-dependencies, with the verbs it was invoked as, the most invoked first.
+    Dependencies {
+        /// File to write the report to, rather than printing it.
+        #[clap(long)]
+        report: Option<String>,
+        /// Forget what's been recorded once it's reported.
+        #[clap(long)]
+        clear: bool,
+    },
+    /// Print, as JSON, an object's slots as an archive `dump` or `backup` wrote has them, or the
+    /// result of a dry run of one of its verbs there. The archive is loaded into a temporary
+    /// database, so the world itself is left alone.
+    Inspect {
+        /// Archive `dump` wrote to read.
+        #[clap(long = "in", required_unless_present = "from", conflicts_with = "from")]
+        input: Option<String>,
+        /// Full archive `backup` wrote to read, as s3://bucket/key.
+        #[clap(long)]
+        from: Option<String>,
+        /// Object to inspect; the system object by default.
+        #[clap(long)]
+        object: Option<Uuid>,
+        /// Verb to run on the object rather than printing its slots.
+        #[clap(long)]
+        verb: Option<String>,
+        /// Arguments to pass the verb, as a JSON array of Values.
+        #[clap(long, default_value = "[]", requires = "verb")]
+        args: String,
+    },
+}
 
-                receive_connection_message(&world, conn_oid, message)
-                    .await
-                    .expect("Could not receive message");
-            }
-        }
-        Err(e) => match e {
-            tungstenite::Error::Protocol(_) | tungstenite::Error::ConnectionClosed => {
-                error!("Closed, deleting {:?}", conn_oid);
-                disconnect(world, conn_oid)
-                    .await
-                    .expect("Unable to destroy connection object");
-            }
-            _ => {}
-        },
-    }
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum StorageKind {
+    /// FoundationDB, located by --fdb-cluster-file.
+    Fdb,
+    /// Embedded single node database, stored at --storage-path.
+    Embedded,
 }
 
-async fn handle_connection(
-    peer: SocketAddr,
-    stream: TcpStream,
-    world: Arc<world::World>,
-) -> tungstenite::Result<()> {
-    let ws_stream = accept_async(stream).await.expect("Failed to accept");
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum JournalPrivacyKind {
+    /// Record message content.
+    Full,
+    /// Record only a digest of message content.
+    Digest,
+    /// Record only when messages were sent and their size.
+    Metadata,
+}
 
-    // Create an unbounded channel stream from tx->rx and let the world own the tx.
-    let (tx, rx) = unbounded();
-    let conn_oid = register_connection(world.clone(), tx, peer)
-        .await
-        .expect("Failed to create connection object");
-    info!("New WebSocket connection: {} to OID {:?}", peer, conn_oid);
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum LogFormat {
+    /// One line of text per event, prefixed with the spans it happened in.
+    Text,
+    /// One JSON object per event, with the fields of the spans it happened in.
+    Json,
+}
 
-    // Split the stream into inbound/outbound...
-    let (outgoing, incoming) = ws_stream.split();
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum PreemptionKind {
+    /// Meter each instruction, so that limits are exact and deterministic.
+    Fuel,
+    /// Interrupt verbs every few milliseconds, limiting them by time.
+    Epoch,
+}
 
-    // Create a future to forward messages from 'rx' into the outbound.
-    let receive_forward = rx.map(Ok).forward(outgoing);
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum OutboundOverflowKind {
+    /// Refuse them; 'send' tells verbs so.
+    Refuse,
+    /// Drop the oldest message queued to make room.
+    DropOldest,
+    /// Close the connection.
+    Disconnect,
+}
 
-    // And create a future to handle inbound messages.
-    let process_incoming = incoming.for_each(|msg| async {
-        handle_message(conn_oid, msg, world.clone()).await;
-    });
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum OverBandwidthKind {
+    /// Hold their messages back until they're within their caps.
+    Throttle,
+    /// Close them.
+    Disconnect,
+}
 
-    pin_mut!(process_incoming, receive_forward);
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum EncryptionKind {
+    /// Keys managed by the object store (SSE-S3).
+    S3,
+    /// Keys managed by KMS (SSE-KMS).
+    Kms,
+}
 
-    // Perform the selection on both inbound/outbound.
-    future::select(receive_forward, process_incoming).await;
+// The object store and key of the archive at `url`, an s3://bucket/key URL, rather than under
+// --s3-bucket.
+fn archive_at(
+    args: &Args,
+    encryption: &Option<ServerSideEncryption>,
+    url: &str,
+) -> Result<(Arc<ObjectStore>, String), Box<dyn Error>> {
+    let (bucket, key) = parse_s3_url(url)?;
+    let store = ObjectStore::open(&ObjectStoreOptions {
+        bucket,
+        region: args.s3_region.clone(),
+        endpoint: args.s3_endpoint.clone(),
+        prefix: String::new(),
+        encryption: encryption.clone(),
+        retention: RetentionPolicy::default(),
+    })?;
+    Ok((Arc::new(store), key))
+}
 
-    Ok(())
+// The whole-world operation the subcommand or flags given ask for, if any. Backups go wherever
+// they're told to, rather than to --s3-bucket.
+fn operation(
+    args: &Args,
+    encryption: &Option<ServerSideEncryption>,
+) -> Result<Option<Operation>, Box<dyn Error>> {
+    let archive_at = |url: &str| archive_at(args, encryption, url);
+    let operation = match &args.command {
+        Some(Command::Doctor) | Some(Command::Inspect { .. }) => None,
+        Some(Command::Dump { out }) => Some(Operation::Dump { out: out.into() }),
+        Some(Command::Load { input }) => Some(Operation::Load {
+            input: input.into(),
+        }),
+        Some(Command::Backup { to, since }) => {
+            let (store, key) = archive_at(to)?;
+            let since = match since {
+                Some(since) => Some(
+                    since
+                        .parse::<Versionstamp>()
+                        .map_err(|_| format!("--since {} isn't a journal position", since))?,
+                ),
+                None => None,
+            };
+            Some(Operation::Backup { store, key, since })
+        }
+        Some(Command::Restore { from }) => {
+            let (store, key) = archive_at(from)?;
+            Some(Operation::Restore { store, key })
+        }
+        Some(Command::Refactor {
+            pattern,
+            replace,
+            apply,
+            report,
+        }) => Some(Operation::Refactor {
+            refactor: Refactor {
+                pattern: Regex::new(pattern)?,
+                replacement: replace.clone(),
+                apply: *apply,
+            },
+            report: report.as_ref().map(Into::into),
+        }),
+        Some(Command::Graph { out, incremental }) => Some(Operation::Graph {
+            out: out.into(),
+            incremental: *incremental,
+        }),
+        Some(Command::IndexReferences) => Some(Operation::IndexReferences),
+        Some(Command::Dependencies { report, clear }) => Some(Operation::Dependencies {
+            report: report.as_ref().map(Into::into),
+            clear: *clear,
+        }),
+        None => flag_operation(args),
+    };
+    Ok(operation)
+}
+
+// What `inspect` was asked to look at: the archive, the object in it, and the verb to run on it
+// with its arguments, if any.
+struct Inspection {
+    archive: Archive,
+    object: Oid,
+    query: Option<(String, Vec<Value>)>,
+}
+
+// The inspection the subcommand given asks for, if it's `inspect`.
+fn inspection(
+    args: &Args,
+    encryption: &Option<ServerSideEncryption>,
+) -> Result<Option<Inspection>, Box<dyn Error>> {
+    let (input, from, object, verb, arguments) = match &args.command {
+        Some(Command::Inspect {
+            input,
+            from,
+            object,
+            verb,
+            args,
+        }) => (input, from, object, verb, args),
+        _ => return Ok(None),
+    };
+    let archive = match (input, from) {
+        (Some(input), _) => Archive::File(input.into()),
+        (None, Some(from)) => {
+            let (store, key) = archive_at(args, encryption, from)?;
+            Archive::Stored { store, key }
+        }
+        (None, None) => return Err("inspect needs --in or --from".into()),
+    };
+    let query = match verb {
+        Some(verb) => {
+            let arguments: Vec<Value> = serde_json::from_str(arguments)
+                .map_err(|e| format!("--args isn't a JSON array of Values: {}", e))?;
+            Some((verb.clone(), arguments))
+        }
+        None => None,
+    };
+    Ok(Some(Inspection {
+        archive,
+        object: Oid {
+            id: object.unwrap_or_else(Uuid::nil),
+        },
+        query,
+    }))
+}
+
+// The whole-world operation the flags given ask for, if any.
+fn flag_operation(args: &Args) -> Option<Operation> {
+    let player = |id| Oid { id };
+    if let Some(id) = args.export_player {
+        Some(Operation::ExportPlayer {
+            player: player(id),
+            path: args.export_path.clone().into(),
+        })
+    } else if let Some(id) = args.player_stats {
+        Some(Operation::PlayerStats { player: player(id) })
+    } else if let Some(tag) = &args.tag_query {
+        Some(Operation::TagQuery { tag: tag.clone() })
+    } else if args.list_builtins {
+        Some(Operation::ListBuiltins)
+    } else {
+        args.erase_player.map(|id| Operation::ErasePlayer {
+            player: player(id),
+            mode: match args.erase_anonymize {
+                true => ErasureMode::Anonymize,
+                false => ErasureMode::Delete,
+            },
+        })
+    }
+}
+
+// The peers given by --peer and --peer-prefix, and the token in --federation-token-file.
+fn federation_options(args: &Args) -> Result<FederationOptions, Box<dyn Error>> {
+    let mut peers = vec![];
+    for peer in &args.peers {
+        let (name, url) = peer
+            .split_once('=')
+            .ok_or_else(|| format!("--peer {} isn't name=url", peer))?;
+        peers.push(PeerOptions {
+            name: name.to_string(),
+            url: url.to_string(),
+            prefix: None,
+        });
+    }
+    for prefix in &args.peer_prefixes {
+        let (name, prefix) = prefix
+            .split_once('=')
+            .ok_or_else(|| format!("--peer-prefix {} isn't name=prefix", prefix))?;
+        if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("--peer-prefix {} isn't hex", prefix).into());
+        }
+        let peer = peers
+            .iter_mut()
+            .find(|peer| peer.name == name)
+            .ok_or_else(|| format!("--peer-prefix names no --peer {}", name))?;
+        peer.prefix = Some(prefix.to_string());
+    }
+    let token = match &args.federation_token_file {
+        Some(token_file) => {
+            let token = std::fs::read_to_string(token_file)?.trim().to_string();
+            if token.is_empty() {
+                return Err("The federation token file is empty".into());
+            }
+            Some(token)
+        }
+        None => None,
+    };
+    Ok(FederationOptions { peers, token })
 }
 
-async fn process(listen_address: String, world: Arc<World>) {
-    let listener = TcpListener::bind(listen_address)
-        .await
-        .expect("Can't listen");
+// A retention period given in days by `flag`, refused if it's too long to represent.
+fn retention_days(days: u64, flag: &str) -> Result<Duration, Box<dyn Error>> {
+    days.checked_mul(24 * 60 * 60)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{} {} is too long", flag, days).into())
+}
 
-    while let Ok((stream, _)) = listener.accept().await {
-        let peer = stream
-            .peer_addr()
-            .expect("connected streams should have a peer address");
-        info!("Peer address: {}", peer);
+// The server-side encryption --s3-encryption asks for.
+fn s3_encryption(args: &Args) -> Option<ServerSideEncryption> {
+    args.s3_encryption.as_ref().map(|kind| match kind {
+        EncryptionKind::S3 => ServerSideEncryption::S3Managed,
+        EncryptionKind::Kms => ServerSideEncryption::Kms(args.s3_kms_key_id.clone()),
+    })
+}
 
-        tokio::spawn(handle_connection(peer, stream, world.clone()));
+// The configuration file's settings, overridden by those given on the command line.
+fn configure(args: &Args) -> Result<Config, Box<dyn Error>> {
+    let mut config = match &args.config {
+        Some(path) => Config::read(Path::new(path))?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+            Config::read(Path::new(DEFAULT_CONFIG_PATH))?
+        }
+        None => Config::default(),
+    };
+    // Settings the file may leave unset are taken from the flags if they're given.
+    fn given<T: Clone>(setting: &mut T, flag: &Option<T>) {
+        if let Some(value) = flag {
+            *setting = value.clone();
+        }
+    }
+    given(&mut config.listen_address, &args.listen_address);
+    config.telnet_address = args.telnet_address.clone().or(config.telnet_address);
+    config.metrics_address = args.metrics_address.clone().or(config.metrics_address);
+    config.admin_address = args.admin_address.clone().or(config.admin_address);
+    config.admin_token_file = args
+        .admin_token_file
+        .clone()
+        .map(Into::into)
+        .or(config.admin_token_file);
+    if let Some(path) = &args.listeners {
+        config.listeners = read_listener_configs(Path::new(path))?;
+    }
+    if let Some(kind) = &args.storage {
+        config.storage = match kind {
+            StorageKind::Fdb => StorageBackend::Fdb,
+            StorageKind::Embedded => StorageBackend::Embedded,
+        };
+    }
+    given(
+        &mut config.storage_path,
+        &args.storage_path.clone().map(Into::into),
+    );
+    config.fdb_cluster_file = args
+        .fdb_cluster_file
+        .clone()
+        .or_else(|| std::env::var("FDB_CLUSTER_FILE").ok())
+        .map(Into::into)
+        .or(config.fdb_cluster_file);
+    given(
+        &mut config.dump_path,
+        &args.dump_path.clone().map(Into::into),
+    );
+    given(&mut config.fuel_limit, &args.fuel_limit);
+    given(&mut config.memory_limit_mb, &args.memory_limit_mb);
+    given(&mut config.time_limit_ms, &args.time_limit_ms);
+    config.checkpoint_interval = args.checkpoint_interval.or(config.checkpoint_interval);
+    if config.admin_address.is_some() && config.admin_token_file.is_none() {
+        return Err("The admin API needs an admin token file".into());
     }
+    Ok(config)
+}
+
+// On SIGHUP: compile every verb afresh, and preload the manifest's objects and verbs again.
+async fn reload(world: &Arc<World>, args: &Args) -> Result<(), Box<dyn Error>> {
+    info!("Reloading");
+    world.module_cache().evict_all();
+    if let Some(path) = &args.preload {
+        preload(world, &PreloadManifest::read(Path::new(path))?).await?;
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    env_logger::init();
+    // The console shows the log itself, so it's written to it instead of stdout.
+    let console_log = (args.console && std::io::stdout().is_tty()).then(ConsoleLog::default);
+    // Errors only, unless RUST_LOG says otherwise.
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    match (&console_log, &args.log_format) {
+        (Some(log), _) => subscriber.with_ansi(false).with_writer(log.clone()).init(),
+        (None, LogFormat::Text) => subscriber.init(),
+        (None, LogFormat::Json) => subscriber.json().init(),
+    }
+    if args.console && console_log.is_none() {
+        warn!("Not showing the console, as stdout isn't a terminal");
+    }
 
-    let world = Arc::new(world::World::new());
+    let config = configure(&args)?;
+    if args.command.is_none()
+        && matches!(config.storage, StorageBackend::Fdb)
+        && args.node_name.is_none()
+    {
+        return Err("Each server sharing FoundationDB needs a --node-name".into());
+    }
+    if args.gc_clear && !args.reference_index {
+        return Err("--gc-clear needs --reference-index".into());
+    }
+    let journal_retention =
+        retention_days(args.journal_retention_days, "--journal-retention-days")?;
+    let audit_retention = retention_days(args.audit_retention_days, "--audit-retention-days")?;
+    let change_retention = retention_days(args.change_retention_days, "--change-retention-days")?;
+    let options = WorldOptions {
+        echo_results: args.echo_results,
+        storage: config.storage(),
+        journal: args.journal.then(|| JournalOptions {
+            privacy: match args.journal_privacy {
+                JournalPrivacyKind::Full => JournalPrivacy::Full,
+                JournalPrivacyKind::Digest => JournalPrivacy::Digest,
+                JournalPrivacyKind::Metadata => JournalPrivacy::Metadata,
+            },
+            retention: journal_retention,
+        }),
+        resume: args.resume_grace_secs.map(|secs| ResumeOptions {
+            grace: Duration::from_secs(secs),
+        }),
+        verb_audit: args.audit_verbs.then(|| VerbAuditOptions {
+            retention: audit_retention,
+        }),
+        auth: AuthPolicy {
+            window: Duration::from_secs(args.login_window_secs),
+            free_attempts: args.login_free_attempts,
+            max_lockout: Duration::from_secs(args.login_max_lockout_secs),
+            ..AuthPolicy::default()
+        },
+        quotas: QuotaPolicy {
+            objects: args.quota_objects,
+            bytes: args.quota_bytes,
+        },
+        module_cache_capacity: Some(args.module_cache_mb * 1024 * 1024),
+        fuel_limit: Some(config.fuel_limit),
+        memory_limit: Some(config.memory_limit_mb * 1024 * 1024),
+        preemption: match args.preemption {
+            PreemptionKind::Fuel => Preemption::Fuel,
+            PreemptionKind::Epoch => Preemption::Epoch,
+        },
+        time_limit: Some(Duration::from_millis(config.time_limit_ms)),
+        outbound: OutboundPolicy {
+            capacity: args.outbound_queue.max(1),
+            overflow: match args.outbound_overflow {
+                OutboundOverflowKind::Refuse => OverflowAction::Refuse,
+                OutboundOverflowKind::DropOldest => OverflowAction::DropOldest,
+                OutboundOverflowKind::Disconnect => OverflowAction::Disconnect,
+            },
+        },
+        bandwidth: BandwidthPolicy {
+            inbound: args.max_inbound_bytes_per_sec,
+            outbound: args.max_outbound_bytes_per_sec,
+            action: match args.over_bandwidth {
+                OverBandwidthKind::Throttle => BandwidthAction::Throttle,
+                OverBandwidthKind::Disconnect => BandwidthAction::Disconnect,
+            },
+        },
+        flood: FloodPolicy {
+            messages_per_sec: args.max_messages_per_sec,
+            bytes_per_sec: args.max_message_bytes_per_sec,
+            max_strikes: args.max_flood_strikes,
+        },
+        retry: RetryPolicy {
+            max_retries: (args.tx_max_retries > 0).then_some(args.tx_max_retries),
+            max_backoff: Duration::from_millis(args.tx_max_backoff_ms),
+            ..RetryPolicy::default()
+        },
+        #[cfg(feature = "faults")]
+        faults: args.inject_faults.then(|| FaultOptions {
+            tx_delay_rate: args.fault_tx_delay_rate,
+            tx_max_delay: Duration::from_millis(args.fault_tx_max_delay_ms),
+            tx_fail_rate: args.fault_tx_fail_rate,
+            frame_drop_rate: args.fault_frame_drop_rate,
+            trap_rate: args.fault_trap_rate,
+        }),
+        trace_verbs: args.trace_verbs,
+        record_dependencies: args.record_dependencies,
+        reference_index: args.reference_index
+            || matches!(args.command, Some(Command::IndexReferences)),
+        gc: GcOptions {
+            roots: args.gc_roots.iter().map(|&id| Oid { id }).collect(),
+            interval: args.gc_interval_secs.map(Duration::from_secs),
+            clear: args.gc_clear,
+        },
+        changes: args.change_journal.then(|| ChangeJournalOptions {
+            retention: change_retention,
+        }),
+        federation: federation_options(&args)?,
+        catalog: args.catalog.clone().map(Into::into),
+        strict_load: args.strict_load,
+        redaction: {
+            let mut redaction = RedactionPolicy {
+                max_bytes: args.log_max_bytes,
+                ..RedactionPolicy::default()
+            };
+            redaction
+                .sensitive_slots
+                .extend(args.sensitive_slots.iter().cloned());
+            redaction
+        },
+        time_zone: args.time_zone.as_deref().map(parse_time_zone).transpose()?,
+        default_locale: args.default_locale.clone(),
+        ping_interval: args.ping_interval_secs.map(Duration::from_secs),
+        idle_timeout: args.idle_timeout_secs.map(Duration::from_secs),
+        tasks_per_connection: Some(args.tasks_per_connection),
+        node: args.node_name.clone(),
+        command_parser: args.command_parser.then(|| {
+            let mut grammar = CommandGrammar::default();
+            if !args.prepositions.is_empty() {
+                grammar.prepositions = args.prepositions.clone();
+            }
+            grammar
+        }),
+    };
+    let encryption = s3_encryption(&args);
+    let operation = operation(&args, &encryption)?;
+    let inspection = inspection(&args, &encryption)?;
+    let dump_target = match args.s3_bucket {
+        Some(bucket) => DumpTarget::ObjectStore(ObjectStoreOptions {
+            bucket,
+            region: args.s3_region,
+            endpoint: args.s3_endpoint,
+            prefix: args.s3_prefix,
+            encryption,
+            retention: RetentionPolicy {
+                keep_last: args.s3_keep,
+                hourly: args.s3_keep_hourly,
+                daily: args.s3_keep_daily,
+                weekly: args.s3_keep_weekly,
+            },
+        }),
+        None => DumpTarget::Directory(config.dump_path.clone()),
+    };
+
+    let listener_configs = config.listeners.clone();
+    const BUILT_IN_LISTENERS: [&str; 4] = [WEBSOCKET_LISTENER, "telnet", "metrics", "admin"];
+    if let Some(config) = listener_configs
+        .iter()
+        .find(|config| BUILT_IN_LISTENERS.contains(&config.name.as_str()))
+    {
+        return Err(format!(
+            "Listener {} is built in, and can't be configured",
+            config.name
+        )
+        .into());
+    }
+
+    // Checked before the world is created, as that fails outright if the database can't be opened.
+    if let Some(Command::Doctor) = &args.command {
+        let mut listen_addresses = vec![config.listen_address.clone()];
+        listen_addresses.extend(config.telnet_address.clone());
+        listen_addresses.extend(config.metrics_address.clone());
+        listen_addresses.extend(config.admin_address.clone());
+        listen_addresses.extend(listener_configs.iter().map(|config| config.address.clone()));
+        let checks = run_checks(&DoctorOptions {
+            storage: options.storage.clone(),
+            dump_target: dump_target.clone(),
+            listen_addresses,
+        })
+        .await;
+        for check in &checks {
+            println!("{}", check);
+        }
+        let failed = checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Failed)
+            .count();
+        if failed > 0 {
+            return Err(format!("{} checks failed", failed).into());
+        }
+        return Ok(());
+    }
+
+    // The archive is opened in a world of its own, so the real one is never created.
+    if let Some(inspection) = inspection {
+        let query = inspection
+            .query
+            .as_ref()
+            .map(|(verb, arguments)| (verb.as_str(), arguments.as_slice()));
+        inspect_archive(&inspection.archive, options, inspection.object, query).await?;
+        return Ok(());
+    }
+
+    let world = Arc::new(world::World::new(options.clone()));
     let sys_oid = Oid { id: Uuid::nil() };
+    if let Some(path) = &args.hooks {
+        let hooks = WasmHooks::load(Path::new(path), world.module_cache().engine())?;
+        world.add_hooks(Arc::new(hooks));
+    }
 
-    let dump_path = std::path::Path::new("dump");
-    let dump_found = load(world.clone(), dump_path).await.unwrap();
+    if let Some(operation) = operation.as_ref().filter(|op| op.needs_empty_world()) {
+        run_operation(&world, operation, &dump_target).await?;
+        return Ok(());
+    }
+
+    let dump_found = load(world.clone(), &dump_target).await?;
     if !dump_found {
         info!("No dump found, bootstrapping...");
-        match bootstrap_world(world.clone(), sys_oid).await {
+        let bootstrapped = match &args.core {
+            Some(dir) => {
+                let core = Core::load(Path::new(dir), world.module_cache().engine())?;
+                install_core(world.clone(), &core).await
+            }
+            None => bootstrap_world(world.clone(), sys_oid).await,
+        };
+        match bootstrapped {
             Ok(()) => {
                 info!("World bootstrapped.")
             }
@@ -125,20 +1180,175 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    info!("Listening on: {}", args.listen_address.clone());
-    tokio::spawn(process(args.listen_address.clone(), world.clone()));
+    // Administrative operations run against the world and exit, rather than serving it.
+    if let Some(operation) = &operation {
+        run_operation(&world, operation, &dump_target).await?;
+        return Ok(());
+    }
+
+    if let Some(path) = &args.preload {
+        preload(&world, &PreloadManifest::read(Path::new(path))?).await?;
+    }
+    run_hooks(&world, LifecyclePoint::PreListen).await?;
 
-    match tokio::signal::ctrl_c().await {
-        Ok(()) => {
-            warn!("Shutting down...");
+    tokio::spawn(world::notify_watchers(world.clone()));
+    tokio::spawn(world::dispatch_calendar(world.clone()));
+    tokio::spawn(world::deliver_events(world.clone()));
+    tokio::spawn(world::keep_alive(world.clone()));
+    tokio::spawn(world::expire_detached(world.clone()));
+    world.module_cache().start_epoch_ticker();
+    if let Some(interval) = options.gc.interval {
+        tokio::spawn(world::collect_garbage_every(world.clone(), interval));
+    }
+    if let Some(changes) = options.changes {
+        tokio::spawn(world::prune_changes_every(world.clone(), changes));
+    }
+    if let Some(journal) = options.journal.clone() {
+        tokio::spawn(world::prune_journal_every(world.clone(), journal));
+    }
+    if let Some(audit) = options.verb_audit.clone() {
+        tokio::spawn(world::prune_verb_audit_every(world.clone(), audit));
+    }
+    world.federation().connect_peers();
+    if let Some(secs) = config.checkpoint_interval {
+        tokio::spawn(world::checkpoint(
+            world.clone(),
+            dump_target.clone(),
+            Duration::from_secs(secs),
+        ));
+    }
+
+    let forgotten = forget_sessions(&world).await?;
+    if forgotten > 0 {
+        info!("Forgot {} sessions left from the last run", forgotten);
+    }
+
+    // Listeners bound by the service manager, under socket activation, are taken rather than bound.
+    let mut known = BUILT_IN_LISTENERS.to_vec();
+    known.extend(listener_configs.iter().map(|config| config.name.as_str()));
+    let listeners = Listeners::from_env(&known);
+    info!("Listening on: {}", config.listen_address);
+    let origin_policy = Arc::new(OriginPolicy {
+        allowed_origins: args.allowed_origins.clone(),
+        allowed_hosts: args.allowed_hosts.clone(),
+    });
+    let proxy = Arc::new(ProxyOptions {
+        proxy_protocol: args.proxy_protocol,
+        trusted_proxies: args.trusted_proxies.clone(),
+    });
+    let mut listener_configs = listener_configs;
+    listener_configs.insert(
+        0,
+        ListenerConfig::built_in(
+            WEBSOCKET_LISTENER,
+            &config.listen_address,
+            Transport::Websocket,
+        ),
+    );
+    if let Some(telnet_address) = &config.telnet_address {
+        let telnet = ListenerConfig::built_in("telnet", telnet_address, Transport::Telnet);
+        listener_configs.insert(1, telnet);
+    }
+    for config in listener_configs {
+        if config.name != WEBSOCKET_LISTENER {
+            info!(
+                "Listening for {:?} on: {} ({})",
+                config.transport, config.address, config.name
+            );
+        }
+        let listener = listeners.take(&config.name, &config.address).await?;
+        net::serve(&world, listener, config, &origin_policy, &proxy);
+    }
+    if let Some(metrics_address) = config.metrics_address.clone() {
+        info!("Serving metrics on: {}", metrics_address);
+        let listener = listeners.take("metrics", &metrics_address).await?;
+        tokio::spawn(net::metrics::listen(listener, world.clone()));
+    }
+    if let (Some(admin_address), Some(token_file)) =
+        (&config.admin_address, &config.admin_token_file)
+    {
+        let token = std::fs::read_to_string(token_file)?.trim().to_string();
+        if token.is_empty() {
+            return Err("The admin token file is empty".into());
         }
-        Err(err) => {
-            error!("Unable to listen for shutdown signal: {}", err);
-            // we also shut down in case of error
+        let admin = Arc::new(AdminOptions {
+            token,
+            dump_target: dump_target.clone(),
+        });
+        info!("Serving the admin API on: {}", admin_address);
+        let listener = listeners.take("admin", admin_address).await?;
+        tokio::spawn(net::admin::listen(listener, world.clone(), admin));
+    }
+
+    let mut console =
+        console_log.map(|log| tokio::spawn(console::run(world.clone(), dump_target.clone(), log)));
+
+    // A service manager stops us with SIGTERM, and asks us to reload with SIGHUP.
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    systemd::notify(&[NotifyState::Ready]);
+    loop {
+        // The console takes ctrl-c as a key, rather than as a signal, and quits.
+        let console_quit = async {
+            match console.as_mut() {
+                Some(console) => console.await,
+                None => future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = console_quit => {
+                match result {
+                    Ok(Ok(())) => warn!("Shutting down..."),
+                    Ok(Err(e)) => error!("Console failed: {}; shutting down...", e),
+                    Err(e) => error!("Console failed: {}; shutting down...", e),
+                }
+                break;
+            }
+            result = tokio::signal::ctrl_c() => {
+                match result {
+                    Ok(()) => warn!("Shutting down..."),
+                    // we also shut down in case of error
+                    Err(err) => error!("Unable to listen for shutdown signal: {}", err),
+                }
+                break;
+            }
+            _ = terminate.recv() => {
+                warn!("Terminated; shutting down...");
+                break;
+            }
+            _ = hangup.recv() => {
+                systemd::notify(&[NotifyState::Reloading]);
+                if let Err(e) = reload(&world, &args).await {
+                    error!("Reload failed: {}", e);
+                }
+                systemd::notify(&[NotifyState::Ready]);
+            }
         }
     }
+    systemd::notify(&[NotifyState::Stopping]);
+    // Put the terminal back before shutting down, so that what's logged meanwhile is seen.
+    if let Some(console) = console.filter(|console| !console.is_finished()) {
+        console.abort();
+        let _ = console.await;
+    }
+
+    let (hits, misses) = world.module_cache().stats();
+    info!("Module cache: {} hits, {} misses", hits, misses);
+
+    if let Some(path) = &args.preload {
+        let path = Path::new(path);
+        let mut manifest = PreloadManifest::read(path)?;
+        let hot = world.stats().into_iter().map(|(oid, verb, _)| (oid, verb));
+        manifest.refresh_verbs(hot);
+        manifest.write(path)?;
+    }
 
-    save(world.clone(), dump_path, &vec![sys_oid]).await?;
+    // Checkpoints dump more than the system object, so the last dump does too, rather than leaving
+    // theirs as of the last checkpoint.
+    match config.checkpoint_interval {
+        Some(_) => save_all(world.clone(), &dump_target).await?,
+        None => save(world.clone(), &dump_target, &[sys_oid]).await?,
+    }
 
     Ok(())
 }
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use sha2::{Digest, Sha512};
+use wasmtime::{Engine, Module};
+
+use value::{Oid, Program};
+
+use crate::compile::{compile, CompileError};
+
+/// Default cap on the total size of compiled modules kept in the cache.
+pub const DEFAULT_CAPACITY_BYTES: u64 = 256 * 1024 * 1024;
+
+/// How often the engine's epoch is advanced, at which running verbs yield.
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// How running verbs are made to give way to others, and held to their limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Preemption {
+    /// By the fuel they consume: their limits are exact and deterministic, at the cost of
+    /// metering every instruction.
+    #[default]
+    Fuel,
+    /// By the engine's epoch alone: cheaper, but verbs are limited by the time they take rather
+    /// than the work they do.
+    Epoch,
+}
+
+/// Compiled modules shared by every connection's VM, keyed by a digest of the program they were
+/// compiled from.
+///
+/// Modules belong to the Engine they were compiled with, so the cache also owns the one Engine all
+/// VMs are created from.
+pub struct ModuleCache {
+    engine: Engine,
+    preemption: Preemption,
+    modules: moka::future::Cache<[u8; 64], Module>,
+    // The digest of the program each verb, by object and name, was last run as, so that its
+    // module can be dropped when its slot is rewritten.
+    verbs: Mutex<HashMap<(Oid, String), [u8; 64]>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// The digest programs' compiled modules are cached under, of their language and code.
+pub fn digest(program: &Program) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    hasher.update([program.lang as u8]);
+    hasher.update(&program.code);
+    hasher.finalize().into()
+}
+
+impl ModuleCache {
+    pub fn new(capacity_bytes: u64, preemption: Preemption) -> Self {
+        let mut config = wasmtime::Config::new();
+        // We need this engine's `Store`s to be async, and (unless preempted by
+        // epoch alone) consume fuel, so that they can co-operatively yield
+        // during execution. They always yield as the engine's epoch advances,
+        // so that they can be killed.
+        config.async_support(true);
+        config.consume_fuel(preemption == Preemption::Fuel);
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).expect("Could not create wasm engine");
+
+        ModuleCache {
+            engine,
+            preemption,
+            modules: moka::future::Cache::builder()
+                .max_capacity(capacity_bytes)
+                // Weighed by the size of the compiled code and data.
+                .weigher(|_, module: &Module| {
+                    module.image_range().len().try_into().unwrap_or(u32::MAX)
+                })
+                .time_to_live(Duration::from_secs(30 * 60))
+                .build(),
+            verbs: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn preemption(&self) -> Preemption {
+        self.preemption
+    }
+
+    /// Advance the engine's epoch every EPOCH_TICK from now on, on a thread of its own, so that
+    /// the ticks keep time however busy the runtime is.
+    pub fn start_epoch_ticker(&self) {
+        let engine = self.engine.clone();
+        std::thread::Builder::new()
+            .name("epoch-ticker".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(EPOCH_TICK);
+                engine.increment_epoch();
+            })
+            .expect("Could not start the epoch ticker");
+    }
+
+    /// Retrieve the compiled module for `program`, compiling it if it's not already cached.
+    /// (Should probably profile this because perhaps in some cases taking the hash could be
+    /// costlier than just compiling.)
+    pub async fn get(&self, program: &Program) -> Result<Module, CompileError> {
+        self.get_digested(digest(program), program).await
+    }
+
+    /// As `get`, for a program whose `digest` has already been taken.
+    pub async fn get_digested(
+        &self,
+        digest: [u8; 64],
+        program: &Program,
+    ) -> Result<Module, CompileError> {
+        let mut compiled = false;
+        let module = self
+            .modules
+            .try_get_with(digest, async {
+                compiled = true;
+                compile(&self.engine, program)
+            })
+            .await;
+        match compiled {
+            true => self.misses.fetch_add(1, Ordering::Relaxed),
+            false => self.hits.fetch_add(1, Ordering::Relaxed),
+        };
+        // Errors are shared between concurrent waiters, so each gets a copy.
+        module.map_err(|e| (*e).clone())
+    }
+
+    /// Drop the compiled module for the program with `digest`, so that it's compiled afresh when
+    /// next needed. False if it wasn't cached.
+    pub async fn evict(&self, digest: &[u8; 64]) -> bool {
+        let cached = self.modules.get(digest).is_some();
+        self.modules.invalidate(digest).await;
+        cached
+    }
+
+    /// Drop every compiled module.
+    pub fn evict_all(&self) {
+        self.modules.invalidate_all();
+        self.verbs.lock().unwrap().clear();
+    }
+
+    /// Remember that the verb `name` on `oid` was run as the program with `digest`.
+    pub fn bind_verb(&self, (oid, name): (Oid, &str), digest: [u8; 64]) {
+        self.verbs
+            .lock()
+            .unwrap()
+            .insert((oid, name.to_string()), digest);
+    }
+
+    /// Drop the compiled module the verb `name` on `oid` was last run as, unless another verb was
+    /// last run as the same program. False if it wasn't known to have been run.
+    pub async fn flush_verb(&self, oid: Oid, name: &str) -> bool {
+        let digest = {
+            let mut verbs = self.verbs.lock().unwrap();
+            match verbs.remove(&(oid, name.to_string())) {
+                Some(digest) if verbs.values().any(|shared| *shared == digest) => return true,
+                Some(digest) => digest,
+                None => return false,
+            }
+        };
+        self.modules.invalidate(&digest).await;
+        true
+    }
+
+    /// Note that the slot `name` on `oid` under `key` has been written. If it's a verb, the module
+    /// it was last run as is dropped now rather than left to expire. (Modules are cached by the
+    /// programs they're compiled from, so a rewritten verb runs as its new program regardless.)
+    pub async fn slot_written(&self, oid: Oid, key: Oid, name: &str) {
+        if oid == key {
+            self.flush_verb(oid, name).await;
+        }
+    }
+
+    /// The number of lookups which found a compiled module, and which had to compile one.
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -0,0 +1,83 @@
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// The engine's registry of unique object (e.g. player) names.
+///
+/// Each name maps to the object holding it, and each object back to its name, so that claiming a
+/// name, renaming, and releasing a name on destruction are all checked and applied within a single
+/// transaction. Names are unique without regard to case or surrounding whitespace.
+pub struct NameTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+pub fn normalize(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+fn name_key(name: &str) -> Key {
+    let name_subspace = Subspace::new(Bytes::from_static("NAME".as_bytes()));
+    let mut tup = Tuple::new();
+    tup.add_string(normalize(name));
+    name_subspace.subspace(&tup).pack().into()
+}
+
+fn holder_key(oid: Oid) -> Key {
+    let holder_subspace = Subspace::new(Bytes::from_static("NAMEOF".as_bytes()));
+    let mut tup = Tuple::new();
+    tup.add_uuid(oid.id);
+    holder_subspace.subspace(&tup).pack().into()
+}
+
+impl<'tx_lifetime> NameTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        NameTxHandle { tr: tx }
+    }
+
+    /// The object holding `name`, if any.
+    pub async fn holder(&self, name: &str) -> Result<Option<Oid>, DbError> {
+        Ok(self.tr.get(name_key(name)).await?.map(|v| {
+            let tuple = Tuple::from_bytes(v).unwrap();
+            Oid {
+                id: *tuple.get_uuid_ref(0).unwrap(),
+            }
+        }))
+    }
+
+    /// The name held by `oid`, as it was given when claimed.
+    pub async fn name_of(&self, oid: Oid) -> Result<Option<String>, DbError> {
+        Ok(self.tr.get(holder_key(oid)).await?.map(|v| {
+            let tuple = Tuple::from_bytes(v).unwrap();
+            tuple.get_string_ref(0).unwrap().clone()
+        }))
+    }
+
+    /// Claim `name` for `oid`, releasing any name it held before.
+    /// Returns false, changing nothing, if the name is held by another object.
+    pub async fn claim(&self, oid: Oid, name: &str) -> Result<bool, DbError> {
+        match self.holder(name).await? {
+            Some(holder) if holder != oid => return Ok(false),
+            _ => {}
+        }
+        self.release(oid).await?;
+
+        let mut holder = Tuple::new();
+        holder.add_uuid(oid.id);
+        self.tr.set(name_key(name), holder.pack());
+        let mut given = Tuple::new();
+        given.add_string(name.trim().to_string());
+        self.tr.set(holder_key(oid), given.pack());
+        Ok(true)
+    }
+
+    /// Release whatever name `oid` holds.
+    pub async fn release(&self, oid: Oid) -> Result<(), DbError> {
+        if let Some(name) = self.name_of(oid).await? {
+            self.tr.clear(name_key(&name));
+            self.tr.clear(holder_key(oid));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::changes::{Versionstamp, CHANGES_BATCH};
+use crate::dump::DumpTarget;
+use crate::impersonation::AUDIT_PAGE;
+use crate::world::{
+    boot, changes_since, connection_summaries, dump_objects, query_impersonation_audit,
+    query_verb_audit, save_all, start_garbage_collection, World,
+};
+use value::Oid;
+
+// Requests are just a request line and headers, so there's no need to read more than this.
+const MAX_REQUEST_LENGTH: usize = 8192;
+
+// How long a client has to send its request, so that idle ones can't hold connections open.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the admin endpoint needs besides the world: the token requests must bear, and where a
+/// forced checkpoint is written.
+pub struct AdminOptions {
+    pub token: String,
+    pub dump_target: DumpTarget,
+}
+
+/// Serve the operator's commands on `listener`, over HTTP, to requests bearing the token
+/// in an `Authorization: Bearer` header. Each answers with JSON:
+///  * `GET /connections` lists the connections to this server;
+///  * `GET /listeners` lists the listeners connections are accepted on, and their entry points;
+///  * `GET /objects/<uuid>` gives the slots of an object, as they'd be dumped;
+///  * `GET /impersonations` lists the impersonations going on, and `GET /impersonations/audit`
+///    gives their audit trail, which may be narrowed to a time range with `from=` and `to=`, in
+///    seconds since the Unix epoch, a page at a time as `{"entries", "next"}`: ask again
+///    `from=` the `next` for the next page, until it's null;
+///  * `GET /audit` gives the verb invocations audited, which may be narrowed to those involving
+///    an object with `?object=<uuid>`, and to a time range with `from=` and `to=`, a page at a
+///    time in the same way;
+///  * `POST /module-cache/evict` drops every compiled module, and
+///    `POST /module-cache/evict/<digest>` the one for the program with that digest, in hex;
+///  * `POST /checkpoint` dumps every object now;
+///  * `POST /gc` starts a garbage collection sweep, reporting the objects unreachable from the
+///    roots, or clearing them with `?clear=true`, and `GET /gc` follows how it's going;
+///  * `GET /changes` gives the changes journaled, in the order they took effect, after the one
+///    whose versionstamp is given with `?since=<hex>`, up to `limit=` of them;
+///  * `POST /connections/<uuid>/boot` closes a connection.
+pub async fn listen(listener: TcpListener, world: Arc<World>, options: Arc<AdminOptions>) {
+    while let Ok((stream, peer)) = listener.accept().await {
+        info!("Admin peer address: {}", peer);
+        let world = world.clone();
+        let options = options.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &world, &options).await {
+                warn!("Admin request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[derive(Serialize)]
+struct Done {
+    done: bool,
+}
+
+// The status and body to answer with.
+type Reply = (&'static str, String);
+
+fn json(value: &impl Serialize) -> Result<Reply, anyhow::Error> {
+    Ok(("200 OK", serde_json::to_string_pretty(value)?))
+}
+
+fn not_found() -> Result<Reply, anyhow::Error> {
+    Ok(("404 Not Found", String::new()))
+}
+
+fn parse_oid(id: &str) -> Option<Oid> {
+    Uuid::parse_str(id).ok().map(|id| Oid { id })
+}
+
+// The value of the parameter `name` in a query string.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+// A time given in seconds since the Unix epoch, with up to nine places of decimals.
+fn parse_time(secs: Option<&str>, default: SystemTime) -> Option<SystemTime> {
+    let secs = match secs {
+        Some(secs) => secs,
+        None => return Some(default),
+    };
+    let (whole, fraction) = secs.split_once('.').unwrap_or((secs, ""));
+    if fraction.len() > 9 || !fraction.bytes().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    let nanos: u32 = format!("{:0<9}", fraction).parse().ok()?;
+    Some(UNIX_EPOCH + Duration::new(whole.parse().ok()?, nanos))
+}
+
+// `time` as parse_time takes it.
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "{}.{:09}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    )
+}
+
+/// Part of a long listing: its entries, and if there may be more, the `from=` to ask for the next
+/// page with.
+#[derive(Serialize)]
+struct Page<T> {
+    entries: Vec<T>,
+    next: Option<String>,
+}
+
+impl<T> Page<T> {
+    // A page of up to `size` entries, which are never cut short in the same instant as the last,
+    // so that the next page starts just after it.
+    fn of(entries: Vec<T>, size: usize, timestamp: impl Fn(&T) -> SystemTime) -> Self {
+        let next = match entries.last() {
+            Some(last) if entries.len() >= size => {
+                Some(format_time(timestamp(last) + Duration::from_nanos(1)))
+            }
+            _ => None,
+        };
+        Page { entries, next }
+    }
+}
+
+fn parse_digest(hex: &str) -> Option<[u8; 64]> {
+    if hex.len() != 128 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 64];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+// Whether the request bears the token. Digests are compared, rather than the tokens themselves,
+// so that the time taken says nothing about how much of the token was right.
+fn authorized(headers: &str, token: &str) -> bool {
+    let presented = headers.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        match name.trim().eq_ignore_ascii_case("authorization") {
+            true => value.trim().strip_prefix("Bearer "),
+            false => None,
+        }
+    });
+    match presented {
+        Some(presented) => Sha256::digest(presented.trim()) == Sha256::digest(token),
+        None => false,
+    }
+}
+
+async fn route(
+    method: &str,
+    path: &str,
+    world: &Arc<World>,
+    options: &AdminOptions,
+) -> Result<Reply, anyhow::Error> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+    match (method, &segments[..]) {
+        ("GET", ["connections"]) => json(&connection_summaries(world)),
+        ("GET", ["listeners"]) => json(&world.listeners().list()),
+        ("GET", ["impersonations"]) => json(&world.impersonations().list()),
+        ("GET", ["impersonations", "audit"]) => {
+            let from = parse_time(query_param(query, "from"), UNIX_EPOCH);
+            let to = parse_time(
+                query_param(query, "to"),
+                SystemTime::now() + Duration::from_secs(1),
+            );
+            match (from, to) {
+                (Some(from), Some(to)) => {
+                    let entries = query_impersonation_audit(world, from, to).await?;
+                    json(&Page::of(entries, AUDIT_PAGE, |entry| entry.timestamp))
+                }
+                _ => Ok(("400 Bad Request", String::new())),
+            }
+        }
+        ("GET", ["audit"]) => {
+            let object = match query_param(query, "object") {
+                Some(id) => match parse_oid(id) {
+                    Some(oid) => Some(oid),
+                    None => return not_found(),
+                },
+                None => None,
+            };
+            let from = parse_time(query_param(query, "from"), UNIX_EPOCH);
+            let to = parse_time(
+                query_param(query, "to"),
+                SystemTime::now() + Duration::from_secs(1),
+            );
+            match (from, to) {
+                (Some(from), Some(to)) => {
+                    let (entries, next) = query_verb_audit(world, object, from, to).await?;
+                    json(&Page {
+                        entries,
+                        next: next.map(format_time),
+                    })
+                }
+                _ => Ok(("400 Bad Request", String::new())),
+            }
+        }
+        ("GET", ["objects", id]) => match parse_oid(id) {
+            Some(oid) => json(&dump_objects(world, &[oid]).await?),
+            None => not_found(),
+        },
+        ("POST", ["module-cache", "evict"]) => {
+            world.module_cache().evict_all();
+            warn!(target: "security", "Module cache emptied by an administrator");
+            json(&Done { done: true })
+        }
+        ("POST", ["module-cache", "evict", digest]) => match parse_digest(digest) {
+            Some(digest) => json(&Done {
+                done: world.module_cache().evict(&digest).await,
+            }),
+            None => not_found(),
+        },
+        ("POST", ["checkpoint"]) => {
+            warn!(target: "security", "Checkpoint forced by an administrator");
+            save_all(world.clone(), &options.dump_target).await?;
+            json(&Done { done: true })
+        }
+        ("GET", ["gc"]) => match world.gc().progress() {
+            Some(progress) => json(&progress),
+            None => not_found(),
+        },
+        ("POST", ["gc"]) => {
+            let clear = query_param(query, "clear") == Some("true");
+            // Clearing checks each object for references once more as it goes.
+            if clear && !world.indexes_references() {
+                return Ok(("400 Bad Request", String::new()));
+            }
+            if !start_garbage_collection(world, clear) {
+                return Ok(("409 Conflict", String::new()));
+            }
+            warn!(target: "security", "Garbage collection started by an administrator");
+            json(&Done { done: true })
+        }
+        ("GET", ["changes"]) => {
+            let since = match query_param(query, "since") {
+                Some(hex) => hex.parse().ok(),
+                None => Some(Versionstamp::ZERO),
+            };
+            let limit = match query_param(query, "limit") {
+                Some(limit) => limit.parse().ok(),
+                None => Some(CHANGES_BATCH),
+            };
+            match (since, limit) {
+                (Some(since), Some(limit)) => json(&changes_since(world, since, limit).await?),
+                _ => Ok(("400 Bad Request", String::new())),
+            }
+        }
+        ("POST", ["connections", id, "boot"]) => match parse_oid(id) {
+            Some(oid) => json(&Done {
+                done: boot(world, oid, "Booted by an administrator"),
+            }),
+            None => not_found(),
+        },
+        _ => not_found(),
+    }
+}
+
+async fn handle_request(
+    mut stream: TcpStream,
+    world: &Arc<World>,
+    options: &AdminOptions,
+) -> std::io::Result<()> {
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(request) => request?,
+        Err(_) => {
+            warn!("Admin request not sent within {:?}", REQUEST_TIMEOUT);
+            return respond(stream, "408 Request Timeout", "").await;
+        }
+    };
+
+    let request = String::from_utf8_lossy(&request);
+    let (request_line, headers) = request.split_once("\r\n").unwrap_or((&request[..], ""));
+    let (status, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        _ if !authorized(headers, &options.token) => {
+            warn!(target: "security", "Unauthorized admin request: {}", request_line);
+            ("401 Unauthorized", String::new())
+        }
+        [method, path] => match route(method, path, world, options).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                warn!("Admin request {} failed: {}", request_line, e);
+                ("500 Internal Server Error", String::new())
+            }
+        },
+        _ => ("400 Bad Request", String::new()),
+    };
+    respond(stream, status, &body).await
+}
+
+// Read up to the end of a request's headers, or MAX_REQUEST_LENGTH of it.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_LENGTH {
+        match stream.read(&mut buffer).await? {
+            0 => break,
+            n => request.extend_from_slice(&buffer[..n]),
+        }
+    }
+    Ok(request)
+}
+
+async fn respond(mut stream: TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,298 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::bandwidth::Traffic;
+use crate::world::{session_bindings, World};
+use value::Oid;
+
+// Requests are just a request line and headers, so there's no need to read more than this.
+const MAX_REQUEST_LENGTH: usize = 8192;
+
+// Players with the most traffic are labelled individually, up to this many; the rest are totalled
+// under player="other", so the number of series stays bounded however many players there are.
+const MAX_LABELLED_PLAYERS: usize = 100;
+
+/// Serve the world's metrics in the Prometheus text format at `/metrics` on `listener`, and
+/// who's logged in, as JSON, at `/sessions`.
+pub async fn listen(listener: TcpListener, world: Arc<World>) {
+    while let Ok((stream, peer)) = listener.accept().await {
+        info!("Metrics peer address: {}", peer);
+        let world = world.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &world).await {
+                warn!("Metrics request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, world: &Arc<World>) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_LENGTH {
+        match stream.read(&mut buffer).await? {
+            0 => break,
+            n => request.extend_from_slice(&buffer[..n]),
+        }
+    }
+
+    let request_line = String::from_utf8_lossy(&request);
+    let (status, content_type, body) =
+        match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+            ["GET", "/metrics"] => ("200 OK", "text/plain; version=0.0.4", render(world)),
+            ["GET", "/sessions"] => match render_sessions(world).await {
+                Ok(body) => ("200 OK", "application/json", body),
+                Err(e) => {
+                    warn!("Could not list sessions: {}", e);
+                    ("500 Internal Server Error", "text/plain", String::new())
+                }
+            },
+            _ => ("404 Not Found", "text/plain", String::new()),
+        };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn render_sessions(world: &Arc<World>) -> Result<String, anyhow::Error> {
+    let bindings = session_bindings(world).await?;
+    Ok(serde_json::to_string_pretty(&bindings)?)
+}
+
+// The player label and traffic of each room_player_bytes_total series.
+fn player_series(mut players: Vec<(Oid, Traffic)>) -> Vec<(String, Traffic)> {
+    players.sort_by_key(|(_, traffic)| {
+        std::cmp::Reverse(traffic.bytes_in.saturating_add(traffic.bytes_out))
+    });
+    let mut series: Vec<(String, Traffic)> = players
+        .iter()
+        .take(MAX_LABELLED_PLAYERS)
+        .map(|(player, traffic)| (player.id.to_string(), *traffic))
+        .collect();
+    if players.len() > MAX_LABELLED_PLAYERS {
+        let mut other = Traffic::default();
+        for (_, traffic) in &players[MAX_LABELLED_PLAYERS..] {
+            other.add(*traffic);
+        }
+        series.push((String::from("other"), other));
+    }
+    series
+}
+
+fn render(world: &Arc<World>) -> String {
+    let mut out = String::new();
+    let traffic = world.traffic();
+    let (hits, misses) = world.module_cache().stats();
+
+    writeln!(out, "# HELP room_connections Open connections.").unwrap();
+    writeln!(out, "# TYPE room_connections gauge").unwrap();
+    writeln!(out, "room_connections {}", world.connection_count()).unwrap();
+
+    writeln!(
+        out,
+        "# HELP room_connection_bytes_total Bytes received from and sent to connections."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_connection_bytes_total counter").unwrap();
+    writeln!(
+        out,
+        "room_connection_bytes_total{{direction=\"in\"}} {}",
+        traffic.bytes_in
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "room_connection_bytes_total{{direction=\"out\"}} {}",
+        traffic.bytes_out
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "# HELP room_player_bytes_total Bytes received from and sent to connections logged in to each player."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_player_bytes_total counter").unwrap();
+    for (player, traffic) in player_series(world.player_traffic()) {
+        writeln!(
+            out,
+            "room_player_bytes_total{{player=\"{}\",direction=\"in\"}} {}",
+            player, traffic.bytes_in
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "room_player_bytes_total{{player=\"{}\",direction=\"out\"}} {}",
+            player, traffic.bytes_out
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP room_transactions_total Transactions committed."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_transactions_total counter").unwrap();
+    writeln!(out, "room_transactions_total {}", world.transactions()).unwrap();
+
+    let retries = world.transaction_retries();
+    writeln!(
+        out,
+        "# HELP room_transaction_retries_total Transactions retried, for any reason."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_transaction_retries_total counter").unwrap();
+    writeln!(out, "room_transaction_retries_total {}", retries.retries).unwrap();
+    writeln!(
+        out,
+        "# HELP room_transaction_conflicts_total Transactions retried after conflicting with others."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_transaction_conflicts_total counter").unwrap();
+    writeln!(
+        out,
+        "room_transaction_conflicts_total {}",
+        retries.conflicts
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "# HELP room_transactions_exhausted_total Transactions given up on after using their retries."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_transactions_exhausted_total counter").unwrap();
+    writeln!(
+        out,
+        "room_transactions_exhausted_total {}",
+        retries.exhausted
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "# HELP room_module_cache_hits_total Compiled program cache hits."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_module_cache_hits_total counter").unwrap();
+    writeln!(out, "room_module_cache_hits_total {}", hits).unwrap();
+    writeln!(
+        out,
+        "# HELP room_module_cache_misses_total Compiled program cache misses."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_module_cache_misses_total counter").unwrap();
+    writeln!(out, "room_module_cache_misses_total {}", misses).unwrap();
+
+    if let Some(last_backup) = world.last_backup() {
+        writeln!(
+            out,
+            "# HELP room_backup_age_seconds Time since the world was last saved or checkpointed."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE room_backup_age_seconds gauge").unwrap();
+        let age = last_backup.elapsed().unwrap_or_default();
+        writeln!(out, "room_backup_age_seconds {}", age.as_secs()).unwrap();
+    }
+
+    let (hits, misses) = world.verb_results().stats();
+    writeln!(
+        out,
+        "# HELP room_verb_cache_hits_total Calls to cacheable verbs answered from the cache."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_verb_cache_hits_total counter").unwrap();
+    writeln!(out, "room_verb_cache_hits_total {}", hits).unwrap();
+    writeln!(
+        out,
+        "# HELP room_verb_cache_misses_total Calls to cacheable verbs which ran them."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_verb_cache_misses_total counter").unwrap();
+    writeln!(out, "room_verb_cache_misses_total {}", misses).unwrap();
+
+    let stats = world.stats();
+    writeln!(
+        out,
+        "# HELP room_verb_invocations_total Invocations of each verb."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_verb_invocations_total counter").unwrap();
+    for (oid, verb, stats) in &stats {
+        writeln!(
+            out,
+            "room_verb_invocations_total{{oid=\"{}\",verb=\"{}\"}} {}",
+            oid.id, verb, stats.invocations
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP room_verb_failures_total Invocations of each verb which trapped or ran out of resources."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_verb_failures_total counter").unwrap();
+    for (oid, verb, stats) in &stats {
+        writeln!(
+            out,
+            "room_verb_failures_total{{oid=\"{}\",verb=\"{}\"}} {}",
+            oid.id, verb, stats.failures
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP room_verb_seconds_total Wall time spent running each verb."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_verb_seconds_total counter").unwrap();
+    for (oid, verb, stats) in &stats {
+        writeln!(
+            out,
+            "room_verb_seconds_total{{oid=\"{}\",verb=\"{}\"}} {}",
+            oid.id,
+            verb,
+            stats.wall_time.as_secs_f64()
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP room_verb_fuel_total Fuel consumed by each verb."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_verb_fuel_total counter").unwrap();
+    for (oid, verb, stats) in &stats {
+        writeln!(
+            out,
+            "room_verb_fuel_total{{oid=\"{}\",verb=\"{}\"}} {}",
+            oid.id, verb, stats.fuel
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP room_verb_host_calls_total Builtins called by each verb."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE room_verb_host_calls_total counter").unwrap();
+    for (oid, verb, stats) in &stats {
+        writeln!(
+            out,
+            "room_verb_host_calls_total{{oid=\"{}\",verb=\"{}\"}} {}",
+            oid.id, verb, stats.host_calls
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,51 @@
+// The server's listeners: those players connect to, over websockets or telnet, and those for
+// operators, serving metrics and the admin API.
+
+pub mod admin;
+pub mod metrics;
+pub mod origin;
+pub mod proxy;
+pub mod systemd;
+pub mod telnet;
+pub mod websocket;
+
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+
+use crate::listeners::{Listener, ListenerConfig, Transport};
+use crate::world::World;
+use origin::OriginPolicy;
+use proxy::ProxyOptions;
+
+/// Register the listener `config` gives in the world, and accept connections on `listener` for it
+/// over its transport.
+pub fn serve(
+    world: &Arc<World>,
+    listener: TcpListener,
+    config: ListenerConfig,
+    origin_policy: &Arc<OriginPolicy>,
+    proxy: &Arc<ProxyOptions>,
+) {
+    world.listeners().add(Listener {
+        name: config.name.clone(),
+        address: config.address.clone(),
+        transport: config.transport,
+        entry: config.entry(),
+    });
+    match config.transport {
+        Transport::Websocket => tokio::spawn(websocket::listen(
+            listener,
+            config.name,
+            world.clone(),
+            origin_policy.clone(),
+            proxy.clone(),
+        )),
+        Transport::Telnet => tokio::spawn(telnet::listen(
+            listener,
+            config.name,
+            world.clone(),
+            proxy.clone(),
+        )),
+    };
+}
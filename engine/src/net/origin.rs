@@ -0,0 +1,61 @@
+use tungstenite::handshake::server::Request;
+
+/// Close code sent to websocket peers whose handshake named an origin or host which isn't allowed.
+/// Rejected browsers only get to see close codes, not the HTTP response, so this is how they can
+/// tell it apart from other failures.
+pub const REJECTED_CLOSE_CODE: u16 = 4003;
+
+/// Which websocket handshakes to accept, to guard against cross-site websocket hijacking (pages on
+/// other sites opening connections with a player's cookies) and DNS rebinding.
+///
+/// An empty list allows anything. Handshakes without an Origin header come from non-browser
+/// clients, which aren't subject to either attack, so they're allowed.
+#[derive(Clone, Debug, Default)]
+pub struct OriginPolicy {
+    /// Allowed Origin headers, e.g. `https://example.com`.
+    pub allowed_origins: Vec<String>,
+    /// Allowed Host headers. Entries without a port match any port.
+    pub allowed_hosts: Vec<String>,
+}
+
+impl OriginPolicy {
+    /// Check a handshake, returning why it's rejected if it is.
+    pub fn check(&self, request: &Request) -> Result<(), String> {
+        let header = |name| {
+            request
+                .headers()
+                .get(name)
+                .map(|h| h.to_str().unwrap_or_default().trim().to_lowercase())
+        };
+
+        if !self.allowed_origins.is_empty() {
+            if let Some(origin) = header("Origin") {
+                let origin = origin.trim_end_matches('/');
+                if !self
+                    .allowed_origins
+                    .iter()
+                    .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+                {
+                    return Err(format!("origin '{}' not allowed", origin));
+                }
+            }
+        }
+
+        if !self.allowed_hosts.is_empty() {
+            let host = header("Host").unwrap_or_default();
+            // (Taking care not to mistake part of a bracketed IPv6 address for a port.)
+            let hostname = match host.rsplit_once(':') {
+                Some((name, port)) if !port.contains(']') => name,
+                _ => host.as_str(),
+            };
+            if !self.allowed_hosts.iter().any(|allowed| {
+                allowed.eq_ignore_ascii_case(&host)
+                    || (!allowed.contains(':') && allowed.eq_ignore_ascii_case(hostname))
+            }) {
+                return Err(format!("host '{}' not allowed", host));
+            }
+        }
+
+        Ok(())
+    }
+}
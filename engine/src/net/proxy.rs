@@ -0,0 +1,124 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tungstenite::handshake::server::Request;
+
+// Every PROXY protocol v2 header starts with this.
+const SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+const VERSION_2: u8 = 0x20;
+const COMMAND_LOCAL: u8 = 0x00;
+const COMMAND_PROXY: u8 = 0x01;
+const FAMILY_INET: u8 = 0x10;
+const FAMILY_INET6: u8 = 0x20;
+
+// How long a peer has to send its PROXY header, so half-open connections don't linger.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How to find the real address of clients connecting through a load balancer or reverse proxy,
+/// rather than recording every peer as the proxy's address.
+#[derive(Clone, Debug, Default)]
+pub struct ProxyOptions {
+    /// Expect every TCP connection to begin with a PROXY protocol v2 header, as sent by e.g.
+    /// HAProxy or AWS NLB, and take the client's address from it. Connections without one are
+    /// refused, so only enable this when all traffic comes through the proxy.
+    pub proxy_protocol: bool,
+    /// Proxies whose X-Forwarded-For headers on websocket upgrades are believed.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl ProxyOptions {
+    /// The address of the client on the other end of `stream`, reading the PROXY header from it
+    /// first if one is expected. `peer` is the address the connection came from.
+    pub async fn client_address(
+        &self,
+        stream: &mut TcpStream,
+        peer: SocketAddr,
+    ) -> io::Result<SocketAddr> {
+        if !self.proxy_protocol {
+            return Ok(peer);
+        }
+        match tokio::time::timeout(HEADER_TIMEOUT, read_header(stream)).await {
+            Ok(Ok(Some(client))) => Ok(client),
+            // Health checks and the like, made by the proxy itself.
+            Ok(Ok(None)) => Ok(peer),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out waiting for PROXY header",
+            )),
+        }
+    }
+
+    /// The address of the client behind a websocket upgrade `request` arriving from `peer`.
+    /// If `peer` is a trusted proxy, this is the last address in X-Forwarded-For which isn't also
+    /// a trusted proxy. (Any before it could have been made up by the client.)
+    pub fn forwarded_for(&self, request: &Request, peer: SocketAddr) -> SocketAddr {
+        if !self.trusted_proxies.contains(&peer.ip()) {
+            return peer;
+        }
+        let forwarded: Vec<IpAddr> = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .filter_map(|a| a.trim().parse().ok())
+            .collect();
+        match forwarded
+            .into_iter()
+            .rev()
+            .find(|ip| !self.trusted_proxies.contains(ip))
+        {
+            // The forwarded header doesn't carry the client's port.
+            Some(ip) => SocketAddr::new(ip, 0),
+            None => peer,
+        }
+    }
+}
+
+// Read a PROXY protocol v2 header, returning the source address it gives, or None for LOCAL
+// connections or address families other than IP.
+async fn read_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut header = [0; 16];
+    stream.read_exact(&mut header).await?;
+    if header[..12] != SIGNATURE {
+        return Err(invalid("Missing PROXY protocol v2 signature"));
+    }
+    if header[12] & 0xF0 != VERSION_2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+    let command = header[12] & 0x0F;
+    let family = header[13] & 0xF0;
+    let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+
+    // The addresses, followed by any TLVs, which we read past and ignore.
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+
+    match (command, family) {
+        (COMMAND_LOCAL, _) => Ok(None),
+        (COMMAND_PROXY, FAMILY_INET) if length >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        (COMMAND_PROXY, FAMILY_INET6) if length >= 36 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        (COMMAND_PROXY, _) => Ok(None),
+        _ => Err(invalid("Unknown PROXY protocol command")),
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Mutex;
+
+use sd_notify::NotifyState;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+// Sockets passed by the service manager start at this descriptor, after stdin, stdout and stderr.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// The name a socket passed by the service manager is taken as the websocket listener by, if it's
+/// the only one and isn't named for any listener.
+pub const WEBSOCKET_LISTENER: &str = "websocket";
+
+/// Listening sockets the service manager bound for us, by the names given them with
+/// `FileDescriptorName=` in the socket unit. Listeners are taken from these where there are any,
+/// and bound to their configured addresses where there aren't.
+#[derive(Default)]
+pub struct Listeners {
+    sockets: Mutex<HashMap<String, std::net::TcpListener>>,
+}
+
+impl Listeners {
+    /// The sockets passed to this process by socket activation, if any, according to the
+    /// `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES` variables. They're left set, as unsetting
+    /// them isn't safe once other threads are running; any process which inherits them has another
+    /// pid, so `LISTEN_PID` tells it they're not its own.
+    pub fn from_env(known: &[&str]) -> Self {
+        let for_us = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let count: RawFd = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+        let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        if !for_us || count <= 0 {
+            return Listeners::default();
+        }
+
+        let mut names: Vec<String> = names.split(':').map(String::from).collect();
+        names.resize(count as usize, String::new());
+        if count == 1 && !known.contains(&names[0].as_str()) {
+            names[0] = WEBSOCKET_LISTENER.to_string();
+        }
+        let mut sockets = HashMap::new();
+        for (i, name) in names.into_iter().enumerate() {
+            // SAFETY: the service manager passes these descriptors to us alone, open, from
+            // SD_LISTEN_FDS_START on, and nothing else in the process has taken them.
+            let socket =
+                unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START + i as RawFd) };
+            if !known.contains(&name.as_str()) {
+                warn!("Ignoring socket {:?} passed by the service manager", name);
+                continue;
+            }
+            info!("Listener {} passed by the service manager", name);
+            sockets.insert(name, socket);
+        }
+        Listeners {
+            sockets: Mutex::new(sockets),
+        }
+    }
+
+    /// The listener `name`: the socket passed for it, if there was one, or else a new one bound to
+    /// `address`.
+    pub async fn take(&self, name: &str, address: &str) -> std::io::Result<TcpListener> {
+        let socket = self.sockets.lock().unwrap().remove(name);
+        match socket {
+            Some(socket) => {
+                socket.set_nonblocking(true)?;
+                TcpListener::from_std(socket)
+            }
+            None => TcpListener::bind(address).await,
+        }
+    }
+}
+
+/// Tell the service manager the server's state, if it's run by one which asked to be told (with
+/// `Type=notify`). Failing to tell it is only logged, as the server can run regardless.
+pub fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("Could not notify the service manager: {}", e);
+    }
+}
@@ -0,0 +1,197 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
+use tungstenite::Message;
+
+use crate::net::proxy::ProxyOptions;
+use crate::world::{
+    admit_message, disconnect, outbound_queue, receive_connection_message, record_received,
+    register_connection, World,
+};
+
+// Telnet commands we need to recognize in order to strip negotiation out of the input.
+const IAC: u8 = 255;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const WILL: u8 = 251;
+const DONT: u8 = 254;
+
+// Lines longer than this are cut off and delivered in pieces, so a client can't make us buffer
+// without bound.
+const MAX_LINE_LENGTH: usize = 8192;
+
+/// Accept plain TCP (telnet) connections on `listener`, registered in the world as `name`.
+/// Connections are registered in the world just like websocket ones, and each line received is
+/// passed to the listener's entry point. Messages sent to the connection are written out one per
+/// line.
+pub async fn listen(
+    listener: TcpListener,
+    name: String,
+    world: Arc<World>,
+    proxy: Arc<ProxyOptions>,
+) {
+    let name = Arc::new(name);
+    while let Ok((stream, _)) = listener.accept().await {
+        let peer = stream
+            .peer_addr()
+            .expect("connected streams should have a peer address");
+        info!("Telnet peer address: {}", peer);
+
+        // The connection's Oid is added once it's registered.
+        let span = info_span!("connection", %peer, transport = "telnet", id = field::Empty);
+        tokio::spawn(
+            handle_connection(peer, stream, name.clone(), world.clone(), proxy.clone())
+                .instrument(span),
+        );
+    }
+}
+
+async fn handle_connection(
+    peer: SocketAddr,
+    mut stream: TcpStream,
+    name: Arc<String>,
+    world: Arc<World>,
+    proxy: Arc<ProxyOptions>,
+) {
+    let peer = match proxy.client_address(&mut stream, peer).await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Refusing telnet connection from {}: {}", peer, e);
+            return;
+        }
+    };
+    let (tx, mut rx) = outbound_queue(&world);
+    let conn_oid = register_connection(world.clone(), tx, peer, None, false, &name)
+        .await
+        .expect("Failed to create connection object");
+    Span::current().record("id", &field::display(conn_oid.id));
+    info!("New telnet connection: {} to OID {:?}", peer, conn_oid);
+
+    let (mut reader, mut writer) = stream.into_split();
+
+    let process_outgoing = async move {
+        while let Some(message) = rx.next().await {
+            let line = match message {
+                Message::Text(s) => s.into_bytes(),
+                Message::Binary(b) => b,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if writer.write_all(&frame_line(&line)).await.is_err() {
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    };
+
+    let process_incoming = async {
+        let mut buffer = BytesMut::with_capacity(1024);
+        loop {
+            match reader.read_buf(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if !record_received(&world, conn_oid, n).await {
+                        break;
+                    }
+                    while let Some(line) = next_line(&mut buffer) {
+                        // A line which can't be checked isn't let through unchecked.
+                        match admit_message(&world, conn_oid, line.len()).await {
+                            Ok(true) => {}
+                            Ok(false) => continue,
+                            Err(e) => {
+                                error!(
+                                    "Could not check {:?} for flooding, dropping its line: {}",
+                                    conn_oid, e
+                                );
+                                continue;
+                            }
+                        }
+                        let task_world = world.clone();
+                        let dispatch = async move {
+                            receive_connection_message(&task_world, conn_oid, line).await
+                        };
+                        spawn_task(&world, conn_oid, "receive", dispatch).await;
+                    }
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = process_outgoing => {},
+        _ = process_incoming => {},
+    }
+
+    error!("Closed, deleting {:?}", conn_oid);
+    if let Err(e) = disconnect(world, conn_oid).await {
+        error!("Unable to destroy connection object {:?}: {}", conn_oid, e);
+    }
+}
+
+/// Take the next complete line off the front of `buffer`, without its terminator and with any
+/// telnet negotiation removed.
+fn next_line(buffer: &mut BytesMut) -> Option<Bytes> {
+    let line = match buffer.iter().position(|b| *b == b'\n') {
+        Some(end) => {
+            let line = buffer.split_to(end);
+            buffer.advance(1);
+            line
+        }
+        None if buffer.len() >= MAX_LINE_LENGTH => buffer.split_to(MAX_LINE_LENGTH),
+        None => return None,
+    };
+    let mut line = strip_telnet_commands(&line);
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Some(Bytes::from(line))
+}
+
+// We don't negotiate any options, so the commands a client sends are just dropped.
+fn strip_telnet_commands(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] != IAC {
+            output.push(input[i]);
+            i += 1;
+            continue;
+        }
+        match input.get(i + 1) {
+            // Escaped literal 255.
+            Some(&IAC) => {
+                output.push(IAC);
+                i += 2;
+            }
+            // Subnegotiation, runs until IAC SE.
+            Some(&SB) => {
+                i += 2;
+                while i < input.len() && !(input[i] == IAC && input.get(i + 1) == Some(&SE)) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            // Option negotiation, with an option code following.
+            Some(&(WILL..=DONT)) => i += 3,
+            _ => i += 2,
+        }
+    }
+    output
+}
+
+fn frame_line(line: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(line.len() + 2);
+    for b in line {
+        if *b == IAC {
+            framed.push(IAC);
+        }
+        framed.push(*b);
+    }
+    framed.extend_from_slice(b"\r\n");
+    framed
+}
@@ -0,0 +1,192 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::{future, pin_mut, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::accept_hdr_async;
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
+use tungstenite::handshake::server::{Request, Response};
+use tungstenite::http::HeaderValue;
+use tungstenite::protocol::frame::{coding::CloseCode, CloseFrame};
+use tungstenite::Message;
+
+use crate::catalog::preferred_locale;
+use crate::net::origin::{OriginPolicy, REJECTED_CLOSE_CODE};
+use crate::net::proxy::ProxyOptions;
+use crate::protocol::{self, FEDERATION_SUBPROTOCOL, RPC_SUBPROTOCOL};
+use crate::resume::RESUME_SUBPROTOCOL_PREFIX;
+use crate::world::{self, disconnect, handle_message, register_connection, World};
+
+async fn handle_connection(
+    peer: SocketAddr,
+    mut stream: TcpStream,
+    name: Arc<String>,
+    world: Arc<World>,
+    origin_policy: Arc<OriginPolicy>,
+    proxy: Arc<ProxyOptions>,
+) -> tungstenite::Result<()> {
+    let mut peer = match proxy.client_address(&mut stream, peer).await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Refusing connection from {}: {}", peer, e);
+            return Ok(());
+        }
+    };
+
+    // Peers which ask for the RPC subprotocol get structured requests/responses rather than having
+    // their frames passed raw to 'receive'. Other worlds get them too, with the federation
+    // subprotocol, if they bear the federation token.
+    let mut rpc = false;
+    let mut federated = false;
+    let mut rejection = None;
+    let mut locale = None;
+    let mut resume_token = None;
+    #[allow(clippy::result_large_err)]
+    let negotiate = |request: &Request, mut response: Response| {
+        peer = proxy.forwarded_for(request, peer);
+        rejection = origin_policy.check(request).err();
+        resume_token = request
+            .headers()
+            .get_all("Sec-WebSocket-Protocol")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .find_map(|p| p.trim().strip_prefix(RESUME_SUBPROTOCOL_PREFIX))
+            .map(String::from);
+        locale = request
+            .headers()
+            .get("Accept-Language")
+            .and_then(|h| h.to_str().ok())
+            .and_then(preferred_locale);
+        let requested = |subprotocol| {
+            request
+                .headers()
+                .get_all("Sec-WebSocket-Protocol")
+                .iter()
+                .filter_map(|h| h.to_str().ok())
+                .flat_map(|h| h.split(','))
+                .any(|p| p.trim() == subprotocol)
+        };
+        if requested(FEDERATION_SUBPROTOCOL) {
+            let authorization = request
+                .headers()
+                .get("Authorization")
+                .and_then(|h| h.to_str().ok());
+            if !world.federation().admits(authorization) {
+                rejection.get_or_insert_with(|| "federation token refused".to_string());
+                return Ok(response);
+            }
+            rpc = true;
+            federated = true;
+            response.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static(FEDERATION_SUBPROTOCOL),
+            );
+        } else if requested(RPC_SUBPROTOCOL) {
+            rpc = true;
+            response.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static(RPC_SUBPROTOCOL),
+            );
+        }
+        Ok(response)
+    };
+    let mut ws_stream = accept_hdr_async(stream, negotiate)
+        .await
+        .expect("Failed to accept");
+
+    // Rejected peers are closed before they're registered, so nothing they send is processed.
+    if let Some(reason) = rejection {
+        warn!(target: "security", "Rejected websocket connection from {}: {}", peer, reason);
+        let close = CloseFrame {
+            code: CloseCode::from(REJECTED_CLOSE_CODE),
+            reason: reason.into(),
+        };
+        return ws_stream.close(Some(close)).await;
+    }
+
+    // Create a queue stream from tx->rx, bounded by the world's outbound policy, and let the world
+    // own the tx.
+    let (tx, rx) = world::outbound_queue(&world);
+    let sender = tx.clone();
+    let resumed =
+        resume_token.and_then(|token| world::resume(&world, &token, tx.clone(), peer, rpc));
+    let conn_oid = match resumed {
+        Some(conn_oid) => conn_oid,
+        None => {
+            let conn_oid = register_connection(world.clone(), tx, peer, locale, rpc, &name)
+                .await
+                .expect("Failed to create connection object");
+            info!("New WebSocket connection: {} to OID {:?}", peer, conn_oid);
+            if federated {
+                world::mark_federated(&world, conn_oid);
+            }
+            conn_oid
+        }
+    };
+    // Structured clients are sent a token to resume with, should they drop: a new one each time
+    // they connect or resume, as each is good only once.
+    if let (true, Some(token)) = (rpc, world::resume_token(&world, conn_oid)) {
+        let message = Message::Binary(protocol::encode_resume(&token));
+        if let Err(e) = world::send_connection_message(world.clone(), conn_oid, message).await {
+            error!("Unable to send {:?} its resume token: {}", conn_oid, e);
+        }
+    }
+    Span::current().record("id", &field::display(conn_oid.id));
+
+    // Split the stream into inbound/outbound...
+    let (outgoing, incoming) = ws_stream.split();
+
+    // Create a future to forward messages from 'rx' into the outbound.
+    let receive_forward = rx.map(Ok).forward(outgoing);
+
+    // And create a future to handle inbound messages.
+    let process_incoming = incoming.for_each(|msg| async {
+        handle_message(conn_oid, msg, world.clone(), rpc).await;
+    });
+
+    pin_mut!(process_incoming, receive_forward);
+
+    // Perform the selection on both inbound/outbound.
+    future::select(receive_forward, process_incoming).await;
+
+    // Kept for the client to resume, if it may; otherwise gone. (Which may have been done
+    // already if the peer went away uncleanly.)
+    if !world::detach(&world, conn_oid, &sender) {
+        if let Err(e) = disconnect(world.clone(), conn_oid).await {
+            error!("Unable to destroy connection object {:?}: {}", conn_oid, e);
+        }
+    }
+    Ok(())
+}
+
+/// Accept websocket connections on `listener`, for the listener called `name`.
+pub async fn listen(
+    listener: TcpListener,
+    name: String,
+    world: Arc<World>,
+    origin_policy: Arc<OriginPolicy>,
+    proxy: Arc<ProxyOptions>,
+) {
+    let name = Arc::new(name);
+    while let Ok((stream, _)) = listener.accept().await {
+        let peer = stream
+            .peer_addr()
+            .expect("connected streams should have a peer address");
+        info!("Peer address: {}", peer);
+
+        // The connection's Oid is added once it's registered.
+        let span = info_span!("connection", %peer, transport = "websocket", id = field::Empty);
+        tokio::spawn(
+            handle_connection(
+                peer,
+                stream,
+                name.clone(),
+                world.clone(),
+                origin_policy.clone(),
+                proxy.clone(),
+            )
+            .instrument(span),
+        );
+    }
+}
@@ -17,16 +17,98 @@ pub struct SlotDef {
     pub name: String,
 }
 
+/// Permission to read a slot's value.
+pub const SLOT_READ: u8 = 1;
+/// Permission to write, or move, a slot.
+pub const SLOT_WRITE: u8 = 2;
+/// Permission to invoke the program in a slot as a verb.
+pub const SLOT_EXECUTE: u8 = 4;
+
+/// What's kept alongside a slot's value: MOO-style property permissions, and when it was made and
+/// last written. A slot's owner may do anything with it, and anyone else what its flags allow.
+/// Slots with no owner are open to anyone who knows their key, as all slots once were.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct SlotMeta {
+    pub owner: Option<Oid>,
+    /// SLOT_READ, SLOT_WRITE and SLOT_EXECUTE, for those other than the owner.
+    pub flags: u8,
+    /// In nanoseconds since the Unix epoch, as Timestamps are.
+    pub created: i64,
+    pub modified: i64,
+}
+
+impl SlotMeta {
+    /// The metadata of a slot made at `now`, with no owner.
+    pub fn new(now: i64) -> Self {
+        SlotMeta {
+            owner: None,
+            flags: SLOT_READ | SLOT_EXECUTE,
+            created: now,
+            modified: now,
+        }
+    }
+
+    /// Whether `player` (None for a connection which isn't logged in) may do what `flag` permits.
+    pub fn allows(&self, player: Option<Oid>, flag: u8) -> bool {
+        match self.owner {
+            None => true,
+            Some(owner) => player == Some(owner) || self.flags & flag != 0,
+        }
+    }
+
+    /// Flags written as MOO writes them, e.g. "rx".
+    pub fn flags_string(flags: u8) -> String {
+        [(SLOT_READ, 'r'), (SLOT_WRITE, 'w'), (SLOT_EXECUTE, 'x')]
+            .iter()
+            .filter(|(flag, _)| flags & flag != 0)
+            .map(|(_, c)| c)
+            .collect()
+    }
+
+    /// The flags in a string such as "rx"; None if it has any other characters.
+    pub fn parse_flags(flags: &str) -> Option<u8> {
+        flags.chars().try_fold(0, |flags, c| match c {
+            'r' => Some(flags | SLOT_READ),
+            'w' => Some(flags | SLOT_WRITE),
+            'x' => Some(flags | SLOT_EXECUTE),
+            _ => None,
+        })
+    }
+
+    /// A Vector of [name, value] pairs: "owner" (if it has one), "flags", "created" and
+    /// "modified".
+    pub fn info(&self) -> Value {
+        let field = |name: &str, value| Value::Vector(vec![Value::String(name.into()), value]);
+        let mut info = vec![];
+        if let Some(owner) = self.owner {
+            info.push(field("owner", Value::IdKey(owner)));
+        }
+        info.push(field(
+            "flags",
+            Value::String(SlotMeta::flags_string(self.flags).into()),
+        ));
+        info.push(field("created", Value::Timestamp(self.created)));
+        info.push(field("modified", Value::Timestamp(self.modified)));
+        Value::Vector(info)
+    }
+}
+
 /// Associate OIDs with slots.
 /// Objects are bags of slots.
 pub trait ObjDBHandle {
-    /// Set a slot on an object
+    /// Set a slot on an object, keeping the metadata it has, if it's already set.
     ///
     /// * `location` what object to set the slot on
     /// * `key` A unique ID which masks visibility on the slot.
     /// * `name` the name of the slot
     /// * `value` the value of the slot
-    fn set_slot(&self, location: Oid, key: Oid, name: String, value: &Value);
+    fn set_slot(
+        &self,
+        location: Oid,
+        key: Oid,
+        name: String,
+        value: &Value,
+    ) -> BoxFuture<'_, Result<(), Error>>;
 
     /// Get a slot from an object
     ///
@@ -35,6 +117,41 @@ pub trait ObjDBHandle {
     /// * `name` the name of the slot
     fn get_slot(&self, location: Oid, key: Oid, name: String) -> BoxFuture<Result<Value, Error>>;
 
+    /// Get the metadata kept with a slot, if it has any. SlotDoesNotExist if it isn't set.
+    ///
+    /// * `location` what object the slot is on
+    /// * `key` The unique ID which masks visibility on the slot.
+    /// * `name` the name of the slot
+    fn get_slot_meta(
+        &self,
+        location: Oid,
+        key: Oid,
+        name: String,
+    ) -> BoxFuture<Result<Option<SlotMeta>, Error>>;
+
+    /// Keep `meta` with a slot, leaving its value as it is. SlotDoesNotExist if it isn't set.
+    ///
+    /// * `location` what object the slot is on
+    /// * `key` The unique ID which masks visibility on the slot.
+    /// * `name` the name of the slot
+    /// * `meta` its owner, permissions and timestamps
+    fn set_slot_meta(
+        &self,
+        location: Oid,
+        key: Oid,
+        name: String,
+        meta: &SlotMeta,
+    ) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Find the slots whose values refer to an object, as an IdKey or within a Vector. Only
+    /// slots set while the reference index is kept are found; SlotDoesNotExist if it isn't kept.
+    ///
+    /// * `oid` the object referred to
+    fn find_references(
+        &self,
+        oid: Oid,
+    ) -> Result<Box<dyn tokio_stream::Stream<Item = SlotDef> + Send + Unpin>, Error>;
+
     /// Find all slots defined for an object
     ///
     /// * `location` what object to get the slot from
@@ -44,11 +161,51 @@ pub trait ObjDBHandle {
         location: Oid,
         key: Oid,
     ) -> Result<Box<dyn tokio_stream::Stream<Item = SlotDef> + Send + Unpin>, Error>;
+
+    /// Move a slot, with its value, to another object, key or name.
+    ///
+    /// * `from` the slot to move. SlotDoesNotExist if it isn't set.
+    /// * `to` where to move it. NameTaken if there's already a slot there.
+    fn move_slot(&self, from: SlotDef, to: SlotDef) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Remove all slots from an object, under every key.
+    ///
+    /// * `location` the object to destroy
+    fn destroy_object(&self, location: Oid);
 }
 
+/// Slots streamed by an AdminHandle have any blobs they hold read back in whole as Binary values.
+/// An item is InternalError if the database couldn't be read, after which there are no more.
 pub trait AdminHandle {
     fn dump_slots(
         &self,
         location: Oid,
-    ) -> Result<Box<dyn tokio_stream::Stream<Item = (SlotDef, Value)> + Send + Unpin>, Error>;
+    ) -> Result<
+        Box<dyn tokio_stream::Stream<Item = Result<(SlotDef, Value), Error>> + Send + Unpin>,
+        Error,
+    >;
+
+    /// Find all slots either located on `oid` or defined with it as their key, across the whole
+    /// database. This is a full scan, so it's only for occasional administrative use.
+    fn slots_involving(
+        &self,
+        oid: Oid,
+    ) -> Result<
+        Box<dyn tokio_stream::Stream<Item = Result<(SlotDef, Value), Error>> + Send + Unpin>,
+        Error,
+    >;
+
+    /// Every object with any slots, in Oid order. Also a full scan.
+    fn objects(&self) -> Result<Box<dyn tokio_stream::Stream<Item = Oid> + Send + Unpin>, Error>;
+
+    /// Every slot in the database. Also a full scan.
+    fn dump_all_slots(
+        &self,
+    ) -> Result<
+        Box<dyn tokio_stream::Stream<Item = Result<(SlotDef, Value), Error>> + Send + Unpin>,
+        Error,
+    >;
+
+    /// Remove a slot.
+    fn clear_slot(&self, slot: SlotDef);
 }
@@ -0,0 +1,469 @@
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Error};
+use s3::creds::Credentials;
+use s3::serde_types::Part;
+use s3::{Bucket, Region};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::retention::RetentionPolicy;
+
+/// Snapshots at or below this size are uploaded with a single PUT, larger ones are uploaded in
+/// parts of this size. S3 requires parts (other than the last) to be at least 5MiB.
+pub const MULTIPART_PART_SIZE: usize = 16 * 1024 * 1024;
+
+const CONTENT_TYPE: &str = "application/json";
+
+const ARCHIVE_CONTENT_TYPE: &str = "application/zstd";
+
+/// The metadata each snapshot is written with holding the SHA-256 of its contents, in hex; read
+/// back without its "x-amz-meta-" prefix.
+const DIGEST_HEADER: &str = "x-amz-meta-sha256";
+const DIGEST_METADATA: &str = "sha256";
+
+/// Archives are read back this many bytes at a time.
+const READ_RANGE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The bucket and key named by `url`, s3://bucket/key.
+pub fn parse_s3_url(url: &str) -> Result<(String, String), Error> {
+    match url
+        .strip_prefix("s3://")
+        .and_then(|path| path.split_once('/'))
+    {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+            Ok((bucket.to_string(), key.to_string()))
+        }
+        _ => Err(anyhow!("{} isn't s3://bucket/key", url)),
+    }
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// A snapshot as read back: its key, its contents, and whether they match the digest it was
+/// written with. Snapshots written before they had digests have none to check them against.
+pub struct Snapshot {
+    pub key: String,
+    pub payload: Vec<u8>,
+    pub intact: Option<bool>,
+}
+
+/// Server-side encryption to request for uploaded snapshots.
+#[derive(Clone, Debug)]
+pub enum ServerSideEncryption {
+    /// Keys managed by the object store (SSE-S3, "AES256").
+    S3Managed,
+    /// Keys managed by KMS, with the given key or the account default if none.
+    Kms(Option<String>),
+}
+
+/// Configuration for dumping to an S3-compatible object store.
+/// Credentials come from the usual AWS environment variables or profile.
+#[derive(Clone, Debug)]
+pub struct ObjectStoreOptions {
+    pub bucket: String,
+    pub region: String,
+    /// Endpoint for S3-compatible stores other than AWS. Implies path-style addressing.
+    pub endpoint: Option<String>,
+    /// Prefix under which snapshots are written.
+    pub prefix: String,
+    pub encryption: Option<ServerSideEncryption>,
+    /// Which snapshots to retain; the rest are deleted after each save.
+    pub retention: RetentionPolicy,
+}
+
+/// Snapshots stored in an object store bucket, each a single json archive of all dumped slots
+/// named by the time it was taken.
+pub struct ObjectStore {
+    bucket: Bucket,
+    // Encryption headers are only valid on the requests which create an object, not on the
+    // individual parts of a multipart upload, so those go through a second handle with them set.
+    create_bucket: Bucket,
+    options: ObjectStoreOptions,
+}
+
+impl ObjectStore {
+    pub fn open(options: &ObjectStoreOptions) -> Result<Self, Error> {
+        let credentials = Credentials::default()?;
+        let bucket = match &options.endpoint {
+            Some(endpoint) => Bucket::new(
+                &options.bucket,
+                Region::Custom {
+                    region: options.region.clone(),
+                    endpoint: endpoint.clone(),
+                },
+                credentials,
+            )?
+            .with_path_style(),
+            None => Bucket::new(&options.bucket, options.region.parse()?, credentials)?,
+        };
+
+        let mut create_bucket = bucket.clone();
+        match &options.encryption {
+            Some(ServerSideEncryption::S3Managed) => {
+                create_bucket.add_header("x-amz-server-side-encryption", "AES256");
+            }
+            Some(ServerSideEncryption::Kms(key_id)) => {
+                create_bucket.add_header("x-amz-server-side-encryption", "aws:kms");
+                if let Some(key_id) = key_id {
+                    create_bucket.add_header("x-amz-server-side-encryption-aws-kms-key-id", key_id);
+                }
+            }
+            None => {}
+        }
+
+        Ok(ObjectStore {
+            bucket,
+            create_bucket,
+            options: options.clone(),
+        })
+    }
+
+    fn snapshot_prefix(&self) -> String {
+        format!("{}snapshot-", self.options.prefix)
+    }
+
+    // When the snapshot at `key` was taken, in milliseconds since the Unix epoch.
+    fn taken(&self, key: &str) -> Option<u64> {
+        key.strip_prefix(&self.snapshot_prefix())?
+            .strip_suffix(".json")?
+            .parse()
+            .ok()
+    }
+
+    /// All snapshot keys, oldest first.
+    async fn snapshots(&self) -> Result<Vec<String>, Error> {
+        let mut keys: Vec<String> = self
+            .bucket
+            .list(self.snapshot_prefix(), None)
+            .await?
+            .into_iter()
+            .flat_map(|page| page.contents.into_iter().map(|object| object.key))
+            .collect();
+        // Timestamps are zero padded, so these sort chronologically.
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn get_snapshot(&self, key: &str) -> Result<Snapshot, Error> {
+        info!("Loading snapshot s3://{}/{}", self.options.bucket, key);
+        let (head, _) = self.bucket.head_object(key).await?;
+        let expected = head
+            .metadata
+            .and_then(|metadata| metadata.get(DIGEST_METADATA).cloned());
+        let payload = self.bucket.get_object(key).await?.to_vec();
+        Ok(Snapshot {
+            key: key.to_string(),
+            intact: expected.map(|expected| hex_digest(&payload) == expected),
+            payload,
+        })
+    }
+
+    pub async fn latest_snapshot(&self) -> Result<Option<Snapshot>, Error> {
+        match self.snapshots().await?.pop() {
+            Some(key) => Ok(Some(self.get_snapshot(&key).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Write a snapshot, with the digest of its contents, returning its key.
+    pub async fn put_snapshot(&self, payload: Vec<u8>) -> Result<String, Error> {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let key = format!("{}{:020}.json", self.snapshot_prefix(), millis);
+        info!(
+            "Writing snapshot s3://{}/{} ({} bytes)",
+            self.options.bucket,
+            key,
+            payload.len()
+        );
+        let mut create_bucket = self.create_bucket.clone();
+        create_bucket.add_header(DIGEST_HEADER, &hex_digest(&payload));
+
+        if payload.len() <= MULTIPART_PART_SIZE {
+            create_bucket
+                .put_object_with_content_type(&key, &payload, CONTENT_TYPE)
+                .await?;
+            return Ok(key);
+        }
+
+        let upload_id = create_bucket
+            .initiate_multipart_upload(&key, CONTENT_TYPE)
+            .await?
+            .upload_id;
+        match self.put_parts(&key, &upload_id, &payload).await {
+            Ok(parts) => {
+                self.bucket
+                    .complete_multipart_upload(&key, &upload_id, parts)
+                    .await?;
+                Ok(key)
+            }
+            Err(e) => {
+                // Don't leave the parts around to be billed for.
+                self.bucket.abort_upload(&key, &upload_id).await?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn put_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        payload: &[u8],
+    ) -> Result<Vec<Part>, Error> {
+        let mut parts = vec![];
+        for (i, chunk) in payload.chunks(MULTIPART_PART_SIZE).enumerate() {
+            let part = self
+                .bucket
+                .put_multipart_chunk(chunk.to_vec(), key, i as u32 + 1, upload_id, CONTENT_TYPE)
+                .await?;
+            parts.push(part);
+        }
+        Ok(parts)
+    }
+
+    /// Begin writing the archive `key` in parts of MULTIPART_PART_SIZE, each given to `put_part`
+    /// as it's ready, returning the upload's id.
+    pub async fn begin_upload(&self, key: &str) -> Result<String, Error> {
+        info!("Writing archive s3://{}/{}", self.options.bucket, key);
+        Ok(self
+            .create_bucket
+            .initiate_multipart_upload(key, ARCHIVE_CONTENT_TYPE)
+            .await?
+            .upload_id)
+    }
+
+    /// Write the `number`th part, counting from 1, of the upload `upload_id`.
+    pub async fn put_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: u32,
+        chunk: Vec<u8>,
+    ) -> Result<Part, Error> {
+        Ok(self
+            .bucket
+            .put_multipart_chunk(chunk, key, number, upload_id, ARCHIVE_CONTENT_TYPE)
+            .await?)
+    }
+
+    /// Finish the upload `upload_id` with its `parts`, or if it failed, discard them.
+    pub async fn finish_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Result<Vec<Part>, Error>,
+    ) -> Result<(), Error> {
+        match parts {
+            Ok(parts) => {
+                self.bucket
+                    .complete_multipart_upload(key, upload_id, parts)
+                    .await?;
+                Ok(())
+            }
+            Err(e) => {
+                // Don't leave the parts around to be billed for.
+                self.bucket.abort_upload(key, upload_id).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// A reader of the object `key`, which fetches it a range at a time as it's read.
+    pub async fn reader(self: &Arc<Self>, key: &str) -> Result<ObjectReader, Error> {
+        let (head, _) = self.bucket.head_object(key).await?;
+        let size = head
+            .content_length
+            .ok_or_else(|| anyhow!("s3://{}/{} has no length", self.options.bucket, key))?;
+        info!(
+            "Reading archive s3://{}/{} ({} bytes)",
+            self.options.bucket, key, size
+        );
+        let (store, key) = (self.clone(), key.to_string());
+        let runtime = tokio::runtime::Handle::current();
+        let fetch = move |start, end| {
+            let range = runtime
+                .block_on(store.bucket.get_object_range(&key, start, Some(end)))
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            Ok(range.to_vec())
+        };
+        Ok(ObjectReader::new(size as u64, READ_RANGE_SIZE, fetch))
+    }
+
+    /// Delete the snapshots the retention policy doesn't keep, but only once the snapshot just
+    /// written, `written`, is listed and has been found whole (`size` bytes), so that pruning
+    /// can't leave nothing but snapshots which won't load. Snapshots whose keys don't say when
+    /// they were taken are never deleted.
+    pub async fn prune(&self, written: &str, size: usize) -> Result<(), Error> {
+        let retention = &self.options.retention;
+        if !retention.prunes() {
+            return Ok(());
+        }
+        let (head, _) = self.bucket.head_object(written).await?;
+        if head.content_length != Some(size as i64) {
+            warn!(
+                "Snapshot s3://{}/{} is {:?} bytes rather than {}, not pruning",
+                self.options.bucket, written, head.content_length, size
+            );
+            return Ok(());
+        }
+        let snapshots = self.snapshots().await?;
+        if !snapshots.iter().any(|key| key == written) {
+            warn!(
+                "Snapshot s3://{}/{} isn't listed yet, not pruning",
+                self.options.bucket, written
+            );
+            return Ok(());
+        }
+        let dated: Vec<(&String, u64)> = snapshots
+            .iter()
+            .filter_map(|key| Some((key, self.taken(key)?)))
+            .collect();
+        let taken: Vec<u64> = dated.iter().map(|(_, taken)| *taken).collect();
+        let retained = retention.retained(&taken);
+        for (i, (key, _)) in dated.iter().enumerate() {
+            if !retained.contains(&i) {
+                info!("Pruning snapshot s3://{}/{}", self.options.bucket, key);
+                self.bucket.delete_object(key).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads an object READ_RANGE_SIZE bytes at a time, as it's read, rather than all at once. Reads
+/// block on the runtime it was made on, so it's to be read on a blocking thread, e.g. one from
+/// `spawn_blocking`.
+pub struct ObjectReader {
+    // Fetches the object's bytes from the first offset to the second, inclusive.
+    fetch: Box<dyn FnMut(u64, u64) -> io::Result<Vec<u8>> + Send>,
+    size: u64,
+    range_size: u64,
+    // Where the next range starts, and the range being read.
+    offset: u64,
+    range: Vec<u8>,
+    position: usize,
+}
+
+impl ObjectReader {
+    fn new(
+        size: u64,
+        range_size: u64,
+        fetch: impl FnMut(u64, u64) -> io::Result<Vec<u8>> + Send + 'static,
+    ) -> Self {
+        ObjectReader {
+            fetch: Box::new(fetch),
+            size,
+            range_size,
+            offset: 0,
+            range: vec![],
+            position: 0,
+        }
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.range.len() {
+            if self.offset >= self.size {
+                return Ok(0);
+            }
+            let end = (self.offset + self.range_size).min(self.size) - 1;
+            let range = (self.fetch)(self.offset, end)?;
+            if range.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.offset += range.len() as u64;
+            self.range = range;
+            self.position = 0;
+        }
+        let n = buf.len().min(self.range.len() - self.position);
+        buf[..n].copy_from_slice(&self.range[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn s3_urls_name_a_bucket_and_key() {
+        assert_eq!(
+            parse_s3_url("s3://backups/worlds/main.tar.zst").unwrap(),
+            ("backups".to_string(), "worlds/main.tar.zst".to_string())
+        );
+        for url in [
+            "backups/main",
+            "s3://backups",
+            "s3://backups/",
+            "s3:///main",
+            "s3://",
+        ] {
+            assert!(parse_s3_url(url).is_err(), "{}", url);
+        }
+    }
+
+    // The ranges a reader's fetched, first to last.
+    type Fetched = Arc<Mutex<Vec<(u64, u64)>>>;
+
+    // A reader of `object`, `range_size` bytes at a time, noting each range it fetches.
+    fn reader(object: &[u8], range_size: u64) -> (ObjectReader, Fetched) {
+        let fetched = Arc::new(Mutex::new(vec![]));
+        let (object, noted) = (object.to_vec(), fetched.clone());
+        let reader = ObjectReader::new(object.len() as u64, range_size, move |start, end| {
+            noted.lock().unwrap().push((start, end));
+            Ok(object[start as usize..=end as usize].to_vec())
+        });
+        (reader, fetched)
+    }
+
+    #[test]
+    fn objects_are_read_a_range_at_a_time() {
+        let object: Vec<u8> = (0..10).collect();
+        let (mut reader, fetched) = reader(&object, 4);
+        let mut buf = [0; 3];
+        let mut read = vec![];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(read, object);
+        assert_eq!(*fetched.lock().unwrap(), vec![(0, 3), (4, 7), (8, 9)]);
+    }
+
+    #[test]
+    fn empty_objects_are_never_fetched() {
+        let (mut reader, fetched) = reader(&[], 4);
+        assert_eq!(reader.read(&mut [0; 8]).unwrap(), 0);
+        assert!(fetched.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn objects_shorter_than_their_length_fail() {
+        let mut reader = ObjectReader::new(8, 4, |_, _| Ok(vec![]));
+        let e = reader.read(&mut [0; 8]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn fetch_failures_are_read_errors() {
+        let mut reader = ObjectReader::new(8, 4, |_, _| Err(io::Error::other("unreachable")));
+        assert_eq!(
+            reader.read(&mut [0; 8]).unwrap_err().to_string(),
+            "unreachable"
+        );
+    }
+}
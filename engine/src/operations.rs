@@ -0,0 +1,317 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+use tracing::info;
+
+use crate::changes::Versionstamp;
+use crate::database::Storage;
+use crate::dump::DumpTarget;
+use crate::object_store::ObjectStore;
+use crate::refactor::Refactor;
+use crate::wasm_vm::WasmVM;
+use crate::world::{
+    backup_world, dependency_report, dry_run_dispatch, dump_objects, erase_player_data,
+    export_graph, export_player_data, export_world, import_world, index_references, list_builtins,
+    player_stats, refactor_programs, restore_world, save, save_all, tagged_objects, ErasureMode,
+    World, WorldOptions,
+};
+use value::{Oid, Value};
+
+/// A whole-world operation, which the server runs and exits after rather than serving the world.
+/// Those which report what they did print it as JSON, or write it to the file they're given.
+pub enum Operation {
+    /// Write every object in the world to an archive.
+    Dump { out: PathBuf },
+    /// Restore a world from an archive into an empty database, then dump it to the dump target.
+    Load { input: PathBuf },
+    /// Stream an archive of the world, or of what's changed since `since`, to object storage.
+    Backup {
+        store: Arc<ObjectStore>,
+        key: String,
+        since: Option<Versionstamp>,
+    },
+    /// Restore a world from an archive in object storage, then dump it to the dump target.
+    Restore {
+        store: Arc<ObjectStore>,
+        key: String,
+    },
+    /// Search every program's source, replacing what's found if the refactor says to, and dump
+    /// the world if anything was.
+    Refactor {
+        refactor: Refactor,
+        report: Option<PathBuf>,
+    },
+    /// Mirror the world's objects and the references between them to a directory, as a graph.
+    Graph { out: PathBuf, incremental: bool },
+    /// Add every slot to the index of references.
+    IndexReferences,
+    /// Report the slots each program has been recorded reading and writing.
+    Dependencies {
+        report: Option<PathBuf>,
+        clear: bool,
+    },
+    /// Write everything kept about a player to a file.
+    ExportPlayer { player: Oid, path: PathBuf },
+    /// Erase everything kept about a player, then dump the objects it was erased from.
+    ErasePlayer { player: Oid, mode: ErasureMode },
+    /// Report the usage counters kept for a player.
+    PlayerStats { player: Oid },
+    /// Report the objects with a tag.
+    TagQuery { tag: String },
+    /// Report the builtins verbs can call.
+    ListBuiltins,
+}
+
+impl Operation {
+    /// Whether it's run on a world with nothing in it, before the dump is loaded or the world
+    /// bootstrapped, rather than on the world the server would serve.
+    pub fn needs_empty_world(&self) -> bool {
+        matches!(self, Operation::Load { .. } | Operation::Restore { .. })
+    }
+}
+
+/// Run `operation` on `world`, dumping what it changes to `dump_target`.
+pub async fn run_operation(
+    world: &Arc<World>,
+    operation: &Operation,
+    dump_target: &DumpTarget,
+) -> Result<(), Error> {
+    match operation {
+        Operation::Dump { out } => {
+            let (objects, slots) = export_world(world, out).await?;
+            info!(
+                "Dumped {} objects ({} slots) to {}",
+                objects,
+                slots,
+                out.display()
+            );
+        }
+        Operation::Load { input } => {
+            let (objects, slots) = import_world(world, input).await?;
+            info!(
+                "Loaded {} objects ({} slots) from {}",
+                objects,
+                slots,
+                input.display()
+            );
+            save_all(world.clone(), dump_target).await?;
+        }
+        Operation::Backup { store, key, since } => {
+            let report = backup_world(world, store, key, *since).await?;
+            print_report(&report, None)?;
+        }
+        Operation::Restore { store, key } => {
+            let report = restore_world(world, store.clone(), key).await?;
+            info!(
+                "Restored {} objects ({} slots) from {}",
+                report.objects, report.slots, key
+            );
+            save_all(world.clone(), dump_target).await?;
+        }
+        Operation::Refactor { refactor, report } => {
+            let refactored = refactor_programs(world, refactor).await?;
+            print_report(&refactored, report.as_deref())?;
+            if refactor.apply {
+                save_all(world.clone(), dump_target).await?;
+            }
+            if !refactored.succeeded() {
+                return Err(anyhow!("Some replacements weren't made; see the report"));
+            }
+        }
+        Operation::Graph { out, incremental } => {
+            let report = export_graph(world, out, *incremental).await?;
+            print_report(&report, None)?;
+        }
+        Operation::IndexReferences => {
+            let (objects, slots) = index_references(world).await?;
+            info!(
+                "Indexed the references of {} objects ({} slots)",
+                objects, slots
+            );
+        }
+        Operation::Dependencies { report, clear } => {
+            let dependencies = dependency_report(world, *clear).await?;
+            print_report(&dependencies, report.as_deref())?;
+        }
+        Operation::ExportPlayer { player, path } => {
+            let export = export_player_data(world, *player).await?;
+            std::fs::write(path, serde_json::to_vec_pretty(&export)?)?;
+            info!("Exported player {} to {}", player.id, path.display());
+        }
+        Operation::ErasePlayer { player, mode } => {
+            let report = erase_player_data(world, *player, *mode).await?;
+            print_report(&report, None)?;
+            // Dump the objects again, so the erased slots aren't restored by the next load.
+            save(world.clone(), dump_target, &report.objects).await?;
+            if !report.verified() {
+                return Err(anyhow!(
+                    "Data remains for player {} after erasure",
+                    player.id
+                ));
+            }
+        }
+        Operation::PlayerStats { player } => {
+            print_report(&player_stats(world, *player).await?, None)?;
+        }
+        Operation::TagQuery { tag } => {
+            print_report(&tagged_objects(world, tag).await?, None)?;
+        }
+        Operation::ListBuiltins => {
+            print_report(&list_builtins(world)?, None)?;
+        }
+    }
+    Ok(())
+}
+
+/// An archive of the world, as `dump` or `backup` wrote it, to look at the world as it was then.
+pub enum Archive {
+    /// A file `dump` wrote.
+    File(PathBuf),
+    /// A full archive `backup` wrote to object storage.
+    Stored {
+        store: Arc<ObjectStore>,
+        key: String,
+    },
+}
+
+/// Open a copy of the world as `archive` has it, in a temporary database which is thrown away with
+/// it. Nothing done to it affects the real world, and nothing it does is journaled or audited.
+pub async fn open_archive(archive: &Archive, options: WorldOptions) -> Result<Arc<World>, Error> {
+    let world = Arc::new(World::new(WorldOptions {
+        storage: Storage::Temporary,
+        journal: None,
+        verb_audit: None,
+        changes: None,
+        #[cfg(feature = "faults")]
+        faults: None,
+        ..options
+    }));
+    match archive {
+        Archive::File(path) => {
+            import_world(&world, path).await?;
+        }
+        Archive::Stored { store, key } => {
+            restore_world(&world, store.clone(), key).await?;
+        }
+    }
+    Ok(world)
+}
+
+/// Run `verb` on `oid`, in a world `open_archive` opened, as a dry run. Reports what
+/// `dry_run_dispatch` does; whatever the verb tries to write is discarded.
+pub async fn query_archive(
+    world: &Arc<World>,
+    oid: Oid,
+    verb: &str,
+    arguments: &[Value],
+) -> Result<Value, Error> {
+    let vm = Arc::new(WasmVM::new(world.clone(), None)?);
+    vm.clone().bind_builtins()?;
+    dry_run_dispatch(world, vm, oid, verb, arguments).await
+}
+
+/// Print, as JSON, the slots of `oid` as `archive` has them, or with `query`, a dry run of the
+/// verb it names on `oid` with the arguments it gives.
+pub async fn inspect_archive(
+    archive: &Archive,
+    options: WorldOptions,
+    oid: Oid,
+    query: Option<(&str, &[Value])>,
+) -> Result<(), Error> {
+    let past = open_archive(archive, options).await?;
+    match query {
+        Some((verb, arguments)) => {
+            print_report(&query_archive(&past, oid, verb, arguments).await?, None)
+        }
+        None => print_report(&dump_objects(&past, &[oid]).await?, None),
+    }
+}
+
+// Write `report` as JSON to `path`, or print it if there isn't one.
+fn print_report<T: Serialize>(report: &T, path: Option<&Path>) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(report)?;
+    match path {
+        Some(path) => std::fs::write(path, json)?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::dump::Dump;
+    use crate::object::SlotDef;
+    use crate::world::{bootstrap_world, load_dumps};
+    use value::{Program, ProgramLang};
+
+    // A verb which returns the system object's motto.
+    const MOTTO: &str = r#"
+return room.get_slot(room.this, room.this, "motto")
+"#;
+
+    fn sys() -> Oid {
+        Oid { id: Uuid::nil() }
+    }
+
+    async fn set_slot(world: &World, name: &str, value: Value) {
+        let dump = Dump {
+            slot_def: SlotDef {
+                location: sys(),
+                key: sys(),
+                name: name.to_string(),
+            },
+            value,
+            meta: None,
+        };
+        load_dumps(world, &[dump]).await.unwrap();
+    }
+
+    fn slot<'a>(dumps: &'a [Dump], name: &str) -> Option<&'a Value> {
+        dumps
+            .iter()
+            .find(|dump| dump.slot_def.name == name)
+            .map(|dump| &dump.value)
+    }
+
+    #[tokio::test]
+    async fn archives_show_the_world_as_it_was() {
+        let world = Arc::new(World::new(WorldOptions {
+            storage: Storage::Temporary,
+            ..WorldOptions::default()
+        }));
+        bootstrap_world(world.clone(), sys()).await.unwrap();
+        let verb = Program::new(ProgramLang::Lua, MOTTO.as_bytes().to_vec());
+        set_slot(&world, "motto_verb", Value::Program(verb)).await;
+        set_slot(&world, "motto", Value::String("then".into())).await;
+        let path = std::env::temp_dir().join(format!("room-{}.tar.zst", Uuid::new_v4()));
+        export_world(&world, &path).await.unwrap();
+        set_slot(&world, "motto", Value::String("now".into())).await;
+
+        let past = open_archive(&Archive::File(path.clone()), WorldOptions::default()).await;
+        std::fs::remove_file(&path).unwrap();
+        let past = past.unwrap();
+        let dumps = dump_objects(&past, &[sys()]).await.unwrap();
+        assert!(
+            matches!(slot(&dumps, "motto"), Some(Value::String(motto)) if motto.as_str() == "then")
+        );
+        let report = query_archive(&past, sys(), "motto_verb", &[])
+            .await
+            .unwrap();
+        let result = match &report {
+            Value::Vector(report) => report.first(),
+            _ => panic!("Reported {:?}", report),
+        };
+        assert!(matches!(result, Some(Value::String(motto)) if motto.as_str() == "then"));
+
+        // The world itself is as it is now.
+        let dumps = dump_objects(&world, &[sys()]).await.unwrap();
+        assert!(
+            matches!(slot(&dumps, "motto"), Some(Value::String(motto)) if motto.as_str() == "now")
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::task::AtomicWaker;
+use futures::Stream;
+use tungstenite::Message;
+
+/// Messages queued for a connection, by default, before its client is taken to be too slow.
+pub const DEFAULT_OUTBOUND_CAPACITY: usize = 1024;
+
+/// What to do with a message for a connection whose queue is full: one whose client isn't
+/// reading what it's sent as fast as it's sent it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowAction {
+    /// Refuse it, and tell its sender so.
+    #[default]
+    Refuse,
+    /// Drop the oldest message queued to make room for it.
+    DropOldest,
+    /// Close the connection.
+    Disconnect,
+}
+
+/// How many messages may be queued for each connection, and what's done when there are more.
+#[derive(Clone, Copy, Debug)]
+pub struct OutboundPolicy {
+    pub capacity: usize,
+    pub overflow: OverflowAction,
+}
+
+impl Default for OutboundPolicy {
+    fn default() -> Self {
+        OutboundPolicy {
+            capacity: DEFAULT_OUTBOUND_CAPACITY,
+            overflow: OverflowAction::default(),
+        }
+    }
+}
+
+/// What became of a message sent to a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// It's queued to go out.
+    Queued,
+    /// The connection's queue is full, and it was refused.
+    WouldBlock,
+    /// The connection is closed, or gone.
+    Disconnected,
+}
+
+impl Delivery {
+    /// The code verbs are given for it by 'send': 0, 1 and 2 respectively.
+    pub fn code(&self) -> i32 {
+        match self {
+            Delivery::Queued => 0,
+            Delivery::WouldBlock => 1,
+            Delivery::Disconnected => 2,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    messages: VecDeque<Message>,
+    // Closed by the server, or for overflowing; the messages already queued still go out.
+    closed: bool,
+    receiver_gone: bool,
+}
+
+struct Queue {
+    state: Mutex<State>,
+    waker: AtomicWaker,
+}
+
+/// Queues messages for a connection, up to its policy's capacity.
+#[derive(Clone)]
+pub struct OutboundSender {
+    queue: Arc<Queue>,
+    policy: OutboundPolicy,
+}
+
+/// The messages queued for a connection, in the order they were sent, until the queue's closed.
+pub struct OutboundReceiver {
+    queue: Arc<Queue>,
+}
+
+/// A queue for a connection's messages, kept to `policy`.
+pub fn outbound(policy: OutboundPolicy) -> (OutboundSender, OutboundReceiver) {
+    let queue = Arc::new(Queue {
+        state: Default::default(),
+        waker: AtomicWaker::new(),
+    });
+    (
+        OutboundSender {
+            queue: queue.clone(),
+            policy,
+        },
+        OutboundReceiver { queue },
+    )
+}
+
+impl OutboundSender {
+    /// Queue `message`, or if the queue's full, do what the policy says.
+    pub fn send(&self, message: Message) -> Delivery {
+        let mut state = self.queue.state.lock().unwrap();
+        if state.closed || state.receiver_gone {
+            return Delivery::Disconnected;
+        }
+        if state.messages.len() >= self.policy.capacity {
+            match self.policy.overflow {
+                OverflowAction::Refuse => return Delivery::WouldBlock,
+                OverflowAction::DropOldest => {
+                    state.messages.pop_front();
+                }
+                OverflowAction::Disconnect => {
+                    state.closed = true;
+                    drop(state);
+                    self.queue.waker.wake();
+                    return Delivery::Disconnected;
+                }
+            }
+        }
+        state.messages.push_back(message);
+        drop(state);
+        self.queue.waker.wake();
+        Delivery::Queued
+    }
+
+    /// Queue `message` whatever the capacity, and close the queue behind it.
+    pub fn close_with(&self, message: Message) {
+        let mut state = self.queue.state.lock().unwrap();
+        if !state.closed {
+            state.messages.push_back(message);
+            state.closed = true;
+        }
+        drop(state);
+        self.queue.waker.wake();
+    }
+
+    /// Close the queue, so that the connection ends once what's already queued has gone out.
+    pub fn close(&self) {
+        self.queue.state.lock().unwrap().closed = true;
+        self.queue.waker.wake();
+    }
+
+    /// Whether the queue's been closed, or its receiver's gone.
+    pub fn is_closed(&self) -> bool {
+        let state = self.queue.state.lock().unwrap();
+        state.closed || state.receiver_gone
+    }
+
+    /// Whether `other` queues to the same receiver.
+    pub fn same_queue(&self, other: &OutboundSender) -> bool {
+        Arc::ptr_eq(&self.queue, &other.queue)
+    }
+
+    /// How many messages are queued.
+    pub fn len(&self) -> usize {
+        self.queue.state.lock().unwrap().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl OutboundReceiver {
+    /// The next message queued, if there is one, without waiting for it.
+    pub fn try_recv(&mut self) -> Option<Message> {
+        self.queue.state.lock().unwrap().messages.pop_front()
+    }
+}
+
+impl Stream for OutboundReceiver {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        // Registered before looking, so that a message queued in between wakes us.
+        self.queue.waker.register(cx.waker());
+        let mut state = self.queue.state.lock().unwrap();
+        match state.messages.pop_front() {
+            Some(message) => Poll::Ready(Some(message)),
+            None if state.closed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for OutboundReceiver {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().receiver_gone = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{FutureExt, StreamExt};
+
+    use super::*;
+
+    fn text(s: &str) -> Message {
+        Message::Text(s.to_string())
+    }
+
+    fn policy(capacity: usize, overflow: OverflowAction) -> OutboundPolicy {
+        OutboundPolicy { capacity, overflow }
+    }
+
+    #[test]
+    fn messages_arrive_in_order() {
+        let (sender, mut receiver) = outbound(OutboundPolicy::default());
+        assert_eq!(sender.send(text("a")), Delivery::Queued);
+        assert_eq!(sender.send(text("b")), Delivery::Queued);
+        assert_eq!(sender.len(), 2);
+        assert_eq!(receiver.try_recv(), Some(text("a")));
+        assert_eq!(receiver.next().now_or_never(), Some(Some(text("b"))));
+        assert!(sender.is_empty());
+        // Nothing's queued, but the queue's open, so the stream waits.
+        assert_eq!(receiver.next().now_or_never(), None);
+    }
+
+    #[test]
+    fn full_queues_refuse() {
+        let (sender, mut receiver) = outbound(policy(1, OverflowAction::Refuse));
+        assert_eq!(sender.send(text("a")), Delivery::Queued);
+        assert_eq!(sender.send(text("b")), Delivery::WouldBlock);
+        assert!(!sender.is_closed());
+        assert_eq!(receiver.try_recv(), Some(text("a")));
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    #[test]
+    fn full_queues_drop_the_oldest() {
+        let (sender, mut receiver) = outbound(policy(2, OverflowAction::DropOldest));
+        for message in ["a", "b", "c"] {
+            assert_eq!(sender.send(text(message)), Delivery::Queued);
+        }
+        assert_eq!(receiver.try_recv(), Some(text("b")));
+        assert_eq!(receiver.try_recv(), Some(text("c")));
+    }
+
+    #[test]
+    fn full_queues_disconnect() {
+        let (sender, receiver) = outbound(policy(1, OverflowAction::Disconnect));
+        assert_eq!(sender.send(text("a")), Delivery::Queued);
+        assert_eq!(sender.send(text("b")), Delivery::Disconnected);
+        assert!(sender.is_closed());
+        assert_eq!(sender.send(text("c")), Delivery::Disconnected);
+        // What was queued before still goes out, then the stream ends.
+        let received: Vec<_> = futures::executor::block_on(receiver.collect());
+        assert_eq!(received, vec![text("a")]);
+    }
+
+    #[test]
+    fn closing_ends_the_stream_after_what_is_queued() {
+        let (sender, receiver) = outbound(policy(1, OverflowAction::Refuse));
+        assert_eq!(sender.send(text("a")), Delivery::Queued);
+        // Over capacity, but queued anyway, as the last message.
+        sender.close_with(text("bye"));
+        assert!(sender.is_closed());
+        sender.close_with(text("again"));
+        let received: Vec<_> = futures::executor::block_on(receiver.collect());
+        assert_eq!(received, vec![text("a"), text("bye")]);
+    }
+
+    #[test]
+    fn senders_to_a_dropped_receiver_are_disconnected() {
+        let (sender, receiver) = outbound(OutboundPolicy::default());
+        let clone = sender.clone();
+        assert!(sender.same_queue(&clone));
+        assert!(!sender.same_queue(&outbound(OutboundPolicy::default()).0));
+        drop(receiver);
+        assert!(clone.is_closed());
+        assert_eq!(sender.send(text("a")), Delivery::Disconnected);
+    }
+
+    #[test]
+    fn sends_wake_a_waiting_receiver() {
+        let (sender, mut receiver) = outbound(OutboundPolicy::default());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            let waiting = tokio::spawn(async move { receiver.next().await });
+            tokio::task::yield_now().await;
+            sender.send(text("a"));
+            assert_eq!(waiting.await.unwrap(), Some(text("a")));
+        });
+    }
+}
@@ -0,0 +1,89 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::stdlib::parse_failed;
+use value::Value;
+
+/// How many compiled patterns are kept for reuse.
+pub const DEFAULT_PATTERN_CAPACITY: u64 = 1024;
+
+// Cap on the size of a compiled pattern, so that a verb can't have the host build an enormous
+// automaton.
+const PATTERN_SIZE_LIMIT: usize = 1024 * 1024;
+
+/// Regular expressions compiled for the `regex_match` and `regex_replace` builtins, shared by
+/// every connection, so that a verb parsing each line of input doesn't compile its patterns
+/// anew every time. The least recently used are dropped once there are too many.
+pub struct PatternCache {
+    patterns: moka::sync::Cache<String, Regex>,
+}
+
+impl Default for PatternCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_PATTERN_CAPACITY)
+    }
+}
+
+impl PatternCache {
+    pub fn new(capacity: u64) -> Self {
+        PatternCache {
+            patterns: moka::sync::Cache::new(capacity),
+        }
+    }
+
+    /// `pattern`, compiled. Patterns which don't compile aren't kept.
+    pub fn get(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        let key = pattern.to_string();
+        if let Some(regex) = self.patterns.get(&key) {
+            return Ok(regex);
+        }
+        let regex = RegexBuilder::new(pattern)
+            .size_limit(PATTERN_SIZE_LIMIT)
+            .build()?;
+        self.patterns.insert(key, regex.clone());
+        Ok(regex)
+    }
+
+    /// `regex_match(String pattern, String subject)`: a Vector of the text of the first match and
+    /// then of each of its groups (empty for those which took no part in it), or an empty Vector
+    /// if the pattern doesn't match; or [BadType, reason] if it isn't valid. None if the arguments
+    /// aren't Strings.
+    pub fn regex_match(&self, args: &[Value]) -> Option<Value> {
+        let (pattern, subject) = match args {
+            [Value::String(pattern), Value::String(subject)] => (pattern, subject),
+            _ => return None,
+        };
+        let regex = match self.get(pattern) {
+            Ok(regex) => regex,
+            Err(e) => return Some(parse_failed(e.to_string())),
+        };
+        let groups = match regex.captures(subject) {
+            Some(captures) => captures
+                .iter()
+                .map(|group| Value::String(group.map_or("", |m| m.as_str()).into()))
+                .collect(),
+            None => vec![],
+        };
+        Some(Value::Vector(groups))
+    }
+
+    /// `regex_replace(String pattern, String subject, String replacement)`: the subject with every
+    /// match of the pattern replaced, with `$1` or `${name}` in the replacement standing for that
+    /// group's text; or [BadType, reason] if the pattern isn't valid.
+    pub fn regex_replace(&self, args: &[Value]) -> Option<Value> {
+        let (pattern, subject, replacement) = match args {
+            [Value::String(pattern), Value::String(subject), Value::String(replacement)] => {
+                (pattern, subject, replacement)
+            }
+            _ => return None,
+        };
+        Some(match self.get(pattern) {
+            Ok(regex) => Value::String(
+                regex
+                    .replace_all(subject, replacement.as_str())
+                    .into_owned()
+                    .into(),
+            ),
+            Err(e) => parse_failed(e.to_string()),
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use serde::{Deserialize, Serialize};
+
+use crate::database::{DbError, Tx};
+use value::{Oid, Value};
+
+/// Usage counters kept for each player by the connection layer.
+///
+/// Each is a separate key updated with atomic operations, so that every connection logged in to
+/// a player can update them without conflicting with the others.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlayerStats {
+    /// Logins granted.
+    pub sessions: i64,
+    /// Seconds spent logged in, over sessions which have ended.
+    pub connected_secs: i64,
+    /// Messages and requests received while logged in.
+    pub commands: i64,
+    /// When the player was last logged in, in seconds since the Unix epoch. Zero if never.
+    pub last_seen: i64,
+}
+
+const SESSIONS: &str = "sessions";
+const CONNECTED_SECS: &str = "connected_secs";
+const COMMANDS: &str = "commands";
+const LAST_SEEN: &str = "last_seen";
+
+impl PlayerStats {
+    /// As a Vector of [name, value] pairs, for verbs.
+    pub fn to_value(&self) -> Value {
+        let field =
+            |name: &str, value| Value::Vector(vec![Value::String(name.into()), Value::I64(value)]);
+        Value::Vector(vec![
+            field(SESSIONS, self.sessions),
+            field(CONNECTED_SECS, self.connected_secs),
+            field(COMMANDS, self.commands),
+            field(LAST_SEEN, self.last_seen),
+        ])
+    }
+}
+
+fn stats_subspace(player: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(player.id);
+    Subspace::new(Bytes::from_static("PLAYERSTATS".as_bytes())).subspace(&tup)
+}
+
+fn stat_key(player: Oid, name: &str) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_string(name.to_string());
+    stats_subspace(player).subspace(&tup).pack().into()
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+pub struct PlayerStatsTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> PlayerStatsTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        PlayerStatsTxHandle { tr: tx }
+    }
+
+    /// Count a login to `player` at `now`.
+    pub fn session_started(&self, player: Oid, now: SystemTime) {
+        self.tr.add(stat_key(player, SESSIONS), 1);
+        self.tr.max(stat_key(player, LAST_SEEN), unix_secs(now));
+    }
+
+    /// Count the time spent in a session which has ended at `now`.
+    pub fn session_ended(&self, player: Oid, connected: Duration, now: SystemTime) {
+        self.tr
+            .add(stat_key(player, CONNECTED_SECS), connected.as_secs() as i64);
+        self.tr.max(stat_key(player, LAST_SEEN), unix_secs(now));
+    }
+
+    /// Count a command received from `player` at `now`.
+    pub fn command(&self, player: Oid, now: SystemTime) {
+        self.tr.add(stat_key(player, COMMANDS), 1);
+        self.tr.max(stat_key(player, LAST_SEEN), unix_secs(now));
+    }
+
+    pub async fn get(&self, player: Oid) -> Result<PlayerStats, DbError> {
+        let counter = |name| async move {
+            let value = self.tr.get(stat_key(player, name)).await?;
+            Ok::<_, DbError>(
+                value
+                    .and_then(|v| Bytes::from(v)[..].try_into().ok())
+                    .map(i64::from_le_bytes)
+                    .unwrap_or(0),
+            )
+        };
+        Ok(PlayerStats {
+            sessions: counter(SESSIONS).await?,
+            connected_secs: counter(CONNECTED_SECS).await?,
+            commands: counter(COMMANDS).await?,
+            last_seen: counter(LAST_SEEN).await?,
+        })
+    }
+
+    pub fn remove(&self, player: Oid) {
+        self.tr
+            .clear_range(stats_subspace(player).range(&Tuple::new()));
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use value::Oid;
+
+/// Verbs kept in a preload manifest when it's refreshed with the hottest ones.
+pub const HOT_VERBS_KEPT: usize = 100;
+
+/// What to load into the caches at startup, before any connections are accepted, so that the first
+/// players in after a restart don't wait on cold storage and compilation.
+///
+/// `objects` are written by hand; every slot on them is read and every program among them
+/// compiled. `verbs` are refreshed with those which took the most time as the server shuts down.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PreloadManifest {
+    #[serde(default)]
+    pub objects: Vec<Uuid>,
+    #[serde(default)]
+    pub verbs: Vec<PreloadVerb>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PreloadVerb {
+    pub oid: Uuid,
+    pub verb: String,
+}
+
+impl PreloadManifest {
+    /// Read the manifest at `path`, or an empty one if there isn't one yet.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        match std::fs::read(path) {
+            Ok(payload) => Ok(serde_json::from_slice(&payload)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Put `hot` (hottest first) at the front of the verbs, followed by those already listed
+    /// which weren't among them, keeping only the first HOT_VERBS_KEPT.
+    pub fn refresh_verbs(&mut self, hot: impl IntoIterator<Item = (Oid, String)>) {
+        let mut verbs: Vec<PreloadVerb> = hot
+            .into_iter()
+            .map(|(oid, verb)| PreloadVerb { oid: oid.id, verb })
+            .collect();
+        for verb in self.verbs.drain(..) {
+            if !verbs.contains(&verb) {
+                verbs.push(verb);
+            }
+        }
+        verbs.truncate(HOT_VERBS_KEPT);
+        self.verbs = verbs;
+    }
+}
@@ -0,0 +1,284 @@
+use bytes::Bytes;
+use tracing::warn;
+
+use crate::verb_error::is_error_map;
+use value::{decode_frame_shared, encode_frame, Error, Oid, Value};
+
+/// Websocket subprotocol clients request in order to speak the structured protocol below. Peers
+/// which don't ask for it get the raw protocol, where frames are handed straight to the 'receive'
+/// verb.
+pub const RPC_SUBPROTOCOL: &str = "room.rpc";
+
+/// Websocket subprotocol other worlds connect with to invoke verbs on this one's objects (see
+/// `federation`). It's the structured protocol, but only accepted with the federation token.
+pub const FEDERATION_SUBPROTOCOL: &str = "room.federation";
+
+/// Request flag asking for the verb to be run as a dry run, whose effects are reported rather
+/// than applied.
+pub const FLAG_DRY_RUN: i32 = 1;
+
+/// Request flag asking for the verb, and those it invokes, to be executed deterministically: with
+/// a logical clock, randomness seeded from the request, and builtins which can't be replayed
+/// refused.
+pub const FLAG_DETERMINISTIC: i32 = 2;
+
+/// A request from a client to invoke `verb` on `target`.
+/// On the wire this is a Value::Vector of [I64 request_id, IdKey target, String verb, Vector args],
+/// optionally followed by I32 flags, sent as a frame (see `value::encode_frame`).
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub request_id: i64,
+    pub target: Oid,
+    pub verb: String,
+    pub args: Vec<Value>,
+    pub dry_run: bool,
+    pub deterministic: bool,
+}
+
+/// The reply to a Request, carrying its request_id so the client can correlate it.
+/// On the wire this is a Value::Vector of [I64 request_id, result]. If the result is an error,
+/// it's followed by the error's I32 code and, if there's text for it, a String message.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub request_id: i64,
+    pub result: Value,
+    pub message: Option<String>,
+}
+
+/// The tag opening a form sent to a client, and the answers it sends back.
+pub const FORM_TAG: &str = "form";
+
+/// The tag opening a client's answers to a form which wouldn't do, which it may correct and send
+/// again.
+pub const FORM_INVALID_TAG: &str = "form_invalid";
+
+/// The tag opening the token a client is sent on connecting, when connections may be resumed:
+/// [String "resume", String token]. A client whose connection drops can come back to it by
+/// connecting with the token as the `resume` query parameter.
+pub const RESUME_TAG: &str = "resume";
+
+/// A client's answers to a form it was sent.
+/// On the wire this is a Value::Vector of [String "form", IdKey form, Vector answers], where the
+/// answers are [String field, value] pairs. A form is sent to the client as [String "form",
+/// IdKey form, Vector fields]; if the answers don't fit it, the client is sent [String
+/// "form_invalid", IdKey form, Vector problems], each a [String field, String reason] pair.
+#[derive(Clone, Debug)]
+pub struct FormAnswers {
+    pub form: Oid,
+    pub answers: Vec<Value>,
+}
+
+/// What a client speaking the structured protocol sends.
+#[derive(Clone, Debug)]
+pub enum Inbound {
+    Request(Request),
+    Form(FormAnswers),
+}
+
+/// Stable codes for the errors clients are told about, which won't be renumbered as the engine's
+/// own errors change. Codes from 1000 are from verbs and the engine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    SlotDoesNotExist = 1001,
+    InvalidProgram = 1002,
+    PermissionDenied = 1003,
+    InternalError = 1004,
+    BadType = 1005,
+    NameTaken = 1006,
+    SecondFactorRequired = 1007,
+    ResourceLimit = 1008,
+    QuotaExceeded = 1009,
+    Contended = 1010,
+    PeerUnavailable = 1011,
+    Trapped = 1012,
+}
+
+impl ErrorCode {
+    /// The code for an engine error, or None for NoError, which isn't one.
+    pub fn of(error: Error) -> Option<ErrorCode> {
+        match error {
+            Error::NoError => None,
+            Error::SlotDoesNotExist => Some(ErrorCode::SlotDoesNotExist),
+            Error::InvalidProgram => Some(ErrorCode::InvalidProgram),
+            Error::PermissionDenied => Some(ErrorCode::PermissionDenied),
+            Error::InternalError => Some(ErrorCode::InternalError),
+            Error::BadType => Some(ErrorCode::BadType),
+            Error::NameTaken => Some(ErrorCode::NameTaken),
+            Error::SecondFactorRequired => Some(ErrorCode::SecondFactorRequired),
+            Error::ResourceLimit => Some(ErrorCode::ResourceLimit),
+            Error::QuotaExceeded => Some(ErrorCode::QuotaExceeded),
+            Error::Contended => Some(ErrorCode::Contended),
+            Error::PeerUnavailable => Some(ErrorCode::PeerUnavailable),
+            Error::Trapped => Some(ErrorCode::Trapped),
+        }
+    }
+
+    /// The key its text is found under in a catalog.
+    pub fn catalog_key(&self) -> &'static str {
+        match self {
+            ErrorCode::SlotDoesNotExist => "error.slot_does_not_exist",
+            ErrorCode::InvalidProgram => "error.invalid_program",
+            ErrorCode::PermissionDenied => "error.permission_denied",
+            ErrorCode::InternalError => "error.internal_error",
+            ErrorCode::BadType => "error.bad_type",
+            ErrorCode::NameTaken => "error.name_taken",
+            ErrorCode::SecondFactorRequired => "error.second_factor_required",
+            ErrorCode::ResourceLimit => "error.resource_limit",
+            ErrorCode::QuotaExceeded => "error.quota_exceeded",
+            ErrorCode::Contended => "error.contended",
+            ErrorCode::PeerUnavailable => "error.peer_unavailable",
+            ErrorCode::Trapped => "error.trapped",
+        }
+    }
+}
+
+impl Inbound {
+    /// Decode what a client sent, its Binaries and Programs sharing `frame`'s buffer.
+    pub fn decode(frame: Bytes) -> Result<Inbound, Error> {
+        let value = decode_frame_shared(frame).map_err(|e| {
+            warn!("Malformed request: {}", e);
+            Error::BadType
+        })?;
+        match &value {
+            Value::Vector(v) => match &v[..] {
+                [Value::String(tag), Value::IdKey(form), Value::Vector(answers)]
+                    if tag == FORM_TAG =>
+                {
+                    Ok(Inbound::Form(FormAnswers {
+                        form: *form,
+                        answers: answers.clone(),
+                    }))
+                }
+                _ => Request::from_value(value).map(Inbound::Request),
+            },
+            _ => Err(Error::BadType),
+        }
+    }
+}
+
+/// A form for a client to fill in, as sent to it.
+pub fn encode_form(form: Oid, fields: &Value) -> Vec<u8> {
+    encode_frame(&Value::Vector(vec![
+        Value::String(FORM_TAG.into()),
+        Value::IdKey(form),
+        fields.clone(),
+    ]))
+}
+
+/// A resume token, as sent to a client.
+pub fn encode_resume(token: &str) -> Vec<u8> {
+    encode_frame(&Value::Vector(vec![
+        Value::String(RESUME_TAG.into()),
+        Value::String(token.into()),
+    ]))
+}
+
+/// What was wrong with a client's answers to a form, as sent to it.
+pub fn encode_form_problems(form: Oid, problems: Value) -> Vec<u8> {
+    encode_frame(&Value::Vector(vec![
+        Value::String(FORM_INVALID_TAG.into()),
+        Value::IdKey(form),
+        problems,
+    ]))
+}
+
+impl Request {
+    /// Decode a request, its Binaries sharing `frame`'s buffer.
+    pub fn decode(frame: Bytes) -> Result<Request, Error> {
+        let value = decode_frame_shared(frame).map_err(|e| {
+            warn!("Malformed request: {}", e);
+            Error::BadType
+        })?;
+        Request::from_value(value)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut request = vec![
+            Value::I64(self.request_id),
+            Value::IdKey(self.target),
+            Value::String(self.verb.as_str().into()),
+            Value::Vector(self.args.clone()),
+        ];
+        let flags = match self.dry_run {
+            true => FLAG_DRY_RUN,
+            false => 0,
+        } | match self.deterministic {
+            true => FLAG_DETERMINISTIC,
+            false => 0,
+        };
+        if flags != 0 {
+            request.push(Value::I32(flags));
+        }
+        encode_frame(&Value::Vector(request))
+    }
+
+    fn from_value(value: Value) -> Result<Request, Error> {
+        match value {
+            Value::Vector(v) => {
+                let (request, flags) = match &v[..] {
+                    [request @ .., Value::I32(flags)] => (request, *flags),
+                    request => (request, 0),
+                };
+                match request {
+                    [Value::I64(request_id), Value::IdKey(target), Value::String(verb), Value::Vector(args)] => {
+                        Ok(Request {
+                            request_id: *request_id,
+                            target: *target,
+                            verb: verb.to_string(),
+                            args: args.clone(),
+                            dry_run: flags & FLAG_DRY_RUN != 0,
+                            deterministic: flags & FLAG_DETERMINISTIC != 0,
+                        })
+                    }
+                    _ => Err(Error::BadType),
+                }
+            }
+            _ => Err(Error::BadType),
+        }
+    }
+}
+
+impl Response {
+    /// The code for the error the result is, if it is one: Trapped for a failed verb's error map.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self.result {
+            Value::Error(e) => ErrorCode::of(e),
+            ref map if is_error_map(map) => Some(ErrorCode::Trapped),
+            _ => None,
+        }
+    }
+
+    /// A response as sent by a server, as its client reads it. The result is as the server had it,
+    /// errors included.
+    pub fn decode(bytes: &[u8]) -> Result<Response, Error> {
+        let value = decode_frame(bytes).map_err(|e| {
+            warn!("Malformed response: {}", e);
+            Error::BadType
+        })?;
+        match value {
+            Value::Vector(v) => match &v[..] {
+                [Value::I64(request_id), result, rest @ ..] => Ok(Response {
+                    request_id: *request_id,
+                    result: result.clone(),
+                    message: match rest {
+                        [Value::I32(_), Value::String(message)] => Some(message.to_string()),
+                        _ => None,
+                    },
+                }),
+                _ => Err(Error::BadType),
+            },
+            _ => Err(Error::BadType),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut response = vec![Value::I64(self.request_id), self.result.clone()];
+        if let Some(code) = self.error_code() {
+            response.push(Value::I32(code as i32));
+            if let Some(message) = &self.message {
+                response.push(Value::String(message.as_str().into()));
+            }
+        }
+        encode_frame(&Value::Vector(response))
+    }
+}
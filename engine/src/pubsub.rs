@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use fdb::{range::Range, subspace::Subspace, tuple::Tuple, Key};
+use futures::future::BoxFuture;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::database::{key_after, DbError, Tx};
+use value::{decode_frame, encode_frame, Oid, Value};
+
+/// The verb events published to a channel are dispatched to on each of its subscribers, as
+/// [IdKey channel, value, IdKey publisher].
+pub const EVENT_VERB: &str = "on_event";
+
+/// Events taken for delivery at a time.
+pub const EVENT_BATCH: usize = 100;
+
+/// How long a server delivering events holds on to delivering them, before another may take over.
+/// It renews its hold as it takes each batch.
+pub const EVENT_LEASE: Duration = Duration::from_secs(30);
+
+/// An event published to a channel, awaiting delivery.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub channel: Oid,
+    pub value: Value,
+    /// The object whose verb published it; nil if none did.
+    pub publisher: Oid,
+    /// The connection whose verb published it, which it's delivered as; None if none did.
+    pub connection: Option<Oid>,
+}
+
+impl Event {
+    fn from_value(value: fdb::Value) -> Option<Self> {
+        let tuple = Tuple::from_bytes(value).ok()?;
+        let oid = |i| {
+            Some(Oid {
+                id: *tuple.get_uuid_ref(i).ok()?,
+            })
+        };
+        let connection = oid(3)?;
+        Some(Event {
+            channel: oid(0)?,
+            value: decode_frame(&tuple.get_bytes_ref(2).ok()?[..]).ok()?,
+            publisher: oid(1)?,
+            connection: (!connection.id.is_nil()).then_some(connection),
+        })
+    }
+}
+
+/// Objects' subscriptions to channels, and the events published to them.
+///
+/// Channels are objects, and so are their subscribers. Each subscription is recorded both by
+/// channel, to find who to deliver to, and by subscriber, to find what to forget when the
+/// subscriber is destroyed. Events are kept, in the order they were published, until they've been
+/// delivered, so that none are delivered for a transaction which didn't commit and none are lost
+/// if delivery's cut short. One server at a time delivers them, while it holds the lease.
+pub struct PubSubTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+fn subscriber_subspace(channel: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(channel.id);
+    Subspace::new(Bytes::from_static("SUBSCRIBER".as_bytes())).subspace(&tup)
+}
+
+fn subscription_subspace(subscriber: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(subscriber.id);
+    Subspace::new(Bytes::from_static("SUBSCRIPTION".as_bytes())).subspace(&tup)
+}
+
+fn member_key(subspace: Subspace, oid: Oid) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_uuid(oid.id);
+    subspace.subspace(&tup).pack().into()
+}
+
+// Events are keyed by "EVENTS" then the versionstamp of the transaction which published them, so
+// that they're delivered in the order they were published in.
+const EVENTS_PREFIX: &[u8] = b"EVENTS";
+
+// Just past every event's key.
+const EVENTS_END: &[u8] = b"EVENTT";
+
+// Added to as events are published, so that delivery can wait on it rather than poll.
+const EVENTS_COUNT: &[u8] = b"EVENT_COUNT";
+
+// Which server is delivering events, and until when, as (uuid, nanoseconds since the Unix epoch).
+const EVENTS_LEASE: &[u8] = b"EVENT_LEASE";
+
+impl<'tx_lifetime> PubSubTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        PubSubTxHandle { tr: tx }
+    }
+
+    pub fn subscribe(&self, channel: Oid, subscriber: Oid) {
+        self.tr.set(
+            member_key(subscriber_subspace(channel), subscriber),
+            Bytes::new(),
+        );
+        self.tr.set(
+            member_key(subscription_subspace(subscriber), channel),
+            Bytes::new(),
+        );
+    }
+
+    pub fn unsubscribe(&self, channel: Oid, subscriber: Oid) {
+        self.tr
+            .clear(member_key(subscriber_subspace(channel), subscriber));
+        self.tr
+            .clear(member_key(subscription_subspace(subscriber), channel));
+    }
+
+    // The objects whose uuids follow `subspace`.
+    async fn members(&self, subspace: Subspace) -> Result<Vec<Oid>, DbError> {
+        let mut stream = self.tr.get_range(subspace.range(&Tuple::new()));
+        let mut members = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, _) = kv?;
+            let key_bytes: Bytes = key.into();
+            let tuple = subspace.unpack(&key_bytes).unwrap();
+            members.push(Oid {
+                id: *tuple.get_uuid_ref(0).unwrap(),
+            });
+        }
+        Ok(members)
+    }
+
+    /// The objects subscribed to `channel`.
+    pub async fn subscribers(&self, channel: Oid) -> Result<Vec<Oid>, DbError> {
+        self.members(subscriber_subspace(channel)).await
+    }
+
+    /// The channels `subscriber` is subscribed to.
+    pub async fn subscriptions(&self, subscriber: Oid) -> Result<Vec<Oid>, DbError> {
+        self.members(subscription_subspace(subscriber)).await
+    }
+
+    /// Forget every subscription `oid` has, and every one to it as a channel.
+    pub async fn clear_object(&self, oid: Oid) -> Result<(), DbError> {
+        for channel in self.subscriptions(oid).await? {
+            self.unsubscribe(channel, oid);
+        }
+        for subscriber in self.subscribers(oid).await? {
+            self.unsubscribe(oid, subscriber);
+        }
+        Ok(())
+    }
+
+    /// Publish `value` to `channel`, for delivery once the transaction has committed, as
+    /// `connection`, the one whose verb published it, if any.
+    pub fn publish(
+        &self,
+        channel: Oid,
+        value: &Value,
+        publisher: Option<Oid>,
+        connection: Option<Oid>,
+    ) {
+        let mut tup = Tuple::new();
+        tup.add_uuid(channel.id);
+        tup.add_uuid(publisher.map_or(Uuid::nil(), |publisher| publisher.id));
+        tup.add_bytes(Bytes::from(encode_frame(value)));
+        tup.add_uuid(connection.map_or(Uuid::nil(), |connection| connection.id));
+        self.tr.set_versionstamped(EVENTS_PREFIX, tup.pack());
+        self.tr.add(Bytes::from_static(EVENTS_COUNT), 1);
+    }
+
+    /// Hold on to delivering events for `holder` for EVENT_LEASE from `now` (in nanoseconds since
+    /// the Unix epoch), unless another holder's hold hasn't run out. False if one's hasn't.
+    pub async fn lease(&self, holder: Uuid, now: i64) -> Result<bool, DbError> {
+        if let Some(lease) = self.tr.get(Bytes::from_static(EVENTS_LEASE)).await? {
+            let held = Tuple::from_bytes(lease)
+                .ok()
+                .and_then(|tuple| Some((*tuple.get_uuid_ref(0).ok()?, tuple.get_i64(1).ok()?)));
+            if let Some((other, until)) = held {
+                if other != holder && until > now {
+                    return Ok(false);
+                }
+            }
+        }
+        let mut tup = Tuple::new();
+        tup.add_uuid(holder);
+        tup.add_i64(now.saturating_add(EVENT_LEASE.as_nanos() as i64));
+        self.tr.set(Bytes::from_static(EVENTS_LEASE), tup.pack());
+        Ok(true)
+    }
+
+    /// Up to `limit` of the events awaiting delivery, the earliest published first, and the key of
+    /// the last one read, to acknowledge them by once they've been delivered. Until then they're
+    /// kept, so that they're delivered again if delivery is cut short.
+    pub async fn pending(&self, limit: usize) -> Result<(Vec<Event>, Option<Key>), DbError> {
+        let range = Range::new(
+            Bytes::from_static(EVENTS_PREFIX),
+            Bytes::from_static(EVENTS_END),
+        );
+        let mut stream = self.tr.get_range(range);
+        let mut events = vec![];
+        let mut last = None;
+        while let Some(kv) = stream.next().await {
+            let (key, value) = kv?;
+            last = Some(key);
+            // Those which can't be read aren't something any server could deliver, and nor are
+            // those published by older servers without their connections.
+            events.extend(Event::from_value(value));
+            if events.len() >= limit {
+                break;
+            }
+        }
+        Ok((events, last))
+    }
+
+    /// Forget the events awaiting delivery up to and including the one at `last`, as they've been
+    /// delivered.
+    pub fn acknowledge(&self, last: Key) {
+        self.tr.clear_range(Range::new(
+            Bytes::from_static(EVENTS_PREFIX),
+            key_after(last),
+        ));
+    }
+
+    /// A future which resolves once more events have been published, after the transaction
+    /// commits.
+    pub fn watch(&self) -> BoxFuture<'static, Result<(), DbError>> {
+        self.tr.watch(Bytes::from_static(EVENTS_COUNT))
+    }
+}
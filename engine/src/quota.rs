@@ -0,0 +1,192 @@
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::database::{DbError, Tx};
+use crate::fdb_object::ObjDBTxHandle;
+use crate::object::SlotDef;
+use value::{encode_frame, Oid, Value};
+
+/// The slot create_object sets on each object it creates for a player, naming them. The objects a
+/// player owns, and the bytes stored in their slots, count against the player's quotas.
+pub const OWNER_SLOT: &str = "owner";
+
+/// The slot on a player, under the system object's key (so that players can't raise their own),
+/// overriding the world's limit on the objects they may own.
+pub const OBJECT_QUOTA_SLOT: &str = "object_quota";
+/// As OBJECT_QUOTA_SLOT, for the bytes they may store.
+pub const BYTE_QUOTA_SLOT: &str = "byte_quota";
+
+/// How many objects each player may own, and how many bytes they may store in their slots, unless
+/// the player's own slots say otherwise. None for no limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QuotaPolicy {
+    pub objects: Option<u64>,
+    pub bytes: Option<u64>,
+}
+
+/// What a player owns, and what they may.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Usage {
+    pub objects: i64,
+    pub bytes: i64,
+    pub object_limit: Option<u64>,
+    pub byte_limit: Option<u64>,
+}
+
+impl Usage {
+    /// Whether one more object would take them over their limit.
+    pub fn objects_exceeded(&self) -> bool {
+        self.object_limit
+            .is_some_and(|limit| self.objects + 1 > limit as i64)
+    }
+
+    /// Whether `more` bytes would take them over their limit.
+    pub fn bytes_exceeded(&self, more: i64) -> bool {
+        more > 0
+            && self
+                .byte_limit
+                .is_some_and(|limit| self.bytes + more > limit as i64)
+    }
+
+    /// A Vector of [name, value] pairs: "objects" and "bytes", and the "object_limit" and
+    /// "byte_limit" if there are any.
+    pub fn info(&self) -> Value {
+        let field =
+            |name: &str, value| Value::Vector(vec![Value::String(name.into()), Value::I64(value)]);
+        let mut info = vec![field("objects", self.objects), field("bytes", self.bytes)];
+        if let Some(limit) = self.object_limit {
+            info.push(field("object_limit", limit as i64));
+        }
+        if let Some(limit) = self.byte_limit {
+            info.push(field("byte_limit", limit as i64));
+        }
+        Value::Vector(info)
+    }
+}
+
+/// The bytes a slot's value counts for: its encoded size, or for a blob, its length.
+pub fn stored_size(value: &Value) -> i64 {
+    match value {
+        Value::Blob(len) => *len as i64,
+        value => encode_frame(value).len() as i64,
+    }
+}
+
+// Each owner's counts.
+const OWNED_OBJECTS: &str = "owned_objects";
+const OWNED_BYTES: &str = "owned_bytes";
+// The bytes stored in each owned object, so that they can be taken off its owner's when it's
+// destroyed.
+const BYTES: &str = "bytes";
+
+fn quota_subspace(oid: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(oid.id);
+    Subspace::new(Bytes::from_static("QUOTA".as_bytes())).subspace(&tup)
+}
+
+fn counter_key(oid: Oid, name: &str) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_string(name.to_string());
+    quota_subspace(oid).subspace(&tup).pack().into()
+}
+
+async fn limit(odb: &ObjDBTxHandle<'_>, player: Oid, name: &str) -> Option<u64> {
+    let system = Oid { id: Uuid::nil() };
+    match odb.get_slot(player, system, String::from(name)).await {
+        Ok(Value::I32(limit)) if limit >= 0 => Some(limit as u64),
+        Ok(Value::I64(limit)) if limit >= 0 => Some(limit as u64),
+        _ => None,
+    }
+}
+
+// Reads and keeps the counts of what players own via one transaction. Owners' counts are read at
+// a snapshot and updated with atomic operations, so that the objects of one owner can be written
+// without conflicting. The price is that writers racing each other can together take an owner a
+// little over a limit.
+pub struct QuotaTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> QuotaTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        QuotaTxHandle { tr: tx }
+    }
+
+    // A count as of the transaction's snapshot, without conflicting with the atomic additions
+    // other transactions make to it; or, if `exact`, as this transaction must find it.
+    async fn counter(&self, oid: Oid, name: &str, exact: bool) -> Result<i64, DbError> {
+        let value = match exact {
+            true => self.tr.get(counter_key(oid, name)).await?,
+            false => self.tr.snapshot_get(counter_key(oid, name)).await?,
+        };
+        Ok(value
+            .and_then(|v| Bytes::from(v)[..].try_into().ok())
+            .map(i64::from_le_bytes)
+            .unwrap_or(0))
+    }
+
+    /// The player who owns `oid`, if anyone does.
+    pub async fn owner_of(&self, oid: Oid) -> Option<Oid> {
+        match ObjDBTxHandle::new(self.tr)
+            .get_slot(oid, oid, String::from(OWNER_SLOT))
+            .await
+        {
+            Ok(Value::IdKey(owner)) => Some(owner),
+            _ => None,
+        }
+    }
+
+    /// What `player` owns, and their limits: the ones in their own slots, or else `policy`'s.
+    pub async fn usage(&self, player: Oid, policy: &QuotaPolicy) -> Result<Usage, DbError> {
+        let odb = ObjDBTxHandle::new(self.tr);
+        Ok(Usage {
+            objects: self.counter(player, OWNED_OBJECTS, false).await?,
+            bytes: self.counter(player, OWNED_BYTES, false).await?,
+            object_limit: limit(&odb, player, OBJECT_QUOTA_SLOT)
+                .await
+                .or(policy.objects),
+            byte_limit: limit(&odb, player, BYTE_QUOTA_SLOT).await.or(policy.bytes),
+        })
+    }
+
+    /// Make `owner` the owner of the new object `oid`.
+    pub fn created(&self, owner: Oid, oid: Oid) {
+        // New, so it has no metadata to keep.
+        let slot = SlotDef {
+            location: oid,
+            key: oid,
+            name: String::from(OWNER_SLOT),
+        };
+        ObjDBTxHandle::new(self.tr).set_slot_and_meta(slot, &Value::IdKey(owner), None);
+        self.tr.add(counter_key(owner, OWNED_OBJECTS), 1);
+    }
+
+    /// Count `delta` more bytes stored in `oid`'s slots, which `owner` owns.
+    pub fn stored(&self, owner: Oid, oid: Oid, delta: i64) {
+        if delta != 0 {
+            self.tr.add(counter_key(owner, OWNED_BYTES), delta);
+            self.tr.add(counter_key(oid, BYTES), delta);
+        }
+    }
+
+    /// Count `oid`, just restored whole with `bytes` stored in its slots, as `owner`'s.
+    pub fn restored(&self, owner: Oid, oid: Oid, bytes: i64) {
+        self.tr.add(counter_key(owner, OWNED_OBJECTS), 1);
+        self.stored(owner, oid, bytes);
+    }
+
+    /// Take `oid`, which is being destroyed, and the bytes stored in it off its owner's counts.
+    pub async fn destroyed(&self, oid: Oid) -> Result<(), DbError> {
+        if let Some(owner) = self.owner_of(oid).await {
+            let bytes = self.counter(oid, BYTES, true).await?;
+            self.tr.add(counter_key(owner, OWNED_OBJECTS), -1);
+            self.tr.add(counter_key(owner, OWNED_BYTES), -bytes);
+        }
+        self.tr
+            .clear_range(quota_subspace(oid).range(&Tuple::new()));
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use value::Value;
+
+/// Bytes of a String, Binary or Program shown in logs by default before it's cut short.
+pub const DEFAULT_MAX_LOGGED_BYTES: usize = 64;
+
+/// Slots (and verbs) whose values (and arguments) are masked in logs by default.
+pub const DEFAULT_SENSITIVE_SLOTS: &[&str] = &["password", "secret", "token"];
+
+/// How Values are shown in the server's logs, so that passwords and tokens passing through verbs
+/// don't end up in them, and large values don't flood them.
+///
+/// Every engine log line carrying a Value shows it through `value` or `slot`.
+#[derive(Clone, Debug)]
+pub struct RedactionPolicy {
+    /// Bytes of a String, Binary or Program shown before it's cut short, with its length.
+    pub max_bytes: usize,
+    /// Names of slots whose values, or verbs whose arguments and results, are masked entirely.
+    /// Matched without regard to case.
+    pub sensitive_slots: HashSet<String>,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        RedactionPolicy {
+            max_bytes: DEFAULT_MAX_LOGGED_BYTES,
+            sensitive_slots: DEFAULT_SENSITIVE_SLOTS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl RedactionPolicy {
+    pub fn is_sensitive(&self, slot_name: &str) -> bool {
+        self.sensitive_slots
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(slot_name))
+    }
+
+    /// `value`, as it may be logged.
+    pub fn value<'a>(&'a self, value: &'a Value) -> Redacted<'a> {
+        Redacted {
+            policy: self,
+            value,
+            masked: false,
+        }
+    }
+
+    /// `value`, as the value of (or arguments to) the slot `slot_name`, as it may be logged.
+    pub fn slot<'a>(&'a self, slot_name: &str, value: &'a Value) -> Redacted<'a> {
+        Redacted {
+            policy: self,
+            value,
+            masked: self.is_sensitive(slot_name),
+        }
+    }
+}
+
+/// A Value formatted for the log, like its Debug form but with long strings and bytes cut short,
+/// programs' code left out, and sensitive values masked.
+pub struct Redacted<'a> {
+    policy: &'a RedactionPolicy,
+    value: &'a Value,
+    masked: bool,
+}
+
+impl Redacted<'_> {
+    fn write_bytes(&self, f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+        let shown = &bytes[..bytes.len().min(self.policy.max_bytes)];
+        for b in shown {
+            write!(f, "{:02x}", b)?;
+        }
+        if shown.len() < bytes.len() {
+            write!(f, "... ({} bytes)", bytes.len())?;
+        }
+        Ok(())
+    }
+
+    fn write_value(&self, f: &mut fmt::Formatter<'_>, value: &Value) -> fmt::Result {
+        match value {
+            Value::String(s) if s.len() > self.policy.max_bytes => {
+                let mut end = self.policy.max_bytes;
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                write!(f, "String({:?}... ({} bytes))", &s[..end], s.len())
+            }
+            Value::Binary(bytes) => {
+                write!(f, "Binary(")?;
+                self.write_bytes(f, bytes)?;
+                write!(f, ")")
+            }
+            Value::Program(program) => write!(
+                f,
+                "Program({:?}, {} bytes)",
+                program.lang,
+                program.code.len()
+            ),
+            Value::Vector(values) => {
+                write!(f, "Vector([")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.write_value(f, value)?;
+                }
+                write!(f, "])")
+            }
+            value => write!(f, "{:?}", value),
+        }
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.masked {
+            true => write!(f, "<redacted>"),
+            false => self.write_value(f, self.value),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use regex::Regex;
+use serde::Serialize;
+
+use crate::object::SlotDef;
+
+/// A find (and optionally replace) across the source of every program in the world.
+///
+/// Only WAT programs are searched; programs stored as wasm binaries have no text to match.
+pub struct Refactor {
+    pub pattern: Regex,
+    /// What to replace matches with, which may refer to the pattern's groups as `$1`, `$name`.
+    /// None only reports matches.
+    pub replacement: Option<String>,
+    /// Whether to write the replacements back. Otherwise they're only compiled and reported.
+    pub apply: bool,
+}
+
+/// A line of a program's source which matched.
+#[derive(Serialize, Debug)]
+pub struct MatchedLine {
+    /// Counting from 1.
+    pub line: usize,
+    pub before: String,
+    /// The line with the replacement made, if there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeStatus {
+    /// Matched, with nothing replaced.
+    Matched,
+    /// Replaced, and the result compiles, but it wasn't applied.
+    Compiles,
+    /// The replaced source didn't compile, or imports a builtin which doesn't exist, so the slot
+    /// was left alone.
+    CompileFailed(String),
+    /// The slot changed between being scanned and the replacements being applied, so it was left
+    /// alone.
+    Conflicted,
+    /// The replacement was written to the slot.
+    Applied,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SlotChange {
+    pub slot: SlotDef,
+    pub matches: usize,
+    pub lines: Vec<MatchedLine>,
+    pub status: ChangeStatus,
+}
+
+/// What a refactor found, and did.
+#[derive(Serialize, Debug, Default)]
+pub struct RefactorReport {
+    pub pattern: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+    pub applied: bool,
+    /// Programs whose source was searched.
+    pub programs_scanned: usize,
+    /// Programs skipped for being wasm binaries.
+    pub binaries_skipped: usize,
+    pub changes: Vec<SlotChange>,
+}
+
+impl RefactorReport {
+    /// True unless some replacement which should have been made wasn't.
+    pub fn succeeded(&self) -> bool {
+        self.changes.iter().all(|change| {
+            matches!(
+                change.status,
+                ChangeStatus::Matched | ChangeStatus::Compiles | ChangeStatus::Applied
+            )
+        })
+    }
+}
+
+impl Refactor {
+    /// The lines of `source` which match, and the source with every match replaced if there's a
+    /// replacement. None if nothing matched.
+    pub fn scan(&self, source: &str) -> Option<(usize, Vec<MatchedLine>, Option<String>)> {
+        let matches = self.pattern.find_iter(source).count();
+        if matches == 0 {
+            return None;
+        }
+        let lines = source
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| self.pattern.is_match(line))
+            .map(|(i, line)| MatchedLine {
+                line: i + 1,
+                before: line.to_string(),
+                after: self
+                    .replacement
+                    .as_ref()
+                    .map(|r| self.pattern.replace_all(line, r.as_str()).into_owned()),
+            })
+            .collect();
+        let replaced = self
+            .replacement
+            .as_ref()
+            .map(|r| self.pattern.replace_all(source, r.as_str()).into_owned());
+        Some((matches, lines, replaced))
+    }
+}
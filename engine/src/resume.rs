@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use value::Oid;
+
+/// What a reconnecting websocket client offers as a subprotocol, followed by its resume token,
+/// alongside any it speaks, e.g. `Sec-WebSocket-Protocol: room.rpc, room.resume.<token>`. It's
+/// never chosen, so the token isn't sent back, and unlike a query it isn't logged with the URL.
+pub const RESUME_SUBPROTOCOL_PREFIX: &str = "room.resume.";
+
+/// How connections which drop may be resumed.
+#[derive(Clone, Copy, Debug)]
+pub struct ResumeOptions {
+    /// How long a dropped connection is kept, with the messages sent to it buffered, for its
+    /// client to come back to it.
+    pub grace: Duration,
+}
+
+/// The key resume tokens are signed with. It's made afresh at startup, as the connections the
+/// tokens are for don't outlive the server either.
+pub struct ResumeKey {
+    key: [u8; 32],
+}
+
+impl Default for ResumeKey {
+    fn default() -> Self {
+        let mut key = [0; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        ResumeKey { key }
+    }
+}
+
+impl ResumeKey {
+    fn mac(&self, connection: Oid, nonce: Uuid) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(connection.id.as_bytes());
+        mac.update(nonce.as_bytes());
+        mac
+    }
+
+    /// The token a client gives to resume `connection`: its Oid, `nonce` and a signature of both,
+    /// in hex. The nonce is what makes each token the connection's given good for one use.
+    pub fn token(&self, connection: Oid, nonce: Uuid) -> String {
+        let signature = self.mac(connection, nonce).finalize().into_bytes();
+        let signature: String = signature.iter().map(|b| format!("{:02x}", b)).collect();
+        format!(
+            "{}.{}.{}",
+            connection.id.simple(),
+            nonce.simple(),
+            signature
+        )
+    }
+
+    /// The connection `token` is for, and its nonce, if it's one this key signed.
+    pub fn verify(&self, token: &str) -> Option<(Oid, Uuid)> {
+        let mut parts = token.splitn(3, '.');
+        let (id, nonce, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        let connection = Oid {
+            id: Uuid::parse_str(id).ok()?,
+        };
+        let nonce = Uuid::parse_str(nonce).ok()?;
+        if signature.len() % 2 != 0 || !signature.is_ascii() {
+            return None;
+        }
+        let signature = (0..signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&signature[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        self.mac(connection, nonce)
+            .verify_slice(&signature)
+            .ok()
+            .map(|_| (connection, nonce))
+    }
+}
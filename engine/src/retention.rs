@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+
+const HOUR_MILLIS: u64 = 60 * 60 * 1000;
+const DAY_MILLIS: u64 = 24 * HOUR_MILLIS;
+
+/// Which snapshots to keep as new ones are written: the most recent `keep_last`, and the most
+/// recent in each of the last `hourly` hours, `daily` days and `weekly` weeks which have any
+/// (days and weeks in UTC, weeks starting on Monday). The most recent snapshot is always kept.
+/// With none of these set, every snapshot is kept.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    pub keep_last: Option<usize>,
+    pub hourly: usize,
+    pub daily: usize,
+    pub weekly: usize,
+}
+
+impl RetentionPolicy {
+    /// Whether the policy would ever prune anything.
+    pub fn prunes(&self) -> bool {
+        self.keep_last.is_some() || self.hourly > 0 || self.daily > 0 || self.weekly > 0
+    }
+
+    /// Which of the snapshots taken at `taken` (milliseconds since the Unix epoch, oldest first)
+    /// to keep, by their indices.
+    pub fn retained(&self, taken: &[u64]) -> HashSet<usize> {
+        let mut keep = HashSet::new();
+        if !self.prunes() {
+            keep.extend(0..taken.len());
+            return keep;
+        }
+        let newest_first = (0..taken.len()).rev();
+        keep.extend(
+            newest_first
+                .clone()
+                .take(self.keep_last.unwrap_or(0).max(1)),
+        );
+
+        // The Unix epoch was a Thursday.
+        let week = |millis: u64| (millis / DAY_MILLIS + 3) / 7;
+        let periods: [(usize, &dyn Fn(u64) -> u64); 3] = [
+            (self.hourly, &|millis| millis / HOUR_MILLIS),
+            (self.daily, &|millis| millis / DAY_MILLIS),
+            (self.weekly, &week),
+        ];
+        for (count, period) in periods {
+            let mut seen = HashSet::new();
+            for i in newest_first.clone() {
+                if seen.len() == count {
+                    break;
+                }
+                // The first seen in each period is the most recent in it.
+                if seen.insert(period(taken[i])) {
+                    keep.insert(i);
+                }
+            }
+        }
+        keep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Monday 2024-01-01 00:00 UTC, in milliseconds since the Unix epoch.
+    const MONDAY: u64 = 1_704_067_200_000;
+
+    fn sorted(keep: HashSet<usize>) -> Vec<usize> {
+        let mut keep: Vec<_> = keep.into_iter().collect();
+        keep.sort_unstable();
+        keep
+    }
+
+    #[test]
+    fn without_a_policy_everything_is_kept() {
+        let policy = RetentionPolicy::default();
+        assert!(!policy.prunes());
+        assert_eq!(sorted(policy.retained(&[1, 2, 3])), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn keep_last_keeps_the_most_recent() {
+        let policy = RetentionPolicy {
+            keep_last: Some(2),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(sorted(policy.retained(&[1, 2, 3, 4])), vec![2, 3]);
+    }
+
+    #[test]
+    fn the_most_recent_is_always_kept() {
+        let policy = RetentionPolicy {
+            keep_last: Some(0),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(sorted(policy.retained(&[1, 2, 3])), vec![2]);
+        assert!(policy.retained(&[]).is_empty());
+    }
+
+    #[test]
+    fn hourly_keeps_the_most_recent_in_each_hour() {
+        let policy = RetentionPolicy {
+            hourly: 2,
+            ..RetentionPolicy::default()
+        };
+        let taken = [
+            MONDAY,
+            MONDAY + HOUR_MILLIS,
+            MONDAY + HOUR_MILLIS + 1000,
+            MONDAY + 2 * HOUR_MILLIS,
+            MONDAY + 2 * HOUR_MILLIS + 1000,
+        ];
+        // The last in the two most recent hours; the earlier hour isn't kept.
+        assert_eq!(sorted(policy.retained(&taken)), vec![2, 4]);
+    }
+
+    #[test]
+    fn daily_counts_days_with_snapshots() {
+        let policy = RetentionPolicy {
+            daily: 2,
+            ..RetentionPolicy::default()
+        };
+        // Nothing on the second day, so the two most recent days with any are the first and third.
+        let taken = [MONDAY, MONDAY + 1000, MONDAY + 2 * DAY_MILLIS];
+        assert_eq!(sorted(policy.retained(&taken)), vec![1, 2]);
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let policy = RetentionPolicy {
+            weekly: 2,
+            ..RetentionPolicy::default()
+        };
+        // The Sunday before, the Monday, and the Sunday after.
+        let taken = [MONDAY - DAY_MILLIS, MONDAY, MONDAY + 6 * DAY_MILLIS];
+        assert_eq!(sorted(policy.retained(&taken)), vec![0, 2]);
+    }
+
+    #[test]
+    fn periods_combine() {
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            daily: 1,
+            weekly: 2,
+            ..RetentionPolicy::default()
+        };
+        let taken = [
+            MONDAY - 7 * DAY_MILLIS,
+            MONDAY - 1000,
+            MONDAY + 1000,
+            MONDAY + 2000,
+        ];
+        // The newest (also the day's and this week's), and last week's newest.
+        assert_eq!(sorted(policy.retained(&taken)), vec![1, 3]);
+    }
+}
@@ -0,0 +1,376 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Error};
+
+/// Parse a human duration, e.g. "2h30m", "90s" or "1day 12hours".
+pub fn parse_duration(text: &str) -> Result<Duration, Error> {
+    humantime::parse_duration(text.trim())
+        .map_err(|e| anyhow!("Invalid duration {:?}: {}", text, e))
+}
+
+// The bounds of each of a cron expression's fields, and the names it may use for their values.
+struct Field {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+}
+
+const MINUTE: Field = Field {
+    name: "minute",
+    min: 0,
+    max: 59,
+    names: &[],
+};
+const HOUR: Field = Field {
+    name: "hour",
+    min: 0,
+    max: 23,
+    names: &[],
+};
+const DAY_OF_MONTH: Field = Field {
+    name: "day_of_month",
+    min: 1,
+    max: 31,
+    names: &[],
+};
+const MONTH: Field = Field {
+    name: "month",
+    min: 1,
+    max: 12,
+    names: &[
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ],
+};
+// 7 is Sunday as well as 0.
+const DAY_OF_WEEK: Field = Field {
+    name: "day_of_week",
+    min: 0,
+    max: 7,
+    names: &["sun", "mon", "tue", "wed", "thu", "fri", "sat"],
+};
+
+impl Field {
+    fn value(&self, text: &str) -> Result<u32, Error> {
+        let lower = text.to_ascii_lowercase();
+        let value = match self.names.iter().position(|name| *name == lower) {
+            Some(i) => i as u32 + self.min,
+            None => text
+                .parse()
+                .map_err(|_| anyhow!("Invalid {} {:?}", self.name, text))?,
+        };
+        if value < self.min || value > self.max {
+            bail!(
+                "{} {} is outside {}-{}",
+                self.name,
+                value,
+                self.min,
+                self.max
+            );
+        }
+        Ok(value)
+    }
+
+    // The values `text` selects, as a bit set. Also whether it leaves the field unrestricted, which
+    // as with cron is any starting "*".
+    fn parse(&self, text: &str) -> Result<(u64, bool), Error> {
+        let mut bits = 0u64;
+        for part in text.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => match step.parse::<u32>() {
+                    Ok(step) if step > 0 => (range, step),
+                    _ => bail!("Invalid {} step {:?}", self.name, step),
+                },
+                None => (part, 1),
+            };
+            let (start, end) = match range {
+                "*" => (self.min, self.max),
+                _ => match range.split_once('-') {
+                    Some((start, end)) => (self.value(start)?, self.value(end)?),
+                    // "5/15" runs from 5 to the end.
+                    None if step > 1 => (self.value(range)?, self.max),
+                    None => {
+                        let value = self.value(range)?;
+                        (value, value)
+                    }
+                },
+            };
+            if start > end {
+                bail!("{} range {:?} runs backwards", self.name, range);
+            }
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok((bits, text.starts_with('*')))
+    }
+}
+
+/// A cron expression: minute, hour, day of month, month and day of week, in UTC.
+///
+/// Each field may be `*`, a value, a range (`1-5`), a step over either (`*/15`, `0-30/10`), or a
+/// comma separated list of those. Months and days of the week may also be given by name (`jan`,
+/// `mon`). As with cron, when both days of the month and of the week are restricted, a day
+/// matching either will do. `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are also
+/// accepted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    // Whether a day need only match one of days_of_month and days_of_week.
+    either_day: bool,
+}
+
+// Days in each month of a leap year, so that February 29th counts as a day which can come.
+const MONTH_DAYS: [u32; 12] = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+// How far ahead to look for a time a schedule fires at: long enough to find a February 29th which
+// is a particular day of the week.
+const SEARCH_DAYS: i64 = 366 * 28;
+
+fn values(bits: u64) -> Vec<u32> {
+    (0..64).filter(|i| bits & (1 << i) != 0).collect()
+}
+
+// The civil date of a day counted from the Unix epoch, as (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl CronSchedule {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let expanded = match text.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            bail!(
+                "Expected 5 fields (minute hour day_of_month month day_of_week), got {}",
+                fields.len()
+            );
+        };
+        let (minutes, _) = MINUTE.parse(minute)?;
+        let (hours, _) = HOUR.parse(hour)?;
+        let (days_of_month, any_day_of_month) = DAY_OF_MONTH.parse(day_of_month)?;
+        let (months, _) = MONTH.parse(month)?;
+        let (mut days_of_week, any_day_of_week) = DAY_OF_WEEK.parse(day_of_week)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week & !(1 << 7)) | 1;
+        }
+        let schedule = CronSchedule {
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            either_day: !any_day_of_month && !any_day_of_week,
+        };
+        let possible = schedule.either_day
+            || values(months).iter().any(|month| {
+                values(days_of_month)
+                    .iter()
+                    .any(|day| *day <= MONTH_DAYS[*month as usize - 1])
+            });
+        if !possible {
+            bail!("{:?} names no day which exists, so would never fire", text);
+        }
+        Ok(schedule)
+    }
+
+    fn day_matches(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // The epoch was a Thursday.
+        let weekday = (days + 4).rem_euclid(7) as u32;
+        let day_of_month = self.days_of_month & (1 << day) != 0;
+        let day_of_week = self.days_of_week & (1 << weekday) != 0;
+        self.months & (1 << month) != 0
+            && match self.either_day {
+                true => day_of_month || day_of_week,
+                false => day_of_month && day_of_week,
+            }
+    }
+
+    /// The first time after `time` that the schedule fires at.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+        let next_minute = since_epoch.as_secs() as i64 / 60 + 1;
+        let first_day = next_minute.div_euclid(24 * 60);
+        for days in first_day..first_day + SEARCH_DAYS {
+            if !self.day_matches(days) {
+                continue;
+            }
+            let earliest = match days == first_day {
+                true => next_minute.rem_euclid(24 * 60),
+                false => 0,
+            };
+            for hour in values(self.hours) {
+                for minute in values(self.minutes) {
+                    let minute_of_day = (hour * 60 + minute) as i64;
+                    if minute_of_day >= earliest {
+                        let minutes = days * 24 * 60 + minute_of_day;
+                        return Some(UNIX_EPOCH + Duration::from_secs(minutes as u64 * 60));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Each field's name and the values it selects.
+    pub fn fields(&self) -> [(&'static str, Vec<u32>); 5] {
+        [
+            (MINUTE.name, values(self.minutes)),
+            (HOUR.name, values(self.hours)),
+            (DAY_OF_MONTH.name, values(self.days_of_month)),
+            (MONTH.name, values(self.months)),
+            (DAY_OF_WEEK.name, values(self.days_of_week)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-01T00:00:00Z, a Monday.
+    const NEW_YEAR_2024: u64 = 1_704_067_200;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn field(schedule: &CronSchedule, name: &str) -> Vec<u32> {
+        let fields = schedule.fields();
+        let (_, values) = fields.iter().find(|(n, _)| *n == name).unwrap();
+        values.clone()
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("2h30m").unwrap(), Duration::from_secs(9000));
+        assert_eq!(parse_duration(" 90s ").unwrap(), Duration::from_secs(90));
+        assert_eq!(
+            parse_duration("1day 12hours").unwrap(),
+            Duration::from_secs(36 * 60 * 60)
+        );
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn cron_fields() {
+        let schedule = CronSchedule::parse("*/15 9-17 * jan-mar mon-fri").unwrap();
+        assert_eq!(field(&schedule, "minute"), vec![0, 15, 30, 45]);
+        assert_eq!(field(&schedule, "hour"), (9..=17).collect::<Vec<_>>());
+        assert_eq!(
+            field(&schedule, "day_of_month"),
+            (1..=31).collect::<Vec<_>>()
+        );
+        assert_eq!(field(&schedule, "month"), vec![1, 2, 3]);
+        assert_eq!(field(&schedule, "day_of_week"), vec![1, 2, 3, 4, 5]);
+
+        let schedule = CronSchedule::parse("5/20,1 0 1,15 * 7").unwrap();
+        assert_eq!(field(&schedule, "minute"), vec![1, 5, 25, 45]);
+        assert_eq!(field(&schedule, "day_of_month"), vec![1, 15]);
+        // Sunday, given as 7, is 0.
+        assert_eq!(field(&schedule, "day_of_week"), vec![0]);
+    }
+
+    #[test]
+    fn cron_shorthands() {
+        assert_eq!(
+            CronSchedule::parse("@daily").unwrap(),
+            CronSchedule::parse("0 0 * * *").unwrap()
+        );
+        assert_eq!(
+            CronSchedule::parse(" @hourly ").unwrap(),
+            CronSchedule::parse("0 * * * *").unwrap()
+        );
+        assert_eq!(
+            CronSchedule::parse("@annually").unwrap(),
+            CronSchedule::parse("0 0 1 1 *").unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_cron_expressions() {
+        for text in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "* * * smarch *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "0 0 30 feb *",
+        ] {
+            assert!(CronSchedule::parse(text).is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn next_after() {
+        let schedule = CronSchedule::parse("30 9 * * *").unwrap();
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2024)),
+            Some(at(NEW_YEAR_2024 + 9 * 3600 + 30 * 60))
+        );
+        // Strictly after: a schedule firing at the time given next fires a day later.
+        let schedule = CronSchedule::parse("@daily").unwrap();
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2024)),
+            Some(at(NEW_YEAR_2024 + 86400))
+        );
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2024 - 1)),
+            Some(at(NEW_YEAR_2024))
+        );
+    }
+
+    #[test]
+    fn next_after_leap_day() {
+        let schedule = CronSchedule::parse("0 12 29 2 *").unwrap();
+        // 2024-02-29T12:00:00Z.
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2024)),
+            Some(at(1_709_208_000))
+        );
+    }
+
+    #[test]
+    fn either_day_matches() {
+        // The 13th, or any Friday; the first Friday of 2024 is the 5th.
+        let schedule = CronSchedule::parse("0 0 13 * fri").unwrap();
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2024)),
+            Some(at(NEW_YEAR_2024 + 4 * 86400))
+        );
+        // Only the 13th when the day of the week isn't restricted.
+        let schedule = CronSchedule::parse("0 0 13 * *").unwrap();
+        assert_eq!(
+            schedule.next_after(at(NEW_YEAR_2024)),
+            Some(at(NEW_YEAR_2024 + 12 * 86400))
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use value::Error::ResourceLimit;
+use value::{encode_frame, Value};
+
+/// How much a dispatch chain's scratchpad may hold, in bytes of its values' encodings (and their
+/// names).
+pub const MAX_SCRATCH_BYTES: usize = 16 * 1024 * 1024;
+
+/// Values shared between the verbs of one dispatch chain (a verb and those it invokes, however
+/// deeply), by name, so that they can hand each other large intermediate results without writing
+/// them to slots or passing them as arguments. It's held by the host, and emptied when the verb
+/// at the root of the chain returns.
+#[derive(Default)]
+pub struct Scratchpad {
+    entries: HashMap<String, (Value, usize)>,
+    bytes: usize,
+}
+
+impl Scratchpad {
+    /// Put `value` under `name`, replacing whatever was there; or ResourceLimit, leaving the
+    /// scratchpad as it was, if that would make it too big.
+    pub fn put(&mut self, name: String, value: Value) -> Result<(), value::Error> {
+        let size = name.len() + encode_frame(&value).len();
+        let replaced = self.entries.get(&name).map_or(0, |(_, size)| *size);
+        let bytes = self.bytes - replaced + size;
+        if bytes > MAX_SCRATCH_BYTES {
+            return Err(ResourceLimit);
+        }
+        self.bytes = bytes;
+        self.entries.insert(name, (value, size));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.entries.get(name).map(|(value, _)| value.clone())
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+}
@@ -0,0 +1,185 @@
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use futures::stream::{BoxStream, StreamExt};
+
+use crate::database::{DbError, Tx};
+use crate::fdb_object::ObjDBTxHandle;
+use crate::object::{ObjDBHandle, SlotDef};
+use value::{Oid, Value};
+
+/// The slot, under an object's own key, which opts it in to search: its String slots under its own
+/// key are indexed as they're set while it holds a number other than zero. Slots under other keys
+/// are never indexed, so that searching can't reveal them.
+pub const SEARCHABLE_SLOT: &str = "searchable";
+
+/// The most slots a search looks at, and so finds.
+pub const SEARCH_LIMIT: usize = 1000;
+
+// Longer words are indexed by their first this many characters, to keep keys short.
+const MAX_TOKEN_CHARS: usize = 64;
+
+/// The words `text` is indexed by: its runs of letters and digits, in lower case, without
+/// repeats.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase().chars().take(MAX_TOKEN_CHARS).collect())
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+// The inverted index: a key ("INDEX", token, location, key, name) for each word of each String
+// slot indexed. Entries are only added and cleared as slots are set, so clearing or moving a slot,
+// or its object opting out, leaves them stale; they're checked against the slot and its object as
+// they're found, and cleared if so.
+fn token_subspace(token: &str) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_string(token.to_string());
+    Subspace::new(Bytes::from_static("INDEX".as_bytes())).subspace(&tup)
+}
+
+fn entry_key(token: &str, slot: &SlotDef) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_uuid(slot.location.id);
+    tup.add_uuid(slot.key.id);
+    tup.add_string(slot.name.clone());
+    token_subspace(token).subspace(&tup).pack().into()
+}
+
+fn entry_slot(token: &str, key: Key) -> SlotDef {
+    let bytes: Bytes = key.into();
+    let tuple = token_subspace(token).unpack(&bytes).unwrap();
+    SlotDef {
+        location: Oid {
+            id: *tuple.get_uuid_ref(0).unwrap(),
+        },
+        key: Oid {
+            id: *tuple.get_uuid_ref(1).unwrap(),
+        },
+        name: tuple.get_string_ref(2).unwrap().clone(),
+    }
+}
+
+/// Whether `value`, held in an object's 'searchable' slot, opts it in to search.
+pub fn opts_in(value: &Value) -> bool {
+    matches!(value, Value::I32(n) if *n != 0) || matches!(value, Value::I64(n) if *n != 0)
+}
+
+// Whether `oid` opts in to search.
+async fn searchable(odb: &ObjDBTxHandle<'_>, oid: Oid) -> bool {
+    odb.get_slot(oid, oid, String::from(SEARCHABLE_SLOT))
+        .await
+        .is_ok_and(|value| opts_in(&value))
+}
+
+// Reads and writes the search index via one transaction.
+pub struct SearchTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> SearchTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        SearchTxHandle { tr: tx }
+    }
+
+    /// Index `slot` by the words of `text`.
+    pub fn index(&self, slot: &SlotDef, text: &str) {
+        for token in tokenize(text) {
+            self.tr.set(entry_key(&token, slot), Bytes::new());
+        }
+    }
+
+    /// Stop indexing `slot` by the words of `text`, which it held.
+    pub fn unindex(&self, slot: &SlotDef, text: &str) {
+        for token in tokenize(text) {
+            self.tr.clear(entry_key(&token, slot));
+        }
+    }
+
+    /// Keep the index up to date with `slot` being set to `value`, before it's set: index the
+    /// words of what it's set to, if it's a String on an object which opts in to search, instead
+    /// of those it held. An object opting in has the String slots it has already indexed.
+    pub async fn update(&self, slot: &SlotDef, value: &Value) {
+        if slot.location != slot.key {
+            return;
+        }
+        let odb = ObjDBTxHandle::new(self.tr);
+        if slot.name == SEARCHABLE_SLOT {
+            if opts_in(value) {
+                if let Ok(mut slots) = odb.dump_slots(slot.location) {
+                    while let Some(Ok((slot, value))) = slots.next().await {
+                        if let (true, Value::String(text)) = (slot.key == slot.location, value) {
+                            self.index(&slot, &text);
+                        }
+                    }
+                }
+            }
+            return;
+        }
+        if !searchable(&odb, slot.location).await {
+            return;
+        }
+        if let Ok(Value::String(previous)) = odb
+            .get_slot(slot.location, slot.key, slot.name.clone())
+            .await
+        {
+            self.unindex(slot, &previous);
+        }
+        if let Value::String(text) = value {
+            self.index(slot, text);
+        }
+    }
+
+    /// The String slots indexed which hold every word of `query`, on objects which still opt in
+    /// to search, among the first `limit` indexed by one of its words. Empty if it has no words.
+    pub fn search(&self, query: &str, limit: usize) -> BoxStream<'_, Result<SlotDef, DbError>> {
+        let mut tokens = tokenize(query);
+        // Candidates are those indexed by the longest word, likely the rarest, and the rest are
+        // checked against the slot itself.
+        tokens.sort_by_key(|token| std::cmp::Reverse(token.chars().count()));
+        let first = match tokens.first() {
+            Some(first) => first.clone(),
+            None => return futures::stream::empty().boxed(),
+        };
+        let range = token_subspace(&first).range(&Tuple::new());
+        let tr = self.tr;
+        self.tr
+            .snapshot_get_range(range)
+            .take(limit)
+            .filter_map(move |kv| {
+                let first = first.clone();
+                let tokens = tokens.clone();
+                async move {
+                    let slot = match kv {
+                        Ok((key, _)) => entry_slot(&first, key),
+                        Err(e) => return Some(Err(e)),
+                    };
+                    // Not a snapshot read, so that a stale entry isn't cleared just as the slot's
+                    // set to hold the word again.
+                    let odb = ObjDBTxHandle::new(tr);
+                    let held = match odb
+                        .get_slot(slot.location, slot.key, slot.name.clone())
+                        .await
+                    {
+                        Ok(Value::String(text)) => tokenize(&text),
+                        _ => vec![],
+                    };
+                    if !held.contains(&first)
+                        || slot.key != slot.location
+                        || !searchable(&odb, slot.location).await
+                    {
+                        tr.clear(entry_key(&first, &slot));
+                        return None;
+                    }
+                    match tokens.iter().all(|token| held.contains(token)) {
+                        true => Some(Ok(slot)),
+                        false => None,
+                    }
+                }
+            })
+            .boxed()
+    }
+}
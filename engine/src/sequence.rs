@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+
+use crate::database::{DbError, Tx};
+
+/// How many ids a node reserves from a sequence at a time.
+pub const SEQUENCE_BATCH: i64 = 100;
+
+/// Named counters handing out increasing ids (ticket numbers, mail ids), starting from 1.
+///
+/// Each holds the highest id reserved so far. Ids are reserved in ranges with an atomic add, so
+/// nodes only touch the counter once per range rather than once per id.
+pub struct SequenceTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+fn sequence_key(name: &str) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_string(name.to_string());
+    Subspace::new(Bytes::from_static("SEQUENCE".as_bytes()))
+        .subspace(&tup)
+        .pack()
+        .into()
+}
+
+impl<'tx_lifetime> SequenceTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        SequenceTxHandle { tr: tx }
+    }
+
+    /// Reserve the next `count` ids of `name`.
+    pub async fn reserve(&self, name: &str, count: i64) -> Result<Range<i64>, DbError> {
+        let key = sequence_key(name);
+        self.tr.add(key.clone(), count);
+        // Reading the counter back makes concurrent reservations conflict, so no two get the same
+        // range.
+        let end = match self.tr.get(key).await? {
+            Some(v) => Bytes::from(v)[..]
+                .try_into()
+                .map(i64::from_le_bytes)
+                .unwrap_or(count),
+            None => count,
+        };
+        Ok(end - count + 1..end + 1)
+    }
+}
+
+/// The ids this node has reserved from each sequence but not yet handed out.
+///
+/// Ids from a node are increasing, but those from different nodes interleave, and ids left in a
+/// range when the server stops are never used.
+#[derive(Default)]
+pub struct SequenceCache {
+    ranges: tokio::sync::Mutex<HashMap<String, Range<i64>>>,
+}
+
+impl SequenceCache {
+    /// The next id of `name`, reserving another range with `reserve` if this node has used up its
+    /// last.
+    pub async fn next<F, Fut>(&self, name: &str, reserve: F) -> Result<i64, anyhow::Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<Range<i64>, anyhow::Error>>,
+    {
+        // Held while reserving, so that only one range is reserved when many want one at once.
+        let mut ranges = self.ranges.lock().await;
+        if let Some(id) = ranges.get_mut(name).and_then(|range| range.next()) {
+            return Ok(id);
+        }
+        let mut range = reserve().await?;
+        let id = range
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty sequence range"))?;
+        ranges.insert(name.to_string(), range);
+        Ok(id)
+    }
+}
@@ -0,0 +1,185 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use serde::{Deserialize, Serialize};
+use tokio_stream::StreamExt;
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// What a server with an embedded database calls itself in session records, if it isn't given a
+/// name. No other server can share its database, so there's no other to be confused with. Servers
+/// sharing FoundationDB must each be named.
+pub const SINGLE_NODE: &str = "local";
+
+/// A connection logged in to a player, as recorded in the database while it lasts, so that who's
+/// online (and from where) can be seen from any server sharing the database, and by the admin
+/// tools, which serve no connections themselves.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SessionRecord {
+    pub player: Oid,
+    /// The server the connection is to.
+    pub node: String,
+    /// The client's address, as the connection sees it.
+    pub address: String,
+    /// When the connection logged in, in seconds since the Unix epoch.
+    pub logged_in: i64,
+}
+
+/// A session, with when it was last heard from.
+#[derive(Serialize, Clone, Debug)]
+pub struct SessionBinding {
+    pub connection: Oid,
+    #[serde(flatten)]
+    pub record: SessionRecord,
+    /// When the connection last sent a command, in seconds since the Unix epoch.
+    pub last_active: i64,
+    pub idle_secs: i64,
+    /// Whether the connection is to this server. Sessions on other servers are as they were
+    /// recorded.
+    pub local: bool,
+}
+
+impl SessionRecord {
+    fn value(&self) -> fdb::Value {
+        let mut tup = Tuple::new();
+        tup.add_uuid(self.player.id);
+        tup.add_string(self.node.clone());
+        tup.add_string(self.address.clone());
+        tup.add_i64(self.logged_in);
+        tup.pack().into()
+    }
+
+    fn from_value(value: fdb::Value) -> Self {
+        let tup = Tuple::from_bytes(value).unwrap();
+        SessionRecord {
+            player: Oid {
+                id: *tup.get_uuid_ref(0).unwrap(),
+            },
+            node: tup.get_string_ref(1).unwrap().clone(),
+            address: tup.get_string_ref(2).unwrap().clone(),
+            logged_in: tup.get_i64(3).unwrap(),
+        }
+    }
+}
+
+fn session_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("SESSION".as_bytes()))
+}
+
+fn connection_tuple(connection: Oid) -> Tuple {
+    let mut tup = Tuple::new();
+    tup.add_uuid(connection.id);
+    tup
+}
+
+fn session_key(connection: Oid) -> Key {
+    session_subspace()
+        .subspace(&connection_tuple(connection))
+        .pack()
+        .into()
+}
+
+fn active_key(connection: Oid) -> Key {
+    Subspace::new(Bytes::from_static("ACTIVITY".as_bytes()))
+        .subspace(&connection_tuple(connection))
+        .pack()
+        .into()
+}
+
+/// A client's address with its host part and port dropped, for listing sessions to those who
+/// needn't know exactly who's connecting: the /24 of an IPv4 address, or the /48 of an IPv6 one.
+pub fn redact_address(address: &str) -> String {
+    match address.parse::<SocketAddr>().map(|address| address.ip()) {
+        Ok(IpAddr::V4(ip)) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let [a, b, c, ..] = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", a, b, c)
+        }
+        Err(_) => String::from("redacted"),
+    }
+}
+
+pub fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// The record of each logged in connection, by connection.
+///
+/// When each was last active is kept apart from it and updated with an atomic max, so that
+/// counting a command doesn't conflict with anything reading the record.
+pub struct SessionTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> SessionTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        SessionTxHandle { tr: tx }
+    }
+
+    /// Record `connection` as logged in, replacing whatever session it had before.
+    pub fn start(&self, connection: Oid, record: &SessionRecord) {
+        self.tr.set(session_key(connection), record.value());
+        self.tr.max(active_key(connection), record.logged_in);
+    }
+
+    /// Note a command from `connection` at `now`.
+    pub fn touch(&self, connection: Oid, now: SystemTime) {
+        self.tr.max(active_key(connection), unix_secs(now));
+    }
+
+    pub fn end(&self, connection: Oid) {
+        self.tr.clear(session_key(connection));
+        self.tr.clear(active_key(connection));
+    }
+
+    /// Every session recorded, with when it was last active, in connection order.
+    pub async fn list(&self) -> Result<Vec<(Oid, SessionRecord, i64)>, DbError> {
+        let sessions = session_subspace();
+        let mut stream = self.tr.get_range(sessions.range(&Tuple::new()));
+        let mut records = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, value) = kv?;
+            let key_bytes: Bytes = key.into();
+            let tuple = sessions.unpack(&key_bytes).unwrap();
+            let connection = Oid {
+                id: *tuple.get_uuid_ref(0).unwrap(),
+            };
+            records.push((connection, SessionRecord::from_value(value)));
+        }
+        let mut sessions = vec![];
+        for (connection, record) in records {
+            let last_active = self
+                .tr
+                .get(active_key(connection))
+                .await?
+                .and_then(|v| Bytes::from(v)[..].try_into().ok())
+                .map(i64::from_le_bytes)
+                .unwrap_or(0);
+            sessions.push((connection, record, last_active));
+        }
+        Ok(sessions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_redacted_to_their_network() {
+        assert_eq!(redact_address("203.0.113.77:51234"), "203.0.113.0/24");
+        assert_eq!(
+            redact_address("[2001:db8:85a3:8d3:1319:8a2e:370:7348]:443"),
+            "2001:db8:85a3::/48"
+        );
+        assert_eq!(redact_address("not an address"), "redacted");
+    }
+}
@@ -0,0 +1,265 @@
+use value::Error::BadType;
+use value::Value;
+
+/// A builtin for working with strings, Vectors and numbers, which only computes its result from
+/// its arguments, so that simple verbs needn't bring a whole language runtime along to do text
+/// processing.
+///
+/// Strings are indexed by character, not byte. Ranges are clamped to what's there, and an index
+/// out of range gets BadType.
+pub struct StdlibBuiltin {
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub description: &'static str,
+    /// The result, or None if the arguments aren't what the signature says.
+    pub call: fn(&[Value]) -> Option<Value>,
+}
+
+pub const STDLIB: &[StdlibBuiltin] = &[
+    StdlibBuiltin {
+        name: "str_concat",
+        signature: "(String ...) -> String",
+        description: "The strings joined together.",
+        call: str_concat,
+    },
+    StdlibBuiltin {
+        name: "str_split",
+        signature: "(String text, String separator) -> Vector",
+        description:
+            "The text split at each separator, or on whitespace if the separator is empty.",
+        call: str_split,
+    },
+    StdlibBuiltin {
+        name: "str_find",
+        signature: "(String text, String needle) -> I64",
+        description: "The index of the first occurrence of the needle in the text, or -1.",
+        call: str_find,
+    },
+    StdlibBuiltin {
+        name: "str_length",
+        signature: "(String text) -> I64",
+        description: "The number of characters in the text.",
+        call: str_length,
+    },
+    StdlibBuiltin {
+        name: "str_slice",
+        signature: "(String text, I64 start, I64 end) -> String",
+        description: "The characters of the text from start up to end.",
+        call: str_slice,
+    },
+    StdlibBuiltin {
+        name: "str_trim",
+        signature: "(String text) -> String",
+        description: "The text without whitespace at either end.",
+        call: str_trim,
+    },
+    StdlibBuiltin {
+        name: "str_lower",
+        signature: "(String text) -> String",
+        description: "The text in lower case.",
+        call: str_lower,
+    },
+    StdlibBuiltin {
+        name: "vec_push",
+        signature: "(Vector vector, Value value) -> Vector",
+        description: "The vector with the value added to its end.",
+        call: vec_push,
+    },
+    StdlibBuiltin {
+        name: "vec_index",
+        signature: "(Vector vector, I64 index) -> Value",
+        description: "The value at an index of the vector, or BadType if it's out of range.",
+        call: vec_index,
+    },
+    StdlibBuiltin {
+        name: "vec_slice",
+        signature: "(Vector vector, I64 start, I64 end) -> Vector",
+        description: "The values of the vector from start up to end.",
+        call: vec_slice,
+    },
+    StdlibBuiltin {
+        name: "vec_length",
+        signature: "(Vector vector) -> I64",
+        description: "The number of values in the vector.",
+        call: vec_length,
+    },
+    StdlibBuiltin {
+        name: "parse_int",
+        signature: "(String text) -> I64",
+        description: "The text read as a decimal integer; or [BadType, reason].",
+        call: parse_int,
+    },
+    StdlibBuiltin {
+        name: "parse_float",
+        signature: "(String text) -> F64",
+        description: "The text read as a decimal number; or [BadType, reason].",
+        call: parse_float,
+    },
+    StdlibBuiltin {
+        name: "to_string",
+        signature: "(Value value) -> String",
+        description: "A number, string or IdKey as text; BadType for anything else.",
+        call: to_string,
+    },
+];
+
+// An I32 or I64 argument, as an index.
+fn index(value: &Value) -> Option<i64> {
+    match value {
+        Value::I64(i) => Some(*i),
+        Value::I32(i) => Some(*i as i64),
+        _ => None,
+    }
+}
+
+// `start..end` clamped to a sequence of `len`.
+fn clamp(start: i64, end: i64, len: usize) -> (usize, usize) {
+    let start = start.clamp(0, len as i64) as usize;
+    let end = end.clamp(0, len as i64) as usize;
+    (start, end.max(start))
+}
+
+pub(crate) fn parse_failed(reason: String) -> Value {
+    Value::Vector(vec![Value::Error(BadType), Value::String(reason.into())])
+}
+
+fn str_concat(args: &[Value]) -> Option<Value> {
+    let mut joined = String::new();
+    for arg in args {
+        match arg {
+            Value::String(s) => joined.push_str(s),
+            _ => return None,
+        }
+    }
+    Some(Value::String(joined.into()))
+}
+
+fn str_split(args: &[Value]) -> Option<Value> {
+    let parts: Vec<&str> = match args {
+        [Value::String(text), Value::String(sep)] if sep.is_empty() => {
+            text.split_whitespace().collect()
+        }
+        [Value::String(text), Value::String(sep)] => text.split(sep.as_str()).collect(),
+        _ => return None,
+    };
+    Some(Value::Vector(
+        parts
+            .into_iter()
+            .map(|part| Value::String(part.into()))
+            .collect(),
+    ))
+}
+
+fn str_find(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::String(text), Value::String(needle)] => Some(Value::I64(
+            text.find(needle.as_str())
+                .map_or(-1, |at| text[..at].chars().count() as i64),
+        )),
+        _ => None,
+    }
+}
+
+fn str_length(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::String(text)] => Some(Value::I64(text.chars().count() as i64)),
+        _ => None,
+    }
+}
+
+fn str_slice(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::String(text), start, end] => {
+            let (start, end) = clamp(index(start)?, index(end)?, text.chars().count());
+            let slice: String = text.chars().skip(start).take(end - start).collect();
+            Some(Value::String(slice.into()))
+        }
+        _ => None,
+    }
+}
+
+fn str_trim(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::String(text)] => Some(Value::String(text.trim().into())),
+        _ => None,
+    }
+}
+
+fn str_lower(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::String(text)] => Some(Value::String(text.to_lowercase().into())),
+        _ => None,
+    }
+}
+
+fn vec_push(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::Vector(values), value] => {
+            let mut values = values.clone();
+            values.push(value.clone());
+            Some(Value::Vector(values))
+        }
+        _ => None,
+    }
+}
+
+fn vec_index(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::Vector(values), i] => {
+            let value = usize::try_from(index(i)?).ok().and_then(|i| values.get(i));
+            Some(value.cloned().unwrap_or(Value::Error(BadType)))
+        }
+        _ => None,
+    }
+}
+
+fn vec_slice(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::Vector(values), start, end] => {
+            let (start, end) = clamp(index(start)?, index(end)?, values.len());
+            Some(Value::Vector(values[start..end].to_vec()))
+        }
+        _ => None,
+    }
+}
+
+fn vec_length(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::Vector(values)] => Some(Value::I64(values.len() as i64)),
+        _ => None,
+    }
+}
+
+fn parse_int(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::String(text)] => Some(match text.trim().parse::<i64>() {
+            Ok(i) => Value::I64(i),
+            Err(e) => parse_failed(e.to_string()),
+        }),
+        _ => None,
+    }
+}
+
+fn parse_float(args: &[Value]) -> Option<Value> {
+    match args {
+        [Value::String(text)] => Some(match text.trim().parse::<f64>() {
+            Ok(f) => Value::F64(f),
+            Err(e) => parse_failed(e.to_string()),
+        }),
+        _ => None,
+    }
+}
+
+fn to_string(args: &[Value]) -> Option<Value> {
+    let text = match args {
+        [Value::String(s)] => return Some(Value::String(s.clone())),
+        [Value::I32(i)] => i.to_string(),
+        [Value::I64(i)] => i.to_string(),
+        [Value::F32(f)] => f.to_string(),
+        [Value::F64(f)] => f.to_string(),
+        [Value::IdKey(oid)] => oid.id.to_hyphenated().to_string(),
+        [_] => return Some(Value::Error(BadType)),
+        _ => return None,
+    };
+    Some(Value::String(text.into()))
+}
@@ -0,0 +1,110 @@
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use tokio_stream::StreamExt;
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// Sets of string tags on objects, for grouping them (e.g. every "quest" object) without scanning
+/// slots.
+///
+/// Each tag is recorded both by tag, to find the objects carrying it, and by object, to find its
+/// tags and remove them when it's destroyed. Tags are case sensitive.
+pub struct TagTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+fn tag_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("TAG".as_bytes()))
+}
+
+fn tagged_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("TAGGED".as_bytes()))
+}
+
+fn tag_tuple(tag: &str) -> Tuple {
+    let mut tup = Tuple::new();
+    tup.add_string(tag.to_string());
+    tup
+}
+
+fn oid_tuple(oid: Oid) -> Tuple {
+    let mut tup = Tuple::new();
+    tup.add_uuid(oid.id);
+    tup
+}
+
+fn tag_key(tag: &str, oid: Oid) -> Key {
+    tag_subspace()
+        .subspace(&tag_tuple(tag))
+        .subspace(&oid_tuple(oid))
+        .pack()
+        .into()
+}
+
+fn tagged_key(oid: Oid, tag: &str) -> Key {
+    tagged_subspace()
+        .subspace(&oid_tuple(oid))
+        .subspace(&tag_tuple(tag))
+        .pack()
+        .into()
+}
+
+impl<'tx_lifetime> TagTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        TagTxHandle { tr: tx }
+    }
+
+    pub fn add(&self, oid: Oid, tag: &str) {
+        self.tr.set(tag_key(tag, oid), Bytes::new());
+        self.tr.set(tagged_key(oid, tag), Bytes::new());
+    }
+
+    pub fn remove(&self, oid: Oid, tag: &str) {
+        self.tr.clear(tag_key(tag, oid));
+        self.tr.clear(tagged_key(oid, tag));
+    }
+
+    /// The objects tagged with `tag`, in Oid order.
+    pub async fn query(&self, tag: &str) -> Result<Vec<Oid>, DbError> {
+        let tagged = tag_subspace().subspace(&tag_tuple(tag));
+        let mut stream = self.tr.get_range(tagged.range(&Tuple::new()));
+        let mut oids = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, _) = kv?;
+            let key_bytes: Bytes = key.into();
+            let tuple = tagged.unpack(&key_bytes).unwrap();
+            oids.push(Oid {
+                id: *tuple.get_uuid_ref(0).unwrap(),
+            });
+        }
+        Ok(oids)
+    }
+
+    /// The tags on `oid`, in order.
+    pub async fn tags_of(&self, oid: Oid) -> Result<Vec<String>, DbError> {
+        let tags = tagged_subspace().subspace(&oid_tuple(oid));
+        let mut stream = self.tr.get_range(tags.range(&Tuple::new()));
+        let mut result = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, _) = kv?;
+            let key_bytes: Bytes = key.into();
+            let tuple = tags.unpack(&key_bytes).unwrap();
+            result.push(tuple.get_string_ref(0).unwrap().clone());
+        }
+        Ok(result)
+    }
+
+    /// Remove every tag from `oid`.
+    pub async fn clear_object(&self, oid: Oid) -> Result<(), DbError> {
+        for tag in self.tags_of(oid).await? {
+            self.tr.clear(tag_key(&tag, oid));
+        }
+        self.tr.clear_range(
+            tagged_subspace()
+                .subspace(&oid_tuple(oid))
+                .range(&Tuple::new()),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::task::JoinHandle;
+
+use value::{Oid, Value};
+
+/// How many messages from one connection may be handled at once, by default.
+pub const DEFAULT_TASKS_PER_CONNECTION: usize = 4;
+
+/// The handling of a message or request from a connection, running as a task of its own.
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    pub id: Oid,
+    pub connection: Oid,
+    pub player: Option<Oid>,
+    /// The verb it was dispatched to.
+    pub verb: String,
+    pub started: SystemTime,
+}
+
+impl TaskInfo {
+    /// A Vector of [name, value] pairs: its "id", "connection", "player" (if it's logged in),
+    /// "verb", when it "started", and how long it's been "running_ms".
+    pub fn value(&self) -> Value {
+        let field = |name: &str, value| Value::Vector(vec![Value::String(name.into()), value]);
+        let mut info = vec![
+            field("id", Value::IdKey(self.id)),
+            field("connection", Value::IdKey(self.connection)),
+        ];
+        if let Some(player) = self.player {
+            info.push(field("player", Value::IdKey(player)));
+        }
+        info.push(field("verb", Value::String(self.verb.as_str().into())));
+        let since_epoch = self.started.duration_since(UNIX_EPOCH).unwrap_or_default();
+        info.push(field(
+            "started",
+            Value::Timestamp(since_epoch.as_nanos() as i64),
+        ));
+        let running = self.started.elapsed().unwrap_or_default();
+        info.push(field("running_ms", Value::I64(running.as_millis() as i64)));
+        Value::Vector(info)
+    }
+}
+
+/// The tasks running now, by id, with the handles to abort them with.
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<HashMap<Oid, (TaskInfo, JoinHandle<()>)>>,
+}
+
+impl TaskRegistry {
+    /// Remember a task, spawned by `spawn` (which is given the task's info and must return its
+    /// handle). The registry is held while it's spawned, so that the task can't finish and
+    /// `forget` itself before it's been remembered.
+    pub fn spawn(&self, info: TaskInfo, spawn: impl FnOnce(&TaskInfo) -> JoinHandle<()>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let handle = spawn(&info);
+        tasks.insert(info.id, (info, handle));
+    }
+
+    pub fn forget(&self, id: Oid) {
+        self.tasks.lock().unwrap().remove(&id);
+    }
+
+    /// The task with an id, if it's still running.
+    pub fn get(&self, id: Oid) -> Option<TaskInfo> {
+        let tasks = self.tasks.lock().unwrap();
+        tasks.get(&id).map(|(info, _)| info.clone())
+    }
+
+    /// Abort a task. The verb it's running stops at its next interruption, and its transaction is
+    /// abandoned. False if there's no such task, as it's finished already.
+    pub fn kill(&self, id: Oid) -> bool {
+        match self.tasks.lock().unwrap().remove(&id) {
+            Some((_, handle)) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Abort every task handling messages from `connection`.
+    pub fn kill_connection(&self, connection: Oid) {
+        self.tasks.lock().unwrap().retain(|_, (info, handle)| {
+            if info.connection == connection {
+                handle.abort();
+            }
+            info.connection != connection
+        });
+    }
+
+    /// Every task running, longest running first.
+    pub fn list(&self) -> Vec<TaskInfo> {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(info, _)| info.clone())
+            .collect();
+        tasks.sort_by_key(|info| info.started);
+        tasks
+    }
+}
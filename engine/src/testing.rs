@@ -0,0 +1,189 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::HeaderValue;
+use tungstenite::Message;
+use uuid::Uuid;
+
+use crate::database::Storage;
+use crate::dump::Dump;
+use crate::listeners::{ListenerConfig, Transport};
+use crate::net::{self, origin::OriginPolicy, proxy::ProxyOptions, systemd::WEBSOCKET_LISTENER};
+use crate::object::SlotDef;
+use crate::protocol::{Request, Response, RPC_SUBPROTOCOL};
+use crate::world::{self, bootstrap_world, load_dumps, World, WorldOptions};
+use value::{Oid, Value};
+
+/// How long a client waits for what it expects before giving up on it.
+pub const EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A world for integration tests: the engine, with the in-memory backend and the bootstrap
+/// objects, serving websocket connections on an ephemeral port of the loopback interface through
+/// the server's built in websocket listener, from any origin. It's gone when the test's runtime
+/// is.
+pub struct TestWorld {
+    world: Arc<World>,
+    address: SocketAddr,
+}
+
+impl TestWorld {
+    /// Start a world with the default options.
+    pub async fn spawn() -> Result<TestWorld, Error> {
+        TestWorld::spawn_with(WorldOptions::default()).await
+    }
+
+    /// Start a world with `options`, but kept in memory whatever storage they ask for.
+    pub async fn spawn_with(options: WorldOptions) -> Result<TestWorld, Error> {
+        let world = Arc::new(World::new(WorldOptions {
+            storage: Storage::Temporary,
+            ..options
+        }));
+        bootstrap_world(world.clone(), Oid { id: Uuid::nil() }).await?;
+        tokio::spawn(world::notify_watchers(world.clone()));
+        tokio::spawn(world::dispatch_calendar(world.clone()));
+        tokio::spawn(world::deliver_events(world.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let config = ListenerConfig::built_in(
+            WEBSOCKET_LISTENER,
+            &address.to_string(),
+            Transport::Websocket,
+        );
+        let origin_policy = Arc::new(OriginPolicy::default());
+        let proxy = Arc::new(ProxyOptions::default());
+        net::serve(&world, listener, config, &origin_policy, &proxy);
+        Ok(TestWorld { world, address })
+    }
+
+    pub fn world(&self) -> &Arc<World> {
+        &self.world
+    }
+
+    /// Where it's listening.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Set the slot `name` on `oid`, under its own key, e.g. to give it a verb.
+    pub async fn set_slot(&self, oid: Oid, name: &str, value: Value) -> Result<(), Error> {
+        let dump = Dump {
+            slot_def: SlotDef {
+                location: oid,
+                key: oid,
+                name: name.to_string(),
+            },
+            value,
+            meta: None,
+        };
+        load_dumps(&self.world, &[dump]).await
+    }
+
+    /// Connect a client speaking the raw protocol, whose messages are given to 'receive'.
+    pub async fn connect(&self) -> Result<TestClient, Error> {
+        TestClient::connect(self.address, false).await
+    }
+
+    /// Connect a client speaking the structured protocol.
+    pub async fn connect_rpc(&self) -> Result<TestClient, Error> {
+        TestClient::connect(self.address, true).await
+    }
+}
+
+/// A client of a TestWorld, to script: send it things, and expect what comes back.
+pub struct TestClient {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: i64,
+}
+
+impl TestClient {
+    async fn connect(address: SocketAddr, rpc: bool) -> Result<TestClient, Error> {
+        let mut request = format!("ws://{}/", address).into_client_request()?;
+        if rpc {
+            request.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static(RPC_SUBPROTOCOL),
+            );
+        }
+        let (stream, _) = connect_async(request).await?;
+        Ok(TestClient { stream, next_id: 1 })
+    }
+
+    /// Send `text` as a text message.
+    pub async fn send(&mut self, text: &str) -> Result<(), Error> {
+        Ok(self.stream.send(Message::Text(text.to_string())).await?)
+    }
+
+    /// The next text or binary message sent to it, within EXPECT_TIMEOUT.
+    pub async fn next_message(&mut self) -> Result<Message, Error> {
+        loop {
+            let message = tokio::time::timeout(EXPECT_TIMEOUT, self.stream.next())
+                .await
+                .map_err(|_| anyhow!("Nothing was sent within {:?}", EXPECT_TIMEOUT))?
+                .ok_or_else(|| anyhow!("The connection was closed"))??;
+            if message.is_text() || message.is_binary() {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Expect the next message sent to it to be `expected`.
+    pub async fn expect(&mut self, expected: &str) -> Result<(), Error> {
+        let message = self.next_message().await?;
+        match message.to_text() {
+            Ok(text) if text == expected => Ok(()),
+            _ => Err(anyhow!(
+                "Expected {:?}, but was sent {:?}",
+                expected,
+                message
+            )),
+        }
+    }
+
+    /// Send `text`, and expect `reply` back.
+    pub async fn send_expect(&mut self, text: &str, reply: &str) -> Result<(), Error> {
+        self.send(text).await?;
+        self.expect(reply).await
+    }
+
+    /// Invoke `verb` on `target` with `args`, over the structured protocol, and wait for the
+    /// response. Anything else sent meanwhile, e.g. the token to resume with, is skipped.
+    pub async fn invoke(
+        &mut self,
+        target: Oid,
+        verb: &str,
+        args: Vec<Value>,
+    ) -> Result<Response, Error> {
+        let request = Request {
+            request_id: self.next_id,
+            target,
+            verb: verb.to_string(),
+            args,
+            dry_run: false,
+            deterministic: false,
+        };
+        self.next_id += 1;
+        self.stream.send(Message::Binary(request.encode())).await?;
+        loop {
+            let message = self.next_message().await?;
+            if !message.is_binary() {
+                continue;
+            }
+            match Response::decode(&message.into_data()) {
+                Ok(response) if response.request_id == request.request_id => return Ok(response),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Close the connection.
+    pub async fn close(mut self) -> Result<(), Error> {
+        Ok(self.stream.close(None).await?)
+    }
+}
@@ -0,0 +1,214 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+// RFC 6238 parameters, as expected by common authenticator apps.
+const STEP_SECS: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LEN: usize = 20;
+// Steps either side of the current one to accept, to allow for clock drift.
+const SKEW_STEPS: i64 = 1;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// An account's second factor: its shared secret, whether it's been confirmed and is required at
+/// login, and the last time step a code was accepted for, so that codes can't be replayed.
+#[derive(Clone, Debug)]
+pub struct TotpRecord {
+    pub secret: Vec<u8>,
+    pub enabled: bool,
+    pub last_step: i64,
+}
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0; SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Unpadded RFC 4648 base32, which is how secrets are presented to authenticator apps.
+pub fn base32(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The otpauth:// URL for provisioning `secret` into an authenticator app, typically by rendering
+/// it as a QR code.
+pub fn otpauth_url(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        base32(secret),
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+// RFC 4226 HOTP value for `counter`.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    truncated % 10u32.pow(DIGITS)
+}
+
+fn step_at(time: SystemTime) -> i64 {
+    (time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / STEP_SECS) as i64
+}
+
+/// The time step `code` is valid for at `now`, if it's valid and the step is later than
+/// `last_step`.
+pub fn verify(secret: &[u8], code: &str, now: SystemTime, last_step: i64) -> Option<i64> {
+    let code: u32 = code.trim().parse().ok()?;
+    let current = step_at(now);
+    (current - SKEW_STEPS..=current + SKEW_STEPS)
+        .filter(|step| *step > last_step && *step >= 0)
+        .find(|step| hotp(secret, *step as u64) == code)
+}
+
+/// Fresh single-use recovery codes, formatted for reading out as xxxxx-xxxxx.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0; 7];
+            rng.fill_bytes(&mut bytes);
+            let code = base32(&bytes).to_lowercase();
+            format!("{}-{}", &code[0..5], &code[5..10])
+        })
+        .collect()
+}
+
+// Recovery codes are random, so an unsalted hash is enough to keep them from being read back.
+fn recovery_hash(code: &str) -> Bytes {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    Bytes::from(Sha256::digest(normalized.as_bytes()).to_vec())
+}
+
+fn totp_key(account: Oid) -> Key {
+    let totp_subspace = Subspace::new(Bytes::from_static("TOTP".as_bytes()));
+    let mut tup = Tuple::new();
+    tup.add_uuid(account.id);
+    totp_subspace.subspace(&tup).pack().into()
+}
+
+fn recovery_subspace(account: Oid) -> Subspace {
+    let mut tup = Tuple::new();
+    tup.add_uuid(account.id);
+    Subspace::new(Bytes::from_static("RECOVERY".as_bytes())).subspace(&tup)
+}
+
+fn recovery_key(account: Oid, code: &str) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_bytes(recovery_hash(code));
+    recovery_subspace(account).subspace(&tup).pack().into()
+}
+
+// Reads and updates accounts' second factors via one transaction.
+pub struct TotpTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> TotpTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        TotpTxHandle { tr: tx }
+    }
+
+    pub async fn get(&self, account: Oid) -> Result<Option<TotpRecord>, DbError> {
+        Ok(self.tr.get(totp_key(account)).await?.map(|v| {
+            let tuple = Tuple::from_bytes(v).unwrap();
+            TotpRecord {
+                secret: tuple.get_bytes_ref(0).unwrap().to_vec(),
+                enabled: tuple.get_bool(1).unwrap(),
+                last_step: tuple.get_i64(2).unwrap(),
+            }
+        }))
+    }
+
+    pub fn put(&self, account: Oid, record: &TotpRecord) {
+        let mut tup = Tuple::new();
+        tup.add_bytes(Bytes::from(record.secret.clone()));
+        tup.add_bool(record.enabled);
+        tup.add_i64(record.last_step);
+        self.tr.set(totp_key(account), tup.pack());
+    }
+
+    /// Remove the account's second factor and its recovery codes.
+    pub fn remove(&self, account: Oid) {
+        self.tr.clear(totp_key(account));
+        self.tr
+            .clear_range(recovery_subspace(account).range(&Tuple::new()));
+    }
+
+    /// Replace the account's recovery codes with `codes`.
+    pub fn set_recovery_codes(&self, account: Oid, codes: &[String]) {
+        self.tr
+            .clear_range(recovery_subspace(account).range(&Tuple::new()));
+        for code in codes {
+            self.tr.set(recovery_key(account, code), Bytes::new());
+        }
+    }
+
+    /// Use up `code`, if it's one of the account's unused recovery codes.
+    pub async fn use_recovery_code(&self, account: Oid, code: &str) -> Result<bool, DbError> {
+        let key = recovery_key(account, code);
+        match self.tr.get(key.clone()).await? {
+            Some(_) => {
+                self.tr.clear(key);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
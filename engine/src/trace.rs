@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::info;
+use value::Oid;
+
+/// What a single verb invocation cost.
+#[derive(Clone, Debug)]
+pub struct Invocation {
+    /// The object the verb was found on.
+    pub oid: Oid,
+    pub verb: String,
+    /// Digest of the program which ran, identifying it across renames and copies.
+    pub module: [u8; 64],
+    pub wall_time: Duration,
+    pub fuel: u64,
+    /// Builtins called, directly, by the verb.
+    pub host_calls: u64,
+    /// Whether it trapped or ran out of resources.
+    pub failed: bool,
+}
+
+/// The first bytes of a program's digest, in hex, which are plenty to tell programs apart in a
+/// trace or log.
+pub fn digest_prefix(digest: &[u8; 64]) -> String {
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Invocation {
+    pub fn module_prefix(&self) -> String {
+        digest_prefix(&self.module)
+    }
+}
+
+/// Totals over every invocation of a verb since startup.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VerbStats {
+    pub invocations: u64,
+    pub failures: u64,
+    pub wall_time: Duration,
+    pub fuel: u64,
+    pub host_calls: u64,
+}
+
+impl VerbStats {
+    fn add(&mut self, invocation: &Invocation) {
+        self.invocations += 1;
+        self.failures += invocation.failed as u64;
+        self.wall_time += invocation.wall_time;
+        self.fuel += invocation.fuel;
+        self.host_calls += invocation.host_calls;
+    }
+}
+
+/// Keeps totals for every verb invoked, by the object it's on and its name, and if asked to,
+/// logs each invocation as it finishes.
+#[derive(Default)]
+pub struct VerbTracer {
+    stats: Mutex<HashMap<(Oid, String), VerbStats>>,
+    log_invocations: bool,
+}
+
+impl VerbTracer {
+    pub fn new(log_invocations: bool) -> Self {
+        VerbTracer {
+            stats: Default::default(),
+            log_invocations,
+        }
+    }
+
+    pub fn record(&self, invocation: &Invocation) {
+        if self.log_invocations {
+            info!(target: "trace",
+                "oid={} verb={} module={} wall_us={} fuel={} host_calls={} failed={}",
+                invocation.oid.id,
+                invocation.verb,
+                invocation.module_prefix(),
+                invocation.wall_time.as_micros(),
+                invocation.fuel,
+                invocation.host_calls,
+                invocation.failed
+            );
+        }
+        let mut stats = self.stats.lock().unwrap();
+        stats
+            .entry((invocation.oid, invocation.verb.clone()))
+            .or_default()
+            .add(invocation);
+    }
+
+    /// Each verb's totals, those which have taken the most wall time first.
+    pub fn stats(&self) -> Vec<(Oid, String, VerbStats)> {
+        let stats = self.stats.lock().unwrap();
+        let mut stats: Vec<_> = stats
+            .iter()
+            .map(|((oid, verb), stats)| (*oid, verb.clone(), *stats))
+            .collect();
+        stats.sort_by_key(|(_, _, stats)| std::cmp::Reverse(stats.wall_time));
+        stats
+    }
+}
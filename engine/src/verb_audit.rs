@@ -0,0 +1,260 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use fdb::{range::Range, subspace::Subspace, tuple::Tuple, Key};
+use int_enum::IntEnum;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::database::{DbError, Tx};
+use crate::verb_error::{TrapKind, VerbError};
+use value::{encode_frame, Oid, Value};
+
+/// Options for keeping an audit trail of verb invocations.
+#[derive(Clone, Debug)]
+pub struct VerbAuditOptions {
+    /// Records older than this are deleted.
+    pub retention: Duration,
+}
+
+/// How a verb invocation ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Outcome {
+    /// It returned a value other than an Error.
+    Returned,
+    /// It returned an Error, or was stopped for exceeding its limits (ResourceLimit).
+    Error(value::Error),
+    /// It trapped, or couldn't be run.
+    Failed,
+}
+
+impl Outcome {
+    pub fn of(result: &Result<Value, anyhow::Error>) -> Self {
+        match result {
+            Ok(Value::Error(e)) => Outcome::Error(*e),
+            Ok(_) => Outcome::Returned,
+            Err(_) => Outcome::Failed,
+        }
+    }
+
+    /// Whether the transaction of the dispatch is abandoned, rather than committed.
+    pub fn abandons(&self) -> bool {
+        matches!(
+            self,
+            Outcome::Failed | Outcome::Error(value::Error::ResourceLimit)
+        )
+    }
+}
+
+/// A verb invocation, as the audit trail records it.
+#[derive(Clone, Debug, Serialize)]
+pub struct InvocationRecord {
+    pub timestamp: SystemTime,
+    /// The object whose verb invoked it, or the player whose message or request it handled. None
+    /// for those the server made itself, such as calendar events and hooks.
+    pub caller: Option<Oid>,
+    /// The object the verb was found on.
+    pub target: Oid,
+    pub verb: String,
+    /// SHA-256 digest of its arguments, as encoded in a frame, in hex: enough to tell whether two
+    /// invocations had the same arguments without keeping them.
+    pub args_digest: String,
+    pub outcome: Outcome,
+    pub fuel: u64,
+    /// How it failed, if it did.
+    pub error: Option<VerbError>,
+}
+
+impl InvocationRecord {
+    pub fn new(
+        caller: Option<Oid>,
+        verb: (Oid, &str),
+        args: &Value,
+        result: &Result<Value, anyhow::Error>,
+        fuel: u64,
+    ) -> Self {
+        let digest = Sha256::digest(encode_frame(args));
+        InvocationRecord {
+            timestamp: SystemTime::now(),
+            caller,
+            target: verb.0,
+            verb: verb.1.to_string(),
+            args_digest: digest.iter().map(|b| format!("{:02x}", b)).collect(),
+            outcome: Outcome::of(result),
+            fuel,
+            error: result.as_ref().err().map(VerbError::of),
+        }
+    }
+
+    fn key(&self) -> Key {
+        let mut tup = Tuple::new();
+        tup.add_i64(micros(self.timestamp));
+        tup.add_uuid(self.target.id);
+        tup.add_i64(SEQUENCE.fetch_add(1, Ordering::Relaxed));
+        audit_subspace().subspace(&tup).pack().into()
+    }
+
+    fn value(&self) -> fdb::Value {
+        let mut tup = Tuple::new();
+        tup.add_uuid(self.caller.map_or(Uuid::nil(), |caller| caller.id));
+        tup.add_string(self.verb.clone());
+        tup.add_string(self.args_digest.clone());
+        match self.outcome {
+            Outcome::Returned => tup.add_i8(0),
+            Outcome::Error(e) => {
+                tup.add_i8(1);
+                tup.add_i8(e.int_value());
+            }
+            Outcome::Failed => tup.add_i8(2),
+        }
+        tup.add_i64(self.fuel as i64);
+        // Then, for those which failed, how.
+        if let Some(error) = &self.error {
+            tup.add_i8(error.kind.int_value());
+            tup.add_string(error.message.clone());
+            match &error.backtrace {
+                Some(backtrace) => tup.add_string(backtrace.clone()),
+                None => tup.add_null(),
+            }
+        }
+        tup.pack().into()
+    }
+
+    fn from_kv(key: Key, value: fdb::Value) -> Self {
+        let key_bytes: Bytes = key.into();
+        let key_tuple = audit_subspace().unpack(&key_bytes).unwrap();
+        let value_tuple = Tuple::from_bytes(value).unwrap();
+        let caller = *value_tuple.get_uuid_ref(0).unwrap();
+        // Errors take a field of their own, for their code, before the fuel.
+        let (outcome, fuel_index) = match value_tuple.get_i8(3).unwrap() {
+            0 => (Outcome::Returned, 4),
+            1 => (
+                Outcome::Error(
+                    value::Error::from_int(value_tuple.get_i8(4).unwrap())
+                        .unwrap_or(value::Error::InternalError),
+                ),
+                5,
+            ),
+            _ => (Outcome::Failed, 4),
+        };
+        InvocationRecord {
+            timestamp: UNIX_EPOCH + Duration::from_micros(key_tuple.get_i64(0).unwrap() as u64),
+            caller: (!caller.is_nil()).then_some(Oid { id: caller }),
+            target: Oid {
+                id: *key_tuple.get_uuid_ref(1).unwrap(),
+            },
+            verb: value_tuple.get_string_ref(1).unwrap().clone(),
+            args_digest: value_tuple.get_string_ref(2).unwrap().clone(),
+            outcome,
+            fuel: value_tuple.get_i64(fuel_index).unwrap() as u64,
+            // Not recorded before failures were.
+            error: value_tuple
+                .get_i8(fuel_index + 1)
+                .ok()
+                .map(|kind| VerbError {
+                    kind: TrapKind::from_int(kind).unwrap_or(TrapKind::Failed),
+                    message: value_tuple
+                        .get_string_ref(fuel_index + 2)
+                        .cloned()
+                        .unwrap_or_default(),
+                    backtrace: value_tuple.get_string_ref(fuel_index + 3).ok().cloned(),
+                }),
+        }
+    }
+}
+
+/// How many records of the audit trail are read at once.
+pub const AUDIT_PAGE: usize = 1000;
+
+// Records are keyed by (timestamp, target, sequence), so that they're read in order and retention
+// can clear everything older than a cutoff from the start.
+fn audit_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("AUDIT".as_bytes()))
+}
+
+// Disambiguates records written in the same microsecond.
+static SEQUENCE: AtomicI64 = AtomicI64::new(0);
+
+fn micros(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64
+}
+
+// Records from `time` on, rounded up to the microsecond, as records' times are truncated to one.
+fn time_key(time: SystemTime) -> Key {
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut tup = Tuple::new();
+    tup.add_i64(((nanos + 999) / 1000) as i64);
+    audit_subspace().subspace(&tup).pack().into()
+}
+
+// Reads and writes the verb audit trail via one transaction.
+pub struct VerbAuditTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+impl<'tx_lifetime> VerbAuditTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        VerbAuditTxHandle { tr: tx }
+    }
+
+    pub fn append(&self, record: &InvocationRecord) {
+        self.tr.set(record.key(), record.value());
+    }
+
+    /// Delete up to `limit` of the records from before `cutoff`, oldest first, returning how many
+    /// were deleted.
+    pub async fn prune(&self, cutoff: SystemTime, limit: usize) -> Result<usize, DbError> {
+        let range = Range::new(audit_subspace().pack(), time_key(cutoff));
+        let mut stream = self.tr.snapshot_get_range(range);
+        let mut pruned = 0;
+        while pruned < limit {
+            let (key, _) = match stream.next().await {
+                Some(kv) => kv?,
+                None => break,
+            };
+            self.tr.clear(key);
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+
+    /// The records in the time range [from, to), oldest first: all of them, or, given `object`,
+    /// those of verbs on it or invoked by it. Only a page of AUDIT_PAGE records (and any more from
+    /// the same microsecond as the last) is read at once, so this also gives where to read on
+    /// from, if it stopped short of `to`.
+    pub async fn records(
+        &self,
+        object: Option<Oid>,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<(Vec<InvocationRecord>, Option<SystemTime>), DbError> {
+        let range = Range::new(time_key(from), time_key(to));
+        let mut stream = self.tr.get_range(range);
+        let mut records = vec![];
+        let (mut read, mut last) = (0, None);
+        while let Some(kv) = stream.next().await {
+            let (key, value) = kv?;
+            let record = InvocationRecord::from_kv(key, value);
+            if read >= AUDIT_PAGE && last != Some(record.timestamp) {
+                return Ok((records, Some(record.timestamp)));
+            }
+            read += 1;
+            last = Some(record.timestamp);
+            let involved = object.map_or(true, |object| {
+                record.target == object || record.caller == Some(object)
+            });
+            if involved {
+                records.push(record);
+            }
+        }
+        Ok((records, None))
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha512};
+
+use crate::module_cache::digest;
+use value::{encode_frame, Oid, Program, Value};
+
+/// How many verb results are kept, at most.
+pub const DEFAULT_CAPACITY: u64 = 10_000;
+
+/// The slot on an object naming which of its verbs' results may be cached, and for how long: a
+/// Vector of [String verb, I64 milliseconds] pairs. Only verbs whose results depend on nothing but
+/// their arguments should be listed, as a cached result is returned without running the verb.
+pub const CACHE_TTL_SLOT: &str = "cache_ttl";
+
+/// The results of verbs marked cacheable, keyed by a digest of the verb, its program and the
+/// arguments it was called with. A verb whose program changes gets a new digest, so its old results
+/// are never returned, and age out.
+pub struct VerbResultCache {
+    results: moka::sync::Cache<[u8; 64], (Value, Instant)>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for VerbResultCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// The key the result of `program`, invoked as `verb` with `args`, is cached under. The same
+/// program on another object, or under another name, may well give another result.
+pub fn key(verb: (Oid, &str), program: &Program, args: &Value) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    hasher.update(verb.0.id.as_bytes());
+    // Length prefixed, so that the name can't run on into the digest.
+    hasher.update((verb.1.len() as u64).to_le_bytes());
+    hasher.update(verb.1.as_bytes());
+    hasher.update(digest(program));
+    // The wire encoding is canonical: equal arguments always encode the same.
+    hasher.update(encode_frame(args));
+    hasher.finalize().into()
+}
+
+impl VerbResultCache {
+    pub fn new(capacity: u64) -> Self {
+        VerbResultCache {
+            results: moka::sync::Cache::new(capacity),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The result cached under `key`, unless it's expired.
+    pub fn get(&self, key: &[u8; 64]) -> Option<Value> {
+        let result = match self.results.get(key) {
+            Some((value, expires)) if expires > Instant::now() => Some(value),
+            Some(_) => {
+                self.results.invalidate(key);
+                None
+            }
+            None => None,
+        };
+        match result {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        result
+    }
+
+    pub fn insert(&self, key: [u8; 64], value: Value, ttl: Duration) {
+        // Copied out of whatever frame it shares, so that the frame isn't cached along with it.
+        self.results
+            .insert(key, (value.detached(), Instant::now() + ttl));
+    }
+
+    /// The number of lookups which found a result, and which had to run the verb.
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+
+use int_enum::IntEnum;
+use serde::Serialize;
+use wasmtime::{Trap, TrapCode};
+
+use value::{Error, Value};
+
+/// Lines of a backtrace kept, from the innermost frame out; deep recursion would otherwise make
+/// the error as large as the stack that overflowed.
+pub const MAX_BACKTRACE_FRAMES: usize = 32;
+
+// What a trap's or a Lua error's description is followed by, when it comes with a backtrace.
+const BACKTRACE_MARKERS: &[&str] = &["\nwasm backtrace:", "\nstack traceback:"];
+
+/// Why a verb failed.
+#[repr(i8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, IntEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum TrapKind {
+    /// It executed `unreachable`, as panicking guests do.
+    Unreachable = 0,
+    MemoryOutOfBounds = 1,
+    StackOverflow = 2,
+    DivisionByZero = 3,
+    IntegerOverflow = 4,
+    BadConversion = 5,
+    TableOutOfBounds = 6,
+    IndirectCallToNull = 7,
+    BadSignature = 8,
+    HeapMisaligned = 9,
+    Interrupted = 10,
+    /// A builtin refused it, e.g. for passing arguments it couldn't make sense of.
+    Host = 11,
+    /// It didn't trap, but couldn't be compiled, or instantiated, or raised a Lua error.
+    Failed = 12,
+}
+
+impl TrapKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrapKind::Unreachable => "unreachable",
+            TrapKind::MemoryOutOfBounds => "memory_out_of_bounds",
+            TrapKind::StackOverflow => "stack_overflow",
+            TrapKind::DivisionByZero => "division_by_zero",
+            TrapKind::IntegerOverflow => "integer_overflow",
+            TrapKind::BadConversion => "bad_conversion",
+            TrapKind::TableOutOfBounds => "table_out_of_bounds",
+            TrapKind::IndirectCallToNull => "indirect_call_to_null",
+            TrapKind::BadSignature => "bad_signature",
+            TrapKind::HeapMisaligned => "heap_misaligned",
+            TrapKind::Interrupted => "interrupted",
+            TrapKind::Host => "host",
+            TrapKind::Failed => "failed",
+        }
+    }
+
+    fn of(trap: &Trap) -> Self {
+        match trap.trap_code() {
+            Some(TrapCode::UnreachableCodeReached) => TrapKind::Unreachable,
+            Some(TrapCode::MemoryOutOfBounds) => TrapKind::MemoryOutOfBounds,
+            Some(TrapCode::StackOverflow) => TrapKind::StackOverflow,
+            Some(TrapCode::IntegerDivisionByZero) => TrapKind::DivisionByZero,
+            Some(TrapCode::IntegerOverflow) => TrapKind::IntegerOverflow,
+            Some(TrapCode::BadConversionToInteger) => TrapKind::BadConversion,
+            Some(TrapCode::TableOutOfBounds) => TrapKind::TableOutOfBounds,
+            Some(TrapCode::IndirectCallToNull) => TrapKind::IndirectCallToNull,
+            Some(TrapCode::BadSignature) => TrapKind::BadSignature,
+            Some(TrapCode::HeapMisaligned) => TrapKind::HeapMisaligned,
+            Some(TrapCode::Interrupt) => TrapKind::Interrupted,
+            // Raised by the host, rather than by a WebAssembly instruction.
+            _ => TrapKind::Host,
+        }
+    }
+}
+
+/// How a verb failed, as the verb which invoked it is told, and as the audit trail records it.
+///
+/// The verb's transaction is abandoned all the same; but rather than the invoking verb getting a
+/// bare InternalError, it gets this as an error map (see `to_value`), and can carry on.
+#[derive(Clone, Debug, Serialize)]
+pub struct VerbError {
+    pub kind: TrapKind,
+    pub message: String,
+    /// The frames it trapped in, innermost first, if the VM gave any; up to MAX_BACKTRACE_FRAMES
+    /// of them.
+    pub backtrace: Option<String>,
+}
+
+impl VerbError {
+    /// What became of a verb whose execution returned `error`.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(verb_error) = error.downcast_ref::<VerbError>() {
+            return verb_error.clone();
+        }
+        let kind = match error.downcast_ref::<Trap>() {
+            Some(trap) => TrapKind::of(trap),
+            None => TrapKind::Failed,
+        };
+        let text = error.to_string();
+        let (message, backtrace) = BACKTRACE_MARKERS
+            .iter()
+            .find_map(|marker| text.split_once(marker))
+            .map_or((text.as_str(), None), |(message, backtrace)| {
+                (message, Some(backtrace))
+            });
+        let backtrace = backtrace
+            .map(|backtrace| {
+                backtrace
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .take(MAX_BACKTRACE_FRAMES)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .filter(|backtrace| !backtrace.is_empty());
+        VerbError {
+            kind,
+            message: message.trim().to_string(),
+            backtrace,
+        }
+    }
+
+    /// As the invoking verb gets it: a Vector of [String name, value] pairs, the first always
+    /// ["error", Error Trapped], then "kind" and "message" as Strings, then "backtrace" if there
+    /// is one.
+    pub fn to_value(&self) -> Value {
+        let field = |name: &str, value| Value::Vector(vec![Value::String(name.into()), value]);
+        let mut map = vec![
+            field("error", Value::Error(Error::Trapped)),
+            field("kind", Value::String(self.kind.name().into())),
+            field("message", Value::String(self.message.as_str().into())),
+        ];
+        if let Some(backtrace) = &self.backtrace {
+            map.push(field("backtrace", Value::String(backtrace.as_str().into())));
+        }
+        Value::Vector(map)
+    }
+}
+
+impl fmt::Display for VerbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.kind.name())
+    }
+}
+
+impl std::error::Error for VerbError {}
+
+/// Whether `value` is the error map of a verb which failed.
+pub fn is_error_map(value: &Value) -> bool {
+    match value {
+        Value::Vector(fields) => matches!(
+            fields.first(),
+            Some(Value::Vector(field)) if matches!(
+                field.as_slice(),
+                [Value::String(name), Value::Error(Error::Trapped)] if name == "error"
+            )
+        ),
+        _ => false,
+    }
+}
+
+/// `result` as a client is sent it. An error map keeps only its "error" and "kind": its message
+/// and backtrace can give away the server's paths or database errors, so they're only logged and
+/// put on the audit trail.
+pub fn for_client(result: Value) -> Value {
+    if !is_error_map(&result) {
+        return result;
+    }
+    match result {
+        Value::Vector(fields) => Value::Vector(
+            fields
+                .into_iter()
+                .filter(|field| match field {
+                    Value::Vector(field) => matches!(
+                        field.first(),
+                        Some(Value::String(name)) if name == "error" || name == "kind"
+                    ),
+                    _ => false,
+                })
+                .collect(),
+        ),
+        other => other,
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use rand::RngCore;
+use wasmtime::{Caller, Linker};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+
+use crate::determinism::Determinism;
+use crate::fdb_object::ObjDBTxHandle;
+use value::{Oid, Value};
+
+/// The slot on an object granting its verbs stdout and stderr, which are the server's own.
+pub const STDOUT_SLOT: &str = "wasi.stdout";
+/// The slot on an object granting its verbs the realtime and monotonic clocks.
+pub const CLOCK_SLOT: &str = "wasi.clock";
+/// The slot on an object granting its verbs random bytes.
+pub const RANDOM_SLOT: &str = "wasi.random";
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+// WASI errno values.
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_FAULT: i32 = 21;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_NOTCAPABLE: i32 = 76;
+
+// WASI clock ids. The CPU time clocks aren't offered at all.
+const CLOCK_REALTIME: i32 = 0;
+const CLOCK_MONOTONIC: i32 = 1;
+
+/// Which WASI capabilities an object's verbs are given. None are, unless the object grants them
+/// with a positive integer in the capability's slot. Verbs never see the server's arguments or
+/// environment, nor any of its files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WasiPolicy {
+    pub stdout: bool,
+    pub clock: bool,
+    pub random: bool,
+}
+
+async fn granted(odb: &ObjDBTxHandle<'_>, oid: Oid, name: &str) -> bool {
+    matches!(
+        odb.get_slot(oid, oid, String::from(name)).await,
+        Ok(Value::I32(1..)) | Ok(Value::I64(1..))
+    )
+}
+
+impl WasiPolicy {
+    /// The capabilities `oid` grants its verbs.
+    pub async fn of(odb: &ObjDBTxHandle<'_>, oid: Oid) -> Self {
+        WasiPolicy {
+            stdout: granted(odb, oid, STDOUT_SLOT).await,
+            clock: granted(odb, oid, CLOCK_SLOT).await,
+            random: granted(odb, oid, RANDOM_SLOT).await,
+        }
+    }
+
+    /// The WASI context for verbs under this policy.
+    pub fn context(&self) -> WasiCtx {
+        let mut builder = WasiCtxBuilder::new();
+        if self.stdout {
+            builder = builder.inherit_stdout().inherit_stderr();
+        }
+        builder.build()
+    }
+}
+
+fn write_guest<T>(caller: &mut Caller<'_, T>, ptr: i32, bytes: &[u8]) -> i32 {
+    let memory = match caller.get_export("memory").and_then(|e| e.into_memory()) {
+        Some(memory) => memory,
+        None => return ERRNO_FAULT,
+    };
+    match memory.write(caller, ptr as u32 as usize, bytes) {
+        Ok(()) => ERRNO_SUCCESS,
+        Err(_) => ERRNO_FAULT,
+    }
+}
+
+fn clock_now(id: i32) -> Option<u64> {
+    // The monotonic clock counts from when it's first read.
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    match id {
+        CLOCK_REALTIME => Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+        ),
+        CLOCK_MONOTONIC => Some(EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64),
+        _ => None,
+    }
+}
+
+/// Replace the WASI clock and random functions in `linker` with ones which answer only guests
+/// whose policy (as `state` finds it in their store's data) grants them, and refuse the rest
+/// with ENOTCAPABLE. Guests executing deterministically, for which `state` also finds their
+/// Determinism, are answered from it. Must be called after WASI itself has been added.
+pub fn add_to_linker<T: 'static>(
+    linker: &mut Linker<T>,
+    state: impl Fn(&mut T) -> (WasiPolicy, Option<&mut Determinism>) + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    linker.allow_shadowing(true);
+    linker.func_wrap(
+        WASI_MODULE,
+        "clock_time_get",
+        move |mut caller: Caller<'_, T>, id: i32, _precision: i64, time_ptr: i32| -> i32 {
+            let now = match state(caller.data_mut()) {
+                (policy, _) if !policy.clock => return ERRNO_NOTCAPABLE,
+                (_, Some(determinism)) if id == CLOCK_REALTIME || id == CLOCK_MONOTONIC => {
+                    Some(determinism.now() as u64)
+                }
+                _ => clock_now(id),
+            };
+            match now {
+                Some(now) => write_guest(&mut caller, time_ptr, &now.to_le_bytes()),
+                None => ERRNO_INVAL,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "clock_res_get",
+        move |mut caller: Caller<'_, T>, id: i32, res_ptr: i32| -> i32 {
+            if !state(caller.data_mut()).0.clock {
+                return ERRNO_NOTCAPABLE;
+            }
+            match clock_now(id) {
+                Some(_) => write_guest(&mut caller, res_ptr, &1u64.to_le_bytes()),
+                None => ERRNO_INVAL,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        WASI_MODULE,
+        "random_get",
+        move |mut caller: Caller<'_, T>, buf: i32, len: i32| -> i32 {
+            if !state(caller.data_mut()).0.random {
+                return ERRNO_NOTCAPABLE;
+            }
+            // Checked first, so that a guest can't have us allocate more than it could hold.
+            let len = len as u32 as usize;
+            let fits = caller
+                .get_export("memory")
+                .and_then(|e| e.into_memory())
+                .is_some_and(|memory| (buf as u32 as usize) + len <= memory.data_size(&caller));
+            if !fits {
+                return ERRNO_FAULT;
+            }
+            let mut bytes = vec![0; len];
+            match state(caller.data_mut()).1 {
+                Some(determinism) => determinism.fill(&mut bytes),
+                None => rand::thread_rng().fill_bytes(&mut bytes),
+            }
+            write_guest(&mut caller, buf, &bytes)
+        },
+    )?;
+    linker.allow_shadowing(false);
+    Ok(())
+}
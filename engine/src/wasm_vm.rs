@@ -1,28 +1,227 @@
-use sha2::Digest;
+use std::future::Future;
 use std::ops::DerefMut;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Error};
 use futures::executor::block_on;
 use futures::lock::Mutex;
-use log::{error, info};
+use tracing::{error, field, info, warn, Span};
 
 use tungstenite::Message;
-use wasmtime::{self, Extern, Module, Trap, Val};
+use uuid::Uuid;
+use wasmtime::{self, Extern, Trap, Val};
 
-use crate::world::{get_slot, send_connection_message, send_verb_dispatch, set_slot, World};
-use value::{append_value, Program, Value};
+use crate::builtins::Privilege;
+use crate::compile::check_program;
+use crate::contents::LOCATION_SLOT;
+use crate::database::Tx;
+use crate::dependencies::{DependencyTxHandle, SlotAccesses};
+use crate::determinism::{Determinism, NONDETERMINISTIC_BUILTINS};
+use crate::fdb_object::ObjDBTxHandle;
+use crate::js_vm::{self, JsContext};
+use crate::lua_vm::{self, LuaContext};
+use crate::module_cache::{self, Preemption};
+use crate::object::SlotDef;
+use crate::outbound::Delivery;
+use crate::scratch::Scratchpad;
+use crate::stdlib::STDLIB;
+use crate::trace::{digest_prefix, Invocation};
+use crate::verb_audit::InvocationRecord;
+use crate::wasi_policy::{self, WasiPolicy};
+use crate::world::{
+    audit_invocation, broadcast, broadcast_recipients, calendar_add, calendar_remove,
+    cancel_scheduled, cancel_send, connection_info, connection_player, contents_of, cooldown_check,
+    cooldown_set, counter_get, counter_incr, create_object, destroy_object, end_impersonation,
+    find_references, format_time, get_slot, impersonate, impersonation_audit, invoke_verb,
+    kill_task, list_slots, login_allowed, login_attempt, login_verify, move_object, move_slot,
+    name_available, next_id, parse_command, parse_cron, parse_duration, player_stats_value,
+    publish, quota_usage, read_blob, rename_object, reschedule, resume_token, scheduled_tasks,
+    search_slots, send_connection_message, send_connection_message_in, send_form, set_slot,
+    set_slot_meta, slot_meta, subscribe, tag_add, tag_query, tag_remove, tags_of, task_list,
+    totp_disable, totp_enable, totp_provision, totp_recovery_codes, unsubscribe, unwatch_slot,
+    upcoming_events, watch_slot, LoginOutcome, World,
+};
+use value::Error::{
+    BadType, InvalidProgram, NoError, PermissionDenied, ResourceLimit, SecondFactorRequired,
+    SlotDoesNotExist,
+};
+use value::{decode_frame, encode_frame, Oid, Program, ProgramLang, Value};
 
 pub struct WasmVM {
+    world: Arc<World>,
     wasm_linker: Arc<Mutex<wasmtime::Linker<VMState>>>,
     wasm_store: Arc<Mutex<wasmtime::Store<VMState>>>,
-    module_cache: moka::future::Cache<Vec<u8>, wasmtime::Module>,
+    // The objects whose verbs are being executed in the dispatch chain, innermost last, and the
+    // scratchpad the chain shares; shared with the VMs it runs the chain's nested invocations on.
+    chain: Arc<std::sync::Mutex<Vec<Oid>>>,
+    scratch: Arc<std::sync::Mutex<Scratchpad>>,
+    // The connection it runs verbs for, if any, and the object whose verb it was made to invoke
+    // another for, if it was.
+    connection: Option<Oid>,
+    invoked_by: std::sync::Mutex<Option<Oid>>,
+    // Whether the verbs it executes are to be executed deterministically.
+    deterministic: AtomicBool,
+}
+
+// Marks a verb on an object as running within its dispatch chain, emptying the chain's
+// scratchpad when the verb at its root returns (or fails).
+struct ChainGuard<'a>(&'a WasmVM);
+
+impl<'a> ChainGuard<'a> {
+    fn enter(vm: &'a WasmVM, oid: Oid) -> Self {
+        vm.chain.lock().unwrap().push(oid);
+        ChainGuard(vm)
+    }
+}
+
+impl Drop for ChainGuard<'_> {
+    fn drop(&mut self) {
+        let mut chain = self.0.chain.lock().unwrap();
+        chain.pop();
+        if chain.is_empty() {
+            self.0.scratch.lock().unwrap().clear();
+        }
+    }
 }
 
 struct VMState {
+    // Built for the capabilities the object whose verb is being executed grants.
     wasi: wasmtime_wasi::WasiCtx,
+    wasi_policy: WasiPolicy,
     world: Arc<World>,
+    // The transaction of the verb being executed, which slot and object builtins act within.
+    // (Login and two-factor bookkeeping is committed separately, so that a verb which is
+    // abandoned can't take its record of failed attempts with it.)
+    tx: Option<Tx>,
+    // The connection this VM runs verbs for, if any.
+    connection: Option<Oid>,
+    // What the verb being executed would have done, if it's a dry run.
+    dry_run: Option<DryRun>,
+    // Builtins the verb being executed has called.
+    host_calls: u64,
+    // The slots the verb being executed has read and written, if dependencies are being recorded.
+    accesses: Option<SlotAccesses>,
+    // The logical clock and seeded randomness of the verb being executed, if it's deterministic.
+    determinism: Option<Determinism>,
+    limiter: GuestLimiter,
+}
+
+/// What a verb executed as a dry run would have done, had its transaction been committed.
+#[derive(Clone, Debug, Default)]
+pub struct DryRun {
+    /// Vectors of [IdKey oid, IdKey key, String name, value] for each slot written.
+    pub writes: Vec<Value>,
+    /// Vectors of [IdKey connection, message] for each message sent.
+    pub messages: Vec<Value>,
+}
+
+/// Fuel per invocation if neither the world nor the verb's object say otherwise.
+pub const DEFAULT_FUEL_LIMIT: u64 = 100_000_000;
+
+/// Memory a guest instance may grow to if neither the world nor the verb's object say otherwise.
+pub const DEFAULT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Time per invocation, when preempted by epoch, if neither the world nor the verb's object say
+/// otherwise.
+pub const DEFAULT_TIME_LIMIT: Duration = Duration::from_secs(5);
+
+// Elements a guest's tables may grow to.
+const TABLE_ELEMENTS_LIMIT: u32 = 10000;
+
+/// The module every builtin is also bound in for guests which can't import functions returning two
+/// values (such as those written in Rust, with the driver crate). There each returns its result's
+/// location and length packed into an i64, as `length << 32 | location`, as such a guest's
+/// 'invoke' may return its own.
+pub const PACKED_HOST_MODULE: &str = "host_packed";
+
+fn pack_location(begin: i32, size: i32) -> i64 {
+    ((size as u32 as i64) << 32) | begin as u32 as i64
+}
+
+fn unpack_location(packed: i64) -> (i32, i32) {
+    (packed as u32 as i32, (packed >> 32) as u32 as i32)
+}
+
+/// The resources a single verb invocation may use.
+#[derive(Clone, Copy, Debug)]
+pub struct ExecutionLimits {
+    /// When preempted by fuel.
+    pub fuel: u64,
+    /// When preempted by epoch.
+    pub time: Duration,
+    /// In bytes, per memory.
+    pub memory: usize,
+}
+
+// Caps the size of guest memories and tables, noting when a guest has been refused so that its
+// failure can be reported as hitting a resource limit.
+#[derive(Default)]
+struct GuestLimiter {
+    memory: usize,
+    exceeded: bool,
+}
+
+impl wasmtime::ResourceLimiter for GuestLimiter {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> bool {
+        let allowed = desired <= self.memory;
+        self.exceeded |= !allowed;
+        allowed
+    }
+
+    fn table_growing(&mut self, _current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        let allowed = desired <= TABLE_ELEMENTS_LIMIT;
+        self.exceeded |= !allowed;
+        allowed
+    }
+}
+
+// Fuel is handed out in slices of this much, yielding co-operatively between them.
+const FUEL_SLICE: u64 = 10000;
+
+// Builtins which commit on their own would escape a dry run's rollback, so they refuse instead.
+fn refused_in_dry_run(caller: &wasmtime::Caller<'_, VMState>) -> bool {
+    caller.data().dry_run.is_some()
+}
+
+fn record_write(
+    caller: &mut wasmtime::Caller<'_, VMState>,
+    oid: Oid,
+    key: Oid,
+    name: &str,
+    value: &Value,
+) {
+    record_slot_written(caller, oid, key, name);
+    if let Some(dry_run) = caller.data_mut().dry_run.as_mut() {
+        dry_run.writes.push(Value::Vector(vec![
+            Value::IdKey(oid),
+            Value::IdKey(key),
+            Value::String(name.into()),
+            value.clone(),
+        ]));
+    }
+}
+
+fn record_slot_written(caller: &mut wasmtime::Caller<'_, VMState>, oid: Oid, key: Oid, name: &str) {
+    if let Some(accesses) = caller.data_mut().accesses.as_mut() {
+        accesses.writes.insert((oid, key, name.to_string()));
+    }
+}
+
+fn record_read(caller: &mut wasmtime::Caller<'_, VMState>, oid: Oid, key: Oid, name: &str) {
+    if let Some(accesses) = caller.data_mut().accesses.as_mut() {
+        accesses.reads.insert((oid, key, name.to_string()));
+    }
+}
+
+fn current_tx(caller: &wasmtime::Caller<'_, VMState>) -> Result<Tx, Trap> {
+    caller
+        .data()
+        .tx
+        .clone()
+        .ok_or_else(|| Trap::new("No transaction"))
 }
 
 // Argument 'stack frame' construction.
@@ -31,46 +230,50 @@ fn pack_args(
     mut store: &mut wasmtime::Store<VMState>,
     instance: &wasmtime::Instance,
     args: &Value,
-) -> usize {
-    // Messagepack the arguments to pass through.
-    let mut args_buf: Vec<u8> = vec![];
-    append_value(&mut args_buf, args);
+) -> Result<usize, Error> {
+    let args_buf = encode_frame(args);
     // Fill module's memory offset 0 with the serialized arguments.
     let memory = instance
         .get_memory(store.deref_mut(), "memory")
-        .expect("expected memory not found");
+        .ok_or_else(|| anyhow!("Module exports no 'memory'"))?;
 
     memory
         .write(store.deref_mut(), 0, args_buf.as_slice())
-        .expect("Could not write argument memory");
+        .map_err(|e| anyhow!("Could not write argument memory: {}", e))?;
 
-    args_buf.len()
+    Ok(args_buf.len())
 }
 
+// Write a builtin's result after its arguments. A result too big for what's left of the guest's
+// memory is replaced by ResourceLimit, so that the verb is told rather than trapped.
 fn pack_result(
     mut caller: &mut wasmtime::Caller<VMState>,
     stack_end: usize,
     result: &Value,
 ) -> Result<usize, Error> {
-    let mut result_buf: Vec<u8> = vec![];
-    append_value(&mut result_buf, result);
-    let mem = &caller.get_export("memory").unwrap();
-    match mem {
-        Extern::Memory(mem) => {
+    let mut result_buf = encode_frame(result);
+    match caller.get_export("memory") {
+        Some(Extern::Memory(mem)) => {
+            let room = mem.data_size(&*caller).saturating_sub(stack_end);
+            if result_buf.len() > room {
+                result_buf = encode_frame(&Value::Error(ResourceLimit));
+            }
             mem.write(caller.deref_mut(), stack_end, result_buf.as_slice())
-                .expect("Could not write result memory");
+                .map_err(|e| anyhow!("Could not write result memory: {}", e))?;
             Ok(result_buf.len())
         }
         _ => Err(anyhow!("Invalid export for 'memory'")),
     }
 }
 
-// Unpack arguments from a stack frame, used by builtins etc.
+// Unpack arguments from a stack frame, used by builtins etc. (Every builtin starts with this, so
+// it's where they're counted.)
 fn unpack_args(
     caller: &mut wasmtime::Caller<VMState>,
     params: &[wasmtime::Val],
 ) -> anyhow::Result<(Vec<Value>, usize)> {
-    let mem = caller.get_export("memory").unwrap();
+    caller.data_mut().host_calls += 1;
+    let mem = caller.get_export("memory");
     let stack_end = match &params[0] {
         Val::I32(p) => *p as usize,
         _ => {
@@ -78,12 +281,14 @@ fn unpack_args(
         }
     };
     match mem {
-        Extern::Memory(mem) => {
-            let _world = caller.data().world.clone();
+        Some(Extern::Memory(mem)) => {
+            if stack_end > mem.data_size(&caller) {
+                return Err(anyhow!("Arguments run past the end of memory"));
+            }
             let mut buffer: Vec<u8> = vec![0; stack_end];
-            mem.read(&caller, 0, &mut buffer).unwrap();
-            let arguments = value::parse_value(&mut buffer.as_slice());
-            match arguments {
+            mem.read(&caller, 0, &mut buffer)
+                .map_err(|e| anyhow!("Could not read argument memory: {}", e))?;
+            match decode_frame(&buffer)? {
                 Value::Vector(v) => Ok((v, stack_end)),
                 _ => Err(anyhow!("Invalid method arguments")),
             }
@@ -101,68 +306,166 @@ fn unpack_results(
     // Fill module's memory offset 0 with the serialized arguments.
     let memory = instance
         .get_memory(store.deref_mut(), "memory")
-        .expect("expected memory not found");
+        .ok_or_else(|| anyhow!("Module exports no 'memory'"))?;
     let mut buffer: Vec<u8> = vec![0; args_len];
 
-    memory.read(store, args_start, &mut buffer).unwrap();
-    let value = value::parse_value(&mut buffer.as_slice());
+    memory
+        .read(store, args_start, &mut buffer)
+        .map_err(|e| anyhow!("Could not read result memory: {}", e))?;
+    let value = decode_frame(&buffer)?;
     Ok(value)
 }
 
+// Bind a builtin in the "host" module, and again in PACKED_HOST_MODULE returning the location of
+// its result packed.
+// Call a builtin, unless it's `refused` to verbs executed deterministically and the caller is one,
+// in which case it returns PermissionDenied.
+fn call_builtin<'a, F>(
+    func: &F,
+    refused: bool,
+    caller: wasmtime::Caller<'a, VMState>,
+    params: &'a [Val],
+    results: &'a mut [Val],
+) -> Box<dyn Future<Output = Result<(), Trap>> + Send + 'a>
+where
+    F: for<'b> Fn(
+        wasmtime::Caller<'b, VMState>,
+        &'b [Val],
+        &'b mut [Val],
+    ) -> Box<dyn Future<Output = Result<(), Trap>> + Send + 'b>,
+{
+    if !refused || caller.data().determinism.is_none() {
+        return func(caller, params, results);
+    }
+    Box::new(async move {
+        let mut caller = caller;
+        let (_, stack_end) = unpack_args(&mut caller, params)?;
+        let return_value = Value::Error(PermissionDenied);
+        let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+        results[0] = Val::I32(stack_end as i32);
+        results[1] = Val::I32(results_size as i32);
+        Ok(())
+    })
+}
+
+fn bind_builtin<F>(linker: &mut wasmtime::Linker<VMState>, name: &str, func: F) -> Result<(), Error>
+where
+    F: for<'a> Fn(
+            wasmtime::Caller<'a, VMState>,
+            &'a [Val],
+            &'a mut [Val],
+        ) -> Box<dyn Future<Output = Result<(), Trap>> + Send + 'a>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    let refused = NONDETERMINISTIC_BUILTINS.contains(&name);
+    let builtin_func_type = wasmtime::FuncType::new(
+        Some(wasmtime::ValType::I32),
+        vec![wasmtime::ValType::I32, wasmtime::ValType::I32].into_iter(),
+    );
+    let host_func = func.clone();
+    linker.func_new_async(
+        "host",
+        name,
+        builtin_func_type,
+        move |caller, params, results| call_builtin(&host_func, refused, caller, params, results),
+    )?;
+
+    let packed_func_type =
+        wasmtime::FuncType::new(Some(wasmtime::ValType::I32), Some(wasmtime::ValType::I64));
+    linker.func_new_async(
+        PACKED_HOST_MODULE,
+        name,
+        packed_func_type,
+        move |caller, params, results| {
+            let func = func.clone();
+            Box::new(async move {
+                let mut location = [Val::I32(0), Val::I32(0)];
+                Pin::from(call_builtin(&func, refused, caller, params, &mut location)).await?;
+                results[0] = match location {
+                    [Val::I32(begin), Val::I32(size)] => Val::I64(pack_location(begin, size)),
+                    _ => return Err(Trap::new("Invalid builtin result")),
+                };
+                Ok(())
+            })
+        },
+    )?;
+    Ok(())
+}
+
+// NoError if the login is granted, SecondFactorRequired if it awaits 'login_verify'. Otherwise
+// PermissionDenied, or if further attempts must wait, the milliseconds to wait.
+fn login_result(outcome: LoginOutcome) -> Value {
+    match outcome {
+        LoginOutcome::Granted => Value::Error(NoError),
+        LoginOutcome::SecondFactorRequired => Value::Error(SecondFactorRequired),
+        LoginOutcome::Denied(wait) if wait.is_zero() => Value::Error(PermissionDenied),
+        LoginOutcome::Denied(wait) => Value::I32(wait_millis(wait)),
+    }
+}
+
+fn wait_millis(wait: Duration) -> i32 {
+    wait.as_millis().min(i32::MAX as u128) as i32
+}
+
 impl WasmVM {
-    pub fn new(world: Arc<World>) -> Result<Self, Error> {
-        let mut config = wasmtime::Config::new();
-        // We need this engine's `Store`s to be async, and consume fuel, so
-        // that they can co-operatively yield during execution.
-        config.async_support(true);
-        config.consume_fuel(true);
-
-        let engine = wasmtime::Engine::new(&config)?;
+    pub fn new(world: Arc<World>, connection: Option<Oid>) -> Result<Self, Error> {
+        // Every VM uses the world's engine, so that compiled modules can be shared between them.
+        let engine = world.module_cache().engine().clone();
         let mut linker = wasmtime::Linker::new(&engine);
 
         wasmtime_wasi::add_to_linker(&mut linker, |state: &mut VMState| &mut state.wasi)?;
+        wasi_policy::add_to_linker(&mut linker, |state: &mut VMState| {
+            (state.wasi_policy, state.determinism.as_mut())
+        })?;
 
         let state = VMState {
-            wasi: wasmtime_wasi::WasiCtxBuilder::new()
-                .inherit_stdio()
-                .inherit_args()?
-                .build(),
-            world,
+            wasi: WasiPolicy::default().context(),
+            wasi_policy: WasiPolicy::default(),
+            world: world.clone(),
+            tx: None,
+            connection,
+            dry_run: None,
+            host_calls: 0,
+            accesses: None,
+            determinism: None,
+            limiter: GuestLimiter::default(),
         };
         let mut store = wasmtime::Store::new(&engine, state);
-
-        // WebAssembly execution will be paused for an async yield every time it
-        // consumes 10000 fuel. Fuel will be refilled u64::MAX times.
-        store.out_of_fuel_async_yield(u64::MAX, 10000);
+        store.limiter(|state| &mut state.limiter);
+        store.epoch_deadline_async_yield_and_update(1);
 
         let vm = WasmVM {
+            world,
             wasm_linker: Arc::new(Mutex::new(linker)),
             wasm_store: Arc::new(Mutex::new(store)),
-            module_cache: moka::future::Cache::builder()
-                .time_to_live(Duration::from_secs(30 * 60))
-                .build(),
+            chain: Default::default(),
+            scratch: Default::default(),
+            connection,
+            invoked_by: Default::default(),
+            deterministic: AtomicBool::new(false),
         };
         Ok(vm)
     }
 
     pub fn bind_builtins(self: Arc<Self>) -> anyhow::Result<(), anyhow::Error> {
-        let builtin_func_type = wasmtime::FuncType::new(
-            Some(wasmtime::ValType::I32),
-            vec![wasmtime::ValType::I32, wasmtime::ValType::I32].into_iter(),
-        );
-
+        let builtins = self.world.builtins();
         let mut linker = block_on(self.wasm_linker.lock());
         let vm = self.clone();
-        linker.func_new_async(
-            "host",
-            "invoke",
-            builtin_func_type.clone(),
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "invoke",
+                "(IdKey oid, String verb, Vector args) -> Value",
+                Privilege::Any,
+                "Invoke a verb on an object with the given arguments, returning its result; one on another world's object is invoked once this verb commits, returning NoError.",
+            ),
             move |mut caller, params, results| {
                 let vm = vm.clone();
 
                 Box::new(async move {
-                    let vm = vm.clone();
-
                     let (arguments, stack_end) = unpack_args(&mut caller, params)?;
                     let (dest_oid, verb, arguments) = match &arguments[..] {
                         [oid, verb, args] => {
@@ -192,11 +495,17 @@ impl WasmVM {
                         }
                     };
                     let world = caller.data().world.clone();
-                    let return_value =
-                        send_verb_dispatch(&world.clone(), vm, *dest_oid, verb.as_str(), arguments)
-                            .await?;
+                    let tx = current_tx(&caller)?;
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => {
+                            let nested = vm.nested()?;
+                            invoke_verb(&world, &tx, nested, *dest_oid, verb.as_str(), arguments)
+                                .await?
+                        }
+                    };
 
-                    let results_size = pack_result(&mut caller, stack_end, &return_value).unwrap();
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
                     results[0] = Val::I32(stack_end as i32);
                     results[1] = Val::I32(results_size as i32);
                     Ok(())
@@ -204,16 +513,22 @@ impl WasmVM {
             },
         )?;
 
-        linker.func_new_async(
-            "host",
-            "log",
-            builtin_func_type.clone(),
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "log",
+                "(...) -> I32",
+                Privilege::Any,
+                "Write the arguments to the server log.",
+            ),
             |mut caller, params, results| {
                 Box::new(async move {
                     let (arguments, stack_end) = unpack_args(&mut caller, params)?;
-                    info!("Log: {:?}", arguments);
+                    let redaction = caller.data().world.redaction();
+                    let shown: Vec<_> = arguments.iter().map(|a| redaction.value(a)).collect();
+                    info!("Log: {:?}", shown);
 
-                    let results_size = pack_result(&mut caller, stack_end, &Value::I32(0)).unwrap();
+                    let results_size = pack_result(&mut caller, stack_end, &Value::I32(0))?;
                     results[0] = Val::I32(stack_end as i32);
                     results[1] = Val::I32(results_size as i32);
                     Ok(())
@@ -221,10 +536,14 @@ impl WasmVM {
             },
         )?;
 
-        linker.func_new_async(
-            "host",
-            "get_slot",
-            builtin_func_type.clone(),
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "get_slot",
+                "(IdKey oid, IdKey key, String name) -> Value",
+                Privilege::Any,
+                "Read a slot, or SlotDoesNotExist.",
+            ),
             |mut caller, params, results| {
                 Box::new(async move {
                     let (arguments, stack_end) = unpack_args(&mut caller, params)?;
@@ -256,10 +575,119 @@ impl WasmVM {
                             return Err(Trap::new("Invalid arguments"));
                         }
                     };
+                    let tx = current_tx(&caller)?;
+                    record_read(&mut caller, *oid, *key, slot_name);
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let return_value =
+                        get_slot(&world, &tx, connection, *oid, *key, slot_name).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "find_references",
+                "(IdKey oid) -> Vector",
+                Privilege::Programmer,
+                "The slots whose values refer to an object, as an IdKey or within a Vector, each as [IdKey location, String name], up to 1000 of them. Only found when the server keeps the reference index; SlotDoesNotExist if it doesn't.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let oid = match &arguments[..] {
+                        [Value::IdKey(oid)] => *oid,
+                        _ => {
+                            error!("Invalid 'find_references' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = find_references(&tx, oid).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "search",
+                "(String query) -> Vector",
+                Privilege::Any,
+                "The String slots under their objects' own keys holding every word of a query, on objects whose 'searchable' slot opts them in, each as [IdKey location, String name], up to 1000 of them. Only the slots the caller may read are found.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let query = match &arguments[..] {
+                        [Value::String(query)] => query.clone(),
+                        _ => {
+                            error!("Invalid 'search' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
                     let world = caller.data().world.clone();
-                    let return_value = get_slot(&world, *oid, *key, slot_name).await?;
+                    let connection = caller.data().connection;
+                    let return_value = search_slots(&world, &tx, connection, &query).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "list_slots",
+                "(IdKey oid, IdKey key) -> Vector",
+                Privilege::Any,
+                "The names of the slots on an object under a key.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+
+                    let (oid, key) = match &arguments[..] {
+                        [oid, key] => {
+                            let oid = match oid {
+                                Value::IdKey(id) => id,
+                                _ => {
+                                    return Err(Trap::new("Invalid destination"));
+                                }
+                            };
+                            let key = match key {
+                                Value::IdKey(id) => id,
+                                _ => {
+                                    return Err(Trap::new("Invalid key"));
+                                }
+                            };
+                            (oid, key)
+                        }
+                        _ => {
+                            error!("Invalid 'list_slots' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    record_read(&mut caller, *oid, *key, "");
+                    let return_value = list_slots(&tx, *oid, *key).await?;
 
-                    let results_size = pack_result(&mut caller, stack_end, &return_value).unwrap();
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
                     results[0] = Val::I32(stack_end as i32);
                     results[1] = Val::I32(results_size as i32);
                     Ok(())
@@ -267,10 +695,14 @@ impl WasmVM {
             },
         )?;
 
-        linker.func_new_async(
-            "host",
-            "set_slot",
-            builtin_func_type.clone(),
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "set_slot",
+                "(IdKey oid, IdKey key, String name, Value value) -> Error",
+                Privilege::Programmer,
+                "Write a slot. QuotaExceeded if its object's owner would be storing more than they may.",
+            ),
             |mut caller, params, results| {
                 Box::new(async move {
                     let (arguments, stack_end) = unpack_args(&mut caller, params)?;
@@ -298,14 +730,29 @@ impl WasmVM {
                             (oid, key, slot_name, value)
                         }
                         _ => {
-                            error!("Invalid 'get_slot' arguments");
+                            error!("Invalid 'set_slot' arguments");
                             return Err(Trap::new("Invalid arguments"));
                         }
                     };
+                    let tx = current_tx(&caller)?;
                     let world = caller.data().world.clone();
-                    set_slot(&world, *oid, *key, slot_name, value).await?;
+                    // Only refusals are returned, as writes have always returned zero.
+                    let connection = caller.data().connection;
+                    let written =
+                        set_slot(&world, &tx, connection, *oid, *key, slot_name, value).await?;
+                    let return_value = match written {
+                        Value::Error(NoError) => {
+                            record_write(&mut caller, *oid, *key, slot_name, value);
+                            world
+                                .module_cache()
+                                .slot_written(*oid, *key, slot_name)
+                                .await;
+                            Value::I32(0)
+                        }
+                        refused => refused,
+                    };
 
-                    let results_size = pack_result(&mut caller, stack_end, &Value::I32(0)).unwrap();
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
                     results[0] = Val::I32(stack_end as i32);
                     results[1] = Val::I32(results_size as i32);
                     Ok(())
@@ -313,43 +760,70 @@ impl WasmVM {
             },
         )?;
 
-        linker.func_new_async(
-            "host",
-            "send",
-            builtin_func_type,
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "create_object",
+                "([String name]) -> IdKey",
+                Privilege::Programmer,
+                "Create an object, optionally claiming a unique name for it, owned by the player whose connection this is. NameTaken if the name is in use, QuotaExceeded if the player owns as many objects as they may.",
+            ),
             |mut caller, params, results| {
                 Box::new(async move {
                     let (arguments, stack_end) = unpack_args(&mut caller, params)?;
-                    let (cid, msg) = match &arguments[..] {
-                        [oid, message] => {
-                            let cid = match &oid {
-                                Value::IdKey(oid) => oid,
-                                _ => {
-                                    error!("Invalid 'send' destination: {:?}", oid);
-                                    return Err(Trap::new("Invalid arguments"));
-                                }
-                            };
+                    let name = match &arguments[..] {
+                        [] => None,
+                        [Value::String(name)] => Some(name.as_str()),
+                        _ => {
+                            error!("Invalid 'create_object' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let id = match caller.data_mut().determinism.as_mut() {
+                        Some(determinism) => determinism.uuid(),
+                        None => Uuid::new_v4(),
+                    };
+                    let tx = current_tx(&caller)?;
+                    let world = caller.data().world.clone();
+                    // Objects made for a player count against their quota.
+                    let owner = caller
+                        .data()
+                        .connection
+                        .and_then(|connection| connection_player(&world, connection));
+                    let return_value = create_object(&world, &tx, Oid { id }, name, owner).await?;
 
-                            let msg = match message {
-                                Value::String(str) => Message::Text(str.clone()),
-                                Value::Binary(bin) => Message::Binary(bin.clone()),
-                                _ => {
-                                    error!("Invalid arguments to 'send': {:?}", arguments);
-                                    return Err(Trap::new("Invalid arguments"));
-                                }
-                            };
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
 
-                            (cid, msg)
-                        }
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "destroy_object",
+                "(IdKey oid) -> Error",
+                Privilege::Programmer,
+                "Remove an object and all its slots. PermissionDenied unless it's the caller's, or the caller's an admin.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let oid = match &arguments[..] {
+                        [Value::IdKey(oid)] => oid,
                         _ => {
-                            error!("Invalid arguments to 'send': {:?}", arguments);
+                            error!("Invalid 'destroy_object' arguments");
                             return Err(Trap::new("Invalid arguments"));
                         }
                     };
+                    let tx = current_tx(&caller)?;
                     let world = caller.data().world.clone();
-                    send_connection_message(world, *cid, msg).await?;
+                    let connection = caller.data().connection;
+                    let return_value = destroy_object(&world, &tx, connection, *oid).await?;
 
-                    let results_size = pack_result(&mut caller, stack_end, &Value::I32(0)).unwrap();
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
                     results[0] = Val::I32(stack_end as i32);
                     results[1] = Val::I32(results_size as i32);
                     Ok(())
@@ -357,56 +831,2353 @@ impl WasmVM {
             },
         )?;
 
-        Ok(())
-    }
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "name_available",
+                "(String name) -> I32",
+                Privilege::Any,
+                "Whether no object has claimed a name.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let name = match &arguments[..] {
+                        [Value::String(name)] => name,
+                        _ => {
+                            error!("Invalid 'name_available' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let available = name_available(&tx, name).await?;
 
-    pub async fn execute(&self, method: &Program, args: &Value) -> Result<Value, anyhow::Error> {
-        // Check to see if we have a cached copy of the compiled Module for this Program, using
-        // a Sha512 digest of the program text.
-        // (Should probably profile this because perhaps in some cases taking the hash could be
-        // costlier than just compiling.)
-        let digest = sha2::Sha512::digest(method.as_slice());
-        let module = self
-            .module_cache
-            .get_with(digest.to_vec(), async move {
-                let store = self.wasm_store.lock().await;
+                    let results_size =
+                        pack_result(&mut caller, stack_end, &Value::I32(available as i32))?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
 
-                Module::new(store.engine(), method).expect("Not able to produce WASM module")
-            })
-            .await;
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "counter_incr",
+                "(IdKey oid, String name, I64 delta) -> Error",
+                Privilege::Any,
+                "Add to a named counter on an object. Verbs adding to the same counter at once don't conflict, as they would setting a slot.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name, delta) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name), Value::I64(delta)] => {
+                            (oid, name, *delta)
+                        }
+                        [Value::IdKey(oid), Value::String(name), Value::I32(delta)] => {
+                            (oid, name, *delta as i64)
+                        }
+                        _ => {
+                            error!("Invalid 'counter_incr' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = counter_incr(&tx, *oid, name, delta);
 
-        // We'll be holding a lock on the actual 'store' throughout execution.
-        // This defacto enforces single-threaded single file access per connection
-        // But I think this is ok for our purposes.
-        let mut store = self.wasm_store.lock().await;
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
 
-        // Use the linker to produce an instance from the module.
-        let instance = {
-            let linker = self.wasm_linker.lock().await;
-            linker
-                .instantiate_async(store.deref_mut(), &module)
-                .await
-                .unwrap()
-        };
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "counter_get",
+                "(IdKey oid, String name) -> I64",
+                Privilege::Any,
+                "The value of a named counter on an object; 0 if it's never been added to.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name)] => (oid, name),
+                        _ => {
+                            error!("Invalid 'counter_get' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = Value::I64(counter_get(&tx, *oid, name).await?);
 
-        // Build the 'stack frame'. Pack args into module's memory.
-        let args_len = pack_args(store.deref_mut(), &instance, args);
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "cooldown_check",
+                "(IdKey oid, String name) -> I32",
+                Privilege::Any,
+                "How many milliseconds are left of a named cooldown on an object. 0 if it's over.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name)] => (oid, name),
+                        _ => {
+                            error!("Invalid 'cooldown_check' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let remaining = cooldown_check(&tx, *oid, name).await?;
 
-        // Retrieve the linked function from the instance and call it.
-        let verb_func = instance
-            .get_typed_func::<i32, (i32, i32), _>(store.deref_mut(), "invoke")
-            .expect("Didn't create typed func");
+                    let return_value = Value::I32(wait_millis(remaining));
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
 
-        // Invocation argument is the length of the argument buffer in memory.
-        let (args_begin, args_size) = verb_func
-            .call_async(store.deref_mut(), args_len as i32)
-            .await?;
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "cooldown_set",
+                "(IdKey oid, String name, I32 millis) -> Error",
+                Privilege::Any,
+                "Start a named cooldown on an object, lasting the given milliseconds. 0 ends it.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name, millis) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name), Value::I32(millis)]
+                            if *millis >= 0 =>
+                        {
+                            (oid, name, *millis as u64)
+                        }
+                        _ => {
+                            error!("Invalid 'cooldown_set' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = cooldown_set(&tx, *oid, name, Duration::from_millis(millis));
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "move_slot",
+                "(IdKey oid, IdKey key, String name, IdKey to_oid, IdKey to_key, String to_name) -> Error",
+                Privilege::Programmer,
+                "Move a slot to another object, key or name, with its watchers. SlotDoesNotExist if it isn't set, NameTaken if the destination is, QuotaExceeded if the destination's owner would be storing more than they may.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (from, to) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::IdKey(key), Value::String(name), Value::IdKey(to_oid), Value::IdKey(to_key), Value::String(to_name)] => (
+                            SlotDef {
+                                location: *oid,
+                                key: *key,
+                                name: name.to_string(),
+                            },
+                            SlotDef {
+                                location: *to_oid,
+                                key: *to_key,
+                                name: to_name.to_string(),
+                            },
+                        ),
+                        _ => {
+                            error!("Invalid 'move_slot' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    record_read(&mut caller, from.location, from.key, &from.name);
+                    record_slot_written(&mut caller, from.location, from.key, &from.name);
+                    record_slot_written(&mut caller, to.location, to.key, &to.name);
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let return_value = move_slot(&world, &tx, connection, from, to).await?;
+                    let module_cache = world.module_cache();
+                    module_cache.slot_written(from.location, from.key, &from.name).await;
+                    module_cache.slot_written(to.location, to.key, &to.name).await;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "slot_meta",
+                "(IdKey oid, IdKey key, String name) -> Vector",
+                Privilege::Programmer,
+                "A slot's metadata, as [name, value] pairs: its \"owner\" if it has one, its \"flags\" (e.g. \"rx\") and when it was \"created\" and \"modified\". SlotDoesNotExist if it isn't set, PermissionDenied if it may not be read.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let slot = match &arguments[..] {
+                        [Value::IdKey(oid), Value::IdKey(key), Value::String(name)] => SlotDef {
+                            location: *oid,
+                            key: *key,
+                            name: name.to_string(),
+                        },
+                        _ => {
+                            error!("Invalid 'slot_meta' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let return_value = slot_meta(&world, &tx, connection, slot).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "set_slot_meta",
+                "(IdKey oid, IdKey key, String name, IdKey owner, String flags) -> Error",
+                Privilege::Programmer,
+                "Give a slot an owner (the nil id for none) and the permissions others have on it, as \"r\", \"w\" and \"x\". Only the slot's owner, or for an unowned slot its object's owner, or an admin may. SlotDoesNotExist if it isn't set, BadType if the flags aren't those.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (slot, owner, flags) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::IdKey(key), Value::String(name), Value::IdKey(owner), Value::String(flags)] => (
+                            SlotDef {
+                                location: *oid,
+                                key: *key,
+                                name: name.to_string(),
+                            },
+                            (!owner.id.is_nil()).then_some(*owner),
+                            flags.clone(),
+                        ),
+                        _ => {
+                            error!("Invalid 'set_slot_meta' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    record_slot_written(&mut caller, slot.location, slot.key, &slot.name);
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let return_value =
+                        set_slot_meta(&world, &tx, connection, slot, owner, &flags).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "move",
+                "(IdKey what, IdKey where) -> Error",
+                Privilege::Programmer,
+                "Move an object into another, keeping its location and their contents in step. PermissionDenied unless the caller may alter the object, and the destination has no owner or is one the caller may alter too. BadType if it would end up inside itself.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (what, destination) = match &arguments[..] {
+                        [Value::IdKey(what), Value::IdKey(destination)] => (*what, *destination),
+                        _ => {
+                            error!("Invalid 'move' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let return_value =
+                        move_object(&world, &tx, connection, what, destination).await?;
+                    if let Value::Error(NoError) = return_value {
+                        let location = Value::IdKey(destination);
+                        record_write(&mut caller, what, what, LOCATION_SLOT, &location);
+                    }
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "contents",
+                "(IdKey location) -> Vector",
+                Privilege::Any,
+                "What's in a location, as IdKeys.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let location = match &arguments[..] {
+                        [Value::IdKey(location)] => *location,
+                        _ => {
+                            error!("Invalid 'contents' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = contents_of(&tx, location).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "tag_add",
+                "(IdKey oid, String tag) -> Error",
+                Privilege::Programmer,
+                "Tag an object. BadType if the tag is empty.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, tag) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(tag)] => (oid, tag),
+                        _ => {
+                            error!("Invalid 'tag_add' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = tag_add(&tx, *oid, tag);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "tag_remove",
+                "(IdKey oid, String tag) -> Error",
+                Privilege::Programmer,
+                "Remove a tag from an object.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, tag) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(tag)] => (oid, tag),
+                        _ => {
+                            error!("Invalid 'tag_remove' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = tag_remove(&tx, *oid, tag);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "tag_query",
+                "(String tag) -> Vector",
+                Privilege::Any,
+                "Every object with a tag, as IdKeys.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let tag = match &arguments[..] {
+                        [Value::String(tag)] => tag,
+                        _ => {
+                            error!("Invalid 'tag_query' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = tag_query(&tx, tag).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "tags_of",
+                "(IdKey oid) -> Vector",
+                Privilege::Any,
+                "The tags on an object, as Strings.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let oid = match &arguments[..] {
+                        [Value::IdKey(oid)] => oid,
+                        _ => {
+                            error!("Invalid 'tags_of' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = tags_of(&tx, *oid).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "read_blob",
+                "(IdKey oid, IdKey key, String name, I64 offset, I32 length) -> Binary",
+                Privilege::Any,
+                "Read part of a Binary slot, which is how Blobs (Binary values too large to keep in their slots) are read.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (slot, offset, length) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::IdKey(key), Value::String(name), Value::I64(offset), Value::I32(length)]
+                            if *offset >= 0 && *length >= 0 =>
+                        {
+                            let slot = SlotDef {
+                                location: *oid,
+                                key: *key,
+                                name: name.to_string(),
+                            };
+                            (slot, *offset as u64, *length as usize)
+                        }
+                        _ => {
+                            error!("Invalid 'read_blob' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = read_blob(&tx, slot, offset, length).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "rename",
+                "(IdKey oid, String name) -> Error",
+                Privilege::Programmer,
+                "Claim a new unique name for an object, releasing its old one. NameTaken if the name is in use.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name)] => (oid, name),
+                        _ => {
+                            error!("Invalid 'rename' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = rename_object(&tx, *oid, name).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "login_allowed",
+                "(IdKey connection, IdKey account) -> I32",
+                Privilege::Security,
+                "How many milliseconds a connection must wait before attempting to log in to an account.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (connection, account) = match &arguments[..] {
+                        [Value::IdKey(connection), Value::IdKey(account)] => (connection, account),
+                        _ => {
+                            error!("Invalid 'login_allowed' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let wait = login_allowed(&world, *connection, *account).await?;
+
+                    // Milliseconds to wait before attempting, 0 if an attempt may be made now.
+                    let return_value = Value::I32(wait_millis(wait));
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "login_attempt",
+                "(IdKey connection, IdKey account, I32 succeeded) -> Value",
+                Privilege::Security,
+                "Record a login attempt once its password has been checked. NoError if granted, SecondFactorRequired, PermissionDenied, or milliseconds to wait.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (connection, account, succeeded) = match &arguments[..] {
+                        [Value::IdKey(connection), Value::IdKey(account), Value::I32(succeeded)] => {
+                            (connection, account, *succeeded != 0)
+                        }
+                        _ => {
+                            error!("Invalid 'login_attempt' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => login_result(
+                            login_attempt(&world, *connection, *account, succeeded).await?,
+                        ),
+                    };
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "login_verify",
+                "(IdKey connection, IdKey account, String code) -> Value",
+                Privilege::Security,
+                "Complete a login with an authenticator or recovery code, answering as login_attempt does.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (connection, account, code) = match &arguments[..] {
+                        [Value::IdKey(connection), Value::IdKey(account), Value::String(code)] => {
+                            (connection, account, code)
+                        }
+                        _ => {
+                            error!("Invalid 'login_verify' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => login_result(login_verify(&world, *connection, *account, code).await?),
+                    };
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "totp_provision",
+                "(IdKey account, String issuer) -> Vector",
+                Privilege::Security,
+                "Start setting up two-factor authentication, returning the secret and an otpauth:// URL.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (account, issuer) = match &arguments[..] {
+                        [Value::IdKey(account), Value::String(issuer)] => (account, issuer),
+                        _ => {
+                            error!("Invalid 'totp_provision' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => totp_provision(&world, *account, issuer).await?,
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "totp_enable",
+                "(IdKey account, String code) -> Vector",
+                Privilege::Security,
+                "Confirm two-factor authentication with a code, returning recovery codes.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (account, code) = match &arguments[..] {
+                        [Value::IdKey(account), Value::String(code)] => (account, code),
+                        _ => {
+                            error!("Invalid 'totp_enable' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => totp_enable(&world, *account, code).await?,
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "totp_disable",
+                "(IdKey account) -> Error",
+                Privilege::Security,
+                "Turn off two-factor authentication.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let account = match &arguments[..] {
+                        [Value::IdKey(account)] => account,
+                        _ => {
+                            error!("Invalid 'totp_disable' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => totp_disable(&world, *account).await?,
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "totp_recovery_codes",
+                "(IdKey account) -> Vector",
+                Privilege::Security,
+                "Replace an account's recovery codes, returning the new ones.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let account = match &arguments[..] {
+                        [Value::IdKey(account)] => account,
+                        _ => {
+                            error!("Invalid 'totp_recovery_codes' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => totp_recovery_codes(&world, *account).await?,
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "connection_info",
+                "(IdKey connection) -> Vector",
+                Privilege::Any,
+                "What's known about a connection, as [name, value] pairs.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let connection = match &arguments[..] {
+                        [Value::IdKey(connection)] => connection,
+                        _ => {
+                            error!("Invalid 'connection_info' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = connection_info(&world, *connection);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "player_stats",
+                "(IdKey player) -> Vector",
+                Privilege::Programmer,
+                "A player's sessions, connected_secs, commands and last_seen, as [name, value] pairs.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let player = match &arguments[..] {
+                        [Value::IdKey(player)] => player,
+                        _ => {
+                            error!("Invalid 'player_stats' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = player_stats_value(&tx, *player).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "quota",
+                "(IdKey player) -> Vector",
+                Privilege::Any,
+                "The objects a player owns and the bytes stored in them, and their object_limit and byte_limit if they have any, as [name, value] pairs.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let player = match &arguments[..] {
+                        [Value::IdKey(player)] => player,
+                        _ => {
+                            error!("Invalid 'quota' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let world = caller.data().world.clone();
+                    let return_value = quota_usage(&world, &tx, *player).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "now",
+                "() -> Timestamp",
+                Privilege::Any,
+                "The current time, in nanoseconds since the Unix epoch; the logical time, if the verb is executed deterministically.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    if !arguments.is_empty() {
+                        error!("Invalid 'now' arguments");
+                        return Err(Trap::new("Invalid arguments"));
+                    }
+                    let now = match caller.data_mut().determinism.as_mut() {
+                        Some(determinism) => determinism.now(),
+                        None => SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_nanos() as i64,
+                    };
+                    let return_value = Value::Timestamp(now);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "format_time",
+                "(Timestamp time, String style) -> String",
+                Privilege::Any,
+                "A time as \"date\", \"time\", \"datetime\", \"iso\" or a strftime pattern, in the connection's player's time zone and locale; or [BadType, reason].",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (nanos, style) = match &arguments[..] {
+                        [Value::Timestamp(nanos), Value::String(style)] => (*nanos, style),
+                        _ => {
+                            error!("Invalid 'format_time' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let return_value = format_time(&world, &tx, connection, nanos, style).await;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "parse_duration",
+                "(String duration) -> I64",
+                Privilege::Any,
+                "A duration such as \"2h30m\", in milliseconds; or [BadType, reason].",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let text = match &arguments[..] {
+                        [Value::String(text)] => text,
+                        _ => {
+                            error!("Invalid 'parse_duration' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let return_value = parse_duration(text);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "parse_cron",
+                "(String expression) -> Vector",
+                Privilege::Any,
+                "A cron expression's fields, and when it next fires, as [name, value] pairs; or [BadType, reason].",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let text = match &arguments[..] {
+                        [Value::String(text)] => text,
+                        _ => {
+                            error!("Invalid 'parse_cron' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let return_value = parse_cron(text);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "calendar_add",
+                "(String name, Timestamp start, Timestamp end, String recurrence, IdKey target, String verb) -> IdKey",
+                Privilege::Programmer,
+                "Add an event to the calendar, recurring by a cron expression unless that's empty; each occurrence is announced by the verb.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (name, times, recurrence, target, verb) = match &arguments[..] {
+                        [Value::String(name), Value::Timestamp(start), Value::Timestamp(end), Value::String(recurrence), Value::IdKey(target), Value::String(verb)] => (name, (*start, *end), recurrence, *target, verb),
+                        _ => {
+                            error!("Invalid 'calendar_add' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let owner = caller
+                        .data()
+                        .connection
+                        .and_then(|connection| connection_player(&world, connection));
+                    let tx = current_tx(&caller)?;
+                    let return_value =
+                        calendar_add(&tx, name, times, recurrence, (target, verb), owner).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "calendar_remove",
+                "(IdKey event) -> Error",
+                Privilege::Programmer,
+                "Remove an event from the calendar; only for its owner or an admin.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let event = match &arguments[..] {
+                        [Value::IdKey(event)] => *event,
+                        _ => {
+                            error!("Invalid 'calendar_remove' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let tx = current_tx(&caller)?;
+                    let return_value = calendar_remove(&world, &tx, connection, event).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "scheduled_tasks",
+                "(IdKey owner) -> Vector",
+                Privilege::Programmer,
+                "The events on the calendar a player added, soonest first, as [name, value] pairs; only for that player or an admin.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let owner = match &arguments[..] {
+                        [Value::IdKey(owner)] => *owner,
+                        _ => {
+                            error!("Invalid 'scheduled_tasks' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let tx = current_tx(&caller)?;
+                    let return_value = scheduled_tasks(&world, &tx, connection, owner).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "cancel_scheduled",
+                "(IdKey event) -> Error",
+                Privilege::Programmer,
+                "Cancel an event on the calendar; only for its owner or an admin.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let event = match &arguments[..] {
+                        [Value::IdKey(event)] => *event,
+                        _ => {
+                            error!("Invalid 'cancel_scheduled' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let tx = current_tx(&caller)?;
+                    let return_value = cancel_scheduled(&world, &tx, connection, event).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "reschedule",
+                "(IdKey event, Timestamp start) -> Error",
+                Privilege::Programmer,
+                "Move the next occurrence of an event on the calendar; only for its owner or an admin.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (event, start) = match &arguments[..] {
+                        [Value::IdKey(event), Value::Timestamp(start)] => (*event, *start),
+                        _ => {
+                            error!("Invalid 'reschedule' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let tx = current_tx(&caller)?;
+                    let return_value = reschedule(&world, &tx, connection, event, start).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "impersonate",
+                "(IdKey player, String mode) -> IdKey",
+                Privilege::Security,
+                "Attach this admin's connection to a player's perspective, \"read_only\" or \"full\"; every impersonation is audited.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (player, mode) = match &arguments[..] {
+                        [Value::IdKey(player), Value::String(mode)] => (*player, mode),
+                        _ => {
+                            error!("Invalid 'impersonate' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let tx = current_tx(&caller)?;
+                    let return_value = impersonate(&world, &tx, connection, player, mode).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "end_impersonation",
+                "() -> Error",
+                Privilege::Security,
+                "Detach this connection from the player it's impersonating.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    if !arguments.is_empty() {
+                        error!("Invalid 'end_impersonation' arguments");
+                        return Err(Trap::new("Invalid arguments"));
+                    }
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let tx = current_tx(&caller)?;
+                    let return_value = end_impersonation(&world, &tx, connection).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "resume_token",
+                "() -> String",
+                Privilege::Any,
+                "A new token this connection's client can reconnect with to resume it, once, for raw protocol clients (structured ones are sent one), superseding any it was given before; SlotDoesNotExist if connections can't be resumed.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    if !arguments.is_empty() {
+                        error!("Invalid 'resume_token' arguments");
+                        return Err(Trap::new("Invalid arguments"));
+                    }
+                    let world = caller.data().world.clone();
+                    let token = caller
+                        .data()
+                        .connection
+                        .and_then(|connection| resume_token(&world, connection));
+                    let return_value = match token {
+                        Some(token) => Value::String(token.into()),
+                        None => Value::Error(SlotDoesNotExist),
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "impersonation_audit",
+                "(Timestamp from, Timestamp to) -> Vector",
+                Privilege::Security,
+                "The audit trail of impersonations between two times, as [name, value] pairs; only for admins.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let times = match &arguments[..] {
+                        [Value::Timestamp(from), Value::Timestamp(to)] => (*from, *to),
+                        _ => {
+                            error!("Invalid 'impersonation_audit' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let tx = current_tx(&caller)?;
+                    let return_value = impersonation_audit(&world, &tx, connection, times).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "upcoming_events",
+                "(I64 window) -> Vector",
+                Privilege::Any,
+                "The occurrences of events starting within a window of milliseconds, as [event, name, start, end] Vectors.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let window = match &arguments[..] {
+                        [Value::I64(window)] if *window >= 0 => Duration::from_millis(*window as u64),
+                        _ => {
+                            error!("Invalid 'upcoming_events' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = upcoming_events(&tx, window).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "next_id",
+                "(String sequence) -> I64",
+                Privilege::Any,
+                "The next id from a named sequence, counting up from 1.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let name = match &arguments[..] {
+                        [Value::String(name)] => name,
+                        _ => {
+                            error!("Invalid 'next_id' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = next_id(&world, name).await?;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "builtins",
+                "() -> Vector",
+                Privilege::Any,
+                "Every builtin, as [name, signature, privilege, description] Vectors.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    if !arguments.is_empty() {
+                        error!("Invalid 'builtins' arguments");
+                        return Err(Trap::new("Invalid arguments"));
+                    }
+                    let world = caller.data().world.clone();
+                    let return_value = Value::Vector(
+                        world
+                            .builtins()
+                            .list()
+                            .iter()
+                            .map(|builtin| builtin.to_value())
+                            .collect(),
+                    );
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "watch_slot",
+                "(IdKey oid, String name) -> Error",
+                Privilege::Any,
+                "Be told when a slot is written, by the system object's 'on_slot_changed' verb, or a message if it has none.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name)] => (oid, name),
+                        _ => {
+                            error!("Invalid 'watch_slot' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let connection = caller.data().connection;
+                    let return_value = watch_slot(&caller.data().world, &tx, connection, *oid, name);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "unwatch_slot",
+                "(IdKey oid, String name) -> Error",
+                Privilege::Any,
+                "Stop being told when a slot is written.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name)] => (oid, name),
+                        _ => {
+                            error!("Invalid 'unwatch_slot' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let connection = caller.data().connection;
+                    let return_value = unwatch_slot(&tx, connection, *oid, name);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        let vm = self.clone();
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "set_verb",
+                "(IdKey oid, String name, String|Binary source, [String lang]) -> Value",
+                Privilege::Programmer,
+                "Compile and store a verb, as WebAssembly or, if lang is \"lua\" or \"javascript\", Lua or JavaScript. InvalidProgram and the compiler's message if it doesn't compile.",
+            ),
+            move |mut caller, params, results| {
+                let vm = vm.clone();
+
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name, source, lang) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name), Value::String(source), lang @ ..] => {
+                            (oid, name, source.as_bytes(), lang)
+                        }
+                        [Value::IdKey(oid), Value::String(name), Value::Binary(source), lang @ ..] => {
+                            (oid, name, &source[..], lang)
+                        }
+                        _ => {
+                            error!("Invalid 'set_verb' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let program = match lang {
+                        [] => Program::wasm(source.to_vec()),
+                        [Value::String(lang)] if lang == "lua" => {
+                            Program::new(ProgramLang::Lua, source.to_vec())
+                        }
+                        [Value::String(lang)] if lang == "javascript" => {
+                            Program::new(ProgramLang::JavaScript, source.to_vec())
+                        }
+                        _ => {
+                            error!("Invalid 'set_verb' language");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+
+                    // Programs which won't compile are reported back to the author, rather than
+                    // stored to fail when invoked.
+                    let return_value = match check_program(vm.world.module_cache().engine(), &program) {
+                        Ok(_) => {
+                            let tx = current_tx(&caller)?;
+                            let program = Value::Program(program);
+                            record_write(&mut caller, *oid, *oid, name, &program);
+                            let connection = caller.data().connection;
+                            let written = set_slot(
+                                &vm.world, &tx, connection, *oid, *oid, name, &program,
+                            )
+                            .await?;
+                            vm.world.module_cache().flush_verb(*oid, name).await;
+                            written
+                        }
+                        Err(e) => Value::Vector(vec![
+                            Value::Error(InvalidProgram),
+                            Value::String(e.to_string().into()),
+                        ]),
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "flush_verb_cache",
+                "(IdKey oid, String name) -> Error",
+                Privilege::Programmer,
+                "Drop the compiled module a verb was last run as, so it's compiled afresh. SlotDoesNotExist if it hasn't been run since it was last cached.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (oid, name) = match &arguments[..] {
+                        [Value::IdKey(oid), Value::String(name)] => (*oid, name),
+                        _ => {
+                            error!("Invalid 'flush_verb_cache' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match world.module_cache().flush_verb(oid, name).await {
+                        true => Value::Error(NoError),
+                        false => Value::Error(SlotDoesNotExist),
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "send",
+                "(IdKey connection, String|Binary message) -> I32",
+                Privilege::Any,
+                "Send a message to a connection. Returns 0 if it's queued to go out, 1 if the connection's queue is full and it was refused, or 2 if the connection's closed or gone.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (cid, msg) = match &arguments[..] {
+                        [oid, message] => {
+                            let cid = match &oid {
+                                Value::IdKey(oid) => oid,
+                                _ => {
+                                    error!(
+                                        "Invalid 'send' destination: {:?}",
+                                        caller.data().world.redaction().value(oid)
+                                    );
+                                    return Err(Trap::new("Invalid arguments"));
+                                }
+                            };
+
+                            let msg = match message {
+                                Value::String(str) => Message::Text(str.to_string()),
+                                Value::Binary(bin) => Message::Binary(bin.to_vec()),
+                                _ => {
+                                    error!(
+                                        "Invalid arguments to 'send': {:?}",
+                                        caller.data().world.redaction().value(message)
+                                    );
+                                    return Err(Trap::new("Invalid arguments"));
+                                }
+                            };
+
+                            (cid, msg)
+                        }
+                        _ => {
+                            let redaction = caller.data().world.redaction();
+                            let shown: Vec<_> =
+                                arguments.iter().map(|a| redaction.value(a)).collect();
+                            error!("Invalid arguments to 'send': {:?}", shown);
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let tx = caller.data().tx.clone();
+                    let delivery = match (caller.data_mut().dry_run.as_mut(), tx) {
+                        (Some(dry_run), _) => {
+                            dry_run.messages.push(Value::Vector(vec![
+                                Value::IdKey(*cid),
+                                arguments[1].clone(),
+                            ]));
+                            Delivery::Queued
+                        }
+                        (None, Some(tx)) => send_connection_message_in(world, &tx, *cid, msg).await?,
+                        (None, None) => send_connection_message(world, *cid, msg).await?,
+                    };
+
+                    let return_value = Value::I32(delivery.code());
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "broadcast",
+                "(IdKey location, String|Binary message[, IdKey except]) -> I64",
+                Privilege::Any,
+                "Send a message to every connected player in a location, but the one excepted. Returns how many connections it was sent to.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (location, message, except) = match &arguments[..] {
+                        [Value::IdKey(location), message] => (*location, message, None),
+                        [Value::IdKey(location), message, Value::IdKey(except)] => {
+                            (*location, message, Some(*except))
+                        }
+                        _ => {
+                            error!("Invalid 'broadcast' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let msg = match message {
+                        Value::String(str) => Message::Text(str.to_string()),
+                        Value::Binary(bin) => Message::Binary(bin.to_vec()),
+                        _ => {
+                            error!(
+                                "Invalid 'broadcast' message: {:?}",
+                                caller.data().world.redaction().value(message)
+                            );
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let world = caller.data().world.clone();
+                    let sent = match caller.data().dry_run.is_some() {
+                        true => {
+                            let recipients =
+                                broadcast_recipients(&world, &tx, location, except).await?;
+                            let dry_run = caller.data_mut().dry_run.as_mut().unwrap();
+                            for connection in &recipients {
+                                dry_run.messages.push(Value::Vector(vec![
+                                    Value::IdKey(*connection),
+                                    message.clone(),
+                                ]));
+                            }
+                            recipients.len()
+                        }
+                        false => broadcast(&world, &tx, location, msg, except).await?,
+                    };
+                    let return_value = Value::I64(sent as i64);
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "send_later",
+                "(IdKey connection, String|Binary message, I64 delay) -> IdKey",
+                Privilege::Any,
+                "Send a message to a connection after a delay in milliseconds, if it's still connected then. Returns a handle to cancel it with.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (cid, msg, delay) = match &arguments[..] {
+                        [Value::IdKey(cid), Value::String(text), Value::I64(delay)] if *delay >= 0 => {
+                            (*cid, Message::Text(text.to_string()), Duration::from_millis(*delay as u64))
+                        }
+                        [Value::IdKey(cid), Value::Binary(bin), Value::I64(delay)] if *delay >= 0 => {
+                            (*cid, Message::Binary(bin.to_vec()), Duration::from_millis(*delay as u64))
+                        }
+                        _ => {
+                            error!("Invalid 'send_later' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let tx = current_tx(&caller)?;
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => send_later(&world, &tx, cid, msg, delay),
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "cancel_send",
+                "(IdKey handle) -> Error",
+                Privilege::Any,
+                "Cancel a message waiting to be sent by 'send_later'; SlotDoesNotExist if it's been sent already.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let handle = match &arguments[..] {
+                        [Value::IdKey(handle)] => *handle,
+                        _ => {
+                            error!("Invalid 'cancel_send' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => cancel_send(&world, handle),
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "send_form",
+                "(IdKey connection, Vector fields, IdKey target, String verb) -> IdKey",
+                Privilege::Any,
+                "Send a connection a form to fill in, each field a Vector of [String name, String type, Vector hints]. Its answers, once they fit the form, are given to the verb on the target with the connection, the form's IdKey and the answers. BadType if the form isn't valid, PermissionDenied if the connection doesn't speak the structured protocol.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (connection, fields, target, verb) = match &arguments[..] {
+                        [Value::IdKey(connection), fields @ Value::Vector(_), Value::IdKey(target), Value::String(verb)] => {
+                            (*connection, fields, *target, verb)
+                        }
+                        _ => {
+                            error!("Invalid 'send_form' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => send_form(&world, connection, fields, target, verb).await?,
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "task_list",
+                "() -> Vector",
+                Privilege::Any,
+                "The messages and requests being handled for the caller's connection and player (or everyone's, for admins), as a Vector of [name, value] pairs for each: its id, connection, player, verb, when it started and how long it's been running_ms.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (_, stack_end) = unpack_args(&mut caller, params)?;
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let tx = current_tx(&caller)?;
+                    let return_value = task_list(&world, &tx, connection).await;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "kill_task",
+                "(IdKey id) -> Error",
+                Privilege::Programmer,
+                "Stop a task listed by 'task_list', abandoning its transaction; SlotDoesNotExist if it's finished already, PermissionDenied if it's another player's.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let id = match &arguments[..] {
+                        [Value::IdKey(id)] => *id,
+                        _ => {
+                            error!("Invalid 'kill_task' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let return_value = match refused_in_dry_run(&caller) {
+                        true => Value::Error(PermissionDenied),
+                        false => kill_task(&world, &current_tx(&caller)?, connection, id).await,
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        let vm = self.clone();
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "subscribe",
+                "(IdKey channel) -> Error",
+                Privilege::Any,
+                "Subscribe the object whose verb is running to a channel, an object, so that its 'on_event' verb is given [IdKey channel, value, IdKey publisher] for each value published there from now on.",
+            ),
+            move |mut caller, params, results| {
+                let vm = vm.clone();
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let channel = match &arguments[..] {
+                        [Value::IdKey(channel)] => *channel,
+                        _ => {
+                            error!("Invalid 'subscribe' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = match vm.innermost() {
+                        Some(subscriber) => subscribe(&tx, channel, subscriber),
+                        None => Value::Error(BadType),
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        let vm = self.clone();
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "unsubscribe",
+                "(IdKey channel) -> Error",
+                Privilege::Any,
+                "Unsubscribe the object whose verb is running from a channel.",
+            ),
+            move |mut caller, params, results| {
+                let vm = vm.clone();
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let channel = match &arguments[..] {
+                        [Value::IdKey(channel)] => *channel,
+                        _ => {
+                            error!("Invalid 'unsubscribe' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value = match vm.innermost() {
+                        Some(subscriber) => unsubscribe(&tx, channel, subscriber),
+                        None => Value::Error(BadType),
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        let vm = self.clone();
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "publish",
+                "(IdKey channel, Value value) -> Error",
+                Privilege::Any,
+                "Publish a value to a channel. Each of its subscribers is given it with 'on_event', once the verb's transaction commits.",
+            ),
+            move |mut caller, params, results| {
+                let vm = vm.clone();
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (channel, value) = match &arguments[..] {
+                        [Value::IdKey(channel), value] => (*channel, value),
+                        _ => {
+                            error!("Invalid 'publish' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let return_value =
+                        publish(&tx, channel, value, vm.innermost(), vm.connection());
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        let vm = self.clone();
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "scratch_put",
+                "(String name, Value value) -> I32",
+                Privilege::Any,
+                "Put a value in the scratchpad shared by this verb and those it invokes, which is emptied when the verb which began the chain returns; or ResourceLimit if the scratchpad would be too big.",
+            ),
+            move |mut caller, params, results| {
+                let vm = vm.clone();
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let (name, value) = match &arguments[..] {
+                        [Value::String(name), value] => (name.to_string(), value.clone()),
+                        _ => {
+                            error!("Invalid 'scratch_put' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let return_value = match vm.scratch.lock().unwrap().put(name, value) {
+                        Ok(()) => Value::I32(0),
+                        Err(e) => Value::Error(e),
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        let vm = self.clone();
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "scratch_get",
+                "(String name) -> Value",
+                Privilege::Any,
+                "A value from the scratchpad of this dispatch chain, or SlotDoesNotExist.",
+            ),
+            move |mut caller, params, results| {
+                let vm = vm.clone();
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let name = match &arguments[..] {
+                        [Value::String(name)] => name,
+                        _ => {
+                            error!("Invalid 'scratch_get' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let return_value = vm
+                        .scratch
+                        .lock()
+                        .unwrap()
+                        .get(name)
+                        .unwrap_or(Value::Error(SlotDoesNotExist));
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "regex_match",
+                "(String pattern, String subject) -> Vector",
+                Privilege::Any,
+                "The first match of a regular expression in the subject and each of its groups, or an empty Vector; or [BadType, reason] if the pattern isn't valid.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let world = caller.data().world.clone();
+                    let return_value = match world.patterns().regex_match(&arguments) {
+                        Some(value) => value,
+                        None => {
+                            error!("Invalid 'regex_match' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "regex_replace",
+                "(String pattern, String subject, String replacement) -> String",
+                Privilege::Any,
+                "The subject with every match of a regular expression replaced, $1 in the replacement being the first group; or [BadType, reason] if the pattern isn't valid.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let world = caller.data().world.clone();
+                    let return_value = match world.patterns().regex_replace(&arguments) {
+                        Some(value) => value,
+                        None => {
+                            error!("Invalid 'regex_replace' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        bind_builtin(
+            &mut linker,
+            builtins.record(
+                "parse_command",
+                "(String line) -> Vector",
+                Privilege::Any,
+                "A line read as a command by the connection's player: [verb, dobj, prep, iobj], the objects as IdKeys if they're found nearby.",
+            ),
+            |mut caller, params, results| {
+                Box::new(async move {
+                    let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                    let line = match &arguments[..] {
+                        [Value::String(line)] => line,
+                        _ => {
+                            error!("Invalid 'parse_command' arguments");
+                            return Err(Trap::new("Invalid arguments"));
+                        }
+                    };
+                    let tx = current_tx(&caller)?;
+                    let world = caller.data().world.clone();
+                    let connection = caller.data().connection;
+                    let return_value = parse_command(&world, &tx, connection, line).await;
+
+                    let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                    results[0] = Val::I32(stack_end as i32);
+                    results[1] = Val::I32(results_size as i32);
+                    Ok(())
+                })
+            },
+        )?;
+
+        // The string, Vector and number builtins, which compute their results from their arguments
+        // alone.
+        for builtin in STDLIB {
+            let (name, call) = (builtin.name, builtin.call);
+            bind_builtin(
+                &mut linker,
+                builtins.record(name, builtin.signature, Privilege::Any, builtin.description),
+                move |mut caller, params, results| {
+                    Box::new(async move {
+                        let (arguments, stack_end) = unpack_args(&mut caller, params)?;
+                        let return_value = match call(&arguments) {
+                            Some(value) => value,
+                            None => {
+                                error!("Invalid '{}' arguments", name);
+                                return Err(Trap::new("Invalid arguments"));
+                            }
+                        };
+
+                        let results_size = pack_result(&mut caller, stack_end, &return_value)?;
+                        results[0] = Val::I32(stack_end as i32);
+                        results[1] = Val::I32(results_size as i32);
+                        Ok(())
+                    })
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Have the verbs it executes from now on (and those they invoke) executed deterministically,
+    /// or not: with a logical clock in place of the wall clock, randomness seeded from the
+    /// invocation, and the builtins in NONDETERMINISTIC_BUILTINS refused.
+    pub fn set_deterministic(&self, deterministic: bool) {
+        self.deterministic.store(deterministic, Ordering::SeqCst);
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic.load(Ordering::SeqCst)
+    }
+
+    /// The connection it runs verbs for, if any.
+    pub fn connection(&self) -> Option<Oid> {
+        self.connection
+    }
+
+    // A VM for the verb it's executing to invoke another on, as its store is held until that verb
+    // returns: one of the same dispatch chain, sharing its scratchpad, for the same connection.
+    fn nested(&self) -> Result<Arc<WasmVM>, Error> {
+        let mut vm = WasmVM::new(self.world.clone(), self.connection)?;
+        vm.chain = self.chain.clone();
+        vm.scratch = self.scratch.clone();
+        vm.set_deterministic(self.is_deterministic());
+        *vm.invoked_by.get_mut().unwrap() = *self.invoked_by.lock().unwrap();
+        let vm = Arc::new(vm);
+        vm.clone().bind_builtins()?;
+        Ok(vm)
+    }
+
+    /// Have the verbs it executes from now on audited as invoked by `caller`'s, when they're not
+    /// invoked by another verb it's executing.
+    pub fn set_caller(&self, caller: Option<Oid>) {
+        *self.invoked_by.lock().unwrap() = caller;
+    }
+
+    // The object whose verb is executing, innermost in the dispatch chain, if one is.
+    fn innermost(&self) -> Option<Oid> {
+        self.chain.lock().unwrap().last().copied()
+    }
+
+    // Who's invoking the verb about to be executed, for the audit trail: the object whose verb
+    // is executing, if one is, otherwise the one it was made for, or else the player whose
+    // connection it runs verbs for.
+    fn caller(&self) -> Option<Oid> {
+        self.innermost()
+            .or(*self.invoked_by.lock().unwrap())
+            .or_else(|| {
+                self.connection
+                    .and_then(|connection| connection_player(&self.world, connection))
+            })
+    }
+
+    /// Run `method` within the transaction `tr`, within `limits`.
+    /// A verb which exceeds them returns ResourceLimit, and its transaction should be abandoned.
+    pub async fn execute(
+        &self,
+        tr: &Tx,
+        verb: (Oid, &str),
+        method: &Program,
+        args: &Value,
+        limits: ExecutionLimits,
+    ) -> Result<Value, anyhow::Error> {
+        let (result, _) = self
+            .execute_with(tr, verb, method, args, limits, None)
+            .await?;
+        Ok(result)
+    }
+
+    /// Run `method` as `execute` does, but as a dry run: messages aren't sent, and builtins which
+    /// would commit on their own refuse with PermissionDenied. Returns the result along with the
+    /// slot writes and messages it would have made. `tr` should be abandoned afterwards.
+    pub async fn dry_run(
+        &self,
+        tr: &Tx,
+        verb: (Oid, &str),
+        method: &Program,
+        args: &Value,
+        limits: ExecutionLimits,
+    ) -> Result<(Value, DryRun), anyhow::Error> {
+        let (result, dry_run) = self
+            .execute_with(tr, verb, method, args, limits, Some(DryRun::default()))
+            .await?;
+        Ok((result, dry_run.unwrap_or_default()))
+    }
+
+    // Runs `method`, which was found as `verb`, and records what it cost with the world's tracer.
+    #[tracing::instrument(
+        name = "verb",
+        skip_all,
+        fields(oid = %verb.0.id, verb = verb.1, module = field::Empty, dry_run = dry_run.is_some())
+    )]
+    async fn execute_with(
+        &self,
+        tr: &Tx,
+        verb: (Oid, &str),
+        method: &Program,
+        args: &Value,
+        limits: ExecutionLimits,
+        dry_run: Option<DryRun>,
+    ) -> Result<(Value, Option<DryRun>), anyhow::Error> {
+        let caller = self.caller();
+        let _chain = ChainGuard::enter(self, verb.0);
+
+        if method.lang == ProgramLang::Lua {
+            let context = LuaContext {
+                world: self.world.clone(),
+                connection: self.connection,
+                tx: tr.clone(),
+                this: verb.0,
+                caller,
+                seed: self
+                    .is_deterministic()
+                    .then(|| Determinism::seed(verb, args)),
+            };
+            return lua_vm::execute(context, verb.1, method, args, limits, dry_run).await;
+        }
+        if method.lang == ProgramLang::JavaScript {
+            let context = JsContext {
+                world: self.world.clone(),
+                connection: self.connection,
+                tx: tr.clone(),
+                this: verb.0,
+                caller,
+                seed: self
+                    .is_deterministic()
+                    .then(|| Determinism::seed(verb, args)),
+            };
+            return js_vm::execute(context, verb.1, method, args, limits, dry_run).await;
+        }
+
+        // Use the world's cached copy of the compiled Module for this Program, if there is one.
+        let digest = module_cache::digest(method);
+        Span::current().record("module", &digest_prefix(&digest).as_str());
+        let module = self
+            .world
+            .module_cache()
+            .get_digested(digest, method)
+            .await
+            .map_err(|e| anyhow!("Could not compile program: {}", e))?;
+        self.world.module_cache().bind_verb(verb, digest);
+
+        // We'll be holding a lock on the actual 'store' throughout execution.
+        // This defacto enforces single-threaded single file access per connection
+        // But I think this is ok for our purposes.
+        let mut store = self.wasm_store.lock().await;
+
+        let metered = self.world.module_cache().preemption() == Preemption::Fuel;
+        if metered {
+            // Start from a fresh budget, discarding whatever the last invocation left. WebAssembly
+            // execution will be paused for an async yield every time it consumes a slice of it.
+            // (The store can't be drained entirely, so a single unit may carry over.)
+            let leftover = store.consume_fuel(0).unwrap_or(0);
+            if leftover > 1 {
+                store.consume_fuel(leftover - 1)?;
+            }
+            // The first slice takes up any remainder, so that the budget is exact.
+            let first_slice = match limits.fuel % FUEL_SLICE {
+                0 => limits.fuel.min(FUEL_SLICE),
+                remainder => remainder,
+            };
+            store.add_fuel(first_slice)?;
+            store.out_of_fuel_async_yield((limits.fuel - first_slice) / FUEL_SLICE, FUEL_SLICE);
+        }
+        // Execution is also paused at every tick of the engine's epoch; unmetered, it's only
+        // paused then, and is abandoned at the first after its time is up.
+        store.set_epoch_deadline(1);
+        let fuel_before = store.fuel_consumed().unwrap_or(0);
+
+        store.data_mut().limiter = GuestLimiter {
+            memory: limits.memory,
+            exceeded: false,
+        };
+        let policy = WasiPolicy::of(&ObjDBTxHandle::new(tr), verb.0).await;
+        if store.data().wasi_policy != policy {
+            store.data_mut().wasi = policy.context();
+            store.data_mut().wasi_policy = policy;
+        }
+        store.data_mut().tx = Some(tr.clone());
+        store.data_mut().dry_run = dry_run;
+        store.data_mut().determinism = self
+            .is_deterministic()
+            .then(|| Determinism::new(Determinism::seed(verb, args)));
+        store.data_mut().host_calls = 0;
+        store.data_mut().accesses = self
+            .world
+            .records_dependencies()
+            .then(SlotAccesses::default);
+        let started = Instant::now();
+        let mut timed_out = false;
+        #[cfg(feature = "faults")]
+        let injected = self.world.faults().is_some_and(|faults| faults.trap_verb());
+        #[cfg(not(feature = "faults"))]
+        let injected = false;
+        let result = match injected {
+            true => Err(Trap::new("Injected fault").into()),
+            false if metered => self.run_module(store.deref_mut(), &module, args).await,
+            false => {
+                let run = self.run_module(store.deref_mut(), &module, args);
+                match tokio::time::timeout(limits.time, run).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        timed_out = true;
+                        Err(anyhow!("Timed out"))
+                    }
+                }
+            }
+        };
+        store.data_mut().tx = None;
+        store.data_mut().determinism = None;
+        let dry_run = store.data_mut().dry_run.take();
+        if let Some(accesses) = store.data_mut().accesses.take() {
+            DependencyTxHandle::new(tr).record(&digest, verb, &accesses);
+        }
+
+        let fuel_used = store.fuel_consumed().unwrap_or(0) - fuel_before;
+        self.world.tracer().record(&Invocation {
+            oid: verb.0,
+            verb: verb.1.to_string(),
+            module: digest,
+            wall_time: started.elapsed(),
+            fuel: fuel_used,
+            host_calls: store.data().host_calls,
+            failed: result.is_err(),
+        });
+        let result = match result {
+            Err(_) if timed_out => {
+                warn!("Verb exceeded its time limit of {:?}", limits.time);
+                Ok(Value::Error(ResourceLimit))
+            }
+            Err(e) if metered && fuel_used >= limits.fuel => {
+                warn!("Verb exhausted its fuel ({} used): {}", fuel_used, e);
+                Ok(Value::Error(ResourceLimit))
+            }
+            // A guest which couldn't cope with being refused memory.
+            Err(e) if store.data().limiter.exceeded => {
+                warn!("Verb exceeded its memory limit of {}: {}", limits.memory, e);
+                Ok(Value::Error(ResourceLimit))
+            }
+            result => result,
+        };
+        // Dry runs aren't audited, as nothing they do is committed.
+        if dry_run.is_none() {
+            let record = InvocationRecord::new(caller, verb, args, &result, fuel_used);
+            audit_invocation(&self.world, tr, record);
+        }
+        Ok((result?, dry_run))
+    }
+
+    async fn run_module(
+        &self,
+        store: &mut wasmtime::Store<VMState>,
+        module: &wasmtime::Module,
+        args: &Value,
+    ) -> Result<Value, anyhow::Error> {
+        // Use the linker to produce an instance from the module.
+        let instance = {
+            let linker = self.wasm_linker.lock().await;
+            linker.instantiate_async(&mut *store, module).await?
+        };
+
+        // Build the 'stack frame'. Pack args into module's memory.
+        let args_len = pack_args(store, &instance, args)?;
+
+        // Retrieve the linked function from the instance and call it. Its signature was checked
+        // when it was compiled, as one of the two 'invoke' may have.
+        // Invocation argument is the length of the argument buffer in memory.
+        let (args_begin, args_size) =
+            match instance.get_typed_func::<i32, (i32, i32), _>(&mut *store, "invoke") {
+                Ok(verb_func) => verb_func.call_async(&mut *store, args_len as i32).await?,
+                Err(_) => {
+                    let verb_func = instance
+                        .get_typed_func::<i32, i64, _>(&mut *store, "invoke")
+                        .expect("Didn't create typed func");
+                    unpack_location(verb_func.call_async(&mut *store, args_len as i32).await?)
+                }
+            };
 
-        unpack_results(
-            store.deref_mut(),
-            &instance,
-            args_begin as usize,
-            args_size as usize,
-        )
+        unpack_results(store, &instance, args_begin as usize, args_size as usize)
     }
 }
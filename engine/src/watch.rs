@@ -0,0 +1,174 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use fdb::{subspace::Subspace, tuple::Tuple, Key};
+use tokio_stream::StreamExt;
+use tracing::warn;
+
+use crate::database::{DbError, Tx};
+use value::Oid;
+
+/// Connections' interest in changes to slots.
+///
+/// Each watch is recorded both by slot, to find who to notify when it's written, and by
+/// connection, to find what to forget when the connection goes away. Watches are on a slot's
+/// location and name, whatever key it's defined with.
+pub struct WatchTxHandle<'tx_lifetime> {
+    tr: &'tx_lifetime Tx,
+}
+
+/// The slots this server's connections may be watching, so that only writes to those have their
+/// watchers looked up. Unwatching doesn't remove a slot, so it may list more than are watched; a
+/// connection's slots are forgotten when it goes.
+#[derive(Default)]
+pub struct WatchIndex {
+    slots: Mutex<HashMap<(Oid, String), HashSet<Oid>>>,
+}
+
+impl WatchIndex {
+    pub fn watch(&self, connection: Oid, oid: Oid, name: &str) {
+        let mut slots = self.slots.lock().unwrap();
+        slots
+            .entry((oid, name.to_string()))
+            .or_default()
+            .insert(connection);
+    }
+
+    /// Have whoever may be watching `name` on `oid` be counted as watching `to_name` on `to_oid`
+    /// too, as their watches are moved there.
+    pub fn copy(&self, oid: Oid, name: &str, to_oid: Oid, to_name: &str) {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some(connections) = slots.get(&(oid, name.to_string())).cloned() {
+            slots
+                .entry((to_oid, to_name.to_string()))
+                .or_default()
+                .extend(connections);
+        }
+    }
+
+    pub fn may_be_watched(&self, oid: Oid, name: &str) -> bool {
+        let slots = self.slots.lock().unwrap();
+        slots.contains_key(&(oid, name.to_string()))
+    }
+
+    pub fn forget_connection(&self, connection: Oid) {
+        let mut slots = self.slots.lock().unwrap();
+        slots.retain(|_, connections| {
+            connections.remove(&connection);
+            !connections.is_empty()
+        });
+    }
+}
+
+fn watch_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("WATCH".as_bytes()))
+}
+
+fn watching_subspace() -> Subspace {
+    Subspace::new(Bytes::from_static("WATCHING".as_bytes()))
+}
+
+fn slot_tuple(oid: Oid, name: &str) -> Tuple {
+    let mut tup = Tuple::new();
+    tup.add_uuid(oid.id);
+    tup.add_string(name.to_string());
+    tup
+}
+
+fn watch_key(connection: Oid, oid: Oid, name: &str) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_uuid(connection.id);
+    watch_subspace()
+        .subspace(&slot_tuple(oid, name))
+        .subspace(&tup)
+        .pack()
+        .into()
+}
+
+fn watching_key(connection: Oid, oid: Oid, name: &str) -> Key {
+    let mut tup = Tuple::new();
+    tup.add_uuid(connection.id);
+    watching_subspace()
+        .subspace(&tup)
+        .subspace(&slot_tuple(oid, name))
+        .pack()
+        .into()
+}
+
+impl<'tx_lifetime> WatchTxHandle<'tx_lifetime> {
+    pub fn new(tx: &'tx_lifetime Tx) -> Self {
+        WatchTxHandle { tr: tx }
+    }
+
+    pub fn watch(&self, connection: Oid, oid: Oid, name: &str) {
+        self.tr.set(watch_key(connection, oid, name), Bytes::new());
+        self.tr
+            .set(watching_key(connection, oid, name), Bytes::new());
+    }
+
+    pub fn unwatch(&self, connection: Oid, oid: Oid, name: &str) {
+        self.tr.clear(watch_key(connection, oid, name));
+        self.tr.clear(watching_key(connection, oid, name));
+    }
+
+    /// The connections watching the slot `name` on `oid`.
+    pub async fn watchers(&self, oid: Oid, name: &str) -> Result<Vec<Oid>, DbError> {
+        let mut stream = self.tr.get_range(
+            watch_subspace()
+                .subspace(&slot_tuple(oid, name))
+                .range(&Tuple::new()),
+        );
+        let mut watchers = vec![];
+        while let Some(kv) = stream.next().await {
+            let (key, _) = kv?;
+            let key_bytes: Bytes = key.into();
+            let watcher = watch_subspace()
+                .unpack(&key_bytes)
+                .ok()
+                .and_then(|tuple| tuple.get_uuid_ref(2).ok().copied());
+            match watcher {
+                Some(id) => watchers.push(Oid { id }),
+                None => warn!("Skipping malformed watch key {:?}", key_bytes),
+            }
+        }
+        Ok(watchers)
+    }
+
+    /// Have everyone watching the slot `name` on `oid` watch `to_name` on `to_oid` instead.
+    pub async fn move_watchers(
+        &self,
+        oid: Oid,
+        name: &str,
+        to_oid: Oid,
+        to_name: &str,
+    ) -> Result<(), DbError> {
+        for connection in self.watchers(oid, name).await? {
+            self.unwatch(connection, oid, name);
+            self.watch(connection, to_oid, to_name);
+        }
+        Ok(())
+    }
+
+    /// Forget everything `connection` is watching.
+    pub async fn clear_connection(&self, connection: Oid) -> Result<(), DbError> {
+        let mut tup = Tuple::new();
+        tup.add_uuid(connection.id);
+        let watching = watching_subspace().subspace(&tup);
+        let mut stream = self.tr.get_range(watching.range(&Tuple::new()));
+        while let Some(kv) = stream.next().await {
+            let (key, _) = kv?;
+            let key_bytes: Bytes = key.into();
+            let slot = watching.unpack(&key_bytes).ok().and_then(|tuple| {
+                let id = *tuple.get_uuid_ref(0).ok()?;
+                Some((Oid { id }, tuple.get_string_ref(1).ok()?.to_string()))
+            });
+            match slot {
+                Some((oid, name)) => self.tr.clear(watch_key(connection, oid, &name)),
+                None => warn!("Skipping malformed watch key {:?}", key_bytes),
+            }
+        }
+        self.tr.clear_range(watching.range(&Tuple::new()));
+        Ok(())
+    }
+}
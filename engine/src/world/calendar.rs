@@ -0,0 +1,309 @@
+use super::*;
+
+/// How often the calendar is checked for events starting.
+pub(super) const CALENDAR_TICK: Duration = Duration::from_secs(1);
+
+/// How long after an occurrence has ended it may still be announced, late.
+pub(super) const CALENDAR_GRACE: Duration = Duration::from_secs(60);
+
+/// Announce events on the calendar as their occurrences start, by running their verbs with the
+/// event's IdKey and name, and the occurrence's start and end Timestamps. Runs forever.
+///
+/// Each occurrence is claimed, by moving its event on to its next, before it's announced, so it's
+/// announced at most once however many nodes are running. Occurrences which were missed, with the
+/// server down, are announced late if they ended less than CALENDAR_GRACE ago, and otherwise
+/// skipped.
+pub async fn dispatch_calendar(world: Arc<World>) {
+    let vm = match WasmVM::new(world.clone(), None) {
+        Ok(vm) => Arc::new(vm),
+        Err(e) => return error!("Could not create a VM for the calendar: {}", e),
+    };
+    if let Err(e) = vm.clone().bind_builtins() {
+        return error!("Could not create a VM for the calendar: {}", e);
+    }
+    let mut ticks = tokio::time::interval(CALENDAR_TICK);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as i64;
+        let claimed = world
+            .run(|tr| async move {
+                let calendar = CalendarTxHandle::new(&tr);
+                let mut claimed = vec![];
+                for mut event in calendar.due(now).await? {
+                    let start = match event.next {
+                        Some(start) => start,
+                        None => continue,
+                    };
+                    event.next = event.following(start.max(now));
+                    calendar.put(&event).await?;
+                    claimed.push((event, start));
+                }
+                Ok(claimed)
+            })
+            .await;
+        let claimed = match claimed {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                error!("Could not check the calendar: {:?}", e);
+                continue;
+            }
+        };
+        for (event, start) in claimed {
+            let end = start + event.duration();
+            if end < now - CALENDAR_GRACE.as_nanos() as i64 {
+                warn!(
+                    "Skipping {:?}'s occurrence at {}, which has ended",
+                    event.name, start
+                );
+                continue;
+            }
+            let args = [
+                Value::IdKey(Oid { id: event.id }),
+                Value::String(event.name.as_str().into()),
+                Value::Timestamp(start),
+                Value::Timestamp(end),
+            ];
+            let (world, vm) = (world.clone(), vm.clone());
+            // Each separately, so one slow announcement doesn't hold up the rest.
+            tokio::spawn(async move {
+                let result = send_verb_dispatch(&world, vm, event.target, &event.verb, &args).await;
+                if let Err(e) = result {
+                    error!("Could not announce {:?}: {}", event.name, e);
+                }
+            });
+        }
+    }
+}
+
+// A parse failure as verbs see it: BadType, and what was wrong.
+pub(super) fn parse_error(e: Error) -> Value {
+    Value::Vector(vec![
+        Value::Error(BadType),
+        Value::String(e.to_string().into()),
+    ])
+}
+
+/// How times are shown to whoever's on `connection`: in the time zone and locale their player's
+/// 'time_zone' and 'locale' slots name, if they're logged in and have them; otherwise in the world's
+/// time zone, and the locale their client asked for or the world's.
+pub async fn time_settings(world: &Arc<World>, tr: &Tx, connection: Option<Oid>) -> TimeSettings {
+    let (player, client_locale) = {
+        let peer_map = world.peer_map.lock().unwrap();
+        match connection.and_then(|connection| peer_map.get(&connection)) {
+            Some(con_record) => (con_record.player, con_record.locale.clone()),
+            None => (None, None),
+        }
+    };
+    let mut settings = TimeSettings {
+        time_zone: world.options.time_zone.unwrap_or(DEFAULT_TIME_ZONE),
+        locale: client_locale
+            .or_else(|| world.options.default_locale.clone())
+            .unwrap_or_else(|| DEFAULT_LOCALE.to_string()),
+    };
+    if let Some(player) = player {
+        let odb = ObjDBTxHandle::new(tr);
+        if let Ok(Value::String(name)) = odb
+            .get_slot(player, player, String::from("time_zone"))
+            .await
+        {
+            match localtime::parse_time_zone(&name) {
+                Ok(time_zone) => settings.time_zone = time_zone,
+                Err(e) => warn!("{:?} has an invalid 'time_zone': {}", player, e),
+            }
+        }
+        if let Ok(Value::String(locale)) =
+            odb.get_slot(player, player, String::from("locale")).await
+        {
+            settings.locale = locale.into();
+        }
+    }
+    settings
+}
+
+/// The Timestamp `nanos` as text in `style`, as whoever's on `connection` should see it; or
+/// [BadType, reason] if the style isn't one `localtime::format_time` knows.
+pub async fn format_time(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    nanos: i64,
+    style: &str,
+) -> Value {
+    let settings = time_settings(world, tr, connection).await;
+    match localtime::format_time(nanos, style, &settings) {
+        Ok(text) => Value::String(text.into()),
+        Err(e) => parse_error(e),
+    }
+}
+
+/// `text` parsed as a duration, in milliseconds.
+pub fn parse_duration(text: &str) -> Value {
+    match schedule::parse_duration(text) {
+        Ok(duration) => Value::I64(duration.as_millis() as i64),
+        Err(e) => parse_error(e),
+    }
+}
+
+/// `text` parsed as a cron expression: the values each field selects, and when it next fires, as
+/// [name, value] pairs.
+pub fn parse_cron(text: &str) -> Value {
+    let schedule = match CronSchedule::parse(text) {
+        Ok(schedule) => schedule,
+        Err(e) => return parse_error(e),
+    };
+    let mut pairs: Vec<Value> = schedule
+        .fields()
+        .into_iter()
+        .map(|(name, values)| {
+            let values = values.into_iter().map(|v| Value::I32(v as i32)).collect();
+            Value::Vector(vec![Value::String(name.into()), Value::Vector(values)])
+        })
+        .collect();
+    if let Some(next) = schedule.next_after(SystemTime::now()) {
+        let nanos = next
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        pairs.push(Value::Vector(vec![
+            Value::String("next".into()),
+            Value::Timestamp(nanos as i64),
+        ]));
+    }
+    Value::Vector(pairs)
+}
+
+/// Add an event to the calendar, whose occurrences start at `start` and recur by the cron
+/// expression `recurrence` if it's not empty, each announced by running `verb` on `target`. It's
+/// owned by `owner`, if it's added for a player. Returns the event's IdKey; BadType if it ends
+/// before it starts, or [BadType, reason] if the recurrence isn't valid.
+pub async fn calendar_add(
+    tr: &Tx,
+    name: &str,
+    (start, end): (i64, i64),
+    recurrence: &str,
+    (target, verb): (Oid, &str),
+    owner: Option<Oid>,
+) -> Result<Value, Error> {
+    if end < start || verb.is_empty() {
+        return Ok(Value::Error(BadType));
+    }
+    let recurrence = (!recurrence.is_empty()).then(|| recurrence.to_string());
+    let event = match CalendarEvent::new(
+        name.to_string(),
+        start,
+        end,
+        recurrence,
+        target,
+        verb.to_string(),
+        owner,
+    ) {
+        Ok(event) => event,
+        Err(e) => return Ok(parse_error(e)),
+    };
+    CalendarTxHandle::new(tr).put(&event).await?;
+    Ok(Value::IdKey(Oid { id: event.id }))
+}
+
+/// Remove an event from the calendar, for `connection`. SlotDoesNotExist if there's no such
+/// event; PermissionDenied if it may not manage it.
+pub async fn calendar_remove(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    id: Oid,
+) -> Result<Value, Error> {
+    let calendar = CalendarTxHandle::new(tr);
+    let event = match calendar.get(id.id).await? {
+        Some(event) => event,
+        None => return Ok(Value::Error(SlotDoesNotExist)),
+    };
+    if !may_manage(world, tr, connection, event.owner).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    calendar.remove(event.id).await?;
+    Ok(Value::Error(NoError))
+}
+
+/// The events on the calendar `owner` added, soonest first, each as a Vector of [name, value]
+/// pairs. PermissionDenied unless `connection` may manage them.
+pub async fn scheduled_tasks(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    owner: Oid,
+) -> Result<Value, Error> {
+    if !may_manage(world, tr, connection, Some(owner)).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    let events = CalendarTxHandle::new(tr).owned_by(owner).await?;
+    Ok(Value::Vector(
+        events.iter().map(|event| event.info()).collect(),
+    ))
+}
+
+/// Cancel an event on the calendar, for `connection`, as calendar_remove does.
+pub async fn cancel_scheduled(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    id: Oid,
+) -> Result<Value, Error> {
+    calendar_remove(world, tr, connection, id).await
+}
+
+/// Move the next occurrence of an event on the calendar to `start`, for `connection`; a one-off
+/// event keeps its duration. SlotDoesNotExist if there's no such event; PermissionDenied if
+/// `connection` may not manage it.
+pub async fn reschedule(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    id: Oid,
+    start: i64,
+) -> Result<Value, Error> {
+    let calendar = CalendarTxHandle::new(tr);
+    let mut event = match calendar.get(id.id).await? {
+        Some(event) => event,
+        None => return Ok(Value::Error(SlotDoesNotExist)),
+    };
+    if !may_manage(world, tr, connection, event.owner).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    event.reschedule(start);
+    calendar.put(&event).await?;
+    Ok(Value::Error(NoError))
+}
+
+/// The occurrences of events starting within `window` from now, soonest first, as Vectors of the
+/// event's IdKey and name and the occurrence's start and end Timestamps.
+pub async fn upcoming_events(tr: &Tx, window: Duration) -> Result<Value, Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let until = (now + window).as_nanos() as i64;
+    let mut occurrences = vec![];
+    for event in CalendarTxHandle::new(tr).due(until).await? {
+        for start in event.occurrences(until) {
+            occurrences.push((start, event.clone()));
+        }
+    }
+    occurrences.sort_by_key(|(start, _)| *start);
+    occurrences.truncate(MAX_OCCURRENCES);
+    let occurrences = occurrences
+        .into_iter()
+        .map(|(start, event)| {
+            let end = start + event.duration();
+            Value::Vector(vec![
+                Value::IdKey(Oid { id: event.id }),
+                Value::String(event.name.into()),
+                Value::Timestamp(start),
+                Value::Timestamp(end),
+            ])
+        })
+        .collect();
+    Ok(Value::Vector(occurrences))
+}
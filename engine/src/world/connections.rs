@@ -0,0 +1,1276 @@
+use super::*;
+
+/// A queue for messages to a new connection, kept to the world's outbound policy: the sender to
+/// register the connection with, and the receiver to write what's sent to it from.
+pub fn outbound_queue(world: &Arc<World>) -> (OutboundSender, OutboundReceiver) {
+    outbound(world.options.outbound)
+}
+
+pub async fn register_connection(
+    world: Arc<World>,
+    sender: OutboundSender,
+    address: SocketAddr,
+    locale: Option<String>,
+    structured: bool,
+    listener: &str,
+) -> Result<Oid, Error> {
+    let new_oid = Oid { id: Uuid::new_v4() };
+    let vm = Arc::new(WasmVM::new(world.clone(), Some(new_oid)).unwrap());
+    vm.clone().bind_builtins()?;
+    let tasks = world
+        .options
+        .tasks_per_connection
+        .unwrap_or(DEFAULT_TASKS_PER_CONNECTION);
+    world.peer_map.lock().unwrap().insert(
+        new_oid,
+        Connection {
+            address,
+            listener: listener.to_string(),
+            entry: world.listeners.entry(listener),
+            sender,
+            vm: vm.clone(),
+            pending_login: None,
+            player: None,
+            logged_in_at: None,
+            locale,
+            connected_at: SystemTime::now(),
+            last_activity: SystemTime::now(),
+            last_heard: Instant::now(),
+            traffic: Default::default(),
+            inbound: Default::default(),
+            outbound: Default::default(),
+            flood_messages: Default::default(),
+            flood_bytes: Default::default(),
+            flood_strikes: 0,
+            tasks: Arc::new(Semaphore::new(tasks.max(1))),
+            idle_vms: vec![vm],
+            structured,
+            forms: Default::default(),
+            federated: false,
+            detached: None,
+            resume_nonce: None,
+        },
+    );
+    Ok(new_oid)
+}
+
+/// Mark `connection` as another world's, which presented the federation token.
+pub fn mark_federated(world: &Arc<World>, connection: Oid) {
+    if let Some(con_record) = world.peer_map.lock().unwrap().get_mut(&connection) {
+        con_record.federated = true;
+    }
+}
+
+/// A token `connection`'s client can give to resume it should it drop, if connections may be
+/// resumed. Only the last one issued is good, and only once.
+pub fn resume_token(world: &Arc<World>, connection: Oid) -> Option<String> {
+    world.options.resume?;
+    let nonce = Uuid::new_v4();
+    world
+        .peer_map
+        .lock()
+        .unwrap()
+        .get_mut(&connection)?
+        .resume_nonce = Some(nonce);
+    Some(world.resume_key.token(connection, nonce))
+}
+
+/// Keep `connection`, whose client has gone, for the client to resume, buffering what's sent to
+/// it meanwhile. `sender` is the one the client was sent messages with; if the connection's since
+/// been resumed with another, it's left alone. False if connections may not be resumed, or this
+/// one was closed by the server (booted, or idle too long) rather than dropped, in which case it
+/// should be disconnected.
+pub fn detach(world: &Arc<World>, connection: Oid, sender: &OutboundSender) -> bool {
+    if world.options.resume.is_none() {
+        return false;
+    }
+    let mut peer_map = world.peer_map.lock().unwrap();
+    let con_record = match peer_map.get_mut(&connection) {
+        Some(con_record) => con_record,
+        None => return true,
+    };
+    if !con_record.sender.same_queue(sender) {
+        return true;
+    }
+    if con_record.sender.is_closed() {
+        return false;
+    }
+    let (buffer_sender, buffer) = outbound_queue(world);
+    con_record.sender = buffer_sender;
+    con_record.detached = Some(Detached {
+        since: Instant::now(),
+        buffer,
+    });
+    info!("{:?} detached, awaiting its client", connection);
+    true
+}
+
+/// Resume the connection `token` is for with the client at `address`, sending it what was
+/// buffered for it and then everything else with `sender`. The token is used up. None if the token
+/// isn't a good one or has been used, the connection has gone or still has its client, or it
+/// doesn't speak the protocol (structured or raw) the client does.
+pub fn resume(
+    world: &Arc<World>,
+    token: &str,
+    sender: OutboundSender,
+    address: SocketAddr,
+    structured: bool,
+) -> Option<Oid> {
+    world.options.resume?;
+    let (connection, nonce) = match world.resume_key.verify(token) {
+        Some(verified) => verified,
+        None => {
+            warn!(target: "security", "Bad resume token from {}", address);
+            return None;
+        }
+    };
+    let mut peer_map = world.peer_map.lock().unwrap();
+    let con_record = peer_map.get_mut(&connection)?;
+    if con_record.resume_nonce != Some(nonce) {
+        warn!(
+            target: "security",
+            "Used or superseded resume token for {:?} from {}", connection, address
+        );
+        return None;
+    }
+    if con_record.detached.is_none() {
+        warn!(
+            target: "security",
+            "Refused to resume {:?}, which still has its client, from {}", connection, address
+        );
+        return None;
+    }
+    // (Those closed while detached, by being booted, are only waiting to expire.)
+    if con_record.structured != structured || con_record.sender.is_closed() {
+        return None;
+    }
+    con_record.resume_nonce = None;
+    con_record.sender = sender;
+    if let Some(mut detached) = con_record.detached.take() {
+        while let Some(message) = detached.buffer.try_recv() {
+            con_record.sender.send(message);
+        }
+    }
+    info!(
+        "{:?} resumed from {} (was {})",
+        connection, address, con_record.address
+    );
+    con_record.address = address;
+    con_record.last_heard = Instant::now();
+    con_record.last_activity = SystemTime::now();
+    Some(connection)
+}
+
+/// Disconnect connections which have been detached for longer than the grace period without
+/// their clients resuming them.
+pub async fn expire_detached(world: Arc<World>) {
+    let grace = match world.options.resume {
+        Some(options) => options.grace,
+        None => return,
+    };
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let expired: Vec<_> = world
+            .peer_map
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, con_record)| {
+                con_record
+                    .detached
+                    .as_ref()
+                    .is_some_and(|detached| detached.since.elapsed() > grace)
+            })
+            .map(|(connection, _)| *connection)
+            .collect();
+        for connection in expired {
+            info!("{:?} wasn't resumed in time", connection);
+            if let Err(e) = disconnect(world.clone(), connection).await {
+                error!("Could not disconnect {:?}: {}", connection, e);
+            }
+        }
+    }
+}
+
+pub async fn disconnect(world: Arc<World>, oid: Oid) -> Result<(), Error> {
+    world.tasks.kill_connection(oid);
+    world.watches.forget_connection(oid);
+    if let Some(impersonation) = world.impersonations.end(oid) {
+        audit_impersonation(&world, &impersonation, AuditEvent::Ended).await?;
+    }
+    let session = world
+        .peer_map
+        .lock()
+        .unwrap()
+        .remove(&oid)
+        .and_then(|con_record| con_record.player.zip(con_record.logged_in_at));
+    let now = SystemTime::now();
+    world
+        .run(|tr| async move {
+            tr.clear(FdbOid(oid));
+            SessionTxHandle::new(&tr).end(oid);
+            if let Some((player, logged_in_at)) = session {
+                PlayerStatsTxHandle::new(&tr).session_ended(player, logged_in_at.elapsed(), now);
+            }
+            WatchTxHandle::new(&tr).clear_connection(oid).await
+        })
+        .await?;
+    Ok(())
+}
+
+// Count `bytes` received from (if `inbound`) or sent to `connection`, returning how long it must
+// be held back to stay within its cap, or None if it's been closed, perhaps for going over it.
+pub(super) fn meter_traffic(
+    world: &Arc<World>,
+    connection: Oid,
+    bytes: usize,
+    inbound: bool,
+) -> Option<Duration> {
+    let policy = &world.options.bandwidth;
+    let mut peer_map = world.peer_map.lock().unwrap();
+    let con_record = peer_map.get_mut(&connection)?;
+    if con_record.sender.is_closed() {
+        return None;
+    }
+
+    if inbound {
+        con_record.last_heard = Instant::now();
+    }
+    let bytes_moved = bytes as u64;
+    let (traffic, meter, cap) = match inbound {
+        true => (
+            Traffic {
+                bytes_in: bytes_moved,
+                bytes_out: 0,
+            },
+            &mut con_record.inbound,
+            policy.inbound,
+        ),
+        false => (
+            Traffic {
+                bytes_in: 0,
+                bytes_out: bytes_moved,
+            },
+            &mut con_record.outbound,
+            policy.outbound,
+        ),
+    };
+    let wait = match cap {
+        Some(rate) => meter.take(bytes, rate, Instant::now()),
+        None => Duration::ZERO,
+    };
+    // What verbs send is metered, but not held back, as that would hold their transactions open;
+    // the connection's next message waits for it instead, before any transaction is begun.
+    let wait = match (inbound, policy.outbound) {
+        (true, Some(rate)) => wait.max(con_record.outbound.debt(rate, Instant::now())),
+        _ => wait,
+    };
+    con_record.traffic.add(traffic);
+    world.traffic.lock().unwrap().add(traffic);
+    if let Some(player) = con_record.player {
+        let mut player_traffic = world.player_traffic.lock().unwrap();
+        player_traffic.entry(player).or_default().add(traffic);
+    }
+
+    if wait.is_zero() || policy.action == BandwidthAction::Throttle {
+        return Some(wait);
+    }
+    warn!(target: "security", "Disconnecting {:?} from {}, over its {} bandwidth cap", connection, con_record.address, if inbound { "inbound" } else { "outbound" });
+    let close = CloseFrame {
+        code: CloseCode::Policy,
+        reason: "Bandwidth cap exceeded".into(),
+    };
+    con_record.sender.close_with(Message::Close(Some(close)));
+    None
+}
+
+/// Account for `bytes` received from `connection`, holding it up for as long as it's over its
+/// inbound bandwidth cap, or its outbound cap for what verbs have sent it. False if it's been closed instead, in which case what was received
+/// should be dropped.
+pub async fn record_received(world: &Arc<World>, connection: Oid, bytes: usize) -> bool {
+    match meter_traffic(world, connection, bytes, true) {
+        Some(wait) => {
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// The verb on the system object run when a connection first has a message refused for flooding.
+pub const FLOOD_VERB: &str = "on_flood";
+
+pub(super) enum FloodVerdict {
+    Admitted,
+    Refused {
+        strikes: u32,
+        player: Option<Oid>,
+        vm: Arc<WasmVM>,
+    },
+    Closed,
+}
+
+// Counts a message of `bytes` from `connection` against the world's flood limits, closing the
+// connection if it's had too many refused in a row.
+pub(super) fn check_flood(world: &Arc<World>, connection: Oid, bytes: usize) -> FloodVerdict {
+    let policy = &world.options.flood;
+    let mut peer_map = world.peer_map.lock().unwrap();
+    let con_record = match peer_map.get_mut(&connection) {
+        Some(con_record) => con_record,
+        None => return FloodVerdict::Closed,
+    };
+    let now = Instant::now();
+    let allowed = policy
+        .messages_per_sec
+        .map_or(true, |rate| con_record.flood_messages.allows(1, rate, now))
+        && policy
+            .bytes_per_sec
+            .map_or(true, |rate| con_record.flood_bytes.allows(bytes, rate, now));
+    if allowed {
+        if let Some(rate) = policy.messages_per_sec {
+            con_record.flood_messages.take(1, rate, now);
+        }
+        if let Some(rate) = policy.bytes_per_sec {
+            con_record.flood_bytes.take(bytes, rate, now);
+        }
+        con_record.flood_strikes = 0;
+        return FloodVerdict::Admitted;
+    }
+
+    con_record.flood_strikes += 1;
+    if con_record.flood_strikes <= policy.max_strikes {
+        return FloodVerdict::Refused {
+            strikes: con_record.flood_strikes,
+            player: con_record.player,
+            vm: con_record.vm.clone(),
+        };
+    }
+    warn!(target: "security", "Disconnecting {:?} from {}, which kept flooding", connection, con_record.address);
+    let close = CloseFrame {
+        code: CloseCode::Policy,
+        reason: "Too many messages".into(),
+    };
+    con_record.sender.close_with(Message::Close(Some(close)));
+    FloodVerdict::Closed
+}
+
+/// Whether a message of `bytes` from `connection` is within the world's flood limits, and should
+/// be handled. If not it's to be dropped; the first of a run of such messages runs the system
+/// object's 'on_flood' verb, if it has one, with the connection and the player it's logged in to
+/// (if it is) as arguments, and a connection which keeps on flooding is closed.
+pub async fn admit_message(
+    world: &Arc<World>,
+    connection: Oid,
+    bytes: usize,
+) -> Result<bool, Error> {
+    match check_flood(world, connection, bytes) {
+        FloodVerdict::Admitted => Ok(true),
+        FloodVerdict::Refused {
+            strikes: 1,
+            player,
+            vm,
+        } => {
+            info!("Refusing messages from {:?}, which is flooding", connection);
+            let mut args = vec![Value::IdKey(connection)];
+            args.extend(player.map(Value::IdKey));
+            run_system_verb_if_defined(world, vm, FLOOD_VERB, &args).await?;
+            Ok(false)
+        }
+        FloodVerdict::Refused { .. } | FloodVerdict::Closed => Ok(false),
+    }
+}
+
+/// What's known about `connection`, as a Vector of [name, value] pairs: its "address", the
+/// "player" it acts as (if any, as `connection_player` has it), the "bytes_in" and "bytes_out" it's moved, when it
+/// "connected_at", its "last_activity" (its last message or request) and how long it's been
+/// "idle_ms" since. SlotDoesNotExist if there's no such connection.
+pub fn connection_info(world: &Arc<World>, connection: Oid) -> Value {
+    let impersonated = connection_player(world, connection);
+    let peer_map = world.peer_map.lock().unwrap();
+    let con_record = match peer_map.get(&connection) {
+        Some(con_record) => con_record,
+        None => return Value::Error(SlotDoesNotExist),
+    };
+    let field = |name: &str, value| Value::Vector(vec![Value::String(name.into()), value]);
+    let mut info = vec![
+        field(
+            "address",
+            Value::String(con_record.address.to_string().into()),
+        ),
+        field(
+            "listener",
+            Value::String(con_record.listener.as_str().into()),
+        ),
+    ];
+    if let Some(player) = impersonated {
+        info.push(field("player", Value::IdKey(player)));
+    }
+    if con_record.federated {
+        info.push(field("federated", Value::I32(1)));
+    }
+    info.push(field(
+        "bytes_in",
+        Value::I64(con_record.traffic.bytes_in as i64),
+    ));
+    info.push(field(
+        "bytes_out",
+        Value::I64(con_record.traffic.bytes_out as i64),
+    ));
+    let timestamp = |time: SystemTime| {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Value::Timestamp(since_epoch.as_nanos() as i64)
+    };
+    info.push(field("connected_at", timestamp(con_record.connected_at)));
+    info.push(field("last_activity", timestamp(con_record.last_activity)));
+    let idle = con_record.last_activity.elapsed().unwrap_or_default();
+    info.push(field("idle_ms", Value::I64(idle.as_millis() as i64)));
+    Value::Vector(info)
+}
+
+/// A connection to this server, as the admin endpoint lists it.
+#[derive(Serialize, Clone, Debug)]
+pub struct ConnectionSummary {
+    pub id: Oid,
+    pub address: String,
+    pub listener: String,
+    pub player: Option<Oid>,
+    pub structured: bool,
+    /// Whether it's another world's.
+    pub federated: bool,
+    /// Whether its client has gone, and it's awaiting being resumed.
+    pub detached: bool,
+    /// Messages queued for it which haven't gone out yet.
+    pub queued: usize,
+    /// When it connected, in seconds since the Unix epoch.
+    pub connected_at: i64,
+    pub idle_secs: i64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Messages being handled for it now.
+    pub tasks: usize,
+}
+
+/// Every connection to this server, the longest connected first.
+pub fn connection_summaries(world: &Arc<World>) -> Vec<ConnectionSummary> {
+    let tasks = world.tasks.list();
+    let peer_map = world.peer_map.lock().unwrap();
+    let mut summaries: Vec<_> = peer_map
+        .iter()
+        .map(|(id, con_record)| ConnectionSummary {
+            id: *id,
+            address: con_record.address.to_string(),
+            listener: con_record.listener.clone(),
+            player: con_record.player,
+            structured: con_record.structured,
+            federated: con_record.federated,
+            detached: con_record.detached.is_some(),
+            queued: con_record.sender.len(),
+            connected_at: unix_secs(con_record.connected_at),
+            idle_secs: con_record
+                .last_activity
+                .elapsed()
+                .unwrap_or_default()
+                .as_secs() as i64,
+            bytes_in: con_record.traffic.bytes_in,
+            bytes_out: con_record.traffic.bytes_out,
+            tasks: tasks.iter().filter(|task| task.connection == *id).count(),
+        })
+        .collect();
+    summaries.sort_by_key(|summary| summary.connected_at);
+    summaries
+}
+
+/// Close `connection`, telling its client why. It's disconnected as its stream ends. False if
+/// there's no such connection.
+pub fn boot(world: &Arc<World>, connection: Oid, reason: &str) -> bool {
+    let peer_map = world.peer_map.lock().unwrap();
+    let con_record = match peer_map.get(&connection) {
+        Some(con_record) => con_record,
+        None => return false,
+    };
+    warn!(target: "security", "Booting {:?} from {}: {}", connection, con_record.address, reason);
+    let close = CloseFrame {
+        code: CloseCode::Policy,
+        reason: reason.to_string().into(),
+    };
+    con_record.sender.close_with(Message::Close(Some(close)));
+    true
+}
+
+/// Handle `connection`'s next message, or request, as a task of its own (running `verb`), so that
+/// the messages after it needn't wait for it to finish. Waits while the connection has as many
+/// being handled as it may. The task can be listed and killed until it's done.
+pub async fn spawn_task(
+    world: &Arc<World>,
+    connection: Oid,
+    verb: &str,
+    dispatch: impl Future<Output = Result<(), Error>> + Send + 'static,
+) {
+    let (slots, player) = match world.peer_map.lock().unwrap().get(&connection) {
+        Some(con_record) => (con_record.tasks.clone(), con_record.player),
+        None => return,
+    };
+    let permit = match slots.acquire_owned().await {
+        Ok(permit) => permit,
+        Err(_) => return,
+    };
+    let info = TaskInfo {
+        id: Oid { id: Uuid::new_v4() },
+        connection,
+        player,
+        verb: verb.to_string(),
+        started: SystemTime::now(),
+    };
+    let task_world = world.clone();
+    // Within the connection's span, as it's made where the message was received.
+    let span = info_span!("message", task = %info.id.id, verb = %info.verb);
+    world.tasks.spawn(info, move |info| {
+        let id = info.id;
+        tokio::spawn(
+            async move {
+                if let Err(e) = dispatch.await {
+                    error!("Task {:?} for {:?} failed: {}", id, connection, e);
+                }
+                task_world.tasks.forget(id);
+                drop(permit);
+            }
+            .instrument(span),
+        )
+    });
+}
+
+// Whether a verb run for `connection` may see and kill `task`: one handling a message from the
+// same connection or player, or any task if it's run by the server itself or for an admin.
+pub(super) async fn may_manage_task(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    task: &TaskInfo,
+) -> bool {
+    match connection {
+        Some(connection) if task.connection == connection => true,
+        _ => may_manage(world, tr, connection, task.player).await,
+    }
+}
+
+/// The tasks `connection` may manage, longest running first, each as a Vector of [name, value]
+/// pairs.
+pub async fn task_list(world: &Arc<World>, tr: &Tx, connection: Option<Oid>) -> Value {
+    let mut tasks = vec![];
+    for task in world.tasks.list() {
+        if may_manage_task(world, tr, connection, &task).await {
+            tasks.push(task.value());
+        }
+    }
+    Value::Vector(tasks)
+}
+
+/// Kill a task for `connection`. SlotDoesNotExist if it's finished already; PermissionDenied if
+/// it's another player's and `connection` isn't an admin's.
+pub async fn kill_task(world: &Arc<World>, tr: &Tx, connection: Option<Oid>, id: Oid) -> Value {
+    let task = match world.tasks.get(id) {
+        Some(task) => task,
+        None => return Value::Error(SlotDoesNotExist),
+    };
+    if !may_manage_task(world, tr, connection, &task).await {
+        return Value::Error(PermissionDenied);
+    }
+    match world.tasks.kill(id) {
+        true => Value::Error(NoError),
+        false => Value::Error(SlotDoesNotExist),
+    }
+}
+
+// A VM to handle a message from `connection` with: one left idle by an earlier message, or a new
+// one. (There are never more in use than the connection may have tasks.)
+pub(super) fn checkout_vm(world: &Arc<World>, connection: Oid) -> Result<Arc<WasmVM>, Error> {
+    let idle = match world.peer_map.lock().unwrap().get_mut(&connection) {
+        Some(con_record) => con_record.idle_vms.pop(),
+        None => return Err(anyhow!("No connection {:?}", connection)),
+    };
+    match idle {
+        Some(vm) => Ok(vm),
+        None => {
+            let vm = Arc::new(WasmVM::new(world.clone(), Some(connection))?);
+            vm.clone().bind_builtins()?;
+            Ok(vm)
+        }
+    }
+}
+
+// Leave `vm` for the connection's next message. (A VM whose task was killed isn't returned, as it
+// was dropped with it.)
+pub(super) fn checkin_vm(world: &Arc<World>, connection: Oid, vm: Arc<WasmVM>) {
+    if let Some(con_record) = world.peer_map.lock().unwrap().get_mut(&connection) {
+        con_record.idle_vms.push(vm);
+    }
+}
+
+/// Handle what a websocket connection sent: from one speaking the structured protocol, a request
+/// or the answers to a form; otherwise a message for 'receive'. `rpc` says which it speaks.
+pub async fn handle_message(
+    conn_oid: Oid,
+    msg: tungstenite::Result<Message>,
+    world: Arc<World>,
+    rpc: bool,
+) {
+    match msg {
+        Ok(m) => {
+            if !record_received(&world, conn_oid, m.len()).await {
+                return;
+            }
+            if m.is_text() || m.is_binary() {
+                // A message which can't be checked isn't let through unchecked.
+                match admit_message(&world, conn_oid, m.len()).await {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(e) => {
+                        error!(
+                            "Could not check {:?} for flooding, dropping its message: {}",
+                            conn_oid, e
+                        );
+                        return;
+                    }
+                }
+            }
+            if rpc && m.is_binary() {
+                // Structured protocol; decode the request and dispatch it to the verb it names, or
+                // the answers to a form and give them to the verb awaiting them.
+                match Inbound::decode(Bytes::from(m.into_data())) {
+                    Ok(Inbound::Form(answers)) => {
+                        let task_world = world.clone();
+                        let dispatch = async move {
+                            receive_form_answers(&task_world, conn_oid, answers).await
+                        };
+                        spawn_task(&world, conn_oid, "form", dispatch).await;
+                    }
+                    Ok(Inbound::Request(request)) => {
+                        let verb = request.verb.clone();
+                        let task_world = world.clone();
+                        let dispatch = async move {
+                            receive_connection_request(&task_world, conn_oid, request).await
+                        };
+                        spawn_task(&world, conn_oid, &verb, dispatch).await;
+                    }
+                    Err(e) => error!("Invalid request from {:?}: {:?}", conn_oid, e),
+                }
+            } else if m.is_text() || m.is_binary() {
+                // Consume message and pass off to receive..
+                let message = Bytes::from(m.into_data());
+
+                let task_world = world.clone();
+                let dispatch =
+                    async move { receive_connection_message(&task_world, conn_oid, message).await };
+                spawn_task(&world, conn_oid, "receive", dispatch).await;
+            }
+        }
+        Err(e) => match e {
+            tungstenite::Error::Protocol(_) | tungstenite::Error::ConnectionClosed => {
+                error!("Closed, deleting {:?}", conn_oid);
+                if let Err(e) = disconnect(world, conn_oid).await {
+                    error!("Unable to destroy connection object {:?}: {}", conn_oid, e);
+                }
+            }
+            _ => {}
+        },
+    }
+}
+
+pub async fn receive_connection_message(
+    world: &Arc<World>,
+    connection: Oid,
+    message: Bytes,
+) -> Result<(), Error> {
+    count_command(world, connection).await?;
+    audit_command(world, connection, || {
+        String::from_utf8_lossy(&message).into_owned()
+    })
+    .await?;
+    let vm = checkout_vm(world, connection)?;
+    let result = receive_with(world, &vm, connection, message).await;
+    checkin_vm(world, connection, vm);
+    result
+}
+
+pub(super) async fn receive_with(
+    world: &Arc<World>,
+    vm: &Arc<WasmVM>,
+    connection: Oid,
+    message: Bytes,
+) -> Result<(), Error> {
+    // Lines from logged in players are read as commands first, if the world has a parser, and
+    // are only given to 'receive' if there's no verb for them.
+    if let Some(grammar) = &world.options.command_parser {
+        let player = connection_player(world, connection);
+        if let (Some(player), Ok(line)) = (player, std::str::from_utf8(&message)) {
+            let result = dispatch_command(world, vm.clone(), grammar, connection, player, line);
+            if let Some(result) = result.await? {
+                if world.options.echo_results {
+                    if let Some(message) = result_message(&for_client(result)) {
+                        send_connection_message(world.clone(), connection, message).await?;
+                    }
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    // The entry point of the listener the connection came in on: the system object's 'receive'
+    // unless it's configured otherwise.
+    let entry = match world.peer_map.lock().unwrap().get(&connection) {
+        Some(con_record) => con_record.entry.clone(),
+        None => EntryPoint::default(),
+    };
+    let m = &message.clone();
+    let entry = &entry;
+    let result = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            let (object, verb) = (entry.object, entry.verb.as_str());
+            match odb.get_slot(object, object, String::from(verb)).await {
+                Ok(sv) => {
+                    // Invoke the entry point's program with connection obj and message as
+                    // arguments.
+                    let message_val =
+                        Value::Vector(vec![Value::IdKey(connection), Value::Binary(m.clone())]);
+
+                    match sv {
+                        Value::Program(p) => {
+                            let limits = execution_limits(world, &odb, object).await;
+                            let result = vm
+                                .execute(&tr, (object, verb), &p, &message_val, limits)
+                                .await;
+                            return commit_unless_failed(result).map(Some);
+                        }
+                        _ => {
+                            error!(
+                                "'{}' not a Program: {:?}",
+                                verb,
+                                world.redaction().slot(verb, &message_val)
+                            )
+                        }
+                    }
+                }
+
+                Err(r) => {
+                    error!("Entry point {:?} '{}' not found: {:?}", object, verb, r)
+                }
+            };
+            Ok(None)
+        })
+        .await;
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            match e.abandoned_for() {
+                Some(reason) => warn!(
+                    "'{}' from {:?} aborted: {:?}",
+                    entry.verb, connection, reason
+                ),
+                None => error!("'{}' from {:?} failed: {}", entry.verb, connection, e),
+            }
+            None
+        }
+    };
+
+    // Deliver the verb's result back to the peer, but only once the transaction has committed.
+    if world.options.echo_results {
+        if let Some(message) = result.as_ref().and_then(result_message) {
+            send_connection_message(world.clone(), connection, message).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Dispatch a structured protocol request from a connection to the verb it names, and reply to the
+/// connection with the result.
+/// The verb is invoked with the connection's Oid followed by the request's arguments. Dry run
+/// requests are answered with the report from `dry_run_dispatch`, and deterministic ones are
+/// executed as `WasmVM::set_deterministic` describes.
+pub async fn receive_connection_request(
+    world: &Arc<World>,
+    connection: Oid,
+    request: Request,
+) -> Result<(), Error> {
+    count_command(world, connection).await?;
+    audit_command(world, connection, || {
+        let args = Value::Vector(request.args.clone());
+        let args = world.redaction().slot(&request.verb, &args);
+        format!("{:?}:{} {:?}", request.target, request.verb, args)
+    })
+    .await?;
+    let locale = {
+        let peer_map = world.peer_map.lock().unwrap();
+        let con_record = &peer_map.get(&connection).unwrap();
+        con_record.locale.clone()
+    };
+
+    let mut arguments = vec![Value::IdKey(connection)];
+    arguments.extend(request.args);
+    let vm = checkout_vm(world, connection)?;
+    vm.set_deterministic(request.deterministic);
+    let result = match request.dry_run {
+        true => {
+            dry_run_dispatch(world, vm.clone(), request.target, &request.verb, &arguments).await
+        }
+        false => {
+            send_verb_dispatch(world, vm.clone(), request.target, &request.verb, &arguments).await
+        }
+    };
+    vm.set_deterministic(false);
+    checkin_vm(world, connection, vm);
+    let result = result?;
+
+    // How a verb failed is kept to the log and the audit trail.
+    let mut response = Response {
+        request_id: request.request_id,
+        result: for_client(result),
+        message: None,
+    };
+    if let Some(code) = response.error_code() {
+        let locale = locale
+            .as_deref()
+            .or(world.options.default_locale.as_deref());
+        let text = world.catalog.lookup(locale, code.catalog_key());
+        response.message = text.map(String::from);
+    }
+    send_connection_message(
+        world.clone(),
+        connection,
+        Message::Binary(response.encode()),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Send `connection` a form to fill in, whose answers are to be given to `verb` on `target`, with
+/// the connection, the form's IdKey and the answers as arguments. Returns the form's IdKey;
+/// BadType if `fields` isn't a form (see `FormDefinition`); PermissionDenied if the connection
+/// doesn't speak the structured protocol (or there's no such connection); ResourceLimit if it has
+/// too many forms unanswered.
+pub async fn send_form(
+    world: &Arc<World>,
+    connection: Oid,
+    fields: &Value,
+    target: Oid,
+    verb: &str,
+) -> Result<Value, Error> {
+    let definition = match FormDefinition::parse(fields) {
+        Ok(definition) => definition,
+        Err(e) => {
+            warn!("Invalid form: {}", e);
+            return Ok(Value::Error(BadType));
+        }
+    };
+    let form = Oid { id: Uuid::new_v4() };
+    {
+        let mut peer_map = world.peer_map.lock().unwrap();
+        let con_record = match peer_map.get_mut(&connection) {
+            Some(con_record) if con_record.structured => con_record,
+            _ => return Ok(Value::Error(PermissionDenied)),
+        };
+        if con_record.forms.len() >= MAX_PENDING_FORMS {
+            return Ok(Value::Error(ResourceLimit));
+        }
+        con_record.forms.insert(
+            form,
+            PendingForm {
+                definition,
+                target,
+                verb: verb.to_string(),
+            },
+        );
+    }
+    let message = Message::Binary(encode_form(form, fields));
+    send_connection_message(world.clone(), connection, message).await?;
+    Ok(Value::IdKey(form))
+}
+
+/// Take `connection`'s answers to a form it was sent, checking them against the form. Answers
+/// which fit are given to the form's verb, and the form is done with; otherwise the connection is
+/// told what was wrong, and may answer again.
+pub async fn receive_form_answers(
+    world: &Arc<World>,
+    connection: Oid,
+    answers: FormAnswers,
+) -> Result<(), Error> {
+    count_command(world, connection).await?;
+    let pending = world
+        .peer_map
+        .lock()
+        .unwrap()
+        .get(&connection)
+        .and_then(|con_record| con_record.forms.get(&answers.form).cloned());
+    let pending = match pending {
+        Some(pending) => pending,
+        None => {
+            warn!(
+                "Answers from {:?} to unknown form {:?}",
+                connection, answers.form
+            );
+            return Ok(());
+        }
+    };
+    let checked = match pending
+        .definition
+        .validate(&answers.answers, world.patterns())
+    {
+        Ok(checked) => checked,
+        Err(problems) => {
+            let message = Message::Binary(encode_form_problems(answers.form, problems));
+            send_connection_message(world.clone(), connection, message).await?;
+            return Ok(());
+        }
+    };
+    if let Some(con_record) = world.peer_map.lock().unwrap().get_mut(&connection) {
+        con_record.forms.remove(&answers.form);
+    }
+
+    let arguments = [
+        Value::IdKey(connection),
+        Value::IdKey(answers.form),
+        checked,
+    ];
+    let vm = checkout_vm(world, connection)?;
+    let result =
+        send_verb_dispatch(world, vm.clone(), pending.target, &pending.verb, &arguments).await;
+    checkin_vm(world, connection, vm);
+    result.map(|_| ())
+}
+
+/// Produce a message to send to a connection from a verb's return value, if it is of a type which
+/// can be sent. Strings go out as text, Vectors are framed as binary.
+pub(super) fn result_message(result: &Value) -> Option<Message> {
+    match result {
+        Value::String(s) => Some(Message::Text(s.to_string())),
+        Value::Binary(b) => Some(Message::Binary(b.to_vec())),
+        Value::Vector(_) => Some(Message::Binary(encode_frame(result))),
+        _ => None,
+    }
+}
+
+/// Send `message` to `conoid`, saying what became of it: whether it was queued, or refused as
+/// the connection's queue is full, or the connection's gone.
+pub async fn send_connection_message(
+    world: Arc<World>,
+    conoid: Oid,
+    message: Message,
+) -> Result<Delivery, Error> {
+    send_message(world, None, conoid, message).await
+}
+
+/// As `send_connection_message`, for a verb sending `message`: it's journaled in `tr`, the verb's
+/// transaction, rather than in a transaction of its own. (So if the verb is abandoned, the message
+/// has been sent but isn't on record.)
+pub async fn send_connection_message_in(
+    world: Arc<World>,
+    tr: &Tx,
+    conoid: Oid,
+    message: Message,
+) -> Result<Delivery, Error> {
+    send_message(world, Some(tr), conoid, message).await
+}
+
+pub(super) async fn send_message(
+    world: Arc<World>,
+    tr: Option<&Tx>,
+    conoid: Oid,
+    message: Message,
+) -> Result<Delivery, Error> {
+    // Messages to connections closed for exceeding their caps are dropped.
+    let wait = match meter_traffic(&world, conoid, message.len(), false) {
+        Some(wait) => wait,
+        None => return Ok(Delivery::Disconnected),
+    };
+    // In a verb's transaction, it's left to the connection's next message to wait.
+    if !wait.is_zero() && tr.is_none() {
+        tokio::time::sleep(wait).await;
+    }
+
+    #[cfg(feature = "faults")]
+    if world.faults().is_some_and(|faults| faults.drop_frame()) {
+        return Ok(Delivery::Queued);
+    }
+
+    let (tx, player) = match world.peer_map.lock().unwrap().get(&conoid) {
+        Some(connection) => (connection.sender.clone(), connection.player),
+        None => return Ok(Delivery::Disconnected),
+    };
+
+    // Journal the message before it goes out, so anything the peer may have seen is on record.
+    if let Some(journal) = &world.options.journal {
+        if let Some(entry) = JournalEntry::new(conoid, player, &message, journal.privacy) {
+            match tr {
+                Some(tr) => JournalTxHandle::new(tr).append(&entry),
+                None => {
+                    let entry = &entry;
+                    world
+                        .run(|tr| async move {
+                            JournalTxHandle::new(&tr).append(entry);
+                            Ok(())
+                        })
+                        .await?
+                }
+            }
+        }
+    }
+
+    // Admins impersonating the player get copies, which aren't metered or journaled.
+    if let Some(player) = player {
+        let watching = world.impersonations.watching(player);
+        let peer_map = world.peer_map.lock().unwrap();
+        for admin in watching.into_iter().filter(|admin| *admin != conoid) {
+            if let Some(con_record) = peer_map.get(&admin) {
+                con_record.sender.send(message.clone());
+            }
+        }
+    }
+
+    let delivery = tx.send(message);
+    if delivery == Delivery::WouldBlock {
+        warn!("Refused a message for {:?}, whose queue is full", conoid);
+    }
+    Ok(delivery)
+}
+
+/// The connections logged in to players in `location`, by the contents index, other than those of
+/// `except`.
+pub async fn broadcast_recipients(
+    world: &Arc<World>,
+    tr: &Tx,
+    location: Oid,
+    except: Option<Oid>,
+) -> Result<Vec<Oid>, Error> {
+    let contents: HashSet<Oid> = ContentsTxHandle::new(tr)
+        .contents(location)
+        .await?
+        .into_iter()
+        .filter(|oid| Some(*oid) != except)
+        .collect();
+    let peer_map = world.peer_map.lock().unwrap();
+    Ok(peer_map
+        .iter()
+        .filter(|(_, con_record)| con_record.player.map_or(false, |p| contents.contains(&p)))
+        .map(|(connection, _)| *connection)
+        .collect())
+}
+
+/// Send `message` to every connection logged in to a player in `location`, except those of
+/// `except`. How many connections it was sent to.
+pub async fn broadcast(
+    world: &Arc<World>,
+    tr: &Tx,
+    location: Oid,
+    message: Message,
+    except: Option<Oid>,
+) -> Result<usize, Error> {
+    let recipients = broadcast_recipients(world, tr, location, except).await?;
+    for connection in &recipients {
+        send_connection_message_in(world.clone(), tr, *connection, message.clone()).await?;
+    }
+    Ok(recipients.len())
+}
+
+/// The longest a message can be held back for by `send_later`.
+pub const MAX_SEND_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The most messages that can be waiting to be sent to any one connection by `send_later`.
+pub const MAX_DELAYED_SENDS: usize = 100;
+
+// A message waiting to be sent by `send_later`: to which connection, and the task sending it once
+// the verb which sent it has committed.
+pub(super) struct DelayedSend {
+    connection: Oid,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+// A handle given out by `send_later`, whose message is waiting for the verb's transaction to
+// commit. Forgotten if it's dropped first, as it is when the transaction is abandoned.
+pub(super) struct PendingSend {
+    world: Arc<World>,
+    handle: Oid,
+    started: bool,
+}
+
+impl PendingSend {
+    fn start(mut self, message: Message, delay: Duration) {
+        self.started = true;
+        let (world, handle) = (self.world.clone(), self.handle);
+        // Held while the task's spawned, so that it can't finish and forget itself before it's
+        // been remembered.
+        let mut delayed_sends = world.delayed_sends.lock().unwrap();
+        // Cancelled by the verb which sent it.
+        let send = match delayed_sends.get_mut(&handle) {
+            Some(send) => send,
+            None => return,
+        };
+        let connection = send.connection;
+        let task_world = world.clone();
+        send.task = Some(tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let world = task_world;
+            world.delayed_sends.lock().unwrap().remove(&handle);
+            if !world.peer_map.lock().unwrap().contains_key(&connection) {
+                return;
+            }
+            if let Err(e) = send_connection_message(world, connection, message).await {
+                error!(
+                    "Could not send a delayed message to {:?}: {}",
+                    connection, e
+                );
+            }
+        }));
+    }
+}
+
+impl Drop for PendingSend {
+    fn drop(&mut self) {
+        if !self.started {
+            self.world
+                .delayed_sends
+                .lock()
+                .unwrap()
+                .remove(&self.handle);
+        }
+    }
+}
+
+/// Send `message` to `connection` once `delay` has passed after `tr` commits, unless it's been
+/// cancelled, or the connection has closed, by then. Nothing is sent if `tr` is abandoned. Returns
+/// an IdKey to cancel it with, BadType if the delay is over MAX_SEND_DELAY, or ResourceLimit if
+/// MAX_DELAYED_SENDS are already waiting for the connection.
+///
+/// Messages waiting to be sent are only held in memory, so those still waiting when the server
+/// stops are never sent.
+pub fn send_later(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Oid,
+    message: Message,
+    delay: Duration,
+) -> Value {
+    if delay > MAX_SEND_DELAY {
+        return Value::Error(BadType);
+    }
+    let handle = Oid { id: Uuid::new_v4() };
+    {
+        let mut delayed_sends = world.delayed_sends.lock().unwrap();
+        let waiting = delayed_sends
+            .values()
+            .filter(|send| send.connection == connection)
+            .count();
+        if waiting >= MAX_DELAYED_SENDS {
+            return Value::Error(ResourceLimit);
+        }
+        // Counted from now, so that one verb can't queue more than that either.
+        delayed_sends.insert(
+            handle,
+            DelayedSend {
+                connection,
+                task: None,
+            },
+        );
+    }
+    let pending = PendingSend {
+        world: world.clone(),
+        handle,
+        started: false,
+    };
+    tr.after_commit(move || pending.start(message, delay));
+    Value::IdKey(handle)
+}
+
+/// Cancel a message waiting to be sent by `send_later`. SlotDoesNotExist if there's no such
+/// message waiting, as it's been sent or cancelled already.
+pub fn cancel_send(world: &Arc<World>, handle: Oid) -> Value {
+    match world.delayed_sends.lock().unwrap().remove(&handle) {
+        Some(send) => {
+            if let Some(task) = send.task {
+                task.abort();
+            }
+            Value::Error(NoError)
+        }
+        None => Value::Error(SlotDoesNotExist),
+    }
+}
+
+/// The verb on the system object run as a connection is closed for being idle.
+pub const IDLE_DISCONNECT_VERB: &str = "on_idle_disconnect";
+
+/// Ping every websocket connection each `ping_interval`, and close those which have sent nothing
+/// for `idle_timeout`, as the world's options say. Each is closed after running the system
+/// object's 'on_idle_disconnect' verb, if it has one, with the connection and the player it's
+/// logged in to (if it is) as arguments.
+///
+/// Telnet connections can't be pinged, so they're idle whenever their players are.
+pub async fn keep_alive(world: Arc<World>) {
+    let (ping_interval, idle_timeout) = (world.options.ping_interval, world.options.idle_timeout);
+    let interval = match (ping_interval, idle_timeout) {
+        (Some(interval), _) => interval,
+        (None, Some(timeout)) => (timeout / 2).max(Duration::from_secs(1)),
+        (None, None) => return,
+    };
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let mut idle = vec![];
+        for (connection, con_record) in world.peer_map.lock().unwrap().iter() {
+            // (Those awaiting being resumed have no client to ping, and expire of themselves.)
+            if con_record.detached.is_some() {
+                continue;
+            }
+            if idle_timeout.is_some_and(|timeout| con_record.last_heard.elapsed() > timeout) {
+                idle.push((*connection, con_record.player, con_record.vm.clone()));
+            } else if ping_interval.is_some() {
+                con_record.sender.send(Message::Ping(vec![]));
+            }
+        }
+        for (connection, player, vm) in idle {
+            info!("Closing {:?}, which has been idle too long", connection);
+            let mut args = vec![Value::IdKey(connection)];
+            args.extend(player.map(Value::IdKey));
+            if let Err(e) =
+                run_system_verb_if_defined(&world, vm, IDLE_DISCONNECT_VERB, &args).await
+            {
+                error!(
+                    "'{}' failed for {:?}: {}",
+                    IDLE_DISCONNECT_VERB, connection, e
+                );
+            }
+            // Which ends its handler, which disconnects it.
+            if let Some(con_record) = world.peer_map.lock().unwrap().get(&connection) {
+                let close = CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "Idle too long".into(),
+                };
+                con_record.sender.close_with(Message::Close(Some(close)));
+            }
+        }
+    }
+}
+
+// Run `verb` on the system object, if it has it.
+pub(super) async fn run_system_verb_if_defined(
+    world: &Arc<World>,
+    vm: Arc<WasmVM>,
+    verb: &str,
+    args: &[Value],
+) -> Result<(), Error> {
+    let sys_oid = Oid { id: Uuid::nil() };
+    let defined = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            let slot = odb.get_slot(sys_oid, sys_oid, String::from(verb)).await;
+            Ok(matches!(slot, Ok(Value::Program(_))))
+        })
+        .await?;
+    if defined {
+        send_verb_dispatch(world, vm, sys_oid, verb, args).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+use super::*;
+
+/// Why a transaction the world ran failed, classified for its callers: whether running it again
+/// could succeed, and if it was abandoned, why, as verbs are told.
+#[derive(Debug)]
+pub enum WorldError {
+    /// It conflicted with another transaction, or timed out, and wasn't retried; running it again
+    /// could succeed.
+    Retryable(DbError),
+    /// It was abandoned with nothing committed, by its closure or for conflicting too often, for
+    /// the reason given.
+    Abandoned(value::Error, DbError),
+    /// It was abandoned with nothing committed, as the verb it ran trapped or otherwise failed.
+    Trapped(Box<VerbError>),
+    /// The database failed, and running it again won't help.
+    Fatal(DbError),
+}
+
+impl WorldError {
+    /// Whether running the transaction again could succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, WorldError::Retryable(_))
+    }
+
+    /// Why the transaction was abandoned, as verbs are told, if it was abandoned rather than
+    /// having failed: its closure's reason, Contended if it ran out of retries, or Trapped.
+    pub fn abandoned_for(&self) -> Option<value::Error> {
+        match self {
+            WorldError::Abandoned(reason, _) => Some(*reason),
+            WorldError::Trapped(_) => Some(value::Error::Trapped),
+            WorldError::Retryable(_) | WorldError::Fatal(_) => None,
+        }
+    }
+}
+
+impl From<DbError> for WorldError {
+    fn from(e: DbError) -> Self {
+        let abandoned_for = match &e {
+            DbError::Aborted(reason) => Some(*reason),
+            DbError::RetriesExhausted(_) => Some(value::Error::Contended),
+            _ => None,
+        };
+        match (e, abandoned_for) {
+            (DbError::Trapped(error), _) => WorldError::Trapped(error),
+            (e, Some(reason)) => WorldError::Abandoned(reason, e),
+            (e, None) if e.is_retryable() => WorldError::Retryable(e),
+            (e, None) => WorldError::Fatal(e),
+        }
+    }
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorldError::Retryable(e) | WorldError::Abandoned(_, e) | WorldError::Fatal(e) => {
+                write!(f, "{}", e)
+            }
+            WorldError::Trapped(e) => write!(f, "Transaction abandoned, its verb failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
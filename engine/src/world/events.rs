@@ -0,0 +1,278 @@
+use super::*;
+
+/// Have `connection` told whenever the slot `name` on `oid` is written, from when `tr` commits.
+/// PermissionDenied if there's no connection to tell.
+pub fn watch_slot(world: &World, tr: &Tx, connection: Option<Oid>, oid: Oid, name: &str) -> Value {
+    match connection {
+        Some(connection) => {
+            world.watches.watch(connection, oid, name);
+            WatchTxHandle::new(tr).watch(connection, oid, name);
+            Value::Error(NoError)
+        }
+        None => Value::Error(PermissionDenied),
+    }
+}
+
+pub fn unwatch_slot(tr: &Tx, connection: Option<Oid>, oid: Oid, name: &str) -> Value {
+    match connection {
+        Some(connection) => {
+            WatchTxHandle::new(tr).unwatch(connection, oid, name);
+            Value::Error(NoError)
+        }
+        None => Value::Error(PermissionDenied),
+    }
+}
+
+// How long delivery waits for events to be published before looking again anyway, and between
+// attempts at delivering one.
+pub(super) const EVENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How many times delivering an event to a subscriber is tried before it's given up on.
+pub(super) const EVENT_ATTEMPTS: u32 = 3;
+
+/// Deliver the events published to channels as the transactions publishing them commit, by
+/// dispatching 'on_event' on each of a channel's subscribers with [IdKey channel, value, IdKey
+/// publisher], for the connection whose verb published it, so that it's permitted no more than
+/// that connection was. Each subscriber is given a channel's events in the order they were
+/// published. Runs until the world goes away.
+///
+/// Events are forgotten only once a batch of them has been delivered, so they're delivered at
+/// least once: a server which stops midway leaves its batch to be delivered again, once its lease
+/// runs out, by whichever server takes it over. A subscriber its event can't be delivered to is
+/// tried EVENT_ATTEMPTS times, and then given up on.
+pub async fn deliver_events(world: Arc<World>) {
+    let holder = Uuid::new_v4();
+    loop {
+        let taken = world
+            .run(|tr| async move {
+                let pubsub = PubSubTxHandle::new(&tr);
+                if !pubsub.lease(holder, now_nanos()).await? {
+                    return Ok(None);
+                }
+                let mut deliveries: HashMap<Oid, Vec<(Option<Oid>, Vec<Value>)>> = HashMap::new();
+                let (events, last) = pubsub.pending(EVENT_BATCH).await?;
+                for event in events {
+                    for subscriber in pubsub.subscribers(event.channel).await? {
+                        deliveries.entry(subscriber).or_default().push((
+                            event.connection,
+                            vec![
+                                Value::IdKey(event.channel),
+                                event.value.clone(),
+                                Value::IdKey(event.publisher),
+                            ],
+                        ));
+                    }
+                }
+                // Set up with the read, so that nothing published since is missed.
+                let watch = last.is_none().then(|| pubsub.watch());
+                Ok(Some((deliveries, last, watch)))
+            })
+            .await;
+        let (deliveries, last) = match taken {
+            Ok(Some((deliveries, Some(last), _))) => (deliveries, last),
+            Ok(Some((_, None, Some(watch)))) => {
+                // Looked again after a while regardless, in case the watch was missed.
+                let _ = tokio::time::timeout(EVENT_POLL_INTERVAL, watch).await;
+                continue;
+            }
+            // Another server is delivering them.
+            Ok(_) => {
+                tokio::time::sleep(EVENT_POLL_INTERVAL).await;
+                continue;
+            }
+            Err(e) => {
+                error!("Could not take events for delivery: {}", e);
+                tokio::time::sleep(EVENT_POLL_INTERVAL).await;
+                continue;
+            }
+        };
+        // Each subscriber separately, with VMs of its own, so one slow subscriber doesn't hold up
+        // the rest; but the whole batch before the next, so that each gets its events in order.
+        let deliveries = deliveries.into_iter().map(|(subscriber, events)| {
+            let world = world.clone();
+            tokio::spawn(async move {
+                let mut vms: HashMap<Option<Oid>, Arc<WasmVM>> = HashMap::new();
+                for (connection, args) in events {
+                    let vm = match vms.get(&connection) {
+                        Some(vm) => vm.clone(),
+                        None => match event_vm(&world, connection) {
+                            Ok(vm) => vms.entry(connection).or_insert(vm).clone(),
+                            Err(e) => {
+                                error!("Could not create a VM for event delivery: {}", e);
+                                continue;
+                            }
+                        },
+                    };
+                    for attempt in 1..=EVENT_ATTEMPTS {
+                        let result =
+                            send_verb_dispatch(&world, vm.clone(), subscriber, EVENT_VERB, &args)
+                                .await;
+                        match result {
+                            Ok(_) => break,
+                            Err(e) => error!(
+                                "Could not deliver an event to {:?} (attempt {} of {}): {}",
+                                subscriber, attempt, EVENT_ATTEMPTS, e
+                            ),
+                        }
+                        if attempt < EVENT_ATTEMPTS {
+                            tokio::time::sleep(EVENT_POLL_INTERVAL).await;
+                        }
+                    }
+                }
+            })
+        });
+        futures::future::join_all(deliveries).await;
+        let last = &last;
+        let acknowledged = world
+            .run(|tr| async move {
+                PubSubTxHandle::new(&tr).acknowledge(last.clone());
+                Ok(())
+            })
+            .await;
+        if let Err(e) = acknowledged {
+            error!("Could not acknowledge delivered events: {}", e);
+        }
+    }
+}
+
+// A VM to deliver events published by `connection`'s verbs with.
+pub(super) fn event_vm(world: &Arc<World>, connection: Option<Oid>) -> Result<Arc<WasmVM>, Error> {
+    let vm = Arc::new(WasmVM::new(world.clone(), connection)?);
+    vm.clone().bind_builtins()?;
+    Ok(vm)
+}
+
+/// Tell connections watching slots about writes to them, as transactions commit. Runs until the
+/// world goes away.
+///
+/// Each watching connection has the system object's 'on_slot_changed' verb invoked with it, the
+/// slot's Oid and the slot's name. Failing that, it's sent them as a message: a Vector of
+/// "slot_changed", the Oid and the name.
+pub async fn notify_watchers(world: Arc<World>) {
+    let mut commits = world.database.subscribe();
+    loop {
+        let written = match commits.recv().await {
+            Ok(written) => written,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(
+                    "Missed slot watch notifications for {} transactions",
+                    missed
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        // What the verbs telling watchers write isn't told in turn, or they'd tell them forever.
+        if written.unwatched {
+            continue;
+        }
+        let mut changed: Vec<(Oid, String)> = vec![];
+        for slot in written.keys.iter().filter_map(slot_at) {
+            if !world.watches.may_be_watched(slot.location, &slot.name) {
+                continue;
+            }
+            if !changed.contains(&(slot.location, slot.name.clone())) {
+                changed.push((slot.location, slot.name));
+            }
+        }
+        for (oid, name) in changed {
+            if let Err(e) = notify_slot_changed(&world, oid, &name).await {
+                error!("Could not notify watchers of {:?}.{}: {}", oid, name, e);
+            }
+        }
+    }
+}
+
+pub(super) async fn notify_slot_changed(
+    world: &Arc<World>,
+    oid: Oid,
+    name: &str,
+) -> Result<(), Error> {
+    let sys_oid = Oid { id: Uuid::nil() };
+    let (watchers, handled) = world
+        .run(|tr| async move {
+            let watchers = WatchTxHandle::new(&tr).watchers(oid, name).await?;
+            let handler = ObjDBTxHandle::new(&tr)
+                .get_slot(sys_oid, sys_oid, String::from("on_slot_changed"))
+                .await;
+            Ok((watchers, matches!(handler, Ok(Value::Program(_)))))
+        })
+        .await?;
+
+    for connection in watchers {
+        let vm = {
+            let peer_map = world.peer_map.lock().unwrap();
+            peer_map
+                .get(&connection)
+                .map(|con_record| con_record.vm.clone())
+        };
+        let vm = match vm {
+            Some(vm) => vm,
+            // Left over from a connection which didn't disconnect cleanly.
+            None => {
+                world.watches.forget_connection(connection);
+                world
+                    .run(|tr| async move { WatchTxHandle::new(&tr).clear_connection(connection).await })
+                    .await?;
+                continue;
+            }
+        };
+        let world = world.clone();
+        let event = vec![
+            Value::IdKey(connection),
+            Value::IdKey(oid),
+            Value::String(name.into()),
+        ];
+        // Each separately, so one slow connection doesn't hold up the rest.
+        tokio::spawn(async move {
+            let notified = match handled {
+                true => unwatched(send_verb_dispatch(
+                    &world,
+                    vm,
+                    sys_oid,
+                    "on_slot_changed",
+                    &event,
+                ))
+                .await
+                .map(|_| ()),
+                false => {
+                    let mut message = vec![Value::String("slot_changed".into())];
+                    message.extend_from_slice(&event[1..]);
+                    let frame = encode_frame(&Value::Vector(message));
+                    send_connection_message(world, connection, Message::Binary(frame))
+                        .await
+                        .map(|_| ())
+                }
+            };
+            if let Err(e) = notified {
+                error!("Could not notify {:?} of a slot change: {}", connection, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Subscribe `subscriber` to `channel`, so that it's given what's published there from now on.
+pub fn subscribe(tr: &Tx, channel: Oid, subscriber: Oid) -> Value {
+    PubSubTxHandle::new(tr).subscribe(channel, subscriber);
+    Value::Error(NoError)
+}
+
+/// Unsubscribe `subscriber` from `channel`.
+pub fn unsubscribe(tr: &Tx, channel: Oid, subscriber: Oid) -> Value {
+    PubSubTxHandle::new(tr).unsubscribe(channel, subscriber);
+    Value::Error(NoError)
+}
+
+/// Publish `value` to `channel`, from `publisher`, run for `connection`: each of its subscribers is
+/// given it, once the transaction commits, with 'on_event' run for the same connection.
+pub fn publish(
+    tr: &Tx,
+    channel: Oid,
+    value: &Value,
+    publisher: Option<Oid>,
+    connection: Option<Oid>,
+) -> Value {
+    PubSubTxHandle::new(tr).publish(channel, value, publisher, connection);
+    Value::Error(NoError)
+}
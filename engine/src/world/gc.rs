@@ -0,0 +1,290 @@
+use super::*;
+
+/// The most slots `find_references` gives.
+pub const REFERENCES_LIMIT: usize = 1000;
+
+/// The slots referring to `oid`, as [IdKey location, String name] Vectors, up to
+/// REFERENCES_LIMIT of them. Their keys are left out, as knowing a slot's key is what lets it be
+/// read. Only those set while the reference index has been kept are found; SlotDoesNotExist if it
+/// isn't kept at all.
+pub async fn find_references(tr: &Tx, oid: Oid) -> Result<Value, Error> {
+    let odb = ObjDBTxHandle::new(tr);
+    let slots = match odb.find_references(oid) {
+        Ok(slots) => slots.take(REFERENCES_LIMIT).collect::<Vec<SlotDef>>().await,
+        Err(e) => return Ok(Value::Error(e)),
+    };
+    Ok(Value::Vector(
+        slots
+            .into_iter()
+            .map(|slot| {
+                Value::Vector(vec![
+                    Value::IdKey(slot.location),
+                    Value::String(slot.name.into()),
+                ])
+            })
+            .collect(),
+    ))
+}
+
+/// Add every slot in the world to the index of references, an object per transaction, for a world
+/// whose slots were set before the index was kept. Returns how many objects and slots there were.
+pub async fn index_references(world: &Arc<World>) -> Result<(usize, usize), Error> {
+    let oids = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::snapshot(&tr);
+            Ok(odb.objects().unwrap().collect::<Vec<Oid>>().await)
+        })
+        .await?;
+    let mut slots = 0;
+    for oid in &oids {
+        slots += world
+            .run(|tr| async move {
+                let odb = ObjDBTxHandle::snapshot(&tr);
+                let dumped = odb.dump_slots(*oid).map_err(DbError::Aborted)?;
+                let dumped = dumped
+                    .collect::<Result<Vec<(SlotDef, Value)>, _>>()
+                    .await
+                    .map_err(DbError::Aborted)?;
+                for (slot, value) in &dumped {
+                    odb.index_references(slot, value);
+                }
+                Ok(dumped.len())
+            })
+            .await?;
+    }
+    Ok((oids.len(), slots))
+}
+
+/// Find the objects which can't be reached from the system object, the open connections and their
+/// players, and the configured roots, by following the IdKeys in objects' slots, the keys their
+/// slots are under and what's in them; and clear those objects if `clear`. How it's going is kept
+/// in the world's GcRegistry as it goes. None if a sweep is going on already.
+///
+/// Each object is read in a transaction of its own, so an object which is only referred to from
+/// ones created since the sweep began can be missed. Objects are checked once more as they're
+/// cleared, in the transaction that clears them, and kept if anything outside the garbage refers
+/// to them by then or they've become roots. That needs the reference index, so without it a sweep
+/// which would clear fails.
+pub async fn collect_garbage(world: &Arc<World>, clear: bool) -> Result<Option<GcProgress>, Error> {
+    if !world.gc.start(clear) {
+        return Ok(None);
+    }
+    sweep_or_fail(world, clear).await?;
+    Ok(world.gc.progress())
+}
+
+/// Collect garbage as `collect_garbage` does, in the background. False if a sweep is going on
+/// already.
+pub fn start_garbage_collection(world: &Arc<World>, clear: bool) -> bool {
+    if !world.gc.start(clear) {
+        return false;
+    }
+    let world = world.clone();
+    tokio::spawn(async move {
+        if let Err(e) = sweep_or_fail(&world, clear).await {
+            error!("Garbage collection failed: {}", e);
+        }
+    });
+    true
+}
+
+// Sweep, recording why if it fails.
+pub(super) async fn sweep_or_fail(world: &Arc<World>, clear: bool) -> Result<(), Error> {
+    let result = sweep_garbage(world, clear).await;
+    if let Err(e) = &result {
+        world.gc.update(|progress| {
+            progress.phase = GcPhase::Failed;
+            progress.error = Some(e.to_string());
+        });
+    }
+    result
+}
+
+// Whether `oid` is kept whatever refers to it, as it has a name, tags, subscriptions, subscribers
+// or counters.
+pub(super) async fn gc_root(tr: &Tx, oid: Oid) -> Result<bool, DbError> {
+    let pubsub = PubSubTxHandle::new(tr);
+    Ok(NameTxHandle::new(tr).name_of(oid).await?.is_some()
+        || !TagTxHandle::new(tr).tags_of(oid).await?.is_empty()
+        || !pubsub.subscriptions(oid).await?.is_empty()
+        || !pubsub.subscribers(oid).await?.is_empty()
+        || CounterTxHandle::new(tr).any(oid).await?)
+}
+
+// Whether `oid` is a connection, or the player of one or logging in on one.
+pub(super) fn is_connected(world: &World, oid: Oid) -> bool {
+    world
+        .peer_map
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(connection, con_record)| {
+            *connection == oid
+                || con_record.player == Some(oid)
+                || con_record.pending_login == Some(oid)
+        })
+}
+
+// Everything garbage collection keeps whatever refers to it that can be found without reading
+// every object: the system object, the configured roots, what's connected, and the calendar's
+// events' targets and owners.
+pub(super) async fn gc_roots(world: &Arc<World>) -> Result<Vec<Oid>, Error> {
+    let mut roots = vec![Oid { id: Uuid::nil() }];
+    roots.extend(world.options.gc.roots.iter().copied());
+    for (connection, con_record) in world.peer_map.lock().unwrap().iter() {
+        roots.push(*connection);
+        roots.extend(con_record.player);
+        roots.extend(con_record.pending_login);
+    }
+    let events = world
+        .run(|tr| async move { CalendarTxHandle::new(&tr).all().await })
+        .await?;
+    for event in events {
+        roots.push(event.target);
+        roots.extend(event.owner);
+    }
+    Ok(roots)
+}
+
+pub(super) async fn sweep_garbage(world: &Arc<World>, clear: bool) -> Result<(), Error> {
+    if clear && !world.options.reference_index {
+        return Err(anyhow!(
+            "Clearing garbage needs the reference index (--reference-index)"
+        ));
+    }
+    // Only the objects there as it starts are candidates, so those made since aren't cleared.
+    let objects = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::snapshot(&tr);
+            Ok(odb.objects().unwrap().collect::<Vec<Oid>>().await)
+        })
+        .await?;
+    world.gc.update(|progress| progress.objects = objects.len());
+
+    let mut marked = HashSet::new();
+    mark(world, gc_roots(world).await?, &mut marked).await?;
+    // Those which are roots in their own right are only found by asking of each object that
+    // wasn't reached from the rest.
+    let mut roots = vec![];
+    for oid in objects.iter().copied().filter(|oid| !marked.contains(oid)) {
+        if world
+            .run(|tr| async move { gc_root(&tr, oid).await })
+            .await?
+        {
+            roots.push(oid);
+        }
+    }
+    mark(world, roots, &mut marked).await?;
+
+    let unreachable: Vec<Oid> = objects
+        .into_iter()
+        .filter(|oid| !marked.contains(oid))
+        .collect();
+    info!(
+        "Garbage collection reached {} objects, and not {}",
+        marked.len(),
+        unreachable.len()
+    );
+    world.gc.update(|progress| {
+        progress.marked = marked.len();
+        progress.unreachable = unreachable.clone();
+        progress.phase = match clear {
+            true => GcPhase::Sweeping,
+            false => GcPhase::Done,
+        };
+    });
+    if !clear {
+        return Ok(());
+    }
+
+    let garbage: HashSet<Oid> = unreachable.iter().copied().collect();
+    let garbage = &garbage;
+    for oid in unreachable.iter().copied() {
+        let cleared = world
+            .run(|tr| async move {
+                let referrers = ObjDBTxHandle::new(&tr)
+                    .find_references(oid)
+                    .map_err(DbError::Aborted)?
+                    .collect::<Vec<SlotDef>>()
+                    .await;
+                if referrers
+                    .iter()
+                    .any(|slot| !garbage.contains(&slot.location))
+                    || gc_root(&tr, oid).await?
+                    || is_connected(world, oid)
+                {
+                    return Ok(false);
+                }
+                if let Err(e) = destroy_object(world, &tr, None, oid).await {
+                    error!("Could not clear {:?}: {}", oid, e);
+                    return Err(DbError::Aborted(InternalError));
+                }
+                Ok(true)
+            })
+            .await?;
+        world.gc.update(|progress| match cleared {
+            true => progress.cleared += 1,
+            false => progress.kept += 1,
+        });
+    }
+    warn!(
+        target: "security",
+        "Garbage collection cleared {} unreachable objects",
+        world.gc.progress().map_or(0, |progress| progress.cleared)
+    );
+    world.gc.update(|progress| progress.phase = GcPhase::Done);
+    Ok(())
+}
+
+// Mark what's reachable from `pending`, one object per transaction, adding it to `marked`.
+pub(super) async fn mark(
+    world: &Arc<World>,
+    mut pending: Vec<Oid>,
+    marked: &mut HashSet<Oid>,
+) -> Result<(), Error> {
+    while let Some(oid) = pending.pop() {
+        if !marked.insert(oid) {
+            continue;
+        }
+        let reached = world
+            .run(|tr| async move {
+                let odb = ObjDBTxHandle::snapshot(&tr);
+                let slots = odb.dump_slots(oid).map_err(DbError::Aborted)?;
+                let slots = slots
+                    .collect::<Result<Vec<(SlotDef, Value)>, _>>()
+                    .await
+                    .map_err(DbError::Aborted)?;
+                let mut reached = ContentsTxHandle::new(&tr).contents(oid).await?;
+                for (slot, value) in &slots {
+                    reached.push(slot.key);
+                    reached.extend(referenced(value));
+                }
+                Ok(reached)
+            })
+            .await?;
+        pending.extend(reached.into_iter().filter(|oid| !marked.contains(oid)));
+        if marked.len() % 1000 == 0 {
+            world.gc.update(|progress| progress.marked = marked.len());
+        }
+    }
+    Ok(())
+}
+
+/// Every `interval`, collect garbage as the world's GcOptions say, logging what was found.
+pub async fn collect_garbage_every(world: Arc<World>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        match collect_garbage(&world, world.options.gc.clear).await {
+            Ok(Some(progress)) => info!(
+                "Scheduled garbage collection found {} unreachable objects, and cleared {}",
+                progress.unreachable.len(),
+                progress.cleared
+            ),
+            Ok(None) => info!("Garbage collection is already running; skipped"),
+            Err(e) => error!("Scheduled garbage collection failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use super::*;
+
+/// Run `method` on `destoid` as a dry run, in a transaction which is always abandoned, and report
+/// what it would have done: a Vector of its result, the slot writes it made (as Vectors of
+/// [IdKey oid, IdKey key, String name, value]) and the messages it sent (as Vectors of
+/// [IdKey connection, message]).
+pub async fn dry_run_dispatch(
+    world: &Arc<World>,
+    vm: Arc<WasmVM>,
+    destoid: Oid,
+    method: &str,
+    arguments: &[Value],
+) -> Result<Value, Error> {
+    let vm = &vm;
+    let report = world
+        .run_uncommitted(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            let program = match odb.get_slot(destoid, destoid, String::from(method)).await {
+                Ok(Value::Program(p)) => p,
+                Ok(_) => return Ok(Value::Error(InvalidProgram)),
+                Err(_) => return Ok(Value::Error(SlotDoesNotExist)),
+            };
+            let limits = execution_limits(world, &odb, destoid).await;
+            let args = Value::Vector(arguments.to_vec());
+            let (result, effects) = match vm
+                .dry_run(&tr, (destoid, method), &program, &args, limits)
+                .await
+            {
+                Ok(run) => run,
+                Err(e) => {
+                    error!("Verb failed: {}", e);
+                    (for_client(VerbError::of(&e).to_value()), DryRun::default())
+                }
+            };
+            Ok(Value::Vector(vec![
+                result,
+                Value::Vector(effects.writes),
+                Value::Vector(effects.messages),
+            ]))
+        })
+        .await?;
+    Ok(report)
+}
+
+pub async fn send_verb_dispatch(
+    world: &Arc<World>,
+    vm: Arc<WasmVM>,
+    destoid: Oid,
+    method: &str,
+    arguments: &[Value],
+) -> Result<Value, Error> {
+    dispatch(world, vm, None, destoid, method, arguments).await
+}
+
+/// Invoke `method` on `destoid` for the verb executing in `tr`, as send_verb_dispatch does;
+/// except that a verb on another world's object is only invoked once `tr` commits, and isn't
+/// waited for: NoError is returned in place of its result.
+pub async fn invoke_verb(
+    world: &Arc<World>,
+    tr: &Tx,
+    vm: Arc<WasmVM>,
+    destoid: Oid,
+    method: &str,
+    arguments: &[Value],
+) -> Result<Value, Error> {
+    dispatch(world, vm, Some(tr), destoid, method, arguments).await
+}
+
+pub(super) async fn dispatch(
+    world: &Arc<World>,
+    vm: Arc<WasmVM>,
+    invoking: Option<&Tx>,
+    destoid: Oid,
+    method: &str,
+    arguments: &[Value],
+) -> Result<Value, Error> {
+    let vm = &vm.clone();
+    let v = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            // Only objects which aren't here can be another world's.
+            if !world.federation.is_empty() && !odb.exists(destoid).await? {
+                if let Some(peer) = remote_peer(world, &odb, destoid).await {
+                    return Ok(Err(peer));
+                }
+            }
+            local_dispatch(world, vm, &tr, &odb, destoid, method, arguments)
+                .await
+                .map(Ok)
+        })
+        .await;
+    match v {
+        Ok(Ok(v)) => Ok(v),
+        Ok(Err(peer)) => Ok(remote_dispatch(peer, invoking, destoid, method, arguments).await),
+        // The invoking verb is told how, and can carry on.
+        Err(WorldError::Trapped(e)) => {
+            warn!("{:?}:{} failed: {}", destoid, method, e);
+            Ok(e.to_value())
+        }
+        Err(e) => match e.abandoned_for() {
+            Some(reason) => {
+                warn!("{:?}:{} aborted: {:?}", destoid, method, reason);
+                Ok(Value::Error(reason))
+            }
+            None => Err(e.into()),
+        },
+    }
+}
+
+// Run `method` on `destoid`, an object of this world's, within `tr`.
+pub(super) async fn local_dispatch(
+    world: &Arc<World>,
+    vm: &Arc<WasmVM>,
+    tr: &Tx,
+    odb: &ObjDBTxHandle<'_>,
+    destoid: Oid,
+    method: &str,
+    arguments: &[Value],
+) -> Result<Value, DbError> {
+    migrate_instance(world, vm, tr, odb, destoid).await?;
+    let slot = SlotDef {
+        location: destoid,
+        key: destoid,
+        name: String::from(method),
+    };
+    if !slot_permitted(world, tr, vm.connection(), &slot, SLOT_EXECUTE).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    match odb.get_slot(destoid, destoid, String::from(method)).await {
+        Ok(sv) => {
+            let message_val = Value::Vector(arguments.to_vec());
+            match sv {
+                Value::Program(p) => {
+                    let ttl = cache_ttl(odb, destoid, method).await;
+                    let key = ttl.map(|_| verb_cache::key((destoid, method), &p, &message_val));
+                    if let Some(cached) = key.as_ref().and_then(|k| world.verb_results.get(k)) {
+                        return Ok(cached);
+                    }
+                    let limits = execution_limits(world, odb, destoid).await;
+                    let result = vm
+                        .execute(tr, (destoid, method), &p, &message_val, limits)
+                        .await;
+                    // Only once it's committed: what a verb whose transaction is retried or
+                    // abandoned returned may never have been so.
+                    if let (Ok(value), Some(key), Some(ttl)) = (&result, key, ttl) {
+                        if !matches!(value, Value::Error(_)) {
+                            let (world, value) = (world.clone(), value.clone());
+                            tr.after_commit(move || world.verb_results.insert(key, value, ttl));
+                        }
+                    }
+                    commit_unless_failed(result)
+                }
+                _ => {
+                    error!(
+                        "slot not a Program: {:?}",
+                        world.redaction().slot(method, &message_val)
+                    );
+                    Ok(Value::Error(InvalidProgram))
+                }
+            }
+        }
+        Err(r) => {
+            error!("Verb not found: {:?}", r);
+            Ok(Value::Error(SlotDoesNotExist))
+        }
+    }
+}
+
+// Run `method` on `destoid`, another world's object, in a transaction of the peer's own: what it
+// does is neither rolled back with, nor retried with, any of this world's. It's run now, unless a
+// verb executing in `invoking` invoked it; then it's run once that transaction commits, so that
+// it isn't run again each time that's retried, nor is that kept open waiting for the peer.
+pub(super) async fn remote_dispatch(
+    peer: Arc<Peer>,
+    invoking: Option<&Tx>,
+    destoid: Oid,
+    method: &str,
+    arguments: &[Value],
+) -> Value {
+    let tr = match invoking {
+        Some(tr) => tr,
+        None => return peer.invoke(destoid, method, arguments).await,
+    };
+    let (method, arguments) = (method.to_string(), arguments.to_vec());
+    tr.after_commit(move || {
+        tokio::spawn(async move {
+            let result = peer.invoke(destoid, &method, &arguments).await;
+            let failed = match result {
+                Value::Error(e) => !matches!(e, NoError),
+                ref map => is_error_map(map),
+            };
+            if failed {
+                warn!("{:?}:{} on peer {} failed", destoid, method, peer.name());
+            }
+        });
+    });
+    Value::Error(NoError)
+}
+
+// The peer whose world `oid` is in, if it's not this one: the one whose prefix it carries, else
+// the one the system object's PEERS_SLOT lists it under.
+pub(super) async fn remote_peer(
+    world: &World,
+    odb: &ObjDBTxHandle<'_>,
+    oid: Oid,
+) -> Option<Arc<Peer>> {
+    if let Some(peer) = world.federation.peer_by_prefix(oid) {
+        return Some(peer);
+    }
+    let sys_oid = Oid { id: Uuid::nil() };
+    let registry = odb
+        .get_slot(sys_oid, SERVER_KEY, String::from(PEERS_SLOT))
+        .await
+        .ok()?;
+    world.federation.peer_listed(&registry, oid)
+}
+
+/// List `object` as `peer`'s, in the system object's PEERS_SLOT, or no longer as any peer's if
+/// that's None. False if that's how it was listed already.
+pub async fn set_remote(
+    world: &Arc<World>,
+    object: Oid,
+    peer: Option<&str>,
+) -> Result<bool, Error> {
+    let changed =
+        world
+            .run(|tr| async move {
+                let sys_oid = Oid { id: Uuid::nil() };
+                let odb = ObjDBTxHandle::new(&tr);
+                let mut entries = match odb
+                    .get_slot(sys_oid, SERVER_KEY, String::from(PEERS_SLOT))
+                    .await
+                {
+                    Ok(Value::Vector(entries)) => entries,
+                    _ => vec![],
+                };
+                let listed = |entry: &Value| match entry {
+                    Value::Vector(pair) => match pair.as_slice() {
+                        [Value::IdKey(listed), Value::String(name)] if *listed == object => {
+                            Some(name.to_string())
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if entries.iter().find_map(listed).as_deref() == peer {
+                    return Ok(false);
+                }
+                entries.retain(|entry| listed(entry).is_none());
+                entries.extend(peer.map(|peer| {
+                    Value::Vector(vec![Value::IdKey(object), Value::String(peer.into())])
+                }));
+                odb.set_slot(
+                    sys_oid,
+                    SERVER_KEY,
+                    String::from(PEERS_SLOT),
+                    &Value::Vector(entries),
+                )
+                .await
+                .map_err(DbError::Aborted)?;
+                Ok(true)
+            })
+            .await?;
+    Ok(changed)
+}
+
+// The limits for verbs on `oid`: those its own 'fuel_limit', 'memory_limit' and 'time_limit_ms'
+// slots set, otherwise the world's.
+pub(super) async fn execution_limits(
+    world: &World,
+    odb: &ObjDBTxHandle<'_>,
+    oid: Oid,
+) -> ExecutionLimits {
+    let fuel = slot_limit(odb, oid, "fuel_limit").await;
+    let memory = slot_limit(odb, oid, "memory_limit").await;
+    let time = slot_limit(odb, oid, "time_limit_ms").await;
+    ExecutionLimits {
+        time: time.map_or_else(
+            || world.options.time_limit.unwrap_or(DEFAULT_TIME_LIMIT),
+            Duration::from_millis,
+        ),
+        fuel: fuel.unwrap_or_else(|| world.options.fuel_limit.unwrap_or(DEFAULT_FUEL_LIMIT)),
+        memory: memory.map_or_else(
+            || world.options.memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT),
+            |limit| limit as usize,
+        ),
+    }
+}
+
+pub(super) async fn slot_limit(odb: &ObjDBTxHandle<'_>, oid: Oid, name: &str) -> Option<u64> {
+    match odb.get_slot(oid, oid, String::from(name)).await {
+        Ok(Value::I64(limit)) if limit > 0 => Some(limit as u64),
+        Ok(Value::I32(limit)) if limit > 0 => Some(limit as u64),
+        _ => None,
+    }
+}
+
+// How long `oid`'s `verb` may have its results cached for, by the object's 'cache_ttl' slot; None
+// if it isn't marked cacheable.
+pub(super) async fn cache_ttl(odb: &ObjDBTxHandle<'_>, oid: Oid, verb: &str) -> Option<Duration> {
+    let marked = match odb.get_slot(oid, oid, String::from(CACHE_TTL_SLOT)).await {
+        Ok(Value::Vector(marked)) => marked,
+        _ => return None,
+    };
+    marked.into_iter().find_map(|entry| match entry {
+        Value::Vector(pair) => match pair.as_slice() {
+            [Value::String(name), Value::I64(ms)] if name == verb && *ms > 0 => {
+                Some(Duration::from_millis(*ms as u64))
+            }
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// The slot on a prototype holding the version of its slot layout, and on each of its instances
+/// the version their slots were last migrated to.
+pub(super) const VERSION_SLOT: &str = "version";
+
+/// The slot on an instance holding its prototype's IdKey.
+pub(super) const PROTOTYPE_SLOT: &str = "prototype";
+
+/// The verb run on an instance whose prototype's version has moved on, with the version it was at.
+pub(super) const MIGRATE_VERB: &str = "on_migrate";
+
+// Brings `oid` up to date with its prototype before a verb runs on it, within `tr`. If the
+// prototype's 'version' slot is ahead of the instance's (or the instance has none, as though it
+// were 0), the instance's 'on_migrate' verb is run with the version it was at, and its 'version'
+// set to the prototype's. An instance without 'on_migrate' just has its version brought up.
+// A migration which fails abandons the transaction, so it's tried again at the next access.
+pub(super) async fn migrate_instance(
+    world: &World,
+    vm: &WasmVM,
+    tr: &Tx,
+    odb: &ObjDBTxHandle<'_>,
+    oid: Oid,
+) -> Result<(), DbError> {
+    let prototype = match odb.get_slot(oid, oid, String::from(PROTOTYPE_SLOT)).await {
+        Ok(Value::IdKey(prototype)) if prototype != oid => prototype,
+        _ => return Ok(()),
+    };
+    let version = match slot_version(odb, prototype).await {
+        Some(version) => version,
+        None => return Ok(()),
+    };
+    let old_version = slot_version(odb, oid).await.unwrap_or(0);
+    if old_version >= version {
+        return Ok(());
+    }
+    // The version is recorded first, in the transaction the migration runs in, so the two commit
+    // or are abandoned together, and the migration sees its instance already up to date.
+    odb.set_slot(oid, oid, String::from(VERSION_SLOT), &Value::I64(version))
+        .await
+        .map_err(DbError::Aborted)?;
+    if let Ok(Value::Program(p)) = odb.get_slot(oid, oid, String::from(MIGRATE_VERB)).await {
+        info!(
+            "Migrating {:?} from version {} to {}",
+            oid, old_version, version
+        );
+        let limits = execution_limits(world, odb, oid).await;
+        let args = Value::Vector(vec![Value::I64(old_version)]);
+        let result = vm.execute(tr, (oid, MIGRATE_VERB), &p, &args, limits).await;
+        commit_unless_failed(result)?;
+    }
+    Ok(())
+}
+
+pub(super) async fn slot_version(odb: &ObjDBTxHandle<'_>, oid: Oid) -> Option<i64> {
+    match odb.get_slot(oid, oid, String::from(VERSION_SLOT)).await {
+        Ok(Value::I64(version)) => Some(version),
+        Ok(Value::I32(version)) => Some(version as i64),
+        _ => None,
+    }
+}
+
+// A verb which failed, or ran out of resources, is abandoned, along with everything it wrote.
+pub(super) fn commit_unless_failed(result: Result<Value, Error>) -> Result<Value, DbError> {
+    match result {
+        Ok(Value::Error(ResourceLimit)) => Err(DbError::Aborted(ResourceLimit)),
+        Ok(result) => Ok(result),
+        Err(e) => {
+            error!("Verb failed: {}", e);
+            Err(DbError::Trapped(Box::new(VerbError::of(&e))))
+        }
+    }
+}
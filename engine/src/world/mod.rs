@@ -0,0 +1,1419 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Error};
+use bytes::Bytes;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Semaphore;
+use tokio::time::MissedTickBehavior;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{error, info, info_span, warn, Instrument};
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::Message;
+use uuid::Uuid;
+
+use crate::archive::{
+    read_archive, ArchiveReport, ArchiveWriter, WorldManifest, ARCHIVE_FORMAT, ARCHIVE_VERSION,
+    DESTROYED_ENTRY, MANIFEST_ENTRY, NAMES_ENTRY,
+};
+use crate::auth::{AuthPolicy, AuthTxHandle, Scope};
+use crate::bandwidth::{BandwidthAction, BandwidthPolicy, FloodPolicy, Meter, Traffic};
+use crate::blob::{holds_blob, BlobTxHandle};
+use crate::builtins::{BuiltinInfo, BuiltinRegistry};
+use crate::calendar::{CalendarEvent, CalendarTxHandle, MAX_OCCURRENCES};
+use crate::catalog::{Catalog, DEFAULT_LOCALE};
+use crate::changes::{
+    ChangeJournalOptions, ChangeRecord, ChangesTxHandle, Versionstamp, CHANGES_BATCH,
+};
+use crate::command::{CommandGrammar, CommandScope};
+use crate::compile::compile;
+use crate::contents::{ContentsTxHandle, LOCATION_SLOT, MAX_NESTING};
+use crate::cooldown::CooldownTxHandle;
+use crate::core::Core;
+use crate::counter::CounterTxHandle;
+use crate::database::{unwatched, Database, DbError, RetryPolicy, RetryStats, Storage, Tx};
+use crate::dependencies::{DependencyReport, DependencyTxHandle, ProgramDependencies};
+use crate::dump::{Dump, DumpTarget};
+#[cfg(feature = "faults")]
+use crate::faults::FaultOptions;
+use crate::fdb_object::{fits_in_slot, object_at, referenced, slot_at, ObjDBTxHandle};
+use crate::federation::{Federation, FederationOptions, Peer, PEERS_SLOT};
+use crate::forms::{FormDefinition, PendingForm, MAX_PENDING_FORMS};
+use crate::gc::{GcOptions, GcPhase, GcProgress, GcRegistry};
+use crate::graph::{object_digest, relationships, GraphReport, GraphState, GraphWriter, Node};
+use crate::hooks::{LifecycleHooks, LifecyclePoint};
+use crate::impersonation::{
+    AuditEntry, AuditEvent, AuditTxHandle, Impersonation, ImpersonationMode, ImpersonationRegistry,
+};
+use crate::journal::{JournalEntry, JournalOptions, JournalTxHandle, PRUNE_BATCH};
+use crate::listeners::{EntryPoint, ListenerRegistry};
+use crate::localtime::{self, TimeSettings, DEFAULT_TIME_ZONE};
+use crate::module_cache::{self, ModuleCache, Preemption};
+use crate::names::{normalize, NameTxHandle};
+use crate::object::{
+    AdminHandle, ObjDBHandle, SlotDef, SlotMeta, SLOT_EXECUTE, SLOT_READ, SLOT_WRITE,
+};
+use crate::object_store::{ObjectStore, MULTIPART_PART_SIZE};
+use crate::outbound::{outbound, Delivery, OutboundPolicy, OutboundReceiver, OutboundSender};
+use crate::patterns::PatternCache;
+use crate::player_stats::{PlayerStats, PlayerStatsTxHandle};
+use crate::preload::PreloadManifest;
+use crate::protocol::{encode_form, encode_form_problems, FormAnswers, Inbound, Request, Response};
+use crate::pubsub::{PubSubTxHandle, EVENT_BATCH, EVENT_VERB};
+use crate::quota::{stored_size, QuotaPolicy, QuotaTxHandle, OWNER_SLOT};
+use crate::redact::RedactionPolicy;
+use crate::refactor::{ChangeStatus, Refactor, RefactorReport, SlotChange};
+use crate::resume::{ResumeKey, ResumeOptions};
+use crate::schedule::{self, CronSchedule};
+use crate::search::{SearchTxHandle, SEARCH_LIMIT};
+use crate::sequence::{SequenceCache, SequenceTxHandle, SEQUENCE_BATCH};
+use crate::sessions::{
+    redact_address, unix_secs, SessionBinding, SessionRecord, SessionTxHandle, SINGLE_NODE,
+};
+use crate::tags::TagTxHandle;
+use crate::tasks::{TaskInfo, TaskRegistry, DEFAULT_TASKS_PER_CONNECTION};
+use crate::totp::{self, TotpRecord, TotpTxHandle};
+use crate::trace::{VerbStats, VerbTracer};
+use crate::verb_audit::{InvocationRecord, VerbAuditOptions, VerbAuditTxHandle};
+use crate::verb_cache::{self, VerbResultCache, CACHE_TTL_SLOT};
+use crate::verb_error::{for_client, is_error_map, VerbError};
+use crate::wasm_vm::{
+    DryRun, ExecutionLimits, WasmVM, DEFAULT_FUEL_LIMIT, DEFAULT_MEMORY_LIMIT, DEFAULT_TIME_LIMIT,
+};
+use crate::watch::{WatchIndex, WatchTxHandle};
+use value::Error::{
+    BadType, InternalError, InvalidProgram, NameTaken, NoError, PermissionDenied, QuotaExceeded,
+    ResourceLimit, SlotDoesNotExist,
+};
+
+use crate::fdb_object::FdbOid;
+use value::{encode_frame, Oid, Program, ProgramLang, Value};
+
+mod calendar;
+mod connections;
+mod error;
+mod events;
+mod gc;
+mod invocation;
+mod movement;
+mod persistence;
+mod sessions;
+mod slots;
+
+pub use calendar::*;
+pub use connections::*;
+pub use error::*;
+pub use events::*;
+pub use gc::*;
+pub use invocation::*;
+pub use movement::*;
+pub use persistence::*;
+pub use sessions::*;
+pub use slots::*;
+
+type PeerMap = Arc<Mutex<HashMap<Oid, Connection>>>;
+
+/// Runtime options for the world, usually populated from the command line.
+#[derive(Clone, Debug, Default)]
+pub struct WorldOptions {
+    /// If set, the return value of the 'receive' verb is sent back to the connection which
+    /// invoked it.
+    pub echo_results: bool,
+
+    /// Which storage backend to keep the world in.
+    pub storage: Storage,
+
+    /// If set, messages sent to connections are journaled for later audit.
+    pub journal: Option<JournalOptions>,
+
+    /// If set, every verb invocation is recorded on an audit trail for later investigation.
+    pub verb_audit: Option<VerbAuditOptions>,
+
+    /// If set, websocket connections which drop are kept for their clients to resume.
+    pub resume: Option<ResumeOptions>,
+
+    /// How many messages may be queued for each connection, and what's done with more.
+    pub outbound: OutboundPolicy,
+
+    /// Throttling of failed login attempts.
+    pub auth: AuthPolicy,
+
+    /// Limits on the objects each player may own and the bytes they may store.
+    pub quotas: QuotaPolicy,
+
+    /// Cap on the total size of cached compiled programs, in bytes. The default if None.
+    pub module_cache_capacity: Option<u64>,
+
+    /// Fuel each verb invocation may consume before it's aborted, unless its object overrides it
+    /// with a 'fuel_limit' slot. The default if None.
+    pub fuel_limit: Option<u64>,
+
+    /// Bytes of memory a verb invocation may grow its instance to, unless its object overrides it
+    /// with a 'memory_limit' slot. The default if None.
+    pub memory_limit: Option<usize>,
+
+    /// How running verbs are preempted: by fuel, when `fuel_limit` limits them, or by epoch, when
+    /// `time_limit` does.
+    pub preemption: Preemption,
+
+    /// Time each verb invocation may take before it's aborted, when verbs are preempted by epoch,
+    /// unless its object overrides it with a 'time_limit_ms' slot. The default if None.
+    pub time_limit: Option<Duration>,
+
+    /// Caps on each connection's bandwidth.
+    pub bandwidth: BandwidthPolicy,
+
+    /// Limits on how fast each connection may send messages.
+    pub flood: FloodPolicy,
+
+    /// Faults to inject, for resilience testing only.
+    #[cfg(feature = "faults")]
+    pub faults: Option<FaultOptions>,
+
+    /// If set, a line is logged with what each verb invocation cost as it finishes.
+    pub trace_verbs: bool,
+
+    /// If set, the slots each verb invocation reads and writes are counted, by program, for
+    /// dependency analysis.
+    pub record_dependencies: bool,
+
+    /// If set, which slots refer to which objects is indexed as slots are set, so that
+    /// `find_references` can answer what still points at an object.
+    pub reference_index: bool,
+
+    /// What garbage collection keeps, and how often it runs by itself.
+    pub gc: GcOptions,
+
+    /// If set, every change to a slot is journaled, in the order the changes took effect, for
+    /// `journal_since` to follow.
+    pub changes: Option<ChangeJournalOptions>,
+
+    /// The other worlds this one links to, whose objects verbs here may be invoked on.
+    pub federation: FederationOptions,
+
+    /// How transactions which conflict, or time out, are retried.
+    pub retry: RetryPolicy,
+
+    /// Directory of translations to add to the built in text catalog.
+    pub catalog: Option<PathBuf>,
+
+    /// If set, a dump which doesn't match its manifest isn't loaded at all, rather than loaded
+    /// as far as it can be.
+    pub strict_load: bool,
+
+    /// How Values are shown in the log.
+    pub redaction: RedactionPolicy,
+
+    /// The time zone times are shown to players in, unless they've chosen their own.
+    /// DEFAULT_TIME_ZONE if None.
+    pub time_zone: Option<Tz>,
+
+    /// The locale text and times are given in to players who haven't chosen one, and whose
+    /// clients didn't ask for one. DEFAULT_LOCALE if None.
+    pub default_locale: Option<String>,
+
+    /// How often websocket connections are pinged, so that those which have gone away are noticed.
+    /// Never if None.
+    pub ping_interval: Option<Duration>,
+
+    /// How long a connection may send nothing at all (pongs included) before it's closed, after
+    /// running the system object's 'on_idle_disconnect' verb. Never if None.
+    pub idle_timeout: Option<Duration>,
+
+    /// How many messages from one connection may be handled at once.
+    /// DEFAULT_TASKS_PER_CONNECTION if None.
+    pub tasks_per_connection: Option<usize>,
+
+    /// What this server is called in the records of the sessions it serves. Required of servers
+    /// sharing FoundationDB, so that they don't take each other's sessions for their own;
+    /// SINGLE_NODE if None.
+    pub node: Option<String>,
+
+    /// If set, lines of text from logged in players are read as commands with this grammar, and
+    /// run as the verbs they name, before (and instead of) being given to 'receive'.
+    pub command_parser: Option<CommandGrammar>,
+}
+
+// Owns the database and WASM runtime, and hosts methods for accessing the world.
+pub struct World {
+    database: Database,
+    module_cache: ModuleCache,
+    patterns: PatternCache,
+    verb_results: VerbResultCache,
+    tasks: TaskRegistry,
+    impersonations: ImpersonationRegistry,
+    listeners: ListenerRegistry,
+    gc: GcRegistry,
+    resume_key: ResumeKey,
+    builtins: BuiltinRegistry,
+    tracer: VerbTracer,
+    catalog: Catalog,
+    hooks: Mutex<Vec<Arc<dyn LifecycleHooks>>>,
+    sequences: SequenceCache,
+    peer_map: PeerMap,
+    // Messages waiting to be sent later, by the handles given out for them.
+    delayed_sends: Mutex<HashMap<Oid, DelayedSend>>,
+    // Bytes moved by every connection since startup, in total and while logged in to each player.
+    traffic: Mutex<Traffic>,
+    player_traffic: Mutex<HashMap<Oid, Traffic>>,
+    watches: WatchIndex,
+    // When the world was last saved or checkpointed, since startup.
+    last_backup: Mutex<Option<SystemTime>>,
+    federation: Federation,
+    options: WorldOptions,
+}
+
+pub struct Connection {
+    address: SocketAddr,
+    // The listener it connected to, and where its messages are given.
+    listener: String,
+    entry: EntryPoint,
+    sender: OutboundSender,
+    vm: Arc<WasmVM>,
+    // The account whose password this connection has given, while it awaits a second factor.
+    pending_login: Option<Oid>,
+    // The account this connection has logged in to, and when.
+    player: Option<Oid>,
+    logged_in_at: Option<Instant>,
+    // The locale the client asked for text in, if any.
+    locale: Option<String>,
+    connected_at: SystemTime,
+    // When the connection last sent a message or request, and when it last sent anything at all.
+    last_activity: SystemTime,
+    last_heard: Instant,
+    traffic: Traffic,
+    inbound: Meter,
+    outbound: Meter,
+    // Messages and bytes allowed in under the flood limits, and how many messages in a row have
+    // been refused.
+    flood_messages: Meter,
+    flood_bytes: Meter,
+    flood_strikes: u32,
+    // Slots for the messages being handled at once, and VMs left over from earlier messages to
+    // handle them with.
+    tasks: Arc<Semaphore>,
+    idle_vms: Vec<Arc<WasmVM>>,
+    // Whether the connection speaks the structured protocol, and the forms it's been sent and
+    // hasn't answered yet.
+    structured: bool,
+    forms: HashMap<Oid, PendingForm>,
+    // Whether it's another world, connected with the federation token.
+    federated: bool,
+    // Since when its client has been gone, and the messages sent to it meanwhile, while it's
+    // awaiting being resumed.
+    detached: Option<Detached>,
+    // The nonce of the last resume token it was given, until that's used.
+    resume_nonce: Option<Uuid>,
+}
+
+struct Detached {
+    since: Instant,
+    buffer: OutboundReceiver,
+}
+
+impl World {
+    pub fn new(options: WorldOptions) -> Self {
+        let database = Database::open(&options.storage)
+            .expect("Could not open database")
+            .with_reference_index(options.reference_index)
+            .with_change_journal(options.changes.is_some())
+            .with_retry_policy(options.retry);
+        #[cfg(feature = "faults")]
+        let database = database.with_faults(options.faults.clone());
+        let module_cache = ModuleCache::new(
+            options
+                .module_cache_capacity
+                .unwrap_or(module_cache::DEFAULT_CAPACITY_BYTES),
+            options.preemption,
+        );
+
+        let catalog = match &options.catalog {
+            Some(dir) => Catalog::load(dir).expect("Could not load text catalog"),
+            None => Catalog::default(),
+        };
+
+        World {
+            database,
+            module_cache,
+            patterns: Default::default(),
+            verb_results: Default::default(),
+            tasks: Default::default(),
+            impersonations: Default::default(),
+            listeners: Default::default(),
+            gc: Default::default(),
+            resume_key: Default::default(),
+            builtins: Default::default(),
+            tracer: VerbTracer::new(options.trace_verbs),
+            catalog,
+            hooks: Default::default(),
+            sequences: Default::default(),
+            peer_map: Arc::new(Mutex::new(Default::default())),
+            delayed_sends: Default::default(),
+            traffic: Default::default(),
+            player_traffic: Default::default(),
+            watches: Default::default(),
+            last_backup: Default::default(),
+            federation: Federation::new(&options.federation),
+            options,
+        }
+    }
+
+    /// Have `hooks` run at each point in the server's lifecycle, after those added already.
+    pub fn add_hooks(&self, hooks: Arc<dyn LifecycleHooks>) {
+        self.hooks.lock().unwrap().push(hooks);
+    }
+
+    /// Compiled programs, shared by every connection.
+    pub fn module_cache(&self) -> &ModuleCache {
+        &self.module_cache
+    }
+
+    /// Compiled regular expressions, shared by every connection.
+    pub fn patterns(&self) -> &PatternCache {
+        &self.patterns
+    }
+
+    /// When the world was last saved or checkpointed, if it has been since the server started.
+    pub fn last_backup(&self) -> Option<SystemTime> {
+        *self.last_backup.lock().unwrap()
+    }
+
+    /// The messages and requests being handled now.
+    pub fn tasks(&self) -> &TaskRegistry {
+        &self.tasks
+    }
+
+    /// Admins' connections attached to players' perspectives.
+    pub fn impersonations(&self) -> &ImpersonationRegistry {
+        &self.impersonations
+    }
+
+    /// The listeners connections are accepted on, and their entry points.
+    pub fn listeners(&self) -> &ListenerRegistry {
+        &self.listeners
+    }
+
+    /// Whether which slots refer to which objects is indexed as they're set.
+    pub fn indexes_references(&self) -> bool {
+        self.options.reference_index
+    }
+
+    /// The garbage collection sweep going on, or the last one.
+    pub fn gc(&self) -> &GcRegistry {
+        &self.gc
+    }
+
+    /// The changes made to slots after `since`, in the order they took effect, and then those
+    /// made from then on, as they're made. The stream ends only if the journal can't be read, with
+    /// why. Nothing's found unless changes are being journaled.
+    pub fn journal_since(
+        self: &Arc<Self>,
+        since: Versionstamp,
+    ) -> impl Stream<Item = Result<ChangeRecord, Error>> + Send + 'static {
+        let world = self.clone();
+        futures::stream::unfold(Some((since, VecDeque::new())), move |state| {
+            let world = world.clone();
+            async move {
+                let (mut cursor, mut pending) = state?;
+                loop {
+                    if let Some(change) = pending.pop_front() {
+                        return Some((Ok(change), Some((cursor, pending))));
+                    }
+                    let read = world
+                        .run(|tr| async move {
+                            let changes = ChangesTxHandle::new(&tr);
+                            let batch = changes.since(cursor, CHANGES_BATCH).await?;
+                            // Set up with the read, so that nothing committed since is missed.
+                            let watch = batch.is_empty().then(|| changes.watch());
+                            Ok((batch, watch))
+                        })
+                        .await;
+                    match read {
+                        Ok((batch, None)) => {
+                            cursor = batch.last().map_or(cursor, |c| c.versionstamp);
+                            pending.extend(batch);
+                        }
+                        // Looked again after a while regardless, in case the watch was missed.
+                        Ok((_, Some(watch))) => {
+                            let _ = tokio::time::timeout(CHANGES_POLL_INTERVAL, watch).await;
+                        }
+                        Err(e) => return Some((Err(e.into()), None)),
+                    }
+                }
+            }
+        })
+    }
+
+    /// The String slots holding every word of `query`, on objects which opt in to search, with
+    /// the objects they're on; up to SEARCH_LIMIT of them.
+    pub async fn search(
+        self: &Arc<Self>,
+        query: &str,
+    ) -> Result<impl Stream<Item = (Oid, SlotDef)>, Error> {
+        let slots = self
+            .run(|tr| async move {
+                SearchTxHandle::new(&tr)
+                    .search(query, SEARCH_LIMIT)
+                    .collect::<Result<Vec<SlotDef>, DbError>>()
+                    .await
+            })
+            .await?;
+        Ok(tokio_stream::iter(
+            slots.into_iter().map(|slot| (slot.location, slot)),
+        ))
+    }
+
+    /// The counter `name` on `oid`, as last committed; zero if it's never been added to.
+    pub async fn counter_get(&self, oid: Oid, name: &str) -> Result<i64, Error> {
+        let value = self
+            .run(|tr| async move { CounterTxHandle::new(&tr).get(oid, name).await })
+            .await?;
+        Ok(value)
+    }
+
+    /// The results of verbs marked cacheable.
+    pub fn verb_results(&self) -> &VerbResultCache {
+        &self.verb_results
+    }
+
+    /// The host functions verbs can call.
+    pub fn builtins(&self) -> &BuiltinRegistry {
+        &self.builtins
+    }
+
+    /// Text for messages to clients, in their locales.
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    /// The other worlds this one links to.
+    pub fn federation(&self) -> &Federation {
+        &self.federation
+    }
+
+    /// What this server is called in session records.
+    pub fn node(&self) -> &str {
+        self.options.node.as_deref().unwrap_or(SINGLE_NODE)
+    }
+
+    /// How Values must be shown in the log.
+    pub fn redaction(&self) -> &RedactionPolicy {
+        &self.options.redaction
+    }
+
+    /// Where verb invocations are recorded as they finish.
+    pub fn tracer(&self) -> &VerbTracer {
+        &self.tracer
+    }
+
+    /// What each verb invoked since startup has cost in total, the most expensive first.
+    pub fn stats(&self) -> Vec<(Oid, String, VerbStats)> {
+        self.tracer.stats()
+    }
+
+    /// Faults to inject, if the world is being tested for resilience.
+    /// Whether the slots verbs read and write are being recorded.
+    pub fn records_dependencies(&self) -> bool {
+        self.options.record_dependencies
+    }
+
+    #[cfg(feature = "faults")]
+    pub fn faults(&self) -> Option<&FaultOptions> {
+        self.options.faults.as_ref()
+    }
+
+    /// The number of transactions committed since startup.
+    pub fn transactions(&self) -> u64 {
+        self.database.committed()
+    }
+
+    /// How often transactions have been retried, and given up on, since startup.
+    pub fn transaction_retries(&self) -> RetryStats {
+        self.database.retry_stats()
+    }
+
+    // Run `f` in a transaction, as the database's `run` does, failing with a WorldError, so that
+    // callers can tell whether to try again, and why it was abandoned if it was.
+    async fn run<T, F, Fut>(&self, f: F) -> Result<T, WorldError>
+    where
+        F: FnMut(Tx) -> Fut,
+        Fut: Future<Output = Result<T, DbError>>,
+    {
+        self.database.run(f).await.map_err(WorldError::from)
+    }
+
+    // Run `f` in a transaction which is abandoned rather than committed.
+    async fn run_uncommitted<T, F, Fut>(&self, f: F) -> Result<T, WorldError>
+    where
+        F: FnOnce(Tx) -> Fut,
+        Fut: Future<Output = Result<T, DbError>>,
+    {
+        self.database
+            .run_uncommitted(f)
+            .await
+            .map_err(WorldError::from)
+    }
+
+    // Make sure everything committed so far is on disk.
+    async fn flush(&self) -> Result<(), WorldError> {
+        self.database.flush().await.map_err(WorldError::from)
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.peer_map.lock().unwrap().len()
+    }
+
+    /// Bytes received and sent over all connections since startup.
+    pub fn traffic(&self) -> Traffic {
+        *self.traffic.lock().unwrap()
+    }
+
+    /// Bytes received and sent over connections logged in to each player since startup.
+    pub fn player_traffic(&self) -> Vec<(Oid, Traffic)> {
+        let player_traffic = self.player_traffic.lock().unwrap();
+        player_traffic.iter().map(|(p, t)| (*p, *t)).collect()
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new(WorldOptions::default())
+    }
+}
+
+// The slot and object functions below act within the transaction of the verb calling them, so
+// that its writes are committed, or abandoned, together.
+
+// How long following the change journal waits for changes before looking again anyway.
+const CHANGES_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often changes past their retention are pruned.
+const CHANGES_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Up to `limit` of the changes made to slots after `since`, in the order they took effect.
+pub async fn changes_since(
+    world: &Arc<World>,
+    since: Versionstamp,
+    limit: usize,
+) -> Result<Vec<ChangeRecord>, Error> {
+    let changes = world
+        .run(|tr| async move { ChangesTxHandle::new(&tr).since(since, limit).await })
+        .await?;
+    Ok(changes)
+}
+
+// How often messages past their retention are pruned from the journal.
+const JOURNAL_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delete journaled messages as they pass the retention `options` give, every so often, a batch
+/// at a time.
+pub async fn prune_journal_every(world: Arc<World>, options: JournalOptions) {
+    let mut ticks = tokio::time::interval(JOURNAL_PRUNE_INTERVAL);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let cutoff = match SystemTime::now().checked_sub(options.retention) {
+            Some(cutoff) => cutoff,
+            None => continue,
+        };
+        let mut pruned = 0;
+        loop {
+            match world
+                .run(|tr| async move { JournalTxHandle::new(&tr).prune(cutoff, PRUNE_BATCH).await })
+                .await
+            {
+                Ok(batch) => {
+                    pruned += batch;
+                    if batch == PRUNE_BATCH {
+                        continue;
+                    }
+                }
+                Err(e) => error!("Could not prune journaled messages: {}", e),
+            }
+            break;
+        }
+        if pruned > 0 {
+            info!("Pruned {} journaled messages", pruned);
+        }
+    }
+}
+
+// How often verb audit records past their retention are pruned.
+const VERB_AUDIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delete verb audit records as they pass the retention `options` give, every so often, a batch
+/// at a time.
+pub async fn prune_verb_audit_every(world: Arc<World>, options: VerbAuditOptions) {
+    let mut ticks = tokio::time::interval(VERB_AUDIT_PRUNE_INTERVAL);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let cutoff = match SystemTime::now().checked_sub(options.retention) {
+            Some(cutoff) => cutoff,
+            None => continue,
+        };
+        let mut pruned = 0;
+        loop {
+            match world
+                .run(|tr| async move { VerbAuditTxHandle::new(&tr).prune(cutoff, PRUNE_BATCH).await })
+                .await
+            {
+                Ok(batch) => {
+                    pruned += batch;
+                    if batch == PRUNE_BATCH {
+                        continue;
+                    }
+                }
+                Err(e) => error!("Could not prune verb audit records: {}", e),
+            }
+            break;
+        }
+        if pruned > 0 {
+            info!("Pruned {} verb audit records", pruned);
+        }
+    }
+}
+
+/// Delete journaled changes as they pass the retention `options` give, every so often, a batch
+/// at a time.
+pub async fn prune_changes_every(world: Arc<World>, options: ChangeJournalOptions) {
+    let mut ticks = tokio::time::interval(CHANGES_PRUNE_INTERVAL);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let cutoff = match SystemTime::now().checked_sub(options.retention) {
+            Some(cutoff) => cutoff,
+            None => continue,
+        };
+        let mut pruned = 0;
+        loop {
+            match world
+                .run(|tr| async move { ChangesTxHandle::new(&tr).prune(cutoff, PRUNE_BATCH).await })
+                .await
+            {
+                Ok(batch) => {
+                    pruned += batch;
+                    if batch == PRUNE_BATCH {
+                        continue;
+                    }
+                }
+                Err(e) => error!("Could not prune journaled changes: {}", e),
+            }
+            break;
+        }
+        if pruned > 0 {
+            info!("Pruned {} journaled changes", pruned);
+        }
+    }
+}
+
+/// Up to `len` bytes of the Binary value in `slot`, from `offset`, whether it's stored as a blob or
+/// not. SlotDoesNotExist if there's no such slot, BadType if it isn't Binary.
+pub async fn read_blob(tr: &Tx, slot: SlotDef, offset: u64, len: usize) -> Result<Value, Error> {
+    let odb = ObjDBTxHandle::new(tr);
+    match odb
+        .get_slot(slot.location, slot.key, slot.name.clone())
+        .await
+    {
+        Ok(Value::Blob(_)) => {
+            let data = BlobTxHandle::new(tr).read(&slot, offset, len).await?;
+            Ok(Value::Binary(data.into()))
+        }
+        Ok(Value::Binary(data)) => {
+            let start = (offset as usize).min(data.len());
+            let end = start.saturating_add(len).min(data.len());
+            Ok(Value::Binary(data.slice(start..end)))
+        }
+        Ok(_) => Ok(Value::Error(BadType)),
+        Err(_) => Ok(Value::Error(SlotDoesNotExist)),
+    }
+}
+
+/// Mint a new object, `oid` (which should be freshly made up), optionally claiming a unique name
+/// for it. Returns the new object's IdKey, or NameTaken if the name is already held.
+/// An object made for a player, `owner`, counts against their quota (QuotaExceeded if it would
+/// take them over), and has an 'owner' slot naming them. Objects otherwise exist only as the slots
+/// set on them, so nothing more is stored until the first one is.
+pub async fn create_object(
+    world: &Arc<World>,
+    tr: &Tx,
+    oid: Oid,
+    name: Option<&str>,
+    owner: Option<Oid>,
+) -> Result<Value, Error> {
+    let quota = QuotaTxHandle::new(tr);
+    if let Some(owner) = owner {
+        let usage = quota.usage(owner, &world.options.quotas).await?;
+        if usage.objects_exceeded() {
+            return Ok(Value::Error(QuotaExceeded));
+        }
+    }
+    if let Some(name) = name {
+        if normalize(name).is_empty() {
+            return Ok(Value::Error(BadType));
+        }
+        if !NameTxHandle::new(tr).claim(oid, name).await? {
+            return Ok(Value::Error(NameTaken));
+        }
+    }
+    if let Some(owner) = owner {
+        quota.created(owner, oid);
+    }
+    Ok(Value::IdKey(oid))
+}
+
+/// What the player `oid` owns, and their quotas, as `Usage::info` gives them.
+pub async fn quota_usage(world: &Arc<World>, tr: &Tx, oid: Oid) -> Result<Value, Error> {
+    let usage = QuotaTxHandle::new(tr)
+        .usage(oid, &world.options.quotas)
+        .await?;
+    Ok(usage.info())
+}
+
+/// Destroy an object, for `connection`, removing all of its slots and releasing its name.
+/// PermissionDenied unless the connection may alter it (see `may_alter`).
+pub async fn destroy_object(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    oid: Oid,
+) -> Result<Value, Error> {
+    if !may_alter(world, tr, connection, oid).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    let odb = ObjDBTxHandle::new(tr);
+    let cdb = ContentsTxHandle::new(tr);
+    if let Some(location) = location_of(&odb, oid).await {
+        cdb.remove(location, oid);
+    }
+    cdb.clear_location(oid);
+    QuotaTxHandle::new(tr).destroyed(oid).await?;
+    odb.destroy_object(oid);
+    NameTxHandle::new(tr).release(oid).await?;
+    CooldownTxHandle::new(tr).clear_object(oid);
+    CounterTxHandle::new(tr).clear_object(oid);
+    TagTxHandle::new(tr).clear_object(oid).await?;
+    PubSubTxHandle::new(tr).clear_object(oid).await?;
+
+    Ok(Value::Error(NoError))
+}
+
+/// How long is left of the cooldown `name` on `oid`. Zero if it's over.
+pub async fn cooldown_check(tr: &Tx, oid: Oid, name: &str) -> Result<Duration, Error> {
+    let remaining = CooldownTxHandle::new(tr)
+        .remaining(oid, name, SystemTime::now())
+        .await?;
+    Ok(remaining)
+}
+
+/// Start the cooldown `name` on `oid`, to last `duration`, replacing any already running.
+pub fn cooldown_set(tr: &Tx, oid: Oid, name: &str, duration: Duration) -> Value {
+    CooldownTxHandle::new(tr).set(oid, name, duration, SystemTime::now());
+    Value::Error(NoError)
+}
+
+/// Add `delta` to the counter `name` on `oid`, without conflicting with anything else adding to
+/// it.
+pub fn counter_incr(tr: &Tx, oid: Oid, name: &str, delta: i64) -> Value {
+    CounterTxHandle::new(tr).incr(oid, name, delta);
+    Value::Error(NoError)
+}
+
+/// The counter `name` on `oid`, as of the transaction; zero if it's never been added to.
+pub async fn counter_get(tr: &Tx, oid: Oid, name: &str) -> Result<i64, Error> {
+    Ok(CounterTxHandle::new(tr).get(oid, name).await?)
+}
+
+/// Tag `oid` with `tag`. BadType if the tag is empty.
+pub fn tag_add(tr: &Tx, oid: Oid, tag: &str) -> Value {
+    if tag.is_empty() {
+        return Value::Error(BadType);
+    }
+    TagTxHandle::new(tr).add(oid, tag);
+    Value::Error(NoError)
+}
+
+/// The next id of the sequence `name`. Each reservation of ids is committed on its own, so ids
+/// taken by a verb which is then abandoned aren't handed out again.
+pub async fn next_id(world: &Arc<World>, name: &str) -> Result<Value, Error> {
+    if name.is_empty() {
+        return Ok(Value::Error(BadType));
+    }
+    let id = world
+        .sequences
+        .next(name, || async {
+            let range = world
+                .run(|tr| async move {
+                    SequenceTxHandle::new(&tr)
+                        .reserve(name, SEQUENCE_BATCH)
+                        .await
+                })
+                .await?;
+            Ok(range)
+        })
+        .await?;
+    Ok(Value::I64(id))
+}
+
+/// Remove `tag` from `oid`, if it has it.
+pub fn tag_remove(tr: &Tx, oid: Oid, tag: &str) -> Value {
+    TagTxHandle::new(tr).remove(oid, tag);
+    Value::Error(NoError)
+}
+
+/// Every object tagged with `tag`, as a Vector of IdKeys.
+pub async fn tag_query(tr: &Tx, tag: &str) -> Result<Value, Error> {
+    let oids = TagTxHandle::new(tr).query(tag).await?;
+    Ok(Value::Vector(oids.into_iter().map(Value::IdKey).collect()))
+}
+
+/// The tags on `oid`, as a Vector of Strings.
+pub async fn tags_of(tr: &Tx, oid: Oid) -> Result<Value, Error> {
+    let tags = TagTxHandle::new(tr).tags_of(oid).await?;
+    Ok(Value::Vector(
+        tags.into_iter()
+            .map(|tag| Value::String(tag.into()))
+            .collect(),
+    ))
+}
+
+/// The objects tagged with `tag`, for administration.
+pub async fn tagged_objects(world: &Arc<World>, tag: &str) -> Result<Vec<Oid>, Error> {
+    let oids = world
+        .run(|tr| async move { TagTxHandle::new(&tr).query(tag).await })
+        .await?;
+    Ok(oids)
+}
+
+/// Whether `name` is free to be claimed.
+pub async fn name_available(tr: &Tx, name: &str) -> Result<bool, Error> {
+    if normalize(name).is_empty() {
+        return Ok(false);
+    }
+    let holder = NameTxHandle::new(tr).holder(name).await?;
+    Ok(holder.is_none())
+}
+
+/// Atomically give `oid` the unique name `name`, releasing the one it held before.
+/// Returns NameTaken, leaving the old name in place, if another object holds the new one.
+pub async fn rename_object(tr: &Tx, oid: Oid, name: &str) -> Result<Value, Error> {
+    if normalize(name).is_empty() {
+        return Ok(Value::Error(BadType));
+    }
+    let claimed = NameTxHandle::new(tr).claim(oid, name).await?;
+
+    match claimed {
+        true => Ok(Value::Error(NoError)),
+        false => Ok(Value::Error(NameTaken)),
+    }
+}
+
+/// The usage counters kept for `player`, as a Vector of [name, value] pairs, for verbs.
+pub async fn player_stats_value(tr: &Tx, player: Oid) -> Result<Value, Error> {
+    let stats = PlayerStatsTxHandle::new(tr).get(player).await?;
+    Ok(stats.to_value())
+}
+
+/// The slots verbs have been recorded reading and writing, by program, the most invoked first;
+/// forgetting them if `clear` is set, so that recording starts afresh. The counters are read a
+/// page at a time, so the report isn't of a single moment if verbs are being recorded meanwhile.
+pub async fn dependency_report(
+    world: &Arc<World>,
+    clear: bool,
+) -> Result<Vec<ProgramDependencies>, Error> {
+    let mut report = DependencyReport::default();
+    let mut after = None;
+    loop {
+        let from = &after;
+        let page = world
+            .run(|tr| async move {
+                DependencyTxHandle::new(&tr)
+                    .counters(from.clone(), PAGE_SIZE)
+                    .await
+            })
+            .await?;
+        let more = page.len() == PAGE_SIZE;
+        after = page.last().map(|(key, _)| key.clone());
+        for (key, value) in page {
+            report.add(key, value);
+        }
+        if !more {
+            break;
+        }
+    }
+    if clear {
+        world
+            .run(|tr| async move {
+                DependencyTxHandle::new(&tr).clear();
+                Ok(())
+            })
+            .await?;
+    }
+    Ok(report.finish())
+}
+
+/// The usage counters kept for `player`.
+pub async fn player_stats(world: &Arc<World>, player: Oid) -> Result<PlayerStats, Error> {
+    let stats = world
+        .run(|tr| async move { PlayerStatsTxHandle::new(&tr).get(player).await })
+        .await?;
+    Ok(stats)
+}
+
+/// Put `record` on the verb audit trail, if the world keeps one: in `tr`, the transaction of the
+/// dispatch it records, so that it's committed along with what the verb did. A dispatch which
+/// failed has its transaction abandoned, so its record is written in a transaction of its own,
+/// to keep the failure on record.
+pub fn audit_invocation(world: &Arc<World>, tr: &Tx, record: InvocationRecord) {
+    if world.options.verb_audit.is_none() {
+        return;
+    }
+    if !record.outcome.abandons() {
+        VerbAuditTxHandle::new(tr).append(&record);
+        return;
+    }
+    let world = world.clone();
+    tokio::spawn(async move {
+        let record = &record;
+        let written = world
+            .run(|tr| async move {
+                VerbAuditTxHandle::new(&tr).append(record);
+                Ok(())
+            })
+            .await;
+        if let Err(e) = written {
+            error!(
+                "Could not audit {:?}:{}: {:?}",
+                record.target, record.verb, e
+            );
+        }
+    });
+}
+
+/// Retrieve the verb invocations audited between `from` and `to`: all of them, or, given
+/// `object`, those of its verbs and those its verbs made. They're read a page at a time, so this
+/// also gives where to read on from, if there are more.
+pub async fn query_verb_audit(
+    world: &Arc<World>,
+    object: Option<Oid>,
+    from: SystemTime,
+    to: SystemTime,
+) -> Result<(Vec<InvocationRecord>, Option<SystemTime>), Error> {
+    let records = world
+        .run(|tr| async move { VerbAuditTxHandle::new(&tr).records(object, from, to).await })
+        .await?;
+    Ok(records)
+}
+
+/// Retrieve the journaled messages sent to `player` between `from` and `to` (or to a connection,
+/// while it wasn't logged in).
+pub async fn query_journal(
+    world: &Arc<World>,
+    player: Oid,
+    from: SystemTime,
+    to: SystemTime,
+) -> Result<Vec<JournalEntry>, Error> {
+    let entries = world
+        .run(|tr| async move { JournalTxHandle::new(&tr).entries(player, from, to).await })
+        .await?;
+    Ok(entries)
+}
+
+/// Everything held about a player, for handing over to them on request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlayerExport {
+    pub player: Oid,
+    pub exported_at: SystemTime,
+    /// The player's unique name, if they hold one.
+    pub name: Option<String>,
+    /// Slots located on the player, or defined with the player as their key.
+    pub slots: Vec<Dump>,
+    /// Messages journaled as sent to the player.
+    pub transcript: Vec<JournalEntry>,
+    /// The usage counters kept for the player.
+    pub stats: PlayerStats,
+    /// The tags on the player.
+    pub tags: Vec<String>,
+}
+
+/// How to treat records which are kept for the integrity of the rest of the world when erasing a
+/// player. Slots belonging to the player are always deleted.
+#[derive(Clone, Copy, Debug)]
+pub enum ErasureMode {
+    Delete,
+    /// Keep journal entries, but with the player's identity and message contents removed.
+    Anonymize,
+}
+
+/// The outcome of erasing a player, including a re-check of the database afterwards.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErasureReport {
+    pub player: Oid,
+    pub slots_removed: usize,
+    /// The objects which had slots removed, and so need dumping again.
+    pub objects: Vec<Oid>,
+    pub journal_entries_erased: usize,
+    pub slots_remaining: usize,
+    pub journal_entries_remaining: usize,
+    pub name_remaining: bool,
+    pub stats_remaining: bool,
+    pub tags_remaining: usize,
+}
+
+impl ErasureReport {
+    /// True if nothing attributable to the player was found after erasure.
+    pub fn verified(&self) -> bool {
+        self.slots_remaining == 0
+            && self.journal_entries_remaining == 0
+            && !self.name_remaining
+            && !self.stats_remaining
+            && self.tags_remaining == 0
+    }
+}
+
+/// Gather all the data associated with `player`.
+pub async fn export_player_data(world: &Arc<World>, player: Oid) -> Result<PlayerExport, Error> {
+    let exported_at = SystemTime::now();
+    let (name, slots, transcript, stats, tags) = world
+        .run(|tr| async move {
+            let name = NameTxHandle::new(&tr).name_of(player).await?;
+            let odb = ObjDBTxHandle::snapshot(&tr);
+            let slots = odb.slots_involving(player).map_err(DbError::Aborted)?;
+            let slots = slots
+                .collect::<Result<Vec<(SlotDef, Value)>, _>>()
+                .await
+                .map_err(DbError::Aborted)?;
+            let slots = with_meta(&odb, slots).await;
+            let transcript = JournalTxHandle::new(&tr)
+                .entries(player, UNIX_EPOCH, exported_at)
+                .await?;
+            let stats = PlayerStatsTxHandle::new(&tr).get(player).await?;
+            let tags = TagTxHandle::new(&tr).tags_of(player).await?;
+            Ok((name, slots, transcript, stats, tags))
+        })
+        .await?;
+
+    Ok(PlayerExport {
+        player,
+        exported_at,
+        name,
+        slots,
+        transcript,
+        stats,
+        tags,
+    })
+}
+
+/// Remove all the data associated with `player`, then check that none remains.
+pub async fn erase_player_data(
+    world: &Arc<World>,
+    player: Oid,
+    mode: ErasureMode,
+) -> Result<ErasureReport, Error> {
+    let (slots_removed, journal_entries_erased, objects) = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            let slots = odb.slots_involving(player).map_err(DbError::Aborted)?;
+            let slots = slots
+                .collect::<Result<Vec<(SlotDef, Value)>, _>>()
+                .await
+                .map_err(DbError::Aborted)?;
+            for (slot_def, _) in &slots {
+                odb.clear_slot(slot_def.clone());
+            }
+            tr.clear(FdbOid(player));
+            NameTxHandle::new(&tr).release(player).await?;
+            TotpTxHandle::new(&tr).remove(player);
+            PlayerStatsTxHandle::new(&tr).remove(player);
+            TagTxHandle::new(&tr).clear_object(player).await?;
+            let erased = JournalTxHandle::new(&tr)
+                .erase(player, matches!(mode, ErasureMode::Anonymize))
+                .await?;
+            let mut objects: Vec<Oid> = slots
+                .iter()
+                .map(|(slot_def, _)| slot_def.location)
+                .collect();
+            objects.push(player);
+            objects.sort_by_key(|oid| oid.id);
+            objects.dedup();
+            Ok((slots.len(), erased, objects))
+        })
+        .await?;
+
+    let export = export_player_data(world, player).await?;
+    let report = ErasureReport {
+        player,
+        slots_removed,
+        objects,
+        journal_entries_erased,
+        slots_remaining: export.slots.len(),
+        journal_entries_remaining: export.transcript.len(),
+        name_remaining: export.name.is_some(),
+        stats_remaining: export.stats != PlayerStats::default(),
+        tags_remaining: export.tags.len(),
+    };
+    info!("Erased player {:?}: {:?}", player, report);
+    Ok(report)
+}
+
+// How many slots, or recorded counters, are read in each transaction when paging through them all.
+const PAGE_SIZE: usize = 1000;
+
+/// Search the source of every program in the world for `refactor`'s pattern, and replace it.
+///
+/// Replacements are staged: every program is first rewritten and compiled without anything being
+/// written, then those which compiled are promoted together in one transaction. A program which
+/// changed in the meantime is left as it is.
+pub async fn refactor_programs(
+    world: &Arc<World>,
+    refactor: &Refactor,
+) -> Result<RefactorReport, Error> {
+    // Read a page at a time; nothing's written until promotion, which checks each program is
+    // still what was read.
+    let mut programs = vec![];
+    let mut after: Option<SlotDef> = None;
+    loop {
+        let from = &after;
+        let page = world
+            .run(|tr| async move {
+                ObjDBTxHandle::snapshot(&tr)
+                    .slots_after(from.clone(), PAGE_SIZE)
+                    .await
+                    .map_err(DbError::Aborted)
+            })
+            .await?;
+        let more = page.len() == PAGE_SIZE;
+        after = page.last().map(|(slot_def, _)| slot_def.clone());
+        programs.extend(
+            page.into_iter()
+                .filter_map(|(slot_def, value)| match value {
+                    Value::Program(source) => Some((slot_def, source)),
+                    _ => None,
+                }),
+        );
+        if !more {
+            break;
+        }
+    }
+
+    // Imports are only resolved when a program's run, so check them against the builtins here.
+    Arc::new(WasmVM::new(world.clone(), None)?).bind_builtins()?;
+    let builtins = world.builtins().list();
+    let unknown_builtin = |module: &wasmtime::Module| {
+        module
+            .imports()
+            .filter(|import| import.module() == "host")
+            .find(|import| !builtins.iter().any(|b| b.name == import.name()))
+            .map(|import| import.name().to_string())
+    };
+
+    let mut report = RefactorReport {
+        pattern: refactor.pattern.to_string(),
+        replacement: refactor.replacement.clone(),
+        applied: refactor.apply,
+        ..Default::default()
+    };
+    let mut staged = vec![];
+    for (slot, source) in programs {
+        let text = match std::str::from_utf8(&source.code) {
+            Ok(text) if source.lang == ProgramLang::Wat => text,
+            _ => {
+                report.binaries_skipped += 1;
+                continue;
+            }
+        };
+        report.programs_scanned += 1;
+        let (matches, lines, replaced) = match refactor.scan(text) {
+            Some(found) => found,
+            None => continue,
+        };
+        let status = match &replaced {
+            None => ChangeStatus::Matched,
+            Some(replaced) => match compile(
+                world.module_cache().engine(),
+                &Program::new(ProgramLang::Wat, replaced.clone()),
+            ) {
+                Ok(module) => match unknown_builtin(&module) {
+                    Some(name) => {
+                        ChangeStatus::CompileFailed(format!("No builtin named '{}'", name))
+                    }
+                    None => ChangeStatus::Compiles,
+                },
+                Err(e) => ChangeStatus::CompileFailed(e.to_string()),
+            },
+        };
+        if let (ChangeStatus::Compiles, Some(replaced)) = (&status, replaced) {
+            let replaced = Program::new(ProgramLang::Wat, replaced);
+            staged.push((report.changes.len(), source, replaced));
+        }
+        report.changes.push(SlotChange {
+            slot,
+            matches,
+            lines,
+            status,
+        });
+    }
+
+    if refactor.apply && !staged.is_empty() {
+        let changes = &report.changes;
+        let staged = &staged;
+        let promoted = world
+            .run(|tr| async move {
+                let odb = ObjDBTxHandle::new(&tr);
+                let mut promoted = vec![];
+                for (i, source, replaced) in staged {
+                    let slot = &changes[*i].slot;
+                    let current = odb
+                        .get_slot(slot.location, slot.key, slot.name.clone())
+                        .await;
+                    let unchanged = matches!(current, Ok(Value::Program(p)) if p == *source);
+                    if unchanged {
+                        odb.set_slot(
+                            slot.location,
+                            slot.key,
+                            slot.name.clone(),
+                            &Value::Program(replaced.clone()),
+                        )
+                        .await
+                        .map_err(DbError::Aborted)?;
+                    }
+                    promoted.push(unchanged);
+                }
+                Ok(promoted)
+            })
+            .await?;
+        for ((i, _, _), unchanged) in staged.iter().zip(promoted) {
+            report.changes[*i].status = match unchanged {
+                true => ChangeStatus::Applied,
+                false => ChangeStatus::Conflicted,
+            };
+        }
+    }
+    info!(
+        "Refactor of /{}/ matched {} of {} programs",
+        report.pattern,
+        report.changes.len(),
+        report.programs_scanned
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A world in memory, with `thing` owned by `owner`, in `place`, which has no owner.
+    async fn world_with_thing(owner: Oid, thing: Oid, place: Oid) -> Arc<World> {
+        let world = Arc::new(World::new(WorldOptions {
+            storage: Storage::Temporary,
+            ..WorldOptions::default()
+        }));
+        let w = &world;
+        world
+            .run(|tr| async move {
+                create_object(w, &tr, thing, None, Some(owner))
+                    .await
+                    .unwrap();
+                move_object(w, &tr, None, thing, place).await.unwrap();
+                Ok(())
+            })
+            .await
+            .unwrap();
+        world
+    }
+
+    fn oid() -> Oid {
+        Oid { id: Uuid::new_v4() }
+    }
+
+    #[tokio::test]
+    async fn foreign_connections_cannot_move_objects() {
+        let (thing, place, elsewhere) = (oid(), oid(), oid());
+        let world = world_with_thing(oid(), thing, place).await;
+        let w = &world;
+        // A connection not logged in as the owner, nor an admin.
+        let connection = Some(oid());
+        let (moved, set, location) = world
+            .run(|tr| async move {
+                let moved = move_object(w, &tr, connection, thing, elsewhere)
+                    .await
+                    .unwrap();
+                let location = Value::IdKey(elsewhere);
+                let set = set_slot(w, &tr, connection, thing, thing, LOCATION_SLOT, &location);
+                let set = set.await.unwrap();
+                let location = location_of(&ObjDBTxHandle::new(&tr), thing).await;
+                Ok((moved, set, location))
+            })
+            .await
+            .unwrap();
+        assert!(matches!(moved, Value::Error(PermissionDenied)));
+        assert!(matches!(set, Value::Error(PermissionDenied)));
+        assert_eq!(location, Some(place));
+
+        // The server itself may.
+        let moved = world
+            .run(|tr| async move { Ok(move_object(w, &tr, None, thing, elsewhere).await.unwrap()) })
+            .await
+            .unwrap();
+        assert!(matches!(moved, Value::Error(NoError)));
+    }
+
+    #[tokio::test]
+    async fn foreign_connections_cannot_destroy_objects() {
+        let (owner, thing, place) = (oid(), oid(), oid());
+        let world = world_with_thing(owner, thing, place).await;
+        let w = &world;
+        let connection = Some(oid());
+        let (destroyed, remaining) = world
+            .run(|tr| async move {
+                let destroyed = destroy_object(w, &tr, connection, thing).await.unwrap();
+                let remaining = QuotaTxHandle::new(&tr).owner_of(thing).await;
+                Ok((destroyed, remaining))
+            })
+            .await
+            .unwrap();
+        assert!(matches!(destroyed, Value::Error(PermissionDenied)));
+        assert_eq!(remaining, Some(owner));
+
+        let (destroyed, remaining) = world
+            .run(|tr| async move {
+                let destroyed = destroy_object(w, &tr, None, thing).await.unwrap();
+                let remaining = QuotaTxHandle::new(&tr).owner_of(thing).await;
+                Ok((destroyed, remaining))
+            })
+            .await
+            .unwrap();
+        assert!(matches!(destroyed, Value::Error(NoError)));
+        assert_eq!(remaining, None);
+    }
+
+    #[tokio::test]
+    async fn failed_transactions_say_whether_to_retry() {
+        let world = World::new(WorldOptions {
+            storage: Storage::Temporary,
+            ..WorldOptions::default()
+        });
+        let aborted = world
+            .run(|_| async move { Err::<(), _>(DbError::Aborted(PermissionDenied)) })
+            .await
+            .unwrap_err();
+        assert_eq!(aborted.abandoned_for(), Some(PermissionDenied));
+        assert!(!aborted.is_retryable());
+
+        let exhausted = WorldError::from(DbError::RetriesExhausted(3));
+        assert_eq!(exhausted.abandoned_for(), Some(value::Error::Contended));
+        assert!(!exhausted.is_retryable());
+        let conflict = WorldError::from(DbError::Conflict);
+        assert_eq!(conflict.abandoned_for(), None);
+        assert!(conflict.is_retryable());
+    }
+}
@@ -0,0 +1,130 @@
+use super::*;
+
+pub(super) async fn location_of(odb: &ObjDBTxHandle<'_>, oid: Oid) -> Option<Oid> {
+    match odb.get_slot(oid, oid, String::from(LOCATION_SLOT)).await {
+        Ok(Value::IdKey(location)) => Some(location),
+        _ => None,
+    }
+}
+
+/// Move `what` into `destination`, for `connection`: set its 'location' slot, and move it from its
+/// old location's contents to the new one's, in one transaction. PermissionDenied unless the
+/// connection may alter `what` (see `may_alter`) and put things in `destination`: one with no
+/// owner, or one it may alter too. BadType if that would put it inside itself, or nest it more
+/// than MAX_NESTING deep.
+pub async fn move_object(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    what: Oid,
+    destination: Oid,
+) -> Result<Value, Error> {
+    let open = QuotaTxHandle::new(tr).owner_of(destination).await.is_none();
+    if !may_alter(world, tr, connection, what).await
+        || !(open || may_alter(world, tr, connection, destination).await)
+    {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    let odb = ObjDBTxHandle::new(tr);
+    let mut at = Some(destination);
+    for _ in 0..MAX_NESTING {
+        match at {
+            Some(oid) if oid == what => return Ok(Value::Error(BadType)),
+            Some(oid) => at = location_of(&odb, oid).await,
+            None => break,
+        }
+    }
+    if at.is_some() {
+        return Ok(Value::Error(BadType));
+    }
+
+    let cdb = ContentsTxHandle::new(tr);
+    if let Some(source) = location_of(&odb, what).await {
+        cdb.remove(source, what);
+    }
+    cdb.add(destination, what);
+    odb.set_slot(
+        what,
+        what,
+        String::from(LOCATION_SLOT),
+        &Value::IdKey(destination),
+    )
+    .await?;
+    Ok(Value::Error(NoError))
+}
+
+/// What's in `location`, as a Vector of IdKeys.
+pub async fn contents_of(tr: &Tx, location: Oid) -> Result<Value, Error> {
+    let contents = ContentsTxHandle::new(tr).contents(location).await?;
+    Ok(Value::Vector(
+        contents.into_iter().map(Value::IdKey).collect(),
+    ))
+}
+
+/// Move a slot to another object, key or name, in one transaction, taking its watchers along.
+/// SlotDoesNotExist if there's no slot at `from`, NameTaken if there's one at `to` already.
+pub async fn move_slot(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    from: SlotDef,
+    to: SlotDef,
+) -> Result<Value, Error> {
+    // That would move an object without the contents index knowing, or disown it.
+    if [&from, &to].iter().any(|slot| {
+        is_location_slot(slot.location, slot.key, &slot.name)
+            || is_owner_slot(slot.location, slot.key, &slot.name)
+    }) {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    for slot in [&from, &to] {
+        if !slot_permitted(world, tr, connection, slot, SLOT_WRITE).await {
+            return Ok(Value::Error(PermissionDenied));
+        }
+    }
+    let odb = ObjDBTxHandle::new(tr);
+    let quota = QuotaTxHandle::new(tr);
+    let (moved, replaced) = (slot_size(&odb, &from).await, slot_size(&odb, &to).await);
+    let (from_owner, to_owner) = (
+        quota.owner_of(from.location).await,
+        quota.owner_of(to.location).await,
+    );
+    if let Some(owner) = to_owner {
+        let mut delta = moved - replaced;
+        if from_owner == Some(owner) {
+            delta -= moved;
+        }
+        if delta > 0 {
+            let usage = quota.usage(owner, &world.options.quotas).await?;
+            if usage.bytes_exceeded(delta) {
+                return Ok(Value::Error(QuotaExceeded));
+            }
+        }
+    }
+    // The index entries under `from` go stale, and are cleared as they're found.
+    if let Ok(value) = odb
+        .get_slot(from.location, from.key, from.name.clone())
+        .await
+    {
+        SearchTxHandle::new(tr).update(&to, &value).await;
+    }
+    if let Err(e) = odb.move_slot(from.clone(), to.clone()).await {
+        return Ok(Value::Error(e));
+    }
+    if let Some(owner) = from_owner {
+        quota.stored(owner, from.location, -moved);
+    }
+    if let Some(owner) = to_owner {
+        quota.stored(owner, to.location, moved - replaced);
+    }
+    // Watches are on a location and name whatever the key, so only move them if that changed.
+    if (from.location, &from.name) != (to.location, &to.name) {
+        world
+            .watches
+            .copy(from.location, &from.name, to.location, &to.name);
+        WatchTxHandle::new(tr)
+            .move_watchers(from.location, &from.name, to.location, &to.name)
+            .await?;
+    }
+    Ok(Value::Error(NoError))
+}
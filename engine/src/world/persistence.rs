@@ -0,0 +1,1037 @@
+use super::*;
+
+/// Load the most recent dump from `target` into slots.
+/// Returns false if there was no dump to load.
+pub async fn load(world: Arc<World>, target: &DumpTarget) -> Result<bool, Error> {
+    let (dumps, report) = target.read(world.options.strict_load).await?;
+    info!(
+        "Read {} slots from dump: {} skipped, {} corrupt, {} not in its manifest, {} missing{}",
+        report.loaded,
+        report.skipped.len(),
+        report.corrupt.len(),
+        report.unlisted.len(),
+        report.missing.len(),
+        match report.verified {
+            true => "",
+            false => " (no manifest to verify against)",
+        }
+    );
+    if !report.clean() {
+        warn!(
+            "Dump failed verification: {}",
+            serde_json::to_string(&report)?
+        );
+    }
+    load_dumps(&world, &dumps).await?;
+    if dumps.is_empty() {
+        return Ok(false);
+    }
+    run_hooks(&world, LifecyclePoint::PostLoad).await?;
+    Ok(true)
+}
+
+/// Run every hook added to the world for `point`, stopping at the first to fail.
+pub async fn run_hooks(world: &Arc<World>, point: LifecyclePoint) -> Result<(), Error> {
+    let hooks = world.hooks.lock().unwrap().clone();
+    for hooks in hooks {
+        hooks.run(world.clone(), point).await?;
+    }
+    Ok(())
+}
+
+// Run the hooks before a save, which only logs their failure, as the save must go ahead anyway.
+pub(super) async fn run_pre_save_hooks(world: &Arc<World>) {
+    if let Err(e) = run_hooks(world, LifecyclePoint::PreSave).await {
+        error!("{}", e);
+    }
+}
+
+/// Run `program` on the system object, as the verb `verb`, in a transaction of its own which is
+/// committed unless it fails. For programs which come from the server's configuration rather than
+/// a slot.
+pub async fn run_system_program(
+    world: &Arc<World>,
+    verb: &str,
+    program: &Program,
+    args: &[Value],
+) -> Result<Value, Error> {
+    let sys_oid = Oid { id: Uuid::nil() };
+    let vm = &Arc::new(WasmVM::new(world.clone(), None)?);
+    vm.clone().bind_builtins()?;
+    let args = &Value::Vector(args.to_vec());
+    let result = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            let limits = execution_limits(world, &odb, sys_oid).await;
+            let result = vm
+                .execute(&tr, (sys_oid, verb), program, args, limits)
+                .await;
+            commit_unless_failed(result)
+        })
+        .await;
+    match result {
+        Err(e) => match e.abandoned_for() {
+            Some(reason) => Ok(Value::Error(reason)),
+            None => Err(e.into()),
+        },
+        Ok(result) => Ok(result),
+    }
+}
+
+// Slots as they're dumped, with their metadata.
+pub(super) async fn with_meta(odb: &ObjDBTxHandle<'_>, slots: Vec<(SlotDef, Value)>) -> Vec<Dump> {
+    let mut dumps = Vec::with_capacity(slots.len());
+    for (slot_def, value) in slots {
+        let meta = odb
+            .get_slot_meta(slot_def.location, slot_def.key, slot_def.name.clone())
+            .await
+            .ok()
+            .flatten();
+        dumps.push(Dump {
+            slot_def,
+            value,
+            meta,
+        });
+    }
+    dumps
+}
+
+/// Write the slots `dumps` hold into the world, each in a transaction of its own.
+pub async fn load_dumps(world: &World, dumps: &[Dump]) -> Result<(), Error> {
+    let mut located = vec![];
+    for dump in dumps {
+        info!(
+            "Loading {:}-{:}.{:} from dump",
+            dump.slot_def.location.id.to_hyphenated().to_string(),
+            dump.slot_def.key.id.to_hyphenated().to_string(),
+            dump.slot_def.name
+        );
+        let placed = world
+            .run(|tr| async move { Ok(restore_slots(&tr, std::slice::from_ref(dump)).await) })
+            .await?;
+        located.extend(placed);
+    }
+    unlocate_dangling(world, &located).await
+}
+
+// Write slots as a dump or archive holds them, keeping the contents and search indexes in step
+// with them; the reference index is kept as they're set. Returns the object each 'location' slot
+// places, and where.
+pub(super) async fn restore_slots(tr: &Tx, dumps: &[Dump]) -> Vec<(Oid, Oid)> {
+    let odb = ObjDBTxHandle::new(tr);
+    let search = SearchTxHandle::new(tr);
+    let mut located = vec![];
+    for dump in dumps {
+        search.update(&dump.slot_def, &dump.value).await;
+        odb.set_slot_and_meta(dump.slot_def.clone(), &dump.value, dump.meta.as_ref());
+        let def = &dump.slot_def;
+        if let (true, Value::IdKey(location)) = (
+            is_location_slot(def.location, def.key, &def.name),
+            &dump.value,
+        ) {
+            ContentsTxHandle::new(tr).add(*location, def.location);
+            located.push((def.location, *location));
+        }
+    }
+    located
+}
+
+// Take those of the `located` objects whose locations don't exist (as a dump or archive which
+// doesn't hold them leaves them) out of their locations, PAGE_SIZE at a time.
+pub(super) async fn unlocate_dangling(world: &World, located: &[(Oid, Oid)]) -> Result<(), Error> {
+    let mut removed = 0;
+    for page in located.chunks(PAGE_SIZE) {
+        removed += world
+            .run(|tr| async move {
+                let odb = ObjDBTxHandle::new(&tr);
+                let cdb = ContentsTxHandle::new(&tr);
+                let mut removed = 0;
+                for &(oid, location) in page {
+                    if odb.exists(location).await? {
+                        continue;
+                    }
+                    odb.clear_slot(SlotDef {
+                        location: oid,
+                        key: oid,
+                        name: String::from(LOCATION_SLOT),
+                    });
+                    cdb.remove(location, oid);
+                    removed += 1;
+                }
+                Ok(removed)
+            })
+            .await?;
+    }
+    if removed > 0 {
+        warn!(
+            "Took {} objects out of locations which don't exist",
+            removed
+        );
+    }
+    Ok(())
+}
+
+// The names held by those of `oids` which hold one, read PAGE_SIZE at a time.
+pub(super) async fn names_of(world: &World, oids: &[Oid]) -> Result<Vec<(Oid, String)>, Error> {
+    let mut names = vec![];
+    for page in oids.chunks(PAGE_SIZE) {
+        let held = world
+            .run(|tr| async move {
+                let names = NameTxHandle::new(&tr);
+                let mut held = vec![];
+                for &oid in page {
+                    if let Some(name) = names.name_of(oid).await? {
+                        held.push((oid, name));
+                    }
+                }
+                Ok(held)
+            })
+            .await?;
+        names.extend(held);
+    }
+    Ok(names)
+}
+
+// Have each of `restored` hold the name `names` gives it, or none, PAGE_SIZE at a time. A name
+// held by an object outside the archive is left with it.
+pub(super) async fn restore_names(
+    world: &World,
+    restored: &[Oid],
+    names: &[(Oid, String)],
+) -> Result<(), Error> {
+    let names: HashMap<Oid, &str> = names
+        .iter()
+        .map(|(oid, name)| (*oid, name.as_str()))
+        .collect();
+    let names = &names;
+    for page in restored.chunks(PAGE_SIZE) {
+        world
+            .run(|tr| async move {
+                let ndb = NameTxHandle::new(&tr);
+                for oid in page {
+                    match names.get(oid) {
+                        Some(name) => {
+                            if !ndb.claim(*oid, name).await? {
+                                warn!(
+                                    "Not restoring the name '{}' of {:?}, held by another object",
+                                    name, oid
+                                );
+                            }
+                        }
+                        None => ndb.release(*oid).await?,
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// The builtins verbs can call, with their signatures and descriptions: those a VM binds, which
+/// one is bound to find out.
+pub fn list_builtins(world: &Arc<World>) -> Result<Vec<BuiltinInfo>, Error> {
+    Arc::new(WasmVM::new(world.clone(), None)?).bind_builtins()?;
+    Ok(world.builtins().list())
+}
+
+/// Dump all the slots on `oids` to `target`, replacing those last written for them. Other objects'
+/// are left as they were.
+pub async fn save(world: Arc<World>, target: &DumpTarget, oids: &[Oid]) -> Result<(), Error> {
+    run_pre_save_hooks(&world).await;
+    let dumps = dump_objects(&world, oids).await?;
+    world.flush().await?;
+
+    target
+        .write_objects(&oids.iter().copied().collect(), &dumps)
+        .await?;
+    *world.last_backup.lock().unwrap() = Some(SystemTime::now());
+    Ok(())
+}
+
+/// Dump every slot in the world to `target`.
+pub async fn save_all(world: Arc<World>, target: &DumpTarget) -> Result<(), Error> {
+    run_pre_save_hooks(&world).await;
+    let dumps = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::snapshot(&tr);
+            let slots = odb.dump_all_slots().map_err(DbError::Aborted)?;
+            let slots = slots
+                .collect::<Result<Vec<(SlotDef, Value)>, _>>()
+                .await
+                .map_err(DbError::Aborted)?;
+            Ok(with_meta(&odb, slots).await)
+        })
+        .await?;
+    world.flush().await?;
+
+    target.write(&dumps).await?;
+    *world.last_backup.lock().unwrap() = Some(SystemTime::now());
+    Ok(())
+}
+
+// Objects written since the last checkpoint.
+#[derive(Default)]
+pub(super) struct Dirty {
+    objects: HashSet<Oid>,
+    // Set if some writes were missed, so that only a full dump will do.
+    everything: bool,
+}
+
+/// Every `interval`, dump the objects written since the last checkpoint to `target`, so that a
+/// crash loses no more than that. If it falls too far behind to know which objects were written,
+/// or can't dump them, it catches up at the next checkpoint.
+pub async fn checkpoint(world: Arc<World>, target: DumpTarget, interval: Duration) {
+    let mut commits = world.database.subscribe();
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticks.tick().await;
+    let mut dirty = Dirty::default();
+    loop {
+        tokio::select! {
+            written = commits.recv() => match written {
+                Ok(written) => dirty.objects.extend(written.keys.iter().filter_map(object_at)),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Checkpoint missed {} transactions, will dump everything", missed);
+                    dirty.everything = true;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = ticks.tick() => {
+                if dirty.everything || !dirty.objects.is_empty() {
+                    match checkpoint_objects(&world, &target, &dirty).await {
+                        Ok(()) => dirty = Dirty::default(),
+                        Err(e) => error!("Checkpoint failed, will retry: {}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub(super) async fn checkpoint_objects(
+    world: &Arc<World>,
+    target: &DumpTarget,
+    dirty: &Dirty,
+) -> Result<(), Error> {
+    let started = Instant::now();
+    if dirty.everything {
+        save_all(world.clone(), target).await?;
+        info!("Checkpointed everything in {:?}", started.elapsed());
+        return Ok(());
+    }
+    run_pre_save_hooks(world).await;
+    let oids: Vec<Oid> = dirty.objects.iter().copied().collect();
+    let dumps = dump_objects(world, &oids).await?;
+    world.flush().await?;
+    target.write_objects(&dirty.objects, &dumps).await?;
+    *world.last_backup.lock().unwrap() = Some(SystemTime::now());
+    info!(
+        "Checkpointed {} objects ({} slots) in {:?}",
+        oids.len(),
+        dumps.len(),
+        started.elapsed()
+    );
+    Ok(())
+}
+
+/// Write every object in the world to a whole-world archive at `path`, with any blobs its slots
+/// hold. Everything's read in the one transaction, so that the archive is the world as it was at a
+/// single version; on FoundationDB that means a world too large to read within its five second
+/// transaction limit can't be exported, and wants `backup_world` instead. Returns how many objects
+/// and slots were written.
+pub async fn export_world(world: &Arc<World>, path: &Path) -> Result<(usize, usize), Error> {
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let manifest = WorldManifest {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        created,
+        engine_version: env!("CARGO_PKG_VERSION").to_string(),
+        journal_position: None,
+        since: None,
+    };
+    let manifest = &manifest;
+    // The archive's written afresh if the transaction is retried. Failing to write it doesn't
+    // fail the transaction, which only reads.
+    world
+        .run(|tr| async move { Ok(write_world(&tr, path, manifest).await) })
+        .await?
+}
+
+// Write every object `tr` sees to a whole-world archive at `path`, returning how many objects and
+// slots were written.
+pub(super) async fn write_world(
+    tr: &Tx,
+    path: &Path,
+    manifest: &WorldManifest,
+) -> Result<(usize, usize), Error> {
+    let encoder = zstd::stream::write::Encoder::new(std::fs::File::create(path)?, 0)?;
+    let mut archive = ArchiveWriter::new(encoder, manifest.created);
+    archive.append(MANIFEST_ENTRY, &serde_json::to_vec_pretty(manifest)?)?;
+
+    let odb = ObjDBTxHandle::new(tr);
+    let ndb = NameTxHandle::new(tr);
+    let mut slots = odb
+        .dump_all_slots()
+        .map_err(|e| anyhow!("Can't read slots: {:?}", e))?;
+    let mut object: Vec<(SlotDef, Value)> = vec![];
+    let mut names: Vec<(Oid, String)> = vec![];
+    let (mut objects, mut written) = (0, 0);
+    loop {
+        let slot = match slots.next().await {
+            Some(Ok(slot)) => Some(slot),
+            Some(Err(e)) => return Err(anyhow!("Can't read slots: {:?}", e)),
+            None => None,
+        };
+        // Slots come ordered by location, so each object's are together.
+        let ended = match (&slot, object.first()) {
+            (Some((slot_def, _)), Some((first, _))) => slot_def.location != first.location,
+            (None, first) => first.is_some(),
+            (Some(_), None) => false,
+        };
+        if ended {
+            let location = object[0].0.location;
+            if let Some(name) = ndb.name_of(location).await? {
+                names.push((location, name));
+            }
+            let dumps = with_meta(&odb, std::mem::take(&mut object)).await;
+            archive.append(
+                &format!("objects/{}.json", location.id.to_hyphenated()),
+                &serde_json::to_vec(&dumps)?,
+            )?;
+            objects += 1;
+            written += dumps.len();
+            if objects % 1000 == 0 {
+                info!("Exported {} objects", objects);
+            }
+        }
+        match slot {
+            Some(slot) => object.push(slot),
+            None => break,
+        }
+    }
+    archive.append(NAMES_ENTRY, &serde_json::to_vec(&names)?)?;
+    archive.finish()?.finish()?;
+    Ok((objects, written))
+}
+
+/// Write the world's objects, and the references between them, to `dir` as a property graph in
+/// Neo4j's bulk import CSV format, reading each object in a transaction of its own. The mirror is
+/// read-only: nothing is written back to the world.
+///
+/// If `incremental`, only objects which have changed since the last export to `dir` are written,
+/// with all their references, along with the objects which have gone since; applying it means
+/// replacing those objects' outgoing relationships, and deleting those gone. References to objects
+/// with no slots are left out, as there's no node for them.
+pub async fn export_graph(
+    world: &Arc<World>,
+    dir: &Path,
+    incremental: bool,
+) -> Result<GraphReport, Error> {
+    let oids = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::snapshot(&tr);
+            Ok(odb.objects().unwrap().collect::<Vec<Oid>>().await)
+        })
+        .await?;
+    let existing: HashSet<Oid> = oids.iter().copied().collect();
+    let previous = match incremental {
+        true => GraphState::read(dir)?,
+        false => GraphState::default(),
+    };
+
+    let mut graph = GraphWriter::create(dir)?;
+    let mut state = GraphState {
+        exported: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        ..GraphState::default()
+    };
+    let mut report = GraphReport {
+        incremental,
+        ..GraphReport::default()
+    };
+    for oid in &oids {
+        let dumps = dump_objects(world, &[*oid]).await?;
+        let digest = object_digest(&dumps)?;
+        let unchanged = previous.objects.get(&oid.id) == Some(&digest);
+        state.objects.insert(oid.id, digest);
+        if unchanged {
+            report.unchanged += 1;
+            continue;
+        }
+        let name = world
+            .run(|tr| async move { NameTxHandle::new(&tr).name_of(*oid).await })
+            .await?;
+        graph.node(&Node {
+            oid: *oid,
+            name,
+            slots: dumps.len(),
+        })?;
+        report.nodes += 1;
+        for relationship in relationships(&dumps) {
+            if existing.contains(&relationship.to) {
+                graph.relationship(&relationship)?;
+                report.relationships += 1;
+            }
+        }
+    }
+    for oid in previous.objects.keys() {
+        if !state.objects.contains_key(oid) {
+            graph.removed(*oid)?;
+            report.removed += 1;
+        }
+    }
+    graph.finish()?;
+    // Only once the export's complete, so a failed one is redone in full next time.
+    state.write(dir)?;
+    Ok(report)
+}
+
+/// Restore the whole-world archive at `path`, each object in a transaction of its own, with the
+/// names its objects held and the contents of each location. Objects in locations the archive
+/// doesn't hold are taken out of them. Refuses unless the database is empty, as the archive is the
+/// whole world rather than a patch to one. Returns how many objects and slots were restored.
+pub async fn import_world(world: &Arc<World>, path: &Path) -> Result<(usize, usize), Error> {
+    let existing = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            Ok(odb.objects().unwrap().next().await)
+        })
+        .await?;
+    if existing.is_some() {
+        return Err(anyhow!("Can only load a world into an empty database"));
+    }
+
+    let (mut received, reading) = read_entries(std::fs::File::open(path)?);
+    let manifest: WorldManifest = match received.recv().await {
+        Some((name, data)) if name == MANIFEST_ENTRY => serde_json::from_slice(&data)?,
+        _ => {
+            reading.await??;
+            return Err(anyhow!("Not a world archive: no manifest"));
+        }
+    };
+    if manifest.format != ARCHIVE_FORMAT || manifest.version > ARCHIVE_VERSION {
+        return Err(anyhow!(
+            "Can't load a {} version {} archive",
+            manifest.format,
+            manifest.version
+        ));
+    }
+    if manifest.since.is_some() {
+        return Err(anyhow!(
+            "Can't load an incremental archive; restore it instead"
+        ));
+    }
+    info!(
+        "Loading world archived at {} by version {}",
+        manifest.created, manifest.engine_version
+    );
+
+    let (mut restored, mut located, mut slots) = (vec![], vec![], 0);
+    while let Some((name, data)) = received.recv().await {
+        if name == NAMES_ENTRY {
+            let names: Vec<(Oid, String)> = serde_json::from_slice(&data)?;
+            restore_names(world, &restored, &names).await?;
+            continue;
+        }
+        if !name.starts_with("objects/") {
+            warn!("Skipping unknown archive entry {}", name);
+            continue;
+        }
+        let dumps: Vec<Dump> = serde_json::from_slice(&data)?;
+        let dumps = &dumps;
+        let placed = world
+            .run(|tr| async move { Ok(restore_slots(&tr, dumps).await) })
+            .await?;
+        located.extend(placed);
+        restored.extend(dumps.first().map(|dump| dump.slot_def.location));
+        slots += dumps.len();
+        if restored.len() % 1000 == 0 {
+            info!("Loaded {} objects", restored.len());
+        }
+    }
+    reading.await??;
+    unlocate_dangling(world, &located).await?;
+    world.flush().await?;
+    Ok((restored.len(), slots))
+}
+
+// Read the entries of the compressed archive `input` and decompress them on a blocking thread,
+// handing them over as they come.
+pub(super) fn read_entries(
+    input: impl std::io::Read + Send + 'static,
+) -> (
+    tokio::sync::mpsc::Receiver<(String, Vec<u8>)>,
+    tokio::task::JoinHandle<Result<(), Error>>,
+) {
+    let (entries, received) = tokio::sync::mpsc::channel(16);
+    let reading = tokio::task::spawn_blocking(move || {
+        read_archive(zstd::stream::read::Decoder::new(input)?, |name, data| {
+            Ok(entries.blocking_send((name, data)).is_ok())
+        })
+    });
+    (received, reading)
+}
+
+// Where the change journal is now, and, if `since` an earlier position, the objects changed since,
+// read from the journal; or None if changes aren't being journaled.
+pub(super) async fn journal_position(
+    world: &Arc<World>,
+    since: Option<Versionstamp>,
+) -> Result<Option<(Versionstamp, HashSet<Oid>)>, Error> {
+    if world.options.changes.is_none() {
+        return Ok(None);
+    }
+    let mut changed = HashSet::new();
+    let mut position = match since {
+        Some(since) => since,
+        None => {
+            let last = world
+                .run(|tr| async move { ChangesTxHandle::new(&tr).last().await })
+                .await?;
+            return Ok(Some((last.unwrap_or(Versionstamp::ZERO), changed)));
+        }
+    };
+    loop {
+        let changes = changes_since(world, position, CHANGES_BATCH).await?;
+        match changes.last() {
+            Some(last) => position = last.versionstamp,
+            None => return Ok(Some((position, changed))),
+        }
+        changed.extend(changes.iter().map(|change| change.location));
+    }
+}
+
+/// Stream an archive of the world, as `export_world` writes, to `key` in `store`, a part at a time
+/// as it's written, reading each object in a transaction of its own. Unlike `export_world`'s, the
+/// archive isn't the world as it was at any one moment: each object is as it was when it was read,
+/// so one changed meanwhile may refer to objects as they were before or after. Those changes are
+/// in the next incremental archive.
+///
+/// If `since`, a journal position an earlier backup gave, the archive is incremental: it holds
+/// only the objects changed since then, whole, and lists those destroyed. It's only complete if
+/// the journal still has every change since, so incremental backups must be taken more often than
+/// the journal is pruned.
+pub async fn backup_world(
+    world: &Arc<World>,
+    store: &ObjectStore,
+    key: &str,
+    since: Option<Versionstamp>,
+) -> Result<ArchiveReport, Error> {
+    // Taken before anything's read, so that any change made while the archive's written is in the
+    // next incremental archive, if it's not in this one.
+    let journal = journal_position(world, since).await?;
+    let oids: Vec<Oid> = match (since, &journal) {
+        (None, _) => {
+            world
+                .run(|tr| async move {
+                    let odb = ObjDBTxHandle::snapshot(&tr);
+                    Ok(odb.objects().unwrap().collect::<Vec<Oid>>().await)
+                })
+                .await?
+        }
+        (Some(_), Some((_, changed))) => {
+            let mut changed: Vec<Oid> = changed.iter().copied().collect();
+            changed.sort_by_key(|oid| oid.id);
+            changed
+        }
+        (Some(_), None) => return Err(anyhow!("Incremental backups need changes to be journaled")),
+    };
+
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut archive = ArchiveWriter::new(zstd::stream::write::Encoder::new(vec![], 0)?, created);
+    let manifest = WorldManifest {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        created,
+        engine_version: env!("CARGO_PKG_VERSION").to_string(),
+        journal_position: journal.as_ref().map(|(position, _)| position.to_string()),
+        since: since.map(|since| since.to_string()),
+    };
+    archive.append(MANIFEST_ENTRY, &serde_json::to_vec_pretty(&manifest)?)?;
+    let mut report = ArchiveReport {
+        journal_position: manifest.journal_position.clone(),
+        since: manifest.since.clone(),
+        ..ArchiveReport::default()
+    };
+
+    let upload_id = store.begin_upload(key).await?;
+    let parts = async {
+        let mut parts = vec![];
+        let mut destroyed = vec![];
+        let mut archived = vec![];
+        for (i, oid) in oids.iter().enumerate() {
+            let dumps = dump_objects(world, &[*oid]).await?;
+            if dumps.is_empty() {
+                // Only an incremental archive's objects can have gone.
+                destroyed.push(*oid);
+                continue;
+            }
+            report.objects += 1;
+            report.slots += dumps.len();
+            archived.push(*oid);
+            archive.append(
+                &format!("objects/{}.json", oid.id.to_hyphenated()),
+                &serde_json::to_vec(&dumps)?,
+            )?;
+            if archive.get_mut().get_ref().len() >= MULTIPART_PART_SIZE {
+                let part = std::mem::take(archive.get_mut().get_mut());
+                let number = parts.len() as u32 + 1;
+                parts.push(store.put_part(key, &upload_id, number, part).await?);
+            }
+            if (i + 1) % 1000 == 0 {
+                info!("Backed up {}/{} objects", i + 1, oids.len());
+            }
+        }
+        let names = names_of(world, &archived).await?;
+        archive.append(NAMES_ENTRY, &serde_json::to_vec(&names)?)?;
+        if since.is_some() {
+            report.destroyed = destroyed.len();
+            archive.append(DESTROYED_ENTRY, &serde_json::to_vec(&destroyed)?)?;
+        }
+        // The last part may be smaller than the rest.
+        let last = archive.finish()?.finish()?;
+        let number = parts.len() as u32 + 1;
+        parts.push(store.put_part(key, &upload_id, number, last).await?);
+        Ok::<_, Error>(parts)
+    }
+    .await;
+    store.finish_upload(key, &upload_id, parts).await?;
+    Ok(report)
+}
+
+/// The slot on the system object, under SERVER_KEY, holding the journal position, as a String, of
+/// the last archive restored into the world: where the next incremental archive restored into it
+/// must follow on from.
+pub const RESTORED_SLOT: &str = "restored_journal_position";
+
+// The journal position of the last archive restored into the world, if it had one.
+pub(super) async fn restored_position(world: &World) -> Result<Option<String>, Error> {
+    let sys_oid = Oid { id: Uuid::nil() };
+    let position = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            Ok(odb
+                .get_slot(sys_oid, SERVER_KEY, String::from(RESTORED_SLOT))
+                .await)
+        })
+        .await?;
+    match position {
+        Ok(Value::String(position)) => Ok(Some(position.to_string())),
+        _ => Ok(None),
+    }
+}
+
+// Record `position` as that of the last archive restored into the world.
+pub(super) async fn record_restored(world: &World, position: Option<&str>) -> Result<(), Error> {
+    let sys_oid = Oid { id: Uuid::nil() };
+    world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            let slot = SlotDef {
+                location: sys_oid,
+                key: SERVER_KEY,
+                name: String::from(RESTORED_SLOT),
+            };
+            match position {
+                Some(position) => {
+                    odb.set_slot_and_meta(slot, &Value::String(position.into()), None)
+                }
+                None => odb.clear_slot(slot),
+            }
+            Ok(())
+        })
+        .await?;
+    Ok(())
+}
+
+/// Restore a world from the archive `key` in `store`, as `backup_world` wrote it, reading it a
+/// range at a time. A full archive can only be restored into an empty database; an incremental
+/// one is restored over the world it follows on from, replacing the objects it holds and
+/// destroying those it lists, and is refused unless the archive last restored into the world was
+/// the one it follows on from. Names and contents are restored as `import_world` restores them.
+pub async fn restore_world(
+    world: &Arc<World>,
+    store: Arc<ObjectStore>,
+    key: &str,
+) -> Result<ArchiveReport, Error> {
+    let (mut received, reading) = read_entries(store.reader(key).await?);
+
+    let manifest: WorldManifest = match received.recv().await {
+        Some((name, data)) if name == MANIFEST_ENTRY => serde_json::from_slice(&data)?,
+        _ => {
+            reading.await??;
+            return Err(anyhow!("Not a world archive: no manifest"));
+        }
+    };
+    if manifest.format != ARCHIVE_FORMAT || manifest.version > ARCHIVE_VERSION {
+        return Err(anyhow!(
+            "Can't restore a {} version {} archive",
+            manifest.format,
+            manifest.version
+        ));
+    }
+    let incremental = manifest.since.is_some();
+    if incremental {
+        let restored = restored_position(world).await?;
+        if restored != manifest.since {
+            return Err(anyhow!(
+                "The archive follows on from journal position {}, but the last restored was {}",
+                manifest.since.unwrap_or_default(),
+                restored.as_deref().unwrap_or("none")
+            ));
+        }
+    } else {
+        let existing = world
+            .run(|tr| async move {
+                let odb = ObjDBTxHandle::new(&tr);
+                Ok(odb.objects().unwrap().next().await)
+            })
+            .await?;
+        if existing.is_some() {
+            return Err(anyhow!(
+                "Can only restore a full archive into an empty database"
+            ));
+        }
+    }
+    info!(
+        "Restoring world archived at {} by version {}",
+        manifest.created, manifest.engine_version
+    );
+
+    let mut report = ArchiveReport {
+        journal_position: manifest.journal_position,
+        since: manifest.since,
+        ..ArchiveReport::default()
+    };
+    let (mut restored, mut located) = (vec![], vec![]);
+    while let Some((name, data)) = received.recv().await {
+        if incremental && name == DESTROYED_ENTRY {
+            let destroyed: Vec<Oid> = serde_json::from_slice(&data)?;
+            let destroyed = &destroyed;
+            world
+                .run(|tr| async move {
+                    for oid in destroyed {
+                        if let Err(e) = destroy_object(world, &tr, None, *oid).await {
+                            error!("Could not destroy {:?}: {}", oid, e);
+                            return Err(DbError::Aborted(InternalError));
+                        }
+                    }
+                    Ok(())
+                })
+                .await?;
+            report.destroyed += destroyed.len();
+            continue;
+        }
+        if name == NAMES_ENTRY {
+            let names: Vec<(Oid, String)> = serde_json::from_slice(&data)?;
+            restore_names(world, &restored, &names).await?;
+            continue;
+        }
+        if !name.starts_with("objects/") {
+            warn!("Skipping unknown archive entry {}", name);
+            continue;
+        }
+        let dumps: Vec<Dump> = serde_json::from_slice(&data)?;
+        let dumps = &dumps;
+        let placed = world
+            .run(|tr| async move {
+                let oid = match (incremental, dumps.first()) {
+                    (true, Some(dump)) => dump.slot_def.location,
+                    _ => return Ok(restore_slots(&tr, dumps).await),
+                };
+                // The object's destroyed and restored whole, so that slots cleared since go too,
+                // along with everything else kept about it, to be restored from the archive in
+                // turn. What's in it is left there, as whatever's moved since is in the archive.
+                let cdb = ContentsTxHandle::new(&tr);
+                let contents = cdb.contents(oid).await?;
+                if let Err(e) = destroy_object(world, &tr, None, oid).await {
+                    error!("Could not replace {:?}: {}", oid, e);
+                    return Err(DbError::Aborted(InternalError));
+                }
+                for thing in contents {
+                    cdb.add(oid, thing);
+                }
+                let placed = restore_slots(&tr, dumps).await;
+                let quota = QuotaTxHandle::new(&tr);
+                if let Some(owner) = quota.owner_of(oid).await {
+                    let bytes = dumps.iter().map(|dump| stored_size(&dump.value)).sum();
+                    quota.restored(owner, oid, bytes);
+                }
+                Ok(placed)
+            })
+            .await?;
+        located.extend(placed);
+        restored.extend(dumps.first().map(|dump| dump.slot_def.location));
+        report.objects += 1;
+        report.slots += dumps.len();
+        if report.objects % 1000 == 0 {
+            info!("Restored {} objects", report.objects);
+        }
+    }
+    reading.await??;
+    unlocate_dangling(world, &located).await?;
+    record_restored(world, report.journal_position.as_deref()).await?;
+    world.flush().await?;
+    Ok(report)
+}
+
+/// All the slots on `oids`, as they'd be dumped.
+pub async fn dump_objects(world: &World, oids: &[Oid]) -> Result<Vec<Dump>, Error> {
+    let dumps = world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::snapshot(&tr);
+            let mut dumps = vec![];
+            for oid in oids {
+                let slots = odb.dump_slots(*oid).map_err(DbError::Aborted)?;
+                let slots = slots
+                    .collect::<Result<Vec<(SlotDef, Value)>, _>>()
+                    .await
+                    .map_err(DbError::Aborted)?;
+                dumps.extend(with_meta(&odb, slots).await);
+            }
+            Ok(dumps)
+        })
+        .await?;
+    Ok(dumps)
+}
+
+/// Warm the storage backend's cache with every slot on the objects in `manifest`, and the module
+/// cache with their programs and the verbs it lists, logging progress as it goes. Objects and
+/// verbs which no longer exist, or programs which no longer compile, are skipped.
+pub async fn preload(world: &Arc<World>, manifest: &PreloadManifest) -> Result<(), Error> {
+    let started = Instant::now();
+    let mut programs = vec![];
+    for (i, id) in manifest.objects.iter().enumerate() {
+        let dumps = dump_objects(world, &[Oid { id: *id }]).await?;
+        info!(
+            "Preloading object {}/{}: {} ({} slots)",
+            i + 1,
+            manifest.objects.len(),
+            id,
+            dumps.len()
+        );
+        programs.extend(dumps.into_iter().filter_map(|dump| match dump.value {
+            Value::Program(program) => Some(program),
+            _ => None,
+        }));
+    }
+    for (i, verb) in manifest.verbs.iter().enumerate() {
+        let oid = Oid { id: verb.oid };
+        let slot = world
+            .run(|tr| async move {
+                let odb = ObjDBTxHandle::snapshot(&tr);
+                Ok(odb.get_slot(oid, oid, verb.verb.clone()).await.ok())
+            })
+            .await?;
+        match slot {
+            // Lua and JavaScript programs are only compiled as they're run.
+            Some(Value::Program(program))
+                if matches!(program.lang, ProgramLang::Lua | ProgramLang::JavaScript) => {}
+            Some(Value::Program(program)) => programs.push(program),
+            _ => warn!(
+                "Not preloading {}:{}, which isn't a verb",
+                verb.oid, verb.verb
+            ),
+        }
+        if (i + 1) % 25 == 0 {
+            info!("Preloading verb {}/{}", i + 1, manifest.verbs.len());
+        }
+    }
+
+    let mut compiled = 0;
+    for program in &programs {
+        match world.module_cache.get(program).await {
+            Ok(_) => compiled += 1,
+            Err(e) => warn!("Not preloading a program which doesn't compile: {}", e),
+        }
+    }
+    info!(
+        "Preloaded {} objects and compiled {} programs in {:?}",
+        manifest.objects.len(),
+        compiled,
+        started.elapsed()
+    );
+    Ok(())
+}
+
+/// Install `core`'s objects into a new world, all in one transaction, claiming each object's name
+/// for it. Fails, installing nothing, if any of the names are already held by other objects.
+pub async fn install_core(world: Arc<World>, core: &Core) -> Result<(), Error> {
+    world
+        .run(|tr| async move {
+            let odb = ObjDBTxHandle::new(&tr);
+            let names = NameTxHandle::new(&tr);
+            for object in &core.objects {
+                if !names.claim(object.oid, &object.name).await? {
+                    error!("Core object name '{}' is already taken", object.name);
+                    return Err(DbError::Aborted(NameTaken));
+                }
+                for (name, value) in &object.slots {
+                    odb.set_slot(object.oid, object.oid, name.clone(), value)
+                        .await
+                        .map_err(DbError::Aborted)?;
+                }
+                info!(
+                    "Installed core object '{}' ({:?}) with {} slots",
+                    object.name,
+                    object.oid,
+                    object.slots.len()
+                );
+            }
+            Ok(())
+        })
+        .await?;
+    run_hooks(&world, LifecyclePoint::PostBootstrap).await
+}
+
+pub async fn bootstrap_world(world: Arc<World>, sys_oid: Oid) -> Result<(), Error> {
+    let bootstrap_objects = |tr| async move {
+        let odb = ObjDBTxHandle::new(&tr);
+
+        // Sys 'log' method.
+        odb.set_slot(
+            sys_oid,
+            sys_oid,
+            String::from("syslog"),
+            &Value::Program(Program::new(
+                ProgramLang::Wat,
+                String::from(
+                r#"(module
+                            (import "host" "log" (func $host/log (param i32) (result i32 i32)))
+                            (memory $mem 1)
+                            (export "memory" (memory $mem))
+                            (func $log (param $0 i32) (result i32 i32) get_local $0 (call $host/log))
+                            (export "invoke" (func $log))
+                            )
+    "#,
+            ))),
+        )
+        .await
+        .map_err(DbError::Aborted)?;
+
+        // Connection 'receive' method. Just does an 'echo' for now.
+        odb.set_slot(
+            sys_oid,
+            sys_oid,
+            String::from("receive"),
+            &Value::Program(Program::new(
+                ProgramLang::Wat,
+                String::from(
+                r#"(module
+                            (import "host" "send" (func $host/send (param i32) (result i32 i32)))
+                            (memory $mem 1)
+                            (export "memory" (memory $mem))
+                            (func $send (param $0 i32) (result i32 i32) get_local $0 (call $host/send))
+                            (export "invoke" (func $send))
+                            )
+    "#,
+            ))),
+        )
+        .await
+        .map_err(DbError::Aborted)?;
+        Ok(())
+    };
+    world.run(bootstrap_objects).await?;
+
+    run_hooks(&world, LifecyclePoint::PostBootstrap).await
+}
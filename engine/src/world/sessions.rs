@@ -0,0 +1,648 @@
+use super::*;
+
+pub(super) fn connection_ip(world: &Arc<World>, connection: Oid) -> Result<IpAddr, Error> {
+    let peer_map = world.peer_map.lock().unwrap();
+    match peer_map.get(&connection) {
+        Some(con_record) => Ok(con_record.address.ip()),
+        None => Err(anyhow!("No such connection: {:?}", connection)),
+    }
+}
+
+// How long the more restrictive of the account's and the address's recent failures require an
+// attempt to wait.
+pub(super) async fn login_wait(
+    adb: &AuthTxHandle<'_>,
+    policy: &AuthPolicy,
+    scopes: &[Scope],
+    now: SystemTime,
+) -> Result<Duration, DbError> {
+    let mut wait = Duration::ZERO;
+    for scope in scopes {
+        let failures = adb.failures(*scope, policy.window_start(now)).await?;
+        wait = wait.max(policy.retry_after(&failures, now));
+    }
+    Ok(wait)
+}
+
+/// How long `connection` must wait before it may attempt to log in to `account`, under the
+/// world's login policy. Zero if it may go ahead.
+pub async fn login_allowed(
+    world: &Arc<World>,
+    connection: Oid,
+    account: Oid,
+) -> Result<Duration, Error> {
+    let scopes = &[
+        Scope::Account(account),
+        Scope::Address(connection_ip(world, connection)?),
+    ];
+    let policy = &world.options.auth;
+    let now = SystemTime::now();
+    let wait = world
+        .run(|tr| async move { login_wait(&AuthTxHandle::new(&tr), policy, scopes, now).await })
+        .await?;
+    Ok(wait)
+}
+
+/// Where a login stands after an attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginOutcome {
+    Granted,
+    /// The password was right, but the account has two-factor authentication enabled, so the
+    /// connection must pass `login_verify` before it's granted.
+    SecondFactorRequired,
+    /// Refused, with how long to wait before trying again.
+    Denied(Duration),
+}
+
+pub(super) fn set_pending_login(world: &Arc<World>, connection: Oid, account: Option<Oid>) {
+    if let Some(con_record) = world.peer_map.lock().unwrap().get_mut(&connection) {
+        con_record.pending_login = account;
+    }
+}
+
+// Log `connection` in to `account`, ending the session it was logged in to before, if any.
+pub(super) async fn set_player(
+    world: &Arc<World>,
+    connection: Oid,
+    account: Oid,
+) -> Result<(), Error> {
+    let (previous, address) = match world.peer_map.lock().unwrap().get_mut(&connection) {
+        Some(con_record) => {
+            let previous = con_record.player.zip(con_record.logged_in_at);
+            con_record.player = Some(account);
+            con_record.logged_in_at = Some(Instant::now());
+            (previous, con_record.address)
+        }
+        None => return Ok(()),
+    };
+    let now = SystemTime::now();
+    let record = &SessionRecord {
+        player: account,
+        node: world.node().to_string(),
+        address: address.to_string(),
+        logged_in: unix_secs(now),
+    };
+    world
+        .run(|tr| async move {
+            let sdb = PlayerStatsTxHandle::new(&tr);
+            if let Some((player, logged_in_at)) = previous {
+                sdb.session_ended(player, logged_in_at.elapsed(), now);
+            }
+            sdb.session_started(account, now);
+            SessionTxHandle::new(&tr).start(connection, record);
+            Ok(())
+        })
+        .await?;
+    Ok(())
+}
+
+/// Who's logged in, where to and from, and how long since each was last heard from: the sessions
+/// recorded by every server sharing the database, in connection order. Those of this server are
+/// checked against its open connections. Clients' addresses are given only as the networks
+/// they're in (see `redact_address`).
+pub async fn session_bindings(world: &Arc<World>) -> Result<Vec<SessionBinding>, Error> {
+    let sessions = world
+        .run(|tr| async move { SessionTxHandle::new(&tr).list().await })
+        .await?;
+    let now = unix_secs(SystemTime::now());
+    let peer_map = world.peer_map.lock().unwrap();
+    let mut bindings = vec![];
+    for (connection, mut record, last_active) in sessions {
+        record.address = redact_address(&record.address);
+        let local = record.node == world.node();
+        if local && !peer_map.contains_key(&connection) {
+            continue;
+        }
+        bindings.push(SessionBinding {
+            connection,
+            record,
+            last_active,
+            idle_secs: (now - last_active).max(0),
+            local,
+        });
+    }
+    Ok(bindings)
+}
+
+/// Forget the sessions recorded by an earlier run of this server, whose connections went with it.
+pub async fn forget_sessions(world: &Arc<World>) -> Result<usize, Error> {
+    let node = world.node();
+    let forgotten = world
+        .run(|tr| async move {
+            let sdb = SessionTxHandle::new(&tr);
+            let mut forgotten = 0;
+            for (connection, record, _) in sdb.list().await? {
+                if record.node == node {
+                    sdb.end(connection);
+                    forgotten += 1;
+                }
+            }
+            Ok(forgotten)
+        })
+        .await?;
+    Ok(forgotten)
+}
+
+/// The player `connection` acts as, if any: the one it's logged in to or, if it's an admin's
+/// fully impersonating a player, that player.
+pub fn connection_player(world: &Arc<World>, connection: Oid) -> Option<Oid> {
+    match world.impersonations.of(connection) {
+        Some(impersonation) if impersonation.mode == ImpersonationMode::Full => {
+            Some(impersonation.player)
+        }
+        _ => logged_in_player(world, connection),
+    }
+}
+
+// Record an event in an impersonation's audit trail, in a transaction of its own so that it's kept
+// whatever becomes of the verb which started or ended it.
+pub(super) async fn audit_impersonation(
+    world: &Arc<World>,
+    impersonation: &Impersonation,
+    event: AuditEvent,
+) -> Result<(), Error> {
+    let entry = &AuditEntry::new(impersonation, event);
+    world
+        .run(|tr| async move {
+            AuditTxHandle::new(&tr).append(entry);
+            Ok(())
+        })
+        .await?;
+    Ok(())
+}
+
+// Record what `connection` sent in the audit trail, as `text` describes it, if it's fully
+// impersonating a player and so has it run as theirs.
+pub(super) async fn audit_command(
+    world: &Arc<World>,
+    connection: Oid,
+    text: impl FnOnce() -> String,
+) -> Result<(), Error> {
+    match world.impersonations.of(connection) {
+        Some(impersonation) if impersonation.mode == ImpersonationMode::Full => {
+            audit_impersonation(world, &impersonation, AuditEvent::Command(text())).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Attach `connection`, an admin's, to `player`'s perspective in `mode`, "read_only" or "full",
+/// ending any impersonation it had going. Returns the impersonation's IdKey; BadType if the mode
+/// isn't one of those or the admin would impersonate themselves; PermissionDenied unless the
+/// connection is logged in to an admin.
+pub async fn impersonate(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    player: Oid,
+    mode: &str,
+) -> Result<Value, Error> {
+    let mode = match ImpersonationMode::parse(mode) {
+        Some(mode) => mode,
+        None => return Ok(Value::Error(BadType)),
+    };
+    let (connection, admin) = match connection.and_then(|c| Some(c).zip(logged_in_player(world, c)))
+    {
+        Some(logged_in) => logged_in,
+        None => return Ok(Value::Error(PermissionDenied)),
+    };
+    if !is_admin(tr, admin).await {
+        warn!(target: "security", "{:?} tried to impersonate {:?}, but isn't an admin", admin, player);
+        return Ok(Value::Error(PermissionDenied));
+    }
+    if admin == player {
+        return Ok(Value::Error(BadType));
+    }
+    let impersonation = Impersonation {
+        id: Oid { id: Uuid::new_v4() },
+        connection,
+        admin,
+        player,
+        mode,
+        started: SystemTime::now(),
+    };
+    // Audited with the verb, and started once it's committed, so that a verb which is retried
+    // or abandoned doesn't start it twice, or at all.
+    let audit = AuditTxHandle::new(tr);
+    if let Some(replaced) = world.impersonations.of(connection) {
+        audit.append(&AuditEntry::new(&replaced, AuditEvent::Ended));
+    }
+    audit.append(&AuditEntry::new(&impersonation, AuditEvent::Started));
+    let id = impersonation.id;
+    let world = world.clone();
+    tr.after_commit(move || {
+        warn!(target: "security", "{:?} impersonating {:?} ({})", admin, player, mode.name());
+        world.impersonations.start(impersonation);
+    });
+    Ok(Value::IdKey(id))
+}
+
+/// End `connection`'s impersonation, once the verb's committed. SlotDoesNotExist if it had none
+/// going.
+pub async fn end_impersonation(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+) -> Result<Value, Error> {
+    let impersonation = match connection.and_then(|connection| world.impersonations.of(connection))
+    {
+        Some(impersonation) => impersonation,
+        None => return Ok(Value::Error(SlotDoesNotExist)),
+    };
+    AuditTxHandle::new(tr).append(&AuditEntry::new(&impersonation, AuditEvent::Ended));
+    let world = world.clone();
+    tr.after_commit(move || {
+        info!(target: "security", "{:?} no longer impersonating {:?}", impersonation.admin, impersonation.player);
+        world.impersonations.end(impersonation.connection);
+    });
+    Ok(Value::Error(NoError))
+}
+
+/// The audit trail of impersonations between `from` and `to`, oldest first, each entry as a
+/// Vector of [name, value] pairs: a page of about AUDIT_PAGE of them, so the next starts just
+/// after the last one's timestamp. PermissionDenied unless `connection` is logged in to an admin.
+pub async fn impersonation_audit(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    (from, to): (i64, i64),
+) -> Result<Value, Error> {
+    let admin = connection.and_then(|connection| logged_in_player(world, connection));
+    match admin {
+        Some(admin) if is_admin(tr, admin).await => {}
+        _ => return Ok(Value::Error(PermissionDenied)),
+    }
+    let time = |nanos: i64| UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64);
+    let entries = AuditTxHandle::new(tr).entries(time(from), time(to)).await?;
+    Ok(Value::Vector(
+        entries.iter().map(AuditEntry::info).collect(),
+    ))
+}
+
+/// The audit trail of impersonations between `from` and `to`, oldest first, a page at a time as
+/// `impersonation_audit` reads it.
+pub async fn query_impersonation_audit(
+    world: &Arc<World>,
+    from: SystemTime,
+    to: SystemTime,
+) -> Result<Vec<AuditEntry>, Error> {
+    let entries = world
+        .run(|tr| async move { AuditTxHandle::new(&tr).entries(from, to).await })
+        .await?;
+    Ok(entries)
+}
+
+// The player `connection` is logged in to, if any, whoever it's impersonating.
+pub(super) fn logged_in_player(world: &Arc<World>, connection: Oid) -> Option<Oid> {
+    world
+        .peer_map
+        .lock()
+        .unwrap()
+        .get(&connection)
+        .and_then(|con_record| con_record.player)
+}
+
+/// `line` read as a command by the player `connection` is logged in to, as a Vector of
+/// [String verb, dobj, String prep, iobj]. The objects are IdKeys if they name something in the
+/// player's scope, or the text given if not; parts the command doesn't have are empty Strings.
+/// BadType if the line is blank, PermissionDenied if the connection isn't logged in.
+pub async fn parse_command(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    line: &str,
+) -> Value {
+    let player = match connection.and_then(|connection| connection_player(world, connection)) {
+        Some(player) => player,
+        None => return Value::Error(PermissionDenied),
+    };
+    let grammar = world.options.command_parser.clone().unwrap_or_default();
+    let command = match grammar.parse(line) {
+        Some(command) => command,
+        None => return Value::Error(BadType),
+    };
+    let scope = CommandScope::of(tr, player).await;
+    Value::Vector(vec![
+        Value::String(command.verb.as_str().into()),
+        scope.object(tr, &command.dobj).await,
+        Value::String(command.prep.clone().unwrap_or_default().into()),
+        scope.object(tr, &command.iobj).await,
+    ])
+}
+
+// Run `line` from `player` as a command: the verb it names on the first of the player, their
+// location, the direct object and the indirect object which has it, with the connection, player,
+// dobj, prep and iobj as arguments. None if the line is blank or no such verb is found.
+pub(super) async fn dispatch_command(
+    world: &Arc<World>,
+    vm: Arc<WasmVM>,
+    grammar: &CommandGrammar,
+    connection: Oid,
+    player: Oid,
+    line: &str,
+) -> Result<Option<Value>, Error> {
+    let command = match grammar.parse(line) {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let (command, verb) = (&command, &grammar.verb_name(&command));
+    let found = world
+        .run(|tr| async move {
+            let scope = CommandScope::of(&tr, player).await;
+            let dobj = scope.object(&tr, &command.dobj).await;
+            let iobj = scope.object(&tr, &command.iobj).await;
+            let mut targets = vec![player];
+            targets.extend(scope.location);
+            for object in [&dobj, &iobj] {
+                if let Value::IdKey(oid) = object {
+                    targets.push(*oid);
+                }
+            }
+            let odb = ObjDBTxHandle::new(&tr);
+            for target in targets {
+                if let Ok(Value::Program(_)) = odb.get_slot(target, target, verb.clone()).await {
+                    return Ok(Some((target, dobj, iobj)));
+                }
+            }
+            Ok(None)
+        })
+        .await?;
+    let (target, dobj, iobj) = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let arguments = [
+        Value::IdKey(connection),
+        Value::IdKey(player),
+        dobj,
+        Value::String(command.prep.clone().unwrap_or_default().into()),
+        iobj,
+    ];
+    send_verb_dispatch(world, vm, target, verb, &arguments)
+        .await
+        .map(Some)
+}
+
+// Count a command received from `connection` towards the player it's logged in to, if any.
+pub(super) async fn count_command(world: &Arc<World>, connection: Oid) -> Result<(), Error> {
+    let player = world
+        .peer_map
+        .lock()
+        .unwrap()
+        .get_mut(&connection)
+        .and_then(|con_record| {
+            con_record.last_activity = SystemTime::now();
+            con_record.player
+        });
+    if let Some(player) = player {
+        let now = SystemTime::now();
+        world
+            .run(|tr| async move {
+                PlayerStatsTxHandle::new(&tr).command(player, now);
+                SessionTxHandle::new(&tr).touch(connection, now);
+                Ok(())
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Record the outcome of a login attempt, once verb code has checked the credentials.
+/// The login is only granted if it `succeeded`, the connection isn't being made to wait, and the
+/// account doesn't also require a second factor.
+pub async fn login_attempt(
+    world: &Arc<World>,
+    connection: Oid,
+    account: Oid,
+    succeeded: bool,
+) -> Result<LoginOutcome, Error> {
+    let address = connection_ip(world, connection)?;
+    let scopes = &[Scope::Account(account), Scope::Address(address)];
+    let policy = &world.options.auth;
+    let now = SystemTime::now();
+    let (locked_for, wait, second_factor) = world
+        .run(|tr| async move {
+            let adb = AuthTxHandle::new(&tr);
+            let locked_for = login_wait(&adb, policy, scopes, now).await?;
+            if !locked_for.is_zero() {
+                return Ok((locked_for, locked_for, false));
+            }
+            if succeeded {
+                // Failures are only forgiven once the whole login has succeeded.
+                let totp = TotpTxHandle::new(&tr).get(account).await?;
+                if totp.is_some_and(|record| record.enabled) {
+                    return Ok((Duration::ZERO, Duration::ZERO, true));
+                }
+                adb.clear(Scope::Account(account));
+                return Ok((Duration::ZERO, Duration::ZERO, false));
+            }
+            for scope in scopes {
+                adb.record_failure(*scope, now, policy.window_start(now));
+            }
+            // Count the failure just recorded towards the wait for the next attempt.
+            let wait = login_wait(&adb, policy, scopes, now).await?;
+            Ok((Duration::ZERO, wait, false))
+        })
+        .await?;
+
+    // Security events, for monitoring.
+    if !locked_for.is_zero() {
+        warn!(target: "security", "Login to {:?} from {} refused, locked out for {:?}", account, address, locked_for);
+    } else if !succeeded {
+        info!(target: "security", "Failed login to {:?} from {}", account, address);
+        if !wait.is_zero() {
+            warn!(target: "security", "Locking out logins to {:?} from {} for {:?}", account, address, wait);
+        }
+    }
+    let outcome = match succeeded && locked_for.is_zero() {
+        true if second_factor => LoginOutcome::SecondFactorRequired,
+        true => LoginOutcome::Granted,
+        false => LoginOutcome::Denied(wait),
+    };
+    set_pending_login(
+        world,
+        connection,
+        (outcome == LoginOutcome::SecondFactorRequired).then_some(account),
+    );
+    if outcome == LoginOutcome::Granted {
+        set_player(world, connection, account).await?;
+    }
+    Ok(outcome)
+}
+
+/// Complete a login which required a second factor, with either a code from the account's
+/// authenticator or one of its unused recovery codes. Wrong codes count as failed logins.
+pub async fn login_verify(
+    world: &Arc<World>,
+    connection: Oid,
+    account: Oid,
+    code: &str,
+) -> Result<LoginOutcome, Error> {
+    let address = connection_ip(world, connection)?;
+    let pending = world
+        .peer_map
+        .lock()
+        .unwrap()
+        .get(&connection)
+        .and_then(|con_record| con_record.pending_login);
+    if pending != Some(account) {
+        warn!(target: "security", "Second factor for {:?} from {} without a password", account, address);
+        return Ok(LoginOutcome::Denied(Duration::ZERO));
+    }
+
+    let scopes = &[Scope::Account(account), Scope::Address(address)];
+    let policy = &world.options.auth;
+    let now = SystemTime::now();
+    let (outcome, recovery_used) = world
+        .run(|tr| async move {
+            let adb = AuthTxHandle::new(&tr);
+            let tdb = TotpTxHandle::new(&tr);
+            let locked_for = login_wait(&adb, policy, scopes, now).await?;
+            if !locked_for.is_zero() {
+                return Ok((LoginOutcome::Denied(locked_for), false));
+            }
+            let mut record = match tdb.get(account).await? {
+                Some(record) if record.enabled => record,
+                _ => return Ok((LoginOutcome::Denied(Duration::ZERO), false)),
+            };
+            if let Some(step) = totp::verify(&record.secret, code, now, record.last_step) {
+                record.last_step = step;
+                tdb.put(account, &record);
+                adb.clear(Scope::Account(account));
+                return Ok((LoginOutcome::Granted, false));
+            }
+            if tdb.use_recovery_code(account, code).await? {
+                adb.clear(Scope::Account(account));
+                return Ok((LoginOutcome::Granted, true));
+            }
+            for scope in scopes {
+                adb.record_failure(*scope, now, policy.window_start(now));
+            }
+            let wait = login_wait(&adb, policy, scopes, now).await?;
+            Ok((LoginOutcome::Denied(wait), false))
+        })
+        .await?;
+
+    match outcome {
+        LoginOutcome::Granted => {
+            if recovery_used {
+                warn!(target: "security", "Recovery code used for {:?} from {}", account, address);
+            }
+            set_pending_login(world, connection, None);
+            set_player(world, connection, account).await?;
+        }
+        LoginOutcome::Denied(wait) => {
+            info!(target: "security", "Failed second factor for {:?} from {}", account, address);
+            if !wait.is_zero() {
+                warn!(target: "security", "Locking out logins to {:?} from {} for {:?}", account, address, wait);
+            }
+        }
+        LoginOutcome::SecondFactorRequired => {}
+    }
+    Ok(outcome)
+}
+
+/// Start setting up two-factor authentication for `account`, with a new secret which only takes
+/// effect once confirmed by `totp_enable`.
+/// Returns a Vector of the base32 secret and an otpauth:// URL for authenticator apps, labelled
+/// with `issuer` and the account's name. PermissionDenied if it's already enabled.
+pub async fn totp_provision(
+    world: &Arc<World>,
+    account: Oid,
+    issuer: &str,
+) -> Result<Value, Error> {
+    let secret = totp::generate_secret();
+    let record = &TotpRecord {
+        secret: secret.clone(),
+        enabled: false,
+        last_step: 0,
+    };
+    let name = world
+        .run(|tr| async move {
+            let tdb = TotpTxHandle::new(&tr);
+            if tdb.get(account).await?.is_some_and(|r| r.enabled) {
+                return Ok(None);
+            }
+            tdb.put(account, record);
+            let name = NameTxHandle::new(&tr).name_of(account).await?;
+            Ok(Some(name.unwrap_or_else(|| account.id.to_string())))
+        })
+        .await?;
+    match name {
+        Some(name) => Ok(Value::Vector(vec![
+            Value::String(totp::base32(&secret).into()),
+            Value::String(totp::otpauth_url(issuer, &name, &secret).into()),
+        ])),
+        None => Ok(Value::Error(PermissionDenied)),
+    }
+}
+
+/// Turn on two-factor authentication for `account`, once `code` shows its authenticator has the
+/// secret from `totp_provision`.
+/// Returns a Vector of the account's recovery codes, which are only ever shown this once (they're
+/// stored hashed). PermissionDenied if the code is wrong or there's nothing to confirm.
+pub async fn totp_enable(world: &Arc<World>, account: Oid, code: &str) -> Result<Value, Error> {
+    let codes = &totp::generate_recovery_codes();
+    let now = SystemTime::now();
+    let enabled = world
+        .run(|tr| async move {
+            let tdb = TotpTxHandle::new(&tr);
+            let mut record = match tdb.get(account).await? {
+                Some(record) if !record.enabled => record,
+                _ => return Ok(false),
+            };
+            match totp::verify(&record.secret, code, now, record.last_step) {
+                Some(step) => {
+                    record.enabled = true;
+                    record.last_step = step;
+                    tdb.put(account, &record);
+                    tdb.set_recovery_codes(account, codes);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        })
+        .await?;
+    if !enabled {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    info!(target: "security", "Two-factor authentication enabled for {:?}", account);
+    Ok(Value::Vector(
+        codes.iter().map(|c| Value::String(c.into())).collect(),
+    ))
+}
+
+/// Turn off two-factor authentication for `account`, discarding its secret and recovery codes.
+/// It's up to verb code to have re-authenticated the player first.
+pub async fn totp_disable(world: &Arc<World>, account: Oid) -> Result<Value, Error> {
+    world
+        .run(|tr| async move {
+            TotpTxHandle::new(&tr).remove(account);
+            Ok(())
+        })
+        .await?;
+    info!(target: "security", "Two-factor authentication disabled for {:?}", account);
+    Ok(Value::Error(NoError))
+}
+
+/// Replace `account`'s recovery codes with a fresh set, returned as a Vector of Strings.
+/// PermissionDenied if two-factor authentication isn't enabled.
+pub async fn totp_recovery_codes(world: &Arc<World>, account: Oid) -> Result<Value, Error> {
+    let codes = &totp::generate_recovery_codes();
+    let replaced = world
+        .run(|tr| async move {
+            let tdb = TotpTxHandle::new(&tr);
+            if !tdb.get(account).await?.is_some_and(|r| r.enabled) {
+                return Ok(false);
+            }
+            tdb.set_recovery_codes(account, codes);
+            Ok(true)
+        })
+        .await?;
+    match replaced {
+        true => Ok(Value::Vector(
+            codes.iter().map(|c| Value::String(c.into())).collect(),
+        )),
+        false => Ok(Value::Error(PermissionDenied)),
+    }
+}
@@ -0,0 +1,340 @@
+use super::*;
+
+/// The value in a slot, for `connection`. PermissionDenied if the slot's metadata doesn't let it
+/// be read.
+pub async fn get_slot(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    oid: Oid,
+    key: Oid,
+    slot_name: &str,
+) -> Result<Value, Error> {
+    let slot = SlotDef {
+        location: oid,
+        key,
+        name: String::from(slot_name),
+    };
+    if !slot_permitted(world, tr, connection, &slot, SLOT_READ).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    let odb = ObjDBTxHandle::new(tr);
+    match odb.get_slot(oid, key, String::from(slot_name)).await {
+        Ok(slot) => Ok(slot),
+        Err(_err) => Ok(Value::Error(SlotDoesNotExist)),
+    }
+}
+
+/// The names of the slots on `oid` under `key`.
+pub async fn list_slots(tr: &Tx, oid: Oid, key: Oid) -> Result<Value, Error> {
+    let odb = ObjDBTxHandle::snapshot(tr);
+    let names = match odb.get_slots(oid, key) {
+        Ok(slots) => {
+            slots
+                .map(|slot| Value::String(slot.name.into()))
+                .collect::<Vec<Value>>()
+                .await
+        }
+        Err(_err) => vec![],
+    };
+
+    Ok(Value::Vector(names))
+}
+
+/// Set a slot, for `connection`. PermissionDenied if the slot's metadata doesn't let it be
+/// written; a new slot has no owner. Its metadata is kept, with the time it was written. BadType
+/// if the value holds a Blob handle, and ResourceLimit if it's too large to store.
+pub async fn set_slot(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    oid: Oid,
+    key: Oid,
+    slot_name: &str,
+    value: &Value,
+) -> Result<Value, Error> {
+    // Where an object is can only change as a move, so that the contents index follows it.
+    if is_location_slot(oid, key, slot_name) {
+        return match value {
+            Value::IdKey(destination) => {
+                move_object(world, tr, connection, oid, *destination).await
+            }
+            _ => Ok(Value::Error(BadType)),
+        };
+    }
+    // Who owns an object is only set as it's created, so that it can't be disowned to escape
+    // its owner's quota.
+    if is_owner_slot(oid, key, slot_name) {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    if holds_blob(value) {
+        return Ok(Value::Error(BadType));
+    }
+    if !fits_in_slot(value) {
+        return Ok(Value::Error(ResourceLimit));
+    }
+    let slot = SlotDef {
+        location: oid,
+        key,
+        name: String::from(slot_name),
+    };
+    if !slot_permitted(world, tr, connection, &slot, SLOT_WRITE).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    let odb = ObjDBTxHandle::new(tr);
+    let quota = QuotaTxHandle::new(tr);
+    if let Some(owner) = quota.owner_of(oid).await {
+        let replaced = slot_size(&odb, &slot).await;
+        let delta = stored_size(value) - replaced;
+        // Storing less can't take them over.
+        if delta > 0 {
+            let usage = quota.usage(owner, &world.options.quotas).await?;
+            if usage.bytes_exceeded(delta) {
+                return Ok(Value::Error(QuotaExceeded));
+            }
+        }
+        quota.stored(owner, oid, delta);
+    }
+    let now = now_nanos();
+    let meta = match odb.get_slot_meta(oid, key, String::from(slot_name)).await {
+        Ok(Some(meta)) => SlotMeta {
+            modified: now,
+            ..meta
+        },
+        _ => SlotMeta::new(now),
+    };
+    SearchTxHandle::new(tr).update(&slot, value).await;
+    odb.set_slot_and_meta(slot, value, Some(&meta));
+
+    Ok(Value::Error(NoError))
+}
+
+/// The String slots holding every word of `query`, on objects which opt in to search, as
+/// [IdKey location, String name] Vectors, up to SEARCH_LIMIT of them; only those the connection
+/// may read. Only slots under their objects' own keys are indexed.
+pub async fn search_slots(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    query: &str,
+) -> Result<Value, Error> {
+    let slots = SearchTxHandle::new(tr)
+        .search(query, SEARCH_LIMIT)
+        .collect::<Result<Vec<SlotDef>, DbError>>()
+        .await?;
+    let mut found = vec![];
+    for slot in slots {
+        if slot_permitted(world, tr, connection, &slot, SLOT_READ).await {
+            found.push(Value::Vector(vec![
+                Value::IdKey(slot.location),
+                Value::String(slot.name.into()),
+            ]));
+        }
+    }
+    Ok(Value::Vector(found))
+}
+
+// Now, in nanoseconds since the Unix epoch, as slots' metadata keeps times.
+pub(super) fn now_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as i64
+}
+
+// Whether `connection` may do what `flag` permits with `slot`: slots not yet set, or without
+// metadata or an owner, are open to all; otherwise it's up to their flags, unless it's the server
+// itself, the owner or an admin asking. Slots under SERVER_KEY are only for the server, and those
+// whose metadata can't be read are for no-one.
+pub(super) async fn slot_permitted(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    slot: &SlotDef,
+    flag: u8,
+) -> bool {
+    if slot.key == SERVER_KEY {
+        return connection.is_none();
+    }
+    let meta = match ObjDBTxHandle::new(tr)
+        .get_slot_meta(slot.location, slot.key, slot.name.clone())
+        .await
+    {
+        Ok(Some(meta)) => meta,
+        Ok(None) | Err(SlotDoesNotExist) => return true,
+        Err(_) => return false,
+    };
+    let player = connection.and_then(|connection| connection_player(world, connection));
+    meta.allows(player, flag) || may_manage(world, tr, connection, meta.owner).await
+}
+
+/// A slot's metadata, for `connection`, as SlotMeta::info gives it. SlotDoesNotExist if there's
+/// no such slot, or it has none; PermissionDenied if it may not be read.
+pub async fn slot_meta(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    slot: SlotDef,
+) -> Result<Value, Error> {
+    if !slot_permitted(world, tr, connection, &slot, SLOT_READ).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    match ObjDBTxHandle::new(tr)
+        .get_slot_meta(slot.location, slot.key, slot.name)
+        .await
+    {
+        Ok(Some(meta)) => Ok(meta.info()),
+        Ok(None) | Err(_) => Ok(Value::Error(SlotDoesNotExist)),
+    }
+}
+
+/// Give a slot, for `connection`, `owner` (None to disown it) and the permissions in `flags`,
+/// e.g. "rx". Only the server, an admin, the slot's owner, or for a slot with no owner the owner
+/// of its object, may. SlotDoesNotExist if there's no such slot; BadType if the flags aren't
+/// ones of "rwx".
+pub async fn set_slot_meta(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    slot: SlotDef,
+    owner: Option<Oid>,
+    flags: &str,
+) -> Result<Value, Error> {
+    let flags = match SlotMeta::parse_flags(flags) {
+        Some(flags) => flags,
+        None => return Ok(Value::Error(BadType)),
+    };
+    let odb = ObjDBTxHandle::new(tr);
+    let meta = match odb
+        .get_slot_meta(slot.location, slot.key, slot.name.clone())
+        .await
+    {
+        Ok(Some(meta)) => meta,
+        Ok(None) => SlotMeta::new(now_nanos()),
+        Err(_) => return Ok(Value::Error(SlotDoesNotExist)),
+    };
+    let responsible = match meta.owner {
+        Some(owner) => Some(owner),
+        None => QuotaTxHandle::new(tr).owner_of(slot.location).await,
+    };
+    if !may_manage(world, tr, connection, responsible).await {
+        return Ok(Value::Error(PermissionDenied));
+    }
+    let meta = SlotMeta {
+        owner,
+        flags,
+        ..meta
+    };
+    match odb
+        .set_slot_meta(slot.location, slot.key, slot.name, &meta)
+        .await
+    {
+        Ok(()) => Ok(Value::Error(NoError)),
+        Err(e) => Ok(Value::Error(e)),
+    }
+}
+
+pub(super) fn is_location_slot(oid: Oid, key: Oid, slot_name: &str) -> bool {
+    oid == key && slot_name == LOCATION_SLOT
+}
+
+pub(super) fn is_owner_slot(oid: Oid, key: Oid, slot_name: &str) -> bool {
+    oid == key && slot_name == OWNER_SLOT
+}
+
+// The bytes the value in `slot` counts for against its owner's quota; zero if there's none.
+pub(super) async fn slot_size(odb: &ObjDBTxHandle<'_>, slot: &SlotDef) -> i64 {
+    odb.get_slot(slot.location, slot.key, slot.name.clone())
+        .await
+        .map_or(0, |value| stored_size(&value))
+}
+
+/// The key the server keeps its own slots under. Verbs run for a connection can neither read nor
+/// write slots under it, whatever their metadata says.
+pub const SERVER_KEY: Oid = Oid {
+    id: Uuid::from_u128(0x7365_7276_6572),
+};
+
+/// The slot on the system object, under SERVER_KEY, listing the players, as IdKeys, who are
+/// admins: they may manage any event on the calendar, and impersonate players.
+pub const ADMINS_SLOT: &str = "admins";
+
+/// Whether a verb run for `connection` may manage an event owned by `owner`: it may if it's run by
+/// the server itself, for no connection, or for the event's owner or an admin.
+pub(super) async fn may_manage(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    owner: Option<Oid>,
+) -> bool {
+    let player = match connection {
+        Some(connection) => match connection_player(world, connection) {
+            Some(player) => player,
+            None => return false,
+        },
+        None => return true,
+    };
+    owner == Some(player) || is_admin(tr, player).await
+}
+
+// Whether a verb run for `connection` may alter `oid` as a whole, moving or destroying it: it may
+// if it's run by the server itself, or for the object's owner, the object itself (a player) or an
+// admin. An object with no owner is only the server's and admins' to alter.
+pub(super) async fn may_alter(
+    world: &Arc<World>,
+    tr: &Tx,
+    connection: Option<Oid>,
+    oid: Oid,
+) -> bool {
+    if connection.and_then(|connection| connection_player(world, connection)) == Some(oid) {
+        return true;
+    }
+    let owner = QuotaTxHandle::new(tr).owner_of(oid).await;
+    may_manage(world, tr, connection, owner).await
+}
+
+// Whether `player` is listed in the system object's admins slot.
+pub(super) async fn is_admin(tr: &Tx, player: Oid) -> bool {
+    admins(tr).await.contains(&player)
+}
+
+pub(super) async fn admins(tr: &Tx) -> Vec<Oid> {
+    let sys_oid = Oid { id: Uuid::nil() };
+    match ObjDBTxHandle::new(tr)
+        .get_slot(sys_oid, SERVER_KEY, String::from(ADMINS_SLOT))
+        .await
+    {
+        Ok(Value::Vector(admins)) => admins
+            .into_iter()
+            .filter_map(|admin| match admin {
+                Value::IdKey(admin) => Some(admin),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Make `player` an admin, or no longer one. False if that's what it was already.
+pub async fn set_admin(world: &Arc<World>, player: Oid, admin: bool) -> Result<bool, Error> {
+    let changed = world
+        .run(|tr| async move {
+            let mut admins = admins(&tr).await;
+            if admins.contains(&player) == admin {
+                return Ok(false);
+            }
+            match admin {
+                true => admins.push(player),
+                false => admins.retain(|other| *other != player),
+            }
+            let sys_oid = Oid { id: Uuid::nil() };
+            let admins = Value::Vector(admins.into_iter().map(Value::IdKey).collect());
+            ObjDBTxHandle::new(&tr)
+                .set_slot(sys_oid, SERVER_KEY, String::from(ADMINS_SLOT), &admins)
+                .await
+                .map_err(DbError::Aborted)?;
+            Ok(true)
+        })
+        .await?;
+    Ok(changed)
+}
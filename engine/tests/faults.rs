@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use room::faults::FaultOptions;
+use room::testing::TestWorld;
+use room::world::WorldOptions;
+use uuid::Uuid;
+use value::{Oid, Program, ProgramLang, Value};
+
+// A 'receive' which counts the messages it's given in a slot on the system object, and replies
+// with the count.
+const COUNTING_RECEIVE: &str = r#"
+local connection = ...
+local count = room.get_slot(room.this, room.this, "count")
+if type(count) ~= "number" then
+    count = 0
+end
+count = count + 1
+room.set_slot(room.this, room.this, "count", count)
+room.send(connection, tostring(count))
+"#;
+
+fn sys() -> Oid {
+    Oid { id: Uuid::nil() }
+}
+
+async fn spawn_counting(faults: FaultOptions) -> TestWorld {
+    let world = TestWorld::spawn_with(WorldOptions {
+        faults: Some(faults),
+        ..WorldOptions::default()
+    })
+    .await
+    .unwrap();
+    let program = Program::new(ProgramLang::Lua, COUNTING_RECEIVE.as_bytes().to_vec());
+    world
+        .set_slot(sys(), "receive", Value::Program(program))
+        .await
+        .unwrap();
+    world
+}
+
+#[tokio::test]
+async fn failed_transactions_are_retried() {
+    let world = spawn_counting(FaultOptions {
+        tx_fail_rate: 0.3,
+        ..FaultOptions::default()
+    })
+    .await;
+    let mut client = world.connect().await.unwrap();
+    for count in 1..=20 {
+        client
+            .send_expect("count", &count.to_string())
+            .await
+            .unwrap();
+    }
+    assert!(world.world().transaction_retries().retries > 0);
+}
+
+#[tokio::test]
+async fn delayed_transactions_still_commit() {
+    let world = spawn_counting(FaultOptions {
+        tx_delay_rate: 1.0,
+        tx_max_delay: Duration::from_millis(20),
+        ..FaultOptions::default()
+    })
+    .await;
+    let mut client = world.connect().await.unwrap();
+    for count in 1..=5 {
+        client
+            .send_expect("count", &count.to_string())
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn dropped_messages_are_not_sent() {
+    let world = spawn_counting(FaultOptions {
+        frame_drop_rate: 1.0,
+        ..FaultOptions::default()
+    })
+    .await;
+    let mut client = world.connect().await.unwrap();
+    client.send("count").await.unwrap();
+    assert!(client.next_message().await.is_err());
+}
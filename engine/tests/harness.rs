@@ -0,0 +1,53 @@
+use room::testing::TestWorld;
+use uuid::Uuid;
+use value::{Oid, Program, ProgramLang, Value};
+
+// A 'receive' which sends each message back as it was sent.
+const ECHO_RECEIVE: &str = r#"
+local connection, message = ...
+room.send(connection, message)
+"#;
+
+// A verb which doubles the number it's given.
+const DOUBLE: &str = r#"
+local connection, n = ...
+return n * 2
+"#;
+
+fn sys() -> Oid {
+    Oid { id: Uuid::nil() }
+}
+
+fn lua(source: &str) -> Value {
+    Value::Program(Program::new(ProgramLang::Lua, source.as_bytes().to_vec()))
+}
+
+#[tokio::test]
+async fn messages_are_given_to_receive() {
+    let world = TestWorld::spawn().await.unwrap();
+    world
+        .set_slot(sys(), "receive", lua(ECHO_RECEIVE))
+        .await
+        .unwrap();
+    let mut client = world.connect().await.unwrap();
+    client.send_expect("hello", "hello").await.unwrap();
+    client.send_expect("again", "again").await.unwrap();
+    client.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn requests_invoke_verbs() {
+    let world = TestWorld::spawn().await.unwrap();
+    world.set_slot(sys(), "double", lua(DOUBLE)).await.unwrap();
+    let mut client = world.connect_rpc().await.unwrap();
+    let response = client
+        .invoke(sys(), "double", vec![Value::I64(21)])
+        .await
+        .unwrap();
+    assert!(matches!(response.result, Value::I64(42)));
+    assert!(response.message.is_none());
+
+    let response = client.invoke(sys(), "triple", vec![]).await.unwrap();
+    assert!(response.error_code().is_some());
+    client.close().await.unwrap();
+}
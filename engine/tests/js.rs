@@ -0,0 +1,125 @@
+use room::protocol::ErrorCode;
+use room::testing::{TestWorld, EXPECT_TIMEOUT};
+use uuid::Uuid;
+use value::{Oid, Program, ProgramLang, Value};
+
+// A verb which remembers the text it's given in a slot, reads it back, and has it doubled twice
+// by a Lua verb, returning all three.
+const REMEMBER: &str = r#"
+const [connection, text] = arguments;
+room.set_slot(room.this, room.this, "remembered", text);
+const remembered = room.get_slot(room.this, room.this, "remembered");
+return [
+    remembered,
+    room.invoke(room.this, "double", [remembered]),
+    room.invoke(room.this, "double", [text + "!"]),
+];
+"#;
+
+// A verb which returns what `remember` remembered.
+const RECALL: &str = r#"
+return room.get_slot(room.this, room.this, "remembered");
+"#;
+
+// A verb which returns the text it's given twice over.
+const DOUBLE: &str = r#"
+local text = ...
+return text .. text
+"#;
+
+// A verb which loops forever, catching whatever's thrown at it.
+const LOOPING: &str = r#"
+for (;;) {
+    try {
+        for (;;) {}
+    } catch (e) {}
+}
+"#;
+
+// A verb which holds on to ever more memory, catching whatever's thrown at it.
+const HOARDING: &str = r#"
+const hoard = [];
+for (;;) {
+    try {
+        hoard.push("x".repeat(4096) + hoard.length);
+    } catch (e) {}
+}
+"#;
+
+fn sys() -> Oid {
+    Oid { id: Uuid::nil() }
+}
+
+fn program(lang: ProgramLang, source: &str) -> Value {
+    Value::Program(Program::new(lang, source.as_bytes().to_vec()))
+}
+
+async fn spawn() -> TestWorld {
+    let world = TestWorld::spawn().await.unwrap();
+    let verbs = [
+        ("remember", program(ProgramLang::JavaScript, REMEMBER)),
+        ("recall", program(ProgramLang::JavaScript, RECALL)),
+        ("double", program(ProgramLang::Lua, DOUBLE)),
+        ("looping", program(ProgramLang::JavaScript, LOOPING)),
+        ("hoarding", program(ProgramLang::JavaScript, HOARDING)),
+    ];
+    for (name, verb) in verbs {
+        world.set_slot(sys(), name, verb).await.unwrap();
+    }
+    world
+}
+
+fn text(value: &Value) -> &str {
+    match value {
+        Value::String(text) => text.as_str(),
+        _ => panic!("Returned {:?}", value),
+    }
+}
+
+#[tokio::test]
+async fn javascript_verbs_read_and_write_slots_and_invoke_verbs() {
+    let world = spawn().await;
+    let mut client = world.connect_rpc().await.unwrap();
+    let args = vec![Value::String("hi".into())];
+    let response = client.invoke(sys(), "remember", args).await.unwrap();
+    assert_eq!(response.error_code(), None);
+    let results: Vec<&str> = match &response.result {
+        Value::Vector(results) => results.iter().map(text).collect(),
+        _ => panic!("Returned {:?}", response.result),
+    };
+    assert_eq!(results, ["hi", "hihi", "hi!hi!"]);
+
+    // What it wrote was committed.
+    let response = client.invoke(sys(), "recall", vec![]).await.unwrap();
+    assert_eq!(text(&response.result), "hi");
+}
+
+#[tokio::test]
+async fn javascript_verbs_are_stopped_by_their_watchdog() {
+    let world = spawn().await;
+    world
+        .set_slot(sys(), "time_limit_ms", Value::I64(200))
+        .await
+        .unwrap();
+    let mut client = world.connect_rpc().await.unwrap();
+    let response = client.invoke(sys(), "looping", vec![]).await.unwrap();
+    assert_eq!(response.error_code(), Some(ErrorCode::ResourceLimit));
+}
+
+#[tokio::test]
+async fn javascript_verbs_are_stopped_at_their_heap_limit() {
+    let world = spawn().await;
+    // Long enough that only running out of heap can stop the verb before the client gives up
+    // waiting for the response.
+    let time_limit = (EXPECT_TIMEOUT * 10).as_millis() as i64;
+    let limits = [
+        ("memory_limit", Value::I64(8 << 20)),
+        ("time_limit_ms", Value::I64(time_limit)),
+    ];
+    for (name, limit) in limits {
+        world.set_slot(sys(), name, limit).await.unwrap();
+    }
+    let mut client = world.connect_rpc().await.unwrap();
+    let response = client.invoke(sys(), "hoarding", vec![]).await.unwrap();
+    assert_eq!(response.error_code(), Some(ErrorCode::ResourceLimit));
+}
@@ -0,0 +1,166 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use room::protocol::ErrorCode;
+use room::testing::TestWorld;
+use room::verb_audit::VerbAuditOptions;
+use room::verb_error::TrapKind;
+use room::world::{query_verb_audit, WorldOptions};
+use uuid::Uuid;
+use value::{Oid, Program, ProgramLang, Value};
+
+// A verb which fails, saying why.
+const FAILING: &str = r#"
+error("the callee gave up")
+"#;
+
+// A verb which executes `unreachable`, as a panicking guest would.
+const UNREACHABLE: &str = r#"
+(module
+    (memory $mem 1)
+    (export "memory" (memory $mem))
+    (func $invoke (param i32) (result i32 i32)
+        unreachable)
+    (export "invoke" (func $invoke)))
+"#;
+
+// A verb which loads from past the end of its one page of memory.
+const OUT_OF_BOUNDS: &str = r#"
+(module
+    (memory $mem 1)
+    (export "memory" (memory $mem))
+    (func $invoke (param i32) (result i32 i32)
+        (drop (i32.load (i32.const 131072)))
+        (i32.const 0)
+        (i32.const 0))
+    (export "invoke" (func $invoke)))
+"#;
+
+// A verb which invokes the verb it's given the name of, and returns the kind and message of the
+// error map it gets back.
+const INVOKING: &str = r#"
+local connection, verb = ...
+local failed = room.invoke(room.this, verb, {})
+return {failed[2][2], failed[3][2]}
+"#;
+
+fn sys() -> Oid {
+    Oid { id: Uuid::nil() }
+}
+
+fn program(lang: ProgramLang, source: &str) -> Value {
+    Value::Program(Program::new(lang, source.as_bytes().to_vec()))
+}
+
+async fn spawn_with(options: WorldOptions) -> TestWorld {
+    let world = TestWorld::spawn_with(options).await.unwrap();
+    let verbs = [
+        ("failing", program(ProgramLang::Lua, FAILING)),
+        ("unreachable", program(ProgramLang::Wat, UNREACHABLE)),
+        ("out_of_bounds", program(ProgramLang::Wat, OUT_OF_BOUNDS)),
+        ("invoking", program(ProgramLang::Lua, INVOKING)),
+    ];
+    for (name, verb) in verbs {
+        world.set_slot(sys(), name, verb).await.unwrap();
+    }
+    world
+}
+
+async fn spawn() -> TestWorld {
+    spawn_with(WorldOptions::default()).await
+}
+
+// The kind and message of the error map `verb` gave the verb which invoked it.
+async fn invoked(world: &TestWorld, verb: &str) -> (String, String) {
+    let mut client = world.connect_rpc().await.unwrap();
+    let args = vec![Value::String(verb.into())];
+    let response = client.invoke(sys(), "invoking", args).await.unwrap();
+    assert_eq!(response.error_code(), None);
+    match &response.result {
+        Value::Vector(fields) => match fields.as_slice() {
+            [Value::String(kind), Value::String(message)] => {
+                (kind.to_string(), message.to_string())
+            }
+            _ => panic!("Returned {:?}", response.result),
+        },
+        _ => panic!("Returned {:?}", response.result),
+    }
+}
+
+// The names of the fields of the error map a client was sent for invoking `verb`.
+async fn sent_fields(world: &TestWorld, verb: &str) -> Vec<String> {
+    let mut client = world.connect_rpc().await.unwrap();
+    let response = client.invoke(sys(), verb, vec![]).await.unwrap();
+    assert_eq!(response.error_code(), Some(ErrorCode::Trapped));
+    let fields = match &response.result {
+        Value::Vector(fields) => fields,
+        _ => panic!("Returned {:?}", response.result),
+    };
+    fields
+        .iter()
+        .filter_map(|field| match field {
+            Value::Vector(field) => match field.first() {
+                Some(Value::String(name)) => Some(name.to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn a_failed_callee_returns_an_error_map_to_its_caller() {
+    let world = spawn().await;
+    let (kind, message) = invoked(&world, "failing").await;
+    assert_eq!(kind, "failed");
+    assert!(message.contains("the callee gave up"));
+}
+
+#[tokio::test]
+async fn a_trapped_callee_says_how_it_trapped() {
+    let world = spawn().await;
+    let (kind, message) = invoked(&world, "unreachable").await;
+    assert_eq!(kind, "unreachable");
+    assert!(!message.is_empty());
+    let (kind, _) = invoked(&world, "out_of_bounds").await;
+    assert_eq!(kind, "memory_out_of_bounds");
+}
+
+#[tokio::test]
+async fn clients_are_not_told_why_a_verb_failed() {
+    let world = spawn().await;
+    for verb in ["failing", "unreachable", "out_of_bounds"] {
+        assert_eq!(sent_fields(&world, verb).await, ["error", "kind"]);
+    }
+}
+
+#[tokio::test]
+async fn the_audit_trail_keeps_why_a_verb_failed() {
+    let world = spawn_with(WorldOptions {
+        verb_audit: Some(VerbAuditOptions {
+            retention: Duration::from_secs(3600),
+        }),
+        ..WorldOptions::default()
+    })
+    .await;
+    assert_eq!(sent_fields(&world, "unreachable").await, ["error", "kind"]);
+
+    // A failure's record is written in a transaction of its own, after the verb's is abandoned.
+    for _ in 0..50 {
+        let (records, _) =
+            query_verb_audit(world.world(), Some(sys()), UNIX_EPOCH, SystemTime::now())
+                .await
+                .unwrap();
+        let failed = records
+            .iter()
+            .filter(|record| record.verb == "unreachable")
+            .find_map(|record| record.error.as_ref());
+        if let Some(error) = failed {
+            assert_eq!(error.kind, TrapKind::Unreachable);
+            assert!(!error.message.is_empty());
+            assert!(error.backtrace.is_some());
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The failure wasn't audited");
+}
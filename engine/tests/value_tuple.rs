@@ -0,0 +1,16 @@
+use proptest::prelude::*;
+use room::fdb_object::FdbValue;
+
+#[path = "../../value/tests/common/mod.rs"]
+mod common;
+use common::{encoded, value};
+
+proptest! {
+    // Values are stored as FDB tuples, Vectors as nested ones; they must come back as they went.
+    #[test]
+    fn values_round_trip_through_tuples(v in value()) {
+        let stored: fdb::Value = (&FdbValue(v.clone())).into();
+        let FdbValue(loaded) = stored.into();
+        prop_assert_eq!(encoded(&loaded), encoded(&v));
+    }
+}
@@ -0,0 +1,87 @@
+//! The `#[verb]` attribute, for declaring verbs written with the driver as plain Rust functions.
+//!
+//! ```ignore
+//! use driver::{verb, Oid};
+//!
+//! #[verb]
+//! fn rename(oid: Oid, name: String) -> Result<i64, driver::Error> {
+//!     ...
+//! }
+//! ```
+//!
+//! The function is kept as it is, and the module's 'invoke' is generated to call it: each argument
+//! the host passed is converted to its parameter's type with `driver::FromValue`, and its result
+//! back with `driver::IntoValue`. A verb passed the wrong number of arguments, or one of the wrong
+//! type, returns BadType without being run. Since each verb is its own module, there's one
+//! `#[verb]` to a crate.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, FnArg, Ident, ItemFn, Pat};
+
+#[proc_macro_attribute]
+pub fn verb(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(Span::call_site(), "#[verb] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let function = parse_macro_input!(item as ItemFn);
+    match trampoline(&function) {
+        Ok(trampoline) => quote!(#function #trampoline).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+// The exported 'invoke' which unpacks the arguments for `function` and packs up its result.
+fn trampoline(function: &ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let signature = &function.sig;
+    if let Some(token) = &signature.asyncness {
+        return Err(syn::Error::new(token.span(), "verbs can't be async"));
+    }
+    if !signature.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            signature.generics.span(),
+            "verbs can't be generic",
+        ));
+    }
+
+    let mut names = vec![];
+    let mut types = vec![];
+    for (i, input) in signature.inputs.iter().enumerate() {
+        let input = match input {
+            FnArg::Typed(input) => input,
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new(receiver.span(), "verbs are free functions"))
+            }
+        };
+        // Named after the parameter where there is a name, so errors read naturally.
+        let name = match &*input.pat {
+            Pat::Ident(pat) => pat.ident.clone(),
+            _ => Ident::new(&format!("arg{}", i), input.pat.span()),
+        };
+        names.push(name);
+        types.push(&input.ty);
+    }
+
+    let verb = &signature.ident;
+    Ok(quote! {
+        #[no_mangle]
+        pub extern "C" fn invoke(args_len: i32) -> i64 {
+            ::driver::entry(args_len, |args| {
+                let mut __args = args.into_iter();
+                #(
+                    let #names = match __args.next().and_then(<#types as ::driver::FromValue>::from_value) {
+                        Some(arg) => arg,
+                        None => return ::driver::Value::Error(::driver::Error::BadType),
+                    };
+                )*
+                if __args.next().is_some() {
+                    return ::driver::Value::Error(::driver::Error::BadType);
+                }
+                ::driver::IntoValue::into_value(#verb(#(#names),*))
+            })
+        }
+    })
+}
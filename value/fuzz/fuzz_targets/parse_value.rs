@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use value::{append_value, decode_frame, parse_value};
+
+// Bytes as a client might send them: decoded or refused, never a panic. Anything decoded is
+// written back as the bytes it was read from.
+fuzz_target!(|data: &[u8]| {
+    let mut buf = data;
+    if let Ok(parsed) = parse_value(&mut buf) {
+        let consumed = data.len() - buf.len();
+        let mut encoded = vec![];
+        append_value(&mut encoded, &parsed);
+        assert_eq!(encoded, &data[..consumed]);
+    }
+    let _ = decode_frame(data);
+});
@@ -1,7 +1,12 @@
 use bytes::buf::{Buf, BufMut};
+use bytes::Bytes;
 use int_enum::IntEnum;
 use serde::{Deserialize, Serialize};
 
+mod text;
+
+pub use text::Text;
+
 // An Oid is 128-bit V4 UUID.
 // Used to identify objects & keys on objects.
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -21,11 +26,83 @@ pub enum ValueType {
     IdKey = 6,   // Refs to Objects
     Vector = 7,  // Collections of Values
     Binary = 8,  // Byte arrays
-    Program = 9, // WAS code,
+    Program = 9, // Code, tagged with its language
     Error = 10,
+    Timestamp = 11, // Nanoseconds since the Unix epoch
+    Blob = 12,      // Handles to Binary values stored in chunks
+}
+
+/// The language a Program is written in, which decides how it's compiled and run.
+#[repr(i8)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, Hash, PartialEq, IntEnum)]
+pub enum ProgramLang {
+    /// WebAssembly text.
+    Wat = 0,
+    /// A WebAssembly binary module.
+    Wasm = 1,
+    /// JavaScript source.
+    JavaScript = 2,
+    /// Lua source, run by the engine's Lua VM.
+    Lua = 3,
+}
+
+/// The first bytes of every WebAssembly binary module.
+pub const WASM_MAGIC: &[u8] = b"\0asm";
+
+impl ProgramLang {
+    /// The language of WebAssembly `code`, which is a binary module if it starts like one and WAT
+    /// otherwise. For programs stored before they were tagged, which were always one or the other.
+    pub fn detect(code: &[u8]) -> Self {
+        match code.starts_with(WASM_MAGIC) {
+            true => ProgramLang::Wasm,
+            false => ProgramLang::Wat,
+        }
+    }
+}
+
+/// The code of a verb, tagged with the language it's written in.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(from = "StoredProgram")]
+pub struct Program {
+    pub lang: ProgramLang,
+    pub code: Bytes,
+}
+
+impl Program {
+    pub fn new(lang: ProgramLang, code: impl Into<Bytes>) -> Self {
+        Program {
+            lang,
+            code: code.into(),
+        }
+    }
+
+    /// A WebAssembly program, as WAT or a binary module, whichever `code` is.
+    pub fn wasm(code: impl Into<Bytes>) -> Self {
+        let code = code.into();
+        Program {
+            lang: ProgramLang::detect(&code),
+            code,
+        }
+    }
 }
 
-pub type Program = Vec<u8>;
+// Programs as they're serialized now, or as they were before they were tagged with their language:
+// just their code.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredProgram {
+    Tagged { lang: ProgramLang, code: Bytes },
+    Untagged(Bytes),
+}
+
+impl From<StoredProgram> for Program {
+    fn from(stored: StoredProgram) -> Self {
+        match stored {
+            StoredProgram::Tagged { lang, code } => Program { lang, code },
+            StoredProgram::Untagged(code) => Program::wasm(code),
+        }
+    }
+}
 
 #[repr(i8)]
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, IntEnum)]
@@ -36,6 +113,13 @@ pub enum Error {
     PermissionDenied = 3,
     InternalError = 4,
     BadType = 5,
+    NameTaken = 6,
+    SecondFactorRequired = 7,
+    ResourceLimit = 8,
+    QuotaExceeded = 9,
+    Contended = 10,
+    PeerUnavailable = 11,
+    Trapped = 12,
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Value {
@@ -44,74 +128,232 @@ pub enum Value {
     F32(f32),
     F64(f64),
     U128(u128),
-    String(String),
+    /// Decoded from a `Bytes` buffer, it shares that buffer as Binary does.
+    String(Text),
     Vector(Vec<Value>),
-    Binary(Vec<u8>),
+    /// Decoded from a `Bytes` buffer, it shares that buffer rather than copying out of it.
+    Binary(Bytes),
     Program(Program),
     IdKey(Oid),
     Error(Error),
+    Timestamp(i64),
+    /// A Binary value too large to keep in its slot, which is stored in chunks instead; this is
+    /// its length. It's read in pieces with the slot's location, key and name.
+    Blob(u64),
+}
+
+impl Value {
+    /// `self` with its Strings, Binaries and Programs copied into buffers of their own, for values
+    /// kept long after the frame they were decoded from, which would otherwise be kept whole.
+    pub fn detached(&self) -> Value {
+        match self {
+            Value::String(s) => Value::String(s.as_str().into()),
+            Value::Binary(b) => Value::Binary(Bytes::copy_from_slice(b)),
+            Value::Program(p) => Value::Program(Program {
+                lang: p.lang,
+                code: Bytes::copy_from_slice(&p.code),
+            }),
+            Value::Vector(v) => Value::Vector(v.iter().map(Value::detached).collect()),
+            v => v.clone(),
+        }
+    }
+}
+
+/// The first byte of every frame, so that a frame can be told from other data.
+pub const FRAME_MAGIC: u8 = 0xD5;
+
+/// The version of the value encoding which frames are written in.
+pub const FRAME_VERSION: u8 = 1;
+
+/// The magic byte, the version, and the length of the encoded value (a big-endian u32).
+pub const FRAME_HEADER_LEN: usize = 6;
+
+/// How deeply Vectors may be nested in a value being decoded.
+pub const MAX_DEPTH: usize = 64;
+
+/// Why bytes couldn't be decoded as a value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes ended before the value did.
+    Truncated,
+    UnknownType(i8),
+    UnknownError(i8),
+    UnknownLanguage(i8),
+    InvalidUtf8,
+    /// Vectors were nested more than MAX_DEPTH deep.
+    TooDeep,
+    BadMagic(u8),
+    UnsupportedVersion(u8),
+    /// The length in a frame's header isn't the length of what follows it.
+    LengthMismatch {
+        declared: usize,
+        actual: usize,
+    },
+    /// Bytes were left over after the frame's value.
+    TrailingBytes(usize),
 }
 
-pub fn parse_value(buf: &mut dyn Buf) -> Value {
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "value is truncated"),
+            DecodeError::UnknownType(t) => write!(f, "unknown value type {}", t),
+            DecodeError::UnknownError(e) => write!(f, "unknown error {}", e),
+            DecodeError::UnknownLanguage(l) => write!(f, "unknown program language {}", l),
+            DecodeError::InvalidUtf8 => write!(f, "string is not UTF-8"),
+            DecodeError::TooDeep => write!(f, "vectors nested over {} deep", MAX_DEPTH),
+            DecodeError::BadMagic(b) => write!(f, "not a frame (starts with {:#04x})", b),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported frame version {}", v),
+            DecodeError::LengthMismatch { declared, actual } => {
+                write!(f, "frame declares {} bytes but has {}", declared, actual)
+            }
+            DecodeError::TrailingBytes(n) => write!(f, "{} bytes after the frame's value", n),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn need(buf: &dyn Buf, len: usize) -> Result<(), DecodeError> {
+    match buf.remaining() >= len {
+        true => Ok(()),
+        false => Err(DecodeError::Truncated),
+    }
+}
+
+// The bytes of a String, Binary or Program. Taken from a `Bytes` buffer, they're a slice of it
+// rather than a copy; from anything else, such as a plain slice, they're copied.
+fn parse_bytes(buf: &mut dyn Buf) -> Result<Bytes, DecodeError> {
+    need(buf, 4)?;
+    let len = buf.get_u32() as usize;
+    need(buf, len)?;
+    Ok(buf.copy_to_bytes(len))
+}
+
+/// Decode a value written by `append_value`, leaving `buf` after it.
+pub fn parse_value(buf: &mut dyn Buf) -> Result<Value, DecodeError> {
+    parse_nested(buf, 0)
+}
+
+fn parse_nested(buf: &mut dyn Buf, depth: usize) -> Result<Value, DecodeError> {
+    need(buf, 1)?;
     let type_val_idx = buf.get_i8();
-    let tval = ValueType::from_int(type_val_idx).unwrap();
-    match tval {
+    let tval =
+        ValueType::from_int(type_val_idx).map_err(|_| DecodeError::UnknownType(type_val_idx))?;
+    let value = match tval {
         ValueType::I32 => {
-            let num = buf.get_i32();
-            Value::I32(num)
+            need(buf, 4)?;
+            Value::I32(buf.get_i32())
         }
         ValueType::I64 => {
-            let num = buf.get_i64();
-            Value::I64(num)
+            need(buf, 8)?;
+            Value::I64(buf.get_i64())
         }
         ValueType::F32 => {
-            let num = buf.get_f32();
-            Value::F32(num)
+            need(buf, 4)?;
+            Value::F32(buf.get_f32())
         }
         ValueType::F64 => {
-            let num = buf.get_f64();
-            Value::F64(num)
+            need(buf, 8)?;
+            Value::F64(buf.get_f64())
+        }
+        ValueType::V128 => {
+            need(buf, 16)?;
+            Value::U128(buf.get_u128())
         }
-        ValueType::V128 => Value::U128(buf.get_u128()),
         ValueType::String => {
-            let len = buf.get_u32() as usize;
-            let mut dst_bytes: Vec<u8> = Vec::with_capacity(len);
-            dst_bytes.resize(len, 0);
-            buf.copy_to_slice(dst_bytes.as_mut_slice());
-            Value::String(String::from_utf8(dst_bytes).unwrap())
+            Value::String(Text::from_utf8(parse_bytes(buf)?).map_err(|_| DecodeError::InvalidUtf8)?)
         }
         ValueType::IdKey => {
-            let oid_bytes = buf.get_u128();
+            need(buf, 16)?;
             Value::IdKey(Oid {
-                id: uuid::Uuid::from_u128(oid_bytes),
+                id: uuid::Uuid::from_u128(buf.get_u128()),
             })
         }
         ValueType::Vector => {
+            if depth >= MAX_DEPTH {
+                return Err(DecodeError::TooDeep);
+            }
+            need(buf, 4)?;
             let size: usize = buf.get_u32() as usize;
+            // Every element takes at least a byte, which bounds what a bad size can allocate.
+            need(buf, size)?;
             let mut l_val: Vec<Value> = Vec::with_capacity(size);
             for _n in 0..size {
-                l_val.push(parse_value(buf));
+                l_val.push(parse_nested(buf, depth + 1)?);
             }
             Value::Vector(l_val)
         }
-        ValueType::Binary => {
-            let len = buf.get_u32() as usize;
-            let mut dst_bytes: Vec<u8> = Vec::with_capacity(len);
-            dst_bytes.resize(len, 0);
-            buf.copy_to_slice(dst_bytes.as_mut_slice());
-            Value::Binary(dst_bytes)
-        }
+        ValueType::Binary => Value::Binary(parse_bytes(buf)?),
         ValueType::Program => {
-            let len = buf.get_u32() as usize;
-            let mut dst_bytes: Vec<u8> = Vec::with_capacity(len);
-            dst_bytes.resize(len, 0);
-            buf.copy_to_slice(dst_bytes.as_mut_slice());
-            Value::Program(dst_bytes)
+            need(buf, 1)?;
+            let lang = buf.get_i8();
+            let lang =
+                ProgramLang::from_int(lang).map_err(|_| DecodeError::UnknownLanguage(lang))?;
+            Value::Program(Program::new(lang, parse_bytes(buf)?))
         }
         ValueType::Error => {
+            need(buf, 1)?;
             let num = buf.get_i8();
-            Value::Error(Error::from_int(num).unwrap())
+            Value::Error(Error::from_int(num).map_err(|_| DecodeError::UnknownError(num))?)
+        }
+        ValueType::Timestamp => {
+            need(buf, 8)?;
+            Value::Timestamp(buf.get_i64())
         }
+        ValueType::Blob => {
+            need(buf, 8)?;
+            Value::Blob(buf.get_u64())
+        }
+    };
+    Ok(value)
+}
+
+/// Encode `val` as a frame: a header giving the encoding's version and length, then the value as
+/// written by `append_value`. This is how values cross websockets and the boundary into verbs.
+pub fn encode_frame(val: &Value) -> Vec<u8> {
+    let mut body = vec![];
+    append_value(&mut body, val);
+    let mut buf = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+    buf.put_u8(FRAME_MAGIC);
+    buf.put_u8(FRAME_VERSION);
+    buf.put_u32(body.len() as u32);
+    buf.put(body.as_slice());
+    buf
+}
+
+/// Decode a frame written by `encode_frame`, which must be the whole of `bytes`.
+pub fn decode_frame(mut bytes: &[u8]) -> Result<Value, DecodeError> {
+    parse_frame(&mut bytes)
+}
+
+/// Decode a frame as `decode_frame` does, but with the Strings, Binaries and Programs in it
+/// sharing `frame`'s buffer rather than copied out of it.
+pub fn decode_frame_shared(mut frame: Bytes) -> Result<Value, DecodeError> {
+    parse_frame(&mut frame)
+}
+
+fn parse_frame(buf: &mut dyn Buf) -> Result<Value, DecodeError> {
+    need(buf, FRAME_HEADER_LEN)?;
+    let magic = buf.get_u8();
+    if magic != FRAME_MAGIC {
+        return Err(DecodeError::BadMagic(magic));
+    }
+    let version = buf.get_u8();
+    if version != FRAME_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let declared = buf.get_u32() as usize;
+    if declared != buf.remaining() {
+        return Err(DecodeError::LengthMismatch {
+            declared,
+            actual: buf.remaining(),
+        });
+    }
+    let value = parse_value(buf)?;
+    match buf.remaining() {
+        0 => Ok(value),
+        n => Err(DecodeError::TrailingBytes(n)),
     }
 }
 
@@ -157,16 +399,25 @@ pub fn append_value(buf: &mut Vec<u8>, val: &Value) {
         Value::Binary(b) => {
             buf.put_i8(ValueType::Binary as i8);
             buf.put_u32(b.len() as u32);
-            buf.put(b.to_owned().as_slice());
+            buf.put(b.as_ref());
         }
-        Value::Program(b) => {
-            buf.put_i8(ValueType::Binary as i8);
-            buf.put_u32(b.len() as u32);
-            buf.put(b.to_owned().as_slice());
+        Value::Program(p) => {
+            buf.put_i8(ValueType::Program as i8);
+            buf.put_i8(p.lang as i8);
+            buf.put_u32(p.code.len() as u32);
+            buf.put(p.code.as_ref());
         }
         Value::Error(err) => {
             buf.put_i8(ValueType::Error as i8);
             buf.put_i8(*err as i8);
         }
+        Value::Timestamp(nanos) => {
+            buf.put_i8(ValueType::Timestamp as i8);
+            buf.put_i64(*nanos);
+        }
+        Value::Blob(len) => {
+            buf.put_i8(ValueType::Blob as i8);
+            buf.put_u64(*len);
+        }
     }
 }
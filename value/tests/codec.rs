@@ -0,0 +1,62 @@
+use proptest::prelude::*;
+use value::{
+    decode_frame, decode_frame_shared, encode_frame, parse_value, DecodeError, Value, MAX_DEPTH,
+};
+
+mod common;
+use common::{encoded, value};
+
+proptest! {
+    #[test]
+    fn parse_value_reads_what_append_value_wrote(v in value()) {
+        let bytes = encoded(&v);
+        let mut buf = &bytes[..];
+        let parsed = parse_value(&mut buf).unwrap();
+        prop_assert!(buf.is_empty());
+        prop_assert_eq!(encoded(&parsed), bytes);
+    }
+
+    #[test]
+    fn decode_frame_reads_what_encode_frame_wrote(v in value()) {
+        let decoded = decode_frame(&encode_frame(&v)).unwrap();
+        prop_assert_eq!(encoded(&decoded), encoded(&v));
+    }
+
+    #[test]
+    fn decode_frame_shared_reads_what_encode_frame_wrote(v in value()) {
+        let decoded = decode_frame_shared(encode_frame(&v).into()).unwrap();
+        prop_assert_eq!(encoded(&decoded), encoded(&v));
+    }
+
+    #[test]
+    fn truncated_values_are_refused(v in value(), cut in any::<prop::sample::Index>()) {
+        let bytes = encoded(&v);
+        let mut buf = &bytes[..cut.index(bytes.len())];
+        prop_assert!(parse_value(&mut buf).is_err());
+    }
+
+    // Whatever a client sends, it's decoded or refused, and anything decoded is written back as
+    // the bytes it was read from.
+    #[test]
+    fn arbitrary_bytes_dont_panic(bytes in prop::collection::vec(any::<u8>(), 0..1024)) {
+        let mut buf = &bytes[..];
+        if let Ok(parsed) = parse_value(&mut buf) {
+            let consumed = bytes.len() - buf.len();
+            prop_assert_eq!(encoded(&parsed), &bytes[..consumed]);
+        }
+        let _ = decode_frame(&bytes);
+    }
+}
+
+#[test]
+fn deep_nesting_is_refused() {
+    let mut v = Value::I32(0);
+    for _ in 0..=MAX_DEPTH {
+        v = Value::Vector(vec![v]);
+    }
+    let bytes = encoded(&v);
+    assert!(matches!(
+        parse_value(&mut &bytes[..]),
+        Err(DecodeError::TooDeep)
+    ));
+}
@@ -0,0 +1,50 @@
+use int_enum::IntEnum;
+use proptest::prelude::*;
+use uuid::Uuid;
+use value::{Error, Oid, Program, ProgramLang, Value};
+
+// Every variant of a fieldless enum with i8 discriminants from 0, as `from_int` knows them.
+fn variants<T: Clone + std::fmt::Debug + 'static>(from_int: fn(i8) -> Option<T>) -> Vec<T> {
+    (0..=i8::MAX).filter_map(from_int).collect()
+}
+
+/// Any value but a Vector.
+pub fn leaf() -> impl Strategy<Value = Value> {
+    let errors = variants(|n| Error::from_int(n).ok());
+    let langs = variants(|n| ProgramLang::from_int(n).ok());
+    prop_oneof![
+        10 => any::<i32>().prop_map(Value::I32),
+        10 => any::<i64>().prop_map(Value::I64),
+        10 => prop::num::f32::ANY.prop_map(Value::F32),
+        10 => prop::num::f64::ANY.prop_map(Value::F64),
+        10 => any::<u128>().prop_map(Value::U128),
+        10 => any::<String>().prop_map(|s| Value::String(s.into())),
+        10 => prop::collection::vec(any::<u8>(), 0..64).prop_map(|b| Value::Binary(b.into())),
+        // Larger than any buffer is likely to start out with.
+        1 => prop::collection::vec(any::<u8>(), 65_536..262_144)
+            .prop_map(|b| Value::Binary(b.into())),
+        10 => (prop::sample::select(langs), prop::collection::vec(any::<u8>(), 0..256))
+            .prop_map(|(lang, code)| Value::Program(Program::new(lang, code))),
+        10 => any::<u128>().prop_map(|id| Value::IdKey(Oid {
+            id: Uuid::from_u128(id)
+        })),
+        10 => prop::sample::select(errors).prop_map(Value::Error),
+        10 => any::<i64>().prop_map(Value::Timestamp),
+        10 => any::<u64>().prop_map(Value::Blob),
+    ]
+}
+
+/// Any value, Vectors nested up to eight deep included.
+pub fn value() -> impl Strategy<Value = Value> {
+    leaf().prop_recursive(8, 256, 16, |inner| {
+        prop::collection::vec(inner, 0..16).prop_map(Value::Vector)
+    })
+}
+
+/// `value` as `append_value` writes it. Values aren't comparable, so round trips are checked by
+/// comparing these.
+pub fn encoded(value: &Value) -> Vec<u8> {
+    let mut buf = vec![];
+    value::append_value(&mut buf, value);
+    buf
+}